@@ -0,0 +1,21 @@
+//! Build script for the Neotron Desktop BIOS.
+//!
+//! Records when we were built, for the BIOS identity ROM. Set
+//! `SOURCE_DATE_EPOCH` for reproducible builds.
+
+fn main() {
+	// Only the timestamp depends on anything outside the source, so don't
+	// run again for every change to the code
+	println!("cargo:rerun-if-changed=build.rs");
+	println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+	let build_time = std::env::var("SOURCE_DATE_EPOCH")
+		.ok()
+		.and_then(|s| s.parse::<u64>().ok())
+		.unwrap_or_else(|| {
+			std::time::SystemTime::now()
+				.duration_since(std::time::UNIX_EPOCH)
+				.map(|d| d.as_secs())
+				.unwrap_or(0)
+		});
+	println!("cargo:rustc-env=NEOTRON_BUILD_TIME={}", build_time);
+}
@@ -0,0 +1,209 @@
+//! # BIOS call statistics
+//!
+//! Every BIOS function the OS calls goes through a wrapper (see
+//! [`crate::apitrace`]) which counts the call, and the bytes in any buffers it
+//! was given. Once a second we work out how many calls each function got in
+//! the last second. The debug console shows the lot with `stats api`, and
+//! `--api-stats` puts the busiest functions along the bottom of the window.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The counters for one BIOS function.
+pub struct Counters {
+	/// How many times it has been called
+	calls: AtomicU64,
+	/// How many bytes it has been given in buffers
+	bytes: AtomicU64,
+	/// How many calls it got in the last whole second
+	rate: AtomicU64,
+	/// What `calls` was at the start of this second
+	calls_last_second: AtomicU64,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many functions `--api-stats` shows.
+const SUMMARY_LENGTH: usize = 3;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Start working out the call rates.
+pub fn start() {
+	std::thread::spawn(|| loop {
+		std::thread::sleep(Duration::from_secs(1));
+		for (_name, counters) in crate::apitrace::COUNTERS {
+			counters.sample();
+		}
+	});
+}
+
+/// A table of every function the OS has called, busiest first.
+pub fn report() -> String {
+	let rows = busiest();
+	if rows.is_empty() {
+		return "The OS hasn't called the BIOS yet".to_string();
+	}
+	let mut output = format!(
+		"{:30} {:>10} {:>12} {:>14}",
+		"Function", "Calls/s", "Calls", "Bytes"
+	);
+	let mut total_rate = 0;
+	let mut total_calls = 0;
+	let mut total_bytes = 0;
+	for (name, rate, calls, bytes) in rows {
+		output.push_str(&format!(
+			"\n{:30} {:>10} {:>12} {:>14}",
+			name, rate, calls, bytes
+		));
+		total_rate += rate;
+		total_calls += calls;
+		total_bytes += bytes;
+	}
+	output.push_str(&format!(
+		"\n{:30} {:>10} {:>12} {:>14}",
+		"Total", total_rate, total_calls, total_bytes
+	));
+	output
+}
+
+/// The busiest few functions in the last second, in one line.
+pub fn summary() -> String {
+	let rows = busiest();
+	let total: u64 = rows.iter().map(|row| row.1).sum();
+	let mut line = format!("BIOS: {}/s", total);
+	for (name, rate, _calls, _bytes) in rows.iter().take(SUMMARY_LENGTH) {
+		if *rate != 0 {
+			line.push_str(&format!(", {} {}/s", name, rate));
+		}
+	}
+	line
+}
+
+/// How many calls the OS has made to the BIOS, since we last started
+/// counting from zero.
+pub fn total_calls() -> u64 {
+	crate::apitrace::COUNTERS
+		.iter()
+		.map(|(_name, counters)| counters.calls.load(Ordering::Relaxed))
+		.sum()
+}
+
+/// Every BIOS function, called or not, as (name, calls, bytes).
+pub fn totals() -> Vec<(&'static str, u64, u64)> {
+	crate::apitrace::COUNTERS
+		.iter()
+		.map(|(name, counters)| {
+			(
+				*name,
+				counters.calls.load(Ordering::Relaxed),
+				counters.bytes.load(Ordering::Relaxed),
+			)
+		})
+		.collect()
+}
+
+/// Start counting again from zero.
+pub fn reset() {
+	for (_name, counters) in crate::apitrace::COUNTERS {
+		counters.reset();
+	}
+}
+
+/// Every function that has been called, as (name, calls in the last second,
+/// calls, bytes), busiest first.
+fn busiest() -> Vec<(&'static str, u64, u64, u64)> {
+	let mut rows: Vec<_> = crate::apitrace::COUNTERS
+		.iter()
+		.map(|(name, counters)| {
+			(
+				*name,
+				counters.rate.load(Ordering::Relaxed),
+				counters.calls.load(Ordering::Relaxed),
+				counters.bytes.load(Ordering::Relaxed),
+			)
+		})
+		.filter(|row| row.2 != 0)
+		.collect();
+	rows.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+	rows
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Default for Counters {
+	fn default() -> Counters {
+		Counters::new()
+	}
+}
+
+impl Counters {
+	pub const fn new() -> Counters {
+		Counters {
+			calls: AtomicU64::new(0),
+			bytes: AtomicU64::new(0),
+			rate: AtomicU64::new(0),
+			calls_last_second: AtomicU64::new(0),
+		}
+	}
+
+	/// Count a call, which was given buffers holding this many bytes.
+	pub fn record(&self, bytes: usize) {
+		self.calls.fetch_add(1, Ordering::Relaxed);
+		if bytes != 0 {
+			self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+		}
+	}
+
+	/// Work out how many calls we got in the last second.
+	fn sample(&self) {
+		let calls = self.calls.load(Ordering::Relaxed);
+		let before = self.calls_last_second.swap(calls, Ordering::Relaxed);
+		self.rate
+			.store(calls.saturating_sub(before), Ordering::Relaxed);
+	}
+
+	fn reset(&self) {
+		self.calls.store(0, Ordering::Relaxed);
+		self.bytes.store(0, Ordering::Relaxed);
+		self.rate.store(0, Ordering::Relaxed);
+		self.calls_last_second.store(0, Ordering::Relaxed);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
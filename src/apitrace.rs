@@ -0,0 +1,506 @@
+//! # Tracing every BIOS call
+//!
+//! The OS gets a copy of the BIOS API where every function counts its calls
+//! (see [`crate::apistats`]) and then calls the real one. With `--trace-api`,
+//! each function also writes a line to a trace as it returns, with a sequence
+//! number, its arguments and its result. Buffers are shown by their length, not their
+//! contents. Functions that don't return, like `power_control`, are traced as
+//! they're called instead.
+//!
+//! The wrappers are made by the `traced_api` macro, from a list of every
+//! function in the API. The list fills in a whole [`common::Api`], so the
+//! compiler tells us if we've missed one.
+//!
+//! Every call, traced or not, goes through a wrapper, so the wrappers also do
+//! what every BIOS function needs: they feed the watchdog and keep to the
+//! `--cpu-throttle` pace. The functions marked `#[checkpoint]` in the list
+//! are the ones the OS calls while it waits for something, which is where
+//! it stops when we pause it.
+//!
+//! Some functions are called thousands of times a second, so each function
+//! only gets so many lines a second (see `--trace-api-limit`), and you can
+//! leave some out entirely with `--trace-api-exclude`. Every call still gets a
+//! sequence number, so gaps in the numbers show where calls were left out.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use neotron_common_bios as common;
+
+use crate::trace::Trace;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// How to show an argument or result in the trace.
+trait Show {
+	fn show(&self) -> String;
+
+	/// How many bytes of buffer this is, for the statistics.
+	fn bytes(&self) -> usize {
+		0
+	}
+}
+
+/// Keeps one function to so many lines a second.
+struct Limiter {
+	/// Which second (since we started) we're counting lines in
+	second: AtomicU64,
+	/// How many lines we've written this second
+	lines: AtomicU32,
+	/// How many calls we've left out since the last line we wrote
+	skipped: AtomicU64,
+}
+
+/// A call we're going to write to the trace when it returns.
+struct Call {
+	sequence: u64,
+	name: &'static str,
+	args: String,
+	skipped: u64,
+}
+
+// -----------------------------------------------------------------------------
+// Macros
+// -----------------------------------------------------------------------------
+
+/// Make a wrapper for each BIOS function that counts and traces its calls,
+/// and an API full of them.
+///
+/// Mark a function `#[checkpoint]` to have its wrapper call
+/// [`crate::pause::checkpoint`] too.
+macro_rules! traced_api {
+	(
+		returns {
+			$($(#[$pause:ident])? fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*
+		}
+		never_returns {
+			$(fn $nr_name:ident($($nr_arg:ident: $nr_ty:ty),*);)*
+		}
+	) => {
+		$(
+			mod $name {
+				pub static COUNTERS: crate::apistats::Counters = crate::apistats::Counters::new();
+				pub static LIMITER: super::Limiter = super::Limiter::new();
+			}
+
+			extern "C" fn $name($($arg: $ty),*) -> $ret {
+				crate::watchdog::feed();
+				crate::throttle::pace();
+				$(crate::pause::$pause();)?
+				$name::COUNTERS.record(0 $(+ $arg.bytes())*);
+				let call = start(stringify!($name), &$name::LIMITER, || {
+					let args: Vec<String> = vec![$(format!("{}={}", stringify!($arg), $arg.show())),*];
+					args.join(", ")
+				});
+				let span = crate::timeline::span(stringify!($name), "bios");
+				// Some of these are unsafe functions, and the OS has promised
+				// to call them properly
+				#[allow(unused_unsafe)]
+				let result = unsafe { (crate::BIOS_API.$name)($($arg),*) };
+				drop(span);
+				if let Some(call) = call {
+					finish(call, &result.show());
+				}
+				result
+			}
+		)*
+
+		$(
+			mod $nr_name {
+				pub static COUNTERS: crate::apistats::Counters = crate::apistats::Counters::new();
+				pub static LIMITER: super::Limiter = super::Limiter::new();
+			}
+
+			extern "C" fn $nr_name($($nr_arg: $nr_ty),*) -> ! {
+				crate::watchdog::feed();
+				crate::throttle::pace();
+				$nr_name::COUNTERS.record(0 $(+ $nr_arg.bytes())*);
+				let call = start(stringify!($nr_name), &$nr_name::LIMITER, || {
+					let args: Vec<String> = vec![$(format!("{}={}", stringify!($nr_arg), $nr_arg.show())),*];
+					args.join(", ")
+				});
+				if let Some(call) = call {
+					finish(call, "(doesn't return)");
+				}
+				crate::timeline::instant(stringify!($nr_name), "bios");
+				(crate::BIOS_API.$nr_name)($($nr_arg),*)
+			}
+		)*
+
+		/// The BIOS API, with every function counted and traced.
+		pub static API: common::Api = common::Api {
+			$($name,)*
+			$($nr_name,)*
+		};
+
+		/// The name of every function in the BIOS API.
+		pub const NAMES: &[&str] = &[$(stringify!($name),)* $(stringify!($nr_name),)*];
+
+		/// The call counters for every function in the BIOS API.
+		pub static COUNTERS: &[(&str, &crate::apistats::Counters)] = &[
+			$((stringify!($name), &$name::COUNTERS),)*
+			$((stringify!($nr_name), &$nr_name::COUNTERS),)*
+		];
+	};
+}
+
+/// Show these types the way `Debug` does.
+macro_rules! show_with_debug {
+	($($ty:ty),* $(,)?) => {
+		$(
+			impl Show for $ty {
+				fn show(&self) -> String {
+					format!("{:?}", self)
+				}
+			}
+		)*
+	};
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+traced_api! {
+	returns {
+		fn api_version_get() -> common::Version;
+		fn bios_version_get() -> common::FfiString<'static>;
+		fn serial_get_info(device_id: u8) -> common::FfiOption<common::serial::DeviceInfo>;
+		fn serial_configure(device_id: u8, config: common::serial::Config) -> common::ApiResult<()>;
+		fn serial_write(
+			device_id: u8,
+			data: common::FfiByteSlice,
+			timeout: common::FfiOption<common::Timeout>
+		) -> common::ApiResult<usize>;
+		#[checkpoint]
+		fn serial_read(
+			device_id: u8,
+			data: common::FfiBuffer,
+			timeout: common::FfiOption<common::Timeout>
+		) -> common::ApiResult<usize>;
+		fn time_clock_get() -> common::Time;
+		fn time_clock_set(time: common::Time) -> ();
+		#[checkpoint]
+		fn time_ticks_get() -> common::Ticks;
+		fn time_ticks_per_second() -> common::Ticks;
+		fn configuration_get(buffer: common::FfiBuffer) -> common::ApiResult<usize>;
+		fn configuration_set(buffer: common::FfiByteSlice) -> common::ApiResult<()>;
+		fn video_is_valid_mode(mode: common::video::Mode) -> bool;
+		fn video_mode_needs_vram(mode: common::video::Mode) -> bool;
+		fn video_set_mode(mode: common::video::Mode, vram: *mut u32) -> common::ApiResult<()>;
+		fn video_get_mode() -> common::video::Mode;
+		fn video_get_framebuffer() -> *mut u32;
+		#[checkpoint]
+		fn video_wait_for_line(line: u16) -> ();
+		fn video_get_palette(palette_idx: u8) -> common::FfiOption<common::video::RGBColour>;
+		fn video_set_palette(palette_idx: u8, colour: common::video::RGBColour) -> ();
+		fn video_set_whole_palette(start: *const common::video::RGBColour, length: usize) -> ();
+		fn memory_get_region(region_index: u8) -> common::FfiOption<common::MemoryRegion>;
+		#[checkpoint]
+		fn hid_get_event() -> common::ApiResult<common::FfiOption<common::hid::HidEvent>>;
+		fn hid_set_leds(leds: common::hid::KeyboardLeds) -> common::ApiResult<()>;
+		fn i2c_bus_get_info(bus_id: u8) -> common::FfiOption<common::i2c::BusInfo>;
+		fn i2c_write_read(
+			bus_id: u8,
+			i2c_device_address: u8,
+			tx: common::FfiByteSlice,
+			tx2: common::FfiByteSlice,
+			rx: common::FfiBuffer
+		) -> common::ApiResult<()>;
+		fn audio_mixer_channel_get_info(
+			audio_mixer_id: u8
+		) -> common::FfiOption<common::audio::MixerChannelInfo>;
+		fn audio_mixer_channel_set_level(audio_mixer_id: u8, level: u8) -> common::ApiResult<()>;
+		fn audio_output_set_config(config: common::audio::Config) -> common::ApiResult<()>;
+		fn audio_output_get_config() -> common::ApiResult<common::audio::Config>;
+		#[checkpoint]
+		fn audio_output_data(samples: common::FfiByteSlice) -> common::ApiResult<usize>;
+		#[checkpoint]
+		fn audio_output_get_space() -> common::ApiResult<usize>;
+		fn audio_input_set_config(config: common::audio::Config) -> common::ApiResult<()>;
+		fn audio_input_get_config() -> common::ApiResult<common::audio::Config>;
+		#[checkpoint]
+		fn audio_input_data(samples: common::FfiBuffer) -> common::ApiResult<usize>;
+		fn audio_input_get_count() -> common::ApiResult<usize>;
+		fn bus_select(peripheral_id: common::FfiOption<u8>) -> ();
+		fn bus_get_info(peripheral_id: u8) -> common::FfiOption<common::bus::PeripheralInfo>;
+		fn bus_write_read(
+			tx: common::FfiByteSlice,
+			tx2: common::FfiByteSlice,
+			rx: common::FfiBuffer
+		) -> common::ApiResult<()>;
+		fn bus_exchange(buffer: common::FfiBuffer) -> common::ApiResult<()>;
+		fn bus_interrupt_status() -> u32;
+		fn block_dev_get_info(device_id: u8) -> common::FfiOption<common::block_dev::DeviceInfo>;
+		fn block_dev_eject(device_id: u8) -> common::ApiResult<()>;
+		fn block_write(
+			device_id: u8,
+			start_block: common::block_dev::BlockIdx,
+			num_blocks: u8,
+			data: common::FfiByteSlice
+		) -> common::ApiResult<()>;
+		fn block_read(
+			device_id: u8,
+			start_block: common::block_dev::BlockIdx,
+			num_blocks: u8,
+			data: common::FfiBuffer
+		) -> common::ApiResult<()>;
+		fn block_verify(
+			device_id: u8,
+			start_block: common::block_dev::BlockIdx,
+			num_blocks: u8,
+			data: common::FfiByteSlice
+		) -> common::ApiResult<()>;
+		#[checkpoint]
+		fn power_idle() -> ();
+		fn compare_and_swap_bool(
+			value: &std::sync::atomic::AtomicBool,
+			old_value: bool,
+			new_value: bool
+		) -> bool;
+	}
+	never_returns {
+		fn power_control(mode: common::FfiPowerMode);
+	}
+}
+
+/// Where the trace goes, once it's turned on.
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// Whether we're writing a trace.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The functions to leave out of the trace.
+static EXCLUDE: OnceLock<Vec<String>> = OnceLock::new();
+
+/// The most lines each function gets a second, or zero for no limit.
+static LIMIT: AtomicU32 = AtomicU32::new(0);
+
+/// The sequence number of the next call.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// When we started, for the rate limit.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Trace every BIOS call from now on.
+pub fn start_trace(trace: Trace, exclude: Vec<String>, limit: u32) {
+	*TRACE.lock().unwrap() = Some(trace);
+	let _ = EXCLUDE.set(exclude);
+	LIMIT.store(limit, Ordering::Relaxed);
+	EPOCH.get_or_init(Instant::now);
+	ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Parse the name of a BIOS function, for `--trace-api-exclude`.
+pub fn parse_name(name: &str) -> Result<String, String> {
+	if NAMES.contains(&name) {
+		Ok(name.to_string())
+	} else {
+		Err(format!("{:?} is not a BIOS function", name))
+	}
+}
+
+/// A function has been called. Move the virtual clock on (if we have one),
+/// and if we're going to trace the call, number it and describe its
+/// arguments.
+fn start(name: &'static str, limiter: &Limiter, args: impl FnOnce() -> String) -> Option<Call> {
+	crate::clock::bios_called();
+	let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+	if !ENABLED.load(Ordering::Relaxed) {
+		return None;
+	}
+	if EXCLUDE
+		.get()
+		.is_some_and(|exclude| exclude.iter().any(|n| n == name))
+	{
+		return None;
+	}
+	let skipped = limiter.admit()?;
+	Some(Call {
+		sequence,
+		name,
+		args: args(),
+		skipped,
+	})
+}
+
+/// Write a call to the trace.
+fn finish(call: Call, result: &str) {
+	let mut trace = TRACE.lock().unwrap();
+	let Some(trace) = trace.as_mut() else {
+		return;
+	};
+	let skipped = if call.skipped != 0 {
+		format!(" [{} earlier calls not shown]", call.skipped)
+	} else {
+		String::new()
+	};
+	trace.line(format_args!(
+		"#{:08} {}({}) -> {}{}",
+		call.sequence, call.name, call.args, result, skipped
+	));
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Limiter {
+	const fn new() -> Limiter {
+		Limiter {
+			second: AtomicU64::new(0),
+			lines: AtomicU32::new(0),
+			skipped: AtomicU64::new(0),
+		}
+	}
+
+	/// Can we write another line? If so, returns how many calls we left out
+	/// before this one.
+	fn admit(&self) -> Option<u64> {
+		let limit = LIMIT.load(Ordering::Relaxed);
+		if limit != 0 {
+			let second = EPOCH.get_or_init(Instant::now).elapsed().as_secs();
+			if self.second.swap(second, Ordering::Relaxed) != second {
+				self.lines.store(0, Ordering::Relaxed);
+			}
+			if self.lines.fetch_add(1, Ordering::Relaxed) >= limit {
+				self.skipped.fetch_add(1, Ordering::Relaxed);
+				return None;
+			}
+		}
+		Some(self.skipped.swap(0, Ordering::Relaxed))
+	}
+}
+
+show_with_debug!(
+	u8,
+	u16,
+	u32,
+	usize,
+	bool,
+	(),
+	common::Version,
+	common::Time,
+	common::Ticks,
+	common::Timeout,
+	common::Error,
+	common::MemoryRegion,
+	common::serial::Config,
+	common::serial::DeviceInfo,
+	common::video::Mode,
+	common::video::RGBColour,
+	common::hid::HidEvent,
+	common::hid::KeyboardLeds,
+	common::i2c::BusInfo,
+	common::audio::Config,
+	common::audio::MixerChannelInfo,
+	common::bus::PeripheralInfo,
+	common::block_dev::BlockIdx,
+	common::block_dev::DeviceInfo,
+);
+
+impl<T: Show> Show for common::FfiOption<T> {
+	fn show(&self) -> String {
+		match self {
+			common::FfiOption::Some(value) => format!("Some({})", value.show()),
+			common::FfiOption::None => "None".to_string(),
+		}
+	}
+}
+
+impl<T: Show> Show for common::ApiResult<T> {
+	fn show(&self) -> String {
+		match self {
+			common::ApiResult::Ok(value) => format!("Ok({})", value.show()),
+			common::ApiResult::Err(e) => format!("Err({})", e.show()),
+		}
+	}
+}
+
+impl Show for common::FfiString<'_> {
+	fn show(&self) -> String {
+		format!("{:?}", self.as_str())
+	}
+}
+
+impl Show for common::FfiBuffer<'_> {
+	fn show(&self) -> String {
+		format!("{} bytes", self.bytes())
+	}
+
+	// We don't look inside, as the BIOS function hasn't checked it yet
+	fn bytes(&self) -> usize {
+		self.data_len
+	}
+}
+
+impl Show for common::FfiByteSlice<'_> {
+	fn show(&self) -> String {
+		format!("{} bytes", self.bytes())
+	}
+
+	fn bytes(&self) -> usize {
+		self.data_len
+	}
+}
+
+impl Show for common::FfiPowerMode {
+	fn show(&self) -> String {
+		match self.make_safe() {
+			Ok(mode) => format!("{:?}", mode),
+			Err(_) => format!("{:?}", self),
+		}
+	}
+}
+
+impl<T> Show for *mut T {
+	fn show(&self) -> String {
+		format!("{:p}", *self)
+	}
+}
+
+impl<T> Show for *const T {
+	fn show(&self) -> String {
+		format!("{:p}", *self)
+	}
+}
+
+impl Show for &std::sync::atomic::AtomicBool {
+	fn show(&self) -> String {
+		format!("{:p}", *self)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
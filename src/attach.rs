@@ -0,0 +1,170 @@
+//! # Waiting for a debugger
+//!
+//! With `--wait`, we load the OS library and bring up the window as usual, but
+//! hold the OS thread back until you've attached a debugger. We print our
+//! process ID, and where the OS library and `os_main` ended up, so you can
+//! work out where its symbols are. Press Enter, or send us `SIGUSR1`, to start
+//! the OS.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Set when it's time to start the OS.
+static GO: AtomicBool = AtomicBool::new(false);
+
+/// Set while the OS is being held back.
+static WAITING: AtomicBool = AtomicBool::new(false);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Say where the OS is, and how to start it, and start listening for the go
+/// signal.
+///
+/// Call this before the window opens, so the details come out first.
+pub fn announce(os_path: &Path, main: crate::OsMain) {
+	WAITING.store(true, Ordering::Relaxed);
+	let main = main as *const u8;
+	println!("Waiting for a debugger (--wait)");
+	println!("  BIOS process ID:   {}", std::process::id());
+	println!("  OS library:        {}", os_path.display());
+	match sys::base_address(main) {
+		Some(base) => {
+			println!("  OS load address:   {:p}", base);
+			println!(
+				"  os_main:           {:p} (load address + {:#x})",
+				main,
+				main as usize - base as usize
+			);
+		}
+		None => println!("  os_main:           {:p}", main),
+	}
+	if sys::listen_for_signal() {
+		println!("Press Enter, or send SIGUSR1 to this process, to start the OS");
+	} else {
+		println!("Press Enter to start the OS");
+	}
+	std::thread::spawn(|| {
+		let mut line = String::new();
+		// If stdin is closed, the signal is the only way to start
+		if std::io::stdin().read_line(&mut line).is_ok_and(|n| n > 0) {
+			GO.store(true, Ordering::Relaxed);
+		}
+	});
+}
+
+/// Hold the OS thread here until we're told to start the OS.
+pub fn wait() {
+	while !GO.load(Ordering::Relaxed) {
+		std::thread::sleep(Duration::from_millis(50));
+	}
+	WAITING.store(false, Ordering::Relaxed);
+	log::info!("Debugger wait over - starting the OS");
+}
+
+/// Are we holding the OS back for a debugger?
+pub fn is_waiting() -> bool {
+	WAITING.load(Ordering::Relaxed)
+}
+
+// -----------------------------------------------------------------------------
+// Host specific code
+// -----------------------------------------------------------------------------
+
+#[cfg(unix)]
+mod sys {
+	use std::sync::atomic::Ordering;
+
+	/// Where the shared library holding this address was loaded.
+	pub fn base_address(address: *const u8) -> Option<*const u8> {
+		let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+		// Safety: dladdr only looks the address up
+		if unsafe { libc::dladdr(address.cast(), &mut info) } == 0 || info.dli_fbase.is_null() {
+			return None;
+		}
+		Some(info.dli_fbase as *const u8)
+	}
+
+	/// Start the OS when we get a `SIGUSR1`.
+	pub fn listen_for_signal() -> bool {
+		extern "C" fn on_signal(_signal: libc::c_int) {
+			// Storing to an atomic is safe in a signal handler
+			super::GO.store(true, Ordering::Relaxed);
+		}
+		let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+		// Safety: the handler only touches an atomic
+		unsafe { libc::signal(libc::SIGUSR1, handler) != libc::SIG_ERR }
+	}
+}
+
+#[cfg(windows)]
+mod sys {
+	use std::ffi::c_void;
+
+	/// Look up the module by an address inside it, and don't keep it loaded.
+	const FLAGS: u32 = 0x4 | 0x2;
+
+	extern "system" {
+		fn GetModuleHandleExW(flags: u32, name: *const c_void, module: *mut *mut c_void) -> i32;
+	}
+
+	/// Where the DLL holding this address was loaded.
+	pub fn base_address(address: *const u8) -> Option<*const u8> {
+		let mut module = std::ptr::null_mut();
+		// Safety: this only looks the address up
+		if unsafe { GetModuleHandleExW(FLAGS, address.cast(), &mut module) } == 0 {
+			return None;
+		}
+		// A module handle is its load address
+		Some(module as *const u8)
+	}
+
+	/// Windows doesn't have `SIGUSR1`.
+	pub fn listen_for_signal() -> bool {
+		false
+	}
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+	pub fn base_address(_address: *const u8) -> Option<*const u8> {
+		None
+	}
+
+	pub fn listen_for_signal() -> bool {
+		false
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
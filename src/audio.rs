@@ -0,0 +1,1363 @@
+//! # Audio support for the Neotron Desktop BIOS
+//!
+//! The OS pushes samples into a FIFO with `audio_output_data`. The host audio
+//! callback (running on SDL's audio thread) pulls them back out, converts them
+//! to floating point and plays them. Audio input works the same way, but in
+//! reverse.
+//!
+//! There are two mixer channels - the output master volume and the input gain.
+//!
+//! If there's no host audio device (or `--audio=null` is given), a null device
+//! runs the same callbacks from a timer thread, so the OS sees the same pacing
+//! as it would with real hardware.
+//!
+//! Host audio devices can only be opened from the GUI thread, so when the OS
+//! changes the configuration we send a [`Request`] to the GUI thread and wait
+//! for it to re-open the device.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use std::sync::{
+	atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+	mpsc, Arc, Mutex,
+};
+
+use log::{info, warn};
+use pix_engine::prelude::*;
+
+use neotron_common_bios as common;
+
+use crate::resample::{Resampler, MAX_CHANNELS};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The sample formats we know how to play.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+	/// 8-bit, signed, mono
+	EightBitMono,
+	/// 8-bit, signed, stereo. Left, then Right.
+	EightBitStereo,
+	/// 16-bit, signed, little-endian, mono
+	SixteenBitMono,
+	/// 16-bit, signed, little-endian, stereo. Left, then Right.
+	SixteenBitStereo,
+}
+
+/// An audio configuration we have checked we can support.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+	pub format: Format,
+	pub sample_rate_hz: u32,
+}
+
+/// Things the OS thread needs the GUI thread to do.
+pub enum Request {
+	/// Re-open the output device with a new configuration.
+	SetOutputConfig {
+		config: Config,
+		reply: mpsc::Sender<Result<(), String>>,
+	},
+	/// Re-open the input device with a new configuration.
+	SetInputConfig {
+		config: Config,
+		reply: mpsc::Sender<Result<(), String>>,
+	},
+}
+
+/// The GUI thread's side of the audio system.
+pub struct Host {
+	requests: mpsc::Receiver<Request>,
+	backend: Backend,
+	output: Option<AudioDevice<Playback>>,
+	input: Option<AudioDevice<Recording>>,
+	/// Used when we have no host output device
+	null_output: Option<NullDevice>,
+	/// Used when we have no host input device
+	null_input: Option<NullDevice>,
+	/// Play this into the input FIFO instead of using a microphone
+	wav_input: Option<WavInput>,
+	/// Set this to stop the thread playing `wav_input`
+	wav_stop: Option<Arc<AtomicBool>>,
+	/// When we last logged the stats, if we're logging them
+	stats_logged_at: Option<std::time::Instant>,
+	/// Part of the name of the host output device to use
+	output_device: Option<String>,
+	/// Part of the name of the host input device to use
+	input_device: Option<String>,
+	/// How much audio the FIFOs hold, in milliseconds
+	latency_ms: u32,
+}
+
+/// Where the audio goes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+	/// The host's audio devices, via SDL
+	Sdl,
+	/// Nowhere, but at the right rate
+	Null,
+}
+
+/// A pretend audio device, which calls an [`AudioCallback`] from a thread.
+///
+/// The thread stops when this is dropped.
+struct NullDevice {
+	stop: Arc<AtomicBool>,
+}
+
+/// How the audio system is set up on the command line.
+pub struct Options {
+	/// Which audio devices to use
+	pub backend: Backend,
+	/// Use this instead of the host's audio input device
+	pub wav_input: Option<WavInput>,
+	/// Log the glitch counters once a second
+	pub log_stats: bool,
+	/// Log the FIFO level history whenever the output underruns
+	pub strict: bool,
+	/// Part of the name of the host output device to use
+	pub output_device: Option<String>,
+	/// Part of the name of the host input device to use
+	pub input_device: Option<String>,
+	/// How much audio the FIFOs hold, in milliseconds
+	pub latency_ms: u32,
+	/// Host output volume, in percent
+	pub volume: u8,
+}
+
+/// Counts of audio glitches since start-up.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+	/// Times the output FIFO ran dry while playing
+	pub output_underruns: u64,
+	/// Calls to `audio_output_data` that couldn't all fit
+	pub output_overruns: u64,
+	/// Calls to `audio_input_data` with nothing to read
+	pub input_underruns: u64,
+	/// Times recorded samples were dropped because the input FIFO was full
+	pub input_overruns: u64,
+}
+
+/// Where the audio output has got to, compared with the host's clock.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Position {
+	/// How many sample frames (at the OS's sample rate) have been played
+	pub frames_played: u64,
+	/// How far the sound card's clock is ahead of the host's clock, in
+	/// milliseconds. Negative if it is behind.
+	pub drift_ms: f64,
+	/// The drift, in parts-per-million of the time we've been playing
+	pub drift_ppm: f64,
+}
+
+/// Tracks the output device's clock.
+struct Clock {
+	/// When the output callback first ran
+	started: Option<std::time::Instant>,
+	/// The output device's sample rate
+	device_rate_hz: u32,
+	/// Frames the output device has asked for since `started`
+	device_frames: u64,
+	/// Frames taken from the output FIFO since the device was opened
+	frames_played: u64,
+	/// When the emulation was paused, if it is
+	paused_at: Option<std::time::Instant>,
+}
+
+/// A WAV file to use as the audio input.
+pub struct WavInput {
+	pub wav: Arc<crate::wav::Wav>,
+	/// Start again when we get to the end?
+	pub looping: bool,
+}
+
+/// Feeds the host audio device from the output FIFO.
+pub struct Playback {
+	/// How many channels the host device has
+	channels: usize,
+	/// The host device's sample rate
+	rate_hz: u32,
+	/// Did we run out of samples last time?
+	starved: bool,
+	/// Converts from the OS's sample rate to the host's
+	resampler: Resampler,
+}
+
+/// Fills the input FIFO from the host audio device.
+pub struct Recording {
+	/// How many channels the host device has
+	channels: usize,
+	/// Converts from the host's sample rate to the OS's
+	resampler: Resampler,
+}
+
+/// An audio stream shared between the OS thread and the audio callback.
+struct Stream {
+	config: Config,
+	/// Raw sample bytes, in the OS's format
+	fifo: VecDeque<u8>,
+	/// Maximum FIFO length, in bytes
+	capacity: usize,
+	/// The FIFO level (in frames) each time the OS sent us samples, oldest
+	/// first
+	history: VecDeque<usize>,
+}
+
+/// One of our audio mixer channels.
+struct MixerChannel {
+	name: &'static str,
+	direction: common::audio::Direction,
+	level: AtomicU8,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The sample rates the OS is allowed to ask for.
+const SUPPORTED_RATES: [u32; 7] = [8000, 11025, 16000, 22050, 24000, 44100, 48000];
+
+/// The configuration used until the OS asks for something else.
+const DEFAULT_CONFIG: Config = Config {
+	format: Format::SixteenBitStereo,
+	sample_rate_hz: 48000,
+};
+
+/// How much audio the FIFOs hold, in milliseconds, unless told otherwise.
+pub const DEFAULT_LATENCY_MS: u32 = 100;
+
+/// How often the null device runs its callback, in milliseconds.
+const NULL_PERIOD_MS: u32 = 5;
+
+/// The smallest latency we'll try for, in milliseconds.
+const MIN_LATENCY_MS: u32 = 2;
+
+/// How long we wait for the GUI thread to re-open a device.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// The highest level any mixer channel goes up to.
+pub const MAX_LEVEL: u8 = 100;
+
+/// Mixer channel for the output master volume.
+const MIXER_OUTPUT: usize = 0;
+
+/// Mixer channel for the input gain.
+const MIXER_INPUT: usize = 1;
+
+static MIXER: [MixerChannel; 2] = [
+	MixerChannel {
+		name: "Master Out",
+		direction: common::audio::Direction::Output,
+		level: AtomicU8::new(MAX_LEVEL),
+	},
+	MixerChannel {
+		name: "Input Gain",
+		direction: common::audio::Direction::Input,
+		level: AtomicU8::new(MAX_LEVEL),
+	},
+];
+
+static OUTPUT: Mutex<Stream> = Mutex::new(Stream::new());
+
+static INPUT: Mutex<Stream> = Mutex::new(Stream::new());
+
+/// How many FIFO levels we remember for `--strict-audio`.
+const HISTORY_LENGTH: usize = 32;
+
+static OUTPUT_UNDERRUNS: AtomicU64 = AtomicU64::new(0);
+
+static OUTPUT_OVERRUNS: AtomicU64 = AtomicU64::new(0);
+
+static INPUT_UNDERRUNS: AtomicU64 = AtomicU64::new(0);
+
+static INPUT_OVERRUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Set by `--strict-audio`
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// The host's own output volume, in percent, from `--volume`.
+///
+/// This is applied after the OS's mixer, and the OS can't see or change it.
+static HOST_VOLUME: AtomicU8 = AtomicU8::new(100);
+
+/// Set when the user has muted the emulator from the host.
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+/// Set while the emulation is paused, to hold up the threads that pretend to
+/// be audio devices.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// The output device's clock.
+static CLOCK: Mutex<Clock> = Mutex::new(Clock::new());
+
+/// Where we send requests to the GUI thread.
+static REQUESTS: Mutex<Option<mpsc::Sender<Request>>> = Mutex::new(None);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Create the GUI thread's half of the audio system.
+pub fn init(options: Options) -> Host {
+	let (sender, receiver) = mpsc::channel();
+	*REQUESTS.lock().unwrap() = Some(sender);
+	STRICT.store(options.strict, Ordering::Relaxed);
+	HOST_VOLUME.store(options.volume.min(100), Ordering::Relaxed);
+	Host {
+		requests: receiver,
+		backend: options.backend,
+		output: None,
+		input: None,
+		null_output: None,
+		null_input: None,
+		wav_input: options.wav_input,
+		wav_stop: None,
+		stats_logged_at: options.log_stats.then(std::time::Instant::now),
+		output_device: options.output_device,
+		input_device: options.input_device,
+		latency_ms: options.latency_ms,
+	}
+}
+
+/// Parse an audio latency from the command line, like `20ms` or `20`.
+///
+/// Values too small to be useful are rounded up, with a warning.
+pub fn parse_latency(text: &str) -> Result<u32, String> {
+	let number = text.trim().trim_end_matches("ms").trim_end();
+	let latency_ms: u32 = number
+		.parse()
+		.map_err(|_| format!("{:?} isn't a latency in milliseconds (e.g. 20ms)", text))?;
+	if latency_ms < MIN_LATENCY_MS {
+		warn!(
+			"Audio latency of {} ms is too small, using {} ms",
+			latency_ms, MIN_LATENCY_MS
+		);
+		return Ok(MIN_LATENCY_MS);
+	}
+	Ok(latency_ms)
+}
+
+/// Print the names of the host audio devices.
+pub fn list_devices() {
+	println!("Audio output devices:");
+	for name in device_names(false) {
+		println!("  {}", name);
+	}
+	println!("Audio input devices:");
+	for name in device_names(true) {
+		println!("  {}", name);
+	}
+}
+
+/// Check we can start the host's audio, and that `--audio-device` and
+/// `--audio-input-device` (if given) match one of its devices.
+///
+/// Returns the devices we'd use. While running, a device that doesn't match
+/// just gets a warning and the default device, but `--check` wants to know.
+pub fn probe(
+	backend: Backend,
+	output_device: Option<&str>,
+	input_device: Option<&str>,
+) -> Result<String, String> {
+	if backend == Backend::Null {
+		return Ok(String::from("null backend, no host devices"));
+	}
+	use sdl2::sys;
+	unsafe {
+		if sys::SDL_InitSubSystem(sys::SDL_INIT_AUDIO) != 0 {
+			return Err(String::from("failed to start SDL audio"));
+		}
+		sys::SDL_QuitSubSystem(sys::SDL_INIT_AUDIO);
+	}
+	let mut chosen = Vec::new();
+	for (pattern, capture, what) in [
+		(output_device, false, "output"),
+		(input_device, true, "input"),
+	] {
+		match pattern {
+			Some(pattern) => {
+				let name = matching_device(pattern, capture)
+					.ok_or_else(|| format!("no audio {} device matches {:?}", what, pattern))?;
+				chosen.push(format!("{} {:?}", what, name));
+			}
+			None => chosen.push(format!("default {}", what)),
+		}
+	}
+	Ok(chosen.join(", "))
+}
+
+/// Get the names of the host's audio input or output devices.
+///
+/// We talk to SDL directly because pix-engine doesn't expose this. SDL counts
+/// how many times the audio subsystem has been started, so this is fine to
+/// call whether or not the GUI is running.
+fn device_names(capture: bool) -> Vec<String> {
+	use sdl2::sys;
+	let is_capture = i32::from(capture);
+	let mut names = Vec::new();
+	unsafe {
+		if sys::SDL_InitSubSystem(sys::SDL_INIT_AUDIO) != 0 {
+			warn!("Failed to start SDL audio");
+			return names;
+		}
+		for idx in 0..sys::SDL_GetNumAudioDevices(is_capture) {
+			let name = sys::SDL_GetAudioDeviceName(idx, is_capture);
+			if !name.is_null() {
+				names.push(
+					std::ffi::CStr::from_ptr(name)
+						.to_string_lossy()
+						.into_owned(),
+				);
+			}
+		}
+		sys::SDL_QuitSubSystem(sys::SDL_INIT_AUDIO);
+	}
+	names
+}
+
+/// Find the full name of the first host device whose name contains `pattern`
+/// (ignoring case).
+///
+/// If there's no match we warn and return `None`, which means the default
+/// device.
+fn find_device(pattern: Option<&str>, capture: bool) -> Option<String> {
+	let pattern = pattern?;
+	let found = matching_device(pattern, capture);
+	if found.is_none() {
+		warn!(
+			"No audio {} device matches {:?}, using the default",
+			if capture { "input" } else { "output" },
+			pattern
+		);
+	}
+	found
+}
+
+/// The full name of the first host device whose name contains `pattern`
+/// (ignoring case), if there is one.
+fn matching_device(pattern: &str, capture: bool) -> Option<String> {
+	let lower_pattern = pattern.to_lowercase();
+	device_names(capture)
+		.into_iter()
+		.find(|name| name.to_lowercase().contains(&lower_pattern))
+}
+
+/// Mute or un-mute the host output. Returns `true` if we're now muted.
+pub fn toggle_mute() -> bool {
+	!MUTED.fetch_xor(true, Ordering::Relaxed)
+}
+
+/// Is the host output muted?
+pub fn is_muted() -> bool {
+	MUTED.load(Ordering::Relaxed)
+}
+
+/// Get the audio output position, if the output has started running.
+///
+/// The sound card's clock won't run at exactly the same rate as the host's
+/// clock (which is what `time_ticks_get` uses), so this also reports how far
+/// they have drifted apart.
+pub fn position() -> Option<Position> {
+	let clock = CLOCK.lock().unwrap();
+	let started = clock.started?;
+	let now = clock.paused_at.unwrap_or_else(std::time::Instant::now);
+	let wall_secs = now.duration_since(started).as_secs_f64();
+	let device_secs = clock.device_frames as f64 / f64::from(clock.device_rate_hz);
+	let drift_secs = device_secs - wall_secs;
+	Some(Position {
+		frames_played: clock.frames_played,
+		drift_ms: drift_secs * 1000.0,
+		drift_ppm: if wall_secs > 0.0 {
+			drift_secs * 1_000_000.0 / wall_secs
+		} else {
+			0.0
+		},
+	})
+}
+
+/// Get the audio glitch counters.
+pub fn stats() -> Stats {
+	Stats {
+		output_underruns: OUTPUT_UNDERRUNS.load(Ordering::Relaxed),
+		output_overruns: OUTPUT_OVERRUNS.load(Ordering::Relaxed),
+		input_underruns: INPUT_UNDERRUNS.load(Ordering::Relaxed),
+		input_overruns: INPUT_OVERRUNS.load(Ordering::Relaxed),
+	}
+}
+
+/// Change the output configuration.
+///
+/// Blocks until the GUI thread has re-opened the host device.
+pub fn set_output_config(config: &common::audio::Config) -> Result<(), common::Error> {
+	let config = Config::try_from(config)?;
+	send_request(|reply| Request::SetOutputConfig { config, reply })
+}
+
+/// Change the input configuration.
+///
+/// Blocks until the GUI thread has re-opened the host device.
+pub fn set_input_config(config: &common::audio::Config) -> Result<(), common::Error> {
+	let config = Config::try_from(config)?;
+	send_request(|reply| Request::SetInputConfig { config, reply })
+}
+
+/// Ask the GUI thread to do something, and wait for it to be done.
+fn send_request<F>(make_request: F) -> Result<(), common::Error>
+where
+	F: FnOnce(mpsc::Sender<Result<(), String>>) -> Request,
+{
+	let (reply, response) = mpsc::channel();
+	{
+		let requests = REQUESTS.lock().unwrap();
+		let Some(requests) = requests.as_ref() else {
+			return Err(common::Error::DeviceError);
+		};
+		requests
+			.send(make_request(reply))
+			.map_err(|_| common::Error::DeviceError)?;
+	}
+	match response.recv_timeout(REQUEST_TIMEOUT) {
+		Ok(Ok(())) => Ok(()),
+		Ok(Err(e)) => {
+			warn!("Host rejected audio config: {}", e);
+			Err(common::Error::UnsupportedConfiguration)
+		}
+		Err(_) => {
+			warn!("Timed out re-opening an audio device");
+			Err(common::Error::DeviceError)
+		}
+	}
+}
+
+/// Get the current output configuration.
+pub fn output_config() -> common::audio::Config {
+	OUTPUT.lock().unwrap().config.into()
+}
+
+/// Copy as many whole sample frames as will fit into the output FIFO.
+///
+/// Returns the number of bytes accepted.
+pub fn output_data(samples: &[u8]) -> usize {
+	let mut output = OUTPUT.lock().unwrap();
+	let frame_size = output.config.format.frame_size();
+	let space = output.capacity.saturating_sub(output.fifo.len());
+	let accepted = samples.len().min(space) / frame_size * frame_size;
+	if accepted < samples.len() / frame_size * frame_size {
+		OUTPUT_OVERRUNS.fetch_add(1, Ordering::Relaxed);
+	}
+	let level = output.fifo.len() / frame_size;
+	if output.history.len() == HISTORY_LENGTH {
+		output.history.pop_front();
+	}
+	output.history.push_back(level);
+	output.fifo.extend(&samples[0..accepted]);
+	accepted
+}
+
+/// How many sample frames can `output_data` accept right now?
+pub fn output_space() -> usize {
+	let output = OUTPUT.lock().unwrap();
+	output.capacity.saturating_sub(output.fifo.len()) / output.config.format.frame_size()
+}
+
+/// Get the current input configuration.
+pub fn input_config() -> common::audio::Config {
+	INPUT.lock().unwrap().config.into()
+}
+
+/// Copy as many whole sample frames as we have (and will fit) from the input
+/// FIFO.
+///
+/// Returns the number of bytes copied.
+pub fn input_data(buffer: &mut [u8]) -> usize {
+	let mut input = INPUT.lock().unwrap();
+	let frame_size = input.config.format.frame_size();
+	let count = buffer.len().min(input.fifo.len()) / frame_size * frame_size;
+	if input.fifo.is_empty() && !buffer.is_empty() {
+		INPUT_UNDERRUNS.fetch_add(1, Ordering::Relaxed);
+	}
+	for (dest, src) in buffer.iter_mut().zip(input.fifo.drain(0..count)) {
+		*dest = src;
+	}
+	count
+}
+
+/// How many sample frames are waiting in the input FIFO?
+pub fn input_count() -> usize {
+	let input = INPUT.lock().unwrap();
+	input.fifo.len() / input.config.format.frame_size()
+}
+
+/// Get information about a mixer channel.
+pub fn mixer_channel_info(id: u8) -> Option<common::audio::MixerChannelInfo> {
+	let channel = MIXER.get(usize::from(id))?;
+	Some(common::audio::MixerChannelInfo {
+		name: common::FfiString::new(channel.name),
+		direction: channel.direction.make_ffi_safe(),
+		max_level: MAX_LEVEL,
+		current_level: channel.level.load(Ordering::Relaxed),
+	})
+}
+
+/// Set the level of a mixer channel.
+///
+/// Levels above [`MAX_LEVEL`] are treated as [`MAX_LEVEL`].
+pub fn set_mixer_level(id: u8, level: u8) -> Result<(), common::Error> {
+	let channel = MIXER
+		.get(usize::from(id))
+		.ok_or(common::Error::InvalidDevice)?;
+	channel.level.store(level.min(MAX_LEVEL), Ordering::Relaxed);
+	Ok(())
+}
+
+/// Feed a WAV file into the input FIFO, at the input's sample rate.
+///
+/// We work out how many frames should have been sent by now, rather than
+/// counting sleeps, so we don't drift.
+fn play_wav_input(wav: Arc<crate::wav::Wav>, looping: bool, stop: Arc<AtomicBool>) {
+	let sample_rate_hz = INPUT.lock().unwrap().config.sample_rate_hz;
+	let mut resampler = Resampler::new(wav.sample_rate_hz, sample_rate_hz, wav.channels);
+	let mut start = std::time::Instant::now();
+	let mut frames_sent: u128 = 0;
+	let mut position = 0;
+	let mut finished = false;
+	let mut frame = [0f32; MAX_CHANNELS];
+	while !stop.load(Ordering::Relaxed) {
+		std::thread::sleep(std::time::Duration::from_millis(5));
+		wait_while_paused(&mut start, &stop);
+		let frames_due = start.elapsed().as_micros() * u128::from(sample_rate_hz) / 1_000_000;
+		let gain = mixer_gain(MIXER_INPUT);
+		let mut stream = INPUT.lock().unwrap();
+		while frames_sent < frames_due {
+			let got_frame = resampler.pull_frame(&mut frame[0..wav.channels], |dest| {
+				if position >= wav.num_frames() {
+					if !looping {
+						return false;
+					}
+					position = 0;
+				}
+				dest.copy_from_slice(wav.frame(position));
+				position += 1;
+				true
+			});
+			if !got_frame {
+				finished = true;
+				break;
+			}
+			// If the OS isn't reading fast enough, we drop samples like a
+			// real ADC would.
+			let _ = stream.push_frame(&frame[0..wav.channels], gain);
+			frames_sent += 1;
+		}
+		if finished {
+			info!("Audio input WAV file finished");
+			return;
+		}
+	}
+}
+
+/// If the emulation is paused, wait until it isn't (or until `stop` is set).
+///
+/// `start` is moved forward by however long we waited, so threads which work
+/// out how many frames are due from their start time don't try to catch up.
+fn wait_while_paused(start: &mut std::time::Instant, stop: &AtomicBool) {
+	if !PAUSED.load(Ordering::Relaxed) {
+		return;
+	}
+	let paused_at = std::time::Instant::now();
+	while PAUSED.load(Ordering::Relaxed) && !stop.load(Ordering::Relaxed) {
+		std::thread::sleep(std::time::Duration::from_millis(u64::from(NULL_PERIOD_MS)));
+	}
+	*start += paused_at.elapsed();
+}
+
+/// Copy one frame of samples into a frame with a different number of
+/// channels.
+///
+/// Mono is copied to every output channel, and anything is mixed down to mono
+/// by averaging. Otherwise channels are copied across in order, with any extra
+/// output channels left silent.
+fn remix(src: &[f32], dest: &mut [f32]) {
+	match (src.len(), dest.len()) {
+		(1, _) => dest.fill(src[0]),
+		(n, 1) => dest[0] = src.iter().sum::<f32>() / n as f32,
+		_ => {
+			dest.fill(0.0);
+			for (dest, src) in dest.iter_mut().zip(src) {
+				*dest = *src;
+			}
+		}
+	}
+}
+
+/// Get the host's own output gain, as a value between `0.0` and `1.0`.
+fn host_gain() -> f32 {
+	if is_muted() {
+		0.0
+	} else {
+		f32::from(HOST_VOLUME.load(Ordering::Relaxed)) / 100.0
+	}
+}
+
+/// Get the gain for a mixer channel, as a value between `0.0` and `1.0`.
+fn mixer_gain(id: usize) -> f32 {
+	f32::from(MIXER[id].level.load(Ordering::Relaxed)) / f32::from(MAX_LEVEL)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Format {
+	/// How many channels this format has.
+	pub fn channels(self) -> usize {
+		match self {
+			Format::EightBitMono | Format::SixteenBitMono => 1,
+			Format::EightBitStereo | Format::SixteenBitStereo => 2,
+		}
+	}
+
+	/// How many bytes there are in each sample for one channel.
+	pub fn sample_size(self) -> usize {
+		match self {
+			Format::EightBitMono | Format::EightBitStereo => 1,
+			Format::SixteenBitMono | Format::SixteenBitStereo => 2,
+		}
+	}
+
+	/// How many bytes there are in one sample for every channel.
+	pub fn frame_size(self) -> usize {
+		self.channels() * self.sample_size()
+	}
+
+	/// Convert one frame of raw bytes into floating point samples, one per
+	/// channel.
+	pub fn decode_frame(self, bytes: &[u8], out: &mut [f32]) {
+		let sample_size = self.sample_size();
+		for (sample, dest) in bytes.chunks_exact(sample_size).zip(out.iter_mut()) {
+			*dest = match sample_size {
+				1 => f32::from(sample[0] as i8) / 128.0,
+				_ => f32::from(i16::from_le_bytes([sample[0], sample[1]])) / 32768.0,
+			};
+		}
+	}
+
+	/// Convert floating point samples, one per channel, into one frame of raw
+	/// bytes.
+	pub fn encode_frame(self, samples: &[f32], out: &mut [u8]) {
+		let sample_size = self.sample_size();
+		for (sample, dest) in samples.iter().zip(out.chunks_exact_mut(sample_size)) {
+			let sample = sample.clamp(-1.0, 1.0);
+			match sample_size {
+				1 => dest[0] = ((sample * 127.0) as i8) as u8,
+				_ => dest.copy_from_slice(&((sample * 32767.0) as i16).to_le_bytes()),
+			}
+		}
+	}
+}
+
+impl TryFrom<common::audio::FfiSampleFormat> for Format {
+	type Error = common::Error;
+
+	fn try_from(value: common::audio::FfiSampleFormat) -> Result<Self, Self::Error> {
+		match value.make_safe() {
+			Ok(common::audio::SampleFormat::EightBitMono) => Ok(Format::EightBitMono),
+			Ok(common::audio::SampleFormat::EightBitStereo) => Ok(Format::EightBitStereo),
+			Ok(common::audio::SampleFormat::SixteenBitMono) => Ok(Format::SixteenBitMono),
+			Ok(common::audio::SampleFormat::SixteenBitStereo) => Ok(Format::SixteenBitStereo),
+			_ => Err(common::Error::UnsupportedConfiguration),
+		}
+	}
+}
+
+impl From<Format> for common::audio::FfiSampleFormat {
+	fn from(value: Format) -> Self {
+		match value {
+			Format::EightBitMono => common::audio::SampleFormat::EightBitMono,
+			Format::EightBitStereo => common::audio::SampleFormat::EightBitStereo,
+			Format::SixteenBitMono => common::audio::SampleFormat::SixteenBitMono,
+			Format::SixteenBitStereo => common::audio::SampleFormat::SixteenBitStereo,
+		}
+		.make_ffi_safe()
+	}
+}
+
+impl TryFrom<&common::audio::Config> for Config {
+	type Error = common::Error;
+
+	fn try_from(value: &common::audio::Config) -> Result<Self, Self::Error> {
+		let format = Format::try_from(value.sample_format)?;
+		if !SUPPORTED_RATES.contains(&value.sample_rate_hz) {
+			return Err(common::Error::UnsupportedConfiguration);
+		}
+		Ok(Config {
+			format,
+			sample_rate_hz: value.sample_rate_hz,
+		})
+	}
+}
+
+impl From<Config> for common::audio::Config {
+	fn from(value: Config) -> Self {
+		common::audio::Config {
+			sample_format: value.format.into(),
+			sample_rate_hz: value.sample_rate_hz,
+		}
+	}
+}
+
+impl std::fmt::Display for Stats {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"output underruns={} overruns={}, input underruns={} overruns={}",
+			self.output_underruns, self.output_overruns, self.input_underruns, self.input_overruns
+		)
+	}
+}
+
+impl std::fmt::Display for Position {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"played {} frames, clock drift {:+.1} ms ({:+.0} ppm)",
+			self.frames_played, self.drift_ms, self.drift_ppm
+		)
+	}
+}
+
+impl Clock {
+	/// Make a clock for a device that hasn't started yet.
+	const fn new() -> Clock {
+		Clock {
+			started: None,
+			device_rate_hz: 0,
+			device_frames: 0,
+			frames_played: 0,
+			paused_at: None,
+		}
+	}
+
+	/// Stop or restart the clock, so time spent paused doesn't count as
+	/// drift.
+	fn set_paused(&mut self, paused: bool) {
+		match (paused, self.paused_at) {
+			(true, None) => self.paused_at = Some(std::time::Instant::now()),
+			(false, Some(paused_at)) => {
+				if let Some(started) = self.started.as_mut() {
+					*started += paused_at.elapsed();
+				}
+				self.paused_at = None;
+			}
+			_ => {}
+		}
+	}
+
+	/// Note that the output callback has run.
+	///
+	/// The first callback just starts the clock, because the device fills its
+	/// buffer up front.
+	fn tick(&mut self, device_rate_hz: u32, device_frames: usize, frames_played: usize) {
+		if self.started.is_some() {
+			self.device_frames += device_frames as u64;
+		} else {
+			self.started = Some(std::time::Instant::now());
+			self.device_rate_hz = device_rate_hz;
+		}
+		self.frames_played += frames_played as u64;
+	}
+}
+
+impl Stream {
+	/// Make a new, empty, stream in the default configuration.
+	const fn new() -> Stream {
+		Stream {
+			config: DEFAULT_CONFIG,
+			fifo: VecDeque::new(),
+			capacity: 0,
+			history: VecDeque::new(),
+		}
+	}
+
+	/// Change the configuration and flush the FIFO, which is sized to hold
+	/// `latency_ms` of audio.
+	fn reconfigure(&mut self, config: Config, latency_ms: u32) {
+		self.config = config;
+		self.fifo.clear();
+		self.history.clear();
+		self.capacity = config.frames_for(latency_ms) * config.format.frame_size();
+	}
+
+	/// Make sure the FIFO can hold at least one host buffer's worth of
+	/// frames, otherwise we would glitch on every callback.
+	///
+	/// Returns the FIFO size, in frames.
+	fn fit_host_buffer(&mut self, host_frames: usize) -> usize {
+		let frame_size = self.config.format.frame_size();
+		if self.capacity / frame_size < host_frames {
+			warn!(
+				"Audio latency too small for the host's {} sample buffer, rounding up",
+				host_frames
+			);
+			self.capacity = host_frames * frame_size;
+		}
+		self.capacity / frame_size
+	}
+
+	/// Scale one frame of samples, convert it to our format and add it to the
+	/// FIFO.
+	///
+	/// The channels are converted with [`remix`]. Returns `false` if the FIFO
+	/// was full.
+	fn push_frame(&mut self, samples: &[f32], gain: f32) -> bool {
+		let format = self.config.format;
+		let frame_size = format.frame_size();
+		if self.fifo.len() + frame_size > self.capacity {
+			INPUT_OVERRUNS.fetch_add(1, Ordering::Relaxed);
+			return false;
+		}
+		let mut scaled = [0f32; 2];
+		let scaled = &mut scaled[0..format.channels()];
+		remix(samples, scaled);
+		for sample in scaled.iter_mut() {
+			*sample *= gain;
+		}
+		let mut raw = [0u8; 4];
+		format.encode_frame(scaled, &mut raw[0..frame_size]);
+		self.fifo.extend(&raw[0..frame_size]);
+		true
+	}
+}
+
+impl Config {
+	/// How many sample frames it takes to fill this many milliseconds.
+	fn frames_for(&self, ms: u32) -> usize {
+		(self.sample_rate_hz as usize * ms as usize / 1000).max(1)
+	}
+
+	/// How many milliseconds this many sample frames lasts.
+	fn ms_for(&self, frames: usize) -> f32 {
+		frames as f32 * 1000.0 / self.sample_rate_hz as f32
+	}
+
+	/// The host buffer size we should ask for - the largest power of two
+	/// that is no more than half the latency, so the FIFO can keep it fed.
+	fn host_buffer_frames(&self, latency_ms: u32) -> u16 {
+		let half = (self.frames_for(latency_ms) / 2).clamp(16, 1 << 15);
+		1 << (usize::BITS - 1 - half.leading_zeros())
+	}
+}
+
+impl Host {
+	/// Open the output device with the default configuration.
+	pub fn start(&mut self, s: &mut PixState) {
+		if let Err(e) = self.open_output(s, DEFAULT_CONFIG) {
+			warn!("No audio output available: {}", e);
+		}
+	}
+
+	/// Handle any requests from the OS thread. Call this regularly from the
+	/// GUI thread.
+	pub fn service(&mut self, s: &mut PixState) {
+		if let Some(logged_at) = self.stats_logged_at {
+			if logged_at.elapsed() >= std::time::Duration::from_secs(1) {
+				info!("Audio: {}", stats());
+				if let Some(position) = position() {
+					info!("Audio: {}", position);
+				}
+				self.stats_logged_at = Some(std::time::Instant::now());
+			}
+		}
+		self.check_devices(s);
+		while let Ok(request) = self.requests.try_recv() {
+			match request {
+				Request::SetOutputConfig { config, reply } => {
+					let old_config = OUTPUT.lock().unwrap().config;
+					let result = self.open_output(s, config);
+					if result.is_err() {
+						// Put things back the way they were
+						let _ = self.open_output(s, old_config);
+					}
+					let _ = reply.send(result);
+				}
+				Request::SetInputConfig { config, reply } => {
+					let result = self.open_input(s, config);
+					let _ = reply.send(result);
+				}
+			}
+		}
+	}
+
+	/// Stop or restart all the audio devices when the emulation is paused or
+	/// resumed.
+	///
+	/// Whatever is in the FIFOs stays there, so playback carries on from
+	/// exactly where it stopped, and the output clock doesn't count the time
+	/// spent paused.
+	pub fn set_paused(&mut self, paused: bool) {
+		PAUSED.store(paused, Ordering::Relaxed);
+		CLOCK.lock().unwrap().set_paused(paused);
+		if let Some(output) = self.output.as_ref() {
+			if paused {
+				output.pause();
+			} else {
+				output.resume();
+			}
+		}
+		if let Some(input) = self.input.as_ref() {
+			if paused {
+				input.pause();
+			} else {
+				input.resume();
+			}
+		}
+	}
+
+	/// Re-open any device that has been unplugged.
+	///
+	/// SDL stops a device when it goes away. Opening it again picks the
+	/// chosen device if it comes back, or the default if not.
+	fn check_devices(&mut self, s: &mut PixState) {
+		let lost = |status: Option<AudioStatus>| status == Some(AudioStatus::Stopped);
+		if lost(self.output.as_ref().map(|d| d.status())) {
+			warn!("Audio output device has gone away - re-opening");
+			let config = OUTPUT.lock().unwrap().config;
+			if let Err(e) = self.open_output(s, config) {
+				warn!("No audio output available: {}", e);
+			}
+		}
+		if lost(self.input.as_ref().map(|d| d.status())) {
+			warn!("Audio input device has gone away - re-opening");
+			let config = INPUT.lock().unwrap().config;
+			if let Err(e) = self.open_input(s, config) {
+				warn!("No audio input available: {}", e);
+			}
+		}
+	}
+
+	/// (Re-)open the output device.
+	///
+	/// The old device is closed first, so nothing is reading the FIFO while we
+	/// flush it and change the format. If there's no host device, we use the
+	/// null device.
+	fn open_output(&mut self, s: &mut PixState, config: Config) -> Result<(), String> {
+		self.output = None;
+		self.null_output = None;
+		OUTPUT.lock().unwrap().reconfigure(config, self.latency_ms);
+		*CLOCK.lock().unwrap() = Clock::new();
+		if self.backend == Backend::Sdl {
+			match self.open_host_output(s, config) {
+				Ok(device) => {
+					self.output = Some(device);
+					return Ok(());
+				}
+				Err(e) => warn!("No host audio output ({}), using the null device", e),
+			}
+		}
+		let playback = Playback::new(config, config.sample_rate_hz, config.format.channels());
+		self.null_output = Some(NullDevice::start(playback, config));
+		let fifo_frames = OUTPUT
+			.lock()
+			.unwrap()
+			.fit_host_buffer(config.frames_for(NULL_PERIOD_MS));
+		info!(
+			"Audio output using the null device at {} Hz, with a {} frame FIFO",
+			config.sample_rate_hz, fifo_frames
+		);
+		Ok(())
+	}
+
+	/// Open the host output device.
+	fn open_host_output(
+		&mut self,
+		s: &mut PixState,
+		config: Config,
+	) -> Result<AudioDevice<Playback>, String> {
+		// We convert the rate and channels ourselves, so let the host pick
+		// whatever it likes.
+		let desired = AudioSpecDesired {
+			freq: None,
+			channels: None,
+			samples: Some(config.host_buffer_frames(self.latency_ms)),
+		};
+		let name = find_device(self.output_device.as_deref(), false);
+		let device = s
+			.open_playback(name.as_deref(), &desired, |spec| {
+				Playback::new(config, spec.freq as u32, usize::from(spec.channels))
+			})
+			.map_err(|e| e.to_string())?;
+		let spec = device.spec();
+		info!("Audio output opened: {:?}", spec);
+		let host_frames = usize::from(spec.samples);
+		let fifo_frames = OUTPUT.lock().unwrap().fit_host_buffer(host_frames);
+		info!(
+			"Audio output latency is {:.1} ms ({} frame FIFO, {} frame host buffer)",
+			config.ms_for(fifo_frames) + host_frames as f32 * 1000.0 / spec.freq as f32,
+			fifo_frames,
+			host_frames
+		);
+		if !PAUSED.load(Ordering::Relaxed) {
+			device.resume();
+		}
+		Ok(device)
+	}
+
+	/// (Re-)open the input device.
+	///
+	/// We don't open the input until the OS asks for it, so we don't turn on
+	/// the microphone unless it's needed. If there's no host device, we use the
+	/// null device, which records silence.
+	fn open_input(&mut self, s: &mut PixState, config: Config) -> Result<(), String> {
+		self.input = None;
+		self.null_input = None;
+		if let Some(stop) = self.wav_stop.take() {
+			stop.store(true, Ordering::Relaxed);
+		}
+		INPUT.lock().unwrap().reconfigure(config, self.latency_ms);
+		if let Some(wav_input) = self.wav_input.as_ref() {
+			// The file is converted to the OS's format as it is played
+			let stop = Arc::new(AtomicBool::new(false));
+			let wav = wav_input.wav.clone();
+			let looping = wav_input.looping;
+			let thread_stop = stop.clone();
+			std::thread::spawn(move || play_wav_input(wav, looping, thread_stop));
+			self.wav_stop = Some(stop);
+			info!(
+				"Audio input playing from {} Hz WAV file at {} Hz",
+				wav_input.wav.sample_rate_hz, config.sample_rate_hz
+			);
+			return Ok(());
+		}
+		if self.backend == Backend::Sdl {
+			match self.open_host_input(s, config) {
+				Ok(device) => {
+					self.input = Some(device);
+					return Ok(());
+				}
+				Err(e) => warn!("No host audio input ({}), using the null device", e),
+			}
+		}
+		let recording = Recording::new(config, config.sample_rate_hz, config.format.channels());
+		self.null_input = Some(NullDevice::start(recording, config));
+		INPUT
+			.lock()
+			.unwrap()
+			.fit_host_buffer(config.frames_for(NULL_PERIOD_MS));
+		info!(
+			"Audio input using the null device at {} Hz",
+			config.sample_rate_hz
+		);
+		Ok(())
+	}
+
+	/// Open the host input device.
+	fn open_host_input(
+		&mut self,
+		s: &mut PixState,
+		config: Config,
+	) -> Result<AudioDevice<Recording>, String> {
+		let desired = AudioSpecDesired {
+			freq: None,
+			channels: None,
+			samples: Some(config.host_buffer_frames(self.latency_ms)),
+		};
+		let name = find_device(self.input_device.as_deref(), true);
+		let device = s
+			.open_capture(name.as_deref(), &desired, |spec| {
+				Recording::new(config, spec.freq as u32, usize::from(spec.channels))
+			})
+			.map_err(|e| e.to_string())?;
+		let spec = device.spec();
+		info!("Audio input opened: {:?}", spec);
+		INPUT
+			.lock()
+			.unwrap()
+			.fit_host_buffer(usize::from(spec.samples));
+		if !PAUSED.load(Ordering::Relaxed) {
+			device.resume();
+		}
+		Ok(device)
+	}
+}
+
+impl Playback {
+	/// Make a callback which plays the OS's audio on a device with the given
+	/// sample rate and number of channels.
+	fn new(config: Config, device_rate_hz: u32, device_channels: usize) -> Playback {
+		Playback {
+			channels: device_channels,
+			rate_hz: device_rate_hz,
+			starved: false,
+			resampler: Resampler::new(
+				config.sample_rate_hz,
+				device_rate_hz,
+				config.format.channels(),
+			),
+		}
+	}
+}
+
+impl Recording {
+	/// Make a callback which records from a device with the given sample rate
+	/// and number of channels.
+	fn new(config: Config, device_rate_hz: u32, device_channels: usize) -> Recording {
+		Recording {
+			channels: device_channels,
+			resampler: Resampler::new(
+				device_rate_hz,
+				config.sample_rate_hz,
+				config.format.channels(),
+			),
+		}
+	}
+}
+
+impl NullDevice {
+	/// Start a thread which calls `callback` as if it were a real device
+	/// running at the configured sample rate.
+	///
+	/// Like [`play_wav_input`], we work out how many frames are due from the
+	/// time since we started, so we don't drift.
+	fn start<CB>(mut callback: CB, config: Config) -> NullDevice
+	where
+		CB: AudioCallback<Channel = f32> + 'static,
+	{
+		let stop = Arc::new(AtomicBool::new(false));
+		let thread_stop = stop.clone();
+		std::thread::spawn(move || {
+			let sample_rate_hz = u128::from(config.sample_rate_hz);
+			let channels = config.format.channels();
+			let mut buffer = Vec::new();
+			let mut start = std::time::Instant::now();
+			let mut frames_done: u128 = 0;
+			while !thread_stop.load(Ordering::Relaxed) {
+				std::thread::sleep(std::time::Duration::from_millis(u64::from(NULL_PERIOD_MS)));
+				wait_while_paused(&mut start, &thread_stop);
+				let frames_due = start.elapsed().as_micros() * sample_rate_hz / 1_000_000;
+				let frames = (frames_due - frames_done) as usize;
+				buffer.clear();
+				buffer.resize(frames * channels, 0.0);
+				callback.callback(&mut buffer);
+				frames_done = frames_due;
+			}
+		});
+		NullDevice { stop }
+	}
+}
+
+impl Drop for NullDevice {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+	}
+}
+
+impl AudioCallback for Playback {
+	type Channel = f32;
+
+	/// Called by SDL when it wants more samples.
+	///
+	/// Each frame is converted from the OS's format to floating point, at the
+	/// host's sample rate and with the host's channel count. If the FIFO runs
+	/// dry, we play silence.
+	fn callback(&mut self, out: &mut [f32]) {
+		let _span = crate::timeline::span("audio output", "audio");
+		let gain = mixer_gain(MIXER_OUTPUT) * host_gain();
+		let mut output = OUTPUT.lock().unwrap();
+		let format = output.config.format;
+		let frame_size = format.frame_size();
+		let mut raw = [0u8; 4];
+		let mut resampled = [0f32; MAX_CHANNELS];
+		let resampled = &mut resampled[0..format.channels()];
+		let mut ran_dry = false;
+		let mut frames_played = 0;
+		for frame in out.chunks_mut(self.channels) {
+			let got_frame = self.resampler.pull_frame(resampled, |decoded| {
+				if output.fifo.len() < frame_size {
+					return false;
+				}
+				for b in raw.iter_mut().take(frame_size) {
+					*b = output.fifo.pop_front().unwrap_or_default();
+				}
+				format.decode_frame(&raw[0..frame_size], decoded);
+				frames_played += 1;
+				true
+			});
+			if got_frame {
+				remix(resampled, frame);
+				for sample in frame.iter_mut() {
+					*sample *= gain;
+				}
+				self.starved = false;
+			} else {
+				frame.fill(0.0);
+				ran_dry = true;
+			}
+		}
+		CLOCK
+			.lock()
+			.unwrap()
+			.tick(self.rate_hz, out.len() / self.channels, frames_played);
+		// Only count the moment we run dry, not every callback after the OS
+		// has stopped playing.
+		if ran_dry && !self.starved {
+			self.starved = true;
+			OUTPUT_UNDERRUNS.fetch_add(1, Ordering::Relaxed);
+			if STRICT.load(Ordering::Relaxed) {
+				warn!(
+					"Audio output underrun. FIFO level (frames) at each audio_output_data call, oldest first: {:?}",
+					output.history
+				);
+			}
+		}
+	}
+}
+
+impl AudioCallback for Recording {
+	type Channel = f32;
+
+	/// Called by SDL when it has samples for us.
+	///
+	/// Each frame is converted to the OS's channel count and sample rate. If
+	/// the FIFO is full, the samples are dropped.
+	fn callback(&mut self, input: &mut [f32]) {
+		let _span = crate::timeline::span("audio input", "audio");
+		let gain = mixer_gain(MIXER_INPUT);
+		let mut stream = INPUT.lock().unwrap();
+		let mut remixed = [0f32; MAX_CHANNELS];
+		let remixed = &mut remixed[0..stream.config.format.channels()];
+		for frame in input.chunks(self.channels) {
+			remix(frame, remixed);
+			self.resampler.push_frame(remixed, |resampled| {
+				let _ = stream.push_frame(resampled, gain);
+			});
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
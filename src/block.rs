@@ -0,0 +1,343 @@
+//! # Block Devices
+//!
+//! The BIOS functions for disks. We have one drive, Block Device 0, which
+//! holds a disk image file (given with `--disk`, or put in later from the
+//! debug console). Block Device 1 can be a transfer device, for moving files
+//! to and from the host - see [`crate::transfer`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{debug, info, warn};
+
+use neotron_common_bios as common;
+
+use crate::hardware::HARDWARE;
+use crate::{heartbeat, lint, timeline, transfer, validate};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// We only have 'normal' sectored emulated disks
+pub const BLOCK_SIZE: usize = 512;
+
+/// Whether the OS is told it can't write to the disk image.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Tell the OS it can't write to the disk image, and refuse if it tries.
+pub fn set_read_only(read_only: bool) {
+	READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+pub extern "C" fn block_dev_get_info(
+	dev_id: u8,
+) -> common::FfiOption<common::block_dev::DeviceInfo> {
+	debug!("block_dev_get_info(dev_id: {})", dev_id);
+	let mut hw_guard = HARDWARE.lock().unwrap();
+	let hw = hw_guard.as_mut().unwrap();
+	if dev_id == 0 {
+		match &mut hw.disk_file {
+			Some(file) => common::FfiOption::Some(common::block_dev::DeviceInfo {
+				name: common::FfiString::new("File0"),
+				device_type: common::block_dev::DeviceType::HardDiskDrive.into(),
+				block_size: BLOCK_SIZE as u32,
+				num_blocks: file.metadata().unwrap().len() / (BLOCK_SIZE as u64),
+				ejectable: false,
+				removable: false,
+				media_present: true,
+				read_only: READ_ONLY.load(Ordering::Relaxed),
+			}),
+			None => common::FfiOption::None,
+		}
+	} else if dev_id == transfer::DEV_ID {
+		match transfer::info() {
+			Some(info) => common::FfiOption::Some(info),
+			None => common::FfiOption::None,
+		}
+	} else {
+		common::FfiOption::None
+	}
+}
+
+pub extern "C" fn block_dev_eject(dev_id: u8) -> common::ApiResult<()> {
+	debug!("block_dev_eject(dev_id: {})", dev_id);
+	common::ApiResult::Ok(())
+}
+
+pub extern "C" fn block_write(
+	dev_id: u8,
+	block_idx: common::block_dev::BlockIdx,
+	num_blocks: u8,
+	buffer: common::FfiByteSlice,
+) -> common::ApiResult<()> {
+	debug!(
+		"block_write(dev_id: {}, block_id: {}, num_blocks: {}, buffer_len: {})",
+		dev_id, block_idx.0, num_blocks, buffer.data_len
+	);
+	let buffer_slice =
+		match validate::bytes("block_write", "its buffer", &buffer).and_then(|buffer| {
+			validate::prefix(
+				"block_write",
+				"its buffer",
+				buffer,
+				usize::from(num_blocks) * BLOCK_SIZE,
+			)
+		}) {
+			Ok(buffer_slice) => buffer_slice,
+			Err(e) => return common::ApiResult::Err(e),
+		};
+	let mut hw_guard = HARDWARE.lock().unwrap();
+	let hw = hw_guard.as_mut().unwrap();
+	lint::check_blocking("block_write");
+	lint::check_block_device("block_write", dev_id, is_present(hw, dev_id));
+	if dev_id == 0 {
+		match &mut hw.disk_file {
+			Some(_) if READ_ONLY.load(Ordering::Relaxed) => {
+				warn!("The OS tried to write to a read-only disk image");
+				common::ApiResult::Err(common::Error::DeviceError)
+			}
+			Some(file) => {
+				let _span = disk_span("disk write", block_idx, num_blocks);
+				if let Err(e) = seek_to_block(file, block_idx, num_blocks) {
+					return common::ApiResult::Err(e);
+				}
+				if let Err(e) = file.write_all(buffer_slice) {
+					log::warn!("Failed to write to disk image: {:?}", e);
+					return common::ApiResult::Err(common::Error::DeviceError);
+				}
+				heartbeat::blocks_written(num_blocks);
+				common::ApiResult::Ok(())
+			}
+			None => common::ApiResult::Err(common::Error::DeviceError),
+		}
+	} else if dev_id == transfer::DEV_ID {
+		transfer::write(block_idx.0, buffer_slice).into()
+	} else {
+		common::ApiResult::Err(common::Error::InvalidDevice)
+	}
+}
+
+pub extern "C" fn block_read(
+	dev_id: u8,
+	block_idx: common::block_dev::BlockIdx,
+	num_blocks: u8,
+	mut buffer: common::FfiBuffer,
+) -> common::ApiResult<()> {
+	debug!(
+		"block_read(dev_id: {}, block_id: {}, num_blocks: {}, buffer_len: {})",
+		dev_id, block_idx.0, num_blocks, buffer.data_len
+	);
+	let buffer_slice =
+		match validate::buffer("block_read", "its buffer", &mut buffer).and_then(|buffer| {
+			validate::prefix_mut(
+				"block_read",
+				"its buffer",
+				buffer,
+				usize::from(num_blocks) * BLOCK_SIZE,
+			)
+		}) {
+			Ok(buffer_slice) => buffer_slice,
+			Err(e) => return common::ApiResult::Err(e),
+		};
+	let mut hw_guard = HARDWARE.lock().unwrap();
+	let hw = hw_guard.as_mut().unwrap();
+	lint::check_blocking("block_read");
+	lint::check_block_device("block_read", dev_id, is_present(hw, dev_id));
+	if dev_id == 0 {
+		match &mut hw.disk_file {
+			Some(file) => {
+				let _span = disk_span("disk read", block_idx, num_blocks);
+				if let Err(e) = seek_to_block(file, block_idx, num_blocks) {
+					return common::ApiResult::Err(e);
+				}
+				if let Err(e) = file.read_exact(buffer_slice) {
+					log::warn!("Failed to read from disk image: {:?}", e);
+					return common::ApiResult::Err(common::Error::DeviceError);
+				}
+				heartbeat::blocks_read(num_blocks);
+				common::ApiResult::Ok(())
+			}
+			None => common::ApiResult::Err(common::Error::DeviceError),
+		}
+	} else if dev_id == transfer::DEV_ID {
+		transfer::read(block_idx.0, buffer_slice).into()
+	} else {
+		common::ApiResult::Err(common::Error::InvalidDevice)
+	}
+}
+
+pub extern "C" fn block_verify(
+	dev_id: u8,
+	block_idx: common::block_dev::BlockIdx,
+	num_blocks: u8,
+	buffer: common::FfiByteSlice,
+) -> common::ApiResult<()> {
+	debug!(
+		"block_verify(dev_id: {}, block_id: {}, num_blocks: {}, buffer_len: {})",
+		dev_id, block_idx.0, num_blocks, buffer.data_len
+	);
+	let buffer_slice =
+		match validate::bytes("block_verify", "its buffer", &buffer).and_then(|buffer| {
+			validate::prefix(
+				"block_verify",
+				"its buffer",
+				buffer,
+				usize::from(num_blocks) * BLOCK_SIZE,
+			)
+		}) {
+			Ok(buffer_slice) => buffer_slice,
+			Err(e) => return common::ApiResult::Err(e),
+		};
+	let mut hw_guard = HARDWARE.lock().unwrap();
+	let hw = hw_guard.as_mut().unwrap();
+	lint::check_blocking("block_verify");
+	lint::check_block_device("block_verify", dev_id, is_present(hw, dev_id));
+	if dev_id == 0 {
+		match &mut hw.disk_file {
+			Some(file) => {
+				let _span = disk_span("disk verify", block_idx, num_blocks);
+				if let Err(e) = seek_to_block(file, block_idx, num_blocks) {
+					return common::ApiResult::Err(e);
+				}
+				let mut read_buffer = vec![0u8; buffer_slice.len()];
+				if let Err(e) = file.read_exact(&mut read_buffer) {
+					log::warn!("Failed to read from disk image: {:?}", e);
+					return common::ApiResult::Err(common::Error::DeviceError);
+				}
+				if read_buffer.as_slice() == buffer_slice {
+					common::ApiResult::Ok(())
+				} else {
+					common::ApiResult::Err(common::Error::DeviceError)
+				}
+			}
+			None => common::ApiResult::Err(common::Error::DeviceError),
+		}
+	} else if dev_id == transfer::DEV_ID {
+		let mut read_buffer = vec![0u8; buffer_slice.len()];
+		match transfer::read(block_idx.0, &mut read_buffer) {
+			Ok(()) if read_buffer.as_slice() == buffer_slice => common::ApiResult::Ok(()),
+			Ok(()) => common::ApiResult::Err(common::Error::DeviceError),
+			Err(e) => common::ApiResult::Err(e),
+		}
+	} else {
+		common::ApiResult::Err(common::Error::InvalidDevice)
+	}
+}
+
+/// Whether there's a block device `dev_id`.
+fn is_present(hw: &crate::hardware::Hardware, dev_id: u8) -> bool {
+	match dev_id {
+		0 => hw.disk_file.is_some(),
+		transfer::DEV_ID => transfer::is_attached(),
+		_ => false,
+	}
+}
+
+/// Get ready to read or write `num_blocks` blocks at `block_idx`, if
+/// they're all inside the disk image.
+///
+/// Seeking past the end of a file works, and then writing makes the file
+/// bigger, so we have to check for ourselves.
+fn seek_to_block(
+	file: &mut std::fs::File,
+	block_idx: common::block_dev::BlockIdx,
+	num_blocks: u8,
+) -> Result<(), common::Error> {
+	let disk_blocks = file
+		.metadata()
+		.map_err(|_| common::Error::DeviceError)?
+		.len() / (BLOCK_SIZE as u64);
+	let in_bounds = block_idx
+		.0
+		.checked_add(u64::from(num_blocks))
+		.is_some_and(|end| end <= disk_blocks);
+	if !in_bounds {
+		return Err(common::Error::BlockOutOfBounds);
+	}
+	file.seek(std::io::SeekFrom::Start(block_idx.0 * BLOCK_SIZE as u64))
+		.map_err(|_| common::Error::DeviceError)?;
+	Ok(())
+}
+
+/// Put a disk operation on the timeline, if we're recording one.
+pub fn disk_span(
+	name: &'static str,
+	block_idx: common::block_dev::BlockIdx,
+	num_blocks: u8,
+) -> Option<timeline::Span> {
+	let mut span = timeline::span(name, "disk")?;
+	span.set_args(format!(
+		"\"block\": {}, \"count\": {}",
+		block_idx.0, num_blocks
+	));
+	Some(span)
+}
+
+/// Swap the disk image for the one at `path`, as if a new disk had been put
+/// in the drive.
+pub fn insert_disk(dev_id: u8, path: &std::path::Path) -> Result<(), String> {
+	if dev_id != 0 {
+		return Err(format!("there is no disk drive {}", dev_id));
+	}
+	let file = crate::disk::open(path).map_err(|e| e.to_string())?;
+	let old = HARDWARE
+		.lock()
+		.unwrap()
+		.as_mut()
+		.unwrap()
+		.disk_file
+		.replace(file);
+	if let Some(old) = old {
+		if let Err(e) = old.sync_all() {
+			warn!("Failed to flush the old disk image: {}", e);
+		}
+	}
+	info!("Disk {} is now {}", dev_id, path.display());
+	Ok(())
+}
+
+/// Take the disk image out of the drive, writing out anything the OS wrote
+/// to it.
+pub fn eject_disk(dev_id: u8) -> Result<(), String> {
+	if dev_id != 0 {
+		return Err(format!("there is no disk drive {}", dev_id));
+	}
+	let old = HARDWARE.lock().unwrap().as_mut().unwrap().disk_file.take();
+	let old = old.ok_or("there is no disk in the drive")?;
+	old.sync_all()
+		.map_err(|e| format!("failed to flush the disk image: {}", e))?;
+	info!("Disk {} ejected", dev_id);
+	Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
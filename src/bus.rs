@@ -0,0 +1,411 @@
+//! # Emulated Neotron Bus peripherals for the Neotron Desktop BIOS
+//!
+//! The Neotron Bus is an SPI bus with a chip-select line for each peripheral.
+//! We emulate a small set of peripherals on it, each of which implements
+//! [`Peripheral`]. The peripheral ID the OS uses is the peripheral's position
+//! in the list, which you give on the command line with
+//! `--bus-device=<type>[:<options>]`.
+//!
+//! Which peripheral is selected is part of the BIOS's hardware state. We're
+//! told when that changes, so peripherals can see their chip-select line
+//! move, and every transfer says which peripheral it is for.
+//!
+//! Each peripheral has an interrupt line. Bit N of `bus_interrupt_status` is
+//! set while peripheral N is asking for attention.
+//!
+//! With `--trace-bus`, we log every transaction. Peripherals can add a note
+//! saying what they think the OS is asking for.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Modules
+// -----------------------------------------------------------------------------
+
+mod flash;
+mod gpio;
+mod loopback;
+mod sdcard;
+mod slot;
+mod timer;
+
+pub use gpio::{lines, set_input, toggle_input};
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use neotron_common_bios as common;
+
+use crate::trace::Trace;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Things that can go wrong on the bus.
+#[derive(Debug)]
+pub enum Error {
+	/// No peripheral is selected
+	NothingSelected,
+}
+
+/// An emulated peripheral on the Neotron Bus.
+pub trait Peripheral: Send {
+	/// What sort of peripheral this looks like to the OS.
+	fn kind(&self) -> common::bus::PeripheralKind;
+
+	/// Our chip-select line has been asserted (`true`) or released
+	/// (`false`).
+	fn chip_select(&mut self, _selected: bool) {}
+
+	/// The OS sent `tx` then `tx2`, ignoring what came back, then clocked in
+	/// enough bytes to fill `rx`.
+	fn write_read(&mut self, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error>;
+
+	/// The OS sent the bytes in `buffer`, and the bytes that came back
+	/// replace them.
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+
+	/// Say what the OS is doing, for `--trace-bus`. `sent` is what it is
+	/// about to send us.
+	fn annotate(&self, _sent: &[u8]) -> Option<String> {
+		None
+	}
+
+	/// We're shutting down, so write out anything we're holding on to.
+	fn flush(&mut self) {}
+
+	/// Is this peripheral's interrupt line asserted?
+	fn interrupt_pending(&mut self) -> bool {
+		false
+	}
+
+	/// Describe this peripheral, for `--list-devices`.
+	fn describe(&self) -> String;
+}
+
+/// The `--bus-device` option.
+#[derive(Debug, Clone)]
+pub enum DeviceSpec {
+	/// A Neotron Bus expansion slot with nothing plugged in
+	Slot,
+	/// A peripheral that sends back what it is sent
+	Loopback,
+	/// A timer that interrupts this many times a second
+	Timer(f64),
+	/// An SD card in SPI mode, using this disk image
+	SdCard(PathBuf),
+	/// A SPI NOR flash chip this big, with its contents in this file
+	Flash(PathBuf, usize),
+	/// Eight inputs and eight outputs, wired to the virtual panel
+	Gpio,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The names of our peripherals. We can't have more peripherals than this.
+const NAMES: [&str; 8] = [
+	"slot0", "slot1", "slot2", "slot3", "slot4", "slot5", "slot6", "slot7",
+];
+
+/// Our peripherals, by peripheral ID.
+static PERIPHERALS: Mutex<Vec<Box<dyn Peripheral>>> = Mutex::new(Vec::new());
+
+/// Where `--trace-bus` goes, if it's turned on.
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// What `bus_interrupt_status` said last time, so we can trace changes.
+static LAST_INTERRUPT_STATUS: AtomicU32 = AtomicU32::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// How many peripherals the bus can have.
+pub fn max_peripherals() -> usize {
+	NAMES.len()
+}
+
+/// Add a peripheral to the bus. It gets the next peripheral ID.
+pub fn add_device(spec: &DeviceSpec) -> Result<(), String> {
+	let mut peripherals = PERIPHERALS.lock().unwrap();
+	if peripherals.len() == NAMES.len() {
+		return Err(format!("the bus can only have {} peripherals", NAMES.len()));
+	}
+	let peripheral: Box<dyn Peripheral> = match spec {
+		DeviceSpec::Slot => Box::new(slot::Slot),
+		DeviceSpec::Loopback => Box::<loopback::Loopback>::default(),
+		DeviceSpec::Timer(rate_hz) => Box::new(timer::Timer::new(*rate_hz)),
+		DeviceSpec::SdCard(path) => Box::new(
+			sdcard::SdCard::open(path)
+				.map_err(|e| format!("failed to open {}: {}", path.display(), e))?,
+		),
+		DeviceSpec::Flash(path, size) => Box::new(
+			flash::Flash::open(path, *size)
+				.map_err(|e| format!("failed to open {}: {}", path.display(), e))?,
+		),
+		DeviceSpec::Gpio => Box::new(gpio::Gpio::new()?),
+	};
+	log::info!(
+		"Bus: peripheral {} is {}",
+		peripherals.len(),
+		peripheral.describe()
+	);
+	peripherals.push(peripheral);
+	Ok(())
+}
+
+/// Get information about a peripheral, or `None` if there is no such
+/// peripheral.
+pub fn info(peripheral_id: u8) -> Option<common::bus::PeripheralInfo> {
+	let peripherals = PERIPHERALS.lock().unwrap();
+	let peripheral = peripherals.get(usize::from(peripheral_id))?;
+	Some(common::bus::PeripheralInfo {
+		name: common::FfiString::new(NAMES[usize::from(peripheral_id)]),
+		kind: peripheral.kind().into(),
+	})
+}
+
+/// List our peripherals, for `--list-devices`.
+pub fn inventory() -> Vec<String> {
+	let peripherals = PERIPHERALS.lock().unwrap();
+	let mut lines = vec![String::from("Neotron Bus peripherals:")];
+	if peripherals.is_empty() {
+		lines.push(String::from("  (none)"));
+	}
+	for (name, peripheral) in NAMES.iter().zip(peripherals.iter()) {
+		lines.push(format!("  {}: {}", name, peripheral.describe()));
+	}
+	lines
+}
+
+/// Start logging every transaction.
+pub fn start_trace(trace: Trace) {
+	*TRACE.lock().unwrap() = Some(trace);
+}
+
+/// Describe every peripheral, for a snapshot.
+pub fn describe_devices() -> Vec<String> {
+	let peripherals = PERIPHERALS.lock().unwrap();
+	NAMES
+		.iter()
+		.zip(peripherals.iter())
+		.map(|(name, peripheral)| format!("Bus {}: {}", name, peripheral.describe()))
+		.collect()
+}
+
+/// Move the chip-select from one peripheral to another.
+///
+/// Returns the peripheral that is now selected. Selecting a peripheral we
+/// don't have selects nothing.
+pub fn select(old: Option<u8>, new: Option<u8>) -> Option<u8> {
+	let mut peripherals = PERIPHERALS.lock().unwrap();
+	let new = new.filter(|id| usize::from(*id) < peripherals.len());
+	if old != new {
+		if let Some(peripheral) = old.and_then(|id| peripherals.get_mut(usize::from(id))) {
+			peripheral.chip_select(false);
+		}
+		if let Some(peripheral) = new.and_then(|id| peripherals.get_mut(usize::from(id))) {
+			peripheral.chip_select(true);
+		}
+	}
+	trace(|trace| match new {
+		Some(id) => trace.line(format_args!("bus: select {}", NAMES[usize::from(id)])),
+		None => trace.line(format_args!("bus: select nothing")),
+	});
+	new
+}
+
+/// Send `tx` then `tx2` to the selected peripheral, then fill `rx`.
+pub fn write_read(selected: Option<u8>, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+	let result = with_selected(selected, |peripheral| {
+		let note = annotate(peripheral, &[tx, tx2].concat());
+		peripheral.write_read(tx, tx2, rx).map(|()| note)
+	});
+	trace(|trace| match &result {
+		Ok(note) => trace.line(format_args!(
+			"bus: {} write_read tx={} tx2={} rx={}{}",
+			NAMES[usize::from(selected.unwrap_or_default())],
+			trace.hex(tx),
+			trace.hex(tx2),
+			trace.hex(rx),
+			note.as_deref().unwrap_or_default()
+		)),
+		Err(e) => trace.line(format_args!("bus: write_read failed: {}", e)),
+	});
+	result.map(|_| ())
+}
+
+/// Exchange bytes with the selected peripheral.
+pub fn exchange(selected: Option<u8>, buffer: &mut [u8]) -> Result<(), Error> {
+	let sent = tracing().then(|| buffer.to_vec());
+	let result = with_selected(selected, |peripheral| {
+		let note = annotate(peripheral, buffer);
+		peripheral.exchange(buffer).map(|()| note)
+	});
+	trace(|trace| match &result {
+		Ok(note) => trace.line(format_args!(
+			"bus: {} exchange tx={} rx={}{}",
+			NAMES[usize::from(selected.unwrap_or_default())],
+			trace.hex(sent.as_deref().unwrap_or_default()),
+			trace.hex(buffer),
+			note.as_deref().unwrap_or_default()
+		)),
+		Err(e) => trace.line(format_args!("bus: exchange failed: {}", e)),
+	});
+	result.map(|_| ())
+}
+
+/// Run a function on the selected peripheral.
+fn with_selected<T, F>(selected: Option<u8>, f: F) -> Result<T, Error>
+where
+	F: FnOnce(&mut dyn Peripheral) -> Result<T, Error>,
+{
+	let mut peripherals = PERIPHERALS.lock().unwrap();
+	let peripheral = selected
+		.and_then(|id| peripherals.get_mut(usize::from(id)))
+		.ok_or(Error::NothingSelected)?;
+	f(peripheral.as_mut())
+}
+
+/// Which peripherals have their interrupt line asserted. Bit N is peripheral
+/// N.
+pub fn interrupt_status() -> u32 {
+	let mut peripherals = PERIPHERALS.lock().unwrap();
+	let mut status = 0;
+	for (id, peripheral) in peripherals.iter_mut().enumerate() {
+		if peripheral.interrupt_pending() {
+			status |= 1 << id;
+		}
+	}
+	let old = LAST_INTERRUPT_STATUS.swap(status, Ordering::Relaxed);
+	if old != status {
+		trace(|trace| {
+			trace.line(format_args!(
+				"bus: interrupt status 0x{:08x} -> 0x{:08x}",
+				old, status
+			))
+		});
+	}
+	status
+}
+
+/// Write out anything the peripherals (and the trace) are holding on to.
+pub fn flush() {
+	for peripheral in PERIPHERALS.lock().unwrap().iter_mut() {
+		peripheral.flush();
+	}
+	trace(Trace::flush);
+}
+
+/// Are we tracing transactions?
+fn tracing() -> bool {
+	TRACE.lock().unwrap().is_some()
+}
+
+/// Write to the trace, if we're tracing.
+fn trace<F>(f: F)
+where
+	F: FnOnce(&mut Trace),
+{
+	if let Some(trace) = TRACE.lock().unwrap().as_mut() {
+		f(trace);
+	}
+}
+
+/// Get a peripheral's note about a transaction, ready to go on the end of a
+/// trace line. We don't bother unless we're tracing.
+fn annotate(peripheral: &dyn Peripheral, sent: &[u8]) -> Option<String> {
+	if !tracing() {
+		return None;
+	}
+	peripheral.annotate(sent).map(|note| format!(" - {}", note))
+}
+
+/// Parse a `--bus-device` option, like `slot`, `loopback`, `timer:10Hz`,
+/// `gpio`, `sdcard:disk.img` or `flash:flash.bin:16MiB`.
+pub fn parse_device(text: &str) -> Result<DeviceSpec, String> {
+	let (kind, options) = match text.split_once(':') {
+		Some((kind, options)) => (kind, Some(options)),
+		None => (text, None),
+	};
+	match (kind, options) {
+		("slot", None) => Ok(DeviceSpec::Slot),
+		("loopback", None) => Ok(DeviceSpec::Loopback),
+		("gpio", None) => Ok(DeviceSpec::Gpio),
+		("timer", Some(rate)) => Ok(DeviceSpec::Timer(parse_rate(rate)?)),
+		("sdcard", Some(path)) => Ok(DeviceSpec::SdCard(PathBuf::from(path))),
+		("flash", Some(options)) => {
+			let (path, size) = options.rsplit_once(':').ok_or_else(|| {
+				format!(
+					"{:?} should be <path>:<size> (e.g. flash.bin:16MiB)",
+					options
+				)
+			})?;
+			Ok(DeviceSpec::Flash(PathBuf::from(path), parse_flash_size(size)?))
+		}
+		_ => Err(format!(
+			"unknown bus device {:?} (try slot, loopback, gpio, timer:<rate>, sdcard:<path> or flash:<path>:<size>)",
+			text
+		)),
+	}
+}
+
+/// Parse how often a timer interrupts, like `10Hz`.
+pub fn parse_rate(text: &str) -> Result<f64, String> {
+	let number = text
+		.strip_suffix("Hz")
+		.or_else(|| text.strip_suffix("hz"))
+		.unwrap_or(text);
+	match number.parse::<f64>() {
+		Ok(rate_hz) if rate_hz > 0.0 && rate_hz.is_finite() => Ok(rate_hz),
+		_ => Err(format!("{:?} is not a rate (try 10Hz)", text)),
+	}
+}
+
+/// Parse how big a flash chip is, like `16MiB`.
+pub fn parse_flash_size(text: &str) -> Result<usize, String> {
+	let size = crate::memory::parse_size(text)?;
+	flash::check_size(size)?;
+	Ok(size)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::NothingSelected => write!(f, "no peripheral selected"),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,409 @@
+//! # SPI NOR flash
+//!
+//! A Winbond W25Qxx-style SPI NOR flash chip, with its contents kept in a
+//! file. We support reading the JEDEC ID (`0x9F`), reading the status register
+//! (`0x05`), write enable and disable (`0x06` and `0x04`), read (`0x03`), fast
+//! read (`0x0B`), page program (`0x02`), 4 KiB sector erase (`0x20`), 64 KiB
+//! block erase (`0xD8`) and chip erase (`0xC7` or `0x60`).
+//!
+//! Like the real chip, a command runs from when the OS selects us until it
+//! deselects us, and programming and erasing happen when we're deselected.
+//! They need a write enable first, and they keep the chip busy for as long as
+//! the datasheet says they typically take (in emulated time). While we're busy
+//! we ignore everything except status reads.
+//!
+//! Programming can only change bits from 1 to 0 - you have to erase a sector
+//! to get them back to 1. If the OS forgets, it gets what a real chip would
+//! give it, and we log a warning.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use neotron_common_bios as common;
+
+use super::{Error, Peripheral};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An emulated SPI NOR flash chip.
+pub struct Flash {
+	/// Where we keep the contents
+	path: PathBuf,
+	/// The same file, open so we can write back what changes
+	file: std::fs::File,
+	/// The contents
+	data: Vec<u8>,
+	/// The Write Enable Latch
+	write_enabled: bool,
+	/// The chip is busy until emulated time gets here
+	busy_until: Duration,
+	/// The command the OS is sending us, if it has started one
+	opcode: Option<u8>,
+	/// How many bytes the OS has sent since it sent the opcode
+	position: usize,
+	/// The address the OS has sent
+	address: usize,
+	/// The data for a page program
+	page: Vec<u8>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Command opcodes
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_WRITE_DISABLE: u8 = 0x04;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_READ: u8 = 0x03;
+const CMD_FAST_READ: u8 = 0x0B;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_BLOCK_ERASE: u8 = 0xD8;
+const CMD_CHIP_ERASE: u8 = 0xC7;
+const CMD_CHIP_ERASE_ALT: u8 = 0x60;
+const CMD_JEDEC_ID: u8 = 0x9F;
+
+/// Status register bits
+const STATUS_BUSY: u8 = 1 << 0;
+const STATUS_WEL: u8 = 1 << 1;
+
+/// Winbond's manufacturer ID, and the W25Q memory type.
+const JEDEC_MANUFACTURER: u8 = 0xEF;
+const JEDEC_MEMORY_TYPE: u8 = 0x40;
+
+/// Addresses are always three bytes.
+const ADDRESS_LEN: usize = 3;
+
+/// Page program wraps around within a page this big.
+const PAGE_SIZE: usize = 256;
+
+/// The smallest thing we can erase.
+const SECTOR_SIZE: usize = 4096;
+
+/// The bigger thing we can erase.
+const BLOCK_SIZE: usize = 65536;
+
+/// The smallest and biggest chips we'll emulate. Bigger chips need four-byte
+/// addresses.
+const MIN_SIZE: usize = 64 * 1024;
+const MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// What erased flash reads as.
+const ERASED: u8 = 0xFF;
+
+/// Typical timings, from the W25Q128JV datasheet.
+const PAGE_PROGRAM_TIME: Duration = Duration::from_micros(400);
+const SECTOR_ERASE_TIME: Duration = Duration::from_millis(45);
+const BLOCK_ERASE_TIME: Duration = Duration::from_millis(150);
+const CHIP_ERASE_TIME_PER_MIB: Duration = Duration::from_millis(2500);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The name of a command, for the bus trace.
+fn opcode_name(opcode: u8) -> &'static str {
+	match opcode {
+		CMD_WRITE_ENABLE => "WRITE ENABLE",
+		CMD_WRITE_DISABLE => "WRITE DISABLE",
+		CMD_READ_STATUS => "READ STATUS",
+		CMD_READ => "READ",
+		CMD_FAST_READ => "FAST READ",
+		CMD_PAGE_PROGRAM => "PAGE PROGRAM",
+		CMD_SECTOR_ERASE => "SECTOR ERASE",
+		CMD_BLOCK_ERASE => "BLOCK ERASE",
+		CMD_CHIP_ERASE | CMD_CHIP_ERASE_ALT => "CHIP ERASE",
+		CMD_JEDEC_ID => "JEDEC ID",
+		_ => "unsupported",
+	}
+}
+
+/// Check a flash chip size is one we can emulate.
+pub fn check_size(size: usize) -> Result<(), String> {
+	if !size.is_power_of_two() || !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+		return Err(format!(
+			"{} bytes is not a flash size (try a power of two from 64KiB to 16MiB)",
+			size
+		));
+	}
+	Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Flash {
+	/// Open the file holding the flash contents. If there isn't one, the chip
+	/// is blank.
+	pub fn open(path: &Path, size: usize) -> Result<Flash, std::io::Error> {
+		let mut file = std::fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(path)?;
+		let mut data = Vec::new();
+		file.read_to_end(&mut data)?;
+		if data.len() != size {
+			data.resize(size, ERASED);
+			file.set_len(0)?;
+			file.rewind()?;
+			file.write_all(&data)?;
+		}
+		Ok(Flash {
+			path: path.to_owned(),
+			file,
+			data,
+			write_enabled: false,
+			busy_until: Duration::ZERO,
+			opcode: None,
+			position: 0,
+			address: 0,
+			page: Vec::new(),
+		})
+	}
+
+	/// Is a program or erase still going on?
+	fn busy(&self) -> bool {
+		crate::clock::elapsed() < self.busy_until
+	}
+
+	/// The status register.
+	fn status(&self) -> u8 {
+		let mut status = 0;
+		if self.busy() {
+			status |= STATUS_BUSY;
+		}
+		if self.write_enabled {
+			status |= STATUS_WEL;
+		}
+		status
+	}
+
+	/// Clock one byte in on MOSI, and one byte out on MISO.
+	fn transfer(&mut self, mosi: u8) -> u8 {
+		let Some(opcode) = self.opcode else {
+			self.start(mosi);
+			return ERASED;
+		};
+		let position = self.position;
+		self.position += 1;
+		if position < ADDRESS_LEN {
+			self.address = (self.address << 8) | usize::from(mosi);
+		}
+		match opcode {
+			CMD_READ_STATUS => self.status(),
+			CMD_JEDEC_ID => {
+				let capacity = self.data.len().trailing_zeros() as u8;
+				let id = [JEDEC_MANUFACTURER, JEDEC_MEMORY_TYPE, capacity];
+				id.get(position).copied().unwrap_or(ERASED)
+			}
+			CMD_READ if position >= ADDRESS_LEN => self.read(position - ADDRESS_LEN),
+			// Fast read has a dummy byte after the address
+			CMD_FAST_READ if position > ADDRESS_LEN => self.read(position - ADDRESS_LEN - 1),
+			CMD_PAGE_PROGRAM if position >= ADDRESS_LEN => {
+				// The chip only holds one page - anything more wraps around
+				if self.page.len() == PAGE_SIZE {
+					self.page.remove(0);
+				}
+				self.page.push(mosi);
+				ERASED
+			}
+			_ => ERASED,
+		}
+	}
+
+	/// The OS sent an opcode.
+	fn start(&mut self, opcode: u8) {
+		if self.busy() && opcode != CMD_READ_STATUS {
+			log::debug!("Flash: ignoring command 0x{:02x} while busy", opcode);
+			return;
+		}
+		match opcode {
+			CMD_WRITE_ENABLE => self.write_enabled = true,
+			CMD_WRITE_DISABLE => self.write_enabled = false,
+			_ => {}
+		}
+		self.opcode = Some(opcode);
+		self.position = 0;
+		self.address = 0;
+		self.page.clear();
+	}
+
+	/// Read from the array, `offset` bytes after the address the OS sent.
+	/// Reads wrap around at the end of the chip.
+	fn read(&self, offset: usize) -> u8 {
+		self.data[(self.address + offset) % self.data.len()]
+	}
+
+	/// The OS deselected us, so carry out any program or erase it asked for.
+	fn finish(&mut self) {
+		let Some(opcode) = self.opcode.take() else {
+			return;
+		};
+		let address = self.address % self.data.len();
+		let has_address = self.position == ADDRESS_LEN;
+		let (range, time) = match opcode {
+			CMD_PAGE_PROGRAM if self.position > ADDRESS_LEN => {
+				let page_start = address - (address % PAGE_SIZE);
+				(page_start..page_start + PAGE_SIZE, PAGE_PROGRAM_TIME)
+			}
+			CMD_SECTOR_ERASE if has_address => {
+				let start = address - (address % SECTOR_SIZE);
+				(start..start + SECTOR_SIZE, SECTOR_ERASE_TIME)
+			}
+			CMD_BLOCK_ERASE if has_address => {
+				let start = address - (address % BLOCK_SIZE);
+				let end = (start + BLOCK_SIZE).min(self.data.len());
+				(start..end, BLOCK_ERASE_TIME)
+			}
+			CMD_CHIP_ERASE | CMD_CHIP_ERASE_ALT if self.position == 0 => {
+				let mib = self.data.len() as f64 / (1024.0 * 1024.0);
+				(0..self.data.len(), CHIP_ERASE_TIME_PER_MIB.mul_f64(mib))
+			}
+			_ => return,
+		};
+		if !self.write_enabled {
+			log::debug!(
+				"Flash: ignoring command 0x{:02x} without write enable",
+				opcode
+			);
+			return;
+		}
+		if opcode == CMD_PAGE_PROGRAM {
+			self.program(address);
+		} else {
+			self.data[range.clone()].fill(ERASED);
+		}
+		self.write_enabled = false;
+		self.busy_until = crate::clock::elapsed() + time;
+		if let Err(e) = self.save(range) {
+			log::warn!("Failed to save flash to {}: {}", self.path.display(), e);
+		}
+	}
+
+	/// Program the page buffer into the array, wrapping around within the
+	/// page. Programming can only clear bits.
+	fn program(&mut self, address: usize) {
+		let page_start = address - (address % PAGE_SIZE);
+		let mut offset = address % PAGE_SIZE;
+		let mut needed_erase = false;
+		for byte in &self.page {
+			let cell = &mut self.data[page_start + offset];
+			needed_erase |= *byte & !*cell != 0;
+			*cell &= *byte;
+			offset = (offset + 1) % PAGE_SIZE;
+		}
+		if needed_erase {
+			log::warn!(
+				"Flash: page program at 0x{:06x} tried to set bits that weren't erased",
+				address
+			);
+		}
+	}
+
+	/// Write part of the array back to the file.
+	fn save(&mut self, range: std::ops::Range<usize>) -> Result<(), std::io::Error> {
+		self.file
+			.seek(std::io::SeekFrom::Start(range.start as u64))?;
+		self.file.write_all(&self.data[range])
+	}
+}
+
+impl Peripheral for Flash {
+	fn kind(&self) -> common::bus::PeripheralKind {
+		common::bus::PeripheralKind::Slot
+	}
+
+	/// Every command starts when we're selected, and ends when we're
+	/// deselected.
+	fn chip_select(&mut self, selected: bool) {
+		if selected {
+			self.opcode = None;
+		} else {
+			self.finish();
+		}
+	}
+
+	/// Decode the command, if the OS is starting one.
+	fn annotate(&self, sent: &[u8]) -> Option<String> {
+		if self.opcode.is_some() {
+			return None;
+		}
+		let opcode = *sent.first()?;
+		let name = opcode_name(opcode);
+		if self.busy() && opcode != CMD_READ_STATUS {
+			return Some(format!("{} (ignored, busy)", name));
+		}
+		match (opcode, sent.get(1..=ADDRESS_LEN)) {
+			(
+				CMD_READ | CMD_FAST_READ | CMD_PAGE_PROGRAM | CMD_SECTOR_ERASE | CMD_BLOCK_ERASE,
+				Some(&[a, b, c]),
+			) => Some(format!("{} 0x{:02x}{:02x}{:02x}", name, a, b, c)),
+			_ => Some(name.to_string()),
+		}
+	}
+
+	fn flush(&mut self) {
+		if let Err(e) = self.file.sync_all() {
+			log::warn!("Failed to flush {}: {}", self.path.display(), e);
+		}
+	}
+
+	fn write_read(&mut self, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+		for byte in tx.iter().chain(tx2) {
+			self.transfer(*byte);
+		}
+		for byte in rx.iter_mut() {
+			*byte = self.transfer(ERASED);
+		}
+		Ok(())
+	}
+
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		for byte in buffer.iter_mut() {
+			*byte = self.transfer(*byte);
+		}
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		format!(
+			"{} KiB SPI flash ({})",
+			self.data.len() / 1024,
+			self.path.display()
+		)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,236 @@
+//! # Virtual GPIO peripheral
+//!
+//! Eight input lines and eight output lines, wired to the virtual panel the
+//! host draws over the corner of the screen (the inputs are DIP switches, the
+//! outputs are LEDs). You can also flip the switches from the debug console.
+//!
+//! Each transfer starts with a command byte. Bit 7 set means write, and the
+//! bottom two bits pick the first register. Every byte after that reads (or
+//! writes) a register, moving on to the next register each time:
+//!
+//! * `0` - the input lines (read only)
+//! * `1` - the output lines
+//! * `2` - which input lines have changed. Write a `1` to clear a bit.
+//! * `3` - which changes raise our interrupt
+//!
+//! With `bus_exchange`, each byte after the command byte comes back holding
+//! the register's value from before any write.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::Mutex;
+
+use neotron_common_bios as common;
+
+use super::{Error, Peripheral};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An emulated GPIO peripheral.
+///
+/// The line state lives in [`LINES`], so the host can draw it.
+pub struct Gpio;
+
+/// The state of the GPIO lines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lines {
+	/// The input lines (the switches)
+	pub inputs: u8,
+	/// The output lines (the LEDs). A `1` bit lights that LED.
+	pub outputs: u8,
+	/// Which inputs have changed since the OS last cleared them
+	pub changed: u8,
+	/// Which changes raise the interrupt
+	pub interrupt_enable: u8,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The lines, if we have a GPIO peripheral.
+static LINES: Mutex<Option<Lines>> = Mutex::new(None);
+
+/// Set in the command byte for a write.
+const COMMAND_WRITE: u8 = 0x80;
+
+/// Register numbers
+const REG_INPUTS: u8 = 0;
+const REG_OUTPUTS: u8 = 1;
+const REG_CHANGED: u8 = 2;
+const REG_INTERRUPT_ENABLE: u8 = 3;
+
+/// How many registers we have.
+const NUM_REGISTERS: u8 = 4;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Get the state of the lines, or `None` if there is no GPIO peripheral.
+pub fn lines() -> Option<Lines> {
+	*LINES.lock().unwrap()
+}
+
+/// Set one of the input lines.
+pub fn set_input(line: u8, high: bool) -> Result<(), String> {
+	if line >= 8 {
+		return Err(format!("there is no input {} (try 0 to 7)", line));
+	}
+	let mut lines = LINES.lock().unwrap();
+	let lines = lines
+		.as_mut()
+		.ok_or("there is no GPIO peripheral on the bus")?;
+	let old = lines.inputs;
+	if high {
+		lines.inputs |= 1 << line;
+	} else {
+		lines.inputs &= !(1 << line);
+	}
+	lines.changed |= old ^ lines.inputs;
+	crate::idle::wake();
+	Ok(())
+}
+
+/// Flip one of the input lines.
+pub fn toggle_input(line: u8) -> Result<(), String> {
+	let inputs = lines()
+		.ok_or("there is no GPIO peripheral on the bus")?
+		.inputs;
+	set_input(line, inputs & (1 << line) == 0)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Gpio {
+	/// Make the GPIO peripheral. There can only be one.
+	pub fn new() -> Result<Gpio, String> {
+		let mut lines = LINES.lock().unwrap();
+		if lines.is_some() {
+			return Err("only one GPIO peripheral is supported".to_string());
+		}
+		*lines = Some(Lines {
+			inputs: 0,
+			outputs: 0,
+			changed: 0,
+			interrupt_enable: 0,
+		});
+		Ok(Gpio)
+	}
+}
+
+impl Lines {
+	/// Read a register.
+	fn read(&self, register: u8) -> u8 {
+		match register {
+			REG_INPUTS => self.inputs,
+			REG_OUTPUTS => self.outputs,
+			REG_CHANGED => self.changed,
+			_ => self.interrupt_enable,
+		}
+	}
+
+	/// Write a register.
+	fn write(&mut self, register: u8, value: u8) {
+		match register {
+			REG_INPUTS => {}
+			REG_OUTPUTS => self.outputs = value,
+			REG_CHANGED => self.changed &= !value,
+			REG_INTERRUPT_ENABLE => self.interrupt_enable = value,
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl Peripheral for Gpio {
+	fn kind(&self) -> common::bus::PeripheralKind {
+		common::bus::PeripheralKind::Slot
+	}
+
+	/// `tx` and `tx2` hold the command and anything to write. Then we carry
+	/// on reading registers to fill `rx`.
+	fn write_read(&mut self, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+		rx.fill(0xFF);
+		let mut sent = tx.iter().chain(tx2);
+		let Some(command) = sent.next() else {
+			return Ok(());
+		};
+		let mut lines = LINES.lock().unwrap();
+		let Some(lines) = lines.as_mut() else {
+			return Ok(());
+		};
+		let write = command & COMMAND_WRITE != 0;
+		let mut register = command % NUM_REGISTERS;
+		for byte in sent {
+			if write {
+				lines.write(register, *byte);
+			}
+			register = (register + 1) % NUM_REGISTERS;
+		}
+		for byte in rx.iter_mut() {
+			*byte = lines.read(register);
+			register = (register + 1) % NUM_REGISTERS;
+		}
+		Ok(())
+	}
+
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		let Some((command, data)) = buffer.split_first_mut() else {
+			return Ok(());
+		};
+		let write = *command & COMMAND_WRITE != 0;
+		let mut register = *command % NUM_REGISTERS;
+		*command = 0xFF;
+		let mut lines = LINES.lock().unwrap();
+		let Some(lines) = lines.as_mut() else {
+			data.fill(0xFF);
+			return Ok(());
+		};
+		for byte in data.iter_mut() {
+			let value = lines.read(register);
+			if write {
+				lines.write(register, *byte);
+			}
+			*byte = value;
+			register = (register + 1) % NUM_REGISTERS;
+		}
+		Ok(())
+	}
+
+	fn interrupt_pending(&mut self) -> bool {
+		lines().is_some_and(|lines| lines.changed & lines.interrupt_enable != 0)
+	}
+
+	fn describe(&self) -> String {
+		"GPIO, 8 in and 8 out (virtual switch/LED panel)".to_string()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
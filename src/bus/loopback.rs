@@ -0,0 +1,103 @@
+//! # Loopback SPI peripheral
+//!
+//! This is what you get if you tie MISO to MOSI. An exchange gives back
+//! exactly what was sent. A write-then-read gives back the last bytes
+//! written, most recent last.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+
+use neotron_common_bios as common;
+
+use super::{Error, Peripheral};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A loopback peripheral.
+#[derive(Default)]
+pub struct Loopback {
+	/// The most recent bytes written
+	history: VecDeque<u8>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many written bytes we remember.
+const HISTORY_LEN: usize = 4096;
+
+/// What we read back if nothing has been written yet.
+const IDLE: u8 = 0xFF;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Loopback {
+	/// Remember some bytes that were written.
+	fn record(&mut self, data: &[u8]) {
+		self.history.extend(data);
+		let excess = self.history.len().saturating_sub(HISTORY_LEN);
+		self.history.drain(..excess);
+	}
+}
+
+impl Peripheral for Loopback {
+	fn kind(&self) -> common::bus::PeripheralKind {
+		common::bus::PeripheralKind::Slot
+	}
+
+	fn write_read(&mut self, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+		self.record(tx);
+		self.record(tx2);
+		let available = self.history.len().min(rx.len());
+		let (padding, recent) = rx.split_at_mut(rx.len() - available);
+		padding.fill(IDLE);
+		for (dest, src) in recent
+			.iter_mut()
+			.zip(self.history.range(self.history.len() - available..))
+		{
+			*dest = *src;
+		}
+		Ok(())
+	}
+
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		// What comes back is what was sent, so the buffer doesn't change
+		self.record(buffer);
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		"loopback (MISO tied to MOSI)".to_string()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,379 @@
+//! # SD card in SPI mode
+//!
+//! An SD card (well, an SDHC card) talking the SPI-mode protocol, backed by a
+//! disk image. This lets you test the OS's low-level SD card driver, rather
+//! than the block device API.
+//!
+//! We support CMD0 (reset), CMD8 (interface condition), CMD16 (set block
+//! length, which must be 512), CMD17 (read one block), CMD24 (write one
+//! block), CMD55 + ACMD41 (initialise) and CMD58 (read OCR). Like an SDHC card,
+//! block addresses are in blocks, not bytes.
+//!
+//! The command and response framing is the real thing: commands are six bytes
+//! starting `0b01xx_xxxx`, responses arrive a byte later, and data blocks have
+//! start tokens, a CRC-16 and (for writes) a data response token followed by
+//! some busy bytes. We don't check the CRCs the OS sends.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use neotron_common_bios as common;
+
+use super::{Error, Peripheral};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An emulated SD card.
+pub struct SdCard {
+	/// The disk image
+	file: std::fs::File,
+	/// Where the disk image is
+	path: PathBuf,
+	/// How many blocks the card has
+	num_blocks: u64,
+	/// What we're doing with the bytes the OS sends
+	state: State,
+	/// Bytes waiting to go out on MISO
+	output: VecDeque<u8>,
+	/// Set until the card has been initialised with ACMD41
+	idle: bool,
+	/// Set if the last command was CMD55, so the next is an app command
+	app_command: bool,
+	/// How many times the OS has sent ACMD41 since reset
+	init_attempts: u32,
+}
+
+/// What we're doing with the bytes the OS sends.
+enum State {
+	/// Looking for a command
+	Command(Vec<u8>),
+	/// Waiting for the start token of a block to write here
+	WriteToken(u64),
+	/// Collecting the data (and CRC) of a block to write here
+	WriteData(u64, Vec<u8>),
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// SD cards have 512 byte blocks.
+const BLOCK_SIZE: usize = 512;
+
+/// How long a command is.
+const COMMAND_LEN: usize = 6;
+
+/// What MISO reads as when the card has nothing to say.
+const IDLE: u8 = 0xFF;
+
+/// Starts a single block of data, in either direction.
+const TOKEN_START_BLOCK: u8 = 0xFE;
+
+/// The card accepted a block we were sent.
+const DATA_ACCEPTED: u8 = 0x05;
+
+/// R1 response bits
+const R1_READY: u8 = 0x00;
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const R1_PARAMETER_ERROR: u8 = 0x40;
+
+/// The OCR we report: powered up, high capacity, 2.7 - 3.6V.
+const OCR: [u8; 4] = [0xC0, 0xFF, 0x80, 0x00];
+
+/// How many ACMD41s the OS must send before the card is ready. Real cards
+/// take a while, and drivers need to cope with that.
+const INIT_ATTEMPTS: u32 = 2;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The name of a command, for the bus trace.
+fn command_name(app_command: bool, index: u8) -> &'static str {
+	match (app_command, index) {
+		(_, 0) => "GO_IDLE_STATE",
+		(_, 8) => "SEND_IF_COND",
+		(_, 55) => "APP_CMD",
+		(_, 58) => "READ_OCR",
+		(true, 41) => "SD_SEND_OP_COND",
+		(false, 16) => "SET_BLOCKLEN",
+		(false, 17) => "READ_SINGLE_BLOCK",
+		(false, 24) => "WRITE_BLOCK",
+		_ => "unsupported",
+	}
+}
+
+/// The CRC-16 (CCITT, initial value zero) used on SD data blocks.
+fn crc16(data: &[u8]) -> u16 {
+	let mut crc: u16 = 0;
+	for byte in data {
+		crc ^= u16::from(*byte) << 8;
+		for _ in 0..8 {
+			crc = if crc & 0x8000 != 0 {
+				(crc << 1) ^ 0x1021
+			} else {
+				crc << 1
+			};
+		}
+	}
+	crc
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl SdCard {
+	/// Open a disk image to use as the card.
+	pub fn open(path: &Path) -> Result<SdCard, std::io::Error> {
+		let file = std::fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.open(path)?;
+		let num_blocks = file.metadata()?.len() / BLOCK_SIZE as u64;
+		Ok(SdCard {
+			file,
+			path: path.to_owned(),
+			num_blocks,
+			state: State::Command(Vec::new()),
+			output: VecDeque::new(),
+			idle: true,
+			app_command: false,
+			init_attempts: 0,
+		})
+	}
+
+	/// Clock one byte in on MOSI, and one byte out on MISO.
+	fn transfer(&mut self, mosi: u8) -> u8 {
+		let miso = self.output.pop_front().unwrap_or(IDLE);
+		match &mut self.state {
+			State::Command(frame) => {
+				// Commands always start 0b01xx_xxxx
+				if !frame.is_empty() || mosi & 0xC0 == 0x40 {
+					frame.push(mosi);
+				}
+				if frame.len() == COMMAND_LEN {
+					let frame = std::mem::take(frame);
+					self.command(&frame);
+				}
+			}
+			State::WriteToken(block) => {
+				if mosi == TOKEN_START_BLOCK {
+					self.state = State::WriteData(*block, Vec::with_capacity(BLOCK_SIZE + 2));
+				}
+			}
+			State::WriteData(block, data) => {
+				data.push(mosi);
+				if data.len() == BLOCK_SIZE + 2 {
+					let block = *block;
+					let data = std::mem::take(data);
+					self.state = State::Command(Vec::new());
+					self.write_block(block, &data[..BLOCK_SIZE]);
+				}
+			}
+		}
+		miso
+	}
+
+	/// Handle a complete command frame.
+	fn command(&mut self, frame: &[u8]) {
+		let index = frame[0] & 0x3F;
+		let arg = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+		let app_command = std::mem::take(&mut self.app_command);
+		log::debug!(
+			"SD card: {}CMD{} 0x{:08x}",
+			if app_command { "A" } else { "" },
+			index,
+			arg
+		);
+		// The response comes out after one byte of nothing
+		self.output.push_back(IDLE);
+		let status = if self.idle { R1_IDLE } else { R1_READY };
+		match (app_command, index) {
+			(_, 0) => {
+				self.idle = true;
+				self.init_attempts = 0;
+				self.output.push_back(R1_IDLE);
+			}
+			(_, 8) => {
+				// Echo back the voltage range and check pattern
+				let echo = (arg & 0xFFF).to_be_bytes();
+				self.output.extend([status, 0x00, 0x00, echo[2], echo[3]]);
+			}
+			(_, 55) => {
+				self.app_command = true;
+				self.output.push_back(status);
+			}
+			(true, 41) => {
+				self.init_attempts += 1;
+				if self.init_attempts >= INIT_ATTEMPTS {
+					self.idle = false;
+				}
+				self.output
+					.push_back(if self.idle { R1_IDLE } else { R1_READY });
+			}
+			(_, 58) => {
+				self.output.push_back(status);
+				self.output.extend(OCR);
+			}
+			(false, 16) if !self.idle => {
+				let ok = arg as usize == BLOCK_SIZE;
+				self.output
+					.push_back(if ok { R1_READY } else { R1_PARAMETER_ERROR });
+			}
+			(false, 17) if !self.idle => self.read_block(u64::from(arg)),
+			(false, 24) if !self.idle => {
+				if u64::from(arg) < self.num_blocks {
+					self.output.push_back(R1_READY);
+					self.state = State::WriteToken(u64::from(arg));
+				} else {
+					self.output.push_back(R1_PARAMETER_ERROR);
+				}
+			}
+			_ => self.output.push_back(status | R1_ILLEGAL_COMMAND),
+		}
+	}
+
+	/// Send a block to the OS, with its start token and CRC.
+	fn read_block(&mut self, block: u64) {
+		let mut data = vec![0u8; BLOCK_SIZE];
+		let result = if block < self.num_blocks {
+			self.file
+				.seek(std::io::SeekFrom::Start(block * BLOCK_SIZE as u64))
+				.and_then(|_| self.file.read_exact(&mut data))
+				.is_ok()
+		} else {
+			false
+		};
+		if !result {
+			self.output.push_back(R1_PARAMETER_ERROR);
+			return;
+		}
+		self.output.push_back(R1_READY);
+		// A little while before the data starts
+		self.output.push_back(IDLE);
+		self.output.push_back(TOKEN_START_BLOCK);
+		self.output.extend(&data);
+		self.output.extend(crc16(&data).to_be_bytes());
+	}
+
+	/// Write a block the OS sent us, then say how it went.
+	fn write_block(&mut self, block: u64, data: &[u8]) {
+		let result = self
+			.file
+			.seek(std::io::SeekFrom::Start(block * BLOCK_SIZE as u64))
+			.and_then(|_| self.file.write_all(data));
+		match result {
+			Ok(()) => {
+				self.output.push_back(DATA_ACCEPTED);
+				// Busy for a bit while we "program" the flash
+				self.output.extend([0x00, 0x00]);
+			}
+			Err(e) => {
+				log::warn!("SD card write failed: {}", e);
+				// Write error
+				self.output.push_back(0x0D);
+			}
+		}
+	}
+}
+
+impl Peripheral for SdCard {
+	fn kind(&self) -> common::bus::PeripheralKind {
+		common::bus::PeripheralKind::SdCard
+	}
+
+	/// Releasing chip-select abandons anything half done.
+	fn chip_select(&mut self, selected: bool) {
+		if !selected {
+			self.output.clear();
+			self.state = State::Command(Vec::new());
+		}
+	}
+
+	/// Decode any command the OS is starting, or say which block a write is
+	/// for.
+	fn annotate(&self, sent: &[u8]) -> Option<String> {
+		match &self.state {
+			State::Command(frame) if frame.is_empty() => {
+				let start = sent.iter().position(|byte| byte & 0xC0 == 0x40)?;
+				let frame = sent.get(start..start + COMMAND_LEN)?;
+				let index = frame[0] & 0x3F;
+				let arg = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+				Some(format!(
+					"{}CMD{} {} 0x{:08x}",
+					if self.app_command { "A" } else { "" },
+					index,
+					command_name(self.app_command, index),
+					arg
+				))
+			}
+			State::Command(_) => None,
+			State::WriteToken(block) | State::WriteData(block, _) => {
+				Some(format!("data for block {}", block))
+			}
+		}
+	}
+
+	fn flush(&mut self) {
+		if let Err(e) = self.file.sync_all() {
+			log::warn!("Failed to flush {}: {}", self.path.display(), e);
+		}
+	}
+
+	fn write_read(&mut self, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+		for byte in tx.iter().chain(tx2) {
+			self.transfer(*byte);
+		}
+		for byte in rx.iter_mut() {
+			*byte = self.transfer(IDLE);
+		}
+		Ok(())
+	}
+
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		for byte in buffer.iter_mut() {
+			*byte = self.transfer(*byte);
+		}
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		format!(
+			"SD card, {} blocks ({})",
+			self.num_blocks,
+			self.path.display()
+		)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
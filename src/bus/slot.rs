@@ -0,0 +1,73 @@
+//! # Empty Neotron Bus expansion slot
+//!
+//! With nothing plugged in, nothing drives the MISO line and its pull-up
+//! makes every byte read back as `0xFF`.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use neotron_common_bios as common;
+
+use super::{Error, Peripheral};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An empty expansion slot.
+pub struct Slot;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What an undriven MISO line reads as.
+const IDLE: u8 = 0xFF;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Peripheral for Slot {
+	fn kind(&self) -> common::bus::PeripheralKind {
+		common::bus::PeripheralKind::Slot
+	}
+
+	fn write_read(&mut self, _tx: &[u8], _tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+		rx.fill(IDLE);
+		Ok(())
+	}
+
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		buffer.fill(IDLE);
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		"expansion slot (empty)".to_string()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
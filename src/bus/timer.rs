@@ -0,0 +1,117 @@
+//! # Timer interrupt peripheral
+//!
+//! Raises its interrupt at a fixed rate, in emulated time. The interrupt stays
+//! raised until the OS acknowledges it, by sending the command byte `0x01`
+//! with `bus_write_read` (or `bus_exchange`). Any bytes the OS reads back in
+//! the same transaction hold the number of periods that have passed since the
+//! last acknowledge (up to 255), so the OS can tell if it missed any.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::time::Duration;
+
+use neotron_common_bios as common;
+
+use super::{Error, Peripheral};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A timer which raises an interrupt periodically.
+pub struct Timer {
+	/// How often the interrupt is raised
+	period: Duration,
+	/// How many periods had passed when the OS last acknowledged
+	acknowledged: u128,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The command byte which acknowledges the interrupt.
+const CMD_ACKNOWLEDGE: u8 = 0x01;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Timer {
+	/// Make a timer that interrupts this many times a second.
+	pub fn new(rate_hz: f64) -> Timer {
+		Timer {
+			period: Duration::from_secs_f64(1.0 / rate_hz),
+			acknowledged: 0,
+		}
+	}
+
+	/// How many whole periods have passed since boot.
+	fn periods(&self) -> u128 {
+		crate::clock::elapsed().as_nanos() / self.period.as_nanos().max(1)
+	}
+
+	/// Handle a command, and fill `reply` with the answer.
+	fn command(&mut self, command: Option<u8>, reply: &mut [u8]) {
+		let now = self.periods();
+		let missed = u8::try_from(now - self.acknowledged).unwrap_or(u8::MAX);
+		if command == Some(CMD_ACKNOWLEDGE) {
+			self.acknowledged = now;
+		}
+		reply.fill(missed);
+	}
+}
+
+impl Peripheral for Timer {
+	fn kind(&self) -> common::bus::PeripheralKind {
+		common::bus::PeripheralKind::Slot
+	}
+
+	fn write_read(&mut self, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+		let command = tx.first().or(tx2.first()).copied();
+		self.command(command, rx);
+		Ok(())
+	}
+
+	fn exchange(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		let command = buffer.first().copied();
+		self.command(command, buffer);
+		Ok(())
+	}
+
+	fn interrupt_pending(&mut self) -> bool {
+		self.periods() > self.acknowledged
+	}
+
+	fn describe(&self) -> String {
+		format!(
+			"timer interrupt every {:.3} ms",
+			self.period.as_secs_f64() * 1000.0
+		)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
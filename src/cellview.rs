@@ -0,0 +1,204 @@
+//! # Cell view for the text modes
+//!
+//! Type `cells on` in the debug console (or the monitor) and, instead of
+//! glyphs, each text cell shows its two bytes as tiny hex digits: the glyph
+//! byte in white, and the attribute byte in yellow. Cells with the blink bit
+//! set have a red background, cells with a bright foreground have a blue
+//! one, and cells with both have a purple one. When the OS writes garbage to
+//! VRAM, this shows exactly which bytes landed where - even the ones that
+//! would draw as blanks.
+//!
+//! An 8x16 cell has room for both bytes, one above the other. An 8x8 cell
+//! only has room for one, so we show the glyph bytes and the attribute bytes
+//! in turn, for a second each.
+//!
+//! Screenshots always show the normal view.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pix_engine::prelude::*;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Whether we're showing the cell view.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// How wide each digit is, in pixels.
+const DIGIT_WIDTH: u32 = 3;
+
+/// How tall each digit is, in pixels.
+const DIGIT_HEIGHT: u32 = 5;
+
+/// A 3x5 pixel font for the hex digits. Each row is three bits, with the
+/// leftmost pixel in bit 2.
+const DIGITS: [[u8; 5]; 16] = [
+	[0b111, 0b101, 0b101, 0b101, 0b111],
+	[0b010, 0b110, 0b010, 0b010, 0b111],
+	[0b111, 0b001, 0b111, 0b100, 0b111],
+	[0b111, 0b001, 0b111, 0b001, 0b111],
+	[0b101, 0b101, 0b111, 0b001, 0b001],
+	[0b111, 0b100, 0b111, 0b001, 0b111],
+	[0b111, 0b100, 0b111, 0b101, 0b111],
+	[0b111, 0b001, 0b010, 0b010, 0b010],
+	[0b111, 0b101, 0b111, 0b101, 0b111],
+	[0b111, 0b101, 0b111, 0b001, 0b111],
+	[0b010, 0b101, 0b111, 0b101, 0b101],
+	[0b110, 0b101, 0b110, 0b101, 0b110],
+	[0b011, 0b100, 0b100, 0b100, 0b011],
+	[0b110, 0b101, 0b101, 0b101, 0b110],
+	[0b111, 0b100, 0b110, 0b100, 0b111],
+	[0b111, 0b100, 0b110, 0b100, 0b100],
+];
+
+/// The colour of the glyph byte's digits.
+const GLYPH_COLOUR: Color = Color::rgb(255, 255, 255);
+
+/// The colour of the attribute byte's digits.
+const ATTR_COLOUR: Color = Color::rgb(255, 224, 64);
+
+/// The blink bit in an attribute byte.
+const ATTR_BLINK: u8 = 1 << 7;
+
+/// The bright bit in an attribute byte (the top bit of the foreground
+/// colour).
+const ATTR_BRIGHT: u8 = 1 << 3;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Show or hide the cell view.
+pub fn set_enabled(enabled: bool) {
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Are we showing the cell view?
+pub fn is_enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Render a texture for each hex digit, first in the glyph byte's colour and
+/// then in the attribute byte's.
+pub fn render_digits(s: &mut PixState) -> PixResult<Vec<TextureId>> {
+	let mut textures = Vec::with_capacity(DIGITS.len() * 2);
+	for colour in [GLYPH_COLOUR, ATTR_COLOUR] {
+		for rows in DIGITS.iter() {
+			let id = s.create_texture(DIGIT_WIDTH, DIGIT_HEIGHT, PixelFormat::Rgba)?;
+			s.set_texture_target(id)?;
+			s.background(Color::TRANSPARENT);
+			s.clear()?;
+			s.stroke(colour);
+			for (y, row) in rows.iter().enumerate() {
+				for x in 0..DIGIT_WIDTH {
+					if row & (0b100 >> x) != 0 {
+						s.point(Point::new([x as i32, y as i32]))?;
+					}
+				}
+			}
+			s.clear_texture_target();
+			textures.push(id);
+		}
+	}
+	crate::videostats::record_uploads(textures.len());
+	Ok(textures)
+}
+
+/// Draw every cell of the text framebuffer as hex digits, returning how many
+/// cells we drew.
+pub fn draw(
+	s: &mut PixState,
+	digits: &[TextureId],
+	mode: common::video::Mode,
+	font_height: u16,
+) -> PixResult<usize> {
+	let (Some(num_cols), Some(num_rows)) = (mode.text_width(), mode.text_height()) else {
+		return Ok(0);
+	};
+	// An 8x8 cell only has room for one byte, so take turns
+	let show_attr_only = font_height < 16 && crate::clock::elapsed().as_secs() % 2 == 1;
+	s.stroke(None);
+	for row in 0..num_rows {
+		for col in 0..num_cols {
+			let byte_offset = usize::from(row * num_cols + col) * 2;
+			let glyph = crate::video::FRAMEBUFFER.get_at(byte_offset);
+			let attr = crate::video::FRAMEBUFFER.get_at(byte_offset + 1);
+			let x = i32::from(col) * 8;
+			let y = i32::from(row) * i32::from(font_height);
+			s.fill(background(attr));
+			s.rect(rect![x, y, 8, i32::from(font_height)])?;
+			if font_height >= 16 {
+				draw_byte(s, digits, x, y + 2, glyph, false)?;
+				draw_byte(s, digits, x, y + 9, attr, true)?;
+			} else if show_attr_only {
+				draw_byte(s, digits, x, y + 1, attr, true)?;
+			} else {
+				draw_byte(s, digits, x, y + 1, glyph, false)?;
+			}
+		}
+	}
+	Ok(usize::from(num_rows) * usize::from(num_cols))
+}
+
+/// Draw one byte as two hex digits, in an 8 pixel wide cell.
+fn draw_byte(
+	s: &mut PixState,
+	digits: &[TextureId],
+	x: i32,
+	y: i32,
+	byte: u8,
+	is_attr: bool,
+) -> PixResult<()> {
+	let offset = if is_attr { DIGITS.len() } else { 0 };
+	for (idx, nibble) in [byte >> 4, byte & 0x0F].into_iter().enumerate() {
+		let digit_box = rect![
+			x + 1 + idx as i32 * 4,
+			y,
+			DIGIT_WIDTH as i32,
+			DIGIT_HEIGHT as i32
+		];
+		s.texture(digits[offset + usize::from(nibble)], None, Some(digit_box))?;
+	}
+	Ok(())
+}
+
+/// The background colour for a cell with this attribute byte, which shows
+/// whether it blinks or is bright.
+fn background(attr: u8) -> Color {
+	match (attr & ATTR_BLINK != 0, attr & ATTR_BRIGHT != 0) {
+		(true, true) => Color::rgb(112, 0, 112),
+		(true, false) => Color::rgb(128, 0, 0),
+		(false, true) => Color::rgb(0, 0, 128),
+		(false, false) => Color::rgb(0, 0, 0),
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
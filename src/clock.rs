@@ -0,0 +1,196 @@
+//! # Emulated time for the Neotron Desktop BIOS
+//!
+//! The OS's sense of time (ticks, the wall clock and video timing) comes from
+//! here. It normally runs at the same rate as the host's clock, but can be
+//! sped up or slowed down with `--time-scale`, and it stands still while the
+//! emulation is paused.
+//!
+//! For tests that need the same thing to happen every run, there's also a
+//! virtual clock (see [`set_virtual`]), which ignores the host's clock and
+//! only moves on when the OS calls the BIOS.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Where emulated time has got to.
+///
+/// Emulated time is `emulated_base` plus however long it has been (in host
+/// time) since `host_base`, multiplied by `scale`. Whenever the scale changes
+/// we move both bases up to the present, so time never jumps.
+struct State {
+	scale: f64,
+	host_base: Option<Instant>,
+	emulated_base: Duration,
+	/// While paused, emulated time stays at `emulated_base`
+	paused: bool,
+	/// With a virtual clock, emulated time stays at `emulated_base`, which
+	/// moves on this much every BIOS call
+	step: Option<Duration>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+static STATE: Mutex<State> = Mutex::new(State {
+	scale: 1.0,
+	host_base: None,
+	emulated_base: Duration::ZERO,
+	paused: false,
+	step: None,
+});
+
+/// Whether we have a virtual clock, so BIOS calls don't have to take the
+/// lock to find out.
+static VIRTUAL: AtomicBool = AtomicBool::new(false);
+
+/// The slowest time can run. Much slower and `Duration` arithmetic overflows.
+pub const MIN_SCALE: f64 = 0.001;
+
+/// The fastest time can run.
+pub const MAX_SCALE: f64 = 1000.0;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Start the clock. Call this when the machine boots.
+pub fn start(scale: f64) {
+	let mut state = STATE.lock().unwrap();
+	state.scale = scale;
+	state.host_base = Some(Instant::now());
+	state.emulated_base = Duration::ZERO;
+}
+
+/// Carry on from this much emulated time, like we'd been running all along.
+pub fn set_elapsed(elapsed: Duration) {
+	let mut state = STATE.lock().unwrap();
+	state.host_base = Some(Instant::now());
+	state.emulated_base = elapsed;
+}
+
+/// How much emulated time has passed since we booted.
+pub fn elapsed() -> Duration {
+	STATE.lock().unwrap().elapsed(Instant::now())
+}
+
+/// How many times faster than the host's clock emulated time runs.
+pub fn scale() -> f64 {
+	STATE.lock().unwrap().scale
+}
+
+/// Change how fast emulated time runs, carrying on from where it is now.
+pub fn set_scale(scale: f64) {
+	let mut state = STATE.lock().unwrap();
+	state.rebase(Instant::now());
+	state.scale = scale;
+}
+
+/// Stop or restart emulated time.
+pub fn set_paused(paused: bool) {
+	let mut state = STATE.lock().unwrap();
+	state.rebase(Instant::now());
+	state.paused = paused;
+}
+
+/// Stop following the host's clock, and instead move emulated time on by
+/// `step` every time the OS calls the BIOS (or go back to the host's clock,
+/// with `None`).
+///
+/// The OS then sees exactly the same times every run, however fast the host
+/// is, as long as it makes the same calls.
+pub fn set_virtual(step: Option<Duration>) {
+	let mut state = STATE.lock().unwrap();
+	state.rebase(Instant::now());
+	state.step = step;
+	VIRTUAL.store(step.is_some(), Ordering::Relaxed);
+}
+
+/// The OS has called the BIOS, so move the virtual clock on, if we have one.
+pub fn bios_called() {
+	if !VIRTUAL.load(Ordering::Relaxed) {
+		return;
+	}
+	let mut state = STATE.lock().unwrap();
+	if let (Some(step), false) = (state.step, state.paused) {
+		state.emulated_base += step;
+	}
+}
+
+/// How long, in host time, it takes for this much emulated time to pass.
+pub fn host_duration(emulated: Duration) -> Duration {
+	emulated.div_f64(scale())
+}
+
+/// Parse a time scale from the command line (or the debug console). It must
+/// be between [`MIN_SCALE`] and [`MAX_SCALE`].
+pub fn parse_scale(text: &str) -> Result<f64, String> {
+	match text.trim().parse::<f64>() {
+		Ok(scale) if (MIN_SCALE..=MAX_SCALE).contains(&scale) => Ok(scale),
+		Ok(scale) if scale.is_finite() && scale > 0.0 => Err(format!(
+			"{:?} is out of range - the time scale must be from {} to {}",
+			text, MIN_SCALE, MAX_SCALE
+		)),
+		_ => Err(format!(
+			"{:?} isn't a valid time scale (try 0.5, 1 or 10)",
+			text
+		)),
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl State {
+	/// How much emulated time has passed at host time `now`.
+	fn elapsed(&self, now: Instant) -> Duration {
+		if self.paused || self.step.is_some() {
+			return self.emulated_base;
+		}
+		let host_elapsed = self
+			.host_base
+			.map(|base| now.saturating_duration_since(base))
+			.unwrap_or_default();
+		self.emulated_base + host_elapsed.mul_f64(self.scale)
+	}
+
+	/// Move both bases up to host time `now`, so we can change how time runs
+	/// from here on without it jumping.
+	fn rebase(&mut self, now: Instant) {
+		self.emulated_base = self.elapsed(now);
+		self.host_base = Some(now);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
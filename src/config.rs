@@ -0,0 +1,367 @@
+//! # Where the options come from
+//!
+//! Every option can also be set with a `NEOTRON_BIOS_*` environment variable
+//! (`--disk` is `NEOTRON_BIOS_DISK`, `--audio-latency` is
+//! `NEOTRON_BIOS_AUDIO_LATENCY`, and so on), which is easier than building a
+//! command line in most CI systems. The command line wins over the
+//! environment, which wins over a config file, which wins over the default.
+//!
+//! Options that are given more than once, like `--i2c-device`, take one
+//! variable per value, numbered from zero (`NEOTRON_BIOS_I2C_DEVICE_0`,
+//! `NEOTRON_BIOS_I2C_DEVICE_1`, ...).
+//!
+//! With a `--profile`, options can also come from its `config.toml` (see
+//! [`File`]), which sits between the environment and the default.
+//!
+//! `--dump-config` uses [`settings`] to show what each option ended up as,
+//! and which of those places it came from.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, ValueHint};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Where an option's value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+	/// It was on the command line
+	CommandLine,
+	/// It was in this environment variable
+	Environment(String),
+	/// It was in this config file
+	ConfigFile(PathBuf),
+	/// Nobody set it, so it has its default value
+	Default,
+	/// Nobody set it, and it doesn't have a default
+	Unset,
+}
+
+/// The value one option ended up with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+	/// The option, as you'd type it (e.g. `--disk`)
+	pub option: String,
+	/// The value, as it was given, if it has one
+	pub value: Option<String>,
+	/// Where the value came from
+	pub source: Source,
+}
+
+/// Options from a TOML config file, like a profile's `config.toml`.
+///
+/// Each key is an option's long name, and its value is what you'd give on the
+/// command line - a string, a number or `true`/`false`, or an array of them
+/// for an option that can be given more than once.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct File {
+	/// Where we read it from
+	pub path: PathBuf,
+	/// Each option in it, and its values, in the order they appear
+	pub entries: Vec<(String, Vec<String>)>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What all our environment variables start with.
+pub const ENV_PREFIX: &str = "NEOTRON_BIOS_";
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The environment variable for the option with this long name (e.g.
+/// `audio-latency` is `NEOTRON_BIOS_AUDIO_LATENCY`).
+pub fn env_name(long: &str) -> String {
+	format!("{}{}", ENV_PREFIX, long.replace('-', "_").to_uppercase())
+}
+
+/// Let every option in `command` be set from its environment variable, apart
+/// from those named in `skip` (by long name).
+///
+/// Options without a long name (like `--help`) are left alone.
+pub fn with_env(command: Command, skip: &[&str]) -> Command {
+	command.mut_args(|arg| match arg.get_long() {
+		Some(long) if !skip.contains(&long) => {
+			let name = env_name(long);
+			arg.env(name)
+		}
+		_ => arg,
+	})
+}
+
+/// Use the options in a config file as the defaults for `command`, apart from
+/// those named in `skip` (by long name), which can't be in one.
+///
+/// Relative paths in options that take a path are relative to the file's
+/// directory, not the current one.
+pub fn with_file(command: Command, file: &File, skip: &[&str]) -> Result<Command, String> {
+	let base = file.path.parent().unwrap_or(Path::new("."));
+	let mut defaults = Vec::new();
+	for (key, values) in &file.entries {
+		let arg = command
+			.get_arguments()
+			.find(|arg| arg.get_long() == Some(key.as_str()))
+			.ok_or_else(|| format!("{}: there's no '--{}' option", file.path.display(), key))?;
+		if skip.contains(&key.as_str()) {
+			return Err(format!(
+				"{}: '--{}' can't go in a config file",
+				file.path.display(),
+				key
+			));
+		}
+		let is_path = matches!(
+			arg.get_value_hint(),
+			ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+		);
+		let mut resolved = Vec::new();
+		for value in values {
+			let parts: Vec<&str> = match arg.get_value_delimiter() {
+				Some(delimiter) => value.split(delimiter).collect(),
+				None => vec![value.as_str()],
+			};
+			for part in parts {
+				if is_path && Path::new(part).is_relative() {
+					resolved.push(base.join(part).to_string_lossy().into_owned());
+				} else {
+					resolved.push(part.to_string());
+				}
+			}
+		}
+		defaults.push((arg.get_id().to_string(), resolved));
+	}
+	Ok(command.mut_args(|arg| {
+		match defaults
+			.iter()
+			.find(|(id, _)| arg.get_id().as_str() == id.as_str())
+		{
+			Some((_, values)) => arg.default_values(values.clone()),
+			None => arg,
+		}
+	}))
+}
+
+/// The numbered environment variables for an option that can be given more
+/// than once, as `(variable, value)` pairs.
+///
+/// We stop at the first number that isn't set, so `_0`, `_1` and `_3` gives
+/// you `_0` and `_1`.
+pub fn indexed(long: &str) -> Vec<(String, String)> {
+	let base = env_name(long);
+	let mut found = Vec::new();
+	for idx in 0.. {
+		let name = format!("{}_{}", base, idx);
+		match std::env::var(&name) {
+			Ok(value) => found.push((name, value)),
+			Err(_) => break,
+		}
+	}
+	found
+}
+
+/// Every option's value, and where it came from.
+///
+/// `indexed` names (by long name) the options that take numbered environment
+/// variables - we list one line for each of those. Options that can't come
+/// from the environment at all (like `--help` or `--list-modes`) are left
+/// out, as they're things to do rather than settings. `file` is the config
+/// file we used, if any.
+pub fn settings(
+	command: &Command,
+	matches: &ArgMatches,
+	indexed: &[&str],
+	file: Option<&File>,
+) -> Vec<Setting> {
+	let mut result = Vec::new();
+	for arg in command.get_arguments() {
+		let Some(long) = arg.get_long() else {
+			continue;
+		};
+		let option = format!("--{}", long);
+		let id = arg.get_id().as_str();
+		let source = matches.value_source(id);
+		if !indexed.contains(&long) && arg.get_env().is_none() {
+			continue;
+		}
+		let from_env = match source {
+			Some(ValueSource::CommandLine) => Vec::new(),
+			_ if indexed.contains(&long) => self::indexed(long),
+			_ => Vec::new(),
+		};
+		if !from_env.is_empty() {
+			for (name, value) in from_env {
+				result.push(Setting {
+					option: option.clone(),
+					value: Some(value),
+					source: Source::Environment(name),
+				});
+			}
+			continue;
+		}
+		let value = matches.get_raw(id).map(|raw| {
+			raw.map(|value| value.to_string_lossy())
+				.collect::<Vec<_>>()
+				.join(",")
+		});
+		let source = match source {
+			Some(ValueSource::CommandLine) => Source::CommandLine,
+			Some(ValueSource::EnvVariable) => Source::Environment(env_name(long)),
+			Some(ValueSource::DefaultValue) => match file {
+				Some(file) if file.entries.iter().any(|(key, _)| key == long) => {
+					Source::ConfigFile(file.path.clone())
+				}
+				_ => Source::Default,
+			},
+			_ => Source::Unset,
+		};
+		result.push(Setting {
+			option,
+			value,
+			source,
+		});
+	}
+	result
+}
+
+/// One value from a config file, as it would be on the command line, or what
+/// sort of thing it was if it can't be.
+fn plain_value(value: toml::Value) -> Result<String, &'static str> {
+	match value {
+		toml::Value::String(text) => Ok(text),
+		toml::Value::Integer(number) => Ok(number.to_string()),
+		toml::Value::Float(number) => Ok(number.to_string()),
+		toml::Value::Boolean(flag) => Ok(flag.to_string()),
+		toml::Value::Datetime(_) => Err("a date"),
+		toml::Value::Array(_) => Err("an array inside an array"),
+		toml::Value::Table(_) => Err("a table"),
+	}
+}
+
+/// Print the settings as a table, for `--dump-config`.
+pub fn dump(settings: &[Setting]) {
+	let values: Vec<String> = settings
+		.iter()
+		.map(|setting| setting.value.clone().unwrap_or_else(|| String::from("-")))
+		.collect();
+	let option_width = settings
+		.iter()
+		.map(|setting| setting.option.len())
+		.chain(std::iter::once("Option".len()))
+		.max()
+		.unwrap_or_default();
+	let value_width = values
+		.iter()
+		.map(|value| value.len())
+		.chain(std::iter::once("Value".len()))
+		.max()
+		.unwrap_or_default();
+	println!(
+		"{:option_width$}  {:value_width$}  From",
+		"Option",
+		"Value",
+		option_width = option_width,
+		value_width = value_width
+	);
+	for (setting, value) in settings.iter().zip(values) {
+		println!(
+			"{:option_width$}  {:value_width$}  {}",
+			setting.option,
+			value,
+			setting.source,
+			option_width = option_width,
+			value_width = value_width
+		);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl File {
+	/// Read a config file. One that doesn't exist has no options in it.
+	pub fn load(path: &Path) -> Result<File, String> {
+		let text = match std::fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				return Ok(File {
+					path: path.to_owned(),
+					entries: Vec::new(),
+				})
+			}
+			Err(e) => return Err(format!("{}: {}", path.display(), e)),
+		};
+		File::parse(path, &text)
+	}
+
+	/// Make sense of the contents of a config file, read from `path`.
+	pub fn parse(path: &Path, text: &str) -> Result<File, String> {
+		let table: toml::Table = text
+			.parse()
+			.map_err(|e| format!("{}: {}", path.display(), e))?;
+		let mut entries = Vec::new();
+		for (key, value) in table {
+			let values = match value {
+				toml::Value::Array(items) => items.into_iter().map(plain_value).collect(),
+				value => plain_value(value).map(|value| vec![value]),
+			}
+			.map_err(|what| {
+				format!(
+					"{}: '{}' should be a string, number or true/false, not {}",
+					path.display(),
+					key,
+					what
+				)
+			})?;
+			entries.push((key, values));
+		}
+		Ok(File {
+			path: path.to_owned(),
+			entries,
+		})
+	}
+}
+
+impl std::fmt::Display for Source {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Source::CommandLine => write!(f, "command line"),
+			Source::Environment(name) => write!(f, "environment ({})", name),
+			Source::ConfigFile(path) => write!(f, "config file ({})", path.display()),
+			Source::Default => write!(f, "default"),
+			Source::Unset => write!(f, "not set"),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,340 @@
+//! # Conflicting options
+//!
+//! clap checks each option on its own, and the simple "you can't have both"
+//! pairs. This module checks the rules that depend on what the options say:
+//! two options writing to the same file, a device that `--isolate` would hide
+//! from the OS, two I²C devices at the same address, and so on. We check them
+//! all before we start anything, so a bad command line gets one clear error
+//! naming both options, rather than a panic or a quiet surprise later.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use crate::{audio, bus, i2c};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The options that can conflict with each other.
+#[derive(Debug, Clone)]
+pub struct Options {
+	/// `--disk`
+	pub disk: Option<PathBuf>,
+	/// The NVRAM file, from `--nvram` or the default
+	pub nvram: Option<PathBuf>,
+	/// `--os`
+	pub os: Vec<PathBuf>,
+	/// `--resume`
+	pub resume: Option<PathBuf>,
+	/// `--snapshot-file`
+	pub snapshot_file: Option<PathBuf>,
+	/// `--trace-api-file`
+	pub trace_api_file: Option<PathBuf>,
+	/// `--trace-bus-file`
+	pub trace_bus_file: Option<PathBuf>,
+	/// `--trace-timeline`
+	pub trace_timeline: Option<PathBuf>,
+	/// `--audio`
+	pub audio_backend: audio::Backend,
+	/// `--audio-device`
+	pub audio_device: Option<String>,
+	/// `--audio-input`
+	pub audio_input: Option<PathBuf>,
+	/// `--audio-input-device`
+	pub audio_input_device: Option<String>,
+	/// `--isolate`
+	pub isolate: bool,
+	/// `--i2c-eeprom`, as the device it adds to Bus 0
+	pub i2c_eeprom: Option<i2c::DeviceSpec>,
+	/// `--i2c-device`
+	pub i2c_devices: Vec<i2c::DeviceSpec>,
+	/// `--bus-device`
+	pub bus_devices: Vec<bus::DeviceSpec>,
+}
+
+/// Two options that can't be used together (or one that can't be used as
+/// often as it was).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+	/// The first option, as it was given (e.g. `--disk`)
+	pub first: String,
+	/// The second option
+	pub second: String,
+	/// Why they can't be used together
+	pub reason: String,
+}
+
+/// How an option uses a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Access {
+	/// We only read it
+	Read,
+	/// We write to it (and maybe read it too)
+	Write,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Check the options against each other. Returns every conflict we find,
+/// or an empty list if there aren't any.
+pub fn check(options: &Options) -> Vec<Conflict> {
+	let mut conflicts = Vec::new();
+	check_files(options, &mut conflicts);
+	check_audio(options, &mut conflicts);
+	check_isolate(options, &mut conflicts);
+	check_i2c(options, &mut conflicts);
+	check_bus(options, &mut conflicts);
+	conflicts
+}
+
+/// Two options can't use the same file if either of them writes to it.
+fn check_files(options: &Options, conflicts: &mut Vec<Conflict>) {
+	let single = [
+		("--disk", &options.disk, Access::Write),
+		("--nvram", &options.nvram, Access::Write),
+		("--resume", &options.resume, Access::Read),
+		("--snapshot-file", &options.snapshot_file, Access::Write),
+		("--trace-api-file", &options.trace_api_file, Access::Write),
+		("--trace-bus-file", &options.trace_bus_file, Access::Write),
+		("--trace-timeline", &options.trace_timeline, Access::Write),
+		("--audio-input", &options.audio_input, Access::Read),
+	];
+	let mut files: Vec<(String, &Path, Access)> = single
+		.into_iter()
+		.filter_map(|(flag, path, access)| Some((flag.to_string(), path.as_deref()?, access)))
+		.collect();
+	for path in &options.os {
+		files.push((String::from("--os"), path, Access::Read));
+	}
+	if let Some(i2c::DeviceSpec {
+		kind: i2c::DeviceKind::Eeprom(eeprom),
+		..
+	}) = &options.i2c_eeprom
+	{
+		files.push((String::from("--i2c-eeprom"), &eeprom.path, Access::Write));
+	}
+	for spec in &options.i2c_devices {
+		if let i2c::DeviceKind::Eeprom(eeprom) = &spec.kind {
+			files.push((String::from("--i2c-device"), &eeprom.path, Access::Write));
+		}
+	}
+	for spec in &options.bus_devices {
+		match spec {
+			bus::DeviceSpec::SdCard(path) | bus::DeviceSpec::Flash(path, _) => {
+				files.push((String::from("--bus-device"), path, Access::Write));
+			}
+			_ => {}
+		}
+	}
+
+	let identities: Vec<PathBuf> = files.iter().map(|(_, path, _)| identity(path)).collect();
+	for (idx, (first, path, first_access)) in files.iter().enumerate() {
+		for (other, (second, _, second_access)) in files.iter().enumerate().skip(idx + 1) {
+			if identities[idx] != identities[other] {
+				continue;
+			}
+			let pair = (first.as_str(), second.as_str());
+			if pair == ("--resume", "--snapshot-file") {
+				// Carrying on from a snapshot and saving it back again is fine
+				continue;
+			}
+			let reason = match (first_access, second_access) {
+				(Access::Read, Access::Read) => continue,
+				(Access::Write, Access::Write) => {
+					format!("they would both write to {}", path.display())
+				}
+				(Access::Write, Access::Read) => format!(
+					"{} would write to {}, which {} reads",
+					first,
+					path.display(),
+					second
+				),
+				(Access::Read, Access::Write) => format!(
+					"{} would write to {}, which {} reads",
+					second,
+					path.display(),
+					first
+				),
+			};
+			conflicts.push(Conflict::new(first, second, reason));
+		}
+	}
+}
+
+/// `--audio=null` doesn't use the host's audio devices.
+fn check_audio(options: &Options, conflicts: &mut Vec<Conflict>) {
+	if options.audio_backend != audio::Backend::Null {
+		return;
+	}
+	if options.audio_device.is_some() {
+		conflicts.push(Conflict::new(
+			"--audio=null",
+			"--audio-device",
+			"the null backend doesn't use a host audio device",
+		));
+	}
+	if options.audio_input_device.is_some() {
+		conflicts.push(Conflict::new(
+			"--audio=null",
+			"--audio-input-device",
+			"the null backend doesn't use a host audio device",
+		));
+	}
+}
+
+/// The isolated OS can't see any I²C, Neotron Bus or audio devices.
+fn check_isolate(options: &Options, conflicts: &mut Vec<Conflict>) {
+	if !options.isolate {
+		return;
+	}
+	let hidden = [
+		("--i2c-eeprom", options.i2c_eeprom.is_some(), "I2C devices"),
+		(
+			"--i2c-device",
+			!options.i2c_devices.is_empty(),
+			"I2C devices",
+		),
+		(
+			"--bus-device",
+			!options.bus_devices.is_empty(),
+			"Neotron Bus peripherals",
+		),
+		(
+			"--audio-input",
+			options.audio_input.is_some(),
+			"audio devices",
+		),
+	];
+	for (flag, given, what) in hidden {
+		if given {
+			conflicts.push(Conflict::new(
+				"--isolate",
+				flag,
+				format!("an isolated OS can't see any {}", what),
+			));
+		}
+	}
+}
+
+/// Only one device can answer at each I²C address.
+fn check_i2c(options: &Options, conflicts: &mut Vec<Conflict>) {
+	let devices: Vec<(&str, &i2c::DeviceSpec)> = options
+		.i2c_eeprom
+		.iter()
+		.map(|spec| ("--i2c-eeprom", spec))
+		.chain(
+			options
+				.i2c_devices
+				.iter()
+				.map(|spec| ("--i2c-device", spec)),
+		)
+		.collect();
+	for (idx, (first, a)) in devices.iter().enumerate() {
+		for (second, b) in devices.iter().skip(idx + 1) {
+			if (a.bus, a.address) == (b.bus, b.address) {
+				conflicts.push(Conflict::new(
+					first,
+					second,
+					format!(
+						"they both put a device at 0x{:02x} on I2C Bus {}",
+						a.address, a.bus
+					),
+				));
+			}
+		}
+	}
+}
+
+/// The Neotron Bus only has so many chip-selects.
+fn check_bus(options: &Options, conflicts: &mut Vec<Conflict>) {
+	let max = bus::max_peripherals();
+	if options.bus_devices.len() > max {
+		conflicts.push(Conflict::new(
+			"--bus-device",
+			"--bus-device",
+			format!(
+				"it was given {} times, but the bus can only have {} peripherals",
+				options.bus_devices.len(),
+				max
+			),
+		));
+	}
+}
+
+/// Something that's the same for two paths to the same file, even if they're
+/// written differently (e.g. `disk.img` and `./disk.img`), and even if the
+/// file doesn't exist yet.
+fn identity(path: &Path) -> PathBuf {
+	if let Ok(canonical) = path.canonicalize() {
+		return canonical;
+	}
+	let parent = match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => parent,
+		_ => Path::new("."),
+	};
+	match (parent.canonicalize(), path.file_name()) {
+		(Ok(parent), Some(name)) => parent.join(name),
+		_ => path.to_owned(),
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Conflict {
+	fn new(first: &str, second: &str, reason: impl Into<String>) -> Conflict {
+		Conflict {
+			first: first.to_string(),
+			second: second.to_string(),
+			reason: reason.into(),
+		}
+	}
+}
+
+impl std::fmt::Display for Conflict {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.first == self.second {
+			write!(
+				f,
+				"'{}' can't be used like this: {}",
+				self.first, self.reason
+			)
+		} else {
+			write!(
+				f,
+				"'{}' can't be used with '{}': {}",
+				self.first, self.second, self.reason
+			)
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,526 @@
+//! # Debug console for the Neotron Desktop BIOS
+//!
+//! With `--debug-console`, we read commands from standard input and print the
+//! results to standard output. Type `help` for a list of commands. The same
+//! commands work over the `--monitor` socket (see [`crate::monitor`]).
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A debug console command.
+struct Command {
+	/// What you type to run it
+	name: &'static str,
+	/// What arguments it takes, for the help text
+	usage: &'static str,
+	/// What it does, for the help text
+	help: &'static str,
+	/// Runs the command with the given arguments, returning the text to print
+	handler: fn(&[&str]) -> Result<String, String>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+static COMMANDS: &[Command] = &[
+	Command {
+		name: "help",
+		usage: "",
+		help: "List the commands",
+		handler: cmd_help,
+	},
+	Command {
+		name: "time-scale",
+		usage: "[<scale>]",
+		help: "Show or change how fast emulated time runs",
+		handler: cmd_time_scale,
+	},
+	Command {
+		name: "audio",
+		usage: "",
+		help: "Show the audio glitch counters and output position",
+		handler: cmd_audio,
+	},
+	Command {
+		name: "button",
+		usage: "[<n> down|up]",
+		help: "Show the PCF8574 panel, or press or release a button",
+		handler: cmd_button,
+	},
+	Command {
+		name: "gpio",
+		usage: "[<n> on|off]",
+		help: "Show the GPIO peripheral's lines, or set an input",
+		handler: cmd_gpio,
+	},
+	Command {
+		name: "snapshot",
+		usage: "[<path>]",
+		help: "Save the state of the machine",
+		handler: cmd_snapshot,
+	},
+	Command {
+		name: "stats",
+		usage: "api|video [reset] | lint",
+		help: "Show the BIOS call, rendering or API misuse statistics",
+		handler: cmd_stats,
+	},
+	Command {
+		name: "reset",
+		usage: "",
+		help: "Reset the OS, like Ctrl+Alt+Del",
+		handler: cmd_reset,
+	},
+	Command {
+		name: "mem",
+		usage: "dump <offset> <len> [<region>] | find <hex>",
+		help: "Show or search the OS's RAM",
+		handler: cmd_mem,
+	},
+	Command {
+		name: "memdump",
+		usage: "<region> <path> [<offset> <len>]",
+		help: "Save some or all of a memory region to a file",
+		handler: cmd_memdump,
+	},
+	Command {
+		name: "info",
+		usage: "mode",
+		help: "Show the video mode",
+		handler: cmd_info,
+	},
+	Command {
+		name: "text",
+		usage: "[attrs]",
+		help: "Show the text on the screen, or each cell's attribute",
+		handler: cmd_text,
+	},
+	Command {
+		name: "cells",
+		usage: "[on|off]",
+		help: "Show each text cell's bytes in hex, instead of its glyph",
+		handler: cmd_cells,
+	},
+	Command {
+		name: "screenshot",
+		usage: "<path>",
+		help: "Save what's in the window as a PNG",
+		handler: cmd_screenshot,
+	},
+	Command {
+		name: "eject",
+		usage: "<n>",
+		help: "Take the disk image out of a drive",
+		handler: cmd_eject,
+	},
+	Command {
+		name: "insert",
+		usage: "<n> <path>",
+		help: "Put a disk image in a drive",
+		handler: cmd_insert,
+	},
+	Command {
+		name: "sendkey",
+		usage: "<keys>",
+		help: "Press some keys together, like ctrl-alt-del",
+		handler: cmd_sendkey,
+	},
+	Command {
+		name: "pause",
+		usage: "",
+		help: "Pause the emulation",
+		handler: cmd_pause,
+	},
+	Command {
+		name: "resume",
+		usage: "",
+		help: "Resume the emulation",
+		handler: cmd_resume,
+	},
+	Command {
+		name: "quit",
+		usage: "",
+		help: "Stop the OS and exit",
+		handler: cmd_quit,
+	},
+];
+
+/// The most matches `mem find` will show.
+const MAX_FIND_MATCHES: usize = 32;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Start reading commands from standard input, on a new thread.
+pub fn start() {
+	std::thread::spawn(|| {
+		let stdin = std::io::stdin();
+		println!("Debug console ready. Type 'help' for a list of commands.");
+		for line in stdin.lock().lines() {
+			let Ok(line) = line else {
+				break;
+			};
+			if let Some(output) = run(&line) {
+				println!("{}", output);
+			}
+		}
+	});
+}
+
+/// Run one line of input, returning the text to print (if any).
+pub fn run(line: &str) -> Option<String> {
+	let words: Vec<&str> = line.split_whitespace().collect();
+	let (name, args) = words.split_first()?;
+	let Some(command) = COMMANDS.iter().find(|c| c.name == *name) else {
+		return Some(format!("Unknown command {:?}. Try 'help'.", name));
+	};
+	match (command.handler)(args) {
+		Ok(output) => Some(output),
+		Err(e) => Some(format!("Error: {}", e)),
+	}
+}
+
+/// Handle the `help` command.
+fn cmd_help(_args: &[&str]) -> Result<String, String> {
+	let mut output = String::from("Commands:");
+	for command in COMMANDS {
+		let usage = format!("{} {}", command.name, command.usage);
+		output.push_str(&format!("\n  {:24} {}", usage, command.help));
+	}
+	Ok(output)
+}
+
+/// Handle the `time-scale` command.
+fn cmd_time_scale(args: &[&str]) -> Result<String, String> {
+	match args {
+		[] => Ok(format!("Time scale is {}", crate::clock::scale())),
+		[scale] => {
+			let scale = crate::clock::parse_scale(scale)?;
+			crate::clock::set_scale(scale);
+			Ok(format!("Time scale set to {}", scale))
+		}
+		_ => Err("usage: time-scale [<scale>]".into()),
+	}
+}
+
+/// Handle the `audio` command.
+fn cmd_audio(_args: &[&str]) -> Result<String, String> {
+	let mut output = format!("Audio: {}", crate::audio::stats());
+	if let Some(position) = crate::audio::position() {
+		output.push_str(&format!("\nAudio: {}", position));
+	}
+	Ok(output)
+}
+
+/// Handle the `mem` command.
+fn cmd_mem(args: &[&str]) -> Result<String, String> {
+	match args {
+		["dump", offset, len, rest @ ..] if rest.len() <= 1 => {
+			let offset = parse_number(offset)?;
+			let len = parse_number(len)?;
+			let region = match rest {
+				[region] => parse_number(region)?
+					.try_into()
+					.map_err(|_| format!("bad region {:?}", region))?,
+				_ => 0,
+			};
+			let bytes = crate::memory::snapshot(region, offset, len)?;
+			Ok(format!(
+				"Live snapshot of Region {} (the OS may be changing it):\n{}",
+				region,
+				crate::hexdump::hexdump(&bytes, offset)
+			))
+		}
+		["find", hex @ ..] if !hex.is_empty() => {
+			let needle = crate::hexdump::parse_hex(&hex.concat())
+				.ok_or("bad hex bytes (try 'de ad be ef')")?;
+			let matches = crate::memory::find(&needle, MAX_FIND_MATCHES);
+			let mut output = format!("Found {} match(es) in a live snapshot:", matches.len());
+			for (region, offset) in &matches {
+				output.push_str(&format!("\n  Region {} offset 0x{:08x}", region, offset));
+			}
+			if matches.len() == MAX_FIND_MATCHES {
+				output.push_str("\n  (stopped looking after this many)");
+			}
+			Ok(output)
+		}
+		_ => Err("usage: mem dump <offset> <len> [<region>] | mem find <hex>".into()),
+	}
+}
+
+/// Handle the `button` command.
+fn cmd_button(args: &[&str]) -> Result<String, String> {
+	match args {
+		[] => {}
+		[button, action @ ("down" | "up")] => {
+			let button = button
+				.parse()
+				.map_err(|_| format!("{:?} is not a button number", button))?;
+			crate::i2c::set_button(button, *action == "down")?;
+		}
+		_ => return Err("usage: button [<n> down|up]".into()),
+	}
+	let panel = crate::i2c::panel().ok_or("there is no PCF8574 on the I2C bus")?;
+	Ok(format!(
+		"LEDs lit: {:08b}, buttons down: {:08b}",
+		!panel.latch, panel.buttons
+	))
+}
+
+/// Handle the `gpio` command.
+fn cmd_gpio(args: &[&str]) -> Result<String, String> {
+	match args {
+		[] => {}
+		[line, action @ ("on" | "off")] => {
+			let line = line
+				.parse()
+				.map_err(|_| format!("{:?} is not an input number", line))?;
+			crate::bus::set_input(line, *action == "on")?;
+		}
+		_ => return Err("usage: gpio [<n> on|off]".into()),
+	}
+	let lines = crate::bus::lines().ok_or("there is no GPIO peripheral on the bus")?;
+	Ok(format!(
+		"Outputs: {:08b}, inputs: {:08b}, changed: {:08b}, interrupt enable: {:08b}",
+		lines.outputs, lines.inputs, lines.changed, lines.interrupt_enable
+	))
+}
+
+/// Save a snapshot of the machine.
+fn cmd_snapshot(args: &[&str]) -> Result<String, String> {
+	let path = match args {
+		[] => crate::SNAPSHOT_PATH
+			.get()
+			.cloned()
+			.ok_or("the machine hasn't started yet")?,
+		[path] => std::path::PathBuf::from(path),
+		_ => return Err("usage: snapshot [<path>]".into()),
+	};
+	crate::take_snapshot(&path)?;
+	Ok(format!("Saved snapshot to {}", path.display()))
+}
+
+/// Show or reset the BIOS call or rendering statistics, or show the API
+/// misuse counts.
+fn cmd_stats(args: &[&str]) -> Result<String, String> {
+	match args {
+		["api"] => Ok(crate::apistats::report()),
+		["api", "reset"] => {
+			crate::apistats::reset();
+			Ok("BIOS call counters reset".to_string())
+		}
+		["video"] => Ok(crate::videostats::report()),
+		["video", "reset"] => {
+			crate::videostats::reset();
+			Ok("Rendering statistics reset".to_string())
+		}
+		["lint"] => Ok(crate::lint::report_counts()),
+		_ => Err("usage: stats api|video [reset] | stats lint".into()),
+	}
+}
+
+/// Reset the OS at its next BIOS call.
+fn cmd_reset(args: &[&str]) -> Result<String, String> {
+	if !args.is_empty() {
+		return Err("usage: reset".into());
+	}
+	crate::request_reset();
+	Ok("The OS will reset at its next BIOS call".to_string())
+}
+
+/// Save a memory region to a file, while the OS is stopped.
+fn cmd_memdump(args: &[&str]) -> Result<String, String> {
+	let (region, path, range) = match args {
+		[region, path] => (region, path, None),
+		[region, path, offset, len] => (
+			region,
+			path,
+			Some((parse_number(offset)?, parse_number(len)?)),
+		),
+		_ => return Err("usage: memdump <region> <path> [<offset> <len>]".into()),
+	};
+	// Take `region0` as well as `0`
+	let region = region
+		.strip_prefix("region")
+		.unwrap_or(region)
+		.parse()
+		.map_err(|_| format!("{:?} is not a region number", region))?;
+	let path = std::path::Path::new(path);
+	let len = crate::dump_memory(region, range, path)?;
+	Ok(format!(
+		"Saved {} bytes of Region {} to {}",
+		len,
+		region,
+		path.display()
+	))
+}
+
+/// Show things about the machine.
+fn cmd_info(args: &[&str]) -> Result<String, String> {
+	match args {
+		["mode"] => Ok(crate::video::describe_mode()),
+		_ => Err("usage: info mode".into()),
+	}
+}
+
+/// Show the text on the screen, or the attribute byte of each cell in hex.
+///
+/// For the text, trailing blanks and blank rows at the bottom are left off.
+fn cmd_text(args: &[&str]) -> Result<String, String> {
+	let screen = crate::video::read_text().ok_or("the screen is not in a text mode")?;
+	let mut lines: Vec<String> = match args {
+		[] => screen
+			.rows
+			.iter()
+			.map(|row| row.trim_end().to_string())
+			.collect(),
+		["attrs"] => screen
+			.attrs
+			.iter()
+			.map(|row| {
+				row.iter()
+					.map(|attr| format!("{:02x}", attr.0))
+					.collect::<Vec<_>>()
+					.join(" ")
+			})
+			.collect(),
+		_ => return Err("usage: text [attrs]".into()),
+	};
+	while lines.last().is_some_and(|line| line.trim().is_empty()) {
+		lines.pop();
+	}
+	Ok(lines.join("\n"))
+}
+
+/// Show or hide the cell view.
+fn cmd_cells(args: &[&str]) -> Result<String, String> {
+	match args {
+		[] => {}
+		["on"] => crate::cellview::set_enabled(true),
+		["off"] => crate::cellview::set_enabled(false),
+		_ => return Err("usage: cells [on|off]".into()),
+	}
+	Ok(format!(
+		"Cell view is {}",
+		if crate::cellview::is_enabled() {
+			"on"
+		} else {
+			"off"
+		}
+	))
+}
+
+/// Save what's in the window as a PNG.
+fn cmd_screenshot(args: &[&str]) -> Result<String, String> {
+	let [path] = args else {
+		return Err("usage: screenshot <path>".into());
+	};
+	let path = std::path::Path::new(path);
+	crate::window::save_screenshot(path)?;
+	Ok(format!("Saved screenshot to {}", path.display()))
+}
+
+/// Take the disk image out of a drive.
+fn cmd_eject(args: &[&str]) -> Result<String, String> {
+	let [drive] = args else {
+		return Err("usage: eject <n>".into());
+	};
+	let drive = parse_drive(drive)?;
+	crate::block::eject_disk(drive)?;
+	Ok(format!("Disk {} ejected", drive))
+}
+
+/// Put a disk image in a drive.
+fn cmd_insert(args: &[&str]) -> Result<String, String> {
+	let [drive, path] = args else {
+		return Err("usage: insert <n> <path>".into());
+	};
+	let drive = parse_drive(drive)?;
+	crate::block::insert_disk(drive, std::path::Path::new(path))?;
+	Ok(format!("Disk {} is now {}", drive, path))
+}
+
+/// Press some keys together, then let go, as if typed on the keyboard.
+fn cmd_sendkey(args: &[&str]) -> Result<String, String> {
+	let [chord] = args else {
+		return Err("usage: sendkey <keys> (e.g. ctrl-alt-del)".into());
+	};
+	let keys = crate::hotkey::parse_chord(chord)?;
+	crate::window::gui_request(crate::window::GuiRequest::SendKeys(keys))?;
+	Ok(format!("Sent {}", chord))
+}
+
+/// Pause the emulation.
+fn cmd_pause(args: &[&str]) -> Result<String, String> {
+	if !args.is_empty() {
+		return Err("usage: pause".into());
+	}
+	crate::window::gui_request(crate::window::GuiRequest::SetPaused(true))?;
+	Ok("Emulation paused".to_string())
+}
+
+/// Resume the emulation.
+fn cmd_resume(args: &[&str]) -> Result<String, String> {
+	if !args.is_empty() {
+		return Err("usage: resume".into());
+	}
+	crate::window::gui_request(crate::window::GuiRequest::SetPaused(false))?;
+	Ok("Emulation resumed".to_string())
+}
+
+/// Stop the OS and exit, like closing the window.
+fn cmd_quit(args: &[&str]) -> Result<String, String> {
+	if !args.is_empty() {
+		return Err("usage: quit".into());
+	}
+	// Shutting down takes a moment, so we have time to say goodbye
+	std::thread::spawn(|| crate::shutdown::shutdown(crate::shutdown::ExitCode::WindowClosed));
+	Ok("Shutting down".to_string())
+}
+
+/// Parse a disk drive number.
+fn parse_drive(text: &str) -> Result<u8, String> {
+	text.parse()
+		.map_err(|_| format!("{:?} is not a drive number", text))
+}
+
+/// Parse a number, in decimal or (with a `0x` prefix) hex.
+fn parse_number(text: &str) -> Result<usize, String> {
+	let result = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+		Some(hex) => usize::from_str_radix(hex, 16),
+		None => text.parse(),
+	};
+	result.map_err(|_| format!("{:?} is not a number", text))
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
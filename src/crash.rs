@@ -0,0 +1,260 @@
+//! # Catching OS panics
+//!
+//! If the OS panics, we want to say so, not leave the window showing a frozen
+//! screen. We can't catch the panic with `catch_unwind`, because it would have
+//! to unwind out through `os_main` and our BIOS functions, which are all
+//! `extern "C"` and so abort the process instead. So we install a panic hook,
+//! which runs on the OS thread before any unwinding happens.
+//!
+//! The hook logs the message and a backtrace, and the window shows a banner.
+//! Then the OS thread waits, and either exits with the "OS panicked" exit code
+//! (with `--exit-on-panic`), or is reset when you press Prefix + R.
+//!
+//! This only sees panics that go through our copy of the Rust standard
+//! library. An OS with its own panic handler, or one built with its own copy
+//! of `std`, deals with its own panics.
+//!
+//! Any panic, in the OS or in the BIOS, also gets a crash report: one text
+//! file with the message, the backtrace, the state of the machine and the
+//! last few trace lines, for attaching to bug reports (see [`save_report`]).
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What we call the OS thread, so the panic hook can spot it.
+pub const OS_THREAD_NAME: &str = "os";
+
+/// What the OS panicked with, if it has panicked and not been reset.
+static CRASH: Mutex<Option<String>> = Mutex::new(None);
+
+/// Wakes up the OS thread when it's time to reset.
+static RESET: Condvar = Condvar::new();
+
+/// Whether we exit rather than wait to be reset.
+static EXIT_ON_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Where crash reports go, if anywhere.
+static REPORT_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// How long we wait to find out the state of the machine for a crash report.
+/// Whatever crashed might be holding a lock we need, so we can't wait forever.
+const STATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Catch panics on the OS thread, and write a crash report for any panic to
+/// `report_dir`.
+///
+/// Panics on any other thread go to the hook that was there before, once
+/// we've written the report.
+pub fn install_hook(exit_on_panic: bool, report_dir: Option<PathBuf>) {
+	EXIT_ON_PANIC.store(exit_on_panic, Ordering::Relaxed);
+	if let Some(dir) = report_dir {
+		let _ = REPORT_DIR.set(dir);
+	}
+	let previous = std::panic::take_hook();
+	std::panic::set_hook(Box::new(move |info| {
+		if crate::isolate::is_child() {
+			// The parent notices we've gone, and says so
+			previous(info);
+			crate::isolate::child_panicked();
+		}
+		let thread = std::thread::current();
+		let payload = info.payload();
+		let message = payload
+			.downcast_ref::<&str>()
+			.copied()
+			.or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+			.unwrap_or("<no message>");
+		let message = match info.location() {
+			Some(location) => format!("{} (at {})", message, location),
+			None => message.to_string(),
+		};
+		let backtrace = std::backtrace::Backtrace::force_capture();
+		if thread.name() != Some(OS_THREAD_NAME) {
+			save_report(
+				&format!(
+					"The BIOS panicked on thread {}: {}",
+					thread.name().unwrap_or("<unnamed>"),
+					message
+				),
+				Some(&backtrace),
+			);
+			previous(info);
+			return;
+		}
+		log::error!("The OS panicked: {}\n{}", message, backtrace);
+		save_report(&format!("The OS panicked: {}", message), Some(&backtrace));
+		crate::remote::notify(crate::remote::Event::Panic(message.to_string()));
+		wait_for_reset(format!("The OS panicked: {}", message));
+		// Returning from the panic hook would abort
+		crate::reset_os()
+	}));
+}
+
+/// Where we put crash reports if `--crash-dir` isn't given.
+///
+/// This is `neotron-desktop-bios/crashes` in the platform's local data
+/// directory (e.g. `~/.local/share` on Linux).
+pub fn default_report_dir() -> Option<PathBuf> {
+	dirs::data_local_dir().map(|dir| dir.join("neotron-desktop-bios").join("crashes"))
+}
+
+/// Write a crash report, if we know where to put it, and say where it went.
+///
+/// The report is one text file, holding `message`, the backtrace, what we
+/// were running, the state of the machine and the last few trace lines.
+pub fn save_report(message: &str, backtrace: Option<&std::backtrace::Backtrace>) {
+	let Some(dir) = REPORT_DIR.get() else {
+		return;
+	};
+	let mut report = format!("Neotron Desktop BIOS crash report\n\n{}\n", message);
+	report.push_str(&format!("\nBIOS version: {}\n", env!("CARGO_PKG_VERSION")));
+	report.push_str(&format!(
+		"OS version: {}\n",
+		crate::loader::os_version().unwrap_or("unknown")
+	));
+	let args: Vec<String> = std::env::args().collect();
+	report.push_str(&format!("Command line: {}\n", args.join(" ")));
+	if let Some(backtrace) = backtrace {
+		report.push_str(&format!("\n## Backtrace\n\n{}\n", backtrace));
+	}
+	// The machine's state is behind locks that whatever crashed might be
+	// holding, so we ask for it on another thread and don't wait too long
+	let (sender, receiver) = std::sync::mpsc::channel();
+	std::thread::spawn(move || {
+		let _ = sender.send(crate::describe_machine());
+	});
+	match receiver.recv_timeout(STATE_TIMEOUT) {
+		Ok(state) => report.push_str(&state),
+		Err(_) => report.push_str("\n(Couldn't get the state of the machine)\n"),
+	}
+	report.push_str("\n## Latest Trace Lines\n\n");
+	match crate::trace::recent_lines() {
+		Some(lines) if !lines.is_empty() => {
+			for line in lines {
+				report.push_str(&line);
+				report.push('\n');
+			}
+		}
+		Some(_) => report.push_str("(None - use --trace-api to see the latest BIOS calls)\n"),
+		None => report.push_str("(The trace was busy)\n"),
+	}
+	match write_report(dir, &report) {
+		Ok(path) => {
+			log::error!("Crash report saved to {}", path.display());
+			eprintln!("Crash report saved to {}", path.display());
+		}
+		Err(e) => log::error!("Failed to save a crash report in {}: {}", dir.display(), e),
+	}
+}
+
+/// Put a crash report in a new file in `dir`, named after the time.
+fn write_report(dir: &std::path::Path, report: &str) -> std::io::Result<PathBuf> {
+	std::fs::create_dir_all(dir)?;
+	let seconds = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	let mut suffix = 0;
+	loop {
+		let name = if suffix == 0 {
+			format!("crash-{}.txt", seconds)
+		} else {
+			format!("crash-{}-{}.txt", seconds, suffix)
+		};
+		let path = dir.join(name);
+		match std::fs::OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&path)
+		{
+			Ok(mut file) => {
+				file.write_all(report.as_bytes())?;
+				return Ok(path);
+			}
+			Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => suffix += 1,
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// What happened to the OS, if it has crashed and not been reset.
+pub fn crash_message() -> Option<String> {
+	CRASH.lock().unwrap().clone()
+}
+
+/// Has the OS crashed, and not been reset?
+pub fn is_crashed() -> bool {
+	CRASH.lock().unwrap().is_some()
+}
+
+/// Reset the OS after a crash. Does nothing if it hasn't crashed.
+pub fn reset() {
+	CRASH.lock().unwrap().take();
+	RESET.notify_all();
+}
+
+/// The OS has crashed. Show `message`, then either exit or wait until we're
+/// asked to reset it.
+///
+/// Call this from the OS thread only.
+pub fn wait_for_reset(message: String) {
+	if let Some(version) = crate::loader::os_version() {
+		log::error!("The OS that crashed says it is {}", version);
+	}
+	if EXIT_ON_PANIC.load(Ordering::Relaxed) {
+		crate::shutdown::power_off(crate::shutdown::ExitCode::OsPanic);
+	}
+	log::info!("Press the hotkey prefix and R to reset the OS");
+	let mut crash = CRASH.lock().unwrap();
+	*crash = Some(message);
+	while crash.is_some() {
+		// Time out now and then, in case we're shutting down
+		crash = RESET
+			.wait_timeout(crash, Duration::from_millis(100))
+			.unwrap()
+			.0;
+		if crate::shutdown::is_stopping() {
+			drop(crash);
+			crate::shutdown::checkpoint();
+			unreachable!("the OS thread to stop for good");
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
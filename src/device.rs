@@ -0,0 +1,684 @@
+//! # Attaching devices
+//!
+//! Every `--device` option uses the same grammar:
+//!
+//! ```text
+//! <class>[:<slot>]=<backend>[:<target>][,<key>[=<value>]]...
+//! ```
+//!
+//! So `disk:0=file:./boot.img,ro` puts `boot.img` in block device 0 and
+//! makes it read-only, `i2c:0x50=eeprom:./ee.bin,size=8KiB` puts an EEPROM at
+//! address `0x50` on I²C Bus 0, and `bus:2=timer,rate=10Hz` puts a timer in
+//! Neotron Bus slot 2. The classes are:
+//!
+//! * `disk[:0]` - `file:<path>`, with `ro` to make it read-only
+//! * `disk[:1]` - `transfer:<dir>`, a [`transfer`] device for moving files
+//!   to and from the host, with `ro` so the OS can only get files, and an
+//!   optional `limit` on their size
+//! * `i2c:<address>` - `eeprom:<path>` (with a `size`), `lm75` (with an
+//!   optional `celsius`) or `pcf8574`, and `bus=1` for I²C Bus 1
+//! * `bus[:<slot>]` - `slot` (an empty slot), `loopback`, `gpio`, `timer`
+//!   (with a `rate`), `sdcard:<path>` or `flash:<path>` (with a `size`). A
+//!   peripheral without a slot goes in the next free one.
+//! * `serial:<port>` - `null`, `loopback` or `slip-tap:<name>` (with an
+//!   optional `mtu`). `--serial<N>` takes the part after the `=`.
+//!
+//! Errors point at the part of the option that's wrong. A [`Device`] prints
+//! as the `--device` option that would attach it, which is how
+//! `--list-devices` shows them.
+//!
+//! This replaces `--disk`, `--i2c-eeprom`, `--i2c-device` and
+//! `--bus-device`, which still work for now.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::{bus, i2c, memory, serial, slip, transfer};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A device to attach to the machine.
+#[derive(Debug, Clone)]
+pub enum Device {
+	/// A disk image in block device 0
+	Disk {
+		/// The disk image
+		path: PathBuf,
+		/// Whether the OS is told it can't write to it
+		read_only: bool,
+	},
+	/// A transfer device in block device 1
+	Transfer(transfer::Spec),
+	/// An I²C device
+	I2c(i2c::DeviceSpec),
+	/// A Neotron Bus peripheral, in this slot or the next free one
+	Bus {
+		/// Which slot it goes in, if it matters
+		slot: Option<u8>,
+		/// What it is
+		spec: bus::DeviceSpec,
+	},
+	/// Something plugged into a serial port
+	Serial {
+		/// Which port
+		port: u8,
+		/// What it is
+		spec: serial::DeviceSpec,
+	},
+}
+
+/// A `--device` option we couldn't make sense of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+	/// The whole option
+	pub text: String,
+	/// Which bytes of it are wrong
+	pub span: Range<usize>,
+	/// What's wrong with them
+	pub message: String,
+}
+
+/// One piece of a `--device` option, and where it is in the option.
+#[derive(Debug, Clone, Copy)]
+struct Part<'a> {
+	text: &'a str,
+	start: usize,
+}
+
+/// The `key=value` options after the backend, and which ones we've used.
+struct Options<'a> {
+	/// The whole `--device` option, for errors
+	text: &'a str,
+	/// Each key, and its value if it has one
+	items: Vec<(Part<'a>, Option<Part<'a>>)>,
+	/// Which of `items` we've used
+	used: Vec<bool>,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--device` option, like `disk:0=file:boot.img,ro`.
+pub fn parse(text: &str) -> Result<Device, Error> {
+	let whole = Part { text, start: 0 };
+	let Some(equals) = text.find('=') else {
+		return Err(whole.error(
+			text,
+			"expected <class>[:<slot>]=<backend>[,<options>] (e.g. disk:0=file:boot.img)",
+		));
+	};
+	let (class, slot) = whole.slice(0, equals).split(':');
+	let (backend, target, mut options) = split_backend(text, whole.slice(equals + 1, text.len()))?;
+
+	let device = match class.text {
+		"disk" => parse_disk(text, slot, backend, target, &mut options)?,
+		"i2c" => parse_i2c(text, class, slot, backend, target, &mut options)?,
+		"bus" => parse_bus(text, slot, backend, target, &mut options)?,
+		"serial" => {
+			let port = match slot {
+				Some(slot) => match slot.text.parse::<u8>() {
+					Ok(number) if usize::from(number) < serial::max_ports() => number,
+					_ => {
+						return Err(slot.error(
+							text,
+							&format!("not a port number (try 0 to {})", serial::max_ports() - 1),
+						))
+					}
+				},
+				None => return Err(class.error(text, "give the port number, like serial:0")),
+			};
+			Device::Serial {
+				port,
+				spec: parse_serial_backend(text, backend, target, &mut options)?,
+			}
+		}
+		_ => return Err(class.error(text, "unknown device class (try disk, i2c, bus or serial)")),
+	};
+	options.finish()?;
+	Ok(device)
+}
+
+/// Parse a `--serial<N>` option, like `slip-tap:neotap0,mtu=1500`. It's the
+/// part of a `--device serial:<N>=...` option after the `=`.
+pub fn parse_serial(text: &str) -> Result<serial::DeviceSpec, Error> {
+	let (backend, target, mut options) = split_backend(text, Part { text, start: 0 })?;
+	let spec = parse_serial_backend(text, backend, target, &mut options)?;
+	options.finish()?;
+	Ok(spec)
+}
+
+/// Split the part of an option after the `=` into the backend, its target
+/// and its options.
+fn split_backend<'a>(
+	text: &'a str,
+	part: Part<'a>,
+) -> Result<(Part<'a>, Option<Part<'a>>, Options<'a>), Error> {
+	let mut items = part.split_all(',');
+	let backend_part = items.remove(0);
+	if backend_part.text.is_empty() {
+		return Err(backend_part.error(text, "expected a backend after the '='"));
+	}
+	let (backend, target) = backend_part.split(':');
+	Ok((backend, target, Options::new(text, items)?))
+}
+
+/// Parse the rest of a `disk` device.
+fn parse_disk(
+	text: &str,
+	slot: Option<Part>,
+	backend: Part,
+	target: Option<Part>,
+	options: &mut Options,
+) -> Result<Device, Error> {
+	// Each backend has a block device of its own
+	let wanted = match backend.text {
+		"file" => "0",
+		"transfer" => "1",
+		_ => {
+			return Err(backend.error(
+				text,
+				"unknown disk backend (try file:<path> or transfer:<dir>)",
+			))
+		}
+	};
+	if let Some(slot) = slot {
+		if slot.text != wanted {
+			let message = if wanted == "0" {
+				"there's only block device 0 for disk images (block device 1 is for transfer:<dir>)"
+			} else {
+				"the transfer device is always block device 1"
+			};
+			return Err(slot.error(text, message));
+		}
+	}
+	if backend.text == "transfer" {
+		let dir = need_target(text, backend, target, "transfer:./outbox")?;
+		let read_only = options.flag("ro")?;
+		let limit = match options.value("limit") {
+			Some(limit) => memory::parse_size(limit.text)
+				.and_then(|bytes| match u32::try_from(bytes) {
+					Ok(_) => Ok(bytes),
+					Err(_) => Err(String::from("the limit must be under 4GiB")),
+				})
+				.map_err(|e| limit.error(text, &e))?,
+			None => transfer::DEFAULT_LIMIT,
+		};
+		return Ok(Device::Transfer(transfer::Spec {
+			dir,
+			read_only,
+			limit,
+		}));
+	}
+	let path = need_target(text, backend, target, "file:boot.img")?;
+	Ok(Device::Disk {
+		path,
+		read_only: options.flag("ro")?,
+	})
+}
+
+/// Parse the rest of an `i2c` device.
+fn parse_i2c(
+	text: &str,
+	class: Part,
+	slot: Option<Part>,
+	backend: Part,
+	target: Option<Part>,
+	options: &mut Options,
+) -> Result<Device, Error> {
+	let address = match slot {
+		Some(slot) => i2c::parse_address(slot.text).map_err(|e| slot.error(text, &e))?,
+		None => {
+			return Err(class.error(text, "give the device's address, like i2c:0x50"));
+		}
+	};
+	let bus = match options.value("bus") {
+		Some(value) => value
+			.text
+			.parse()
+			.map_err(|_| value.error(text, "not a bus number (try 0 or 1)"))?,
+		None => 0,
+	};
+	let kind = match backend.text {
+		"eeprom" => {
+			let path = need_target(text, backend, target, "eeprom:eeprom.bin")?;
+			let size = options.need(backend, "size", "size=8KiB")?;
+			i2c::DeviceKind::Eeprom(i2c::EepromSpec {
+				path,
+				size: memory::parse_size(size.text).map_err(|e| size.error(text, &e))?,
+			})
+		}
+		"lm75" => {
+			no_target(text, backend, target)?;
+			let celsius = match options.value("celsius") {
+				Some(value) => value
+					.text
+					.parse()
+					.map_err(|_| value.error(text, "not a temperature (try 21.5)"))?,
+				None => i2c::LM75_CELSIUS,
+			};
+			i2c::DeviceKind::Lm75(celsius)
+		}
+		"pcf8574" => {
+			no_target(text, backend, target)?;
+			i2c::DeviceKind::Pcf8574
+		}
+		_ => {
+			return Err(backend.error(
+				text,
+				"unknown I2C device (try eeprom:<path>, lm75 or pcf8574)",
+			))
+		}
+	};
+	Ok(Device::I2c(i2c::DeviceSpec { bus, address, kind }))
+}
+
+/// Parse the rest of a `bus` device.
+fn parse_bus(
+	text: &str,
+	slot: Option<Part>,
+	backend: Part,
+	target: Option<Part>,
+	options: &mut Options,
+) -> Result<Device, Error> {
+	let slot = match slot {
+		Some(slot) => match slot.text.parse::<u8>() {
+			Ok(number) if usize::from(number) < bus::max_peripherals() => Some(number),
+			_ => {
+				return Err(slot.error(
+					text,
+					&format!(
+						"not a slot number (try 0 to {})",
+						bus::max_peripherals() - 1
+					),
+				))
+			}
+		},
+		None => None,
+	};
+	let spec = match backend.text {
+		"slot" => {
+			no_target(text, backend, target)?;
+			bus::DeviceSpec::Slot
+		}
+		"loopback" => {
+			no_target(text, backend, target)?;
+			bus::DeviceSpec::Loopback
+		}
+		"gpio" => {
+			no_target(text, backend, target)?;
+			bus::DeviceSpec::Gpio
+		}
+		"timer" => {
+			no_target(text, backend, target)?;
+			let rate = options.need(backend, "rate", "rate=10Hz")?;
+			bus::DeviceSpec::Timer(bus::parse_rate(rate.text).map_err(|e| rate.error(text, &e))?)
+		}
+		"sdcard" => bus::DeviceSpec::SdCard(need_target(text, backend, target, "sdcard:sd.img")?),
+		"flash" => {
+			let path = need_target(text, backend, target, "flash:flash.bin")?;
+			let size = options.need(backend, "size", "size=16MiB")?;
+			bus::DeviceSpec::Flash(
+				path,
+				bus::parse_flash_size(size.text).map_err(|e| size.error(text, &e))?,
+			)
+		}
+		_ => {
+			return Err(backend.error(
+				text,
+				"unknown bus peripheral (try slot, loopback, gpio, timer, sdcard:<path> or flash:<path>)",
+			))
+		}
+	};
+	Ok(Device::Bus { slot, spec })
+}
+
+/// Parse what's plugged into a serial port.
+fn parse_serial_backend(
+	text: &str,
+	backend: Part,
+	target: Option<Part>,
+	options: &mut Options,
+) -> Result<serial::DeviceSpec, Error> {
+	match backend.text {
+		"null" => {
+			no_target(text, backend, target)?;
+			Ok(serial::DeviceSpec::Null)
+		}
+		"loopback" => {
+			no_target(text, backend, target)?;
+			Ok(serial::DeviceSpec::Loopback)
+		}
+		"slip-tap" => {
+			let name = match target {
+				Some(target) => {
+					serial::check_tap_name(target.text).map_err(|e| target.error(text, &e))?;
+					target.text.to_string()
+				}
+				None => {
+					return Err(backend.error(text, "give a TUN device name, like slip-tap:neotap0"))
+				}
+			};
+			let mtu = match options.value("mtu") {
+				Some(value) => match value.text.parse::<usize>() {
+					Ok(mtu) if (slip::MIN_MTU..=slip::MAX_MTU).contains(&mtu) => mtu,
+					_ => {
+						return Err(value.error(
+							text,
+							&format!(
+								"not an MTU (try {} to {} bytes)",
+								slip::MIN_MTU,
+								slip::MAX_MTU
+							),
+						))
+					}
+				},
+				None => slip::DEFAULT_MTU,
+			};
+			Ok(serial::DeviceSpec::SlipTap { name, mtu })
+		}
+		_ => Err(backend.error(
+			text,
+			"unknown serial device (try null, loopback or slip-tap:<name>)",
+		)),
+	}
+}
+
+/// The path after a backend that needs one, like `file:boot.img`.
+fn need_target(
+	text: &str,
+	backend: Part,
+	target: Option<Part>,
+	example: &str,
+) -> Result<PathBuf, Error> {
+	match target {
+		Some(target) if !target.text.is_empty() => Ok(PathBuf::from(target.text)),
+		_ => Err(backend.error(text, &format!("give a path, like {}", example))),
+	}
+}
+
+/// Complain about a path after a backend that doesn't take one.
+fn no_target(text: &str, backend: Part, target: Option<Part>) -> Result<(), Error> {
+	match target {
+		Some(target) => Err(target.error(text, &format!("{} doesn't take a path", backend.text))),
+		None => Ok(()),
+	}
+}
+
+/// Put a Neotron Bus peripheral in `slot`, or in the next free slot if it
+/// doesn't say, filling any gap before it with empty slots.
+pub fn place(
+	peripherals: &mut Vec<bus::DeviceSpec>,
+	slot: Option<u8>,
+	spec: bus::DeviceSpec,
+) -> Result<(), String> {
+	let Some(slot) = slot.map(usize::from) else {
+		peripherals.push(spec);
+		return Ok(());
+	};
+	while peripherals.len() <= slot {
+		peripherals.push(bus::DeviceSpec::Slot);
+	}
+	if !matches!(peripherals[slot], bus::DeviceSpec::Slot) {
+		return Err(format!(
+			"two peripherals want Neotron Bus slot {} ({} and {})",
+			slot,
+			Device::Bus {
+				slot: Some(slot as u8),
+				spec: peripherals[slot].clone(),
+			},
+			Device::Bus {
+				slot: Some(slot as u8),
+				spec,
+			}
+		));
+	}
+	peripherals[slot] = spec;
+	Ok(())
+}
+
+/// A size as it would be written in an option, like `8KiB`.
+fn size_text(bytes: usize) -> String {
+	let described = memory::describe_size(bytes as u64);
+	if described.ends_with("bytes") {
+		bytes.to_string()
+	} else {
+		described.replace(' ', "")
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Device {
+	/// Make a relative path in this device relative to `base` instead, for
+	/// devices from a config file.
+	pub fn relative_to(&mut self, base: &Path) {
+		let path = match self {
+			Device::Disk { path, .. } => path,
+			Device::Transfer(spec) => &mut spec.dir,
+			Device::I2c(i2c::DeviceSpec {
+				kind: i2c::DeviceKind::Eeprom(eeprom),
+				..
+			}) => &mut eeprom.path,
+			Device::Bus {
+				spec: bus::DeviceSpec::SdCard(path) | bus::DeviceSpec::Flash(path, _),
+				..
+			} => path,
+			_ => return,
+		};
+		if path.is_relative() {
+			*path = base.join(&*path);
+		}
+	}
+}
+
+impl std::fmt::Display for Device {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Device::Disk { path, read_only } => {
+				write!(f, "disk:0=file:{}", path.display())?;
+				if *read_only {
+					write!(f, ",ro")?;
+				}
+				Ok(())
+			}
+			Device::Transfer(spec) => {
+				write!(f, "disk:1=transfer:{}", spec.dir.display())?;
+				if spec.read_only {
+					write!(f, ",ro")?;
+				}
+				if spec.limit != transfer::DEFAULT_LIMIT {
+					write!(f, ",limit={}", size_text(spec.limit))?;
+				}
+				Ok(())
+			}
+			Device::I2c(spec) => {
+				write!(f, "i2c:0x{:02x}=", spec.address)?;
+				match &spec.kind {
+					i2c::DeviceKind::Eeprom(eeprom) => write!(
+						f,
+						"eeprom:{},size={}",
+						eeprom.path.display(),
+						size_text(eeprom.size)
+					)?,
+					i2c::DeviceKind::Lm75(celsius) => write!(f, "lm75,celsius={}", celsius)?,
+					i2c::DeviceKind::Pcf8574 => write!(f, "pcf8574")?,
+				}
+				if spec.bus != 0 {
+					write!(f, ",bus={}", spec.bus)?;
+				}
+				Ok(())
+			}
+			Device::Bus { slot, spec } => {
+				write!(f, "bus")?;
+				if let Some(slot) = slot {
+					write!(f, ":{}", slot)?;
+				}
+				match spec {
+					bus::DeviceSpec::Slot => write!(f, "=slot"),
+					bus::DeviceSpec::Loopback => write!(f, "=loopback"),
+					bus::DeviceSpec::Gpio => write!(f, "=gpio"),
+					bus::DeviceSpec::Timer(rate_hz) => write!(f, "=timer,rate={}Hz", rate_hz),
+					bus::DeviceSpec::SdCard(path) => write!(f, "=sdcard:{}", path.display()),
+					bus::DeviceSpec::Flash(path, size) => {
+						write!(f, "=flash:{},size={}", path.display(), size_text(*size))
+					}
+				}
+			}
+			Device::Serial { port, spec } => write!(f, "serial:{}={}", port, spec),
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let before = self.text[..self.span.start].chars().count();
+		let width = self.text[self.span.clone()].chars().count().max(1);
+		writeln!(f, "{}", self.message)?;
+		writeln!(f)?;
+		writeln!(f, "    {}", self.text)?;
+		write!(f, "    {}{}", " ".repeat(before), "^".repeat(width))
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl<'a> Part<'a> {
+	/// The bytes from `start` to `end` of this part.
+	fn slice(self, start: usize, end: usize) -> Part<'a> {
+		Part {
+			text: &self.text[start..end],
+			start: self.start + start,
+		}
+	}
+
+	/// Split this part in two at the first `separator`, if it has one.
+	fn split(self, separator: char) -> (Part<'a>, Option<Part<'a>>) {
+		match self.text.find(separator) {
+			Some(idx) => (
+				self.slice(0, idx),
+				Some(self.slice(idx + 1, self.text.len())),
+			),
+			None => (self, None),
+		}
+	}
+
+	/// Split this part at every `separator`.
+	fn split_all(self, separator: char) -> Vec<Part<'a>> {
+		let mut parts = Vec::new();
+		let mut start = 0;
+		for (idx, _) in self.text.match_indices(separator) {
+			parts.push(self.slice(start, idx));
+			start = idx + 1;
+		}
+		parts.push(self.slice(start, self.text.len()));
+		parts
+	}
+
+	/// Say what's wrong with this part of `text`.
+	fn error(self, text: &str, message: &str) -> Error {
+		Error {
+			text: text.to_string(),
+			span: self.start..self.start + self.text.len(),
+			message: message.to_string(),
+		}
+	}
+}
+
+impl<'a> Options<'a> {
+	/// Split up the options, checking none of them is given twice.
+	fn new(text: &'a str, parts: Vec<Part<'a>>) -> Result<Options<'a>, Error> {
+		let mut items: Vec<(Part, Option<Part>)> = Vec::new();
+		for part in parts {
+			let (key, value) = part.split('=');
+			if key.text.is_empty() {
+				return Err(key.error(text, "expected an option, like ro or size=8KiB"));
+			}
+			if items.iter().any(|(seen, _)| seen.text == key.text) {
+				return Err(key.error(text, "this option was already given"));
+			}
+			items.push((key, value));
+		}
+		let used = vec![false; items.len()];
+		Ok(Options { text, items, used })
+	}
+
+	/// Find an option, and mark it as used.
+	fn find(&mut self, key: &str) -> Option<(Part<'a>, Option<Part<'a>>)> {
+		let idx = self.items.iter().position(|(seen, _)| seen.text == key)?;
+		self.used[idx] = true;
+		Some(self.items[idx])
+	}
+
+	/// Was this flag (like `ro`) given? It can't have a value.
+	fn flag(&mut self, key: &str) -> Result<bool, Error> {
+		match self.find(key) {
+			Some((_, Some(value))) => {
+				Err(value.error(self.text, &format!("{} doesn't take a value", key)))
+			}
+			Some((_, None)) => Ok(true),
+			None => Ok(false),
+		}
+	}
+
+	/// The value of an option (like `size=8KiB`), if it was given.
+	fn value(&mut self, key: &str) -> Option<Part<'a>> {
+		match self.find(key)? {
+			(_, Some(value)) => Some(value),
+			// Point at the key, with nothing after the '='
+			(key, None) => Some(Part {
+				text: "",
+				start: key.start + key.text.len(),
+			}),
+		}
+	}
+
+	/// The value of an option the backend can't do without.
+	fn need(&mut self, backend: Part, key: &str, example: &str) -> Result<Part<'a>, Error> {
+		self.value(key).ok_or_else(|| {
+			backend.error(
+				self.text,
+				&format!("{} needs a {} (add ,{})", backend.text, key, example),
+			)
+		})
+	}
+
+	/// Complain about the first option nobody used.
+	fn finish(self) -> Result<(), Error> {
+		match self.used.iter().position(|used| !used) {
+			Some(idx) => Err(self.items[idx].0.error(self.text, "unknown option")),
+			None => Ok(()),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
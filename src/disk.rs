@@ -0,0 +1,829 @@
+//! # Disk images
+//!
+//! Making, inspecting and converting disk images, for the `disk` subcommands,
+//! and opening them for the block device. The tools and the emulator both
+//! come through here, so they always agree on what a disk image is.
+//!
+//! A disk image is a file holding the disk's blocks, one after the other.
+//! The tools can also read one compressed with gzip (e.g. `disk.img.gz`), but
+//! the block device needs random access, so you have to `disk convert` it
+//! before you can attach it.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::block::BLOCK_SIZE;
+use crate::memory::describe_size;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Things you can do to a disk image from the command line.
+#[derive(Debug, clap::Subcommand)]
+pub enum Action {
+	/// Make a new disk image, full of zeros
+	Create {
+		/// Where to put it
+		#[arg(value_hint = clap::ValueHint::FilePath)]
+		path: PathBuf,
+		/// How big to make it (e.g. 64MiB)
+		#[arg(value_parser = crate::memory::parse_size)]
+		size: usize,
+		/// Give it an MBR partition table. On its own, the table is empty.
+		#[arg(long)]
+		mbr: bool,
+		/// Format it as FAT32 - in one partition, if you also give `--mbr`,
+		/// or the whole disk if not
+		#[arg(long)]
+		fat32: bool,
+	},
+	/// Say how a disk image is stored, how big it is, and how it's
+	/// partitioned
+	Info {
+		/// The disk image
+		#[arg(value_hint = clap::ValueHint::FilePath)]
+		path: PathBuf,
+	},
+	/// Copy a disk image, decompressing it (or compressing it, if the output
+	/// ends in `.gz`)
+	Convert {
+		/// The disk image to read
+		#[arg(value_hint = clap::ValueHint::FilePath)]
+		input: PathBuf,
+		/// Where to write the copy
+		#[arg(value_hint = clap::ValueHint::FilePath)]
+		output: PathBuf,
+	},
+}
+
+/// How a disk image is stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+	/// Just the blocks
+	Raw,
+	/// The blocks, compressed with gzip
+	Gzip,
+}
+
+/// What we found in a disk image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+	/// How it's stored
+	pub format: Format,
+	/// How big it is, in bytes, once decompressed
+	pub size: u64,
+	/// What's in the first block
+	pub layout: Layout,
+}
+
+/// How a disk is laid out, going by its first block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+	/// Nothing we recognise (e.g. a blank disk)
+	Unknown,
+	/// A file system on the whole disk, with no partition table
+	Volume(FileSystem),
+	/// An MBR partition table, with these partitions in it
+	Mbr(Vec<Partition>),
+	/// A GPT partition table, which we don't look inside
+	Gpt,
+}
+
+/// One partition in an MBR partition table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+	/// Which of the four entries in the table it is, from 0
+	pub slot: u8,
+	/// The partition type byte (e.g. 0x0C for FAT32)
+	pub kind: u8,
+	/// Whether it's marked as the one to boot from
+	pub bootable: bool,
+	/// Its first block
+	pub start_block: u64,
+	/// How many blocks it has
+	pub num_blocks: u64,
+	/// The file system in it, if we recognise one
+	pub file_system: Option<FileSystem>,
+}
+
+/// The file systems we can recognise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileSystem {
+	Fat12,
+	Fat16,
+	Fat32,
+}
+
+/// Things that can go wrong with a disk image.
+#[derive(Debug)]
+pub enum Error {
+	/// A disk has to be a whole number of blocks
+	NotWholeBlocks(u64),
+	/// The disk is the wrong size for what we were asked to put on it
+	WrongSize {
+		what: &'static str,
+		size: u64,
+		min: u64,
+		max: u64,
+	},
+	/// We won't overwrite a file that's already there
+	Exists(PathBuf),
+	/// The block device can't use a compressed image
+	Compressed(PathBuf),
+	/// We couldn't read or write a file
+	Io(PathBuf, std::io::Error),
+}
+
+/// A disk image we're reading from the start, decompressing it if we have to.
+struct Source {
+	reader: Box<dyn Read>,
+	format: Format,
+	/// How many bytes we've read so far
+	position: u64,
+}
+
+/// How we lay out a FAT32 volume.
+struct Fat32 {
+	/// How many blocks in the volume
+	num_blocks: u32,
+	/// How many blocks before the volume, on the disk
+	hidden_blocks: u32,
+	sectors_per_cluster: u8,
+	/// How many blocks in each copy of the FAT
+	fat_blocks: u32,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The first two bytes of a gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Where the partition table is in an MBR.
+const PARTITION_TABLE_OFFSET: usize = 446;
+
+/// Where the `0x55, 0xAA` signature goes in a boot block.
+const SIGNATURE_OFFSET: usize = 510;
+
+/// The partition type that says the disk really has a GPT partition table.
+const GPT_PROTECTIVE: u8 = 0xEE;
+
+/// Where `disk create --mbr` starts its partition: 1 MiB in, like most
+/// partitioning tools, so it lines up with the flash pages of an SD card.
+const PARTITION_START: u64 = 2048;
+
+/// The partition type for FAT32 with LBA addressing.
+const FAT32_LBA: u8 = 0x0C;
+
+/// The fewest clusters a FAT32 volume can have.
+const FAT32_MIN_CLUSTERS: u64 = 65525;
+
+/// How many reserved blocks we put at the start of a FAT32 volume.
+const FAT32_RESERVED_BLOCKS: u32 = 32;
+
+/// The smallest FAT32 volume we make, in blocks (about 32.5 MiB).
+const FAT32_MIN_BLOCKS: u64 = 66600;
+
+/// The biggest FAT32 volume we make, in blocks (2 TiB).
+const FAT32_MAX_BLOCKS: u64 = u32::MAX as u64;
+
+/// The partition types we can name.
+#[rustfmt::skip]
+const PARTITION_KINDS: [(u8, &str); 12] = [
+	(0x01, "FAT12"),
+	(0x04, "FAT16 (small)"),
+	(0x05, "Extended"),
+	(0x06, "FAT16"),
+	(0x07, "NTFS or exFAT"),
+	(0x0B, "FAT32"),
+	(0x0C, "FAT32 (LBA)"),
+	(0x0E, "FAT16 (LBA)"),
+	(0x0F, "Extended (LBA)"),
+	(0x82, "Linux swap"),
+	(0x83, "Linux"),
+	(0xEE, "GPT protective"),
+];
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Do what the `disk` subcommand was asked to do.
+pub fn run(action: &Action) -> Result<(), Error> {
+	match action {
+		Action::Create {
+			path,
+			size,
+			mbr,
+			fat32,
+		} => {
+			create(path, *size as u64, *mbr, *fat32)?;
+			println!("Made {}: {}", path.display(), info(path)?);
+		}
+		Action::Info { path } => {
+			println!("{}: {}", path.display(), info(path)?);
+		}
+		Action::Convert { input, output } => {
+			let size = convert(input, output)?;
+			println!("Wrote {} ({})", output.display(), describe_size(size));
+		}
+	}
+	Ok(())
+}
+
+/// Open a disk image for the block device.
+///
+/// Compressed images are refused, as we need to be able to seek around in
+/// them.
+pub fn open(path: &Path) -> Result<std::fs::File, Error> {
+	if detect(path)? == Format::Gzip {
+		return Err(Error::Compressed(path.to_owned()));
+	}
+	let file = std::fs::File::open(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+	let size = file
+		.metadata()
+		.map_err(|e| Error::Io(path.to_owned(), e))?
+		.len();
+	warn_if_partial(path, size);
+	Ok(file)
+}
+
+/// Work out how a disk image is stored.
+pub fn detect(path: &Path) -> Result<Format, Error> {
+	let mut file = std::fs::File::open(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+	let mut magic = [0u8; 2];
+	let len = read_full(&mut file, &mut magic).map_err(|e| Error::Io(path.to_owned(), e))?;
+	if len == magic.len() && magic == GZIP_MAGIC {
+		Ok(Format::Gzip)
+	} else {
+		Ok(Format::Raw)
+	}
+}
+
+/// Find out what's in a disk image.
+///
+/// A compressed image is read from start to end, as we can't seek in it.
+pub fn info(path: &Path) -> Result<Info, Error> {
+	let io_error = |e| Error::Io(path.to_owned(), e);
+	let mut source = Source::open(path)?;
+	let layout = match source.block(0).map_err(io_error)? {
+		None => Layout::Unknown,
+		Some(first) => read_layout(&mut source, &first).map_err(io_error)?,
+	};
+	Ok(Info {
+		format: source.format,
+		size: source.size().map_err(io_error)?,
+		layout,
+	})
+}
+
+/// Make a new disk image, `size` bytes long, with an empty MBR partition
+/// table (if `mbr`) and a FAT32 file system (if `fat32`).
+///
+/// We won't overwrite a file that's already there.
+pub fn create(path: &Path, size: u64, mbr: bool, fat32: bool) -> Result<(), Error> {
+	if size == 0 || leftover(size) != 0 {
+		return Err(Error::NotWholeBlocks(size));
+	}
+	let num_blocks = size / BLOCK_SIZE as u64;
+	let volume_start = if mbr { PARTITION_START } else { 0 };
+	if mbr && num_blocks <= PARTITION_START {
+		return Err(Error::WrongSize {
+			what: "disk with an MBR",
+			size,
+			min: (PARTITION_START + 1) * BLOCK_SIZE as u64,
+			max: u64::MAX,
+		});
+	}
+	let volume = if fat32 {
+		Some(Fat32::new(num_blocks - volume_start, volume_start)?)
+	} else {
+		None
+	};
+
+	let mut file = std::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(path)
+		.map_err(|e| match e.kind() {
+			std::io::ErrorKind::AlreadyExists => Error::Exists(path.to_owned()),
+			_ => Error::Io(path.to_owned(), e),
+		})?;
+	let result = (|| {
+		// A new file reads as zeros, so we only write the blocks that aren't
+		file.set_len(size)?;
+		if mbr {
+			let partition = volume.as_ref().map(|_| Partition {
+				slot: 0,
+				kind: FAT32_LBA,
+				bootable: false,
+				start_block: PARTITION_START,
+				num_blocks: num_blocks - PARTITION_START,
+				file_system: None,
+			});
+			write_block(&mut file, 0, &make_mbr(partition.as_ref()))?;
+		}
+		if let Some(volume) = &volume {
+			volume.write(&mut file, volume_start)?;
+		}
+		file.sync_all()
+	})();
+	if let Err(e) = result {
+		// Don't leave half an image behind
+		let _ = std::fs::remove_file(path);
+		return Err(Error::Io(path.to_owned(), e));
+	}
+	Ok(())
+}
+
+/// Copy the disk image at `input` to `output`, decompressing it if it's
+/// compressed, then compressing it if `output` ends in `.gz`.
+///
+/// Returns how big the disk is, uncompressed. We won't overwrite a file
+/// that's already there.
+pub fn convert(input: &Path, output: &Path) -> Result<u64, Error> {
+	let mut source = Source::open(input)?;
+	let file = std::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(output)
+		.map_err(|e| match e.kind() {
+			std::io::ErrorKind::AlreadyExists => Error::Exists(output.to_owned()),
+			_ => Error::Io(output.to_owned(), e),
+		})?;
+	let writer = std::io::BufWriter::new(file);
+	let compress = output.extension().is_some_and(|ext| ext == "gz");
+	let result = if compress {
+		let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+		std::io::copy(&mut source, &mut encoder)
+			.and_then(|size| encoder.finish()?.flush().map(|_| size))
+	} else {
+		let mut writer = writer;
+		std::io::copy(&mut source, &mut writer).and_then(|size| writer.flush().map(|_| size))
+	};
+	match result {
+		Ok(size) => {
+			warn_if_partial(output, size);
+			Ok(size)
+		}
+		Err(e) => {
+			let _ = std::fs::remove_file(output);
+			// Most likely the input was bad, not the output
+			Err(Error::Io(input.to_owned(), e))
+		}
+	}
+}
+
+/// Work out how the disk is laid out from its `first` block, and look in the
+/// partitions if it has any.
+fn read_layout(source: &mut Source, first: &[u8; BLOCK_SIZE]) -> std::io::Result<Layout> {
+	if let Some(file_system) = file_system(first) {
+		return Ok(Layout::Volume(file_system));
+	}
+	if first[SIGNATURE_OFFSET..] != [0x55, 0xAA] {
+		return Ok(Layout::Unknown);
+	}
+	let mut partitions = Vec::new();
+	for slot in 0..4u8 {
+		let offset = PARTITION_TABLE_OFFSET + usize::from(slot) * 16;
+		let entry = &first[offset..offset + 16];
+		if entry[0] != 0x00 && entry[0] != 0x80 {
+			// Not a partition table after all
+			return Ok(Layout::Unknown);
+		}
+		if entry[4] == GPT_PROTECTIVE {
+			return Ok(Layout::Gpt);
+		}
+		if entry[4] == 0 {
+			continue;
+		}
+		partitions.push(Partition {
+			slot,
+			kind: entry[4],
+			bootable: entry[0] == 0x80,
+			start_block: u64::from(u32::from_le_bytes(entry[8..12].try_into().unwrap())),
+			num_blocks: u64::from(u32::from_le_bytes(entry[12..16].try_into().unwrap())),
+			file_system: None,
+		});
+	}
+	// We might not be able to seek, so read the first block of each partition
+	// in the order they're on the disk
+	let mut order: Vec<usize> = (0..partitions.len()).collect();
+	order.sort_by_key(|idx| partitions[*idx].start_block);
+	let mut last: Option<(u64, Option<[u8; BLOCK_SIZE]>)> = None;
+	for idx in order {
+		let start = partitions[idx].start_block;
+		let block = match last {
+			Some((lba, block)) if lba == start => block,
+			_ => source.block(start)?,
+		};
+		partitions[idx].file_system = block.as_ref().and_then(file_system);
+		last = Some((start, block));
+	}
+	Ok(Layout::Mbr(partitions))
+}
+
+/// Which FAT file system this boot block is for, if any.
+///
+/// Like the FAT specification says, we go by how many clusters it has, not
+/// by the name in the boot block.
+fn file_system(block: &[u8; BLOCK_SIZE]) -> Option<FileSystem> {
+	let u16_at = |offset: usize| u64::from(u16::from_le_bytes([block[offset], block[offset + 1]]));
+	let u32_at = |offset: usize| {
+		u64::from(u32::from_le_bytes(
+			block[offset..offset + 4].try_into().unwrap(),
+		))
+	};
+	let bytes_per_sector = u16_at(11);
+	let sectors_per_cluster = u64::from(block[13]);
+	let reserved = u16_at(14);
+	let num_fats = u64::from(block[16]);
+	let root_entries = u16_at(17);
+	let total = match u16_at(19) {
+		0 => u32_at(32),
+		total => total,
+	};
+	let fat_size = match u16_at(22) {
+		0 => u32_at(36),
+		size => size,
+	};
+	let plausible = block[SIGNATURE_OFFSET..] == [0x55, 0xAA]
+		&& matches!(block[0], 0xEB | 0xE9)
+		&& matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+		&& sectors_per_cluster.is_power_of_two()
+		&& reserved != 0
+		&& num_fats != 0
+		&& fat_size != 0;
+	if !plausible {
+		return None;
+	}
+	let root_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+	let data_sectors = total.checked_sub(reserved + num_fats * fat_size + root_sectors)?;
+	match data_sectors / sectors_per_cluster {
+		0..=4084 => Some(FileSystem::Fat12),
+		4085..=65524 => Some(FileSystem::Fat16),
+		_ => Some(FileSystem::Fat32),
+	}
+}
+
+/// How many bytes are left over after the last whole block.
+fn leftover(size: u64) -> u64 {
+	size % BLOCK_SIZE as u64
+}
+
+/// Warn that the end of a disk image isn't a whole block, if it isn't.
+fn warn_if_partial(path: &Path, size: u64) {
+	if leftover(size) != 0 {
+		warn!(
+			"{} isn't a whole number of blocks, so the last {} bytes can't be used",
+			path.display(),
+			leftover(size)
+		);
+	}
+}
+
+/// Make an MBR, holding `partition` (if any).
+fn make_mbr(partition: Option<&Partition>) -> [u8; BLOCK_SIZE] {
+	let mut block = [0u8; BLOCK_SIZE];
+	if let Some(partition) = partition {
+		let offset = PARTITION_TABLE_OFFSET + usize::from(partition.slot) * 16;
+		let entry = &mut block[offset..offset + 16];
+		entry[0] = if partition.bootable { 0x80 } else { 0x00 };
+		// The cylinder/head/sector addresses say "use the block numbers"
+		entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+		entry[4] = partition.kind;
+		entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+		entry[8..12].copy_from_slice(&(partition.start_block as u32).to_le_bytes());
+		entry[12..16].copy_from_slice(&(partition.num_blocks as u32).to_le_bytes());
+	}
+	block[SIGNATURE_OFFSET..].copy_from_slice(&[0x55, 0xAA]);
+	block
+}
+
+/// The name of an MBR partition type.
+fn partition_kind_name(kind: u8) -> &'static str {
+	PARTITION_KINDS
+		.iter()
+		.find(|(k, _)| *k == kind)
+		.map_or("unknown", |(_, name)| name)
+}
+
+/// Write one block of a disk image.
+fn write_block(
+	file: &mut std::fs::File,
+	lba: u64,
+	block: &[u8; BLOCK_SIZE],
+) -> std::io::Result<()> {
+	file.seek(SeekFrom::Start(lba * BLOCK_SIZE as u64))?;
+	file.write_all(block)
+}
+
+/// Fill `buffer` from `reader`, unless we get to the end first. Returns how
+/// many bytes we read.
+fn read_full(reader: &mut dyn Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+	let mut filled = 0;
+	while filled < buffer.len() {
+		match reader.read(&mut buffer[filled..]) {
+			Ok(0) => break,
+			Ok(len) => filled += len,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(filled)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Source {
+	/// Start reading the disk image at `path`.
+	fn open(path: &Path) -> Result<Source, Error> {
+		let format = detect(path)?;
+		let file = std::fs::File::open(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+		let reader: Box<dyn Read> = match format {
+			Format::Raw => Box::new(std::io::BufReader::new(file)),
+			Format::Gzip => Box::new(flate2::read::GzDecoder::new(std::io::BufReader::new(file))),
+		};
+		Ok(Source {
+			reader,
+			format,
+			position: 0,
+		})
+	}
+
+	/// Read block `lba`, or `None` if the image ends before it.
+	///
+	/// We can't go backwards, so each block must come after the last.
+	fn block(&mut self, lba: u64) -> std::io::Result<Option<[u8; BLOCK_SIZE]>> {
+		let offset = lba * BLOCK_SIZE as u64;
+		let skip = offset.checked_sub(self.position).ok_or_else(|| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"disk image blocks read out of order",
+			)
+		})?;
+		if std::io::copy(&mut self.take(skip), &mut std::io::sink())? < skip {
+			return Ok(None);
+		}
+		let mut block = [0u8; BLOCK_SIZE];
+		if read_full(self, &mut block)? < BLOCK_SIZE {
+			return Ok(None);
+		}
+		Ok(Some(block))
+	}
+
+	/// Read to the end, and say how big the image is.
+	fn size(mut self) -> std::io::Result<u64> {
+		std::io::copy(&mut self, &mut std::io::sink())?;
+		Ok(self.position)
+	}
+}
+
+impl Read for Source {
+	fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+		let len = self.reader.read(buffer)?;
+		self.position += len as u64;
+		Ok(len)
+	}
+}
+
+impl Fat32 {
+	/// Lay out a FAT32 volume `num_blocks` long, starting `hidden_blocks` into
+	/// the disk.
+	///
+	/// The cluster size and FAT size come from the tables and the formula in
+	/// Microsoft's FAT specification.
+	fn new(num_blocks: u64, hidden_blocks: u64) -> Result<Fat32, Error> {
+		let wrong_size = || Error::WrongSize {
+			what: "FAT32 volume",
+			size: num_blocks * BLOCK_SIZE as u64,
+			min: FAT32_MIN_BLOCKS * BLOCK_SIZE as u64,
+			max: FAT32_MAX_BLOCKS * BLOCK_SIZE as u64,
+		};
+		if !(FAT32_MIN_BLOCKS..=FAT32_MAX_BLOCKS).contains(&num_blocks) {
+			return Err(wrong_size());
+		}
+		let sectors_per_cluster: u8 = match num_blocks {
+			0..=532_480 => 1,
+			532_481..=16_777_216 => 8,
+			16_777_217..=33_554_432 => 16,
+			33_554_433..=67_108_864 => 32,
+			_ => 64,
+		};
+		let reserved = u64::from(FAT32_RESERVED_BLOCKS);
+		let divisor = (256 * u64::from(sectors_per_cluster) + 2) / 2;
+		let fat_blocks = (num_blocks - reserved).div_ceil(divisor);
+		let clusters = (num_blocks - reserved - 2 * fat_blocks) / u64::from(sectors_per_cluster);
+		if clusters < FAT32_MIN_CLUSTERS {
+			return Err(wrong_size());
+		}
+		Ok(Fat32 {
+			num_blocks: num_blocks as u32,
+			hidden_blocks: u32::try_from(hidden_blocks).map_err(|_| wrong_size())?,
+			sectors_per_cluster,
+			fat_blocks: fat_blocks as u32,
+		})
+	}
+
+	/// How many clusters the volume has.
+	fn clusters(&self) -> u32 {
+		(self.num_blocks - FAT32_RESERVED_BLOCKS - 2 * self.fat_blocks)
+			/ u32::from(self.sectors_per_cluster)
+	}
+
+	/// Write the volume to a disk image full of zeros, starting at block
+	/// `start`: the boot block and its backup, the FSInfo block and its
+	/// backup, and the start of each FAT. The root directory is the first
+	/// cluster, and is empty.
+	fn write(&self, file: &mut std::fs::File, start: u64) -> std::io::Result<()> {
+		let volume_id = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map_or(0, |d| d.as_secs() as u32);
+
+		let mut boot = [0u8; BLOCK_SIZE];
+		boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+		boot[3..11].copy_from_slice(b"NEOTRON ");
+		boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+		boot[13] = self.sectors_per_cluster;
+		boot[14..16].copy_from_slice(&(FAT32_RESERVED_BLOCKS as u16).to_le_bytes());
+		// Two FATs
+		boot[16] = 2;
+		// A fixed disk
+		boot[21] = 0xF8;
+		// A made-up geometry, which nothing uses
+		boot[24..26].copy_from_slice(&63u16.to_le_bytes());
+		boot[26..28].copy_from_slice(&255u16.to_le_bytes());
+		boot[28..32].copy_from_slice(&self.hidden_blocks.to_le_bytes());
+		boot[32..36].copy_from_slice(&self.num_blocks.to_le_bytes());
+		boot[36..40].copy_from_slice(&self.fat_blocks.to_le_bytes());
+		// The root directory is in cluster 2
+		boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+		// The FSInfo block, then the backup boot block
+		boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+		boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+		boot[64] = 0x80;
+		boot[66] = 0x29;
+		boot[67..71].copy_from_slice(&volume_id.to_le_bytes());
+		boot[71..82].copy_from_slice(b"NO NAME    ");
+		boot[82..90].copy_from_slice(b"FAT32   ");
+		boot[SIGNATURE_OFFSET..].copy_from_slice(&[0x55, 0xAA]);
+
+		let mut fs_info = [0u8; BLOCK_SIZE];
+		fs_info[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+		fs_info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+		// Everything is free except the root directory, and the next free
+		// cluster is the one after it
+		fs_info[488..492].copy_from_slice(&(self.clusters() - 1).to_le_bytes());
+		fs_info[492..496].copy_from_slice(&3u32.to_le_bytes());
+		fs_info[508..512].copy_from_slice(&0xAA55_0000u32.to_le_bytes());
+
+		// The media byte, the reserved entry and the end of the root directory
+		let mut fat = [0u8; BLOCK_SIZE];
+		for (idx, entry) in [0x0FFF_FFF8u32, 0x0FFF_FFFF, 0x0FFF_FFFF]
+			.into_iter()
+			.enumerate()
+		{
+			fat[idx * 4..idx * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+		}
+
+		write_block(file, start, &boot)?;
+		write_block(file, start + 1, &fs_info)?;
+		write_block(file, start + 6, &boot)?;
+		write_block(file, start + 7, &fs_info)?;
+		for copy in 0..2 {
+			let fat_start =
+				start + u64::from(FAT32_RESERVED_BLOCKS) + copy * u64::from(self.fat_blocks);
+			write_block(file, fat_start, &fat)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::fmt::Display for Info {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let format = match self.format {
+			Format::Raw => "raw",
+			Format::Gzip => "gzip compressed",
+		};
+		write!(
+			f,
+			"{}, {} ({} blocks)",
+			format,
+			describe_size(self.size),
+			self.size / BLOCK_SIZE as u64
+		)?;
+		if leftover(self.size) != 0 {
+			write!(
+				f,
+				", and {} bytes that aren't a whole block",
+				leftover(self.size)
+			)?;
+		}
+		match &self.layout {
+			Layout::Unknown => write!(f, "\nNo partition table or file system that we recognise"),
+			Layout::Volume(file_system) => {
+				write!(f, "\n{} file system, with no partition table", file_system)
+			}
+			Layout::Gpt => write!(f, "\nGPT partition table"),
+			Layout::Mbr(partitions) => {
+				write!(f, "\nMBR partition table")?;
+				if partitions.is_empty() {
+					write!(f, ", with no partitions")?;
+				}
+				for partition in partitions {
+					write!(
+						f,
+						"\n  {}: {} (0x{:02X}){}, from block {}, {} blocks ({})",
+						partition.slot,
+						partition_kind_name(partition.kind),
+						partition.kind,
+						if partition.bootable { ", bootable" } else { "" },
+						partition.start_block,
+						partition.num_blocks,
+						describe_size(partition.num_blocks * BLOCK_SIZE as u64)
+					)?;
+					if let Some(file_system) = partition.file_system {
+						write!(f, ", {} file system", file_system)?;
+					}
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+impl std::fmt::Display for FileSystem {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			FileSystem::Fat12 => write!(f, "FAT12"),
+			FileSystem::Fat16 => write!(f, "FAT16"),
+			FileSystem::Fat32 => write!(f, "FAT32"),
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::NotWholeBlocks(size) => {
+				write!(
+					f,
+					"{} bytes isn't a whole number of {} byte blocks",
+					size, BLOCK_SIZE
+				)
+			}
+			Error::WrongSize {
+				what,
+				size,
+				min,
+				max,
+			} => {
+				if size < min {
+					write!(f, "a {} must be at least {} bytes, not {}", what, min, size)
+				} else {
+					write!(f, "a {} can be at most {} bytes, not {}", what, max, size)
+				}
+			}
+			Error::Exists(path) => write!(f, "{} already exists", path.display()),
+			Error::Compressed(path) => write!(
+				f,
+				"{} is compressed - decompress it with `disk convert` first",
+				path.display()
+			),
+			Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,404 @@
+//! # Guard pages and read-only memory for the Neotron Desktop BIOS
+//!
+//! With `--guard-pages`, each OS RAM region is allocated straight from the
+//! host OS with an inaccessible page either side of it. If the OS runs off
+//! either end of a region it faults immediately, and our fault handler says
+//! which region was overrun and by how much, instead of the OS quietly
+//! scribbling over the host's heap.
+//!
+//! The end of each region touches the upper guard page, or comes within 15
+//! bytes of it, as the start of a region is always 16-byte aligned. If a
+//! region isn't a whole number of pages long, there will be a small gap
+//! between the lower guard page and the start of the region.
+//!
+//! We also use this to make the ROM region read-only, so the fault handler
+//! can tell you when the OS tries to write to it.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Where a guarded region is, so the fault handler can find it.
+///
+/// These are atomics because the fault handler can't take a lock.
+struct Guarded {
+	/// Address of the first byte of the region
+	start: AtomicUsize,
+	/// Length of the region in bytes, or zero if this slot is unused
+	length: AtomicUsize,
+	/// Address of the lower guard page
+	lower_guard: AtomicUsize,
+	/// Set if the region itself is read-only
+	read_only: AtomicBool,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many regions we can guard.
+const MAX_REGIONS: usize = 4;
+
+/// What the start of each region is aligned to, like the OS would get from
+/// the normal allocator.
+const ALIGNMENT: usize = 16;
+
+/// All the regions we have guarded, by region number.
+static GUARDED: [Guarded; MAX_REGIONS] = [const { Guarded::new() }; MAX_REGIONS];
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Install our fault handler, so overruns and writes to read-only memory are
+/// reported clearly.
+pub fn install_handler() {
+	sys::install_handler();
+}
+
+/// Allocate `length` bytes with guard pages either side, for use as the given
+/// memory region.
+///
+/// Returns `None` if the host won't give us the memory, or we don't know how to
+/// make guard pages on this host.
+pub fn allocate(region: usize, length: usize) -> Option<*mut u8> {
+	let slot = GUARDED.get(region)?;
+	let page = sys::page_size();
+	let body = length.checked_next_multiple_of(page)?;
+	let total = body.checked_add(2 * page)?;
+	let base = sys::map(total, page)?;
+	let start = (base + page + (body - length)) & !(ALIGNMENT - 1);
+	slot.lower_guard.store(base, Ordering::SeqCst);
+	slot.start.store(start, Ordering::SeqCst);
+	slot.length.store(length, Ordering::SeqCst);
+	Some(start as *mut u8)
+}
+
+/// Allocate some read-only memory (with guard pages) containing `data`, for
+/// use as the given memory region.
+///
+/// Returns `None` if the host won't give us the memory, or we don't know how to
+/// protect it on this host.
+pub fn allocate_read_only(region: usize, data: &[u8]) -> Option<*mut u8> {
+	let start = allocate(region, data.len())?;
+	// Safety: we were just given `data.len()` writable bytes here
+	unsafe {
+		std::ptr::copy_nonoverlapping(data.as_ptr(), start, data.len());
+	}
+	let slot = &GUARDED[region];
+	let body = slot.lower_guard.load(Ordering::SeqCst) + sys::page_size();
+	let end = start as usize + data.len();
+	sys::make_read_only(body, end - body)?;
+	slot.read_only.store(true, Ordering::SeqCst);
+	Some(start)
+}
+
+/// Called from the fault handler. If `addr` is in one of our guard pages or
+/// read-only regions, explain what happened and abort. Otherwise return, so the fault can be
+/// handled as normal.
+///
+/// This runs in a signal handler, so it mustn't allocate or take locks.
+fn check_fault(addr: usize) {
+	let page = sys::page_size();
+	for (region, slot) in GUARDED.iter().enumerate() {
+		let length = slot.length.load(Ordering::SeqCst);
+		if length == 0 {
+			continue;
+		}
+		let start = slot.start.load(Ordering::SeqCst);
+		let lower_guard = slot.lower_guard.load(Ordering::SeqCst);
+		let end = start + length;
+		let upper_guard = end.next_multiple_of(page);
+		let mut buffer = [0u8; 128];
+		let mut cursor = std::io::Cursor::new(&mut buffer[..]);
+		if (lower_guard..lower_guard + page).contains(&addr) {
+			let _ = writeln!(
+				cursor,
+				"OS accessed memory before the start of Region {} at offset -{}",
+				region,
+				start - addr
+			);
+		} else if (upper_guard..upper_guard + page).contains(&addr) {
+			let _ = writeln!(
+				cursor,
+				"OS wrote past end of Region {} at offset {}",
+				region,
+				addr - start
+			);
+		} else if slot.read_only.load(Ordering::SeqCst) && (start..end).contains(&addr) {
+			let _ = writeln!(
+				cursor,
+				"OS wrote to read-only Region {} at offset {}",
+				region,
+				addr - start
+			);
+		} else {
+			continue;
+		}
+		let used = cursor.position() as usize;
+		sys::write_stderr(&buffer[..used]);
+		std::process::abort();
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Guarded {
+	const fn new() -> Guarded {
+		Guarded {
+			start: AtomicUsize::new(0),
+			length: AtomicUsize::new(0),
+			lower_guard: AtomicUsize::new(0),
+			read_only: AtomicBool::new(false),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Host specific code
+// -----------------------------------------------------------------------------
+
+#[cfg(unix)]
+mod sys {
+	use std::sync::OnceLock;
+
+	/// The fault handlers that were installed before ours.
+	static PREVIOUS: OnceLock<[libc::sigaction; 2]> = OnceLock::new();
+
+	/// The signals a guard page access might raise.
+	const SIGNALS: [libc::c_int; 2] = [libc::SIGSEGV, libc::SIGBUS];
+
+	pub fn page_size() -> usize {
+		unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+	}
+
+	/// Map `total` bytes of inaccessible memory, then open up everything but
+	/// the first and last page. Returns the address of the first page.
+	pub fn map(total: usize, page: usize) -> Option<usize> {
+		unsafe {
+			let base = libc::mmap(
+				std::ptr::null_mut(),
+				total,
+				libc::PROT_NONE,
+				libc::MAP_PRIVATE | libc::MAP_ANON,
+				-1,
+				0,
+			);
+			if base == libc::MAP_FAILED {
+				return None;
+			}
+			let body = base.cast::<u8>().add(page).cast();
+			if libc::mprotect(body, total - 2 * page, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+				libc::munmap(base, total);
+				return None;
+			}
+			Some(base as usize)
+		}
+	}
+
+	/// Make some whole pages read-only.
+	pub fn make_read_only(addr: usize, len: usize) -> Option<()> {
+		let result = unsafe { libc::mprotect(addr as *mut libc::c_void, len, libc::PROT_READ) };
+		(result == 0).then_some(())
+	}
+
+	pub fn install_handler() {
+		PREVIOUS.get_or_init(|| unsafe {
+			let mut action: libc::sigaction = std::mem::zeroed();
+			action.sa_sigaction = on_fault as *const () as libc::sighandler_t;
+			action.sa_flags = libc::SA_SIGINFO;
+			libc::sigemptyset(&mut action.sa_mask);
+			let mut previous: [libc::sigaction; 2] = std::mem::zeroed();
+			for (signal, old) in SIGNALS.iter().zip(previous.iter_mut()) {
+				libc::sigaction(*signal, &action, old);
+			}
+			previous
+		});
+	}
+
+	pub fn write_stderr(message: &[u8]) {
+		unsafe {
+			libc::write(libc::STDERR_FILENO, message.as_ptr().cast(), message.len());
+		}
+	}
+
+	extern "C" fn on_fault(
+		signal: libc::c_int,
+		info: *mut libc::siginfo_t,
+		_context: *mut libc::c_void,
+	) {
+		let addr = unsafe { (*info).si_addr() } as usize;
+		super::check_fault(addr);
+		// Not one of ours. Put back whoever was handling this before (e.g.
+		// Rust's stack overflow detection) and return, so the faulting
+		// instruction runs again and they get to see it.
+		if let Some(previous) = PREVIOUS.get() {
+			if let Some(idx) = SIGNALS.iter().position(|s| *s == signal) {
+				unsafe {
+					libc::sigaction(signal, &previous[idx], std::ptr::null_mut());
+				}
+			}
+		}
+	}
+}
+
+#[cfg(windows)]
+mod sys {
+	use std::ffi::c_void;
+
+	const MEM_COMMIT: u32 = 0x1000;
+	const MEM_RESERVE: u32 = 0x2000;
+	const MEM_RELEASE: u32 = 0x8000;
+	const PAGE_NOACCESS: u32 = 0x01;
+	const PAGE_READONLY: u32 = 0x02;
+	const PAGE_READWRITE: u32 = 0x04;
+	const EXCEPTION_ACCESS_VIOLATION: u32 = 0xC000_0005;
+	const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+	const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+
+	#[repr(C)]
+	struct ExceptionRecord {
+		code: u32,
+		flags: u32,
+		record: *mut ExceptionRecord,
+		address: *mut c_void,
+		num_parameters: u32,
+		information: [usize; 15],
+	}
+
+	#[repr(C)]
+	struct ExceptionPointers {
+		record: *mut ExceptionRecord,
+		context: *mut c_void,
+	}
+
+	extern "system" {
+		fn VirtualAlloc(addr: *mut c_void, size: usize, kind: u32, protect: u32) -> *mut c_void;
+		fn VirtualFree(addr: *mut c_void, size: usize, kind: u32) -> i32;
+		fn VirtualProtect(addr: *mut c_void, size: usize, protect: u32, old: *mut u32) -> i32;
+		fn AddVectoredExceptionHandler(
+			first: u32,
+			handler: unsafe extern "system" fn(*mut ExceptionPointers) -> i32,
+		) -> *mut c_void;
+		fn GetStdHandle(which: u32) -> *mut c_void;
+		fn WriteFile(
+			handle: *mut c_void,
+			data: *const u8,
+			len: u32,
+			written: *mut u32,
+			overlapped: *mut c_void,
+		) -> i32;
+	}
+
+	pub fn page_size() -> usize {
+		4096
+	}
+
+	/// Allocate `total` bytes of inaccessible memory, then open up everything
+	/// but the first and last page. Returns the address of the first page.
+	pub fn map(total: usize, page: usize) -> Option<usize> {
+		unsafe {
+			let base = VirtualAlloc(
+				std::ptr::null_mut(),
+				total,
+				MEM_COMMIT | MEM_RESERVE,
+				PAGE_NOACCESS,
+			);
+			if base.is_null() {
+				return None;
+			}
+			let body = base.cast::<u8>().add(page).cast();
+			let mut old = 0;
+			if VirtualProtect(body, total - 2 * page, PAGE_READWRITE, &mut old) == 0 {
+				VirtualFree(base, 0, MEM_RELEASE);
+				return None;
+			}
+			Some(base as usize)
+		}
+	}
+
+	/// Make some whole pages read-only.
+	pub fn make_read_only(addr: usize, len: usize) -> Option<()> {
+		let mut old = 0;
+		let result = unsafe { VirtualProtect(addr as *mut c_void, len, PAGE_READONLY, &mut old) };
+		(result != 0).then_some(())
+	}
+
+	pub fn install_handler() {
+		unsafe {
+			AddVectoredExceptionHandler(1, on_fault);
+		}
+	}
+
+	pub fn write_stderr(message: &[u8]) {
+		unsafe {
+			let mut written = 0;
+			WriteFile(
+				GetStdHandle(STD_ERROR_HANDLE),
+				message.as_ptr(),
+				message.len() as u32,
+				&mut written,
+				std::ptr::null_mut(),
+			);
+		}
+	}
+
+	unsafe extern "system" fn on_fault(pointers: *mut ExceptionPointers) -> i32 {
+		let record = &*(*pointers).record;
+		if record.code == EXCEPTION_ACCESS_VIOLATION && record.num_parameters >= 2 {
+			super::check_fault(record.information[1]);
+		}
+		EXCEPTION_CONTINUE_SEARCH
+	}
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+	pub fn page_size() -> usize {
+		4096
+	}
+
+	pub fn map(_total: usize, _page: usize) -> Option<usize> {
+		None
+	}
+
+	pub fn make_read_only(_addr: usize, _len: usize) -> Option<()> {
+		None
+	}
+
+	pub fn install_handler() {}
+
+	pub fn write_stderr(_message: &[u8]) {}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
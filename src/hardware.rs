@@ -0,0 +1,454 @@
+//! # Hardware
+//!
+//! Our emulated hardware, and the BIOS functions that don't have a module of
+//! their own: the BIOS version, the configuration store, memory, I²C, audio,
+//! the Neotron Bus and power.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use log::{debug, info, warn};
+
+use neotron_common_bios as common;
+
+use crate::{audio, bus, clock, i2c, idle, lint, memory, nvram, profile, shutdown, validate};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// All our emulated hardware
+pub struct Hardware {
+	/// What the host's wall clock said when we booted up, in nanoseconds
+	/// since the Neotron epoch
+	pub boot_wall_ns: i128,
+	/// Our disk image
+	pub disk_file: Option<std::fs::File>,
+	/// How far the OS's wall time is ahead of the host's, in nanoseconds
+	pub clock_offset_ns: i128,
+	/// Which Neotron Bus peripheral is selected, if any
+	pub bus_selected: Option<u8>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// When we booted up
+pub static HARDWARE: Mutex<Option<Hardware>> = Mutex::new(None);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Switch the hardware on, with `disk` in the drive. The wall clock starts
+/// at the host's time.
+pub fn init(disk: Option<std::fs::File>) {
+	*HARDWARE.lock().unwrap() = Some(Hardware {
+		boot_wall_ns: crate::time::host_nanos_since_neotron_epoch(),
+		disk_file: disk,
+		// A profile keeps the clock where the OS last set it
+		clock_offset_ns: profile::load_rtc().unwrap_or(0),
+		bus_selected: None,
+	});
+}
+
+/// Returns the version number of the BIOS API.
+pub extern "C" fn api_version_get() -> common::Version {
+	debug!("api_version_get()");
+	common::API_VERSION
+}
+
+/// Returns a pointer to a static string slice containing the BIOS Version.
+///
+/// This string contains the version number and build string of the BIOS.
+/// For C compatibility this string is null-terminated and guaranteed to
+/// only contain ASCII characters (bytes with a value 127 or lower). We
+/// also pass the length (excluding the null) to make it easy to construct
+/// a Rust string. It is unspecified as to whether the string is located
+/// in Flash ROM or RAM (but it's likely to be Flash ROM).
+pub extern "C" fn bios_version_get() -> common::FfiString<'static> {
+	debug!("bios_version_get()");
+	common::FfiString::new("Neotron Desktop BIOS\0")
+}
+
+/// Get the configuration data block.
+///
+/// Configuration data is, to the BIOS, just a block of bytes of a given
+/// length. How it stores them is up to the BIOS - it could be EEPROM, or
+/// battery-backed SRAM.
+///
+/// We copy as much of the stored data as will fit into the buffer, and return
+/// the length of the stored data. If that is bigger than the buffer, the OS
+/// only got part of it. So, pass an empty buffer to find out how big a buffer
+/// you need.
+pub extern "C" fn configuration_get(mut os_buffer: common::FfiBuffer) -> common::ApiResult<usize> {
+	let os_buffer = match validate::buffer("configuration_get", "its buffer", &mut os_buffer) {
+		Ok(os_buffer) => os_buffer,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	match nvram::read() {
+		Ok(read_data) => {
+			for (src, dest) in read_data.iter().zip(os_buffer.iter_mut()) {
+				*dest = *src;
+			}
+			common::ApiResult::Ok(read_data.len())
+		}
+		Err(nvram::Error::NoFile) => common::ApiResult::Err(common::Error::Unimplemented),
+		Err(e) => {
+			warn!("Failed to get config: {}", e);
+			common::ApiResult::Err(common::Error::DeviceError)
+		}
+	}
+}
+
+/// Set the configuration data block.
+///
+/// See `configuration_get`. Setting an empty block erases the NVRAM.
+pub extern "C" fn configuration_set(buffer: common::FfiByteSlice) -> common::ApiResult<()> {
+	let buffer = match validate::bytes("configuration_set", "its buffer", &buffer) {
+		Ok(buffer) => buffer,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let result = if buffer.is_empty() {
+		nvram::erase()
+	} else {
+		nvram::write(nvram::strip_boot_args(buffer))
+	};
+	match result {
+		Ok(_) => common::ApiResult::Ok(()),
+		Err(nvram::Error::NoFile) => common::ApiResult::Err(common::Error::Unimplemented),
+		Err(e @ nvram::Error::TooBig { .. }) => {
+			warn!("Failed to write config: {}", e);
+			common::ApiResult::Err(common::Error::UnsupportedConfiguration)
+		}
+		Err(e) => {
+			warn!("Failed to write config: {}", e);
+			common::ApiResult::Err(common::Error::DeviceError)
+		}
+	}
+}
+
+/// Find out how large a given region of memory is.
+///
+/// The first region is the 'main application region' and is defined to always
+/// start at address `0x2000_0000` on a standard Cortex-M system. This
+/// application region stops just before the BIOS reserved memory, at the top of
+/// the internal SRAM. The OS will have been linked to use the first 1 KiB of
+/// this region.
+///
+/// Other regions may be located at other addresses (e.g. external DRAM or
+/// PSRAM).
+///
+/// The OS will always load non-relocatable applications into the bottom of
+/// Region 0. It can allocate OS specific structures from any other Region (if
+/// any), or from the top of Region 0 (although this reduces the maximum
+/// application space available). The OS will prefer lower numbered regions
+/// (other than Region 0), so faster memory should be listed first.
+///
+/// If the region number given is invalid, the function returns `(null, 0)`.
+pub extern "C" fn memory_get_region(region: u8) -> common::FfiOption<common::MemoryRegion> {
+	memory::get_region(region).into()
+}
+
+/// Get information about one of our emulated I²C buses.
+pub extern "C" fn i2c_bus_get_info(i2c_bus: u8) -> common::FfiOption<common::i2c::BusInfo> {
+	debug!("i2c_bus_get_info({})", i2c_bus);
+	i2c::bus_info(i2c_bus).into()
+}
+
+/// Transact with a device on one of our emulated I²C buses.
+pub extern "C" fn i2c_write_read(
+	i2c_bus: u8,
+	i2c_device_address: u8,
+	tx: common::FfiByteSlice,
+	tx2: common::FfiByteSlice,
+	mut rx: common::FfiBuffer,
+) -> common::ApiResult<()> {
+	let buffers = validate::bytes("i2c_write_read", "tx", &tx).and_then(|tx| {
+		let tx2 = validate::bytes("i2c_write_read", "tx2", &tx2)?;
+		let rx = validate::buffer("i2c_write_read", "rx", &mut rx)?;
+		Ok((tx, tx2, rx))
+	});
+	let (tx, tx2, rx) = match buffers {
+		Ok(buffers) => buffers,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	debug!(
+		"i2c_write_read({}, 0x{:02x}, {:?}, {:?})",
+		i2c_bus, i2c_device_address, tx, tx2
+	);
+	match i2c::write_read(i2c_bus, i2c_device_address, tx, tx2, rx) {
+		Ok(()) => common::ApiResult::Ok(()),
+		Err(i2c::Error::NoBus) => common::ApiResult::Err(common::Error::InvalidDevice),
+		// A real bus tells us nothing answered, which is what an OS bus scan
+		// is looking for
+		Err(i2c::Error::NoDevice) => common::ApiResult::Err(common::Error::DeviceError),
+		Err(e) => {
+			debug!("i2c_write_read failed: {}", e);
+			common::ApiResult::Err(common::Error::DeviceError)
+		}
+	}
+}
+
+/// Get information about an audio mixer channel.
+///
+/// Channel 0 is the output master volume and channel 1 is the input gain.
+pub extern "C" fn audio_mixer_channel_get_info(
+	audio_mixer_id: u8,
+) -> common::FfiOption<common::audio::MixerChannelInfo> {
+	let info = audio::mixer_channel_info(audio_mixer_id);
+	debug!(
+		"audio_mixer_channel_get_info({}) -> {:?}",
+		audio_mixer_id, info
+	);
+	info.into()
+}
+
+/// Set the level of an audio mixer channel.
+pub extern "C" fn audio_mixer_channel_set_level(
+	audio_mixer_id: u8,
+	level: u8,
+) -> common::ApiResult<()> {
+	debug!(
+		"audio_mixer_channel_set_level({}, {})",
+		audio_mixer_id, level
+	);
+	audio::set_mixer_level(audio_mixer_id, level).into()
+}
+
+/// Configure the audio output.
+///
+/// If accepted, the output FIFO is flushed and the host audio device is
+/// re-opened with the new settings.
+pub extern "C" fn audio_output_set_config(config: common::audio::Config) -> common::ApiResult<()> {
+	debug!("audio_output_set_config({:?})", config);
+	audio::set_output_config(&config).into()
+}
+
+/// Get the audio output's current configuration.
+pub extern "C" fn audio_output_get_config() -> common::ApiResult<common::audio::Config> {
+	let config = audio::output_config();
+	debug!("audio_output_get_config() -> {:?}", config);
+	common::ApiResult::Ok(config)
+}
+
+/// Send audio samples to the output FIFO.
+///
+/// Returns how many bytes were accepted, which is always a whole number of
+/// sample frames.
+///
+/// # Safety
+///
+/// `samples` must point to that many readable bytes.
+pub unsafe extern "C" fn audio_output_data(
+	samples: common::FfiByteSlice,
+) -> common::ApiResult<usize> {
+	let samples = match validate::bytes("audio_output_data", "its samples", &samples) {
+		Ok(samples) => samples,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let accepted = audio::output_data(samples);
+	debug!("audio_output_data({}) -> {}", samples.len(), accepted);
+	common::ApiResult::Ok(accepted)
+}
+
+/// How many sample frames can be sent to `audio_output_data` without any being
+/// dropped?
+///
+/// When nothing is queued, this is the size of the whole FIFO, as set by
+/// `--audio-latency`.
+pub extern "C" fn audio_output_get_space() -> common::ApiResult<usize> {
+	let space = audio::output_space();
+	debug!("audio_output_get_space() -> {}", space);
+	common::ApiResult::Ok(space)
+}
+
+/// Configure the audio input.
+///
+/// If accepted, the input FIFO is flushed and the host audio device is
+/// (re-)opened with the new settings.
+pub extern "C" fn audio_input_set_config(config: common::audio::Config) -> common::ApiResult<()> {
+	debug!("audio_input_set_config({:?})", config);
+	audio::set_input_config(&config).into()
+}
+
+/// Get the audio input's current configuration.
+pub extern "C" fn audio_input_get_config() -> common::ApiResult<common::audio::Config> {
+	let config = audio::input_config();
+	debug!("audio_input_get_config() -> {:?}", config);
+	common::ApiResult::Ok(config)
+}
+
+/// Get recorded samples from the input FIFO.
+///
+/// Returns how many bytes were copied, which is always a whole number of
+/// sample frames.
+///
+/// # Safety
+///
+/// `samples` must point to that many writable bytes.
+pub unsafe extern "C" fn audio_input_data(
+	mut samples: common::FfiBuffer,
+) -> common::ApiResult<usize> {
+	let buffer = match validate::buffer("audio_input_data", "its buffer", &mut samples) {
+		Ok(buffer) if !buffer.is_empty() => buffer,
+		Ok(_) => return common::ApiResult::Err(common::Error::DeviceError),
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let count = audio::input_data(buffer);
+	debug!("audio_input_data({}) -> {}", buffer.len(), count);
+	common::ApiResult::Ok(count)
+}
+
+/// How many sample frames are waiting to be read with `audio_input_data`?
+pub extern "C" fn audio_input_get_count() -> common::ApiResult<usize> {
+	let count = audio::input_count();
+	debug!("audio_input_get_count() -> {}", count);
+	common::ApiResult::Ok(count)
+}
+
+/// Select a peripheral on the Neotron Bus, or deselect everything.
+pub extern "C" fn bus_select(peripheral_id: common::FfiOption<u8>) {
+	let peripheral_id: Option<u8> = peripheral_id.into();
+	debug!("bus_select({:?})", peripheral_id);
+	let mut hw_guard = HARDWARE.lock().unwrap();
+	let hw = hw_guard.as_mut().unwrap();
+	hw.bus_selected = bus::select(hw.bus_selected, peripheral_id);
+}
+
+/// Get information about a peripheral on the Neotron Bus.
+pub extern "C" fn bus_get_info(
+	peripheral_id: u8,
+) -> common::FfiOption<common::bus::PeripheralInfo> {
+	debug!("bus_get_info({})", peripheral_id);
+	bus::info(peripheral_id).into()
+}
+
+/// Send some bytes to the selected peripheral, then read some back.
+pub extern "C" fn bus_write_read(
+	tx: common::FfiByteSlice,
+	tx2: common::FfiByteSlice,
+	mut rx: common::FfiBuffer,
+) -> common::ApiResult<()> {
+	let buffers = validate::bytes("bus_write_read", "tx", &tx).and_then(|tx| {
+		let tx2 = validate::bytes("bus_write_read", "tx2", &tx2)?;
+		let rx = validate::buffer("bus_write_read", "rx", &mut rx)?;
+		Ok((tx, tx2, rx))
+	});
+	let (tx, tx2, rx) = match buffers {
+		Ok(buffers) => buffers,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	debug!("bus_write_read({:?}, {:?})", tx, tx2);
+	let selected = HARDWARE.lock().unwrap().as_ref().unwrap().bus_selected;
+	match bus::write_read(selected, tx, tx2, rx) {
+		Ok(()) => common::ApiResult::Ok(()),
+		Err(e) => {
+			debug!("bus_write_read failed: {}", e);
+			common::ApiResult::Err(common::Error::InvalidDevice)
+		}
+	}
+}
+
+/// Exchange bytes with the selected peripheral, full-duplex.
+pub extern "C" fn bus_exchange(mut buffer: common::FfiBuffer) -> common::ApiResult<()> {
+	debug!("bus_exchange()");
+	let buffer = match validate::buffer("bus_exchange", "its buffer", &mut buffer) {
+		Ok(buffer) => buffer,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let selected = HARDWARE.lock().unwrap().as_ref().unwrap().bus_selected;
+	match bus::exchange(selected, buffer) {
+		Ok(()) => common::ApiResult::Ok(()),
+		Err(e) => {
+			debug!("bus_exchange failed: {}", e);
+			common::ApiResult::Err(common::Error::InvalidDevice)
+		}
+	}
+}
+
+/// Which Neotron Bus peripherals are asking for attention. Bit N is
+/// peripheral N.
+pub extern "C" fn bus_interrupt_status() -> u32 {
+	let status = bus::interrupt_status();
+	debug!("bus_interrupt_status() -> 0x{:08x}", status);
+	status
+}
+
+/// Wait until something happens, or for one tick at most.
+pub extern "C" fn power_idle() {
+	lint::check_blocking("power_idle");
+	let tick = std::time::Duration::from_secs(1) / crate::time::ticks_per_second() as u32;
+	idle::wait(clock::host_duration(tick));
+}
+
+/// Turn the system off, or reset it.
+///
+/// We don't have a bootloader, so a bootloader reset is just a reset. Anything
+/// we don't recognise turns us off, with the BIOS error exit code.
+///
+/// Standby isn't supported: `PowerMode` in neotron-common-bios 0.12 has no
+/// standby mode for the OS to ask for. We can add it once the common crate
+/// does.
+pub extern "C" fn power_control(mode: common::FfiPowerMode) -> ! {
+	match mode.make_safe() {
+		Ok(common::PowerMode::Reset | common::PowerMode::Bootloader) => crate::reset_os(),
+		Ok(common::PowerMode::Off) => {
+			info!("OS turned the power off. Quitting...");
+			shutdown::power_off(shutdown::ExitCode::PowerOff)
+		}
+		_ => {
+			warn!("Got unknown power mode {:?}, so turning off", mode);
+			shutdown::power_off(shutdown::ExitCode::BiosError)
+		}
+	}
+}
+
+pub extern "C" fn compare_and_swap_bool(
+	item: &std::sync::atomic::AtomicBool,
+	old_value: bool,
+	new_value: bool,
+) -> bool {
+	item.compare_exchange(old_value, new_value, Ordering::Relaxed, Ordering::Relaxed)
+		.is_ok()
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Hardware {
+	/// How many nanoseconds since the Neotron epoch our emulated wall clock
+	/// says it is, before any offset set by the OS.
+	pub fn wall_nanos(&self) -> i128 {
+		self.boot_wall_ns + clock::elapsed().as_nanos() as i128
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
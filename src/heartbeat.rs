@@ -0,0 +1,184 @@
+//! # Heartbeat
+//!
+//! With `--heartbeat 60s`, we log one INFO line every minute saying how the
+//! machine is getting on: how long it's been up, how many frames we've drawn,
+//! and how busy the OS has kept the BIOS, the disk, the serial ports, the
+//! audio and the keyboard. The lines all start `Heartbeat:`, so in the log of
+//! a long soak test you can grep for them and see roughly when, and where,
+//! things went wrong.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The running totals, since we started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+	pub frames: u64,
+	pub blocks_read: u64,
+	pub blocks_written: u64,
+	pub serial_in: u64,
+	pub serial_out: u64,
+	pub hid_delivered: u64,
+	pub hid_dropped: u64,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Frames the window has drawn.
+static FRAMES: AtomicU64 = AtomicU64::new(0);
+
+/// Blocks the OS has read from the disk.
+static BLOCKS_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Blocks the OS has written to the disk.
+static BLOCKS_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes the OS has read from the serial ports.
+static SERIAL_IN: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes the OS has written to the serial ports. See [`SERIAL_IN`].
+static SERIAL_OUT: AtomicU64 = AtomicU64::new(0);
+
+/// Key events the OS has picked up with `hid_get_event`.
+static HID_DELIVERED: AtomicU64 = AtomicU64::new(0);
+
+/// Key events thrown away before the OS picked them up.
+static HID_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--heartbeat` interval, like `60s` or `5m`. A plain number is in
+/// seconds.
+pub fn parse_interval(text: &str) -> Result<Duration, String> {
+	crate::watchdog::parse_timeout(text)
+		.map_err(|_| format!("{:?} is not an interval (try 60s or 5m)", text.trim()))
+}
+
+/// Log a heartbeat line every `interval`.
+pub fn start(interval: Duration) {
+	std::thread::spawn(move || {
+		let started = Instant::now();
+		let mut last_time = started;
+		let mut last_frames = 0;
+		let mut last_calls = 0;
+		loop {
+			std::thread::sleep(interval);
+			let now = Instant::now();
+			let frames = FRAMES.load(Ordering::Relaxed);
+			// `stats api reset` can take the count back to zero
+			let calls = crate::apistats::total_calls();
+			let seconds = (now - last_time).as_secs_f64();
+			log::info!(
+				"Heartbeat: up {}, {} frames ({:.1} fps), {} BIOS calls, disk {} read/{} written, serial {} in/{} out, {} audio underruns, HID {} delivered/{} dropped",
+				format_uptime(now - started),
+				frames,
+				frames.saturating_sub(last_frames) as f64 / seconds,
+				calls.saturating_sub(last_calls),
+				BLOCKS_READ.load(Ordering::Relaxed),
+				BLOCKS_WRITTEN.load(Ordering::Relaxed),
+				SERIAL_IN.load(Ordering::Relaxed),
+				SERIAL_OUT.load(Ordering::Relaxed),
+				crate::audio::stats().output_underruns,
+				HID_DELIVERED.load(Ordering::Relaxed),
+				HID_DROPPED.load(Ordering::Relaxed),
+			);
+			last_time = now;
+			last_frames = frames;
+			last_calls = calls;
+		}
+	});
+}
+
+/// The running totals, for `--metrics`.
+pub fn totals() -> Totals {
+	Totals {
+		frames: FRAMES.load(Ordering::Relaxed),
+		blocks_read: BLOCKS_READ.load(Ordering::Relaxed),
+		blocks_written: BLOCKS_WRITTEN.load(Ordering::Relaxed),
+		serial_in: SERIAL_IN.load(Ordering::Relaxed),
+		serial_out: SERIAL_OUT.load(Ordering::Relaxed),
+		hid_delivered: HID_DELIVERED.load(Ordering::Relaxed),
+		hid_dropped: HID_DROPPED.load(Ordering::Relaxed),
+	}
+}
+
+/// Count a frame drawn by the window.
+pub fn frame() {
+	FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count blocks read from the disk.
+pub fn blocks_read(count: u8) {
+	BLOCKS_READ.fetch_add(u64::from(count), Ordering::Relaxed);
+}
+
+/// Count blocks written to the disk.
+pub fn blocks_written(count: u8) {
+	BLOCKS_WRITTEN.fetch_add(u64::from(count), Ordering::Relaxed);
+}
+
+/// Count bytes the OS read from a serial port.
+pub fn serial_in(count: usize) {
+	SERIAL_IN.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Count bytes the OS wrote to a serial port.
+pub fn serial_out(count: usize) {
+	SERIAL_OUT.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Count a key event given to the OS.
+pub fn hid_delivered() {
+	HID_DELIVERED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a key event thrown away.
+pub fn hid_dropped() {
+	HID_DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Show an uptime like `3h02m07s`.
+fn format_uptime(uptime: Duration) -> String {
+	let seconds = uptime.as_secs();
+	format!(
+		"{}h{:02}m{:02}s",
+		seconds / 3600,
+		(seconds / 60) % 60,
+		seconds % 60
+	)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,70 @@
+//! # Hex dumps for the Neotron Desktop BIOS
+//!
+//! Shows bytes in the classic hex-and-ASCII layout, sixteen to a line, and
+//! reads hex bytes typed in by the user.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Format some bytes as a hex dump. The offsets shown start at `base`.
+pub fn hexdump(data: &[u8], base: usize) -> String {
+	let mut output = String::new();
+	for (idx, line) in data.chunks(16).enumerate() {
+		if idx != 0 {
+			output.push('\n');
+		}
+		output.push_str(&format!("{:08x}: ", base + idx * 16));
+		for col in 0..16 {
+			match line.get(col) {
+				Some(byte) => output.push_str(&format!("{:02x} ", byte)),
+				None => output.push_str("   "),
+			}
+		}
+		output.push(' ');
+		for byte in line {
+			let ch = if byte.is_ascii_graphic() || *byte == b' ' {
+				char::from(*byte)
+			} else {
+				'.'
+			};
+			output.push(ch);
+		}
+	}
+	output
+}
+
+/// Parse some hex bytes, ignoring any whitespace.
+pub fn parse_hex(text: &str) -> Option<Vec<u8>> {
+	let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+	let pairs = digits.chunks_exact(2);
+	if !pairs.remainder().is_empty() {
+		return None;
+	}
+	pairs
+		.map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+		.collect()
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,286 @@
+//! # Human Interface Devices
+//!
+//! The BIOS functions for the keyboard. The window sends us key presses (see
+//! [`crate::window`]) through a channel, and the OS picks them up one at a
+//! time with `hid_get_event`.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+use pix_engine::prelude::*;
+
+use neotron_common_bios as common;
+
+use crate::{clock, heartbeat};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AppEvent {
+	Started,
+	KeyUp(Key),
+	KeyDown(Key),
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// HID events come from here
+static EV_QUEUE: Mutex<Option<mpsc::Receiver<AppEvent>>> = Mutex::new(None);
+
+/// Key presses to play back, earliest first, with the emulated time each is
+/// due.
+static SCRIPT: Mutex<VecDeque<(Duration, AppEvent)>> = Mutex::new(VecDeque::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Start a new queue for key presses, throwing away any still in the old one,
+/// and give back where to send them.
+pub fn connect() -> mpsc::Sender<AppEvent> {
+	let (sender, receiver) = mpsc::channel();
+	EV_QUEUE.lock().unwrap().replace(receiver);
+	sender
+}
+
+/// Wait for the window to say it's ready. Call this on the OS thread, before
+/// the OS starts.
+pub fn wait_for_start() {
+	let queue = EV_QUEUE.lock().unwrap();
+	let ev = queue.as_ref().unwrap().recv().unwrap();
+	assert_eq!(ev, AppEvent::Started);
+}
+
+/// Play back some key presses: each event reaches the OS once emulated time
+/// gets to the time it's given with.
+///
+/// Scripted key presses come before any from the window. With a virtual
+/// clock (see [`clock::set_virtual`]) the OS sees them at the same point
+/// every run.
+pub fn play_script(events: Vec<(Duration, AppEvent)>) {
+	let mut script = SCRIPT.lock().unwrap();
+	script.extend(events);
+	script.make_contiguous().sort_by_key(|(at, _)| *at);
+}
+
+/// The next scripted key press, if it's due.
+fn next_scripted() -> Option<AppEvent> {
+	let mut script = SCRIPT.lock().unwrap();
+	let (at, _) = script.front()?;
+	if *at > clock::elapsed() {
+		return None;
+	}
+	script.pop_front().map(|(_, event)| event)
+}
+
+/// Forget any keys pressed for an OS that has gone.
+pub fn discard_pending() {
+	while let Ok(event) = EV_QUEUE.lock().unwrap().as_ref().unwrap().try_recv() {
+		if matches!(event, AppEvent::KeyUp(_) | AppEvent::KeyDown(_)) {
+			heartbeat::hid_dropped();
+		}
+	}
+}
+
+/// Get the next available HID event, if any.
+///
+/// This function doesn't block. It will return `Ok(None)` if there is no event ready.
+pub extern "C" fn hid_get_event() -> common::ApiResult<common::FfiOption<common::hid::HidEvent>> {
+	let queue = EV_QUEUE.lock().unwrap();
+	let (key, make_event): (Key, fn(common::hid::KeyCode) -> common::hid::HidEvent) =
+		match next_scripted().or_else(|| queue.as_ref().unwrap().try_recv().ok()) {
+			Some(AppEvent::KeyUp(key)) => (key, common::hid::HidEvent::KeyRelease),
+			Some(AppEvent::KeyDown(key)) => (key, common::hid::HidEvent::KeyPress),
+			_ => return common::ApiResult::Ok(common::FfiOption::None),
+		};
+	match convert_keycode(key) {
+		Some(code) => {
+			let event = make_event(code);
+			debug!("hid_get_event() -> {:?}", event);
+			heartbeat::hid_delivered();
+			common::ApiResult::Ok(common::FfiOption::Some(event))
+		}
+		None => {
+			debug!(
+				"hid_get_event() dropped {:?}, which has no Neotron keycode",
+				key
+			);
+			heartbeat::hid_dropped();
+			common::ApiResult::Ok(common::FfiOption::None)
+		}
+	}
+}
+
+/// Convert a pix-engine keycode into a Neotron BIOS keycode.
+///
+/// Returns `None` for keys the Neotron has no keycode for. Most of those are
+/// characters that only have a key of their own on some keyboard layouts
+/// (like `&` on a French keyboard).
+pub fn convert_keycode(key: Key) -> Option<common::hid::KeyCode> {
+	match key {
+		Key::Backspace => Some(common::hid::KeyCode::Backspace),
+		Key::Tab => Some(common::hid::KeyCode::Tab),
+		Key::Return => Some(common::hid::KeyCode::Return),
+		Key::Escape => Some(common::hid::KeyCode::Escape),
+		Key::Space => Some(common::hid::KeyCode::Spacebar),
+		Key::Hash => Some(common::hid::KeyCode::Oem7),
+		Key::Quote => Some(common::hid::KeyCode::Oem3),
+		Key::Comma => Some(common::hid::KeyCode::OemComma),
+		Key::Minus => Some(common::hid::KeyCode::OemMinus),
+		Key::Period => Some(common::hid::KeyCode::OemPeriod),
+		Key::Slash => Some(common::hid::KeyCode::Oem2),
+		Key::Num0 => Some(common::hid::KeyCode::Key0),
+		Key::Num1 => Some(common::hid::KeyCode::Key1),
+		Key::Num2 => Some(common::hid::KeyCode::Key2),
+		Key::Num3 => Some(common::hid::KeyCode::Key3),
+		Key::Num4 => Some(common::hid::KeyCode::Key4),
+		Key::Num5 => Some(common::hid::KeyCode::Key5),
+		Key::Num6 => Some(common::hid::KeyCode::Key6),
+		Key::Num7 => Some(common::hid::KeyCode::Key7),
+		Key::Num8 => Some(common::hid::KeyCode::Key8),
+		Key::Num9 => Some(common::hid::KeyCode::Key9),
+		Key::Semicolon => Some(common::hid::KeyCode::Oem1),
+		Key::Equals => Some(common::hid::KeyCode::OemPlus),
+		Key::LeftBracket => Some(common::hid::KeyCode::Oem4),
+		Key::Backslash => Some(common::hid::KeyCode::Oem5),
+		Key::RightBracket => Some(common::hid::KeyCode::Oem6),
+		Key::Backquote => Some(common::hid::KeyCode::Oem8),
+		Key::A => Some(common::hid::KeyCode::A),
+		Key::B => Some(common::hid::KeyCode::B),
+		Key::C => Some(common::hid::KeyCode::C),
+		Key::D => Some(common::hid::KeyCode::D),
+		Key::E => Some(common::hid::KeyCode::E),
+		Key::F => Some(common::hid::KeyCode::F),
+		Key::G => Some(common::hid::KeyCode::G),
+		Key::H => Some(common::hid::KeyCode::H),
+		Key::I => Some(common::hid::KeyCode::I),
+		Key::J => Some(common::hid::KeyCode::J),
+		Key::K => Some(common::hid::KeyCode::K),
+		Key::L => Some(common::hid::KeyCode::L),
+		Key::M => Some(common::hid::KeyCode::M),
+		Key::N => Some(common::hid::KeyCode::N),
+		Key::O => Some(common::hid::KeyCode::O),
+		Key::P => Some(common::hid::KeyCode::P),
+		Key::Q => Some(common::hid::KeyCode::Q),
+		Key::R => Some(common::hid::KeyCode::R),
+		Key::S => Some(common::hid::KeyCode::S),
+		Key::T => Some(common::hid::KeyCode::T),
+		Key::U => Some(common::hid::KeyCode::U),
+		Key::V => Some(common::hid::KeyCode::V),
+		Key::W => Some(common::hid::KeyCode::W),
+		Key::X => Some(common::hid::KeyCode::X),
+		Key::Y => Some(common::hid::KeyCode::Y),
+		Key::Z => Some(common::hid::KeyCode::Z),
+		Key::Delete => Some(common::hid::KeyCode::Delete),
+		Key::CapsLock => Some(common::hid::KeyCode::CapsLock),
+		Key::F1 => Some(common::hid::KeyCode::F1),
+		Key::F2 => Some(common::hid::KeyCode::F2),
+		Key::F3 => Some(common::hid::KeyCode::F3),
+		Key::F4 => Some(common::hid::KeyCode::F4),
+		Key::F5 => Some(common::hid::KeyCode::F5),
+		Key::F6 => Some(common::hid::KeyCode::F6),
+		Key::F7 => Some(common::hid::KeyCode::F7),
+		Key::F8 => Some(common::hid::KeyCode::F8),
+		Key::F9 => Some(common::hid::KeyCode::F9),
+		Key::F10 => Some(common::hid::KeyCode::F10),
+		Key::F11 => Some(common::hid::KeyCode::F11),
+		Key::F12 => Some(common::hid::KeyCode::F12),
+		Key::PrintScreen => Some(common::hid::KeyCode::PrintScreen),
+		Key::ScrollLock => Some(common::hid::KeyCode::ScrollLock),
+		Key::Pause => Some(common::hid::KeyCode::PauseBreak),
+		Key::Insert => Some(common::hid::KeyCode::Insert),
+		Key::Home => Some(common::hid::KeyCode::Home),
+		Key::PageUp => Some(common::hid::KeyCode::PageUp),
+		Key::End => Some(common::hid::KeyCode::End),
+		Key::PageDown => Some(common::hid::KeyCode::PageDown),
+		Key::Right => Some(common::hid::KeyCode::ArrowRight),
+		Key::Left => Some(common::hid::KeyCode::ArrowLeft),
+		Key::Down => Some(common::hid::KeyCode::ArrowDown),
+		Key::Up => Some(common::hid::KeyCode::ArrowUp),
+		Key::NumLock => Some(common::hid::KeyCode::NumpadLock),
+		Key::KpDivide => Some(common::hid::KeyCode::NumpadDivide),
+		Key::KpMultiply => Some(common::hid::KeyCode::NumpadMultiply),
+		Key::KpMinus => Some(common::hid::KeyCode::NumpadSubtract),
+		Key::KpPlus => Some(common::hid::KeyCode::NumpadAdd),
+		Key::KpEnter => Some(common::hid::KeyCode::NumpadEnter),
+		Key::Kp1 => Some(common::hid::KeyCode::Numpad1),
+		Key::Kp2 => Some(common::hid::KeyCode::Numpad2),
+		Key::Kp3 => Some(common::hid::KeyCode::Numpad3),
+		Key::Kp4 => Some(common::hid::KeyCode::Numpad4),
+		Key::Kp5 => Some(common::hid::KeyCode::Numpad5),
+		Key::Kp6 => Some(common::hid::KeyCode::Numpad6),
+		Key::Kp7 => Some(common::hid::KeyCode::Numpad7),
+		Key::Kp8 => Some(common::hid::KeyCode::Numpad8),
+		Key::Kp9 => Some(common::hid::KeyCode::Numpad9),
+		Key::Kp0 => Some(common::hid::KeyCode::Numpad0),
+		Key::KpPeriod => Some(common::hid::KeyCode::NumpadPeriod),
+		Key::LCtrl => Some(common::hid::KeyCode::LControl),
+		Key::LShift => Some(common::hid::KeyCode::LShift),
+		Key::LAlt => Some(common::hid::KeyCode::LAlt),
+		Key::LGui => Some(common::hid::KeyCode::LWin),
+		Key::RCtrl => Some(common::hid::KeyCode::RControl),
+		Key::RShift => Some(common::hid::KeyCode::RShift),
+		Key::RAlt => Some(common::hid::KeyCode::RAltGr),
+		Key::RGui => Some(common::hid::KeyCode::RWin),
+		Key::Exclaim
+		| Key::Quotedbl
+		| Key::Dollar
+		| Key::Percent
+		| Key::Ampersand
+		| Key::LeftParen
+		| Key::RightParen
+		| Key::Asterisk
+		| Key::Plus
+		| Key::Colon
+		| Key::Less
+		| Key::Greater
+		| Key::Question
+		| Key::At
+		| Key::Caret
+		| Key::Underscore
+		| Key::KpEquals
+		| Key::KpComma
+		| Key::Unhandled => None,
+		// Anything pix-engine adds in future
+		_ => None,
+	}
+}
+
+/// Control the keyboard LEDs.
+pub extern "C" fn hid_set_leds(_leds: common::hid::KeyboardLeds) -> common::ApiResult<()> {
+	debug!("hid_set_leds()");
+	Err(common::Error::Unimplemented).into()
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
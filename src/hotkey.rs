@@ -0,0 +1,298 @@
+//! # Host hotkeys for the Neotron Desktop BIOS
+//!
+//! Host hotkeys only fire while the *prefix* key (Right-Ctrl by default) is
+//! held down. While it is acting as a prefix, the prefix key is not sent to
+//! the OS. If it is pressed and released on its own, or used with a key that
+//! isn't a host hotkey, the OS sees it as a normal key.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use pix_engine::prelude::Key;
+
+use crate::hid::AppEvent;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Something the host does when a hotkey is pressed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+	/// Switch between a window and full-screen.
+	ToggleFullscreen,
+	/// Mute or un-mute the audio output.
+	ToggleMute,
+	/// Pause the emulation, or resume it if it is paused.
+	Pause,
+	/// Save a snapshot of the machine.
+	Snapshot,
+	/// Reset the OS.
+	Reset,
+}
+
+/// What should happen as a result of a key event.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+	/// Send these events (possibly none) to the OS.
+	Forward(Vec<AppEvent>),
+	/// Perform a host action. The OS sees nothing.
+	Action(Action),
+}
+
+/// Where we are with the prefix key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+	/// Prefix is not held.
+	Idle,
+	/// Prefix is held and hasn't been sent to the OS. `used` is set once a
+	/// hotkey has fired.
+	Held { used: bool },
+	/// Prefix is held and the OS has been told it is down.
+	PassThrough,
+}
+
+/// Tracks the hotkey prefix key and turns key events into OS events or host
+/// actions.
+pub struct Prefix {
+	key: Key,
+	state: State,
+	/// Keys which fired a hotkey, so we can eat their key-up event.
+	swallowed: Vec<Key>,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a prefix key name from the command line.
+pub fn parse_key(name: &str) -> Result<Key, String> {
+	let key = match name.to_ascii_lowercase().as_str() {
+		"rctrl" => Key::RCtrl,
+		"lctrl" => Key::LCtrl,
+		"ralt" => Key::RAlt,
+		"lalt" => Key::LAlt,
+		"rshift" => Key::RShift,
+		"lshift" => Key::LShift,
+		"rgui" => Key::RGui,
+		"lgui" => Key::LGui,
+		"scrolllock" => Key::ScrollLock,
+		"pause" => Key::Pause,
+		_ => {
+			return Err(format!(
+				"unknown key {:?} (try RCtrl, LCtrl, RAlt, LAlt, RShift, LShift, RGui, LGui, ScrollLock or Pause)",
+				name
+			))
+		}
+	};
+	Ok(key)
+}
+
+/// Parse a chord of keys pressed together, like `ctrl-alt-del` or `shift-a`,
+/// for the debug console's `sendkey`.
+pub fn parse_chord(text: &str) -> Result<Vec<Key>, String> {
+	text.split('-').map(parse_chord_key).collect()
+}
+
+/// Parse one key of a chord.
+fn parse_chord_key(name: &str) -> Result<Key, String> {
+	const LETTERS: [Key; 26] = [
+		Key::A,
+		Key::B,
+		Key::C,
+		Key::D,
+		Key::E,
+		Key::F,
+		Key::G,
+		Key::H,
+		Key::I,
+		Key::J,
+		Key::K,
+		Key::L,
+		Key::M,
+		Key::N,
+		Key::O,
+		Key::P,
+		Key::Q,
+		Key::R,
+		Key::S,
+		Key::T,
+		Key::U,
+		Key::V,
+		Key::W,
+		Key::X,
+		Key::Y,
+		Key::Z,
+	];
+	const DIGITS: [Key; 10] = [
+		Key::Num0,
+		Key::Num1,
+		Key::Num2,
+		Key::Num3,
+		Key::Num4,
+		Key::Num5,
+		Key::Num6,
+		Key::Num7,
+		Key::Num8,
+		Key::Num9,
+	];
+	const FUNCTION_KEYS: [Key; 12] = [
+		Key::F1,
+		Key::F2,
+		Key::F3,
+		Key::F4,
+		Key::F5,
+		Key::F6,
+		Key::F7,
+		Key::F8,
+		Key::F9,
+		Key::F10,
+		Key::F11,
+		Key::F12,
+	];
+	let lower = name.to_ascii_lowercase();
+	let key = match lower.as_str() {
+		"ctrl" => Key::LCtrl,
+		"alt" => Key::LAlt,
+		"shift" => Key::LShift,
+		"gui" => Key::LGui,
+		"del" | "delete" => Key::Delete,
+		"ret" | "return" | "enter" => Key::Return,
+		"esc" | "escape" => Key::Escape,
+		"tab" => Key::Tab,
+		"spc" | "space" => Key::Space,
+		"backspace" => Key::Backspace,
+		"insert" => Key::Insert,
+		"home" => Key::Home,
+		"end" => Key::End,
+		"pgup" => Key::PageUp,
+		"pgdn" => Key::PageDown,
+		"up" => Key::Up,
+		"down" => Key::Down,
+		"left" => Key::Left,
+		"right" => Key::Right,
+		_ => {
+			let mut chars = lower.chars();
+			match (chars.next(), chars.next()) {
+				(Some(c @ 'a'..='z'), None) => LETTERS[usize::from(c as u8 - b'a')],
+				(Some(c @ '0'..='9'), None) => DIGITS[usize::from(c as u8 - b'0')],
+				(Some('f'), Some(_)) => lower[1..]
+					.parse::<usize>()
+					.ok()
+					.and_then(|n| FUNCTION_KEYS.get(n.wrapping_sub(1)).copied())
+					.or_else(|| parse_key(name).ok())
+					.ok_or_else(|| format!("unknown key {:?}", name))?,
+				_ => parse_key(name).map_err(|_| format!("unknown key {:?}", name))?,
+			}
+		}
+	};
+	Ok(key)
+}
+
+/// Which host action, if any, is bound to this key.
+fn action_for(key: Key) -> Option<Action> {
+	match key {
+		Key::F => Some(Action::ToggleFullscreen),
+		Key::M => Some(Action::ToggleMute),
+		Key::P => Some(Action::Pause),
+		Key::S => Some(Action::Snapshot),
+		Key::R => Some(Action::Reset),
+		_ => None,
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Prefix {
+	/// Create a new tracker using the given key as the prefix.
+	pub fn new(key: Key) -> Prefix {
+		Prefix {
+			key,
+			state: State::Idle,
+			swallowed: Vec::new(),
+		}
+	}
+
+	/// Handle a key being pressed (or auto-repeating).
+	pub fn key_down(&mut self, key: Key) -> Outcome {
+		if key == self.key {
+			return match self.state {
+				State::Idle => {
+					self.state = State::Held { used: false };
+					Outcome::Forward(vec![])
+				}
+				State::Held { .. } => Outcome::Forward(vec![]),
+				State::PassThrough => Outcome::Forward(vec![AppEvent::KeyDown(key)]),
+			};
+		}
+
+		if self.swallowed.contains(&key) {
+			// Don't fire the action again on auto-repeat
+			return Outcome::Forward(vec![]);
+		}
+
+		match self.state {
+			State::Held { .. } => {
+				if let Some(action) = action_for(key) {
+					self.state = State::Held { used: true };
+					self.swallowed.push(key);
+					Outcome::Action(action)
+				} else {
+					// Not one of ours, so the prefix is being used as a
+					// normal modifier.
+					self.state = State::PassThrough;
+					Outcome::Forward(vec![AppEvent::KeyDown(self.key), AppEvent::KeyDown(key)])
+				}
+			}
+			State::Idle | State::PassThrough => Outcome::Forward(vec![AppEvent::KeyDown(key)]),
+		}
+	}
+
+	/// Handle a key being released.
+	pub fn key_up(&mut self, key: Key) -> Outcome {
+		if key == self.key {
+			let events = match self.state {
+				State::Held { used: false } => {
+					vec![AppEvent::KeyDown(key), AppEvent::KeyUp(key)]
+				}
+				State::Held { used: true } => vec![],
+				State::Idle | State::PassThrough => vec![AppEvent::KeyUp(key)],
+			};
+			self.state = State::Idle;
+			return Outcome::Forward(events);
+		}
+
+		if let Some(idx) = self.swallowed.iter().position(|k| *k == key) {
+			self.swallowed.remove(idx);
+			Outcome::Forward(vec![])
+		} else {
+			Outcome::Forward(vec![AppEvent::KeyUp(key)])
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,318 @@
+//! # Emulated I²C devices for the Neotron Desktop BIOS
+//!
+//! We don't have a real I²C bus, so we emulate two buses, and some chips on
+//! them that the OS might want to talk to. Each chip implements
+//! [`I2cDevice`], and lives in a registry keyed by bus and address, which
+//! `i2c_write_read` looks in.
+//!
+//! You choose which chips go where on the command line, with
+//! `--i2c-device=<bus>:<address>:<type>[:<options>]`.
+//!
+//! Like a real bus, nothing answers at an address with no device on it, so
+//! the OS can scan a bus by sending an empty write to every address. The
+//! addresses the I²C specification reserves (0x00 to 0x07 and 0x78 to 0x7F)
+//! never answer.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Modules
+// -----------------------------------------------------------------------------
+
+mod eeprom;
+mod lm75;
+mod pcf8574;
+
+pub use pcf8574::{panel, set_button};
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Things that can go wrong on the I²C bus.
+#[derive(Debug)]
+pub enum Error {
+	/// There is no such bus
+	NoBus,
+	/// Nothing answered at that address (the address was NACKed)
+	NoDevice,
+	/// The device's backing file couldn't be written
+	Io(std::io::Error),
+}
+
+/// An emulated chip on one of our I²C buses.
+pub trait I2cDevice: Send {
+	/// The OS sent us some bytes, then a STOP.
+	fn write(&mut self, data: &[u8]) -> Result<(), Error>;
+
+	/// The OS wants some bytes, then will send a STOP.
+	fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+
+	/// The OS sent us some bytes, then a repeated START, and now wants some
+	/// bytes back.
+	///
+	/// Most chips treat this the same as a write followed by a read.
+	fn write_then_read(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+		self.write(data)?;
+		self.read(buffer)
+	}
+
+	/// Describe this device, for `--list-devices`.
+	fn describe(&self) -> String;
+}
+
+/// The `--i2c-device` option.
+#[derive(Debug, Clone)]
+pub struct DeviceSpec {
+	/// Which bus the device is on
+	pub bus: u8,
+	/// The device's 7-bit address
+	pub address: u8,
+	/// What sort of device it is
+	pub kind: DeviceKind,
+}
+
+/// The kinds of device we can emulate.
+#[derive(Debug, Clone)]
+pub enum DeviceKind {
+	/// A 24C64-style EEPROM
+	Eeprom(EepromSpec),
+	/// An LM75 temperature sensor, reading this many °C
+	Lm75(f32),
+	/// A PCF8574 GPIO expander, wired to the virtual LED/button panel
+	Pcf8574,
+}
+
+/// The `--i2c-eeprom` option.
+#[derive(Debug, Clone)]
+pub struct EepromSpec {
+	/// The file holding the EEPROM contents
+	pub path: PathBuf,
+	/// How big the EEPROM is, in bytes
+	pub size: usize,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What an LM75 reads if you don't say.
+pub const LM75_CELSIUS: f32 = lm75::DEFAULT_CELSIUS;
+
+/// The names of our buses. The bus number is the index into this list.
+const BUS_NAMES: [&str; 2] = ["I2C0", "I2C1"];
+
+/// The addresses the I²C specification reserves for special purposes (general
+/// call, CBUS, high-speed mode, 10-bit addressing and so on).
+const RESERVED_ADDRESSES: [std::ops::RangeInclusive<u8>; 2] = [0x00..=0x07, 0x78..=0x7F];
+
+/// All our devices, by bus and address.
+static DEVICES: Mutex<BTreeMap<(u8, u8), Box<dyn I2cDevice>>> = Mutex::new(BTreeMap::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Attach a device to a bus.
+pub fn add_device(spec: &DeviceSpec) -> Result<(), String> {
+	if usize::from(spec.bus) >= BUS_NAMES.len() {
+		return Err(format!("there is no I2C Bus {}", spec.bus));
+	}
+	if is_reserved(spec.address) {
+		return Err(format!(
+			"0x{:02x} is a reserved I2C address (use 0x08 to 0x77)",
+			spec.address
+		));
+	}
+	let mut devices = DEVICES.lock().unwrap();
+	if devices.contains_key(&(spec.bus, spec.address)) {
+		return Err(format!(
+			"two devices at 0x{:02x} on I2C Bus {}",
+			spec.address, spec.bus
+		));
+	}
+	let device: Box<dyn I2cDevice> = match &spec.kind {
+		DeviceKind::Eeprom(eeprom) => Box::new(
+			eeprom::Eeprom::open(eeprom)
+				.map_err(|e| format!("failed to load {}: {}", eeprom.path.display(), e))?,
+		),
+		DeviceKind::Lm75(celsius) => Box::new(lm75::Lm75::new(*celsius)),
+		DeviceKind::Pcf8574 => Box::new(pcf8574::Pcf8574::new()?),
+	};
+	log::info!(
+		"I2C: {} at 0x{:02x} on Bus {}",
+		device.describe(),
+		spec.address,
+		spec.bus
+	);
+	devices.insert((spec.bus, spec.address), device);
+	Ok(())
+}
+
+/// Get information about a bus, or `None` if there is no such bus.
+pub fn bus_info(bus: u8) -> Option<common::i2c::BusInfo> {
+	let name = BUS_NAMES.get(usize::from(bus))?;
+	Some(common::i2c::BusInfo {
+		name: common::FfiString::new(name),
+	})
+}
+
+/// List our buses, and the devices on them, for `--list-devices`.
+pub fn inventory() -> Vec<String> {
+	let devices = DEVICES.lock().unwrap();
+	let mut lines = vec![String::from("I2C buses:")];
+	for (bus, name) in BUS_NAMES.iter().enumerate() {
+		lines.push(format!("  {}: {}", bus, name));
+		for ((_, address), device) in devices.range((bus as u8, 0)..=(bus as u8, 0x7F)) {
+			lines.push(format!("    0x{:02x}: {}", address, device.describe()));
+		}
+	}
+	lines
+}
+
+/// Describe every device, for a snapshot.
+pub fn describe_devices() -> Vec<String> {
+	let devices = DEVICES.lock().unwrap();
+	devices
+		.iter()
+		.map(|((bus, address), device)| {
+			format!("I2C {} 0x{:02x}: {}", bus, address, device.describe())
+		})
+		.collect()
+}
+
+/// Is this one of the addresses the I²C specification reserves?
+pub fn is_reserved(address: u8) -> bool {
+	RESERVED_ADDRESSES
+		.iter()
+		.any(|range| range.contains(&address))
+}
+
+/// Talk to a device: send `tx` then `tx2`, then fill `rx`.
+///
+/// If there's nothing to send or receive, this just checks whether a device
+/// answers at that address.
+pub fn write_read(bus: u8, address: u8, tx: &[u8], tx2: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+	if usize::from(bus) >= BUS_NAMES.len() {
+		return Err(Error::NoBus);
+	}
+	if is_reserved(address) {
+		return Err(Error::NoDevice);
+	}
+	let mut devices = DEVICES.lock().unwrap();
+	let device = devices.get_mut(&(bus, address)).ok_or(Error::NoDevice)?;
+	let tx: Vec<u8> = tx.iter().chain(tx2).copied().collect();
+	match (tx.is_empty(), rx.is_empty()) {
+		(false, false) => device.write_then_read(&tx, rx),
+		(true, false) => device.read(rx),
+		(false, true) => device.write(&tx),
+		// The device ACKed its address, and that's all the OS wanted
+		(true, true) => Ok(()),
+	}
+}
+
+/// Parse an `--i2c-device` option, like `0:0x50:eeprom:eeprom.bin:8KiB` or
+/// `1:0x48:lm75:21.5`.
+pub fn parse_device(text: &str) -> Result<DeviceSpec, String> {
+	let bad = || {
+		format!(
+			"{:?} should be <bus>:<address>:<type>[:<options>] (e.g. 1:0x48:lm75)",
+			text
+		)
+	};
+	let mut parts = text.splitn(4, ':');
+	let bus = parts.next().ok_or_else(bad)?;
+	let address = parts.next().ok_or_else(bad)?;
+	let kind = parts.next().ok_or_else(bad)?;
+	let options = parts.next();
+	let bus = bus
+		.parse()
+		.map_err(|_| format!("{:?} is not a bus number", bus))?;
+	let address = parse_address(address)?;
+	let kind = match (kind, options) {
+		("eeprom", Some(options)) => DeviceKind::Eeprom(parse_eeprom(options)?),
+		("lm75", None) => DeviceKind::Lm75(LM75_CELSIUS),
+		("lm75", Some(celsius)) => DeviceKind::Lm75(
+			celsius
+				.parse()
+				.map_err(|_| format!("{:?} is not a temperature", celsius))?,
+		),
+		("pcf8574", None) => DeviceKind::Pcf8574,
+		_ => {
+			return Err(format!(
+				"unknown device {:?} (try eeprom:<path>:<size>, lm75[:<celsius>] or pcf8574)",
+				kind
+			))
+		}
+	};
+	Ok(DeviceSpec { bus, address, kind })
+}
+
+/// Parse an `--i2c-eeprom` option, like `eeprom.bin:8KiB`.
+pub fn parse_eeprom(text: &str) -> Result<EepromSpec, String> {
+	let (path, size) = text
+		.rsplit_once(':')
+		.ok_or_else(|| format!("{:?} should be <path>:<size> (e.g. eeprom.bin:8KiB)", text))?;
+	Ok(EepromSpec {
+		path: PathBuf::from(path),
+		size: crate::memory::parse_size(size)?,
+	})
+}
+
+/// Parse a 7-bit I²C device address, like `0x50`.
+pub fn parse_address(text: &str) -> Result<u8, String> {
+	let address = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+		Some(hex) => u8::from_str_radix(hex, 16),
+		None => text.parse(),
+	}
+	.map_err(|_| format!("{:?} is not an I2C address (try 0x50)", text))?;
+	if address > 0x7F {
+		return Err(format!("0x{:02x} is more than seven bits", address));
+	}
+	Ok(address)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::NoBus => write!(f, "no such bus"),
+			Error::NoDevice => write!(f, "no device at that address"),
+			Error::Io(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,125 @@
+//! # Emulated 24C64-style EEPROM
+//!
+//! The OS sends a two-byte address, then either some data to write (which
+//! wraps around within a 32-byte page, like the real thing) or does a read
+//! (which carries on across pages, and wraps at the end of the array). The
+//! contents are kept in a file, which is saved after every write.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::PathBuf;
+
+use super::{EepromSpec, Error, I2cDevice};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An emulated 24C64-style EEPROM.
+pub struct Eeprom {
+	/// Where we keep the contents
+	path: PathBuf,
+	/// The contents
+	data: Vec<u8>,
+	/// The chip's internal address counter
+	pointer: usize,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Writes wrap around within a page this big.
+const PAGE_SIZE: usize = 32;
+
+/// What erased EEPROM reads as.
+const ERASED: u8 = 0xFF;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Eeprom {
+	/// Load the EEPROM contents from its file. If there isn't one, the EEPROM
+	/// is blank.
+	pub fn open(spec: &EepromSpec) -> Result<Eeprom, std::io::Error> {
+		let mut data = match std::fs::read(&spec.path) {
+			Ok(data) => data,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+			Err(e) => return Err(e),
+		};
+		data.resize(spec.size, ERASED);
+		Ok(Eeprom {
+			path: spec.path.clone(),
+			data,
+			pointer: 0,
+		})
+	}
+
+	/// Write to the array, wrapping around within the current page, then save
+	/// the contents.
+	fn write_array(&mut self, data: &[u8]) -> Result<(), Error> {
+		let page_start = self.pointer - (self.pointer % PAGE_SIZE);
+		let mut offset = self.pointer % PAGE_SIZE;
+		for byte in data {
+			if let Some(cell) = self.data.get_mut(page_start + offset) {
+				*cell = *byte;
+			}
+			offset = (offset + 1) % PAGE_SIZE;
+		}
+		self.pointer = (page_start + offset) % self.data.len();
+		crate::nvram::replace_file(&self.path, &self.data).map_err(Error::Io)
+	}
+}
+
+impl I2cDevice for Eeprom {
+	/// The first two bytes set the address. Any more are written to the
+	/// array.
+	fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		if let [hi, lo, data @ ..] = data {
+			self.pointer = usize::from(u16::from_be_bytes([*hi, *lo])) % self.data.len();
+			if !data.is_empty() {
+				self.write_array(data)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Read from wherever the address counter has got to.
+	fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		for byte in buffer.iter_mut() {
+			*byte = self.data[self.pointer];
+			self.pointer = (self.pointer + 1) % self.data.len();
+		}
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		format!("{} byte EEPROM ({})", self.data.len(), self.path.display())
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,127 @@
+//! # Emulated LM75 temperature sensor
+//!
+//! The first byte the OS writes selects a register. Any more bytes are written
+//! to that register. Reads come from the selected register. The temperature
+//! never changes.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use super::{Error, I2cDevice};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An emulated LM75 temperature sensor.
+pub struct Lm75 {
+	/// What we read, in °C
+	celsius: f32,
+	/// Which register is selected
+	pointer: u8,
+	/// The configuration register
+	config: u8,
+	/// The hysteresis register
+	hysteresis: [u8; 2],
+	/// The over-temperature shutdown register
+	over_temperature: [u8; 2],
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What we read if you don't say otherwise.
+pub const DEFAULT_CELSIUS: f32 = 25.0;
+
+/// Register numbers
+const REG_TEMPERATURE: u8 = 0;
+const REG_CONFIG: u8 = 1;
+const REG_HYSTERESIS: u8 = 2;
+const REG_OVER_TEMPERATURE: u8 = 3;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Convert a temperature into the LM75's 9-bit two's complement format, in
+/// the top bits of a big-endian 16-bit value.
+fn encode(celsius: f32) -> [u8; 2] {
+	let half_degrees = (celsius * 2.0).round().clamp(-110.0, 250.0) as i16;
+	(half_degrees << 7).to_be_bytes()
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Lm75 {
+	/// Make a sensor that always reads this temperature.
+	pub fn new(celsius: f32) -> Lm75 {
+		Lm75 {
+			celsius,
+			pointer: REG_TEMPERATURE,
+			config: 0,
+			hysteresis: encode(75.0),
+			over_temperature: encode(80.0),
+		}
+	}
+}
+
+impl I2cDevice for Lm75 {
+	fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		let Some((pointer, data)) = data.split_first() else {
+			return Ok(());
+		};
+		self.pointer = pointer & 0x03;
+		match (self.pointer, data) {
+			(REG_CONFIG, [value, ..]) => self.config = *value,
+			(REG_HYSTERESIS, [hi, lo, ..]) => self.hysteresis = [*hi, *lo],
+			(REG_OVER_TEMPERATURE, [hi, lo, ..]) => self.over_temperature = [*hi, *lo],
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		let register: &[u8] = match self.pointer {
+			REG_TEMPERATURE => &encode(self.celsius),
+			REG_CONFIG => &[self.config],
+			REG_HYSTERESIS => &self.hysteresis,
+			_ => &self.over_temperature,
+		};
+		// Reading past the end of a register just repeats it
+		for (byte, value) in buffer.iter_mut().zip(register.iter().cycle()) {
+			*byte = *value;
+		}
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		format!("LM75 temperature sensor ({} °C)", self.celsius)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
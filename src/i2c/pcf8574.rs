@@ -0,0 +1,133 @@
+//! # Emulated PCF8574 GPIO expander
+//!
+//! The eight pins are wired to a virtual panel of eight LEDs and eight
+//! buttons, which the host draws over the corner of the screen.
+//!
+//! Like the real chip, each pin is quasi-bidirectional. Writing a byte sets
+//! the output latch. An LED is lit when its pin is driven low, because that's
+//! how LEDs are usually wired to a PCF8574. Pressing a button pulls its pin
+//! low, so to read a button the OS must first write a `1` to that pin.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::Mutex;
+
+use super::{Error, I2cDevice};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An emulated PCF8574 GPIO expander.
+///
+/// The pin state lives in [`PANEL`], so the host can draw it.
+pub struct Pcf8574;
+
+/// The state of the virtual panel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Panel {
+	/// The output latch. A `0` bit lights that LED.
+	pub latch: u8,
+	/// Which buttons are held down. A `1` bit pulls that pin low.
+	pub buttons: u8,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The panel, if we have a PCF8574.
+static PANEL: Mutex<Option<Panel>> = Mutex::new(None);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Get the state of the panel, or `None` if there is no PCF8574.
+pub fn panel() -> Option<Panel> {
+	*PANEL.lock().unwrap()
+}
+
+/// Press or release one of the panel buttons.
+pub fn set_button(button: u8, pressed: bool) -> Result<(), String> {
+	if button >= 8 {
+		return Err(format!("there is no button {} (try 0 to 7)", button));
+	}
+	let mut panel = PANEL.lock().unwrap();
+	let panel = panel.as_mut().ok_or("there is no PCF8574 on the I2C bus")?;
+	if pressed {
+		panel.buttons |= 1 << button;
+	} else {
+		panel.buttons &= !(1 << button);
+	}
+	crate::idle::wake();
+	Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Pcf8574 {
+	/// Make the PCF8574 and its panel. There can only be one.
+	pub fn new() -> Result<Pcf8574, String> {
+		let mut panel = PANEL.lock().unwrap();
+		if panel.is_some() {
+			return Err("only one PCF8574 is supported".to_string());
+		}
+		// The pins come up high
+		*panel = Some(Panel {
+			latch: 0xFF,
+			buttons: 0,
+		});
+		Ok(Pcf8574)
+	}
+}
+
+impl I2cDevice for Pcf8574 {
+	/// Each byte written replaces the output latch.
+	fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		if let Some(last) = data.last() {
+			if let Some(panel) = PANEL.lock().unwrap().as_mut() {
+				panel.latch = *last;
+			}
+		}
+		Ok(())
+	}
+
+	/// Every byte read is the state of the pins.
+	fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+		let pins = panel().map_or(0xFF, |p| p.latch & !p.buttons);
+		buffer.fill(pins);
+		Ok(())
+	}
+
+	fn describe(&self) -> String {
+		"PCF8574 GPIO expander (virtual LED/button panel)".to_string()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
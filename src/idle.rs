@@ -0,0 +1,70 @@
+//! # Waking the OS up when it's idle
+//!
+//! When the OS has nothing to do it calls `power_idle`, which waits here until
+//! something happens that it might care about (a key is pressed, a frame is
+//! drawn, a button is pushed and so on), or one tick has passed, whichever
+//! comes first. Anything that could give the OS work to do should call
+//! [`wake`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Has something happened since the OS last went idle?
+static PENDING: Mutex<bool> = Mutex::new(false);
+
+/// Wakes up the OS thread when something happens.
+static WOKEN: Condvar = Condvar::new();
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Something happened that the OS might care about.
+pub fn wake() {
+	*PENDING.lock().unwrap() = true;
+	WOKEN.notify_all();
+}
+
+/// Block the calling thread until something happens, or until `timeout` has
+/// passed.
+///
+/// If something happened since the last call, this returns straight away.
+/// Call this from the OS thread only - never from the GUI thread.
+pub fn wait(timeout: Duration) {
+	let pending = PENDING.lock().unwrap();
+	let (mut pending, _) = WOKEN
+		.wait_timeout_while(pending, timeout, |pending| !*pending)
+		.unwrap();
+	*pending = false;
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,197 @@
+//! # Hardware inventory
+//!
+//! A list of everything the OS will find on the machine we've been asked to
+//! build: the block devices, serial ports, I²C devices, Neotron Bus
+//! peripherals, memory regions and audio. `--list-devices` prints it, and
+//! every normal run logs it at start-up, so you can check a complicated
+//! machine before a long run, and paste it into a bug report.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::PathBuf;
+
+use crate::block::BLOCK_SIZE;
+use crate::{audio, bus, device, i2c, memory, nvram, rom, serial, transfer, video};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The parts of the machine that come from the command line, rather than
+/// from a device registry.
+pub struct Machine {
+	/// The disk image, if we have one
+	pub disk: Option<PathBuf>,
+	/// Whether the OS is told it can't write to the disk image
+	pub disk_read_only: bool,
+	/// The transfer device, if we have one
+	pub transfer: Option<transfer::Spec>,
+	/// How big Region 1 is, if we have one
+	pub ram2_size: Option<usize>,
+	/// Whether the RAM regions have guard pages
+	pub guard_pages: bool,
+	/// Whether the OS runs in a process of its own
+	pub isolate: bool,
+	/// Where the audio goes
+	pub audio_backend: audio::Backend,
+	/// The host audio output device, if not the default
+	pub audio_device: Option<String>,
+	/// A WAV file to use as the audio input, and whether it loops
+	pub audio_input: Option<(PathBuf, bool)>,
+	/// The host audio input device, if not the default
+	pub audio_input_device: Option<String>,
+	/// How much audio to buffer, in milliseconds
+	pub audio_latency_ms: u32,
+	/// The host audio output volume, in percent
+	pub volume: u8,
+	/// The disk, serial ports, I2C devices and Neotron Bus peripherals, as
+	/// `--device` options would give them
+	pub devices: Vec<device::Device>,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Describe everything the OS will see, one line at a time.
+///
+/// The serial ports, I²C devices and Neotron Bus peripherals must already have
+/// been added.
+pub fn describe(machine: &Machine) -> Vec<String> {
+	let mut lines = Vec::new();
+	if machine.isolate {
+		lines.push(String::from(
+			"The OS is isolated, so it sees no serial ports, I2C devices, audio or Neotron Bus peripherals",
+		));
+	}
+
+	lines.push(String::from("Block devices:"));
+	match &machine.disk {
+		Some(path) => {
+			let capacity = match std::fs::metadata(path) {
+				Ok(metadata) => {
+					let bytes = metadata.len();
+					format!(
+						"{} blocks ({})",
+						bytes / BLOCK_SIZE as u64,
+						memory::describe_size(bytes)
+					)
+				}
+				Err(e) => format!("can't read it: {}", e),
+			};
+			lines.push(format!(
+				"  0: File0, {}, {}, hard disk, {}, fixed",
+				path.display(),
+				capacity,
+				if machine.disk_read_only {
+					"read-only"
+				} else {
+					"read-write"
+				}
+			));
+		}
+		None if machine.transfer.is_none() => lines.push(String::from("  (none)")),
+		None => {}
+	}
+	if let Some(spec) = &machine.transfer {
+		lines.push(format!(
+			"  1: Transfer1, {}, files up to {}, {}",
+			spec.dir.display(),
+			memory::describe_size(spec.limit as u64),
+			if spec.read_only {
+				"get only"
+			} else {
+				"get and put"
+			}
+		));
+	}
+
+	lines.extend(serial::inventory());
+	lines.extend(i2c::inventory());
+	lines.extend(bus::inventory());
+
+	lines.push(String::from("Memory regions:"));
+	let ram_kind = if machine.isolate {
+		"RAM, shared with the OS process"
+	} else if machine.guard_pages {
+		"RAM, with guard pages"
+	} else {
+		"RAM"
+	};
+	let sizes: Vec<usize> = std::iter::once(memory::REGION0_SIZE)
+		.chain(machine.ram2_size)
+		.collect();
+	for (region, length) in sizes.iter().enumerate() {
+		lines.push(format!(
+			"  {}: {}, {}",
+			region,
+			memory::describe_size(*length as u64),
+			ram_kind
+		));
+	}
+	lines.push(format!(
+		"  {}: {}, ROM (BIOS identity)",
+		sizes.len(),
+		memory::describe_size(rom::LENGTH as u64)
+	));
+	lines.push(format!(
+		"  Video RAM: {}",
+		memory::describe_size(video::FRAMEBUFFER.size() as u64)
+	));
+	lines.push(format!("NVRAM: {}", nvram::describe()));
+
+	lines.push(String::from("Audio:"));
+	let backend = match machine.audio_backend {
+		audio::Backend::Sdl => "SDL",
+		audio::Backend::Null => "null (plays nothing)",
+	};
+	lines.push(format!(
+		"  Output: {}, {}, {}% volume, {} ms latency",
+		backend,
+		machine.audio_device.as_deref().unwrap_or("default device"),
+		machine.volume,
+		machine.audio_latency_ms
+	));
+	let input = match (&machine.audio_input, &machine.audio_input_device) {
+		(Some((path, true)), _) => format!("{}, looping", path.display()),
+		(Some((path, false)), _) => path.display().to_string(),
+		(None, Some(device)) => device.clone(),
+		(None, None) => String::from("default device"),
+	};
+	lines.push(format!("  Input: {}", input));
+
+	lines.push(String::from("As --device options:"));
+	if machine.devices.is_empty() {
+		lines.push(String::from("  (none)"));
+	}
+	for device in &machine.devices {
+		lines.push(format!("  --device {}", device));
+	}
+
+	lines
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,1392 @@
+//! # Running the OS in a child process
+//!
+//! With `--isolate`, the OS runs in a child process, so if it corrupts its
+//! memory and crashes, it only takes itself down. The window stays up, tells
+//! you what happened, and Prefix + R starts a fresh OS.
+//!
+//! The child is a `fork` of the emulator, so it has the OS library loaded at
+//! the same address we do. Its RAM regions and the video RAM are shared memory
+//! (allocated before the first fork), so pointers into them mean the same
+//! thing in both processes. The child gets its own table of BIOS functions,
+//! which pass each call through a shared mailbox to the OS thread here, and a
+//! socket says when there's a call or a reply waiting. The OS thread runs the
+//! real BIOS function and sends back the result, so pausing, the watchdog,
+//! tracing and so on all work as normal. Any buffers are copied through the
+//! mailbox, as the OS might have them on its own stack.
+//!
+//! The OS can write over the mailbox as easily as anything else, so the
+//! parent doesn't trust it. A call is an opcode and a few numbers, and the
+//! parent checks every one (and every buffer length) before it runs the call.
+//! A call it doesn't understand stops the child, as if it had crashed.
+//!
+//! This first version passes on the time, video, memory, HID, block device,
+//! configuration and power functions. The serial, I²C, audio and Neotron Bus
+//! functions say there's nothing there. It only works on Unix hosts, and not
+//! with `--guard-pages` or `--cpu-throttle`.
+//!
+//! The child process must not take any locks or allocate memory, as it was
+//! forked from a process with other threads running. So it only makes raw
+//! system calls, and never logs anything. If the OS gives it a bad pointer,
+//! it asks the parent to say so.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use neotron_common_bios as common;
+
+use crate::validate;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A BIOS call from the child, with any buffer lengths. The buffers
+/// themselves are in [`Mailbox::data`].
+///
+/// In the mailbox, a call is an [`Opcode`] and some plain numbers (see
+/// [`Call::encode`]), so whatever the OS leaves there, the parent only reads
+/// numbers, and it checks them all before it runs anything.
+#[derive(Debug, Clone)]
+enum Call {
+	TimeClockGet,
+	TimeClockSet(common::Time),
+	TimeTicksGet,
+	TimeTicksPerSecond,
+	ConfigurationGet(usize),
+	ConfigurationSet(usize),
+	VideoIsValidMode(common::video::Mode),
+	VideoModeNeedsVram(common::video::Mode),
+	VideoSetMode(common::video::Mode, *mut u32),
+	VideoGetMode,
+	VideoGetFramebuffer,
+	VideoWaitForLine(u16),
+	VideoGetPalette(u8),
+	VideoSetPalette(u8, common::video::RGBColour),
+	VideoSetWholePalette(usize),
+	MemoryGetRegion(u8),
+	HidGetEvent,
+	HidSetLeds(common::hid::KeyboardLeds),
+	BlockDevGetInfo(u8),
+	BlockDevEject(u8),
+	BlockWrite(u8, common::block_dev::BlockIdx, u8, usize),
+	BlockRead(u8, common::block_dev::BlockIdx, u8, usize),
+	BlockVerify(u8, common::block_dev::BlockIdx, u8, usize),
+	PowerIdle,
+	PowerControl(common::FfiPowerMode),
+	/// The OS gave a BIOS function a bad pointer and length, which the
+	/// parent reports for us
+	BadArgument(Checked, usize, usize, validate::Problem),
+}
+
+/// The BIOS calls the child passes to the parent, as they're numbered in the
+/// mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+	TimeClockGet,
+	TimeClockSet,
+	TimeTicksGet,
+	TimeTicksPerSecond,
+	ConfigurationGet,
+	ConfigurationSet,
+	VideoIsValidMode,
+	VideoModeNeedsVram,
+	VideoSetMode,
+	VideoGetMode,
+	VideoGetFramebuffer,
+	VideoWaitForLine,
+	VideoGetPalette,
+	VideoSetPalette,
+	VideoSetWholePalette,
+	MemoryGetRegion,
+	HidGetEvent,
+	HidSetLeds,
+	BlockDevGetInfo,
+	BlockDevEject,
+	BlockWrite,
+	BlockRead,
+	BlockVerify,
+	PowerIdle,
+	PowerControl,
+	BadArgument,
+}
+
+/// The BIOS functions the child checks the OS's pointers for, so it can tell
+/// the parent which one had a bad pointer without passing it any pointers.
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+enum Checked {
+	ConfigurationGet,
+	ConfigurationSet,
+	VideoSetWholePalette,
+	BlockWrite,
+	BlockRead,
+	BlockVerify,
+}
+
+/// What a BIOS call returned.
+#[derive(Debug, Clone)]
+enum Reply {
+	Nothing,
+	Bool(bool),
+	Time(common::Time),
+	Ticks(common::Ticks),
+	Mode(common::video::Mode),
+	Pointer(*mut u32),
+	Colour(common::FfiOption<common::video::RGBColour>),
+	Region(common::FfiOption<common::MemoryRegion>),
+	HidEvent(common::ApiResult<common::FfiOption<common::hid::HidEvent>>),
+	BlockDevice(common::FfiOption<common::block_dev::DeviceInfo>),
+	Result(common::ApiResult<()>),
+	Size(common::ApiResult<usize>),
+}
+
+/// Where the child and the OS thread pass calls and replies. It lives in
+/// shared memory.
+///
+/// The OS can scribble on it, so the parent never trusts what's in it.
+#[repr(C)]
+struct Mailbox {
+	/// The call, as an [`Opcode`] and its arguments
+	call: [u64; CALL_WORDS],
+	reply: std::mem::MaybeUninit<Reply>,
+	data: [u8; DATA_SIZE],
+}
+
+/// The child process we're running the OS in.
+struct Child {
+	pid: i32,
+	/// Our end of the socket
+	socket: i32,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How big a buffer we can pass in one call - enough for 255 blocks.
+const DATA_SIZE: usize = 256 * 1024;
+
+/// How many numbers a call takes up in the mailbox: an [`Opcode`], then up
+/// to four arguments. Any argument a call doesn't have must be zero.
+pub const CALL_WORDS: usize = 5;
+
+/// The exit code of a child process that panicked.
+const PANIC_EXIT_CODE: i32 = 101;
+
+/// The exit code of a child process that got a reply it didn't expect.
+const PROTOCOL_EXIT_CODE: i32 = 102;
+
+/// How long we give a child process that has hung up on us to finish exiting.
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// Every call, in the order of their opcodes.
+const OPCODES: [Opcode; 26] = [
+	Opcode::TimeClockGet,
+	Opcode::TimeClockSet,
+	Opcode::TimeTicksGet,
+	Opcode::TimeTicksPerSecond,
+	Opcode::ConfigurationGet,
+	Opcode::ConfigurationSet,
+	Opcode::VideoIsValidMode,
+	Opcode::VideoModeNeedsVram,
+	Opcode::VideoSetMode,
+	Opcode::VideoGetMode,
+	Opcode::VideoGetFramebuffer,
+	Opcode::VideoWaitForLine,
+	Opcode::VideoGetPalette,
+	Opcode::VideoSetPalette,
+	Opcode::VideoSetWholePalette,
+	Opcode::MemoryGetRegion,
+	Opcode::HidGetEvent,
+	Opcode::HidSetLeds,
+	Opcode::BlockDevGetInfo,
+	Opcode::BlockDevEject,
+	Opcode::BlockWrite,
+	Opcode::BlockRead,
+	Opcode::BlockVerify,
+	Opcode::PowerIdle,
+	Opcode::PowerControl,
+	Opcode::BadArgument,
+];
+
+/// Every function we check pointers for, in the order of their codes.
+const CHECKED: [Checked; 6] = [
+	Checked::ConfigurationGet,
+	Checked::ConfigurationSet,
+	Checked::VideoSetWholePalette,
+	Checked::BlockWrite,
+	Checked::BlockRead,
+	Checked::BlockVerify,
+];
+
+/// Set if we run the OS in a child process.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set in the child process.
+static IS_CHILD: AtomicBool = AtomicBool::new(false);
+
+/// The shared mailbox.
+static MAILBOX: AtomicPtr<Mailbox> = AtomicPtr::new(std::ptr::null_mut());
+
+/// In the child, its end of the socket.
+static CHILD_SOCKET: AtomicI32 = AtomicI32::new(-1);
+
+/// The child process running the OS, if there is one.
+static CHILD: Mutex<Option<Child>> = Mutex::new(None);
+
+/// The BIOS functions the OS gets in the child process.
+static CHILD_API: common::Api = common::Api {
+	api_version_get,
+	bios_version_get,
+	serial_get_info,
+	serial_configure,
+	serial_write,
+	serial_read,
+	time_clock_get,
+	time_clock_set,
+	time_ticks_get,
+	time_ticks_per_second,
+	configuration_get,
+	configuration_set,
+	video_is_valid_mode,
+	video_mode_needs_vram,
+	video_set_mode,
+	video_get_mode,
+	video_get_framebuffer,
+	video_wait_for_line,
+	video_get_palette,
+	video_set_palette,
+	video_set_whole_palette,
+	memory_get_region,
+	hid_get_event,
+	hid_set_leds,
+	i2c_bus_get_info,
+	i2c_write_read,
+	audio_mixer_channel_get_info,
+	audio_mixer_channel_set_level,
+	audio_output_set_config,
+	audio_output_get_config,
+	audio_output_data,
+	audio_output_get_space,
+	audio_input_set_config,
+	audio_input_get_config,
+	audio_input_data,
+	audio_input_get_count,
+	bus_select,
+	bus_get_info,
+	bus_write_read,
+	bus_exchange,
+	bus_interrupt_status,
+	block_dev_get_info,
+	block_dev_eject,
+	block_write,
+	block_read,
+	block_verify,
+	power_idle,
+	power_control,
+	compare_and_swap_bool,
+};
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Get ready to run the OS in a child process.
+///
+/// Call this before allocating the OS's memory, which must then come from
+/// [`map_shared`].
+pub fn init() -> Result<(), String> {
+	let mailbox = map_shared(std::mem::size_of::<Mailbox>())?;
+	MAILBOX.store(mailbox.cast(), Ordering::Relaxed);
+	ENABLED.store(true, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Do we run the OS in a child process?
+pub fn is_enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Are we the child process?
+pub fn is_child() -> bool {
+	IS_CHILD.load(Ordering::Relaxed)
+}
+
+/// Get some zeroed memory that child processes will share with us.
+pub fn map_shared(length: usize) -> Result<*mut u8, String> {
+	sys::map_shared(length).map_err(|e| format!("can't allocate shared memory: {}", e))
+}
+
+/// Start the OS in a new child process, and run its BIOS calls until it
+/// resets or dies.
+///
+/// Call this from the OS thread only.
+pub fn run() -> ! {
+	let (pid, socket) = match sys::fork(child_main) {
+		Ok(child) => child,
+		Err(e) => {
+			log::error!("Failed to start the OS process: {}", e);
+			crate::shutdown::power_off(crate::shutdown::ExitCode::BiosError);
+		}
+	};
+	log::info!("OS running in process {}", pid);
+	*CHILD.lock().unwrap() = Some(Child { pid, socket });
+	let mailbox = MAILBOX.load(Ordering::Relaxed);
+	let mut garbled = None;
+	while sys::receive(socket) {
+		// Safety: init() set this up. The OS might scribble on it at any
+		// time, so we read the call once, as plain numbers, and check it.
+		let words = unsafe { std::ptr::read_volatile(std::ptr::addr_of!((*mailbox).call)) };
+		let Some(call) = Call::decode(&words) else {
+			garbled = Some(words[0]);
+			break;
+		};
+		let reply = dispatch(call, mailbox);
+		// Safety: as above, and the child doesn't read this until we poke it
+		unsafe {
+			std::ptr::addr_of_mut!((*mailbox).reply).write(std::mem::MaybeUninit::new(reply))
+		};
+		if !sys::send(socket) {
+			break;
+		}
+	}
+	// The socket closing means the child is on its way out, so give it a
+	// moment to finish before we reap it. A child that sent us nonsense
+	// doesn't get a moment.
+	let status = CHILD
+		.lock()
+		.unwrap()
+		.take()
+		.map(|child| match garbled {
+			Some(opcode) => {
+				sys::stop(child.pid, child.socket, Duration::ZERO);
+				format!(
+					"sent a BIOS call we don't understand (opcode {:#x}), so it was stopped",
+					opcode
+				)
+			}
+			None => sys::stop(child.pid, child.socket, EXIT_GRACE),
+		})
+		.unwrap_or_else(|| "went away".to_string());
+	log::error!("The OS process {}", status);
+	crate::crash::save_report(&format!("The OS process {}", status), None);
+	crate::crash::wait_for_reset(format!("The OS process {}", status));
+	crate::reset_os()
+}
+
+/// Stop the child process running the OS, if there is one.
+///
+/// Returns what happened to it.
+pub fn stop_child() -> Option<String> {
+	let child = CHILD.lock().unwrap().take()?;
+	Some(sys::stop(child.pid, child.socket, Duration::ZERO))
+}
+
+/// Run a call from the child, using the real BIOS functions.
+///
+/// Any buffer is copied out of the mailbox first, so the OS can't change it
+/// while we're using it.
+fn dispatch(call: Call, mailbox: *mut Mailbox) -> Reply {
+	let api = crate::api();
+	match call {
+		Call::TimeClockGet => Reply::Time((api.time_clock_get)()),
+		Call::TimeClockSet(time) => {
+			(api.time_clock_set)(time);
+			Reply::Nothing
+		}
+		Call::TimeTicksGet => Reply::Ticks((api.time_ticks_get)()),
+		Call::TimeTicksPerSecond => Reply::Ticks((api.time_ticks_per_second)()),
+		Call::ConfigurationGet(len) => {
+			let mut buffer = vec![0; len];
+			let result = (api.configuration_get)(common::FfiBuffer::new(&mut buffer));
+			copy_back(mailbox, &buffer);
+			Reply::Size(result)
+		}
+		Call::ConfigurationSet(len) => Reply::Result((api.configuration_set)(
+			common::FfiByteSlice::new(&copy_out(mailbox, len)),
+		)),
+		Call::VideoIsValidMode(mode) => Reply::Bool((api.video_is_valid_mode)(mode)),
+		Call::VideoModeNeedsVram(mode) => Reply::Bool((api.video_mode_needs_vram)(mode)),
+		Call::VideoSetMode(mode, vram) => {
+			// We read the VRAM from the GUI, so it had better be memory we
+			// share with the child
+			if !vram.is_null() && !crate::memory::is_ram(vram.cast(), mode.frame_size_bytes()) {
+				log::warn!("OS gave us VRAM outside its RAM regions, which --isolate can't show");
+				return Reply::Result(common::ApiResult::Err(
+					common::Error::UnsupportedConfiguration,
+				));
+			}
+			// Safety: we just checked the VRAM is somewhere sensible
+			Reply::Result(unsafe { (api.video_set_mode)(mode, vram) })
+		}
+		Call::VideoGetMode => Reply::Mode((api.video_get_mode)()),
+		Call::VideoGetFramebuffer => Reply::Pointer((api.video_get_framebuffer)()),
+		Call::VideoWaitForLine(line) => {
+			(api.video_wait_for_line)(line);
+			Reply::Nothing
+		}
+		Call::VideoGetPalette(idx) => Reply::Colour((api.video_get_palette)(idx)),
+		Call::VideoSetPalette(idx, colour) => {
+			(api.video_set_palette)(idx, colour);
+			Reply::Nothing
+		}
+		Call::VideoSetWholePalette(len) => {
+			let colours: Vec<common::video::RGBColour> = copy_out(mailbox, len * 4)
+				.chunks_exact(4)
+				.map(|c| {
+					common::video::RGBColour::from_packed(u32::from_ne_bytes([
+						c[0], c[1], c[2], c[3],
+					]))
+				})
+				.collect();
+			// Safety: the pointer and length come from a real Vec
+			unsafe { (api.video_set_whole_palette)(colours.as_ptr(), colours.len()) };
+			Reply::Nothing
+		}
+		Call::MemoryGetRegion(region) => Reply::Region((api.memory_get_region)(region)),
+		Call::HidGetEvent => Reply::HidEvent((api.hid_get_event)()),
+		Call::HidSetLeds(leds) => Reply::Result((api.hid_set_leds)(leds)),
+		Call::BlockDevGetInfo(device) => Reply::BlockDevice((api.block_dev_get_info)(device)),
+		Call::BlockDevEject(device) => Reply::Result((api.block_dev_eject)(device)),
+		Call::BlockWrite(device, block, count, len) => Reply::Result((api.block_write)(
+			device,
+			block,
+			count,
+			common::FfiByteSlice::new(&copy_out(mailbox, len)),
+		)),
+		Call::BlockRead(device, block, count, len) => {
+			let mut buffer = vec![0; len];
+			let result =
+				(api.block_read)(device, block, count, common::FfiBuffer::new(&mut buffer));
+			copy_back(mailbox, &buffer);
+			Reply::Result(result)
+		}
+		Call::BlockVerify(device, block, count, len) => Reply::Result((api.block_verify)(
+			device,
+			block,
+			count,
+			common::FfiByteSlice::new(&copy_out(mailbox, len)),
+		)),
+		Call::PowerIdle => {
+			(api.power_idle)();
+			Reply::Nothing
+		}
+		Call::PowerControl(mode) => (api.power_control)(mode),
+		Call::BadArgument(function, address, len, problem) => {
+			let (name, what) = function.describe();
+			validate::report_problem(name, what, address, len, problem);
+			Reply::Nothing
+		}
+	}
+}
+
+/// Copy the start of the mailbox's buffer out of shared memory.
+///
+/// [`Call::decode`] makes sure `len` fits.
+fn copy_out(mailbox: *mut Mailbox, len: usize) -> Vec<u8> {
+	assert!(len <= DATA_SIZE);
+	let mut buffer = vec![0; len];
+	// Safety: init() set up the mailbox, and we checked the length. The
+	// child might be changing the bytes as we copy them, but they're only
+	// bytes.
+	unsafe {
+		std::ptr::copy_nonoverlapping(
+			std::ptr::addr_of!((*mailbox).data).cast::<u8>(),
+			buffer.as_mut_ptr(),
+			len,
+		);
+	}
+	buffer
+}
+
+/// Copy a buffer into the start of the mailbox's buffer, for the child.
+fn copy_back(mailbox: *mut Mailbox, buffer: &[u8]) {
+	assert!(buffer.len() <= DATA_SIZE);
+	// Safety: as for `copy_out`
+	unsafe {
+		std::ptr::copy_nonoverlapping(
+			buffer.as_ptr(),
+			std::ptr::addr_of_mut!((*mailbox).data).cast::<u8>(),
+			buffer.len(),
+		);
+	}
+}
+
+/// Read the length of a buffer of `size`-byte items from the mailbox. Returns
+/// `None` if the buffer wouldn't fit in the mailbox.
+fn buffer_len(word: u64, size: usize) -> Option<usize> {
+	let len = usize::try_from(word).ok()?;
+	len.checked_mul(size).filter(|bytes| *bytes <= DATA_SIZE)?;
+	Some(len)
+}
+
+/// Read a video mode from the mailbox.
+fn mode(word: u64) -> Option<common::video::Mode> {
+	common::video::Mode::try_from_u8(word.try_into().ok()?)
+}
+
+/// Where the child process starts.
+fn child_main(socket: i32) -> ! {
+	IS_CHILD.store(true, Ordering::Relaxed);
+	CHILD_SOCKET.store(socket, Ordering::Relaxed);
+	let main = *crate::OS_MAIN.get().expect("OS to have been loaded");
+	// Safety: the OS was built to be called like this
+	unsafe { main(&CHILD_API) }
+}
+
+/// A child process panicked. Let the parent know.
+pub fn child_panicked() -> ! {
+	sys::exit(PANIC_EXIT_CODE)
+}
+
+/// In the child, pass a call to the OS thread in the parent and wait for the
+/// reply. Any buffer must already be in the mailbox.
+///
+/// If the parent has gone, so do we.
+fn call(call: Call) -> Reply {
+	let socket = CHILD_SOCKET.load(Ordering::Relaxed);
+	// Safety: only one of us touches the mailbox at a time, and it's our turn
+	let mailbox = unsafe { &mut *MAILBOX.load(Ordering::Relaxed) };
+	mailbox.call = call.encode();
+	if !sys::send(socket) || !sys::receive(socket) {
+		sys::exit(0);
+	}
+	// Safety: the parent wrote a reply before it poked the socket
+	unsafe { mailbox.reply.assume_init_read() }
+}
+
+/// In the child, put `words` in the mailbox as a call, fill its buffer with
+/// `fill`, and pass it to the parent, as an OS that scribbled over the mailbox
+/// might. The parent should stop us rather than run it, so this only returns
+/// if the parent took the call.
+///
+/// This lets the tests check the parent survives. It does nothing in the
+/// parent.
+pub fn scribble(words: [u64; CALL_WORDS], fill: u8) {
+	if !is_child() {
+		return;
+	}
+	let socket = CHILD_SOCKET.load(Ordering::Relaxed);
+	// Safety: as for `call`
+	let mailbox = unsafe { &mut *MAILBOX.load(Ordering::Relaxed) };
+	mailbox.call = words;
+	mailbox.data.fill(fill);
+	if !sys::send(socket) || !sys::receive(socket) {
+		sys::exit(0);
+	}
+}
+
+/// In the child, get the mailbox's buffer.
+fn data() -> &'static mut [u8; DATA_SIZE] {
+	// Safety: only one of us touches the mailbox at a time, and it's our turn
+	unsafe { &mut (*MAILBOX.load(Ordering::Relaxed)).data }
+}
+
+/// In the child, we got a reply that doesn't match the call.
+fn bad_reply() -> ! {
+	sys::exit(PROTOCOL_EXIT_CODE)
+}
+
+// -----------------------------------------------------------------------------
+// BIOS functions in the child
+// -----------------------------------------------------------------------------
+
+extern "C" fn api_version_get() -> common::Version {
+	common::API_VERSION
+}
+
+extern "C" fn bios_version_get() -> common::FfiString<'static> {
+	common::FfiString::new("Neotron Desktop BIOS\0")
+}
+
+extern "C" fn serial_get_info(_device: u8) -> common::FfiOption<common::serial::DeviceInfo> {
+	common::FfiOption::None
+}
+
+extern "C" fn serial_configure(
+	_device: u8,
+	_config: common::serial::Config,
+) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn serial_write(
+	_device: u8,
+	_data: common::FfiByteSlice,
+	_timeout: common::FfiOption<common::Timeout>,
+) -> common::ApiResult<usize> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn serial_read(
+	_device: u8,
+	_data: common::FfiBuffer,
+	_timeout: common::FfiOption<common::Timeout>,
+) -> common::ApiResult<usize> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn time_clock_get() -> common::Time {
+	match call(Call::TimeClockGet) {
+		Reply::Time(time) => time,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn time_clock_set(time: common::Time) {
+	call(Call::TimeClockSet(time));
+}
+
+extern "C" fn time_ticks_get() -> common::Ticks {
+	match call(Call::TimeTicksGet) {
+		Reply::Ticks(ticks) => ticks,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn time_ticks_per_second() -> common::Ticks {
+	match call(Call::TimeTicksPerSecond) {
+		Reply::Ticks(ticks) => ticks,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn configuration_get(mut buffer: common::FfiBuffer) -> common::ApiResult<usize> {
+	let buffer = match checked_buffer(Checked::ConfigurationGet, &mut buffer) {
+		Ok(buffer) => buffer,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let len = buffer.len().min(DATA_SIZE);
+	let result = match call(Call::ConfigurationGet(len)) {
+		Reply::Size(result) => result,
+		_ => bad_reply(),
+	};
+	buffer[..len].copy_from_slice(&data()[..len]);
+	result
+}
+
+extern "C" fn configuration_set(buffer: common::FfiByteSlice) -> common::ApiResult<()> {
+	let buffer = match checked_bytes(Checked::ConfigurationSet, &buffer) {
+		Ok(buffer) => buffer,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let Some(len) = copy_in(buffer) else {
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	};
+	match call(Call::ConfigurationSet(len)) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn video_is_valid_mode(mode: common::video::Mode) -> bool {
+	match call(Call::VideoIsValidMode(mode)) {
+		Reply::Bool(valid) => valid,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn video_mode_needs_vram(mode: common::video::Mode) -> bool {
+	match call(Call::VideoModeNeedsVram(mode)) {
+		Reply::Bool(needs_vram) => needs_vram,
+		_ => bad_reply(),
+	}
+}
+
+unsafe extern "C" fn video_set_mode(
+	mode: common::video::Mode,
+	vram: *mut u32,
+) -> common::ApiResult<()> {
+	match call(Call::VideoSetMode(mode, vram)) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn video_get_mode() -> common::video::Mode {
+	match call(Call::VideoGetMode) {
+		Reply::Mode(mode) => mode,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn video_get_framebuffer() -> *mut u32 {
+	match call(Call::VideoGetFramebuffer) {
+		Reply::Pointer(framebuffer) => framebuffer,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn video_wait_for_line(line: u16) {
+	call(Call::VideoWaitForLine(line));
+}
+
+extern "C" fn video_get_palette(idx: u8) -> common::FfiOption<common::video::RGBColour> {
+	match call(Call::VideoGetPalette(idx)) {
+		Reply::Colour(colour) => colour,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn video_set_palette(idx: u8, colour: common::video::RGBColour) {
+	call(Call::VideoSetPalette(idx, colour));
+}
+
+unsafe extern "C" fn video_set_whole_palette(
+	start: *const common::video::RGBColour,
+	length: usize,
+) {
+	let colours = match validate::check_palette(start, length) {
+		Ok(colours) => colours,
+		Err(problem) => {
+			refuse(
+				Checked::VideoSetWholePalette,
+				start as usize,
+				length,
+				problem,
+			);
+			return;
+		}
+	};
+	if colours.len() < length {
+		refuse(
+			Checked::VideoSetWholePalette,
+			start as usize,
+			length,
+			validate::Problem::LongPalette,
+		);
+	}
+	for (chunk, colour) in data().chunks_exact_mut(4).zip(colours) {
+		chunk.copy_from_slice(&colour.as_packed().to_ne_bytes());
+	}
+	call(Call::VideoSetWholePalette(colours.len()));
+}
+
+extern "C" fn memory_get_region(region: u8) -> common::FfiOption<common::MemoryRegion> {
+	match call(Call::MemoryGetRegion(region)) {
+		Reply::Region(region) => region,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn hid_get_event() -> common::ApiResult<common::FfiOption<common::hid::HidEvent>> {
+	match call(Call::HidGetEvent) {
+		Reply::HidEvent(event) => event,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn hid_set_leds(leds: common::hid::KeyboardLeds) -> common::ApiResult<()> {
+	match call(Call::HidSetLeds(leds)) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn i2c_bus_get_info(_bus: u8) -> common::FfiOption<common::i2c::BusInfo> {
+	common::FfiOption::None
+}
+
+extern "C" fn i2c_write_read(
+	_bus: u8,
+	_address: u8,
+	_tx: common::FfiByteSlice,
+	_tx2: common::FfiByteSlice,
+	_rx: common::FfiBuffer,
+) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_mixer_channel_get_info(
+	_mixer: u8,
+) -> common::FfiOption<common::audio::MixerChannelInfo> {
+	common::FfiOption::None
+}
+
+extern "C" fn audio_mixer_channel_set_level(_mixer: u8, _level: u8) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_output_set_config(_config: common::audio::Config) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_output_get_config() -> common::ApiResult<common::audio::Config> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+unsafe extern "C" fn audio_output_data(_samples: common::FfiByteSlice) -> common::ApiResult<usize> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_output_get_space() -> common::ApiResult<usize> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_input_set_config(_config: common::audio::Config) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_input_get_config() -> common::ApiResult<common::audio::Config> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+unsafe extern "C" fn audio_input_data(_samples: common::FfiBuffer) -> common::ApiResult<usize> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn audio_input_get_count() -> common::ApiResult<usize> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn bus_select(_peripheral: common::FfiOption<u8>) {}
+
+extern "C" fn bus_get_info(_peripheral: u8) -> common::FfiOption<common::bus::PeripheralInfo> {
+	common::FfiOption::None
+}
+
+extern "C" fn bus_write_read(
+	_tx: common::FfiByteSlice,
+	_tx2: common::FfiByteSlice,
+	_rx: common::FfiBuffer,
+) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn bus_exchange(_buffer: common::FfiBuffer) -> common::ApiResult<()> {
+	common::ApiResult::Err(common::Error::Unimplemented)
+}
+
+extern "C" fn bus_interrupt_status() -> u32 {
+	0
+}
+
+extern "C" fn block_dev_get_info(device: u8) -> common::FfiOption<common::block_dev::DeviceInfo> {
+	match call(Call::BlockDevGetInfo(device)) {
+		Reply::BlockDevice(info) => info,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn block_dev_eject(device: u8) -> common::ApiResult<()> {
+	match call(Call::BlockDevEject(device)) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn block_write(
+	device: u8,
+	block: common::block_dev::BlockIdx,
+	count: u8,
+	data: common::FfiByteSlice,
+) -> common::ApiResult<()> {
+	let data = match checked_bytes(Checked::BlockWrite, &data) {
+		Ok(data) => data,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let Some(len) = copy_in(data) else {
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	};
+	match call(Call::BlockWrite(device, block, count, len)) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn block_read(
+	device: u8,
+	block: common::block_dev::BlockIdx,
+	count: u8,
+	mut buffer: common::FfiBuffer,
+) -> common::ApiResult<()> {
+	let buffer = match checked_buffer(Checked::BlockRead, &mut buffer) {
+		Ok(buffer) => buffer,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	if buffer.len() > DATA_SIZE {
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	}
+	let result = match call(Call::BlockRead(device, block, count, buffer.len())) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	};
+	buffer.copy_from_slice(&self::data()[..buffer.len()]);
+	result
+}
+
+extern "C" fn block_verify(
+	device: u8,
+	block: common::block_dev::BlockIdx,
+	count: u8,
+	data: common::FfiByteSlice,
+) -> common::ApiResult<()> {
+	let data = match checked_bytes(Checked::BlockVerify, &data) {
+		Ok(data) => data,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	let Some(len) = copy_in(data) else {
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	};
+	match call(Call::BlockVerify(device, block, count, len)) {
+		Reply::Result(result) => result,
+		_ => bad_reply(),
+	}
+}
+
+extern "C" fn power_idle() {
+	call(Call::PowerIdle);
+}
+
+extern "C" fn power_control(mode: common::FfiPowerMode) -> ! {
+	call(Call::PowerControl(mode));
+	// The parent never replies to this - it either exits or kills us
+	bad_reply()
+}
+
+extern "C" fn compare_and_swap_bool(
+	item: &std::sync::atomic::AtomicBool,
+	old_value: bool,
+	new_value: bool,
+) -> bool {
+	item.compare_exchange(old_value, new_value, Ordering::Relaxed, Ordering::Relaxed)
+		.is_ok()
+}
+
+/// In the child, check a byte slice the OS gave `function`, and turn it into a
+/// Rust slice. We can't log here, so the parent reports any problem.
+fn checked_bytes<'a>(
+	function: Checked,
+	slice: &'a common::FfiByteSlice,
+) -> Result<&'a [u8], common::Error> {
+	if let Err(problem) = validate::check(slice.data, slice.data_len) {
+		refuse(function, slice.data as usize, slice.data_len, problem);
+		return Err(common::Error::DeviceError);
+	}
+	if slice.data_len == 0 {
+		return Ok(&[]);
+	}
+	// Safety: we checked the pointer isn't null and the length is possible,
+	// and the OS promises the rest
+	Ok(unsafe { std::slice::from_raw_parts(slice.data, slice.data_len) })
+}
+
+/// In the child, check a buffer the OS gave `function` to write into, and turn
+/// it into a Rust slice. We can't log here, so the parent reports any problem.
+fn checked_buffer<'a>(
+	function: Checked,
+	buffer: &'a mut common::FfiBuffer,
+) -> Result<&'a mut [u8], common::Error> {
+	if let Err(problem) = validate::check(buffer.data, buffer.data_len) {
+		refuse(function, buffer.data as usize, buffer.data_len, problem);
+		return Err(common::Error::DeviceError);
+	}
+	if buffer.data_len == 0 {
+		return Ok(&mut []);
+	}
+	// Safety: as for `checked_bytes`
+	Ok(unsafe { std::slice::from_raw_parts_mut(buffer.data, buffer.data_len) })
+}
+
+/// In the child, ask the parent to report a bad argument.
+fn refuse(function: Checked, address: usize, len: usize, problem: validate::Problem) {
+	call(Call::BadArgument(function, address, len, problem));
+}
+
+/// In the child, copy a buffer into the mailbox. Returns its length, or
+/// `None` if it doesn't fit.
+fn copy_in(buffer: &[u8]) -> Option<usize> {
+	data().get_mut(..buffer.len())?.copy_from_slice(buffer);
+	Some(buffer.len())
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Call {
+	/// Write this call as it goes in the mailbox.
+	fn encode(&self) -> [u64; CALL_WORDS] {
+		let (opcode, args): (Opcode, [u64; CALL_WORDS - 1]) = match self {
+			Call::TimeClockGet => (Opcode::TimeClockGet, [0; 4]),
+			Call::TimeClockSet(time) => (
+				Opcode::TimeClockSet,
+				[u64::from(time.secs), u64::from(time.nsecs), 0, 0],
+			),
+			Call::TimeTicksGet => (Opcode::TimeTicksGet, [0; 4]),
+			Call::TimeTicksPerSecond => (Opcode::TimeTicksPerSecond, [0; 4]),
+			Call::ConfigurationGet(len) => (Opcode::ConfigurationGet, [*len as u64, 0, 0, 0]),
+			Call::ConfigurationSet(len) => (Opcode::ConfigurationSet, [*len as u64, 0, 0, 0]),
+			Call::VideoIsValidMode(mode) => {
+				(Opcode::VideoIsValidMode, [u64::from(mode.as_u8()), 0, 0, 0])
+			}
+			Call::VideoModeNeedsVram(mode) => (
+				Opcode::VideoModeNeedsVram,
+				[u64::from(mode.as_u8()), 0, 0, 0],
+			),
+			Call::VideoSetMode(mode, vram) => (
+				Opcode::VideoSetMode,
+				[u64::from(mode.as_u8()), *vram as usize as u64, 0, 0],
+			),
+			Call::VideoGetMode => (Opcode::VideoGetMode, [0; 4]),
+			Call::VideoGetFramebuffer => (Opcode::VideoGetFramebuffer, [0; 4]),
+			Call::VideoWaitForLine(line) => (Opcode::VideoWaitForLine, [u64::from(*line), 0, 0, 0]),
+			Call::VideoGetPalette(idx) => (Opcode::VideoGetPalette, [u64::from(*idx), 0, 0, 0]),
+			Call::VideoSetPalette(idx, colour) => (
+				Opcode::VideoSetPalette,
+				[u64::from(*idx), u64::from(colour.as_packed()), 0, 0],
+			),
+			Call::VideoSetWholePalette(len) => {
+				(Opcode::VideoSetWholePalette, [*len as u64, 0, 0, 0])
+			}
+			Call::MemoryGetRegion(region) => {
+				(Opcode::MemoryGetRegion, [u64::from(*region), 0, 0, 0])
+			}
+			Call::HidGetEvent => (Opcode::HidGetEvent, [0; 4]),
+			Call::HidSetLeds(leds) => {
+				let bits = u64::from(leds.is_caps_lock_on())
+					| u64::from(leds.is_scroll_lock_on()) << 1
+					| u64::from(leds.is_num_lock_on()) << 2;
+				(Opcode::HidSetLeds, [bits, 0, 0, 0])
+			}
+			Call::BlockDevGetInfo(device) => {
+				(Opcode::BlockDevGetInfo, [u64::from(*device), 0, 0, 0])
+			}
+			Call::BlockDevEject(device) => (Opcode::BlockDevEject, [u64::from(*device), 0, 0, 0]),
+			Call::BlockWrite(device, block, count, len) => (
+				Opcode::BlockWrite,
+				[u64::from(*device), block.0, u64::from(*count), *len as u64],
+			),
+			Call::BlockRead(device, block, count, len) => (
+				Opcode::BlockRead,
+				[u64::from(*device), block.0, u64::from(*count), *len as u64],
+			),
+			Call::BlockVerify(device, block, count, len) => (
+				Opcode::BlockVerify,
+				[u64::from(*device), block.0, u64::from(*count), *len as u64],
+			),
+			Call::PowerIdle => (Opcode::PowerIdle, [0; 4]),
+			Call::PowerControl(mode) => (Opcode::PowerControl, [u64::from(mode.0), 0, 0, 0]),
+			Call::BadArgument(function, address, len, problem) => (
+				Opcode::BadArgument,
+				[
+					*function as u64,
+					*address as u64,
+					*len as u64,
+					u64::from(problem.code()),
+				],
+			),
+		};
+		let [a, b, c, d] = args;
+		[opcode as u64, a, b, c, d]
+	}
+
+	/// Read a call from the mailbox, checking every number in it.
+	///
+	/// Returns `None` unless it's a call the child could have made: a known
+	/// opcode, arguments that fit their types, buffers that fit in the
+	/// mailbox, and zero for any arguments the call doesn't have.
+	fn decode(words: &[u64; CALL_WORDS]) -> Option<Call> {
+		let opcode = Opcode::from_code(u8::try_from(words[0]).ok()?)?;
+		let [_, a, b, c, d] = *words;
+		let call = match opcode {
+			Opcode::TimeClockGet => Call::TimeClockGet,
+			Opcode::TimeClockSet => Call::TimeClockSet(common::Time {
+				secs: a.try_into().ok()?,
+				nsecs: b.try_into().ok()?,
+			}),
+			Opcode::TimeTicksGet => Call::TimeTicksGet,
+			Opcode::TimeTicksPerSecond => Call::TimeTicksPerSecond,
+			Opcode::ConfigurationGet => Call::ConfigurationGet(buffer_len(a, 1)?),
+			Opcode::ConfigurationSet => Call::ConfigurationSet(buffer_len(a, 1)?),
+			Opcode::VideoIsValidMode => Call::VideoIsValidMode(mode(a)?),
+			Opcode::VideoModeNeedsVram => Call::VideoModeNeedsVram(mode(a)?),
+			Opcode::VideoSetMode => {
+				Call::VideoSetMode(mode(a)?, usize::try_from(b).ok()? as *mut u32)
+			}
+			Opcode::VideoGetMode => Call::VideoGetMode,
+			Opcode::VideoGetFramebuffer => Call::VideoGetFramebuffer,
+			Opcode::VideoWaitForLine => Call::VideoWaitForLine(a.try_into().ok()?),
+			Opcode::VideoGetPalette => Call::VideoGetPalette(a.try_into().ok()?),
+			Opcode::VideoSetPalette => Call::VideoSetPalette(
+				a.try_into().ok()?,
+				common::video::RGBColour::from_packed(b.try_into().ok()?),
+			),
+			Opcode::VideoSetWholePalette => Call::VideoSetWholePalette(buffer_len(a, 4)?),
+			Opcode::MemoryGetRegion => Call::MemoryGetRegion(a.try_into().ok()?),
+			Opcode::HidGetEvent => Call::HidGetEvent,
+			Opcode::HidSetLeds => {
+				let mut leds = common::hid::KeyboardLeds::new();
+				if a & 1 != 0 {
+					leds = leds.set_caps_lock_on();
+				}
+				if a & 2 != 0 {
+					leds = leds.set_scroll_lock_on();
+				}
+				if a & 4 != 0 {
+					leds = leds.set_num_lock_on();
+				}
+				Call::HidSetLeds(leds)
+			}
+			Opcode::BlockDevGetInfo => Call::BlockDevGetInfo(a.try_into().ok()?),
+			Opcode::BlockDevEject => Call::BlockDevEject(a.try_into().ok()?),
+			Opcode::BlockWrite => Call::BlockWrite(
+				a.try_into().ok()?,
+				common::block_dev::BlockIdx(b),
+				c.try_into().ok()?,
+				buffer_len(d, 1)?,
+			),
+			Opcode::BlockRead => Call::BlockRead(
+				a.try_into().ok()?,
+				common::block_dev::BlockIdx(b),
+				c.try_into().ok()?,
+				buffer_len(d, 1)?,
+			),
+			Opcode::BlockVerify => Call::BlockVerify(
+				a.try_into().ok()?,
+				common::block_dev::BlockIdx(b),
+				c.try_into().ok()?,
+				buffer_len(d, 1)?,
+			),
+			Opcode::PowerIdle => Call::PowerIdle,
+			Opcode::PowerControl => Call::PowerControl(common::FfiPowerMode(a.try_into().ok()?)),
+			Opcode::BadArgument => Call::BadArgument(
+				Checked::from_code(a.try_into().ok()?)?,
+				b.try_into().ok()?,
+				c.try_into().ok()?,
+				validate::Problem::from_code(d.try_into().ok()?)?,
+			),
+		};
+		// Writing it back out catches any bits we skipped over
+		(call.encode() == *words).then_some(call)
+	}
+}
+
+impl Opcode {
+	/// The call with this number, if there is one.
+	fn from_code(code: u8) -> Option<Opcode> {
+		OPCODES.get(usize::from(code)).copied()
+	}
+}
+
+impl Checked {
+	/// The function with this number, if there is one.
+	fn from_code(code: u8) -> Option<Checked> {
+		CHECKED.get(usize::from(code)).copied()
+	}
+
+	/// The function's name, and the argument we checked.
+	fn describe(self) -> (&'static str, &'static str) {
+		match self {
+			Checked::ConfigurationGet => ("configuration_get", "its buffer"),
+			Checked::ConfigurationSet => ("configuration_set", "its buffer"),
+			Checked::VideoSetWholePalette => ("video_set_whole_palette", "its palette"),
+			Checked::BlockWrite => ("block_write", "its buffer"),
+			Checked::BlockRead => ("block_read", "its buffer"),
+			Checked::BlockVerify => ("block_verify", "its buffer"),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Host specific code
+// -----------------------------------------------------------------------------
+
+#[cfg(unix)]
+mod sys {
+	use std::time::{Duration, Instant};
+
+	/// Map some zeroed memory that is shared with child processes.
+	pub fn map_shared(length: usize) -> std::io::Result<*mut u8> {
+		// Safety: we're asking for fresh memory, not changing anything
+		let ptr = unsafe {
+			libc::mmap(
+				std::ptr::null_mut(),
+				length,
+				libc::PROT_READ | libc::PROT_WRITE,
+				libc::MAP_SHARED | libc::MAP_ANON,
+				-1,
+				0,
+			)
+		};
+		if ptr == libc::MAP_FAILED {
+			Err(std::io::Error::last_os_error())
+		} else {
+			Ok(ptr.cast())
+		}
+	}
+
+	/// Start a child process running `child`, joined to us by a socket.
+	///
+	/// Returns the child's process ID and our end of the socket.
+	pub fn fork(child: fn(i32) -> !) -> std::io::Result<(i32, i32)> {
+		let mut sockets = [0; 2];
+		// Safety: we give it somewhere to put two file descriptors
+		if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, sockets.as_mut_ptr()) }
+			!= 0
+		{
+			return Err(std::io::Error::last_os_error());
+		}
+		let [ours, theirs] = sockets;
+		// Safety: the child only makes system calls, so it doesn't matter
+		// what the other threads were doing
+		match unsafe { libc::fork() } {
+			-1 => {
+				let e = std::io::Error::last_os_error();
+				unsafe {
+					libc::close(ours);
+					libc::close(theirs);
+				}
+				Err(e)
+			}
+			0 => {
+				unsafe {
+					libc::close(ours);
+				}
+				#[cfg(any(target_os = "linux", target_os = "android"))]
+				unsafe {
+					// Don't outlive the emulator
+					libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+				}
+				child(theirs)
+			}
+			pid => {
+				unsafe {
+					libc::close(theirs);
+				}
+				Ok((pid, ours))
+			}
+		}
+	}
+
+	/// Poke the other end of the socket. Returns `false` if it has gone.
+	pub fn send(socket: i32) -> bool {
+		let byte = 1u8;
+		loop {
+			// Safety: we're writing one byte from a local
+			match unsafe { libc::write(socket, (&byte as *const u8).cast(), 1) } {
+				1 => return true,
+				-1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {
+				}
+				_ => return false,
+			}
+		}
+	}
+
+	/// Wait for the other end of the socket to poke us. Returns `false` if it
+	/// has gone.
+	pub fn receive(socket: i32) -> bool {
+		let mut byte = 0u8;
+		loop {
+			// Safety: we're reading one byte into a local
+			match unsafe { libc::read(socket, (&mut byte as *mut u8).cast(), 1) } {
+				1 => return true,
+				-1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {
+				}
+				_ => return false,
+			}
+		}
+	}
+
+	/// Kill a child process (if it isn't already dead), and say how it ended.
+	pub fn stop(pid: i32, socket: i32, grace: Duration) -> String {
+		let mut status = 0;
+		let deadline = Instant::now() + grace;
+		// Safety: these are our child and our socket
+		unsafe {
+			libc::close(socket);
+			while libc::waitpid(pid, &mut status, libc::WNOHANG) == 0 {
+				if Instant::now() >= deadline {
+					libc::kill(pid, libc::SIGKILL);
+					libc::waitpid(pid, &mut status, 0);
+					return "was stopped".to_string();
+				}
+				std::thread::sleep(Duration::from_millis(10));
+			}
+		}
+		if libc::WIFSIGNALED(status) {
+			let signal = libc::WTERMSIG(status);
+			let name = match signal {
+				libc::SIGSEGV => " (SIGSEGV)",
+				libc::SIGBUS => " (SIGBUS)",
+				libc::SIGILL => " (SIGILL)",
+				libc::SIGFPE => " (SIGFPE)",
+				libc::SIGABRT => " (SIGABRT)",
+				libc::SIGKILL => " (SIGKILL)",
+				_ => "",
+			};
+			format!("was killed by signal {}{}", signal, name)
+		} else if libc::WEXITSTATUS(status) == super::PANIC_EXIT_CODE {
+			"panicked".to_string()
+		} else {
+			format!("exited with code {}", libc::WEXITSTATUS(status))
+		}
+	}
+
+	/// Leave the child process, without running any Rust clean-up.
+	pub fn exit(code: i32) -> ! {
+		// Safety: nothing in the child needs tidying up
+		unsafe { libc::_exit(code) }
+	}
+}
+
+#[cfg(not(unix))]
+mod sys {
+	use std::time::Duration;
+
+	pub fn map_shared(_length: usize) -> std::io::Result<*mut u8> {
+		Err(std::io::Error::new(
+			std::io::ErrorKind::Unsupported,
+			"--isolate only works on Unix hosts",
+		))
+	}
+
+	pub fn fork(_child: fn(i32) -> !) -> std::io::Result<(i32, i32)> {
+		unreachable!("map_shared stops us getting this far")
+	}
+
+	pub fn send(_socket: i32) -> bool {
+		false
+	}
+
+	pub fn receive(_socket: i32) -> bool {
+		false
+	}
+
+	pub fn stop(_pid: i32, _socket: i32, _grace: Duration) -> String {
+		String::new()
+	}
+
+	pub fn exit(code: i32) -> ! {
+		std::process::exit(code)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,366 @@
+//! # A little JSON
+//!
+//! Just enough JSON for the `--remote` protocol: we parse one message at a
+//! time into a [`Value`], and a [`Value`] prints as compact JSON on one line.
+//! Numbers are kept as `f64`, which is plenty for drive numbers and the like.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Any JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	/// `null`
+	Null,
+	/// `true` or `false`
+	Bool(bool),
+	/// A number
+	Number(f64),
+	/// A string
+	String(String),
+	/// An array
+	Array(Vec<Value>),
+	/// An object, with its members in the order they were given
+	Object(Vec<(String, Value)>),
+}
+
+/// Where we've got to in the text we're parsing.
+struct Parser<'a> {
+	text: &'a str,
+	pos: usize,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse some JSON text, which must be exactly one value.
+pub fn parse(text: &str) -> Result<Value, String> {
+	let mut parser = Parser { text, pos: 0 };
+	let value = parser.value()?;
+	parser.skip_space();
+	if parser.pos != text.len() {
+		return Err(parser.error("unexpected text after the value"));
+	}
+	Ok(value)
+}
+
+/// Quote a string for JSON.
+pub fn quote(text: &str) -> String {
+	let mut quoted = String::with_capacity(text.len() + 2);
+	quoted.push('"');
+	for ch in text.chars() {
+		match ch {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			ch if ch.is_control() => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+			ch => quoted.push(ch),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Value {
+	/// An object, from `(key, value)` pairs.
+	pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+		Value::Object(
+			members
+				.into_iter()
+				.map(|(key, value)| (key.to_string(), value))
+				.collect(),
+		)
+	}
+
+	/// A member of an object, if this is an object and it has one.
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		match self {
+			Value::Object(members) => members
+				.iter()
+				.find(|(name, _)| name == key)
+				.map(|(_, value)| value),
+			_ => None,
+		}
+	}
+
+	/// The string, if this is a string.
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::String(text) => Some(text),
+			_ => None,
+		}
+	}
+
+	/// The number, if this is a whole number that isn't negative.
+	pub fn as_u64(&self) -> Option<u64> {
+		match self {
+			Value::Number(number) if number.fract() == 0.0 && *number >= 0.0 => {
+				Some(*number as u64)
+			}
+			_ => None,
+		}
+	}
+
+	/// The items, if this is an array.
+	pub fn as_array(&self) -> Option<&[Value]> {
+		match self {
+			Value::Array(items) => Some(items),
+			_ => None,
+		}
+	}
+}
+
+impl From<&str> for Value {
+	fn from(text: &str) -> Value {
+		Value::String(text.to_string())
+	}
+}
+
+impl From<String> for Value {
+	fn from(text: String) -> Value {
+		Value::String(text)
+	}
+}
+
+impl From<bool> for Value {
+	fn from(flag: bool) -> Value {
+		Value::Bool(flag)
+	}
+}
+
+impl From<u64> for Value {
+	fn from(number: u64) -> Value {
+		Value::Number(number as f64)
+	}
+}
+
+impl std::fmt::Display for Value {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Value::Null => write!(f, "null"),
+			Value::Bool(flag) => write!(f, "{}", flag),
+			Value::Number(number) if number.is_finite() => write!(f, "{}", number),
+			// JSON can't say infinity or NaN
+			Value::Number(_) => write!(f, "null"),
+			Value::String(text) => write!(f, "{}", quote(text)),
+			Value::Array(items) => {
+				write!(f, "[")?;
+				for (idx, item) in items.iter().enumerate() {
+					if idx != 0 {
+						write!(f, ",")?;
+					}
+					write!(f, "{}", item)?;
+				}
+				write!(f, "]")
+			}
+			Value::Object(members) => {
+				write!(f, "{{")?;
+				for (idx, (key, value)) in members.iter().enumerate() {
+					if idx != 0 {
+						write!(f, ",")?;
+					}
+					write!(f, "{}:{}", quote(key), value)?;
+				}
+				write!(f, "}}")
+			}
+		}
+	}
+}
+
+impl Parser<'_> {
+	/// Describe a problem at the current position.
+	fn error(&self, message: &str) -> String {
+		format!("{} at byte {}", message, self.pos)
+	}
+
+	/// The next character, without moving past it.
+	fn peek(&self) -> Option<char> {
+		self.text[self.pos..].chars().next()
+	}
+
+	/// Move past any white space.
+	fn skip_space(&mut self) {
+		while let Some(ch @ (' ' | '\t' | '\n' | '\r')) = self.peek() {
+			self.pos += ch.len_utf8();
+		}
+	}
+
+	/// Move past `expected`, or fail if it isn't next.
+	fn expect(&mut self, expected: &str) -> Result<(), String> {
+		if self.text[self.pos..].starts_with(expected) {
+			self.pos += expected.len();
+			Ok(())
+		} else {
+			Err(self.error(&format!("expected {:?}", expected)))
+		}
+	}
+
+	/// Parse any value.
+	fn value(&mut self) -> Result<Value, String> {
+		self.skip_space();
+		match self.peek() {
+			Some('n') => self.expect("null").map(|_| Value::Null),
+			Some('t') => self.expect("true").map(|_| Value::Bool(true)),
+			Some('f') => self.expect("false").map(|_| Value::Bool(false)),
+			Some('"') => self.string().map(Value::String),
+			Some('[') => self.array(),
+			Some('{') => self.object(),
+			Some('-' | '0'..='9') => self.number(),
+			Some(_) => Err(self.error("expected a value")),
+			None => Err(self.error("expected a value, not the end")),
+		}
+	}
+
+	/// Parse a number.
+	fn number(&mut self) -> Result<Value, String> {
+		let start = self.pos;
+		while let Some(ch @ ('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) = self.peek() {
+			self.pos += ch.len_utf8();
+		}
+		self.text[start..self.pos]
+			.parse()
+			.map(Value::Number)
+			.map_err(|_| format!("bad number at byte {}", start))
+	}
+
+	/// Parse a string, including its quotes.
+	fn string(&mut self) -> Result<String, String> {
+		self.expect("\"")?;
+		let mut result = String::new();
+		loop {
+			let Some(ch) = self.peek() else {
+				return Err(self.error("unfinished string"));
+			};
+			self.pos += ch.len_utf8();
+			match ch {
+				'"' => return Ok(result),
+				'\\' => {
+					let Some(escape) = self.peek() else {
+						return Err(self.error("unfinished string"));
+					};
+					self.pos += escape.len_utf8();
+					result.push(match escape {
+						'"' => '"',
+						'\\' => '\\',
+						'/' => '/',
+						'b' => '\u{8}',
+						'f' => '\u{c}',
+						'n' => '\n',
+						'r' => '\r',
+						't' => '\t',
+						'u' => self.unicode_escape()?,
+						_ => return Err(self.error("bad escape")),
+					});
+				}
+				ch => result.push(ch),
+			}
+		}
+	}
+
+	/// Parse the four hex digits after a `\u` (and the second half of a
+	/// surrogate pair, if this is the first half).
+	fn unicode_escape(&mut self) -> Result<char, String> {
+		let first = self.hex4()?;
+		let code = if (0xD800..0xDC00).contains(&first) {
+			self.expect("\\u")?;
+			let second = self.hex4()?;
+			if !(0xDC00..0xE000).contains(&second) {
+				return Err(self.error("bad surrogate pair"));
+			}
+			0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00)
+		} else {
+			first
+		};
+		char::from_u32(code).ok_or_else(|| self.error("bad character"))
+	}
+
+	/// Parse four hex digits.
+	fn hex4(&mut self) -> Result<u32, String> {
+		let digits = self
+			.text
+			.get(self.pos..self.pos + 4)
+			.ok_or_else(|| self.error("unfinished escape"))?;
+		let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("bad escape"))?;
+		self.pos += 4;
+		Ok(code)
+	}
+
+	/// Parse an array.
+	fn array(&mut self) -> Result<Value, String> {
+		self.expect("[")?;
+		let mut items = Vec::new();
+		self.skip_space();
+		if self.peek() == Some(']') {
+			self.pos += 1;
+			return Ok(Value::Array(items));
+		}
+		loop {
+			items.push(self.value()?);
+			self.skip_space();
+			match self.peek() {
+				Some(',') => self.pos += 1,
+				Some(']') => {
+					self.pos += 1;
+					return Ok(Value::Array(items));
+				}
+				_ => return Err(self.error("expected ',' or ']'")),
+			}
+		}
+	}
+
+	/// Parse an object.
+	fn object(&mut self) -> Result<Value, String> {
+		self.expect("{")?;
+		let mut members = Vec::new();
+		self.skip_space();
+		if self.peek() == Some('}') {
+			self.pos += 1;
+			return Ok(Value::Object(members));
+		}
+		loop {
+			self.skip_space();
+			let key = self.string()?;
+			self.skip_space();
+			self.expect(":")?;
+			members.push((key, self.value()?));
+			self.skip_space();
+			match self.peek() {
+				Some(',') => self.pos += 1,
+				Some('}') => {
+					self.pos += 1;
+					return Ok(Value::Object(members));
+				}
+				_ => return Err(self.error("expected ',' or '}'")),
+			}
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,489 @@
+//! # Neotron Desktop BIOS
+//!
+//! Implement a Neotron BIOS as a Linux/Windows/macOS desktop application.
+//!
+//! The framebuffer is draw in a window. SD/MMC cards can be passed as files or block devices.
+//!
+//! This library is the whole BIOS: the API we give the OS (split up into
+//! [`video`], [`hid`], [`block`], [`serial`], [`time`] and, for everything
+//! else, [`hardware`]), and the [`window`] that shows the screen. The
+//! `neotron-desktop-bios` binary just reads the command line, sets things up
+//! and opens the window.
+//!
+//! There's only one emulated machine, so it lives in statics. Call
+//! [`power_on`] to put it back the way it is when you switch it on - the
+//! BIOS does that at start-up, and tests can do it between runs.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2022
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// ===========================================================================
+// Imports
+// ===========================================================================
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, OnceLock};
+
+use common::video::RGBColour;
+use log::{info, warn};
+
+use neotron_common_bios as common;
+
+use crate::block::BLOCK_SIZE;
+use crate::hardware::HARDWARE;
+use crate::video::{FRAMEBUFFER, PALETTE, VIDEO_MODE};
+
+pub mod apistats;
+pub mod apitrace;
+pub mod attach;
+pub mod audio;
+pub mod block;
+pub mod bus;
+mod cellview;
+pub mod clock;
+pub mod config;
+pub mod conflicts;
+pub mod console;
+pub mod crash;
+pub mod device;
+pub mod disk;
+mod font;
+mod guard;
+pub mod hardware;
+pub mod heartbeat;
+mod hexdump;
+pub mod hid;
+pub mod hotkey;
+pub mod i2c;
+mod idle;
+pub mod inventory;
+pub mod isolate;
+pub mod json;
+pub mod lint;
+pub mod loader;
+pub mod memory;
+pub mod metrics;
+pub mod monitor;
+pub mod nvram;
+mod palette;
+mod panel;
+mod pause;
+pub mod preflight;
+pub mod profile;
+pub mod remote;
+pub mod render;
+pub mod resample;
+pub mod rom;
+pub mod serial;
+pub mod shutdown;
+pub mod slip;
+pub mod smoke;
+mod snapshot;
+pub mod throttle;
+pub mod time;
+pub mod timeline;
+pub mod trace;
+pub mod transfer;
+mod validate;
+pub mod video;
+pub mod videostats;
+pub mod vnc;
+pub mod watchdog;
+pub mod wav;
+pub mod webdisplay;
+pub mod window;
+
+// ===========================================================================
+// Types
+// ===========================================================================
+
+/// The OS's entry point
+pub type OsMain = unsafe extern "C" fn(api: &'static common::Api) -> !;
+
+// ===========================================================================
+// Global Variables
+// ===========================================================================
+
+/// The functions we export to the OS
+pub static BIOS_API: common::Api = common::Api {
+	api_version_get: hardware::api_version_get,
+	bios_version_get: hardware::bios_version_get,
+	serial_get_info: serial::serial_get_info,
+	serial_configure: serial::serial_configure,
+	serial_write: serial::serial_write,
+	serial_read: serial::serial_read,
+	time_clock_get: time::time_clock_get,
+	time_clock_set: time::time_clock_set,
+	configuration_get: hardware::configuration_get,
+	configuration_set: hardware::configuration_set,
+	video_is_valid_mode: video::video_is_valid_mode,
+	video_mode_needs_vram: video::video_mode_needs_vram,
+	video_set_mode: video::video_set_mode,
+	video_get_mode: video::video_get_mode,
+	video_get_framebuffer: video::video_get_framebuffer,
+	video_wait_for_line: video::video_wait_for_line,
+	memory_get_region: hardware::memory_get_region,
+	hid_get_event: hid::hid_get_event,
+	hid_set_leds: hid::hid_set_leds,
+	video_get_palette: video::video_get_palette,
+	video_set_palette: video::video_set_palette,
+	video_set_whole_palette: video::video_set_whole_palette,
+	i2c_bus_get_info: hardware::i2c_bus_get_info,
+	i2c_write_read: hardware::i2c_write_read,
+	audio_mixer_channel_get_info: hardware::audio_mixer_channel_get_info,
+	audio_mixer_channel_set_level: hardware::audio_mixer_channel_set_level,
+	audio_output_set_config: hardware::audio_output_set_config,
+	audio_output_get_config: hardware::audio_output_get_config,
+	audio_output_data: hardware::audio_output_data,
+	audio_output_get_space: hardware::audio_output_get_space,
+	audio_input_set_config: hardware::audio_input_set_config,
+	audio_input_get_config: hardware::audio_input_get_config,
+	audio_input_data: hardware::audio_input_data,
+	audio_input_get_count: hardware::audio_input_get_count,
+	bus_select: hardware::bus_select,
+	bus_get_info: hardware::bus_get_info,
+	bus_write_read: hardware::bus_write_read,
+	bus_exchange: hardware::bus_exchange,
+	time_ticks_get: time::time_ticks_get,
+	time_ticks_per_second: time::time_ticks_per_second,
+	bus_interrupt_status: hardware::bus_interrupt_status,
+	block_dev_get_info: block::block_dev_get_info,
+	block_dev_eject: block::block_dev_eject,
+	block_write: block::block_write,
+	block_read: block::block_read,
+	block_verify: block::block_verify,
+	power_idle: hardware::power_idle,
+	power_control: hardware::power_control,
+	compare_and_swap_bool: hardware::compare_and_swap_bool,
+};
+
+/// Where the OS starts, so we can start it again when it resets.
+static OS_MAIN: OnceLock<OsMain> = OnceLock::new();
+
+/// Where snapshots are saved if we aren't told otherwise.
+static SNAPSHOT_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Whether an OS reset also erases the NVRAM.
+static COLD_BOOT: AtomicBool = AtomicBool::new(false);
+
+// ===========================================================================
+// Macros
+// ===========================================================================
+
+// None
+
+// ===========================================================================
+// Functions
+// ===========================================================================
+
+/// Put the emulated machine back the way it is when you switch it on, with
+/// `disk` in the drive: the clocks starting from now, the default video mode
+/// and palette, a blank screen, and no key presses waiting.
+///
+/// Key presses for the OS go in the sender we give back. Only call this
+/// while the OS isn't running.
+pub fn power_on(disk: Option<std::fs::File>) -> mpsc::Sender<hid::AppEvent> {
+	clock::set_elapsed(std::time::Duration::ZERO);
+	hardware::init(disk);
+	video::reset_video();
+	hid::connect()
+}
+
+/// Start the OS on its own thread, once the window says it's ready.
+///
+/// With a `watchdog` (a timeout, and what to do when it runs out), we keep
+/// an eye on the OS from when it starts.
+pub fn boot(
+	main_func: OsMain,
+	watchdog: Option<(std::time::Duration, watchdog::Action)>,
+) -> std::io::Result<()> {
+	let os_thread = std::thread::Builder::new().name(crash::OS_THREAD_NAME.into());
+	os_thread.spawn(move || unsafe {
+		// Wait for Started message
+		hid::wait_for_start();
+		if attach::is_waiting() {
+			attach::wait();
+		}
+		lint::started();
+		info!("Video init complete. OS starting...");
+		let main_func = *OS_MAIN.get_or_init(|| main_func);
+		if let Some((timeout, action)) = watchdog {
+			watchdog::start(timeout, action);
+		}
+		if isolate::is_enabled() {
+			isolate::run();
+		}
+		// The OS never returns. If it panics, the `crash` module takes over.
+		main_func(api())
+	})?;
+	Ok(())
+}
+
+/// Choose whether an OS reset also erases the NVRAM.
+pub fn set_cold_boot(cold_boot: bool) {
+	COLD_BOOT.store(cold_boot, Ordering::Relaxed);
+}
+
+/// Choose where snapshots are saved if we aren't told otherwise.
+pub fn set_snapshot_path(path: PathBuf) {
+	SNAPSHOT_PATH.get_or_init(|| path);
+}
+
+/// Start the OS again, as if the reset button had been pressed.
+///
+/// The RAM is refilled so the new OS doesn't see what the old one left
+/// behind, the video goes back to how it was at power on, and any unread key
+/// presses are dropped. With `--cold-boot` the NVRAM is erased too.
+///
+/// This is called on the OS thread, from inside the old OS, and we never
+/// return to it.
+fn reset_os() -> ! {
+	isolate::stop_child();
+	memory::refill();
+	video::reset_video();
+	hid::discard_pending();
+	if COLD_BOOT.load(Ordering::Relaxed) {
+		if let Err(e) = nvram::erase() {
+			warn!("Failed to erase NVRAM on reset: {}", e);
+		}
+		info!("Cold reset: RAM refilled and NVRAM erased. OS restarting...");
+	} else {
+		info!("Warm reset: RAM refilled. OS restarting...");
+	}
+	if isolate::is_enabled() {
+		isolate::run();
+	}
+	let main_func = OS_MAIN.get().expect("OS to have started");
+	unsafe { main_func(api()) }
+}
+
+/// The BIOS API to give the OS, which counts every call (and traces it, if
+/// we were asked to).
+pub fn api() -> &'static common::Api {
+	&apitrace::API
+}
+
+/// Reset the OS from the host, like pressing Ctrl+Alt+Del.
+///
+/// This takes the same path as the OS asking for a reset, the next time the
+/// OS calls the BIOS. An OS that has crashed is reset straight away.
+fn request_reset() {
+	info!("Resetting the OS");
+	if crash::is_crashed() {
+		crash::reset();
+	} else {
+		watchdog::request_reset();
+	}
+}
+
+/// Describe the state of the machine, for a crash report: the video mode,
+/// what's on the screen, the palette, the BIOS call statistics and the
+/// attached devices.
+fn describe_machine() -> String {
+	let mut report = format!("\n## Video\n\n{}\n\n", video::describe_mode());
+	if let Some(screen) = video::read_text() {
+		for row in &screen.rows {
+			report.push_str(row.trim_end());
+			report.push('\n');
+		}
+	} else {
+		report.push_str("(Not a text mode, so we can't show what's on the screen)\n");
+	}
+	report.push_str("\n## Palette\n");
+	for (index, entry) in PALETTE.iter().enumerate() {
+		if index % 8 == 0 {
+			report.push_str(&format!("\n{:3}:", index));
+		}
+		let rgb = RGBColour::from_packed(entry.load(Ordering::Relaxed));
+		report.push_str(&format!(
+			" #{:02x}{:02x}{:02x}",
+			rgb.red(),
+			rgb.green(),
+			rgb.blue()
+		));
+	}
+	report.push_str(&format!("\n\n## BIOS Calls\n\n{}\n", apistats::report()));
+	report.push_str("\n## Devices\n\n");
+	let devices = describe_devices();
+	if devices.is_empty() {
+		report.push_str("(None)\n");
+	}
+	for device in devices {
+		report.push_str(&device);
+		report.push('\n');
+	}
+	report
+}
+
+/// Describe the devices attached to the machine, so we can tell if a snapshot
+/// was taken with different ones.
+fn describe_devices() -> Vec<String> {
+	let mut devices = Vec::new();
+	if let Some(disk) = HARDWARE
+		.lock()
+		.unwrap()
+		.as_ref()
+		.and_then(|hw| hw.disk_file.as_ref())
+	{
+		let blocks = disk.metadata().map(|m| m.len()).unwrap_or(0) / BLOCK_SIZE as u64;
+		devices.push(format!("Disk: {} blocks", blocks));
+	}
+	devices.extend(serial::describe_devices());
+	devices.extend(i2c::describe_devices());
+	devices.extend(bus::describe_devices());
+	devices
+}
+
+/// Save the state of the machine to a file.
+///
+/// The OS is stopped at its next BIOS call while we copy everything out, then
+/// carries on (unless it was already paused). Call this from any thread but
+/// the OS thread, and not from the GUI thread, as it can block for a while.
+fn take_snapshot(path: &std::path::Path) -> Result<(), String> {
+	let snapshot = while_frozen(|| snapshot::Snapshot {
+		regions: memory::dump_ram(),
+		vram: (0..640 * 480).map(|idx| FRAMEBUFFER.get_at(idx)).collect(),
+		palette: PALETTE
+			.iter()
+			.map(|entry| entry.load(Ordering::Relaxed))
+			.collect(),
+		video_mode: VIDEO_MODE.load(Ordering::Relaxed),
+		elapsed_ns: clock::elapsed().as_nanos() as u64,
+		clock_offset_ns: HARDWARE
+			.lock()
+			.unwrap()
+			.as_ref()
+			.map_or(0, |hw| hw.clock_offset_ns),
+		nvram: nvram::load().ok(),
+		devices: describe_devices(),
+	})?;
+	snapshot.save(path).map_err(|e| e.to_string())?;
+	info!("Saved snapshot to {}", path.display());
+	Ok(())
+}
+
+/// Save part of a memory region to a file. With no `range` (an offset and a
+/// length), we save the whole region.
+///
+/// The bytes are copied out while the OS is stopped, so they all come from
+/// the same moment. Call this from any thread but the OS and GUI threads.
+fn dump_memory(
+	region: u8,
+	range: Option<(usize, usize)>,
+	path: &std::path::Path,
+) -> Result<usize, String> {
+	let (offset, len) = match range {
+		Some(range) => range,
+		None => {
+			let region_info = memory::get_region(region)
+				.ok_or_else(|| format!("there is no Region {}", region))?;
+			(0, region_info.length)
+		}
+	};
+	let bytes = while_frozen(|| memory::snapshot(region, offset, len))??;
+	std::fs::write(path, &bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+	info!(
+		"Saved {} bytes of Region {} from offset 0x{:x} to {}",
+		bytes.len(),
+		region,
+		offset,
+		path.display()
+	);
+	Ok(bytes.len())
+}
+
+/// Stop the OS at its next BIOS call, run `f`, then let the OS carry on
+/// (unless it was already paused).
+///
+/// Call this from any thread but the OS thread, and not from the GUI thread,
+/// as it can block for a while.
+fn while_frozen<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+	let was_paused = pause::is_paused();
+	if !pause::freeze(std::time::Duration::from_secs(2)) {
+		if !was_paused {
+			pause::set_paused(false);
+		}
+		return Err("the OS didn't stop - is it calling the BIOS?".into());
+	}
+	let result = f();
+	if !was_paused {
+		pause::set_paused(false);
+	}
+	Ok(result)
+}
+
+/// Put the machine back the way it was when a snapshot was taken.
+///
+/// We can't restore the OS thread itself, so call this before the OS starts -
+/// it then boots with its RAM, video and clocks as they were.
+pub fn resume_snapshot(path: &std::path::Path) -> Result<(), String> {
+	let snapshot = snapshot::Snapshot::load(path)?;
+	memory::restore_ram(&snapshot.regions)?;
+	for (idx, byte) in snapshot.vram.iter().take(640 * 480).enumerate() {
+		FRAMEBUFFER.write_at(idx, *byte);
+	}
+	for (entry, rgb) in PALETTE.iter().zip(&snapshot.palette) {
+		entry.store(*rgb, Ordering::Relaxed);
+	}
+	match common::video::Mode::try_from_u8(snapshot.video_mode) {
+		// We don't know where the OS's own VRAM was, so we can't show a mode
+		// that needs some
+		Some(mode) if video::needs_vram(mode) => {
+			warn!(
+				"The snapshot was in mode {}, which needs VRAM from the OS - starting in mode 0",
+				snapshot.video_mode
+			);
+		}
+		_ => VIDEO_MODE.store(snapshot.video_mode, Ordering::Relaxed),
+	}
+	clock::set_elapsed(std::time::Duration::from_nanos(snapshot.elapsed_ns));
+	if let Some(hw) = HARDWARE.lock().unwrap().as_mut() {
+		hw.clock_offset_ns = snapshot.clock_offset_ns;
+	}
+	if let Some(data) = &snapshot.nvram {
+		nvram::write(data).map_err(|e| format!("NVRAM: {}", e))?;
+	}
+	let devices = describe_devices();
+	if devices != snapshot.devices {
+		warn!("The snapshot was taken with different devices attached:");
+		for device in &snapshot.devices {
+			warn!("  was: {}", device);
+		}
+		for device in &devices {
+			warn!("  now: {}", device);
+		}
+	}
+	info!("Resumed from snapshot {}", path.display());
+	Ok(())
+}
+
+/// Save a snapshot on a new thread, so the caller doesn't have to wait for
+/// the OS to stop.
+fn spawn_snapshot(path: PathBuf) {
+	std::thread::spawn(move || {
+		if let Err(e) = take_snapshot(&path) {
+			warn!("Failed to save snapshot to {}: {}", path.display(), e);
+		}
+	});
+}
+
+// ===========================================================================
+// End of File
+// ===========================================================================
@@ -0,0 +1,214 @@
+//! # Spotting API misuse
+//!
+//! With `--strict-api`, we watch for the OS using the BIOS API in ways that
+//! work here but are probably bugs, and would fail (or behave differently)
+//! on real hardware:
+//!
+//! * asking for the framebuffer before the BIOS has finished starting up
+//! * block device operations on a device that isn't there
+//! * writing to a serial port it hasn't configured
+//! * setting a palette entry the current video mode doesn't use
+//! * calling a function that blocks from a thread other than the OS thread
+//!
+//! We warn the first time each thing happens at each site (that is, each
+//! function and device), and count it quietly after that. Type `stats lint`
+//! in the debug console to see the counts, which are also logged when we
+//! exit. None of this changes what the BIOS does.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Whether we're checking.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set once the BIOS has started up and handed over to the OS.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Each site we've warned about, and how many times it has happened.
+static SEEN: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+
+/// The serial ports the OS has configured.
+static SERIAL_CONFIGURED: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Start checking.
+pub fn enable() {
+	ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Are we checking?
+pub fn is_enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Note that the BIOS has started up, and the OS is running.
+pub fn started() {
+	STARTED.store(true, Ordering::Relaxed);
+}
+
+/// Check the BIOS has started up before the OS asks `call` about the video.
+pub fn check_started(call: &str) {
+	if is_enabled() && !STARTED.load(Ordering::Relaxed) {
+		report(call, || {
+			format!("{} was called before the BIOS finished starting up", call)
+		});
+	}
+}
+
+/// Check there's a device for a block operation.
+pub fn check_block_device(call: &str, dev_id: u8, present: bool) {
+	if is_enabled() && !present {
+		report(&format!("{}({})", call, dev_id), || {
+			format!(
+				"{} was called for block device {}, which isn't there - check block_dev_get_info first",
+				call, dev_id
+			)
+		});
+	}
+}
+
+/// Note that the OS has configured a serial port.
+pub fn serial_configured(device: u8) {
+	if is_enabled() {
+		let mut configured = SERIAL_CONFIGURED.lock().unwrap();
+		if !configured.contains(&device) {
+			configured.push(device);
+		}
+	}
+}
+
+/// Check the OS has configured a serial port before writing to it.
+pub fn check_serial_configured(call: &str, device: u8) {
+	if is_enabled() && !SERIAL_CONFIGURED.lock().unwrap().contains(&device) {
+		report(&format!("{}({})", call, device), || {
+			format!(
+				"{} was called for serial port {}, which hasn't been configured with serial_configure",
+				call, device
+			)
+		});
+	}
+}
+
+/// Check a palette entry is one the current video mode uses.
+pub fn check_palette_index(call: &str, index: u8, mode: common::video::Mode) {
+	if !is_enabled() {
+		return;
+	}
+	let colours = match mode.format() {
+		common::video::Format::Chunky1 => 2,
+		common::video::Format::Chunky2 => 4,
+		common::video::Format::Chunky8 => 256,
+		// The text modes use 16 foreground colours, and the first eight as
+		// background colours
+		_ => 16,
+	};
+	if usize::from(index) >= colours {
+		report(&format!("{}({:?})", call, mode.format()), || {
+			format!(
+				"{} was called for palette entry {}, but the current mode ({:?}) only uses {} colours",
+				call,
+				index,
+				mode.format(),
+				colours
+			)
+		});
+	}
+}
+
+/// Check a function that blocks is being called from the OS thread.
+pub fn check_blocking(call: &str) {
+	if !is_enabled() {
+		return;
+	}
+	let thread = std::thread::current();
+	if thread.name() != Some(crate::crash::OS_THREAD_NAME) {
+		let name = thread.name().unwrap_or("<unnamed>").to_string();
+		report(&format!("{} on {}", call, name), || {
+			format!(
+				"{} blocks, but was called from thread {}, not the OS thread - it may hold up whatever that thread does",
+				call, name
+			)
+		});
+	}
+}
+
+/// How many times each kind of misuse has happened.
+pub fn report_counts() -> String {
+	if !is_enabled() {
+		return "Run with --strict-api to look for API misuse".to_string();
+	}
+	let seen = SEEN.lock().unwrap();
+	if seen.is_empty() {
+		return "No API misuse seen".to_string();
+	}
+	let mut output = format!("{:50} {:>10}", "Site", "Times");
+	for (site, count) in seen.iter() {
+		output.push_str(&format!("\n{:50} {:>10}", site, count));
+	}
+	output
+}
+
+/// Log how many times each kind of misuse has happened, if any has. Call
+/// this as we exit.
+pub fn log_counts() {
+	if !is_enabled() {
+		return;
+	}
+	let Ok(seen) = SEEN.try_lock() else {
+		return;
+	};
+	for (site, count) in seen.iter() {
+		log::warn!("API misuse at {}: {} time(s)", site, count);
+	}
+}
+
+/// Count a misuse at `site`, warning about it if it's the first time.
+fn report(site: &str, message: impl FnOnce() -> String) {
+	let mut seen = SEEN.lock().unwrap();
+	match seen.iter_mut().find(|(seen_site, _)| seen_site == site) {
+		Some((_, count)) => *count += 1,
+		None => {
+			log::warn!(
+				"API misuse: {} (further cases are counted - see `stats lint`)",
+				message()
+			);
+			seen.push((site.to_string(), 1));
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
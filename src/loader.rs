@@ -0,0 +1,340 @@
+//! # Finding and loading the OS
+//!
+//! The OS is a shared library exporting `os_main`. We use the ones given with
+//! `--os` if there are any, otherwise the one the `NEOTRON_OS` environment
+//! variable points at, otherwise a few conventional places (see
+//! [`candidates`]). Where there's more than one, we boot the first one that
+//! works (see [`choose`]).
+//!
+//! If the library won't load, we try to say why - see [`load`].
+//!
+//! Before we start the OS, we ask it which version of the BIOS API it was
+//! built against, by calling its `os_api_version` function if it has one (see
+//! [`check_api_version`]). If it exports `os_version_get`, we ask it for its
+//! name and version too, for the log, the window title and crash reports (see
+//! [`os_version`]).
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The function an OS can export to tell us which BIOS API it expects.
+type OsApiVersion = unsafe extern "C" fn() -> common::Version;
+
+/// The function an OS can export to tell us its name and version.
+type OsVersionGet = unsafe extern "C" fn() -> common::FfiString<'static>;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The environment variable that says where the OS is.
+pub const ENV_VAR: &str = "NEOTRON_OS";
+
+/// The OS library's name, without the platform's prefix and extension.
+const LIBRARY_NAME: &str = "neotron_os";
+
+/// How much of the library we look at to work out what sort of binary it is.
+const HEADER_SIZE: u64 = 4096;
+
+/// What the OS we booted says it is, if it told us.
+static OS_VERSION: OnceLock<String> = OnceLock::new();
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Work out which OS library to boot, and load it.
+///
+/// The paths given on the command line always win, then the environment
+/// variable, then the conventional places. We try each in order, and boot the
+/// first one that loads and wants a BIOS API we can provide, logging why we
+/// skipped the others. If none of them will do, the error says what we tried.
+pub fn choose(explicit: &[PathBuf]) -> Result<(PathBuf, crate::OsMain), String> {
+	let (paths, source) = if !explicit.is_empty() {
+		(explicit.to_vec(), "--os")
+	} else if let Some(path) = std::env::var_os(ENV_VAR) {
+		(vec![PathBuf::from(path)], ENV_VAR)
+	} else {
+		(candidates(), "the default search path")
+	};
+	let mut skipped = String::new();
+	for path in &paths {
+		match check(path).and_then(|_| load(path)) {
+			Ok(main) => {
+				log::info!("Booting the OS from {} (from {})", path.display(), source);
+				return Ok((path.clone(), main));
+			}
+			Err(e) if explicit.is_empty() && !path.exists() => {
+				// Not having an OS in most of the conventional places is normal
+				log::debug!("No OS at {}", path.display());
+				skipped.push_str(&format!("\n  {}: {}", path.display(), e));
+			}
+			Err(e) => {
+				log::warn!("Skipping the OS at {}: {}", path.display(), e);
+				skipped.push_str(&format!("\n  {}: {}", path.display(), e));
+			}
+		}
+	}
+	if explicit.is_empty() && std::env::var_os(ENV_VAR).is_none() {
+		Err(format!(
+			"no OS given - use --os or set {}. We also looked in:{}",
+			ENV_VAR, skipped
+		))
+	} else {
+		Err(format!("none of the OS libraries would do:{}", skipped))
+	}
+}
+
+/// The conventional places for the OS library: the current directory, then
+/// the build output of a Neotron OS checkout next to this one.
+///
+/// The file name follows the host's convention, like `libneotron_os.so`,
+/// `libneotron_os.dylib` or `neotron_os.dll`.
+pub fn candidates() -> Vec<PathBuf> {
+	let file_name = format!(
+		"{}{}{}",
+		std::env::consts::DLL_PREFIX,
+		LIBRARY_NAME,
+		std::env::consts::DLL_SUFFIX
+	);
+	[
+		"",
+		"../neotron-os/target/debug",
+		"../neotron-os/target/release",
+		"../Neotron-OS/target/debug",
+		"../Neotron-OS/target/release",
+	]
+	.iter()
+	.map(|dir| Path::new(dir).join(&file_name))
+	.collect()
+}
+
+/// Load the OS library, check its API version, and find its entry point.
+///
+/// If it all works out, the library stays loaded until we exit. If it won't
+/// load, we look at the file to see whether it was built for another
+/// architecture, and otherwise pass on what the host's loader said (which is
+/// usually about a missing symbol or library).
+pub fn load(path: &Path) -> Result<crate::OsMain, String> {
+	// Safety: we're trusting the OS library not to do anything daft when it
+	// is loaded
+	let lib = match unsafe { libloading::Library::new(path) } {
+		Ok(lib) => lib,
+		Err(e) => {
+			let header = read_header(path).map_err(|e| format!("can't read it: {}", e))?;
+			return Err(match binary_arch(&header) {
+				Some(Ok(arch)) if arch != std::env::consts::ARCH => format!(
+					"it was built for {}, but this BIOS is running on {} - rebuild the OS for this machine",
+					arch,
+					std::env::consts::ARCH
+				),
+				None => "it isn't a shared library (it's not ELF, Mach-O or PE)".to_string(),
+				_ => format!("the loader said: {}", e),
+			});
+		}
+	};
+	// Safety: we can't check the signature of a C symbol, so this is only as
+	// good as the OS's word
+	let main = match unsafe { lib.get::<crate::OsMain>(b"os_main") } {
+		Ok(main) => *main,
+		Err(e) => {
+			return Err(format!(
+				"it doesn't export os_main() ({}). Make sure it's built as a library, and that os_main is \
+				`#[no_mangle] pub extern \"C\" fn os_main(api: &Api) -> !`",
+				e
+			))
+		}
+	};
+	check_api_version(&lib)?;
+	if let Some(version) = read_os_version(&lib) {
+		log::info!("OS says it is {}", version);
+		let _ = OS_VERSION.set(version);
+	}
+	// The entry point is only good while the library is loaded
+	std::mem::forget(lib);
+	Ok(main)
+}
+
+/// Check the OS was built against a version of the BIOS API we can give it.
+///
+/// A different major version is an error. A different minor version gets a
+/// warning, as it probably works, but any crashes might be down to it. An OS
+/// that doesn't export `os_api_version` gets the benefit of the doubt.
+fn check_api_version(lib: &libloading::Library) -> Result<(), String> {
+	let ours = common::API_VERSION;
+	// Safety: if the OS exports this symbol, it has this signature
+	let theirs = match unsafe { lib.get::<OsApiVersion>(b"os_api_version") } {
+		Ok(os_api_version) => unsafe { os_api_version() },
+		Err(_) => {
+			log::info!(
+				"OS doesn't export os_api_version(), so assuming it wants BIOS API {}",
+				describe(ours)
+			);
+			return Ok(());
+		}
+	};
+	if theirs.major() != ours.major() {
+		Err(format!(
+			"the OS was built for BIOS API {}, but this BIOS provides {}",
+			describe(theirs),
+			describe(ours)
+		))
+	} else if theirs.minor() != ours.minor() {
+		log::warn!(
+			"The OS was built for BIOS API {}, but this BIOS provides {} - carrying on anyway",
+			describe(theirs),
+			describe(ours)
+		);
+		Ok(())
+	} else {
+		log::info!("OS wants BIOS API {}", describe(theirs));
+		Ok(())
+	}
+}
+
+/// What the OS we booted says it is, like `Neotron OS v0.5.0`, if it exports
+/// `os_version_get`.
+pub fn os_version() -> Option<&'static str> {
+	OS_VERSION.get().map(String::as_str)
+}
+
+/// Ask the OS for its name and version, if it exports `os_version_get`.
+fn read_os_version(lib: &libloading::Library) -> Option<String> {
+	// Safety: if the OS exports this symbol, it has this signature
+	let os_version_get = unsafe { lib.get::<OsVersionGet>(b"os_version_get") }.ok()?;
+	let version = unsafe { os_version_get() };
+	// Keep control characters out of the window title
+	let version: String = String::from_utf8_lossy(version.as_str().as_bytes())
+		.chars()
+		.filter(|c| !c.is_control())
+		.collect();
+	let version = version.trim();
+	(!version.is_empty()).then(|| version.to_string())
+}
+
+/// Format a version like `0.6.1`.
+pub fn describe(version: common::Version) -> String {
+	format!(
+		"{}.{}.{}",
+		version.major(),
+		version.minor(),
+		version.patch()
+	)
+}
+
+/// Read the start of a file, which is enough to see what sort of binary it is.
+fn read_header(path: &Path) -> std::io::Result<Vec<u8>> {
+	use std::io::Read;
+	let mut header = Vec::new();
+	std::fs::File::open(path)?
+		.take(HEADER_SIZE)
+		.read_to_end(&mut header)?;
+	Ok(header)
+}
+
+/// Which architecture a binary was built for, in the form
+/// [`std::env::consts::ARCH`] uses.
+///
+/// Gives `None` if it isn't an ELF, Mach-O or PE file, and `Some(Err(..))` if
+/// it is but we don't recognise the architecture (or it's a universal binary
+/// with several).
+fn binary_arch(header: &[u8]) -> Option<Result<&'static str, ()>> {
+	let u16_at = |offset: usize, big_endian: bool| {
+		let bytes: [u8; 2] = header.get(offset..offset + 2)?.try_into().ok()?;
+		Some(if big_endian {
+			u16::from_be_bytes(bytes)
+		} else {
+			u16::from_le_bytes(bytes)
+		})
+	};
+	let u32_le_at = |offset: usize| {
+		let bytes: [u8; 4] = header.get(offset..offset + 4)?.try_into().ok()?;
+		Some(u32::from_le_bytes(bytes))
+	};
+	if header.starts_with(b"\x7fELF") {
+		let is_64_bit = header.get(4) == Some(&2);
+		let big_endian = header.get(5) == Some(&2);
+		return Some(match u16_at(18, big_endian)? {
+			0x03 => Ok("x86"),
+			0x3E => Ok("x86_64"),
+			0x28 => Ok("arm"),
+			0xB7 => Ok("aarch64"),
+			0xF3 if is_64_bit => Ok("riscv64"),
+			0xF3 => Ok("riscv32"),
+			_ => Err(()),
+		});
+	}
+	if header.starts_with(&[0xCE, 0xFA, 0xED, 0xFE])
+		|| header.starts_with(&[0xCF, 0xFA, 0xED, 0xFE])
+	{
+		return Some(match u32_le_at(4)? {
+			0x0000_0007 => Ok("x86"),
+			0x0100_0007 => Ok("x86_64"),
+			0x0000_000C => Ok("arm"),
+			0x0100_000C => Ok("aarch64"),
+			_ => Err(()),
+		});
+	}
+	if header.starts_with(&[0xCA, 0xFE, 0xBA, 0xBE]) {
+		// A universal binary - the loader knows better than we do
+		return Some(Err(()));
+	}
+	if header.starts_with(b"MZ") {
+		let pe_offset = usize::try_from(u32_le_at(0x3C)?).ok()?;
+		if header.get(pe_offset..pe_offset + 4)? != b"PE\0\0" {
+			return None;
+		}
+		return Some(match u16_at(pe_offset + 4, false)? {
+			0x014C => Ok("x86"),
+			0x8664 => Ok("x86_64"),
+			0x01C0 | 0x01C4 => Ok("arm"),
+			0xAA64 => Ok("aarch64"),
+			_ => Err(()),
+		});
+	}
+	None
+}
+
+/// Check there's an OS library at this path, before we try to load it.
+fn check(path: &Path) -> Result<(), String> {
+	if path.is_file() {
+		Ok(())
+	} else if path.exists() {
+		Err("it's not a file".to_string())
+	} else {
+		Err("it doesn't exist".to_string())
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -3,6 +3,9 @@
 //! Implement a Neotron BIOS as a Linux/Windows/macOS desktop application.
 //!
 //! The framebuffer is draw in a window. SD/MMC cards can be passed as files or block devices.
+//!
+//! The BIOS itself is in the library - this just reads the command line, sets
+//! up the machine to match, and opens the window.
 
 // -----------------------------------------------------------------------------
 // Licence Statement
@@ -27,164 +30,342 @@
 // Imports
 // ===========================================================================
 
-use std::io::prelude::*;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicPtr;
-use std::sync::{
-	atomic::{AtomicU32, AtomicU8, Ordering},
-	mpsc, Mutex,
-};
 
-use clap::Parser;
-use common::video::RGBColour;
-use log::{debug, info};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueHint};
+use log::{info, warn};
 use pix_engine::prelude::*;
 
 use neotron_common_bios as common;
 
-mod font;
-mod palette;
+use neotron_desktop_bios::window::{MyApp, SCALE_FACTOR, WINDOW_TITLE};
+use neotron_desktop_bios::{
+	apistats, apitrace, attach, audio, block, bus, clock, config, conflicts, console, crash,
+	device, disk, heartbeat, hotkey, i2c, inventory, isolate, lint, loader, memory, metrics,
+	monitor, nvram, preflight, profile, remote, rom, serial, shutdown, smoke, throttle, time,
+	timeline, trace, transfer, video, videostats, vnc, watchdog, wav, webdisplay,
+};
 
 // ===========================================================================
 // Types
 // ===========================================================================
 
-struct MyApp {
-	mode: common::video::Mode,
-	font8x16: Vec<TextureId>,
-	font8x8: Vec<TextureId>,
-	sender: mpsc::Sender<AppEvent>,
-	reset: bool,
-}
-
-#[derive(Debug, PartialEq, Eq)]
-enum AppEvent {
-	Started,
-	KeyUp(Key),
-	KeyDown(Key),
-}
-
-/// Our video RAM
-struct Framebuffer<const N: usize> {
-	contents: std::cell::UnsafeCell<[u8; N]>,
-	alt_pointer: AtomicPtr<u32>,
-}
-
 /// A Desktop GUI version of a Neotron BIOS
 #[derive(Parser)]
-#[command(author, version, about)]
+#[command(author, version, about, subcommand_negates_reqs = true)]
 struct Args {
-	/// Path to the OS library
+	#[command(subcommand)]
+	command: Option<Command>,
+	/// Path to the OS library (defaults to `$NEOTRON_OS`, then
+	/// `libneotron_os` in the current directory or `../neotron-os/target`).
+	/// Give it more than once, or a comma-separated list, and we boot the
+	/// first one that works.
+	#[arg(long, value_delimiter = ',', value_hint = ValueHint::FilePath)]
+	os: Vec<PathBuf>,
+	/// Attach a device (e.g. `disk:0=file:boot.img,ro`, `i2c:0x48=lm75,bus=1`
+	/// or `bus:2=timer,rate=10Hz`). Give this more than once for more
+	/// devices.
+	#[arg(long, value_parser = device::parse)]
+	device: Vec<device::Device>,
+	/// Path to a file to use as a disk image (going away - use `--device
+	/// disk=file:<path>`)
+	#[arg(long, value_hint = ValueHint::FilePath)]
+	disk: Option<PathBuf>,
+	/// Path to NVRAM file (defaults to `nvram.bin` in your config directory)
+	#[arg(long, global = true, value_hint = ValueHint::FilePath)]
+	nvram: Option<PathBuf>,
+	/// Size of the NVRAM in bytes, like a real EEPROM. Blank NVRAM reads as
+	/// this many 0xFF bytes.
+	#[arg(long, global = true)]
+	nvram_size: Option<usize>,
+	/// Give the OS this boot command line (e.g. `"boot=sd0 loglevel=debug"`),
+	/// in front of its configuration block
+	#[arg(long, value_parser = nvram::parse_boot_args)]
+	os_args: Option<String>,
+	/// Damage the NVRAM contents as the OS reads them, to test its error
+	/// handling (e.g. `bitflip:0.01` or `offset:12`)
+	#[arg(long, value_parser = nvram::parse_corruption)]
+	nvram_corrupt: Option<nvram::Corruption>,
+	/// Key to hold down to use the host hotkeys (e.g. RCtrl, LAlt, ScrollLock)
+	#[arg(long, default_value = "RCtrl", value_parser = hotkey::parse_key)]
+	hotkey_prefix: Key,
+	/// Path to a WAV file to use as the audio input, instead of a microphone
+	#[arg(long, conflicts_with = "audio_input_device", value_hint = ValueHint::FilePath)]
+	audio_input: Option<PathBuf>,
+	/// Loop the audio input WAV file, rather than stopping at the end
+	#[arg(long, requires = "audio_input")]
+	audio_input_loop: bool,
+	/// Log the audio underrun/overrun counters once a second
 	#[arg(long)]
-	os: PathBuf,
-	/// Path to a file to use as a disk image
+	audio_stats: bool,
+	/// Log the audio output FIFO level history whenever it underruns
 	#[arg(long)]
-	disk: Option<PathBuf>,
-	/// Path to NVRAM file
+	strict_audio: bool,
+	/// List the host audio devices and exit
 	#[arg(long)]
-	nvram: Option<PathBuf>,
+	list_audio: bool,
+	/// List the host audio devices and everything the OS will find on the
+	/// machine (disks, serial ports, I2C and Neotron Bus devices, memory and
+	/// audio), and exit
+	#[arg(long)]
+	list_devices: bool,
+	/// List the video modes the OS can use, and exit
+	#[arg(long)]
+	list_modes: bool,
+	/// Use this machine profile (e.g. `pico2`) for the NVRAM, the real-time
+	/// clock, the window position and a `config.toml` of options. We make it
+	/// if it doesn't exist yet.
+	#[arg(long, global = true)]
+	profile: Option<String>,
+	/// List the machine profiles, and exit
+	#[arg(long)]
+	list_profiles: bool,
+	/// Go through start-up without opening the window or starting the OS -
+	/// open the disk, add the devices, bind the monitor, start the audio and
+	/// load the OS - then say what passed and what failed, and exit (with
+	/// code 1 if anything failed)
+	#[arg(long)]
+	check: bool,
+	/// Print the value of every option, and whether it came from the command
+	/// line, a `NEOTRON_BIOS_*` environment variable or the default, and exit
+	#[arg(long)]
+	dump_config: bool,
+	/// Use the first host audio output device whose name contains this
+	#[arg(long)]
+	audio_device: Option<String>,
+	/// Use the first host audio input device whose name contains this
+	#[arg(long)]
+	audio_input_device: Option<String>,
+	/// How much audio to buffer (e.g. 20ms). Smaller is more responsive, but
+	/// more likely to glitch.
+	#[arg(long, value_parser = audio::parse_latency, default_value_t = audio::DEFAULT_LATENCY_MS)]
+	audio_latency: u32,
+	/// Where audio goes. `null` plays nothing, but at the right rate.
+	#[arg(long = "audio", value_enum, default_value_t = audio::Backend::Sdl)]
+	audio_backend: audio::Backend,
+	/// Run emulated time this many times faster than real time (e.g. 10, or
+	/// 0.5)
+	#[arg(long, default_value_t = 1.0, value_parser = clock::parse_scale)]
+	time_scale: f64,
+	/// Read debug commands from standard input
+	#[arg(long)]
+	debug_console: bool,
+	/// Take debug commands from clients of this socket (e.g.
+	/// `unix:/tmp/neotron.sock` or `tcp:4444`)
+	#[arg(long, value_parser = monitor::parse_address)]
+	monitor: Option<monitor::Address>,
+	/// Let a program drive the emulator with JSON over this socket (e.g.
+	/// `unix:/tmp/neotron-rc.sock` or `tcp:127.0.0.1:5555`)
+	#[arg(long, value_parser = remote::parse_address)]
+	remote: Option<monitor::Address>,
+	/// Serve the screen to VNC viewers on this address (e.g. `:5901`, or
+	/// `0.0.0.0:5901` for other machines)
+	#[arg(long, value_parser = vnc::parse_address)]
+	vnc: Option<std::net::SocketAddr>,
+	/// Serve Prometheus metrics at `/metrics` on this address (e.g.
+	/// `127.0.0.1:9600`, or just `9600`)
+	#[arg(long, value_parser = metrics::parse_address)]
+	metrics: Option<std::net::SocketAddr>,
+	/// Show the screen in a web browser, from a page served on this address
+	/// (e.g. `127.0.0.1:8090`, or just `8090`)
+	#[arg(long, value_parser = webdisplay::parse_address)]
+	web_display: Option<std::net::SocketAddr>,
+	/// Make `time_ticks_get` count in microseconds, rather than milliseconds
+	#[arg(long)]
+	fine_ticks: bool,
+	/// Host audio output volume, in percent. The OS can't change this.
+	#[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+	volume: u8,
+	/// Give the OS a second block of RAM as Region 1 (e.g. 8MiB), like the
+	/// external PSRAM on some boards
+	#[arg(long, value_parser = memory::parse_size)]
+	ram2_size: Option<usize>,
+	/// Fill the OS's RAM with this byte before it starts (e.g. 0xAA), to
+	/// catch code that expects fresh RAM to be zero
+	#[arg(long, default_value = "0x00", value_parser = memory::parse_fill)]
+	ram_fill: u8,
+	/// Put inaccessible guard pages either side of the OS's RAM, so overruns
+	/// are caught straight away
+	#[arg(long)]
+	guard_pages: bool,
+	/// Run the OS in a child process, so a crash only takes out the OS (Unix
+	/// only)
+	#[arg(long, conflicts_with_all = ["guard_pages", "cpu_throttle"])]
+	isolate: bool,
+	/// Print where the OS was loaded, and wait for Enter (or SIGUSR1) before
+	/// starting it, so you can attach a debugger
+	#[arg(long)]
+	wait: bool,
+	/// Warn about the OS using the BIOS API in ways that are probably bugs,
+	/// like setting a palette entry the video mode doesn't use
+	#[arg(long)]
+	strict_api: bool,
+	/// Erase the NVRAM too when the OS resets itself, as well as refilling
+	/// its RAM
+	#[arg(long)]
+	cold_boot: bool,
+	/// Put a 24C64-style EEPROM on I2C Bus 0, kept in this file (e.g.
+	/// `eeprom.bin:8KiB`) (going away - use `--device
+	/// i2c:0x50=eeprom:<path>,size=<size>`)
+	#[arg(long, value_parser = i2c::parse_eeprom)]
+	i2c_eeprom: Option<i2c::EepromSpec>,
+	/// The I2C address of the `--i2c-eeprom` EEPROM
+	#[arg(long, default_value = "0x50", value_parser = i2c::parse_address, requires = "i2c_eeprom")]
+	i2c_eeprom_address: u8,
+	/// Put a device on an I2C bus (e.g. `1:0x48:lm75` or
+	/// `0:0x50:eeprom:eeprom.bin:8KiB`). Give this more than once for more
+	/// devices. (Going away - use `--device i2c:...`)
+	#[arg(long, value_parser = i2c::parse_device)]
+	i2c_device: Vec<i2c::DeviceSpec>,
+	/// Put a peripheral on the Neotron Bus (e.g. `slot`). Give this more than
+	/// once for more peripherals - the first is peripheral 0. (Going away -
+	/// use `--device bus=...`)
+	#[arg(long, value_parser = bus::parse_device)]
+	bus_device: Vec<bus::DeviceSpec>,
+	/// Plug something into serial port 0: `null`, `loopback`, or
+	/// `slip-tap:<name>[,mtu=<bytes>]` for SLIP networking through a host TUN
+	/// device
+	#[arg(long, value_parser = device::parse_serial)]
+	serial0: Option<serial::DeviceSpec>,
+	/// Plug something into serial port 1 (see `--serial0`)
+	#[arg(long, value_parser = device::parse_serial)]
+	serial1: Option<serial::DeviceSpec>,
+	/// Plug something into serial port 2 (see `--serial0`)
+	#[arg(long, value_parser = device::parse_serial)]
+	serial2: Option<serial::DeviceSpec>,
+	/// Plug something into serial port 3 (see `--serial0`)
+	#[arg(long, value_parser = device::parse_serial)]
+	serial3: Option<serial::DeviceSpec>,
+	/// Log every Neotron Bus transaction to standard error
+	#[arg(long)]
+	trace_bus: bool,
+	/// Write the `--trace-bus` log to this file instead
+	#[arg(long, requires = "trace_bus", value_hint = ValueHint::FilePath)]
+	trace_bus_file: Option<PathBuf>,
+	/// Show how often the OS calls the busiest BIOS functions, along the
+	/// bottom of the window
+	#[arg(long)]
+	api_stats: bool,
+	/// Log the frame times and how long rendering takes once a minute
+	#[arg(long)]
+	video_stats: bool,
+	/// Log a one-line summary of what the machine has been doing this often
+	/// (e.g. `60s` or `5m`)
+	#[arg(long, value_parser = heartbeat::parse_interval)]
+	heartbeat: Option<std::time::Duration>,
+	/// Log every call the OS makes to the BIOS to standard error, with its
+	/// arguments and result
+	#[arg(long)]
+	trace_api: bool,
+	/// Write the `--trace-api` log to this file instead
+	#[arg(long, requires = "trace_api", value_hint = ValueHint::FilePath)]
+	trace_api_file: Option<PathBuf>,
+	/// Leave these BIOS functions out of the `--trace-api` log (e.g.
+	/// `time_ticks_get,video_wait_for_line`)
+	#[arg(long, requires = "trace_api", value_delimiter = ',', value_parser = apitrace::parse_name)]
+	trace_api_exclude: Vec<String>,
+	/// Log at most this many calls a second to each BIOS function in the
+	/// `--trace-api` log (0 for no limit)
+	#[arg(long, default_value_t = 100)]
+	trace_api_limit: u32,
+	/// Record BIOS calls, frames, disk operations and audio callbacks, and
+	/// write them to this file on exit, for a timeline viewer like Perfetto
+	/// (e.g. `out.json`)
+	#[arg(long, value_hint = ValueHint::FilePath)]
+	trace_timeline: Option<PathBuf>,
+	/// Show at most this many bytes of each payload in a trace
+	#[arg(long, default_value_t = 16)]
+	trace_bytes: usize,
+	/// Only give the OS this share of the host's CPU time (e.g. `5%`), to
+	/// show up code that would be slow on real hardware
+	#[arg(long, value_parser = throttle::parse_share)]
+	cpu_throttle: Option<f64>,
+	/// Decide the OS has hung if it doesn't call the BIOS for this long (e.g.
+	/// `10s`)
+	#[arg(long, value_parser = watchdog::parse_timeout)]
+	watchdog: Option<std::time::Duration>,
+	/// What to do when the OS hangs
+	#[arg(long, value_enum, default_value_t = watchdog::Action::Wait, requires = "watchdog")]
+	watchdog_action: watchdog::Action,
+	/// Where `Prefix + S` and the `snapshot` console command save the machine
+	#[arg(long, default_value = "snapshot.neo", value_hint = ValueHint::FilePath)]
+	snapshot_file: PathBuf,
+	/// Put the machine back the way it was in this snapshot, then start the
+	/// OS (e.g. `snap.neo`)
+	#[arg(long, value_hint = ValueHint::FilePath)]
+	resume: Option<PathBuf>,
+	/// Exit with code 2 if the OS panics, rather than waiting for a reset
+	#[arg(long)]
+	exit_on_panic: bool,
+	/// Where to save crash reports (defaults to `crashes` in your local data
+	/// directory)
+	#[arg(long, value_hint = ValueHint::DirPath)]
+	crash_dir: Option<PathBuf>,
+	/// Exit with code 4 rather than 0 when the window is closed, so scripts
+	/// can tell that the OS didn't turn the power off itself
+	#[arg(long)]
+	fail_on_close: bool,
+	/// Shut down after this long (e.g. `5s`) or this many frames (e.g.
+	/// `300frames`), with an exit code saying whether the OS was healthy
+	#[arg(long, value_parser = smoke::parse_limit)]
+	exit_after: Option<smoke::Limit>,
+	/// Draw into an invisible window, so no display server is needed
+	#[arg(long)]
+	headless: bool,
 }
 
-/// All our emulated hardware
-struct Hardware {
-	/// When we booted up
-	boot_time: std::time::Instant,
-	/// Our disk image
-	disk_file: Option<std::fs::File>,
+/// Options that can't come from plain environment variables - the things to
+/// do rather than settings, and the options in [`INDEXED_ENV`].
+const NOT_FROM_ENV: &[&str] = &[
+	"list-audio",
+	"list-devices",
+	"list-modes",
+	"list-profiles",
+	"dump-config",
+	"check",
+	"device",
+	"i2c-device",
+	"bus-device",
+];
+
+/// Options that can't go in a profile's config file - the things to do
+/// rather than settings, and the profile itself.
+const NOT_IN_FILE: &[&str] = &[
+	"list-audio",
+	"list-devices",
+	"list-modes",
+	"list-profiles",
+	"dump-config",
+	"check",
+	"profile",
+];
+
+/// Options that can be given more than once, so they take numbered
+/// environment variables (e.g. `NEOTRON_BIOS_DEVICE_0`).
+const INDEXED_ENV: &[&str] = &["device", "i2c-device", "bus-device"];
+
+/// Things we can do instead of running the emulator.
+#[derive(clap::Subcommand)]
+enum Command {
+	/// Look at or change the NVRAM file
+	Nvram {
+		#[command(subcommand)]
+		action: nvram::Action,
+	},
+	/// Make, look at or convert a disk image
+	Disk {
+		#[command(subcommand)]
+		action: disk::Action,
+	},
+	/// Print a script that makes your shell complete our options and
+	/// subcommands
+	Completions {
+		/// The shell to complete for
+		shell: clap_complete::Shell,
+	},
 }
 
-// ===========================================================================
-// Global Variables
-// ===========================================================================
-
-/// We only have 'normal' sectored emulated disks
-const BLOCK_SIZE: usize = 512;
-
-/// The default VRAM we share in a very hazardous way with the OS.
-///
-/// Big enough for 640x480 @ 256 colour.
-// static mut FRAMEBUFFER: [u8; 307200] = [0u8; 307200];
-static FRAMEBUFFER: Framebuffer<{ 640 * 480 }> = Framebuffer::new();
-
-/// Scale the display to make it readable on a modern monitor
-const SCALE_FACTOR: f32 = 2.0;
-
-/// When we booted up
-static HARDWARE: Mutex<Option<Hardware>> = Mutex::new(None);
-
-/// The functions we export to the OS
-static BIOS_API: common::Api = common::Api {
-	api_version_get,
-	bios_version_get,
-	serial_get_info,
-	serial_configure,
-	serial_write,
-	serial_read,
-	time_clock_get,
-	time_clock_set,
-	configuration_get,
-	configuration_set,
-	video_is_valid_mode,
-	video_mode_needs_vram,
-	video_set_mode,
-	video_get_mode,
-	video_get_framebuffer,
-	video_wait_for_line,
-	memory_get_region,
-	hid_get_event,
-	hid_set_leds,
-	video_get_palette,
-	video_set_palette,
-	video_set_whole_palette,
-	i2c_bus_get_info,
-	i2c_write_read,
-	audio_mixer_channel_get_info,
-	audio_mixer_channel_set_level,
-	audio_output_set_config,
-	audio_output_get_config,
-	audio_output_data,
-	audio_output_get_space,
-	audio_input_set_config,
-	audio_input_get_config,
-	audio_input_data,
-	audio_input_get_count,
-	bus_select,
-	bus_get_info,
-	bus_write_read,
-	bus_exchange,
-	time_ticks_get,
-	time_ticks_per_second,
-	bus_interrupt_status,
-	block_dev_get_info,
-	block_dev_eject,
-	block_write,
-	block_read,
-	block_verify,
-	power_idle,
-	power_control,
-	compare_and_swap_bool,
-};
-
-/// Our standard 256 colour palette
-static PALETTE: [AtomicU32; 256] = palette::make_default_palette();
-
-/// Our current video mode.
-///
-/// Defaulting to Mode 0 - 640x480 timing, 80x30 text mode
-static VIDEO_MODE: AtomicU8 = AtomicU8::new(0);
-
-/// HID events come from here
-static EV_QUEUE: Mutex<Option<mpsc::Receiver<AppEvent>>> = Mutex::new(None);
-
-/// Where the OS config is read from or written to.
-static CONFIG_FILE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
-
-// ===========================================================================
-// Macros
-// ===========================================================================
-
-// None
-
 // ===========================================================================
 // Functions
 // ===========================================================================
@@ -197,1116 +378,760 @@ static CONFIG_FILE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
 fn main() {
 	env_logger::init();
 
-	let args = Args::parse();
-
-	// Let's go!
-	info!("Netron Desktop BIOS");
-
-	{
-		let mut hw = HARDWARE.lock().unwrap();
-		*hw = Some(Hardware {
-			boot_time: std::time::Instant::now(),
-			disk_file: args
-				.disk
-				.map(|path| std::fs::File::open(path).expect("open disk file")),
+	let mut command = config::with_env(Args::command(), NOT_FROM_ENV);
+	// The profile's config file changes the defaults, so we look for the
+	// profile before we parse the command line properly
+	let profile = command
+		.clone()
+		.ignore_errors(true)
+		.try_get_matches()
+		.ok()
+		.and_then(|matches| matches.get_one::<String>("profile").cloned())
+		.map(|name| match profile::Profile::open_default(&name) {
+			Ok(profile) => profile,
+			Err(e) => {
+				eprintln!("Profile error: {}", e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
+			}
+		});
+	let file = profile.as_ref().map(|profile| {
+		let file = config::File::load(&profile.config_path()).and_then(|file| {
+			command = config::with_file(command.clone(), &file, NOT_IN_FILE)?;
+			Ok(file)
 		});
+		match file {
+			Ok(file) => file,
+			Err(e) => {
+				eprintln!("Profile error: {}", e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
+			}
+		}
+	});
+	let matches = command.clone().get_matches();
+	let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+	// The numbered variables win over the config file, but not the command line
+	if matches.value_source("i2c_device") != Some(ValueSource::CommandLine) {
+		if let Some(devices) = from_env("i2c-device", i2c::parse_device) {
+			args.i2c_device = devices;
+		}
+	}
+	if matches.value_source("bus_device") != Some(ValueSource::CommandLine) {
+		if let Some(devices) = from_env("bus-device", bus::parse_device) {
+			args.bus_device = devices;
+		}
+	}
+	if let (Some(ValueSource::DefaultValue), Some(profile)) =
+		(matches.value_source("device"), profile.as_ref())
+	{
+		// These came from the profile's config file
+		for device in args.device.iter_mut() {
+			device.relative_to(profile.dir());
+		}
+	}
+	if matches.value_source("device") != Some(ValueSource::CommandLine) {
+		if let Some(devices) = from_env("device", |text| {
+			device::parse(text).map_err(|e| e.to_string())
+		}) {
+			args.device = devices;
+		}
+	}
+	let (disk_read_only, transfer) = attach_devices(&mut args);
+	if let Some(profile) = profile.clone() {
+		profile::set_active(profile);
 	}
 
-	let white_on_black = common::video::Attr::new(
-		common::video::TextForegroundColour::White,
-		common::video::TextBackgroundColour::Black,
-		false,
-	);
-	for char_idx in 0..(80 * 60) {
-		// Blank
-		FRAMEBUFFER.write_at(char_idx * 2, b' ');
-		// White on Black
-		FRAMEBUFFER.write_at((char_idx * 2) + 1, white_on_black.as_u8());
+	if let Some(Command::Completions { shell }) = args.command {
+		let name = command.get_name().to_string();
+		clap_complete::generate(shell, &mut command.clone(), name, &mut std::io::stdout());
+		return;
 	}
 
-	// Process args
-	info!("Loading OS from: {}", args.os.display());
-	let lib = unsafe { libloading::Library::new(args.os).expect("library to load") };
-	println!("Loaded!");
+	if args.dump_config {
+		config::dump(&config::settings(
+			&command,
+			&matches,
+			INDEXED_ENV,
+			file.as_ref(),
+		));
+		return;
+	}
 
-	if let Some(config_path) = args.nvram {
-		info!("Loading OS config from: {}", config_path.display());
-		*CONFIG_FILE_PATH.lock().unwrap() = Some(config_path);
+	if args.list_modes {
+		video::list_modes();
+		return;
 	}
 
-	let default_mode = unsafe { common::video::Mode::from_u8(0) };
-	let width = (default_mode.horizontal_pixels() as f32) * SCALE_FACTOR;
-	let height = (default_mode.vertical_lines() as f32) * SCALE_FACTOR;
-	info!("Default Window set to {} x {}", width, height);
+	if args.list_profiles {
+		list_profiles(profile.as_ref());
+		return;
+	}
 
-	// Make a window
-	let mut engine = Engine::builder()
-		.dimensions(width as u32, height as u32)
-		.scale(SCALE_FACTOR, SCALE_FACTOR)
-		.title("Neotron Desktop BIOS")
-		.show_frame_rate()
-		.target_frame_rate(60)
-		.build()
-		.unwrap();
-	let (sender, receiver) = mpsc::channel();
-	let mut app = MyApp {
-		mode: default_mode,
-		font8x16: Vec::new(),
-		font8x8: Vec::new(),
-		sender,
-		reset: true,
-	};
+	if args.headless {
+		smoke::go_headless();
+	}
 
-	EV_QUEUE.lock().unwrap().replace(receiver);
+	let nvram_path = args
+		.nvram
+		.clone()
+		.or_else(|| profile.as_ref().map(profile::Profile::nvram_path))
+		.or_else(nvram::default_path);
+	if let Some(path) = nvram_path.clone() {
+		nvram::set_path(path);
+	}
+	if let Some(size) = args.nvram_size {
+		nvram::set_size(size);
+	}
+	if let Some(boot_args) = args.os_args.clone() {
+		info!("Boot arguments: {:?}", boot_args);
+		nvram::set_boot_args(boot_args);
+	}
+	if let Some(corruption) = args.nvram_corrupt {
+		warn!("NVRAM will be corrupted on read: {:?}", corruption);
+		nvram::set_corruption(corruption);
+	}
+	if let Some(Command::Nvram { action }) = args.command.as_ref() {
+		if let Err(e) = nvram::run(action) {
+			eprintln!("NVRAM error: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+		return;
+	}
+	if let Some(Command::Disk { action }) = args.command.as_ref() {
+		if let Err(e) = disk::run(action) {
+			eprintln!("Disk error: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+		return;
+	}
 
-	// Run the OS
-	std::thread::spawn(move || unsafe {
-		// Wait for Started message
-		let queue = EV_QUEUE.lock().unwrap();
-		let ev = queue.as_ref().unwrap().recv().unwrap();
-		assert_eq!(ev, AppEvent::Started);
-		drop(queue);
-		info!("Video init complete. OS starting...");
-		let main_func: libloading::Symbol<unsafe extern "C" fn(api: &'static common::Api) -> !> =
-			lib.get(b"os_main").expect("os_main() not found");
-		main_func(&BIOS_API);
+	let eeprom = args.i2c_eeprom.clone().map(|eeprom| i2c::DeviceSpec {
+		bus: 0,
+		address: args.i2c_eeprom_address,
+		kind: i2c::DeviceKind::Eeprom(eeprom),
 	});
+	let conflicts = conflicts::check(&conflicts::Options {
+		disk: args.disk.clone(),
+		nvram: nvram_path.clone(),
+		os: args.os.clone(),
+		resume: args.resume.clone(),
+		snapshot_file: Some(args.snapshot_file.clone()),
+		trace_api_file: args.trace_api_file.clone(),
+		trace_bus_file: args.trace_bus_file.clone(),
+		trace_timeline: args.trace_timeline.clone(),
+		audio_backend: args.audio_backend,
+		audio_device: args.audio_device.clone(),
+		audio_input: args.audio_input.clone(),
+		audio_input_device: args.audio_input_device.clone(),
+		isolate: args.isolate,
+		i2c_eeprom: eeprom.clone(),
+		i2c_devices: args.i2c_device.clone(),
+		bus_devices: args.bus_device.clone(),
+	});
+	if args.check {
+		preflight(&args, transfer.as_ref(), eeprom.as_ref(), &conflicts);
+	}
+	if !conflicts.is_empty() {
+		for conflict in &conflicts {
+			eprintln!("error: {}", conflict);
+		}
+		std::process::exit(shutdown::ExitCode::BiosError.code());
+	}
 
-	engine.run(&mut app).unwrap();
-}
-
-/// Returns the version number of the BIOS API.
-extern "C" fn api_version_get() -> common::Version {
-	debug!("api_version_get()");
-	common::API_VERSION
-}
-
-/// Returns a pointer to a static string slice containing the BIOS Version.
-///
-/// This string contains the version number and build string of the BIOS.
-/// For C compatibility this string is null-terminated and guaranteed to
-/// only contain ASCII characters (bytes with a value 127 or lower). We
-/// also pass the length (excluding the null) to make it easy to construct
-/// a Rust string. It is unspecified as to whether the string is located
-/// in Flash ROM or RAM (but it's likely to be Flash ROM).
-extern "C" fn bios_version_get() -> common::FfiString<'static> {
-	debug!("bios_version_get()");
-	common::FfiString::new("Neotron Desktop BIOS\0")
-}
-
-/// Get information about the Serial ports in the system.
-///
-/// Serial ports are ordered octet-oriented pipes. You can push octets
-/// into them using a 'write' call, and pull bytes out of them using a
-/// 'read' call. They have options which allow them to be configured at
-/// different speeds, or with different transmission settings (parity
-/// bits, stop bits, etc) - you set these with a call to
-/// `SerialConfigure`. They may physically be a MIDI interface, an RS-232
-/// port or a USB-Serial port. There is no sense of 'open' or 'close' -
-/// that is an Operating System level design feature. These APIs just
-/// reflect the raw hardware, in a similar manner to the registers exposed
-/// by a memory-mapped UART peripheral.
-extern "C" fn serial_get_info(_device: u8) -> common::FfiOption<common::serial::DeviceInfo> {
-	debug!("serial_get_info()");
-	common::FfiOption::None
-}
-
-/// Set the options for a given serial device. An error is returned if the
-/// options are invalid for that serial device.
-extern "C" fn serial_configure(
-	_device: u8,
-	_config: common::serial::Config,
-) -> common::ApiResult<()> {
-	debug!("serial_configure()");
-	Err(common::Error::Unimplemented).into()
-}
-
-/// Write bytes to a serial port. There is no sense of 'opening' or
-/// 'closing' the device - serial devices are always open. If the return
-/// value is `Ok(n)`, the value `n` may be less than the size of the given
-/// buffer. If so, that means not all of the data could be transmitted -
-/// only the first `n` bytes were.
-extern "C" fn serial_write(
-	_device: u8,
-	_data: common::FfiByteSlice,
-	_timeout: common::FfiOption<common::Timeout>,
-) -> common::ApiResult<usize> {
-	debug!("serial_write()");
-	Err(common::Error::Unimplemented).into()
-}
-
-/// Read bytes from a serial port. There is no sense of 'opening' or
-/// 'closing' the device - serial devices are always open. If the return value
-///  is `Ok(n)`, the value `n` may be less than the size of the given buffer.
-///  If so, that means not all of the data could be received - only the
-///  first `n` bytes were filled in.
-extern "C" fn serial_read(
-	_device: u8,
-	_data: common::FfiBuffer,
-	_timeout: common::FfiOption<common::Timeout>,
-) -> common::ApiResult<usize> {
-	debug!("serial_read()");
-	Err(common::Error::Unimplemented).into()
-}
-
-/// Get the current wall time.
-///
-/// The Neotron BIOS does not understand time zones, leap-seconds or the
-/// Gregorian calendar. It simply stores time as an incrementing number of
-/// seconds since some epoch, and the number of milliseconds since that second
-/// began. A day is assumed to be exactly 86,400 seconds long. This is a lot
-/// like POSIX time, except we have a different epoch - the Neotron epoch is
-/// 2000-01-01T00:00:00Z. It is highly recommend that you store UTC in the BIOS
-/// and use the OS to handle time-zones.
-///
-/// If the BIOS does not have a battery-backed clock, or if that battery has
-/// failed to keep time, the system starts up assuming it is the epoch.
-extern "C" fn time_clock_get() -> common::Time {
-	debug!("time_clock_get()");
-	// 946684800 seconds between 2000-01-01 and 1970-01-01
-	let epoch = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(946684800);
-	let difference = epoch.elapsed().unwrap_or_default();
-	// We're good until 2068, when I shall be retired.
-	assert!(difference.as_secs() <= u64::from(u32::MAX));
-	common::Time {
-		secs: difference.as_secs() as u32,
-		nsecs: difference.subsec_nanos(),
+	for spec in eeprom.iter().chain(&args.i2c_device) {
+		if let Err(e) = i2c::add_device(spec) {
+			eprintln!("I2C error: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
 	}
-}
 
-/// Set the current wall time.
-///
-/// See `time_get` for a description of now the Neotron BIOS should handle
-/// time.
-///
-/// You only need to call this whenever you get a new sense of the current
-/// time (e.g. the user has updated the current time, or if you get a GPS
-/// fix). The BIOS should push the time out to the battery-backed Real
-/// Time Clock, if it has one.
-extern "C" fn time_clock_set(time: common::Time) {
-	debug!("time_clock_set({:?})", time);
-}
+	for spec in &args.bus_device {
+		if let Err(e) = bus::add_device(spec) {
+			eprintln!("Bus error: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+	}
 
-/// Get the configuration data block.
-///
-/// Configuration data is, to the BIOS, just a block of bytes of a given
-/// length. How it stores them is up to the BIOS - it could be EEPROM, or
-/// battery-backed SRAM.
-extern "C" fn configuration_get(mut os_buffer: common::FfiBuffer) -> common::ApiResult<usize> {
-	let file_path = CONFIG_FILE_PATH.lock().unwrap().clone();
-	let Some(os_buffer) = os_buffer.as_mut_slice() else {
-		return common::ApiResult::Err(common::Error::DeviceError);
-	};
-	match file_path.as_ref() {
-		Some(path) => match std::fs::read(path) {
-			Ok(read_data) => {
-				for (src, dest) in read_data.iter().zip(os_buffer.iter_mut()) {
-					*dest = *src;
-				}
-				common::ApiResult::Ok(read_data.len())
-			}
-			Err(_e) => {
-				println!("Failed to get config from {:?}", path);
-				common::ApiResult::Err(common::Error::DeviceError)
+	for (port, spec) in serial_ports(&args) {
+		if let Err(e) = serial::add_port(port, spec) {
+			eprintln!("Serial error: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+	}
+	if args.trace_bus {
+		match trace::Trace::new(args.trace_bus_file.as_deref(), args.trace_bytes) {
+			Ok(trace) => bus::start_trace(trace),
+			Err(e) => {
+				eprintln!("Failed to create bus trace: {}", e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
 			}
-		},
-		None => common::ApiResult::Err(common::Error::Unimplemented),
+		}
 	}
-}
 
-/// Set the configuration data block.
-///
-/// See `configuration_get`.
-extern "C" fn configuration_set(buffer: common::FfiByteSlice) -> common::ApiResult<()> {
-	let file_path = CONFIG_FILE_PATH.lock().unwrap().clone();
-	match file_path.as_ref() {
-		Some(path) => match std::fs::write(path, buffer.as_slice()) {
-			Ok(_) => common::ApiResult::Ok(()),
-			Err(_e) => {
-				println!("Failed to write config to {:?}", path);
-				common::ApiResult::Err(common::Error::DeviceError)
+	if args.trace_api {
+		match trace::Trace::new(args.trace_api_file.as_deref(), args.trace_bytes) {
+			Ok(trace) => {
+				apitrace::start_trace(trace, args.trace_api_exclude.clone(), args.trace_api_limit)
 			}
-		},
-		None => common::ApiResult::Err(common::Error::Unimplemented),
+			Err(e) => {
+				eprintln!("Failed to create API trace: {}", e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
+			}
+		}
 	}
-}
 
-/// Does this Neotron BIOS support this video mode?
-extern "C" fn video_is_valid_mode(mode: common::video::Mode) -> bool {
-	let result = match mode.as_u8() {
-		// 640x480 80x30 text mode
-		0 => true,
-		// 640x480 80x60 text mode
-		1 => true,
-		// 640x480, 8-bpp bitmap mode
-		4 => true,
-		// 640x480, 4-bpp bitmap mode
-		5 => true,
-		// 640x480, 2-bpp bitmap mode
-		6 => true,
-		// 640x480, 1-bpp bitmap mode
-		7 => true,
-		// nothing else will work
-		_ => false,
+	let machine = inventory::Machine {
+		disk: args.disk.clone(),
+		disk_read_only,
+		transfer: transfer.clone(),
+		ram2_size: args.ram2_size,
+		guard_pages: args.guard_pages,
+		isolate: args.isolate,
+		audio_backend: args.audio_backend,
+		audio_device: args.audio_device.clone(),
+		audio_input: args
+			.audio_input
+			.clone()
+			.map(|path| (path, args.audio_input_loop)),
+		audio_input_device: args.audio_input_device.clone(),
+		audio_latency_ms: args.audio_latency,
+		volume: args.volume,
+		devices: attached(&args, disk_read_only, transfer.as_ref(), eeprom.as_ref()),
 	};
-	debug!("video_is_valid_mode({:?}) = {}", mode, result);
-	result
-}
-
-/// Switch to a new video mode.
-///
-/// The contents of the screen are undefined after a call to this function.
-extern "C" fn video_set_mode(mode: common::video::Mode, fb: *mut u32) -> common::ApiResult<()> {
-	info!("video_set_mode({:?})", mode);
-	if !video_is_valid_mode(mode) {
-		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
-	}
-	// We know this is a valid video mode because it was set with `video_set_mode`.
-	let mode_value = mode.as_u8();
-	VIDEO_MODE.store(mode_value, Ordering::Relaxed);
-	FRAMEBUFFER.alt_pointer.store(fb, Ordering::Relaxed);
-	common::ApiResult::Ok(())
-}
-
-/// Returns the video mode the BIOS is currently in.
-///
-/// The OS should call this function immediately after start-up and note
-/// the value - this is the `default` video mode which can always be
-/// serviced without supplying extra RAM.
-extern "C" fn video_get_mode() -> common::video::Mode {
-	debug!("video_get_mode()");
-	let mode_value = VIDEO_MODE.load(Ordering::Relaxed);
-	// We know this is a valid video mode because it was set with `video_set_mode`.
-	unsafe { common::video::Mode::from_u8(mode_value) }
-}
-
-/// Get the framebuffer address.
-///
-/// We can write through this address to the video framebuffer. The
-/// meaning of the data we write, and the size of the region we are
-/// allowed to write to, is a function of the current video mode (see
-/// `video_get_mode`).
-extern "C" fn video_get_framebuffer() -> *mut u32 {
-	let p = FRAMEBUFFER.get_pointer();
-	debug!("video_get_framebuffer() -> {:p}", p);
-	p
-}
-
-/// Find out whether the given video mode needs more VRAM than we currently have.
-///
-/// The answer is no for any currently supported video mode (which is just the four text modes right now).
-extern "C" fn video_mode_needs_vram(_mode: common::video::Mode) -> bool {
-	debug!("video_mode_needs_vram()");
-	false
-}
-
-/// Find out how large a given region of memory is.
-///
-/// The first region is the 'main application region' and is defined to always
-/// start at address `0x2000_0000` on a standard Cortex-M system. This
-/// application region stops just before the BIOS reserved memory, at the top of
-/// the internal SRAM. The OS will have been linked to use the first 1 KiB of
-/// this region.
-///
-/// Other regions may be located at other addresses (e.g. external DRAM or
-/// PSRAM).
-///
-/// The OS will always load non-relocatable applications into the bottom of
-/// Region 0. It can allocate OS specific structures from any other Region (if
-/// any), or from the top of Region 0 (although this reduces the maximum
-/// application space available). The OS will prefer lower numbered regions
-/// (other than Region 0), so faster memory should be listed first.
-///
-/// If the region number given is invalid, the function returns `(null, 0)`.
-extern "C" fn memory_get_region(region: u8) -> common::FfiOption<common::MemoryRegion> {
-	static mut MEMORY_BLOCK: (*mut u8, usize) = (std::ptr::null_mut(), 0);
-	match region {
-		0 => {
-			if unsafe { MEMORY_BLOCK.0.is_null() } {
-				// Allocate 1 MiB of storage space for the OS to use
-				let mut data = Box::new([0u8; 1024 * 1024]);
-				unsafe {
-					MEMORY_BLOCK.0 = data.as_mut_ptr();
-					MEMORY_BLOCK.1 = std::mem::size_of_val(&*data);
-				}
-				std::mem::forget(data);
+	if args.list_audio || args.list_devices {
+		if args.list_devices {
+			for line in inventory::describe(&machine) {
+				println!("{}", line);
 			}
-			common::FfiOption::Some(common::MemoryRegion {
-				start: unsafe { MEMORY_BLOCK.0 },
-				length: unsafe { MEMORY_BLOCK.1 },
-				kind: common::FfiMemoryKind::from(common::MemoryKind::Ram),
-			})
 		}
-		_ => common::FfiOption::None,
+		audio::list_devices();
+		return;
 	}
-}
 
-/// Get the next available HID event, if any.
-///
-/// This function doesn't block. It will return `Ok(None)` if there is no event ready.
-extern "C" fn hid_get_event() -> common::ApiResult<common::FfiOption<common::hid::HidEvent>> {
-	let queue = EV_QUEUE.lock().unwrap();
-	match queue.as_ref().unwrap().try_recv() {
-		Ok(AppEvent::KeyUp(key)) => {
-			let code = common::hid::HidEvent::KeyRelease(convert_keycode(key));
-			debug!("hid_get_event() -> {:?}", code);
-			common::ApiResult::Ok(common::FfiOption::Some(code))
-		}
-		Ok(AppEvent::KeyDown(key)) => {
-			let code = common::hid::HidEvent::KeyPress(convert_keycode(key));
-			debug!("hid_get_event() -> {:?}", code);
-			common::ApiResult::Ok(common::FfiOption::Some(code))
-		}
-		_ => common::ApiResult::Ok(common::FfiOption::None),
+	// Let's go!
+	info!("Netron Desktop BIOS");
+	for line in inventory::describe(&machine) {
+		info!("{}", line);
 	}
-}
 
-/// Convert a pix-engine keycode into a Neotron BIOS keycode
-fn convert_keycode(key: Key) -> common::hid::KeyCode {
-	match key {
-		Key::Backspace => common::hid::KeyCode::Backspace,
-		Key::Tab => common::hid::KeyCode::Tab,
-		Key::Return => common::hid::KeyCode::Return,
-		Key::Escape => common::hid::KeyCode::Escape,
-		Key::Space => common::hid::KeyCode::Spacebar,
-		// Key::Exclaim => common::hid::KeyCode::Exclaim,
-		// Key::Quotedbl => common::hid::KeyCode::Quotedbl,
-		Key::Hash => common::hid::KeyCode::Oem7,
-		// Key::Dollar => common::hid::KeyCode::Dollar,
-		// Key::Percent => common::hid::KeyCode::Percent,
-		// Key::Ampersand => common::hid::KeyCode::Ampersand,
-		Key::Quote => common::hid::KeyCode::Oem3,
-		// Key::LeftParen => common::hid::KeyCode::LeftParen,
-		// Key::RightParen => common::hid::KeyCode::RightParen,
-		// Key::Asterisk => common::hid::KeyCode::Asterisk,
-		// Key::Plus => common::hid::KeyCode::Plus,
-		Key::Comma => common::hid::KeyCode::OemComma,
-		Key::Minus => common::hid::KeyCode::OemMinus,
-		Key::Period => common::hid::KeyCode::OemPeriod,
-		Key::Slash => common::hid::KeyCode::Oem2,
-		Key::Num0 => common::hid::KeyCode::Key0,
-		Key::Num1 => common::hid::KeyCode::Key1,
-		Key::Num2 => common::hid::KeyCode::Key2,
-		Key::Num3 => common::hid::KeyCode::Key3,
-		Key::Num4 => common::hid::KeyCode::Key4,
-		Key::Num5 => common::hid::KeyCode::Key5,
-		Key::Num6 => common::hid::KeyCode::Key6,
-		Key::Num7 => common::hid::KeyCode::Key7,
-		Key::Num8 => common::hid::KeyCode::Key8,
-		Key::Num9 => common::hid::KeyCode::Key9,
-		// Key::Colon => common::hid::KeyCode::Colon,
-		Key::Semicolon => common::hid::KeyCode::Oem1,
-		// Key::Less => common::hid::KeyCode::Less,
-		Key::Equals => common::hid::KeyCode::OemPlus,
-		// Key::Greater => common::hid::KeyCode::Greater,
-		// Key::Question => common::hid::KeyCode::Question,
-		// Key::At => common::hid::KeyCode::At,
-		Key::LeftBracket => common::hid::KeyCode::Oem4,
-		Key::Backslash => common::hid::KeyCode::Oem5,
-		Key::RightBracket => common::hid::KeyCode::Oem6,
-		// Key::Caret => common::hid::KeyCode::Caret,
-		// Key::Underscore => common::hid::KeyCode::Underscore,
-		Key::Backquote => common::hid::KeyCode::Oem8,
-		Key::A => common::hid::KeyCode::A,
-		Key::B => common::hid::KeyCode::B,
-		Key::C => common::hid::KeyCode::C,
-		Key::D => common::hid::KeyCode::D,
-		Key::E => common::hid::KeyCode::E,
-		Key::F => common::hid::KeyCode::F,
-		Key::G => common::hid::KeyCode::G,
-		Key::H => common::hid::KeyCode::H,
-		Key::I => common::hid::KeyCode::I,
-		Key::J => common::hid::KeyCode::J,
-		Key::K => common::hid::KeyCode::K,
-		Key::L => common::hid::KeyCode::L,
-		Key::M => common::hid::KeyCode::M,
-		Key::N => common::hid::KeyCode::N,
-		Key::O => common::hid::KeyCode::O,
-		Key::P => common::hid::KeyCode::P,
-		Key::Q => common::hid::KeyCode::Q,
-		Key::R => common::hid::KeyCode::R,
-		Key::S => common::hid::KeyCode::S,
-		Key::T => common::hid::KeyCode::T,
-		Key::U => common::hid::KeyCode::U,
-		Key::V => common::hid::KeyCode::V,
-		Key::W => common::hid::KeyCode::W,
-		Key::X => common::hid::KeyCode::X,
-		Key::Y => common::hid::KeyCode::Y,
-		Key::Z => common::hid::KeyCode::Z,
-		Key::Delete => common::hid::KeyCode::Delete,
-		Key::CapsLock => common::hid::KeyCode::CapsLock,
-		Key::F1 => common::hid::KeyCode::F1,
-		Key::F2 => common::hid::KeyCode::F2,
-		Key::F3 => common::hid::KeyCode::F3,
-		Key::F4 => common::hid::KeyCode::F4,
-		Key::F5 => common::hid::KeyCode::F5,
-		Key::F6 => common::hid::KeyCode::F6,
-		Key::F7 => common::hid::KeyCode::F7,
-		Key::F8 => common::hid::KeyCode::F8,
-		Key::F9 => common::hid::KeyCode::F9,
-		Key::F10 => common::hid::KeyCode::F10,
-		Key::F11 => common::hid::KeyCode::F11,
-		Key::F12 => common::hid::KeyCode::F12,
-		Key::PrintScreen => common::hid::KeyCode::PrintScreen,
-		Key::ScrollLock => common::hid::KeyCode::ScrollLock,
-		Key::Pause => common::hid::KeyCode::PauseBreak,
-		Key::Insert => common::hid::KeyCode::Insert,
-		Key::Home => common::hid::KeyCode::Home,
-		Key::PageUp => common::hid::KeyCode::PageUp,
-		Key::End => common::hid::KeyCode::End,
-		Key::PageDown => common::hid::KeyCode::PageDown,
-		Key::Right => common::hid::KeyCode::ArrowRight,
-		Key::Left => common::hid::KeyCode::ArrowLeft,
-		Key::Down => common::hid::KeyCode::ArrowDown,
-		Key::Up => common::hid::KeyCode::ArrowUp,
-		Key::NumLock => common::hid::KeyCode::NumpadLock,
-		Key::KpDivide => common::hid::KeyCode::NumpadDivide,
-		Key::KpMultiply => common::hid::KeyCode::NumpadMultiply,
-		Key::KpMinus => common::hid::KeyCode::NumpadSubtract,
-		Key::KpPlus => common::hid::KeyCode::NumpadAdd,
-		Key::KpEnter => common::hid::KeyCode::NumpadEnter,
-		Key::Kp1 => common::hid::KeyCode::Numpad1,
-		Key::Kp2 => common::hid::KeyCode::Numpad2,
-		Key::Kp3 => common::hid::KeyCode::Numpad3,
-		Key::Kp4 => common::hid::KeyCode::Numpad4,
-		Key::Kp5 => common::hid::KeyCode::Numpad5,
-		Key::Kp6 => common::hid::KeyCode::Numpad6,
-		Key::Kp7 => common::hid::KeyCode::Numpad7,
-		Key::Kp8 => common::hid::KeyCode::Numpad8,
-		Key::Kp9 => common::hid::KeyCode::Numpad9,
-		Key::Kp0 => common::hid::KeyCode::Numpad0,
-		Key::KpPeriod => common::hid::KeyCode::NumpadPeriod,
-		// Key::KpEquals => common::hid::KeyCode::KpEquals,
-		// Key::KpComma => common::hid::KeyCode::KpComma,
-		Key::LCtrl => common::hid::KeyCode::LControl,
-		Key::LShift => common::hid::KeyCode::LShift,
-		Key::LAlt => common::hid::KeyCode::LAlt,
-		Key::LGui => common::hid::KeyCode::LWin,
-		Key::RCtrl => common::hid::KeyCode::RControl,
-		Key::RShift => common::hid::KeyCode::RShift,
-		Key::RAlt => common::hid::KeyCode::RAltGr,
-		Key::RGui => common::hid::KeyCode::RWin,
-		_ => common::hid::KeyCode::X,
+	if let Some(path) = &args.trace_timeline {
+		timeline::start(path.clone());
 	}
-}
-
-/// Control the keyboard LEDs.
-extern "C" fn hid_set_leds(_leds: common::hid::KeyboardLeds) -> common::ApiResult<()> {
-	debug!("hid_set_leds()");
-	Err(common::Error::Unimplemented).into()
-}
-
-/// Wait for the next occurence of the specified video scan-line.
-///
-/// In general we must assume that the video memory is read top-to-bottom
-/// as the picture is being drawn on the monitor (e.g. via a VGA video
-/// signal). If you modify video memory during this *drawing period*
-/// there is a risk that the image on the monitor (however briefly) may
-/// contain some parts from before the modification and some parts from
-/// after. This can given rise to the *tearing effect* where it looks
-/// like the screen has been torn (or ripped) across because there is a
-/// discontinuity part-way through the image.
-///
-/// This function busy-waits until the video drawing has reached a
-/// specified scan-line on the video frame.
-///
-/// There is no error code here. If the line you ask for is beyond the
-/// number of visible scan-lines in the current video mode, it waits util
-/// the last visible scan-line is complete.
-///
-/// If you wait for the last visible line until drawing, you stand the
-/// best chance of your pixels operations on the video RAM being
-/// completed before scan-lines start being sent to the monitor for the
-/// next frame.
-///
-/// You can also use this for a crude `16.7 ms` delay but note that
-/// some video modes run at `70 Hz` and so this would then give you a
-/// `14.3ms` second delay.
-extern "C" fn video_wait_for_line(_line: u16) {
-	debug!("video_wait_for_line()");
-	// TODO
-}
-
-extern "C" fn video_get_palette(index: u8) -> common::FfiOption<common::video::RGBColour> {
-	debug!("video_get_palette({})", index);
-	let entry = PALETTE.get(usize::from(index));
-	let entry_value =
-		entry.map(|raw| common::video::RGBColour::from_packed(raw.load(Ordering::Relaxed)));
-	match entry_value {
-		Some(rgb) => common::FfiOption::Some(rgb),
-		None => common::FfiOption::None,
+	clock::start(args.time_scale);
+	apistats::start();
+	if args.video_stats {
+		videostats::start_logging();
 	}
-}
-
-extern "C" fn video_set_palette(index: u8, rgb: common::video::RGBColour) {
-	debug!("video_set_palette({}, #{:6x})", index, rgb.as_packed());
-	if let Some(e) = PALETTE.get(usize::from(index)) {
-		e.store(rgb.as_packed(), Ordering::Relaxed);
+	if let Some(interval) = args.heartbeat {
+		heartbeat::start(interval);
 	}
-}
-
-unsafe extern "C" fn video_set_whole_palette(
-	palette: *const common::video::RGBColour,
-	length: usize,
-) {
-	debug!("video_set_whole_palette({:p}, {})", palette, length);
-	let slice = std::slice::from_raw_parts(palette, length);
-	for (entry, new_rgb) in PALETTE.iter().zip(slice) {
-		entry.store(new_rgb.as_packed(), Ordering::Relaxed);
+	let disk = args.disk.as_ref().map(|path| match disk::open(path) {
+		Ok(file) => file,
+		Err(e) => {
+			eprintln!("Can't use the disk image: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+	});
+	block::set_read_only(disk_read_only);
+	if let Some(spec) = &transfer {
+		if let Err(e) = transfer::attach(spec) {
+			eprintln!("Can't attach the transfer device: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
 	}
-}
-
-extern "C" fn i2c_bus_get_info(_i2c_bus: u8) -> common::FfiOption<common::i2c::BusInfo> {
-	debug!("i2c_bus_get_info");
-	common::FfiOption::None
-}
-
-extern "C" fn i2c_write_read(
-	_i2c_bus: u8,
-	_i2c_device_address: u8,
-	_tx: common::FfiByteSlice,
-	_tx2: common::FfiByteSlice,
-	_rx: common::FfiBuffer,
-) -> common::ApiResult<()> {
-	debug!("i2c_write_read");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_mixer_channel_get_info(
-	_audio_mixer_id: u8,
-) -> common::FfiOption<common::audio::MixerChannelInfo> {
-	debug!("audio_mixer_channel_get_info");
-	common::FfiOption::None
-}
-
-extern "C" fn audio_mixer_channel_set_level(
-	_audio_mixer_id: u8,
-	_level: u8,
-) -> common::ApiResult<()> {
-	debug!("audio_mixer_channel_set_level");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_output_set_config(_config: common::audio::Config) -> common::ApiResult<()> {
-	debug!("audio_output_set_config");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
+	let sender = neotron_desktop_bios::power_on(disk);
 
-extern "C" fn audio_output_get_config() -> common::ApiResult<common::audio::Config> {
-	debug!("audio_output_get_config");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-unsafe extern "C" fn audio_output_data(_samples: common::FfiByteSlice) -> common::ApiResult<usize> {
-	debug!("audio_output_data");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_output_get_space() -> common::ApiResult<usize> {
-	debug!("audio_output_get_space");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_input_set_config(_config: common::audio::Config) -> common::ApiResult<()> {
-	debug!("audio_input_set_config");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_input_get_config() -> common::ApiResult<common::audio::Config> {
-	debug!("audio_input_get_config");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_input_data(_samples: common::FfiBuffer) -> common::ApiResult<usize> {
-	debug!("audio_input_data");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn audio_input_get_count() -> common::ApiResult<usize> {
-	debug!("audio_input_get_count");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn bus_select(_periperal_id: common::FfiOption<u8>) {
-	debug!("bus_select");
-}
-
-extern "C" fn bus_get_info(_periperal_id: u8) -> common::FfiOption<common::bus::PeripheralInfo> {
-	debug!("bus_get_info");
-	common::FfiOption::None
-}
-
-extern "C" fn bus_write_read(
-	_tx: common::FfiByteSlice,
-	_tx2: common::FfiByteSlice,
-	_rx: common::FfiBuffer,
-) -> common::ApiResult<()> {
-	debug!("bus_write_read");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn bus_exchange(_buffer: common::FfiBuffer) -> common::ApiResult<()> {
-	debug!("bus_exchange");
-	common::ApiResult::Err(common::Error::Unimplemented)
-}
-
-extern "C" fn time_ticks_get() -> common::Ticks {
-	let mut hw_guard = HARDWARE.lock().unwrap();
-	let hw = hw_guard.as_mut().unwrap();
-	let boot_time = hw.boot_time;
-	let difference = boot_time.elapsed();
-	debug!("time_ticks_get() -> {}", difference.as_millis());
-	common::Ticks(difference.as_millis() as u64)
-}
-
-/// We simulate a 1 kHz tick
-extern "C" fn time_ticks_per_second() -> common::Ticks {
-	debug!("time_ticks_per_second()");
-	common::Ticks(1000)
-}
-
-extern "C" fn bus_interrupt_status() -> u32 {
-	debug!("bus_interrupt_status()");
-	0
-}
-
-extern "C" fn block_dev_get_info(dev_id: u8) -> common::FfiOption<common::block_dev::DeviceInfo> {
-	debug!("block_dev_get_info(dev_id: {})", dev_id);
-	let mut hw_guard = HARDWARE.lock().unwrap();
-	let hw = hw_guard.as_mut().unwrap();
-	if dev_id == 0 {
-		match &mut hw.disk_file {
-			Some(file) => common::FfiOption::Some(common::block_dev::DeviceInfo {
-				name: common::FfiString::new("File0"),
-				device_type: common::block_dev::DeviceType::HardDiskDrive.into(),
-				block_size: BLOCK_SIZE as u32,
-				num_blocks: file.metadata().unwrap().len() / (BLOCK_SIZE as u64),
-				ejectable: false,
-				removable: false,
-				media_present: true,
-				read_only: false,
-			}),
-			None => common::FfiOption::None,
+	// Process args
+	let (os_path, main_func) = match loader::choose(&args.os) {
+		Ok(chosen) => chosen,
+		Err(e) => {
+			eprintln!("Can't load the OS: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
 		}
-	} else {
-		common::FfiOption::None
+	};
+	println!("Loaded {}", os_path.display());
+	if args.wait {
+		attach::announce(&os_path, main_func);
 	}
-}
-
-extern "C" fn block_dev_eject(dev_id: u8) -> common::ApiResult<()> {
-	debug!("block_dev_eject(dev_id: {})", dev_id);
-	common::ApiResult::Ok(())
-}
+	let title = match loader::os_version() {
+		Some(version) => format!("{} - {} ({})", WINDOW_TITLE, version, os_path.display()),
+		None => format!("{} - {}", WINDOW_TITLE, os_path.display()),
+	};
 
-extern "C" fn block_write(
-	dev_id: u8,
-	block_idx: common::block_dev::BlockIdx,
-	num_blocks: u8,
-	buffer: common::FfiByteSlice,
-) -> common::ApiResult<()> {
-	debug!(
-		"block_write(dev_id: {}, block_id: {}, num_blocks: {}, buffer_len: {})",
-		dev_id, block_idx.0, num_blocks, buffer.data_len
-	);
-	let mut hw_guard = HARDWARE.lock().unwrap();
-	let hw = hw_guard.as_mut().unwrap();
-	if dev_id == 0 {
-		match &mut hw.disk_file {
-			Some(file) => {
-				if file
-					.seek(std::io::SeekFrom::Start(block_idx.0 * BLOCK_SIZE as u64))
-					.is_err()
-				{
-					return common::ApiResult::Err(common::Error::BlockOutOfBounds);
-				}
-				let buffer_slice = &buffer.as_slice()[0..usize::from(num_blocks) * BLOCK_SIZE];
-				if let Err(e) = file.write_all(buffer_slice) {
-					log::warn!("Failed to write to disk image: {:?}", e);
-					return common::ApiResult::Err(common::Error::DeviceError);
-				}
-				common::ApiResult::Ok(())
+	let wav_input = args.audio_input.as_ref().map(|path| {
+		info!("Loading audio input from: {}", path.display());
+		match wav::Wav::load(path) {
+			Ok(wav) => audio::WavInput {
+				wav: std::sync::Arc::new(wav),
+				looping: args.audio_input_loop,
+			},
+			Err(e) => {
+				eprintln!("Failed to load {}: {}", path.display(), e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
 			}
-			None => common::ApiResult::Err(common::Error::DeviceError),
 		}
-	} else {
-		common::ApiResult::Err(common::Error::InvalidDevice)
-	}
-}
+	});
 
-extern "C" fn block_read(
-	dev_id: u8,
-	block_idx: common::block_dev::BlockIdx,
-	num_blocks: u8,
-	mut buffer: common::FfiBuffer,
-) -> common::ApiResult<()> {
-	debug!(
-		"block_read(dev_id: {}, block_id: {}, num_blocks: {}, buffer_len: {})",
-		dev_id, block_idx.0, num_blocks, buffer.data_len
-	);
-	let mut hw_guard = HARDWARE.lock().unwrap();
-	let hw = hw_guard.as_mut().unwrap();
-	if dev_id == 0 {
-		match &mut hw.disk_file {
-			Some(file) => {
-				if file
-					.seek(std::io::SeekFrom::Start(block_idx.0 * BLOCK_SIZE as u64))
-					.is_err()
-				{
-					return common::ApiResult::Err(common::Error::BlockOutOfBounds);
-				}
-				if let Some(buffer_slice) = buffer.as_mut_slice() {
-					let buffer_slice = &mut buffer_slice[0..usize::from(num_blocks) * BLOCK_SIZE];
-					if let Err(e) = file.read_exact(buffer_slice) {
-						log::warn!("Failed to read from disk image: {:?}", e);
-						return common::ApiResult::Err(common::Error::DeviceError);
-					}
-				}
-				common::ApiResult::Ok(())
-			}
-			None => common::ApiResult::Err(common::Error::DeviceError),
+	if args.debug_console {
+		console::start();
+	}
+	if let Some(address) = &args.monitor {
+		if let Err(e) = monitor::start(address) {
+			eprintln!("Can't start the monitor: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
 		}
-	} else {
-		common::ApiResult::Err(common::Error::InvalidDevice)
 	}
-}
-
-extern "C" fn block_verify(
-	dev_id: u8,
-	block_idx: common::block_dev::BlockIdx,
-	num_blocks: u8,
-	buffer: common::FfiByteSlice,
-) -> common::ApiResult<()> {
-	debug!(
-		"block_read(dev_id: {}, block_id: {}, num_blocks: {}, buffer_len: {})",
-		dev_id, block_idx.0, num_blocks, buffer.data_len
-	);
-	let mut hw_guard = HARDWARE.lock().unwrap();
-	let hw = hw_guard.as_mut().unwrap();
-	if dev_id == 0 {
-		match &mut hw.disk_file {
-			Some(file) => {
-				if file
-					.seek(std::io::SeekFrom::Start(block_idx.0 * BLOCK_SIZE as u64))
-					.is_err()
-				{
-					return common::ApiResult::Err(common::Error::BlockOutOfBounds);
-				}
-				let buffer_slice = &buffer.as_slice()[0..usize::from(num_blocks) * BLOCK_SIZE];
-				let mut read_buffer = vec![0u8; buffer_slice.len()];
-				if let Err(e) = file.read_exact(&mut read_buffer) {
-					log::warn!("Failed to write to disk image: {:?}", e);
-					return common::ApiResult::Err(common::Error::DeviceError);
-				}
-				if read_buffer.as_slice() == buffer_slice {
-					common::ApiResult::Ok(())
-				} else {
-					common::ApiResult::Err(common::Error::DeviceError)
-				}
-			}
-			None => common::ApiResult::Err(common::Error::DeviceError),
+	if let Some(address) = &args.remote {
+		if let Err(e) = remote::start(address) {
+			eprintln!("Can't start the remote-control socket: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+	}
+	if let Some(address) = args.vnc {
+		if let Err(e) = vnc::start(address) {
+			eprintln!("Can't start the VNC server: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+	}
+	if let Some(address) = args.metrics {
+		if let Err(e) = metrics::start(address) {
+			eprintln!("Can't start the metrics server: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
+		}
+	}
+	if let Some(address) = args.web_display {
+		if let Err(e) = webdisplay::start(address) {
+			eprintln!("Can't start the web display: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
 		}
-	} else {
-		common::ApiResult::Err(common::Error::InvalidDevice)
 	}
-}
-
-extern "C" fn power_idle() {
-	std::thread::sleep(std::time::Duration::from_millis(1));
-}
 
-extern "C" fn power_control(mode: common::FfiPowerMode) -> ! {
-	println!("Got power mode {:?}, but quitting...", mode);
-	std::process::exit(0);
-}
+	neotron_desktop_bios::set_cold_boot(args.cold_boot);
+	if args.strict_api {
+		lint::enable();
+	}
+	shutdown::set_fail_on_close(args.fail_on_close);
+	if let Some(limit) = args.exit_after {
+		smoke::set_limit(limit);
+	}
+	crash::install_hook(
+		args.exit_on_panic,
+		args.crash_dir.clone().or_else(crash::default_report_dir),
+	);
 
-extern "C" fn compare_and_swap_bool(
-	item: &std::sync::atomic::AtomicBool,
-	old_value: bool,
-	new_value: bool,
-) -> bool {
-	item.compare_exchange(old_value, new_value, Ordering::Relaxed, Ordering::Relaxed)
-		.is_ok()
-}
+	if let Some(share) = args.cpu_throttle {
+		info!("OS throttled to {}% of the CPU", share * 100.0);
+		throttle::set_share(share);
+	}
 
-// ===========================================================================
-// Impl Blocks
-// ===========================================================================
+	time::set_fine_ticks(args.fine_ticks);
 
-impl MyApp {
-	const NUM_FG: usize = 16;
+	info!("NVRAM: {}", nvram::describe());
+	nvram::check();
 
-	/// Generate an RGBA texture for each glyph, in each foreground colour.
-	///
-	/// We have 256 glyphs, in each of 16 colours, so this is expensive and
-	/// slow. But it makes rendering text acceptably fast.
-	fn render_font(
-		font: &font::Font,
-		texture_buffer: &mut Vec<TextureId>,
-		s: &mut PixState,
-	) -> PixResult<()> {
-		let mut slot = 0;
-		for glyph in 0..=255 {
-			for palette_entry in PALETTE.iter().take(Self::NUM_FG) {
-				let fg = RGBColour::from_packed(palette_entry.load(Ordering::Relaxed));
-				debug!(
-					"Drawing glyph {} from font {} in colour {:06x}",
-					glyph,
-					font.name,
-					fg.as_packed()
-				);
-				let texture_id = if texture_buffer.len() > slot {
-					texture_buffer[slot]
-				} else {
-					let id = s.create_texture(8, font.height as u32, PixelFormat::Rgba)?;
-					texture_buffer.push(id);
-					id
-				};
-				slot += 1;
-				s.set_texture_target(texture_id)?;
-				s.background(Color::TRANSPARENT);
-				s.clear()?;
-				s.stroke(rgb!(fg.red(), fg.green(), fg.blue(), 255));
-				for font_y in 0..(font.height as i32) {
-					let mut font_line =
-						font.data[((glyph as usize) * font.height) + font_y as usize];
-					for font_x in 0..8i32 {
-						if (font_line & 0x80) != 0 {
-							s.point(Point::new([font_x, font_y]))?;
-						};
-						font_line <<= 1;
-					}
-				}
-				s.clear_texture_target();
-			}
-		}
-		Ok(())
+	let mut features = rom::FEATURE_AUDIO | rom::FEATURE_NVRAM;
+	if args.disk.is_some() {
+		features |= rom::FEATURE_DISK;
 	}
-
-	/// Generate an RGBA texture for each glyph, in each foreground colour, in
-	/// each font.
-	fn render_glyphs(&mut self, s: &mut PixState) -> PixResult<()> {
-		Self::render_font(&font::font16::FONT, &mut self.font8x16, s)?;
-		Self::render_font(&font::font8::FONT, &mut self.font8x8, s)?;
-		Ok(())
+	if args.ram2_size.is_some() {
+		features |= rom::FEATURE_RAM2;
 	}
-
-	fn render_text(
-		&self,
-		font: &[pix_engine::texture::TextureId],
-		font_height: u16,
-		s: &mut PixState,
-	) -> PixResult<()> {
-		let num_cols = self.mode.text_width().unwrap();
-		let num_rows = self.mode.text_height().unwrap();
-		let mut bg_idx = 0;
-		let mut bg_rgb = {
-			let bg = RGBColour::from_packed(PALETTE[usize::from(bg_idx)].load(Ordering::Relaxed));
-			rgb!(bg.red(), bg.green(), bg.blue())
-		};
-		s.stroke(None);
-		// FRAMEBUFFER is an num_cols x num_rows size array of (u8_glyph, u8_attr).
-		for row in 0..num_rows {
-			let y = row * font_height;
-			for col in 0..num_cols {
-				let cell_no = (row * num_cols) + col;
-				let byte_offset = usize::from(cell_no) * 2;
-				let x = col * 8;
-				let glyph = FRAMEBUFFER.get_at(byte_offset);
-				let attr = common::video::Attr(FRAMEBUFFER.get_at(byte_offset + 1));
-				let fg_idx = attr.fg().make_ffi_safe().0;
-				let new_bg_idx = attr.bg().make_ffi_safe().0;
-				if new_bg_idx != bg_idx {
-					bg_idx = new_bg_idx;
-					let bg = RGBColour::from_packed(
-						PALETTE[usize::from(bg_idx)].load(Ordering::Relaxed),
-					);
-					bg_rgb = rgb!(bg.red(), bg.green(), bg.blue());
-				}
-				let glyph_box = rect!(i32::from(x), i32::from(y), 8i32, font_height as i32,);
-				s.fill(bg_rgb);
-				s.rect(glyph_box)?;
-				let slot = (usize::from(glyph) * Self::NUM_FG) + usize::from(fg_idx);
-				s.texture(font[slot], None, Some(glyph_box))?;
+	if args.guard_pages {
+		features |= rom::FEATURE_GUARD_PAGES;
+	}
+	if args.isolate {
+		let shared_vram = isolate::init().and_then(|_| isolate::map_shared(640 * 480));
+		match shared_vram {
+			// Safety: the shared mapping is the size we asked for, and is
+			// never unmapped
+			Ok(vram) => unsafe { video::FRAMEBUFFER.move_to(vram) },
+			Err(e) => {
+				eprintln!("Can't isolate the OS: {}", e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
 			}
 		}
-		Ok(())
 	}
+	memory::init(memory::Options {
+		ram2_size: args.ram2_size,
+		fill: args.ram_fill,
+		guard_pages: args.guard_pages,
+		shared: args.isolate,
+		rom: rom::identity(features),
+	});
 
-	fn render_chunky<const BPP: usize>(&self, s: &mut PixState) -> PixResult<()> {
-		let shift = 8 - BPP;
-		let num_colours = 1 << BPP;
-		let pixels_per_byte = 8 / BPP;
-		let num_col_bytes = self.mode.line_size_bytes();
-		let num_rows = self.mode.vertical_lines() as usize;
-		let colours = Self::make_colours(num_colours);
-		for y in 0..num_rows {
-			let y_bytes = y * num_col_bytes;
-			for x_byte in 0..num_col_bytes {
-				let byte_offset = y_bytes + x_byte;
-				let mut data = FRAMEBUFFER.get_at(byte_offset);
-				let x_start = x_byte * pixels_per_byte;
-				for x in 0..pixels_per_byte {
-					let bit = (data >> shift) as usize;
-					s.stroke(colours[bit]);
-					let p = point!((x_start + x) as i32, y as i32);
-					s.point(p)?;
-					data <<= BPP;
-				}
-			}
+	if let Some(path) = args.resume.as_deref() {
+		if let Err(e) = neotron_desktop_bios::resume_snapshot(path) {
+			eprintln!("Failed to resume from {}: {}", path.display(), e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
 		}
-		Ok(())
 	}
+	neotron_desktop_bios::set_snapshot_path(args.snapshot_file.clone());
 
-	fn make_colours(count: usize) -> Vec<pix_engine::color::Color> {
-		let mut result = vec![];
-		for palette_entry in PALETTE.iter().take(count) {
-			let rgb = RGBColour::from_packed(palette_entry.load(Ordering::Relaxed));
-			result.push(rgb!(rgb.red(), rgb.green(), rgb.blue()));
-		}
-		if count == 2 {
-			// special case - use black/white for 2 colour mode, not black/blue
-			result[1] = rgb!(0xFF, 0xFF, 0xFF);
-		}
-		result
+	let default_mode = unsafe { common::video::Mode::from_u8(0) };
+	let width = (default_mode.horizontal_pixels() as f32) * SCALE_FACTOR;
+	let height = (default_mode.vertical_lines() as f32) * SCALE_FACTOR;
+	info!("Default Window set to {} x {}", width, height);
+
+	// Make a window
+	let mut builder = Engine::builder();
+	builder
+		.dimensions(width as u32, height as u32)
+		.scale(SCALE_FACTOR, SCALE_FACTOR)
+		.title(&title)
+		.show_frame_rate()
+		.target_frame_rate(60);
+	if let Some((x, y)) = profile::load_window_position() {
+		builder.position(x, y);
+	}
+	let mut engine = builder.build().unwrap();
+	let audio = audio::init(audio::Options {
+		backend: args.audio_backend,
+		wav_input,
+		log_stats: args.audio_stats,
+		strict: args.strict_audio,
+		output_device: args.audio_device,
+		input_device: args.audio_input_device,
+		latency_ms: args.audio_latency,
+		volume: args.volume,
+	});
+	let mut app = MyApp::new(title, sender, args.hotkey_prefix, args.api_stats, audio);
+
+	// Run the OS
+	let watchdog = args.watchdog.map(|timeout| (timeout, args.watchdog_action));
+	if let Err(e) = neotron_desktop_bios::boot(main_func, watchdog) {
+		eprintln!("Failed to start the OS thread: {}", e);
+		std::process::exit(shutdown::ExitCode::BiosError.code());
 	}
+
+	engine.run(&mut app).unwrap();
 }
 
-impl PixEngine for MyApp {
-	/// Perform application initialisation.
-	fn on_start(&mut self, s: &mut PixState) -> PixResult<()> {
-		self.render_glyphs(s)?;
-		// Let the rest of the OS start now
-		self.sender.send(AppEvent::Started).unwrap();
-		Ok(())
+/// Check everything `--check` covers, print the results and exit.
+fn preflight(
+	args: &Args,
+	transfer: Option<&transfer::Spec>,
+	eeprom: Option<&i2c::DeviceSpec>,
+	conflicts: &[conflicts::Conflict],
+) -> ! {
+	let mut report = preflight::Report::new();
+	report.record(
+		"Options",
+		if conflicts.is_empty() {
+			Ok(String::from("no conflicts"))
+		} else {
+			Err(conflicts
+				.iter()
+				.map(ToString::to_string)
+				.collect::<Vec<_>>()
+				.join("; "))
+		},
+	);
+	if let Some(path) = &args.disk {
+		report.record("Disk", preflight::disk(path));
+	}
+	if let Some(spec) = transfer {
+		report.record(
+			"Transfer device",
+			transfer::Transfer::new(spec).map(|_| spec.dir.display().to_string()),
+		);
+	}
+	report.record("NVRAM", preflight::nvram());
+	if let Some(path) = &args.resume {
+		report.record("Snapshot", preflight::snapshot(path));
+	}
+	if eeprom.is_some() || !args.i2c_device.is_empty() {
+		let errors: Vec<String> = eeprom
+			.into_iter()
+			.chain(&args.i2c_device)
+			.filter_map(|spec| i2c::add_device(spec).err())
+			.collect();
+		report.record(
+			"I2C devices",
+			if errors.is_empty() {
+				Ok(i2c::describe_devices().join(", "))
+			} else {
+				Err(errors.join("; "))
+			},
+		);
+	}
+	if !args.bus_device.is_empty() {
+		let errors: Vec<String> = args
+			.bus_device
+			.iter()
+			.filter_map(|spec| bus::add_device(spec).err())
+			.collect();
+		report.record(
+			"Bus devices",
+			if errors.is_empty() {
+				Ok(bus::describe_devices().join(", "))
+			} else {
+				Err(errors.join("; "))
+			},
+		);
+	}
+	let ports = serial_ports(args);
+	if !ports.is_empty() {
+		let errors: Vec<String> = ports
+			.into_iter()
+			.filter_map(|(port, spec)| serial::add_port(port, spec).err())
+			.collect();
+		report.record(
+			"Serial ports",
+			if errors.is_empty() {
+				Ok(serial::describe_devices().join(", "))
+			} else {
+				Err(errors.join("; "))
+			},
+		);
+	}
+	if let Some(address) = &args.monitor {
+		report.record(
+			"Monitor",
+			monitor::probe(address).map(|_| address.to_string()),
+		);
 	}
+	if let Some(address) = &args.remote {
+		report.record(
+			"Remote control",
+			monitor::probe(address).map(|_| address.to_string()),
+		);
+	}
+	if let Some(address) = args.vnc {
+		report.record(
+			"VNC server",
+			monitor::probe(&monitor::Address::Tcp(address)).map(|_| address.to_string()),
+		);
+	}
+	if let Some(address) = args.metrics {
+		report.record(
+			"Metrics",
+			monitor::probe(&monitor::Address::Tcp(address)).map(|_| address.to_string()),
+		);
+	}
+	if let Some(address) = args.web_display {
+		report.record(
+			"Web display",
+			monitor::probe(&monitor::Address::Tcp(address)).map(|_| address.to_string()),
+		);
+	}
+	report.record(
+		"Audio",
+		audio::probe(
+			args.audio_backend,
+			args.audio_device.as_deref(),
+			args.audio_input_device.as_deref(),
+		),
+	);
+	if let Some(path) = &args.audio_input {
+		report.record("Audio input", preflight::wav(path));
+	}
+	report.record("OS", preflight::os(&args.os));
 
-	/// Terminate the process to ensure the OS thread dies too.
-	fn on_stop(&mut self, _s: &mut PixState) -> PixResult<()> {
+	for line in report.lines() {
+		println!("{}", line);
+	}
+	if report.passed() {
 		std::process::exit(0);
 	}
+	std::process::exit(shutdown::ExitCode::BiosError.code());
+}
+
+/// Move the `--device` options into the older options they replace, and warn
+/// about any of those older options that were used.
+///
+/// Returns whether the disk image is read-only, and the transfer device if
+/// there is one.
+fn attach_devices(args: &mut Args) -> (bool, Option<transfer::Spec>) {
+	let old = args
+		.disk
+		.iter()
+		.map(|path| {
+			(
+				"--disk",
+				device::Device::Disk {
+					path: path.clone(),
+					read_only: false,
+				},
+			)
+		})
+		.chain(args.i2c_eeprom.iter().map(|eeprom| {
+			(
+				"--i2c-eeprom",
+				device::Device::I2c(i2c::DeviceSpec {
+					bus: 0,
+					address: args.i2c_eeprom_address,
+					kind: i2c::DeviceKind::Eeprom(eeprom.clone()),
+				}),
+			)
+		}))
+		.chain(
+			args.i2c_device
+				.iter()
+				.map(|spec| ("--i2c-device", device::Device::I2c(spec.clone()))),
+		)
+		.chain(args.bus_device.iter().map(|spec| {
+			(
+				"--bus-device",
+				device::Device::Bus {
+					slot: None,
+					spec: spec.clone(),
+				},
+			)
+		}));
+	for (option, device) in old {
+		eprintln!(
+			"warning: {} is going away in the next release - use --device {}",
+			option, device
+		);
+	}
 
-	/// Called whenever the app has an event to process.
-	///
-	/// We send key up and key down events into a queue for the OS to process later.
-	fn on_event(&mut self, _s: &mut PixState, event: &Event) -> PixResult<bool> {
-		match event {
-			Event::KeyUp {
-				key: Some(key),
-				keymod: _,
-				repeat: _,
+	let mut read_only = false;
+	let mut transfer = None;
+	for device in std::mem::take(&mut args.device) {
+		match device {
+			device::Device::Disk {
+				path,
+				read_only: device_read_only,
 			} => {
-				self.sender.send(AppEvent::KeyUp(*key)).unwrap();
-				Ok(true)
+				if let Some(old) = &args.disk {
+					eprintln!(
+						"error: two disk images were given ({} and {})",
+						old.display(),
+						path.display()
+					);
+					std::process::exit(shutdown::ExitCode::BiosError.code());
+				}
+				args.disk = Some(path);
+				read_only = device_read_only;
 			}
-			Event::KeyDown {
-				key: Some(key),
-				keymod: _,
-				repeat: _,
-			} => {
-				self.sender.send(AppEvent::KeyDown(*key)).unwrap();
-				Ok(true)
+			device::Device::Transfer(spec) => {
+				if let Some(old) = transfer.replace(spec) {
+					eprintln!(
+						"error: two transfer devices were given (one is {})",
+						old.dir.display()
+					);
+					std::process::exit(shutdown::ExitCode::BiosError.code());
+				}
 			}
-			Event::Window {
-				win_event: WindowEvent::Moved(_, _),
-				..
-			} => {
-				// need to reset the scale when the window is moved?
-				self.reset = true;
-				Ok(true)
+			device::Device::I2c(spec) => args.i2c_device.push(spec),
+			device::Device::Bus { slot, spec } => {
+				if let Err(e) = device::place(&mut args.bus_device, slot, spec) {
+					eprintln!("error: {}", e);
+					std::process::exit(shutdown::ExitCode::BiosError.code());
+				}
 			}
-			_ => {
-				debug!("Didn't know about {:?}", event);
-				Ok(false)
+			device::Device::Serial { port, spec } => {
+				let option = match port {
+					0 => &mut args.serial0,
+					1 => &mut args.serial1,
+					2 => &mut args.serial2,
+					_ => &mut args.serial3,
+				};
+				if let Some(old) = option.replace(spec.clone()) {
+					eprintln!(
+						"error: two things want serial port {} ({} and {})",
+						port, old, spec
+					);
+					std::process::exit(shutdown::ExitCode::BiosError.code());
+				}
 			}
 		}
 	}
+	(read_only, transfer)
+}
 
-	/// Called in a tight-loop to update the application.
-	///
-	/// We convert the contents of `FRAMEBUFFER` into pixels on the canvas.
-	fn on_update(&mut self, s: &mut PixState) -> PixResult<()> {
-		let mode_value = VIDEO_MODE.load(Ordering::Relaxed);
-		let new_mode = unsafe { common::video::Mode::from_u8(mode_value) };
-		if new_mode != self.mode || self.reset {
-			info!("New video mode detected, or needs reset");
-			self.reset = false;
-			self.mode = new_mode;
-			let width = (new_mode.horizontal_pixels() as f32) * SCALE_FACTOR;
-			let height = (new_mode.vertical_lines() as f32) * SCALE_FACTOR;
-			info!("Window set to {} x {}", width, height);
-			s.set_window_dimensions((width as u32, height as u32))?;
-			s.scale(SCALE_FACTOR, SCALE_FACTOR)?;
-			s.background(rgb!(0, 0, 0));
-			s.clear()?;
-		}
-
-		s.blend_mode(BlendMode::Blend);
-
-		match self.mode.format() {
-			common::video::Format::Text8x16 => self.render_text(&self.font8x16, 16, s)?,
-			common::video::Format::Text8x8 => self.render_text(&self.font8x8, 8, s)?,
-			common::video::Format::Chunky1 => self.render_chunky::<1>(s)?,
-			common::video::Format::Chunky2 => self.render_chunky::<2>(s)?,
-			common::video::Format::Chunky4 => self.render_chunky::<4>(s)?,
-			common::video::Format::Chunky8 => self.render_chunky::<8>(s)?,
-			_ => {
-				// Unknown mode - do nothing
-			}
-		}
-
-		Ok(())
-	}
+/// The serial ports with something plugged into them.
+fn serial_ports(args: &Args) -> Vec<(u8, &serial::DeviceSpec)> {
+	[&args.serial0, &args.serial1, &args.serial2, &args.serial3]
+		.into_iter()
+		.zip(0..)
+		.filter_map(|(spec, port)| Some((port, spec.as_ref()?)))
+		.collect()
 }
 
-impl<const N: usize> Framebuffer<N> {
-	/// Create a new blank Framebuffer.
-	///
-	/// Everything is zero initialised.
-	const fn new() -> Framebuffer<N> {
-		Framebuffer {
-			contents: std::cell::UnsafeCell::new([0u8; N]),
-			alt_pointer: AtomicPtr::new(core::ptr::null_mut()),
-		}
-	}
+/// Everything attached to the machine, as `--device` options would give it.
+fn attached(
+	args: &Args,
+	disk_read_only: bool,
+	transfer: Option<&transfer::Spec>,
+	eeprom: Option<&i2c::DeviceSpec>,
+) -> Vec<device::Device> {
+	let disk = args.disk.iter().map(|path| device::Device::Disk {
+		path: path.clone(),
+		read_only: disk_read_only,
+	});
+	let transfer = transfer.map(|spec| device::Device::Transfer(spec.clone()));
+	let i2c = eeprom
+		.into_iter()
+		.chain(&args.i2c_device)
+		.map(|spec| device::Device::I2c(spec.clone()));
+	let bus = args
+		.bus_device
+		.iter()
+		.enumerate()
+		.map(|(slot, spec)| device::Device::Bus {
+			slot: u8::try_from(slot).ok(),
+			spec: spec.clone(),
+		});
+	let serial = serial_ports(args)
+		.into_iter()
+		.map(|(port, spec)| device::Device::Serial {
+			port,
+			spec: spec.clone(),
+		});
+	disk.chain(transfer)
+		.chain(i2c)
+		.chain(bus)
+		.chain(serial)
+		.collect()
+}
 
-	/// Set a byte in the framebuffer.
-	///
-	/// Panics if you try and write out of bounds.
-	///
-	/// Uses volatile writes.
-	fn write_at(&self, offset: usize, value: u8) {
-		unsafe {
-			let array_ptr = self.get_pointer() as *mut u8;
-			let byte_ptr = array_ptr.add(offset);
-			byte_ptr.write_volatile(value);
+/// Print the profiles we have, marking the one we're using.
+fn list_profiles(active: Option<&profile::Profile>) {
+	let Some(root) = profile::root() else {
+		eprintln!("Profile error: {}", profile::Error::NoConfigDir);
+		std::process::exit(shutdown::ExitCode::BiosError.code());
+	};
+	let names = match profile::list(&root) {
+		Ok(names) => names,
+		Err(e) => {
+			eprintln!("Profile error: {}", e);
+			std::process::exit(shutdown::ExitCode::BiosError.code());
 		}
+	};
+	println!("Profiles in {}:", root.display());
+	if names.is_empty() {
+		println!("  (none)");
 	}
-
-	/// Get a byte from the framebuffer.
-	///
-	/// Panics if you try and read out of bounds.
-	///
-	/// Uses volatile reads.
-	fn get_at(&self, offset: usize) -> u8 {
-		unsafe {
-			let array_ptr = self.get_pointer() as *const u8;
-			let byte_ptr = array_ptr.add(offset);
-			byte_ptr.read_volatile()
+	for name in names {
+		if active.map(profile::Profile::name) == Some(name.as_str()) {
+			println!("* {}", name);
+		} else {
+			println!("  {}", name);
 		}
 	}
+}
 
-	/// Get a pointer to the framebuffer you can give to the OS.
-	fn get_pointer(&self) -> *mut u32 {
-		let mut p = self.alt_pointer.load(Ordering::Relaxed);
-		if p.is_null() {
-			p = self.contents.get() as *mut u32;
-		}
-		p
+/// Read an option that can be given more than once from its numbered
+/// environment variables, if any of them are set. Only use this if it wasn't
+/// on the command line.
+fn from_env<T>(long: &str, parse: fn(&str) -> Result<T, String>) -> Option<Vec<T>> {
+	let found = config::indexed(long);
+	if found.is_empty() {
+		return None;
 	}
+	let parsed = found
+		.into_iter()
+		.map(|(name, value)| match parse(&value) {
+			Ok(parsed) => parsed,
+			Err(e) => {
+				eprintln!("error: invalid value '{}' for {}: {}", value, name, e);
+				std::process::exit(shutdown::ExitCode::BiosError.code());
+			}
+		})
+		.collect();
+	Some(parsed)
 }
 
-unsafe impl<const N: usize> Sync for Framebuffer<N> {}
-
 // ===========================================================================
 // End of File
 // ===========================================================================
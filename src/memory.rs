@@ -0,0 +1,368 @@
+//! # OS memory for the Neotron Desktop BIOS
+//!
+//! Region 0 is the OS's main RAM. If asked, we also provide a Region 1, like
+//! the external PSRAM on some boards. Every region is allocated once at
+//! start-up and never freed.
+//!
+//! Fresh RAM is filled with a pattern byte (zero unless you ask otherwise), so
+//! you can catch OS code that wrongly expects uninitialised memory to be zero.
+//!
+//! RAM regions can also have guard pages either side - see [`crate::guard`].
+//! Or with `--isolate`, they are shared with the OS's process - see
+//! [`crate::isolate`].
+//!
+//! After the RAM regions there is a small read-only region with some
+//! information about the BIOS in it - see [`crate::rom`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::Mutex;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// How the OS memory is set up.
+pub struct Options {
+	/// How big Region 1 is, if we have one
+	pub ram2_size: Option<usize>,
+	/// What to fill fresh RAM with
+	pub fill: u8,
+	/// Put guard pages either side of each RAM region
+	pub guard_pages: bool,
+	/// Share the RAM regions with the OS's process
+	pub shared: bool,
+	/// What to put in the ROM region
+	pub rom: Vec<u8>,
+}
+
+/// A block of host memory we have given to the OS.
+struct Block {
+	start: *mut u8,
+	length: usize,
+	kind: common::MemoryKind,
+	/// What to fill it with when the OS resets
+	fill: u8,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How big Region 0 is.
+pub const REGION0_SIZE: usize = 1024 * 1024;
+
+/// All our memory regions, in region number order.
+static REGIONS: Mutex<Vec<Block>> = Mutex::new(Vec::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Allocate all the memory regions.
+pub fn init(options: Options) {
+	crate::guard::install_handler();
+	let mut regions = REGIONS.lock().unwrap();
+	let sizes = std::iter::once(REGION0_SIZE).chain(options.ram2_size);
+	for (idx, size) in sizes.enumerate() {
+		let block = if options.shared {
+			Block::allocate_shared(idx, size, options.fill)
+		} else if options.guard_pages {
+			Block::allocate_guarded(idx, size, options.fill)
+		} else {
+			Block::allocate(size, common::MemoryKind::Ram, options.fill)
+		};
+		regions.push(block);
+	}
+	let rom_region = regions.len();
+	regions.push(Block::allocate_rom(rom_region, &options.rom));
+	log::info!("RAM fill byte: 0x{:02x}", options.fill);
+	for (idx, block) in regions.iter().enumerate() {
+		log::info!("Memory Region {}: {}", idx, block.region());
+	}
+}
+
+/// Fill every RAM region with its fill byte again, like a power cycle.
+///
+/// Only call this when the OS isn't running.
+pub fn refill() {
+	let regions = REGIONS.lock().unwrap();
+	for block in regions.iter() {
+		if block.kind == common::MemoryKind::Ram {
+			// Safety: the block is ours, and the OS isn't using it
+			unsafe {
+				std::ptr::write_bytes(block.start, block.fill, block.length);
+			}
+		}
+	}
+}
+
+/// Get a memory region, or `None` if there is no such region.
+pub fn get_region(region: u8) -> Option<common::MemoryRegion> {
+	let regions = REGIONS.lock().unwrap();
+	regions.get(usize::from(region)).map(Block::region)
+}
+
+/// Copy some bytes out of a memory region.
+///
+/// The OS owns this memory and may be changing it as we read, so this is only
+/// a snapshot. Each byte is read with a volatile read, and we make no
+/// assumptions about what is in there.
+pub fn snapshot(region: u8, offset: usize, len: usize) -> Result<Vec<u8>, String> {
+	let regions = REGIONS.lock().unwrap();
+	let block = regions
+		.get(usize::from(region))
+		.ok_or_else(|| format!("there is no Region {}", region))?;
+	let end = offset
+		.checked_add(len)
+		.filter(|end| *end <= block.length)
+		.ok_or_else(|| format!("Region {} is only {} bytes long", region, block.length))?;
+	let bytes = (offset..end)
+		// Safety: we checked this is inside the block, and blocks are never
+		// freed
+		.map(|idx| unsafe { block.start.add(idx).read_volatile() })
+		.collect();
+	Ok(bytes)
+}
+
+/// Copy out the contents of every RAM region, for a snapshot.
+///
+/// Only call this when the OS isn't running.
+pub fn dump_ram() -> Vec<Vec<u8>> {
+	let regions = REGIONS.lock().unwrap();
+	regions
+		.iter()
+		.filter(|block| block.kind == common::MemoryKind::Ram)
+		// Safety: the block is ours, and the OS isn't using it
+		.map(|block| unsafe { std::slice::from_raw_parts(block.start, block.length) }.to_vec())
+		.collect()
+}
+
+/// Put back the contents of every RAM region, from a snapshot.
+///
+/// The regions must be the same size as they were when the snapshot was
+/// taken. Only call this when the OS isn't running.
+pub fn restore_ram(contents: &[Vec<u8>]) -> Result<(), String> {
+	let regions = REGIONS.lock().unwrap();
+	let ram: Vec<&Block> = regions
+		.iter()
+		.filter(|block| block.kind == common::MemoryKind::Ram)
+		.collect();
+	let sizes: Vec<usize> = ram.iter().map(|block| block.length).collect();
+	let saved: Vec<usize> = contents.iter().map(Vec::len).collect();
+	if sizes != saved {
+		return Err(format!(
+			"the snapshot has RAM regions of {:?} bytes, but we have {:?} (check --ram2-size)",
+			saved, sizes
+		));
+	}
+	for (block, data) in ram.iter().zip(contents) {
+		// Safety: the block is ours, it's the right size, and the OS isn't
+		// using it
+		unsafe {
+			std::ptr::copy_nonoverlapping(data.as_ptr(), block.start, block.length);
+		}
+	}
+	Ok(())
+}
+
+/// Is this range of memory entirely inside one of the RAM regions?
+pub fn is_ram(start: *const u8, length: usize) -> bool {
+	let start = start as usize;
+	let Some(end) = start.checked_add(length) else {
+		return false;
+	};
+	let regions = REGIONS.lock().unwrap();
+	regions.iter().any(|block| {
+		let block_start = block.start as usize;
+		block.kind == common::MemoryKind::Ram
+			&& start >= block_start
+			&& end <= block_start + block.length
+	})
+}
+
+/// Look for some bytes in every memory region.
+///
+/// Returns the region and offset of each match, in order, up to `limit`
+/// matches.
+pub fn find(needle: &[u8], limit: usize) -> Vec<(u8, usize)> {
+	let mut matches = Vec::new();
+	if needle.is_empty() {
+		return matches;
+	}
+	let num_regions = REGIONS.lock().unwrap().len();
+	for region in 0..num_regions {
+		let region = region as u8;
+		let length = REGIONS.lock().unwrap()[usize::from(region)].length;
+		let Ok(haystack) = snapshot(region, 0, length) else {
+			continue;
+		};
+		for (offset, window) in haystack.windows(needle.len()).enumerate() {
+			if window == needle {
+				matches.push((region, offset));
+				if matches.len() == limit {
+					return matches;
+				}
+			}
+		}
+	}
+	matches
+}
+
+/// Parse a fill byte from the command line (e.g. `0xAA` or `170`).
+pub fn parse_fill(s: &str) -> Result<u8, String> {
+	let s = s.trim();
+	let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+		Some(hex) => u8::from_str_radix(hex, 16),
+		None => s.parse(),
+	};
+	result.map_err(|_| format!("{:?} is not a byte (try 0xAA or 0)", s))
+}
+
+/// Parse a memory size from the command line (e.g. `8MiB`, `512KiB` or
+/// `4096`).
+pub fn parse_size(s: &str) -> Result<usize, String> {
+	let s = s.trim();
+	let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	let (number, unit) = s.split_at(split);
+	let number: usize = number
+		.parse()
+		.map_err(|_| format!("{:?} is not a size (try 8MiB or 512KiB)", s))?;
+	let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+		"" | "b" => 1,
+		"k" | "kib" => 1024,
+		"m" | "mib" => 1024 * 1024,
+		"g" | "gib" => 1024 * 1024 * 1024,
+		_ => return Err(format!("unknown unit {:?} (try KiB, MiB or GiB)", unit)),
+	};
+	match number.checked_mul(multiplier) {
+		Some(0) => Err("size must be more than zero".to_string()),
+		Some(size) => Ok(size),
+		None => Err(format!("{:?} is too big", s)),
+	}
+}
+
+/// Say how big something is, in the biggest unit (all powers of two) that
+/// divides it exactly - the opposite of [`parse_size`].
+pub fn describe_size(bytes: u64) -> String {
+	const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+	for (unit, name) in UNITS {
+		if bytes != 0 && bytes & (unit - 1) == 0 {
+			return format!("{} {}", bytes / unit, name);
+		}
+	}
+	format!("{} bytes", bytes)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+// Safety: The OS is given these pointers and can do what it likes with them.
+// We only hand them out, so it is fine to move them between threads.
+unsafe impl Send for Block {}
+
+impl Block {
+	/// Allocate some host memory filled with the given byte. We never free
+	/// it.
+	fn allocate(length: usize, kind: common::MemoryKind, fill: u8) -> Block {
+		let data: &'static mut [u8] = Box::leak(vec![fill; length].into_boxed_slice());
+		Block {
+			start: data.as_mut_ptr(),
+			length,
+			kind,
+			fill,
+		}
+	}
+
+	/// Allocate some RAM, filled with the given byte, with guard pages either
+	/// side. If we can't, fall back to normal RAM.
+	fn allocate_guarded(region: usize, length: usize, fill: u8) -> Block {
+		let Some(start) = crate::guard::allocate(region, length) else {
+			log::warn!(
+				"Couldn't put guard pages around Region {} - using normal memory",
+				region
+			);
+			return Block::allocate(length, common::MemoryKind::Ram, fill);
+		};
+		// Safety: the guard module just gave us `length` writable bytes here
+		unsafe {
+			std::ptr::write_bytes(start, fill, length);
+		}
+		Block {
+			start,
+			length,
+			kind: common::MemoryKind::Ram,
+			fill,
+		}
+	}
+
+	/// Allocate some RAM, filled with the given byte, that the OS's process
+	/// shares with us. If we can't, we give up, as the OS wouldn't work.
+	fn allocate_shared(region: usize, length: usize, fill: u8) -> Block {
+		let start = crate::isolate::map_shared(length).unwrap_or_else(|e| {
+			panic!("Couldn't allocate Region {}: {}", region, e);
+		});
+		// Safety: we just got `length` writable bytes here
+		unsafe {
+			std::ptr::write_bytes(start, fill, length);
+		}
+		Block {
+			start,
+			length,
+			kind: common::MemoryKind::Ram,
+			fill,
+		}
+	}
+
+	/// Allocate some read-only memory containing `data`. If we can't make it
+	/// read-only, the OS gets a writable copy instead.
+	fn allocate_rom(region: usize, data: &[u8]) -> Block {
+		let start = crate::guard::allocate_read_only(region, data).unwrap_or_else(|| {
+			log::warn!("Couldn't make Region {} read-only", region);
+			Box::leak(data.to_vec().into_boxed_slice()).as_mut_ptr()
+		});
+		Block {
+			start,
+			length: data.len(),
+			kind: common::MemoryKind::Rom,
+			fill: 0,
+		}
+	}
+
+	/// Describe this block in the form the BIOS API wants.
+	fn region(&self) -> common::MemoryRegion {
+		common::MemoryRegion {
+			start: self.start,
+			length: self.length,
+			kind: common::FfiMemoryKind::from(self.kind),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
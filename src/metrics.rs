@@ -0,0 +1,279 @@
+//! # Prometheus metrics
+//!
+//! With `--metrics=127.0.0.1:9600`, we answer `GET /metrics` with the counters
+//! we already keep - frames drawn, BIOS calls, disk and serial traffic, audio
+//! underruns, dropped HID events and watchdog resets - in the Prometheus text
+//! format, so a long soak test can be graphed rather than read from the log.
+//!
+//! The HTTP server is as small as we could make it. It answers one request
+//! per connection, one connection at a time.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::fmt::Write as _;
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::{apistats, audio, heartbeat, watchdog};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// When we started serving metrics, for `neotron_uptime_seconds`.
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// How long we wait for a client to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The biggest request we'll read. We only need the first line.
+const MAX_REQUEST: usize = 8192;
+
+/// How big a disk block is, to turn block counts into bytes.
+const BLOCK_SIZE: u64 = 512;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--metrics` address, like `127.0.0.1:9600` or just `9600`. A port
+/// with no host listens on `127.0.0.1`.
+pub fn parse_address(text: &str) -> Result<SocketAddr, String> {
+	let text = if text.parse::<u16>().is_ok() {
+		format!("127.0.0.1:{}", text)
+	} else {
+		text.to_string()
+	};
+	text.to_socket_addrs()
+		.map_err(|e| format!("bad address {:?}: {}", text, e))?
+		.next()
+		.ok_or_else(|| format!("{:?} doesn't have an address", text))
+}
+
+/// Start serving metrics, on a new thread.
+///
+/// Fails if we can't listen on the address, like if something else already
+/// is.
+pub fn start(address: SocketAddr) -> Result<(), String> {
+	if !address.ip().is_loopback() {
+		log::warn!("The metrics on {} can be read by other machines", address);
+	}
+	let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+	STARTED.get_or_init(Instant::now);
+	log::info!("Metrics listening on http://{}/metrics", address);
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			match stream {
+				Ok(stream) => {
+					if let Err(e) = serve(stream) {
+						log::debug!("Metrics client went away: {}", e);
+					}
+				}
+				Err(e) => log::warn!("Metrics failed to accept a client: {}", e),
+			}
+		}
+	});
+	Ok(())
+}
+
+/// Answer one HTTP request.
+fn serve(mut stream: TcpStream) -> std::io::Result<()> {
+	stream.set_read_timeout(Some(READ_TIMEOUT))?;
+	let request = read_request(&mut stream)?;
+	let mut words = request.lines().next().unwrap_or("").split_whitespace();
+	let method = words.next().unwrap_or("");
+	// Ignore any query string - we have nothing to filter by
+	let path = words.next().unwrap_or("").split('?').next().unwrap_or("");
+	let (status, body) = match (method, path) {
+		("GET" | "HEAD", "/metrics") => ("200 OK", render()),
+		("GET" | "HEAD", _) => ("404 Not Found", String::from("Try /metrics\n")),
+		_ => (
+			"405 Method Not Allowed",
+			String::from("Only GET works here\n"),
+		),
+	};
+	let mut response = format!(
+		"HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+		status,
+		body.len()
+	);
+	if method != "HEAD" {
+		response.push_str(&body);
+	}
+	stream.write_all(response.as_bytes())?;
+	stream.flush()
+}
+
+/// Read the request up to the blank line after the headers. We never want a
+/// body.
+fn read_request(stream: &mut TcpStream) -> std::io::Result<String> {
+	let mut request = Vec::new();
+	let mut buffer = [0u8; 1024];
+	while !request.windows(4).any(|w| w == b"\r\n\r\n") && !request.ends_with(b"\n\n") {
+		let n = stream.read(&mut buffer)?;
+		if n == 0 {
+			break;
+		}
+		request.extend_from_slice(&buffer[..n]);
+		if request.len() > MAX_REQUEST {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				"request too big",
+			));
+		}
+	}
+	Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// Write out every metric, in the Prometheus text format.
+pub fn render() -> String {
+	let uptime = STARTED
+		.get()
+		.map_or(0.0, |started| started.elapsed().as_secs_f64());
+	let totals = heartbeat::totals();
+	let audio = audio::stats();
+	let (fired, resets) = watchdog::counts();
+	// (name, help, value) - everything but the uptime only goes up
+	let counters = [
+		(
+			"neotron_frames_total",
+			"Frames drawn by the window",
+			totals.frames,
+		),
+		(
+			"neotron_disk_read_bytes_total",
+			"Bytes read from block devices",
+			totals.blocks_read * BLOCK_SIZE,
+		),
+		(
+			"neotron_disk_written_bytes_total",
+			"Bytes written to block devices",
+			totals.blocks_written * BLOCK_SIZE,
+		),
+		(
+			"neotron_serial_received_bytes_total",
+			"Bytes the OS read from serial devices",
+			totals.serial_in,
+		),
+		(
+			"neotron_serial_sent_bytes_total",
+			"Bytes the OS wrote to serial devices",
+			totals.serial_out,
+		),
+		(
+			"neotron_hid_events_total",
+			"HID events handed to the OS",
+			totals.hid_delivered,
+		),
+		(
+			"neotron_hid_events_dropped_total",
+			"HID events dropped because the OS didn't read them in time",
+			totals.hid_dropped,
+		),
+		(
+			"neotron_audio_output_underruns_total",
+			"Times the audio output ran dry",
+			audio.output_underruns,
+		),
+		(
+			"neotron_audio_output_overruns_total",
+			"Calls to audio_output_data that couldn't all fit",
+			audio.output_overruns,
+		),
+		(
+			"neotron_audio_input_underruns_total",
+			"Calls to audio_input_data with nothing to read",
+			audio.input_underruns,
+		),
+		(
+			"neotron_audio_input_overruns_total",
+			"Times recorded samples were dropped",
+			audio.input_overruns,
+		),
+		(
+			"neotron_watchdog_fired_total",
+			"Times the watchdog decided the OS had hung",
+			fired,
+		),
+		(
+			"neotron_watchdog_resets_total",
+			"Times the watchdog reset the OS",
+			resets,
+		),
+	];
+
+	let mut out = String::new();
+	header(
+		&mut out,
+		"neotron_uptime_seconds",
+		"gauge",
+		"Seconds since the emulator started serving metrics",
+	);
+	let _ = writeln!(out, "neotron_uptime_seconds {:.3}", uptime);
+	for (name, help, value) in counters {
+		header(&mut out, name, "counter", help);
+		let _ = writeln!(out, "{} {}", name, value);
+	}
+
+	let calls = apistats::totals();
+	header(
+		&mut out,
+		"neotron_bios_calls_total",
+		"counter",
+		"Calls to each BIOS function",
+	);
+	for (function, count, _) in &calls {
+		let _ = writeln!(
+			out,
+			"neotron_bios_calls_total{{function=\"{}\"}} {}",
+			function, count
+		);
+	}
+	header(
+		&mut out,
+		"neotron_bios_call_bytes_total",
+		"counter",
+		"Bytes passed to or from each BIOS function",
+	);
+	for (function, _, bytes) in &calls {
+		let _ = writeln!(
+			out,
+			"neotron_bios_call_bytes_total{{function=\"{}\"}} {}",
+			function, bytes
+		);
+	}
+	out
+}
+
+/// Write the `# HELP` and `# TYPE` lines for a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
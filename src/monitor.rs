@@ -0,0 +1,266 @@
+//! # Debug monitor
+//!
+//! With `--monitor`, we listen on a Unix socket (`unix:/tmp/neotron.sock`) or
+//! a TCP port on this machine (`tcp:4444`), and run debug console commands
+//! sent to us one line at a time, writing back what they print. This lets a
+//! script (or `socat`, or `nc`) drive the emulator, in the same way as typing
+//! into `--debug-console`. Type `help` for a list of commands.
+//!
+//! We talk to one client at a time. When it hangs up, the next one can
+//! connect.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Where the monitor listens.
+#[derive(Debug, Clone)]
+pub enum Address {
+	/// A Unix domain socket at this path
+	Unix(PathBuf),
+	/// A TCP port
+	Tcp(SocketAddr),
+}
+
+/// A client connected to one of our sockets.
+pub trait Connection: Read + Write + Send {
+	/// Another handle on the same connection, so another thread can write to
+	/// it.
+	fn try_clone(&self) -> std::io::Result<Box<dyn Connection>>;
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--monitor` address, like `unix:/tmp/neotron.sock`, `tcp:4444` or
+/// `tcp:127.0.0.1:4444`. A TCP port with no host listens on `127.0.0.1`.
+pub fn parse_address(text: &str) -> Result<Address, String> {
+	if let Some(path) = text.strip_prefix("unix:") {
+		if path.is_empty() {
+			return Err("give a path for the socket, like unix:/tmp/neotron.sock".into());
+		}
+		if !cfg!(unix) {
+			return Err("Unix sockets only work on a Unix host - try tcp:<port>".into());
+		}
+		Ok(Address::Unix(PathBuf::from(path)))
+	} else if let Some(address) = text.strip_prefix("tcp:") {
+		let address = if address.parse::<u16>().is_ok() {
+			format!("127.0.0.1:{}", address)
+		} else {
+			address.to_string()
+		};
+		let address = address
+			.to_socket_addrs()
+			.map_err(|e| format!("bad TCP address {:?}: {}", address, e))?
+			.next()
+			.ok_or_else(|| format!("{:?} doesn't have an address", address))?;
+		Ok(Address::Tcp(address))
+	} else {
+		Err("expected unix:<path> or tcp:[<host>:]<port>".into())
+	}
+}
+
+/// Start listening for monitor clients, on a new thread.
+///
+/// Fails if we can't listen on the address, like if something else already
+/// is.
+pub fn start(address: &Address) -> Result<(), String> {
+	if let Address::Tcp(address) = address {
+		if !address.ip().is_loopback() {
+			log::warn!(
+				"The monitor on {} can be used by other machines - it can read the OS's RAM and change its disks",
+				address
+			);
+		}
+	}
+	listen(address, "Monitor", serve)
+}
+
+/// Listen on `address` on a new thread, handing each client to `serve` in
+/// turn. `name` is what we call the server in the log.
+///
+/// Fails if we can't listen on the address, like if something else already
+/// is.
+pub fn listen(
+	address: &Address,
+	name: &'static str,
+	serve: fn(Box<dyn Connection>),
+) -> Result<(), String> {
+	match address {
+		Address::Unix(path) => {
+			let listener = sys::bind(path)?;
+			log::info!("{} listening on {}", name, path.display());
+			std::thread::spawn(move || sys::accept_all(listener, name, serve));
+		}
+		Address::Tcp(address) => {
+			let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+			log::info!("{} listening on {}", name, address);
+			std::thread::spawn(move || {
+				for stream in listener.incoming() {
+					match stream {
+						Ok(stream) => serve(Box::new(stream)),
+						Err(e) => log::warn!("{} failed to accept a client: {}", name, e),
+					}
+				}
+			});
+		}
+	}
+	Ok(())
+}
+
+/// Check we could listen on the address, without starting the monitor.
+///
+/// We let go of it again straight away, and remove the socket file for a
+/// Unix socket.
+pub fn probe(address: &Address) -> Result<(), String> {
+	match address {
+		Address::Unix(path) => {
+			let listener = sys::bind(path)?;
+			drop(listener);
+			std::fs::remove_file(path).map_err(|e| format!("{}: {}", path.display(), e))
+		}
+		Address::Tcp(address) => TcpListener::bind(address)
+			.map(drop)
+			.map_err(|e| format!("{}: {}", address, e)),
+	}
+}
+
+/// Run commands for one client, until it hangs up.
+fn serve(stream: Box<dyn Connection>) {
+	log::info!("Monitor client connected");
+	if let Err(e) = talk(stream) {
+		log::warn!("Monitor client went away: {}", e);
+	}
+	log::info!("Monitor client disconnected");
+}
+
+/// Read commands from a client and write back the results.
+fn talk(stream: Box<dyn Connection>) -> std::io::Result<()> {
+	let mut writer = stream.try_clone()?;
+	writeln!(
+		writer,
+		"Neotron Desktop BIOS {} monitor. Type 'help' for a list of commands.",
+		env!("CARGO_PKG_VERSION")
+	)?;
+	for line in std::io::BufReader::new(stream).lines() {
+		if let Some(output) = crate::console::run(&line?) {
+			writeln!(writer, "{}", output)?;
+		}
+		writer.flush()?;
+	}
+	Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Connection for TcpStream {
+	fn try_clone(&self) -> std::io::Result<Box<dyn Connection>> {
+		TcpStream::try_clone(self).map(|stream| Box::new(stream) as Box<dyn Connection>)
+	}
+}
+
+impl std::fmt::Display for Address {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Address::Unix(path) => write!(f, "unix:{}", path.display()),
+			Address::Tcp(address) => write!(f, "tcp:{}", address),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Host specific code
+// -----------------------------------------------------------------------------
+
+#[cfg(unix)]
+mod sys {
+	use std::os::unix::net::{UnixListener, UnixStream};
+	use std::path::Path;
+
+	use super::Connection;
+
+	impl Connection for UnixStream {
+		fn try_clone(&self) -> std::io::Result<Box<dyn Connection>> {
+			UnixStream::try_clone(self).map(|stream| Box::new(stream) as Box<dyn Connection>)
+		}
+	}
+
+	/// Listen on a Unix socket, tidying up one left behind by an earlier run.
+	pub fn bind(path: &Path) -> Result<UnixListener, String> {
+		if path.exists() {
+			if UnixStream::connect(path).is_ok() {
+				return Err(format!(
+					"{}: something is already listening",
+					path.display()
+				));
+			}
+			// Nobody's listening, so it's left over from a run that didn't
+			// tidy up after itself
+			std::fs::remove_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+		}
+		UnixListener::bind(path).map_err(|e| format!("{}: {}", path.display(), e))
+	}
+
+	/// Serve each client in turn, for as long as we're running.
+	pub fn accept_all(listener: UnixListener, name: &str, serve: fn(Box<dyn Connection>)) {
+		for stream in listener.incoming() {
+			match stream {
+				Ok(stream) => serve(Box::new(stream)),
+				Err(e) => log::warn!("{} failed to accept a client: {}", name, e),
+			}
+		}
+	}
+}
+
+#[cfg(not(unix))]
+mod sys {
+	use std::path::Path;
+
+	/// We only get here if `parse_address` let a Unix socket through.
+	pub enum UnixListener {}
+
+	pub fn bind(path: &Path) -> Result<UnixListener, String> {
+		Err(format!(
+			"{}: Unix sockets only work on a Unix host",
+			path.display()
+		))
+	}
+
+	pub fn accept_all(listener: UnixListener, _name: &str, _serve: fn(Box<dyn super::Connection>)) {
+		match listener {}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
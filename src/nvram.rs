@@ -0,0 +1,515 @@
+//! # Non-volatile configuration storage for the Neotron Desktop BIOS
+//!
+//! Real Neotron systems keep their configuration in a small EEPROM or some
+//! battery-backed SRAM. We keep it in a file.
+//!
+//! If you give an NVRAM size, we behave like an EEPROM of that size - the OS
+//! can't store any more than that, and a blank NVRAM reads back as all `0xFF`.
+//!
+//! The file starts with a magic number and a CRC-32 of the contents. If the
+//! CRC doesn't match, the OS sees a blank NVRAM rather than garbage. Files
+//! without the header (from older versions) are used as-is.
+//!
+//! For testing how the OS copes with bad data, we can also corrupt the
+//! contents as the OS reads them (see [`Corruption`]).
+//!
+//! With `--os-args`, the OS gets a boot command line in front of its
+//! configuration. The block the OS reads then starts with [`BOOT_ARGS_MAGIC`],
+//! then the command line, then a zero byte, and then the NVRAM contents as
+//! usual. The boot arguments are never stored - if the OS writes back a block
+//! that starts with them, we take them off first. An OS that doesn't know about
+//! boot arguments should be run without `--os-args`.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Things that can go wrong with the NVRAM.
+#[derive(Debug)]
+pub enum Error {
+	/// We don't have an NVRAM file
+	NoFile,
+	/// Some hex bytes on the command line weren't valid
+	BadHex,
+	/// The OS tried to store more than the NVRAM can hold
+	TooBig { len: usize, size: usize },
+	/// The file's checksum is wrong
+	BadChecksum,
+	/// We couldn't read or write the file
+	Io(std::io::Error),
+}
+
+/// Deliberate damage done to the NVRAM contents as the OS reads them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Corruption {
+	/// Flip one random bit in each byte, with this probability
+	BitFlip(f64),
+	/// Invert every bit of the byte at this offset
+	Offset(usize),
+}
+
+/// Things you can do to the NVRAM file from the command line.
+#[derive(Debug, clap::Subcommand)]
+pub enum Action {
+	/// Show the NVRAM contents
+	Dump,
+	/// Change some bytes in the NVRAM
+	Write {
+		/// Where to start writing
+		#[arg(long, default_value_t = 0)]
+		offset: usize,
+		/// The bytes to write, in hex (e.g. "01 02 03" or "010203")
+		#[arg(long)]
+		hex: String,
+	},
+	/// Erase the NVRAM
+	Erase,
+}
+
+/// How the NVRAM is set up.
+struct Settings {
+	/// Where the OS config is read from or written to
+	path: Option<PathBuf>,
+	/// How big the NVRAM is, or `None` for no limit
+	size: Option<usize>,
+	/// How to damage the contents on read, if at all
+	corruption: Option<Corruption>,
+	/// State for our random number generator
+	rng_state: u64,
+	/// The boot command line to give the OS, if any
+	boot_args: Option<String>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What erased EEPROM reads as.
+const ERASED: u8 = 0xFF;
+
+/// The start of every NVRAM file we write.
+const MAGIC: [u8; 4] = *b"NVR1";
+
+/// How long the file header is - the magic number, then the CRC-32 of the
+/// contents (little-endian).
+const HEADER_LEN: usize = 8;
+
+/// The start of the configuration block when it has boot arguments in front.
+pub const BOOT_ARGS_MAGIC: [u8; 4] = *b"ARGS";
+
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+	path: None,
+	size: None,
+	corruption: None,
+	rng_state: 0,
+	boot_args: None,
+});
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Where we keep the NVRAM if `--nvram` isn't given.
+///
+/// This is `neotron-desktop-bios/nvram.bin` in the platform's configuration
+/// directory (e.g. `~/.config` on Linux).
+pub fn default_path() -> Option<PathBuf> {
+	dirs::config_dir().map(|dir| dir.join("neotron-desktop-bios").join("nvram.bin"))
+}
+
+/// Use this file as the NVRAM.
+pub fn set_path(path: PathBuf) {
+	SETTINGS.lock().unwrap().path = Some(path);
+}
+
+/// Limit the NVRAM to this many bytes.
+pub fn set_size(size: usize) {
+	SETTINGS.lock().unwrap().size = Some(size);
+}
+
+/// Give the OS this boot command line, in front of its configuration.
+pub fn set_boot_args(args: String) {
+	SETTINGS.lock().unwrap().boot_args = Some(args);
+}
+
+/// Take the boot arguments off the front of a configuration block, if they
+/// are there.
+pub fn strip_boot_args(data: &[u8]) -> &[u8] {
+	let Some(rest) = data.strip_prefix(&BOOT_ARGS_MAGIC) else {
+		return data;
+	};
+	match rest.iter().position(|b| *b == 0) {
+		Some(end) => &rest[end + 1..],
+		None => data,
+	}
+}
+
+/// Parse an `--os-args` option. It can't contain a zero byte, as that ends
+/// the string.
+pub fn parse_boot_args(text: &str) -> Result<String, String> {
+	if text.contains('\0') {
+		Err("the boot arguments can't contain a NUL".to_string())
+	} else {
+		Ok(text.to_string())
+	}
+}
+
+/// Damage the contents as the OS reads them.
+pub fn set_corruption(corruption: Corruption) {
+	let mut settings = SETTINGS.lock().unwrap();
+	settings.corruption = Some(corruption);
+	// Anything non-zero will do
+	settings.rng_state = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map(|d| d.as_nanos() as u64)
+		.unwrap_or_default()
+		| 1;
+}
+
+/// Parse a `--nvram-corrupt` option, like `bitflip:0.01` or `offset:12`.
+pub fn parse_corruption(text: &str) -> Result<Corruption, String> {
+	let bad = || {
+		format!(
+			"{:?} isn't a valid corruption (try bitflip:<probability> or offset:<byte>)",
+			text
+		)
+	};
+	let (kind, value) = text.split_once(':').ok_or_else(bad)?;
+	match kind {
+		"bitflip" => match value.parse::<f64>() {
+			Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(Corruption::BitFlip(rate)),
+			_ => Err(bad()),
+		},
+		"offset" => value.parse().map(Corruption::Offset).map_err(|_| bad()),
+		_ => Err(bad()),
+	}
+}
+
+/// Check the NVRAM file is intact, and complain loudly if it isn't.
+///
+/// Call this at start-up.
+pub fn check() {
+	let Ok((path, _)) = settings() else {
+		return;
+	};
+	match load() {
+		Ok(_) => {}
+		Err(Error::BadChecksum) => log::error!(
+			"NVRAM file {} is corrupt (bad checksum) - the OS will see a blank NVRAM",
+			path.display()
+		),
+		Err(e) => log::error!("Can't read NVRAM file {}: {}", path.display(), e),
+	}
+}
+
+/// Get the NVRAM contents, as the OS sees them, with any boot arguments in
+/// front.
+///
+/// If the file doesn't exist yet, or is corrupt, the NVRAM is blank - either
+/// empty, or full of `0xFF` if it has a fixed size. If we have boot arguments
+/// but no NVRAM at all, the OS just gets the boot arguments.
+pub fn read() -> Result<Vec<u8>, Error> {
+	let mut data = match load() {
+		Ok(data) => data,
+		Err(Error::BadChecksum) => blank(settings()?.1),
+		Err(Error::NoFile) if SETTINGS.lock().unwrap().boot_args.is_some() => Vec::new(),
+		Err(e) => return Err(e),
+	};
+	let mut settings = SETTINGS.lock().unwrap();
+	settings.corrupt(&mut data);
+	match &settings.boot_args {
+		Some(args) => {
+			let mut block = BOOT_ARGS_MAGIC.to_vec();
+			block.extend_from_slice(args.as_bytes());
+			block.push(0);
+			block.extend_from_slice(&data);
+			Ok(block)
+		}
+		None => Ok(data),
+	}
+}
+
+/// Get the NVRAM contents from the file, without any `--nvram-corrupt`.
+///
+/// If the file doesn't exist yet, the NVRAM is blank.
+pub fn load() -> Result<Vec<u8>, Error> {
+	let (path, size) = settings()?;
+	match std::fs::read(&path) {
+		Ok(file) => decode(&file).ok_or(Error::BadChecksum),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(blank(size)),
+		Err(e) => Err(Error::Io(e)),
+	}
+}
+
+/// Perform an NVRAM command from the command line.
+pub fn run(action: &Action) -> Result<(), Error> {
+	match action {
+		Action::Dump => {
+			let data = load()?;
+			println!("{} bytes of NVRAM in {}", data.len(), describe());
+			println!("{}", crate::hexdump::hexdump(&data, 0));
+		}
+		Action::Write { offset, hex } => {
+			let bytes = crate::hexdump::parse_hex(hex).ok_or(Error::BadHex)?;
+			let mut data = match load() {
+				Ok(data) => data,
+				Err(Error::BadChecksum) => {
+					log::warn!("NVRAM was corrupt - starting from blank");
+					blank(settings()?.1)
+				}
+				Err(e) => return Err(e),
+			};
+			// A fixed size NVRAM never grows, and an unlimited one only as
+			// far as we can hold it
+			let size = settings()?.1;
+			let limit = size.unwrap_or(isize::MAX as usize);
+			let end = offset
+				.checked_add(bytes.len())
+				.filter(|end| *end <= limit)
+				.ok_or(Error::TooBig {
+					len: offset.saturating_add(bytes.len()),
+					size: limit,
+				})?;
+			if data.len() < end {
+				data.try_reserve_exact(end - data.len())
+					.map_err(|_| Error::Io(std::io::ErrorKind::OutOfMemory.into()))?;
+				data.resize(end, ERASED);
+			}
+			data[*offset..end].copy_from_slice(&bytes);
+			write(&data)?;
+			println!("Wrote {} bytes at offset {}", bytes.len(), offset);
+		}
+		Action::Erase => {
+			erase()?;
+			println!("NVRAM erased");
+		}
+	}
+	Ok(())
+}
+
+/// Replace the NVRAM contents.
+pub fn write(data: &[u8]) -> Result<(), Error> {
+	let (path, size) = settings()?;
+	if let Some(size) = size {
+		if data.len() > size {
+			return Err(Error::TooBig {
+				len: data.len(),
+				size,
+			});
+		}
+	}
+	replace_file(&path, &encode(data)).map_err(Error::Io)
+}
+
+/// Replace the contents of a file, such that if we're killed part way
+/// through, the file has either the old contents or the new contents.
+///
+/// We write a temporary file next to it, flush it to disk, then rename it
+/// over the top. The temporary file has a random name, and we never open an
+/// existing file or follow a symbolic link to make it, so nobody can plant a
+/// link for us to write through.
+pub fn replace_file(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	std::fs::create_dir_all(dir)?;
+	let (temp_path, mut file) = create_temporary(dir, path)?;
+	let result = (|| {
+		file.write_all(contents)?;
+		file.sync_all()?;
+		std::fs::rename(&temp_path, path)
+	})();
+	if result.is_err() {
+		let _ = std::fs::remove_file(&temp_path);
+	}
+	result?;
+	// Make sure the rename itself is on disk. You can't open a directory on
+	// Windows, but there the rename is good enough.
+	#[cfg(unix)]
+	std::fs::File::open(dir)?.sync_all()?;
+	Ok(())
+}
+
+/// Make a new, empty file in `dir` to write `path`'s new contents to.
+fn create_temporary(dir: &Path, path: &Path) -> Result<(PathBuf, std::fs::File), std::io::Error> {
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	let mut attempts = 0;
+	loop {
+		let temp_path = dir.join(format!(".{}.{:016x}.tmp", file_name, random()));
+		let mut options = std::fs::OpenOptions::new();
+		options.write(true).create_new(true);
+		#[cfg(unix)]
+		std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+		match options.open(&temp_path) {
+			Ok(file) => return Ok((temp_path, file)),
+			Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 16 => {
+				attempts += 1;
+			}
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// A random number, good enough to name a temporary file.
+fn random() -> u64 {
+	use std::hash::{BuildHasher, Hasher};
+	// Each `RandomState` is seeded differently
+	let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+	hasher.write_u32(std::process::id());
+	hasher.finish()
+}
+
+/// Erase the NVRAM, so it reads back as blank.
+pub fn erase() -> Result<(), Error> {
+	let (path, _) = settings()?;
+	match std::fs::remove_file(&path) {
+		Ok(()) => Ok(()),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+		Err(e) => Err(Error::Io(e)),
+	}
+}
+
+/// Add our header to the NVRAM contents.
+fn encode(data: &[u8]) -> Vec<u8> {
+	let mut file = Vec::with_capacity(HEADER_LEN + data.len());
+	file.extend_from_slice(&MAGIC);
+	file.extend_from_slice(&crc32(data).to_le_bytes());
+	file.extend_from_slice(data);
+	file
+}
+
+/// Get the NVRAM contents from a file, checking the CRC.
+///
+/// Returns `None` if the CRC is wrong.
+fn decode(file: &[u8]) -> Option<Vec<u8>> {
+	if file.len() < HEADER_LEN || file[0..4] != MAGIC {
+		// An old file, with no header
+		return Some(file.to_vec());
+	}
+	let crc = u32::from_le_bytes([file[4], file[5], file[6], file[7]]);
+	let data = &file[HEADER_LEN..];
+	(crc32(data) == crc).then(|| data.to_vec())
+}
+
+/// The standard (IEEE 802.3) CRC-32.
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for byte in data {
+		crc ^= u32::from(*byte);
+		for _ in 0..8 {
+			let mask = (crc & 1).wrapping_neg();
+			crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+		}
+	}
+	!crc
+}
+
+/// Describe the NVRAM, for the start-up log.
+pub fn describe() -> String {
+	let settings = SETTINGS.lock().unwrap();
+	let size = match settings.size {
+		Some(size) => format!("{} bytes", size),
+		None => String::from("unlimited size"),
+	};
+	match settings.path.as_ref() {
+		Some(path) => format!("{} ({})", path.display(), size),
+		None => String::from("none"),
+	}
+}
+
+/// The contents of a blank NVRAM.
+fn blank(size: Option<usize>) -> Vec<u8> {
+	vec![ERASED; size.unwrap_or(0)]
+}
+
+/// Get the NVRAM file and size.
+fn settings() -> Result<(PathBuf, Option<usize>), Error> {
+	let settings = SETTINGS.lock().unwrap();
+	let path = settings.path.clone().ok_or(Error::NoFile)?;
+	Ok((path, settings.size))
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Settings {
+	/// Apply any deliberate corruption to data the OS is about to read.
+	fn corrupt(&mut self, data: &mut [u8]) {
+		match self.corruption {
+			Some(Corruption::BitFlip(rate)) => {
+				for byte in data.iter_mut() {
+					let roll = self.next_random();
+					// Use the top bits for the probability, and the bottom
+					// bits to pick a bit to flip.
+					if ((roll >> 11) as f64 / (1u64 << 53) as f64) < rate {
+						*byte ^= 1 << (roll & 7);
+					}
+				}
+			}
+			Some(Corruption::Offset(offset)) => {
+				if let Some(byte) = data.get_mut(offset) {
+					*byte = !*byte;
+				}
+			}
+			None => {}
+		}
+	}
+
+	/// A quick xorshift random number generator. It only needs to be good
+	/// enough to pick which bytes to damage.
+	fn next_random(&mut self) -> u64 {
+		let mut x = self.rng_state;
+		x ^= x << 13;
+		x ^= x >> 7;
+		x ^= x << 17;
+		self.rng_state = x;
+		x
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::NoFile => write!(f, "no NVRAM file"),
+			Error::BadHex => write!(f, "invalid hex bytes"),
+			Error::BadChecksum => write!(f, "bad checksum"),
+			Error::TooBig { len, size } => {
+				write!(f, "{} bytes won't fit in {} bytes of NVRAM", len, size)
+			}
+			Error::Io(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
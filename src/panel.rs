@@ -0,0 +1,155 @@
+//! # Virtual LED and button panel for the Neotron Desktop BIOS
+//!
+//! If there's a PCF8574 on an I²C bus, we draw its eight LEDs and eight
+//! buttons in the top-right corner of the window. If there's a GPIO peripheral
+//! on the Neotron Bus, we draw its eight output LEDs and eight input switches
+//! under that. Bit 7 is on the left. Click a button to press it, or a switch
+//! to flip it.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use pix_engine::prelude::*;
+
+use crate::{bus, i2c};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Something on the panel you can click.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Control {
+	/// One of the PCF8574's buttons
+	Button(u8),
+	/// One of the GPIO peripheral's input switches
+	Switch(u8),
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The size of the square holding each LED and each button.
+const CELL: i32 = 12;
+
+/// The gap around the edge of the panel.
+const MARGIN: i32 = 4;
+
+/// How wide the panel is.
+const WIDTH: i32 = 8 * CELL + 2 * MARGIN;
+
+/// How tall each section of the panel is.
+const HEIGHT: i32 = 2 * CELL + 2 * MARGIN;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Draw the panel, if we have one, over a screen this wide.
+pub fn draw(s: &mut PixState, screen_width: i32) -> PixResult<()> {
+	let left = screen_width - WIDTH;
+	if let Some(panel) = i2c::panel() {
+		draw_background(s, left, 0)?;
+		for bit in 0..8 {
+			let x = cell_left(left, bit);
+			let lit = panel.latch & (1 << bit) == 0;
+			s.fill(if lit {
+				rgb!(255, 48, 48)
+			} else {
+				rgb!(64, 0, 0)
+			});
+			s.circle(circle![x + CELL / 2, MARGIN + CELL / 2, CELL / 3])?;
+			let pressed = panel.buttons & (1 << bit) != 0;
+			s.fill(if pressed {
+				rgb!(240, 240, 240)
+			} else {
+				rgb!(128, 128, 128)
+			});
+			s.rect(rect![x + 2, MARGIN + CELL + 2, CELL - 4, CELL - 4])?;
+		}
+	}
+	if let Some(lines) = bus::lines() {
+		let top = gpio_top();
+		draw_background(s, left, top)?;
+		for bit in 0..8 {
+			let x = cell_left(left, bit);
+			let lit = lines.outputs & (1 << bit) != 0;
+			s.fill(if lit {
+				rgb!(48, 255, 48)
+			} else {
+				rgb!(0, 64, 0)
+			});
+			s.circle(circle![x + CELL / 2, top + MARGIN + CELL / 2, CELL / 3])?;
+			// A DIP switch, with the knob at the top when the input is high
+			let y = top + MARGIN + CELL;
+			s.fill(rgb!(224, 224, 224));
+			s.rect(rect![x + 3, y + 1, CELL - 6, CELL - 2])?;
+			let on = lines.inputs & (1 << bit) != 0;
+			let knob_y = if on { y + 2 } else { y + CELL / 2 };
+			s.fill(rgb!(48, 48, 48));
+			s.rect(rect![x + 4, knob_y, CELL - 8, CELL / 2 - 2])?;
+		}
+	}
+	Ok(())
+}
+
+/// Which control, if any, is at this point on a screen this wide.
+pub fn control_at(x: i32, y: i32, screen_width: i32) -> Option<Control> {
+	let left = screen_width - WIDTH;
+	let bit =
+		(0..8).find(|bit| (cell_left(left, *bit)..cell_left(left, *bit) + CELL).contains(&x))?;
+	let controls_at = |top: i32| (top + MARGIN + CELL..top + MARGIN + 2 * CELL).contains(&y);
+	if i2c::panel().is_some() && controls_at(0) {
+		Some(Control::Button(bit))
+	} else if bus::lines().is_some() && controls_at(gpio_top()) {
+		Some(Control::Switch(bit))
+	} else {
+		None
+	}
+}
+
+/// Fill in the background of a section of the panel.
+fn draw_background(s: &mut PixState, left: i32, top: i32) -> PixResult<()> {
+	s.stroke(None);
+	s.fill(rgb!(32, 32, 32, 224));
+	s.rect(rect![left, top, WIDTH, HEIGHT])
+}
+
+/// Where the GPIO section starts. It goes under the PCF8574, if there is one.
+fn gpio_top() -> i32 {
+	if i2c::panel().is_some() {
+		HEIGHT
+	} else {
+		0
+	}
+}
+
+/// Where the cell for this bit starts.
+fn cell_left(panel_left: i32, bit: u8) -> i32 {
+	panel_left + MARGIN + (7 - i32::from(bit)) * CELL
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,107 @@
+//! # Pausing the emulation
+//!
+//! We can't stop the OS thread from the outside, so instead the BIOS functions
+//! the OS calls most often check in here first. While we're paused, they
+//! don't return. Once we're shutting down, they never return.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::{Condvar, Mutex};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Are we paused?
+static PAUSED: Mutex<bool> = Mutex::new(false);
+
+/// Wakes up the OS thread when we un-pause.
+static RESUMED: Condvar = Condvar::new();
+
+/// Set while the OS thread is stopped in [`checkpoint`].
+static PARKED: Mutex<bool> = Mutex::new(false);
+
+/// Wakes up anyone waiting for the OS thread to stop.
+static PARKED_CHANGED: Condvar = Condvar::new();
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Pause or resume the OS.
+///
+/// Emulated time stops while we're paused, so the OS doesn't see a jump when
+/// it carries on.
+pub fn set_paused(paused: bool) {
+	crate::clock::set_paused(paused);
+	*PAUSED.lock().unwrap() = paused;
+	if !paused {
+		RESUMED.notify_all();
+	}
+}
+
+/// Is the OS paused?
+pub fn is_paused() -> bool {
+	*PAUSED.lock().unwrap()
+}
+
+/// Block the calling thread until we're not paused.
+///
+/// Call this from the OS thread only - never from the GUI thread.
+pub fn checkpoint() {
+	let mut paused = PAUSED.lock().unwrap();
+	if *paused {
+		set_parked(true);
+		while *paused {
+			paused = RESUMED.wait(paused).unwrap();
+		}
+		set_parked(false);
+	}
+	drop(paused);
+	crate::shutdown::checkpoint();
+}
+
+/// Pause the OS, and wait until it has actually stopped.
+///
+/// Returns `false` if it didn't stop within `timeout` (perhaps it's busy and
+/// not calling the BIOS), in which case it is still paused. Never call this
+/// from the OS thread.
+pub fn freeze(timeout: std::time::Duration) -> bool {
+	set_paused(true);
+	let parked = PARKED.lock().unwrap();
+	let (parked, _) = PARKED_CHANGED
+		.wait_timeout_while(parked, timeout, |parked| !*parked)
+		.unwrap();
+	*parked
+}
+
+/// Note whether the OS thread is stopped.
+fn set_parked(parked: bool) {
+	*PARKED.lock().unwrap() = parked;
+	PARKED_CHANGED.notify_all();
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,181 @@
+//! # Checking the machine before starting it
+//!
+//! `--check` goes through the start-up sequence - opening the disk image,
+//! reading the NVRAM, adding the I²C and Neotron Bus devices, binding the
+//! monitor socket, starting the host's audio and loading the OS - but stops
+//! short of opening the window or starting the OS. Each step passes or fails
+//! on its own, and we print a line for each, so a script finds out straight
+//! away that a port is in use or an image path is wrong, rather than after the
+//! window appears.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use crate::block::BLOCK_SIZE;
+use crate::{disk, loader, memory, nvram, snapshot, wav};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The results of every check, in the order we made them.
+#[derive(Debug, Default)]
+pub struct Report {
+	checks: Vec<Check>,
+}
+
+/// How one part of the machine got on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+	/// What we checked (e.g. `Disk`)
+	pub component: String,
+	/// What we found if it passed, or why it failed
+	pub result: Result<String, String>,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Open the disk image the way the block device does, and say how big it is.
+pub fn disk(path: &Path) -> Result<String, String> {
+	let file = disk::open(path).map_err(|e| e.to_string())?;
+	let bytes = file
+		.metadata()
+		.map_err(|e| format!("{}: {}", path.display(), e))?
+		.len();
+	Ok(format!(
+		"{}, {} blocks ({})",
+		path.display(),
+		bytes / BLOCK_SIZE as u64,
+		memory::describe_size(bytes)
+	))
+}
+
+/// Read the NVRAM file, if we have one. A file that isn't there yet is fine -
+/// the OS gets a blank NVRAM - but a corrupt one isn't.
+pub fn nvram() -> Result<String, String> {
+	match nvram::load() {
+		Ok(_) | Err(nvram::Error::NoFile) => Ok(nvram::describe()),
+		Err(nvram::Error::BadChecksum) => {
+			Err(format!("{} is corrupt (bad checksum)", nvram::describe()))
+		}
+		Err(e) => Err(format!("{}: {}", nvram::describe(), e)),
+	}
+}
+
+/// Read a snapshot we've been asked to resume from.
+pub fn snapshot(path: &Path) -> Result<String, String> {
+	snapshot::Snapshot::load(path)
+		.map(|_| path.display().to_string())
+		.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Read the WAV file we've been asked to use as the audio input.
+pub fn wav(path: &Path) -> Result<String, String> {
+	wav::Wav::load(path)
+		.map(|_| path.display().to_string())
+		.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Find and load the OS library, checking it wants a BIOS API we can give it.
+pub fn os(explicit: &[PathBuf]) -> Result<String, String> {
+	let (path, _main) = loader::choose(explicit)?;
+	Ok(match loader::os_version() {
+		Some(version) => format!("{} ({})", path.display(), version),
+		None => path.display().to_string(),
+	})
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Report {
+	/// An empty report.
+	pub fn new() -> Report {
+		Report::default()
+	}
+
+	/// Add the result of checking one part of the machine.
+	pub fn record(&mut self, component: &str, result: Result<String, String>) {
+		self.checks.push(Check {
+			component: component.to_string(),
+			result,
+		});
+	}
+
+	/// Everything we checked.
+	pub fn checks(&self) -> &[Check] {
+		&self.checks
+	}
+
+	/// Did everything pass?
+	pub fn passed(&self) -> bool {
+		self.checks.iter().all(|check| check.result.is_ok())
+	}
+
+	/// Describe the results, one line for each check, then a total.
+	pub fn lines(&self) -> Vec<String> {
+		let width = self
+			.checks
+			.iter()
+			.map(|check| check.component.len())
+			.max()
+			.unwrap_or_default();
+		let mut lines: Vec<String> = self
+			.checks
+			.iter()
+			.map(|check| {
+				let (verdict, detail) = match &check.result {
+					Ok(detail) => ("PASS", detail),
+					Err(detail) => ("FAIL", detail),
+				};
+				format!(
+					"{}  {:width$}  {}",
+					verdict,
+					check.component,
+					detail,
+					width = width
+				)
+			})
+			.collect();
+		let failed = self
+			.checks
+			.iter()
+			.filter(|check| check.result.is_err())
+			.count();
+		lines.push(if failed == 0 {
+			String::from("All checks passed")
+		} else {
+			format!("{} of {} checks failed", failed, self.checks.len())
+		});
+		lines
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,304 @@
+//! # Machine profiles
+//!
+//! A profile is a directory holding everything about one emulated machine
+//! setup, so switching between them is one `--profile` option. Profiles live
+//! in `profiles` in our config directory (e.g.
+//! `~/.config/neotron-desktop-bios/profiles/pico2`), and each one holds:
+//!
+//! * `config.toml` - options for this machine (see [`crate::config`])
+//! * `nvram.bin` - the NVRAM
+//! * `rtc.toml` - how far the OS has set the real-time clock from the host's
+//! * `window.toml` - where the window was last
+//! * `disks` - somewhere to keep its disk images
+//!
+//! We make a new profile with an empty `disks` directory and a commented-out
+//! `config.toml` the first time it's used.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// One machine setup, and the directory it's kept in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+	name: String,
+	dir: PathBuf,
+}
+
+/// The ways using a profile can go wrong.
+#[derive(Debug)]
+pub enum Error {
+	/// The name would be a bad directory name
+	BadName(String),
+	/// The host doesn't have a config directory for us to put profiles in
+	NoConfigDir,
+	/// We couldn't read or write something in the profile
+	Io(PathBuf, std::io::Error),
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The profile we're using, if any.
+static ACTIVE: Mutex<Option<Profile>> = Mutex::new(None);
+
+/// What a new profile's `config.toml` says.
+const CONFIG_TEMPLATE: &str = "\
+# Options for the '{name}' profile. Each one is a command-line option,
+# without the '--', and they all start commented out. For example:
+#
+# device = [\"disk=file:disks/sd.img\", \"i2c:0x48=lm75,bus=1\"]
+# ram2-size = \"8MiB\"
+# headless = true
+#
+# Relative paths are relative to this directory. The command line and
+# NEOTRON_BIOS_* environment variables win over anything in here.
+";
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Where profiles live, like `~/.config/neotron-desktop-bios/profiles`.
+pub fn root() -> Option<PathBuf> {
+	dirs::config_dir().map(|dir| dir.join("neotron-desktop-bios").join("profiles"))
+}
+
+/// The names of the profiles in `root`, in alphabetical order.
+///
+/// A `root` that doesn't exist yet just has no profiles in it.
+pub fn list(root: &Path) -> Result<Vec<String>, Error> {
+	let entries = match std::fs::read_dir(root) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(Error::Io(root.to_owned(), e)),
+	};
+	let mut names = Vec::new();
+	for entry in entries {
+		let entry = entry.map_err(|e| Error::Io(root.to_owned(), e))?;
+		if entry.path().is_dir() {
+			names.push(entry.file_name().to_string_lossy().into_owned());
+		}
+	}
+	names.sort();
+	Ok(names)
+}
+
+/// Use this profile from now on, for the NVRAM, the real-time clock and the
+/// window position.
+pub fn set_active(profile: Profile) {
+	log::info!(
+		"Using profile {:?} in {}",
+		profile.name,
+		profile.dir.display()
+	);
+	*ACTIVE.lock().unwrap() = Some(profile);
+}
+
+/// The profile we're using, if any.
+pub fn active() -> Option<Profile> {
+	ACTIVE.lock().unwrap().clone()
+}
+
+/// How far the OS set the real-time clock from the host's clock, the last
+/// time it set it, in nanoseconds.
+///
+/// Without a profile, or if the OS has never set the clock, the clock starts
+/// off matching the host's.
+pub fn load_rtc() -> Option<i128> {
+	let path = active()?.rtc_path();
+	let table = read_table(&path)?;
+	let offset = table.get("offset-ns")?.as_integer()?;
+	Some(i128::from(offset))
+}
+
+/// Remember how far the OS has set the real-time clock from the host's clock,
+/// like the battery-backed clock on a real board.
+pub fn save_rtc(offset_ns: i128) {
+	let Some(profile) = active() else {
+		return;
+	};
+	let Ok(offset_ns) = i64::try_from(offset_ns) else {
+		log::warn!("Can't save a clock offset of {} ns", offset_ns);
+		return;
+	};
+	write_table(
+		&profile.rtc_path(),
+		&format!(
+			"# How far the OS set the clock from the host's clock\noffset-ns = {}\n",
+			offset_ns
+		),
+	);
+}
+
+/// Where the window was last, if we have a profile and it knows.
+pub fn load_window_position() -> Option<(i32, i32)> {
+	let path = active()?.window_path();
+	let table = read_table(&path)?;
+	let x = table.get("x")?.as_integer()?;
+	let y = table.get("y")?.as_integer()?;
+	Some((i32::try_from(x).ok()?, i32::try_from(y).ok()?))
+}
+
+/// Remember where the window is, so it opens there next time.
+pub fn save_window_position(x: i32, y: i32) {
+	if let Some(profile) = active() {
+		write_table(&profile.window_path(), &format!("x = {}\ny = {}\n", x, y));
+	}
+}
+
+/// Read one of our small TOML files. A missing or broken file gets a warning
+/// (if broken) and `None`, as these are only ever hints.
+fn read_table(path: &Path) -> Option<toml::Table> {
+	let text = match std::fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+		Err(e) => {
+			log::warn!("Can't read {}: {}", path.display(), e);
+			return None;
+		}
+	};
+	match text.parse() {
+		Ok(table) => Some(table),
+		Err(e) => {
+			log::warn!("Ignoring {}: {}", path.display(), e);
+			None
+		}
+	}
+}
+
+/// Write one of our small TOML files.
+fn write_table(path: &Path, contents: &str) {
+	if let Err(e) = crate::nvram::replace_file(path, contents.as_bytes()) {
+		log::warn!("Can't write {}: {}", path.display(), e);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Profile {
+	/// Open the profile called `name` in `root`, making it if it doesn't exist
+	/// yet.
+	///
+	/// Names are used as directory names, so we only allow letters, numbers,
+	/// `-` and `_`.
+	pub fn open(root: &Path, name: &str) -> Result<Profile, Error> {
+		let good_name = !name.is_empty()
+			&& name
+				.chars()
+				.all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+		if !good_name {
+			return Err(Error::BadName(name.to_string()));
+		}
+		let profile = Profile {
+			name: name.to_string(),
+			dir: root.join(name),
+		};
+		if !profile.dir.exists() {
+			profile.scaffold()?;
+			log::info!("Made a new profile {:?} in {}", name, profile.dir.display());
+		}
+		Ok(profile)
+	}
+
+	/// Open the profile called `name` in our config directory, making it if
+	/// it doesn't exist yet.
+	pub fn open_default(name: &str) -> Result<Profile, Error> {
+		let root = root().ok_or(Error::NoConfigDir)?;
+		Profile::open(&root, name)
+	}
+
+	/// Make the profile's directory, its `disks` directory and its
+	/// `config.toml`.
+	fn scaffold(&self) -> Result<(), Error> {
+		let disks = self.disks_dir();
+		std::fs::create_dir_all(&disks).map_err(|e| Error::Io(disks, e))?;
+		let config = self.config_path();
+		std::fs::write(&config, CONFIG_TEMPLATE.replace("{name}", &self.name))
+			.map_err(|e| Error::Io(config, e))
+	}
+
+	/// What the profile is called.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// The profile's directory.
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	/// The profile's options.
+	pub fn config_path(&self) -> PathBuf {
+		self.dir.join("config.toml")
+	}
+
+	/// The profile's NVRAM.
+	pub fn nvram_path(&self) -> PathBuf {
+		self.dir.join("nvram.bin")
+	}
+
+	/// Where we keep the profile's real-time clock.
+	pub fn rtc_path(&self) -> PathBuf {
+		self.dir.join("rtc.toml")
+	}
+
+	/// Where we keep the profile's window position.
+	pub fn window_path(&self) -> PathBuf {
+		self.dir.join("window.toml")
+	}
+
+	/// Somewhere to keep the profile's disk images.
+	pub fn disks_dir(&self) -> PathBuf {
+		self.dir.join("disks")
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::BadName(name) => write!(
+				f,
+				"{:?} isn't a good profile name - use letters, numbers, '-' and '_'",
+				name
+			),
+			Error::NoConfigDir => write!(f, "this host doesn't have a config directory"),
+			Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
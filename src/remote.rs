@@ -0,0 +1,383 @@
+//! # Remote control
+//!
+//! With `--remote`, we listen on a Unix socket (`unix:/tmp/neotron-rc.sock`)
+//! or a TCP port on this machine (`tcp:5555`) for a program to drive the
+//! emulator. Unlike the `--monitor`, which is for people, this speaks one
+//! JSON object per line, so a CI script can press keys, read the screen and
+//! wait for things to happen without scraping the window.
+//!
+//! Each request is an object with a `cmd`, and an `id` if you want it back:
+//!
+//! ```text
+//! {"id":1,"cmd":"keys","keys":["ctrl-alt-del"]}
+//! {"id":1,"ok":true}
+//! {"id":2,"cmd":"eject","drive":3}
+//! {"id":2,"ok":false,"error":"there is no disk drive 3"}
+//! ```
+//!
+//! After `subscribe`, [`Event`]s arrive as objects with an `event` instead,
+//! in between the replies. There's no authentication, so we only listen
+//! where other machines can't reach us. We talk to one client at a time.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+
+use crate::json::Value;
+use crate::monitor::{self, Address, Connection};
+use crate::window::{self, GuiRequest};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Something that happened, which subscribed clients hear about.
+#[derive(Debug, Clone)]
+pub enum Event {
+	/// The OS changed video mode, to the mode with this number
+	Mode(u8),
+	/// The OS panicked, with this message
+	Panic(String),
+	/// The watchdog decided the OS has hung, as it hasn't called the BIOS
+	/// for this long
+	Watchdog(std::time::Duration),
+}
+
+/// A client that wants to hear about some events.
+struct Subscriber {
+	/// Which client it is
+	client: u64,
+	/// The names of the events it wants
+	events: Vec<&'static str>,
+	/// Where its output goes
+	sender: mpsc::Sender<String>,
+}
+
+/// What a command sends back, apart from the `id` and `ok`.
+type Reply = Result<Vec<(&'static str, Value)>, String>;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The names of the events, for `subscribe`.
+pub const EVENT_NAMES: [&str; 3] = ["mode", "panic", "watchdog"];
+
+/// The clients that have subscribed to events.
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// The number we give the next client.
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--remote` address. It's the same as a `--monitor` address, but
+/// a TCP port has to be on this machine.
+pub fn parse_address(text: &str) -> Result<Address, String> {
+	let address = monitor::parse_address(text)?;
+	match &address {
+		Address::Tcp(tcp) if !tcp.ip().is_loopback() => Err(format!(
+			"{} can be reached from other machines, and there's no authentication (try tcp:127.0.0.1:<port> or unix:<path>)",
+			tcp
+		)),
+		_ => Ok(address),
+	}
+}
+
+/// Start listening for remote control clients, on a new thread.
+///
+/// Fails if we can't listen on the address, like if something else already
+/// is.
+pub fn start(address: &Address) -> Result<(), String> {
+	monitor::listen(address, "Remote control", serve)
+}
+
+/// Tell every client that subscribed to this sort of event that it happened.
+pub fn notify(event: Event) {
+	let mut subscribers = SUBSCRIBERS.lock().unwrap();
+	if subscribers.is_empty() {
+		return;
+	}
+	let name = event.name();
+	let line = event.to_json().to_string();
+	// Anyone who's gone away is forgotten
+	subscribers.retain(|subscriber| {
+		!subscriber.events.contains(&name) || subscriber.sender.send(line.clone()).is_ok()
+	});
+}
+
+/// Talk to one client, until it hangs up.
+fn serve(stream: Box<dyn Connection>) {
+	log::info!("Remote control client connected");
+	let client = NEXT_CLIENT.fetch_add(1, Ordering::Relaxed);
+	if let Err(e) = talk(client, stream) {
+		log::warn!("Remote control client went away: {}", e);
+	}
+	unsubscribe(client);
+	log::info!("Remote control client disconnected");
+}
+
+/// Read requests from a client and send back the replies.
+///
+/// Events can turn up at any time, so everything we send goes through one
+/// channel to a thread that does the writing.
+fn talk(client: u64, stream: Box<dyn Connection>) -> std::io::Result<()> {
+	let mut writer = stream.try_clone()?;
+	let (sender, receiver) = mpsc::channel::<String>();
+	let writing = std::thread::spawn(move || -> std::io::Result<()> {
+		for line in receiver {
+			writeln!(writer, "{}", line)?;
+			writer.flush()?;
+		}
+		Ok(())
+	});
+	let hello = Value::object([
+		("event", Value::from("hello")),
+		("version", Value::from(env!("CARGO_PKG_VERSION"))),
+	]);
+	let _ = sender.send(hello.to_string());
+	for line in std::io::BufReader::new(stream).lines() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let reply = handle(client, &sender, &line);
+		if sender.send(reply.to_string()).is_err() {
+			break;
+		}
+	}
+	unsubscribe(client);
+	drop(sender);
+	writing.join().unwrap_or(Ok(()))
+}
+
+/// Run one request, and say how it went.
+fn handle(client: u64, sender: &mpsc::Sender<String>, line: &str) -> Value {
+	let (id, reply) = match crate::json::parse(line) {
+		Ok(request) => {
+			let id = request.get("id").cloned();
+			let reply = match request.get("cmd").and_then(Value::as_str) {
+				Some(cmd) => run(client, sender, cmd, &request),
+				None => Err(String::from("the request needs a \"cmd\"")),
+			};
+			(id, reply)
+		}
+		Err(e) => (None, Err(format!("bad JSON: {}", e))),
+	};
+	let mut members: Vec<(&str, Value)> = Vec::new();
+	if let Some(id) = id {
+		members.push(("id", id));
+	}
+	match reply {
+		Ok(results) => {
+			members.push(("ok", Value::Bool(true)));
+			members.extend(results);
+		}
+		Err(e) => {
+			members.push(("ok", Value::Bool(false)));
+			members.push(("error", Value::String(e)));
+		}
+	}
+	Value::object(members)
+}
+
+/// Run one command.
+fn run(client: u64, sender: &mpsc::Sender<String>, cmd: &str, request: &Value) -> Reply {
+	match cmd {
+		"keys" => {
+			// One chord, or a list of them to press one after the other
+			let chords: Vec<&str> = match request.get("keys") {
+				Some(Value::String(chord)) => vec![chord.as_str()],
+				Some(Value::Array(chords)) => chords
+					.iter()
+					.map(|chord| chord.as_str().ok_or("\"keys\" should be strings"))
+					.collect::<Result<_, _>>()?,
+				_ => return Err("\"keys\" should be a chord or a list of them".into()),
+			};
+			// Check them all before we press any
+			let chords = chords
+				.into_iter()
+				.map(crate::hotkey::parse_chord)
+				.collect::<Result<Vec<_>, _>>()?;
+			for keys in chords {
+				window::gui_request(GuiRequest::SendKeys(keys))?;
+			}
+			Ok(Vec::new())
+		}
+		"screen" => {
+			let screen = crate::video::read_text().ok_or("the screen is not in a text mode")?;
+			let mut results = vec![
+				("mode", Value::from(crate::video::describe_mode())),
+				(
+					"rows",
+					Value::Array(
+						screen
+							.rows
+							.iter()
+							.map(|row| Value::from(row.trim_end()))
+							.collect(),
+					),
+				),
+			];
+			if request.get("attrs") == Some(&Value::Bool(true)) {
+				let attrs = screen
+					.attrs
+					.iter()
+					.map(|row| {
+						Value::Array(row.iter().map(|attr| Value::from(u64::from(attr.0))).collect())
+					})
+					.collect();
+				results.push(("attrs", Value::Array(attrs)));
+			}
+			Ok(results)
+		}
+		"screenshot" => {
+			let path = string(request, "path")?;
+			window::save_screenshot(Path::new(path))?;
+			Ok(Vec::new())
+		}
+		"insert" => {
+			let path = string(request, "path")?;
+			crate::block::insert_disk(drive(request)?, Path::new(path))?;
+			Ok(Vec::new())
+		}
+		"eject" => {
+			crate::block::eject_disk(drive(request)?)?;
+			Ok(Vec::new())
+		}
+		"pause" | "resume" => {
+			window::gui_request(GuiRequest::SetPaused(cmd == "pause"))?;
+			Ok(Vec::new())
+		}
+		"subscribe" => {
+			let events: Vec<&'static str> = match request.get("events") {
+				None => EVENT_NAMES.to_vec(),
+				Some(Value::Array(names)) => names
+					.iter()
+					.map(|name| {
+						EVENT_NAMES
+							.iter()
+							.find(|known| Some(**known) == name.as_str())
+							.copied()
+							.ok_or_else(|| {
+								format!(
+									"unknown event {} (try {})",
+									name,
+									EVENT_NAMES.join(", ")
+								)
+							})
+					})
+					.collect::<Result<_, _>>()?,
+				Some(_) => return Err("\"events\" should be a list of event names".into()),
+			};
+			let listed = Value::Array(events.iter().map(|name| Value::from(*name)).collect());
+			unsubscribe(client);
+			SUBSCRIBERS.lock().unwrap().push(Subscriber {
+				client,
+				events,
+				sender: sender.clone(),
+			});
+			Ok(vec![("events", listed)])
+		}
+		"unsubscribe" => {
+			unsubscribe(client);
+			Ok(Vec::new())
+		}
+		"console" => {
+			let output = crate::console::run(string(request, "line")?).unwrap_or_default();
+			Ok(vec![("output", Value::from(output))])
+		}
+		_ => Err(format!(
+			"unknown command {:?} (try keys, screen, screenshot, insert, eject, pause, resume, subscribe, unsubscribe or console)",
+			cmd
+		)),
+	}
+}
+
+/// A string member of a request, which it can't do without.
+fn string<'a>(request: &'a Value, key: &str) -> Result<&'a str, String> {
+	request
+		.get(key)
+		.and_then(Value::as_str)
+		.ok_or_else(|| format!("the request needs a string {:?}", key))
+}
+
+/// The drive a request is about, which is drive 0 if it doesn't say.
+fn drive(request: &Value) -> Result<u8, String> {
+	match request.get("drive") {
+		None => Ok(0),
+		Some(value) => value
+			.as_u64()
+			.and_then(|drive| u8::try_from(drive).ok())
+			.ok_or_else(|| format!("{} is not a drive number", value)),
+	}
+}
+
+/// Stop sending events to a client.
+fn unsubscribe(client: u64) {
+	SUBSCRIBERS
+		.lock()
+		.unwrap()
+		.retain(|subscriber| subscriber.client != client);
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Event {
+	/// What the event is called, for `subscribe`.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Event::Mode(_) => "mode",
+			Event::Panic(_) => "panic",
+			Event::Watchdog(_) => "watchdog",
+		}
+	}
+
+	/// The event, as we send it.
+	pub fn to_json(&self) -> Value {
+		let mut members = vec![("event", Value::from(self.name()))];
+		match self {
+			Event::Mode(mode) => {
+				members.push(("mode", Value::from(u64::from(*mode))));
+				members.push(("description", Value::from(crate::video::describe_mode())));
+			}
+			Event::Panic(message) => members.push(("message", Value::from(message.as_str()))),
+			Event::Watchdog(since) => {
+				members.push(("since_last_call_ms", Value::from(since.as_millis() as u64)))
+			}
+		}
+		Value::object(members)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,228 @@
+//! # Drawing the screen in software
+//!
+//! The window draws text modes with SDL, a glyph texture at a time. This
+//! module draws the same picture into a plain RGBA [`Image`], without SDL or a
+//! window, so we can save it as a PNG or compare it with what it should look
+//! like. The window draws bitmap modes this way too, then shows the picture
+//! as a single texture. The golden-image tests in `tests/golden.rs` use it to make sure the
+//! glyphs, the colours and the bitmap modes keep coming out the same.
+//!
+//! Like the window, we don't draw blinking text any differently.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use neotron_common_bios as common;
+
+use common::video::RGBColour;
+
+use crate::font;
+use crate::video::{current_mode, FRAMEBUFFER, PALETTE};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A picture, four bytes (red, green, blue and alpha) per pixel, a row at a
+/// time from the top left.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Image {
+	pub width: usize,
+	pub height: usize,
+	pub pixels: Vec<u8>,
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Draw what's on the screen, in whatever video mode we're in.
+pub fn render_screen() -> Image {
+	render_mode(current_mode())
+}
+
+/// Draw the framebuffer as if we were in `mode`.
+pub fn render_mode(mode: common::video::Mode) -> Image {
+	let mut image = Image::new(
+		usize::from(mode.horizontal_pixels()),
+		usize::from(mode.vertical_lines()),
+	);
+	match mode.format() {
+		common::video::Format::Text8x16 => render_text(&mut image, mode, &font::font16::FONT),
+		common::video::Format::Text8x8 => render_text(&mut image, mode, &font::font8::FONT),
+		common::video::Format::Chunky1 => render_chunky::<1>(&mut image, mode),
+		common::video::Format::Chunky2 => render_chunky::<2>(&mut image, mode),
+		common::video::Format::Chunky4 => render_chunky::<4>(&mut image, mode),
+		common::video::Format::Chunky8 => render_chunky::<8>(&mut image, mode),
+		_ => {
+			// Anything else is left black, like in the window
+		}
+	}
+	image
+}
+
+/// The colours each pixel value means in a bitmap mode with `count` colours.
+///
+/// These are the first `count` palette entries, so in the 2 colour mode a
+/// clear bit is entry 0 (the background) and a set bit is entry 1 (the
+/// foreground).
+pub fn chunky_colours(count: usize) -> Vec<RGBColour> {
+	(0..count).map(palette_colour).collect()
+}
+
+/// The colour in palette entry `index`.
+fn palette_colour(index: usize) -> RGBColour {
+	RGBColour::from_packed(PALETTE[index].load(Ordering::Relaxed))
+}
+
+/// Draw the text framebuffer with `font`.
+fn render_text(image: &mut Image, mode: common::video::Mode, font: &font::Font) {
+	let num_cols = usize::from(mode.text_width().unwrap());
+	let num_rows = usize::from(mode.text_height().unwrap());
+	for row in 0..num_rows {
+		for col in 0..num_cols {
+			let byte_offset = ((row * num_cols) + col) * 2;
+			let glyph = usize::from(FRAMEBUFFER.get_at(byte_offset));
+			let attr = common::video::Attr(FRAMEBUFFER.get_at(byte_offset + 1));
+			let fg = palette_colour(usize::from(attr.fg().make_ffi_safe().0));
+			let bg = palette_colour(usize::from(attr.bg().make_ffi_safe().0));
+			for font_y in 0..font.height {
+				let mut font_line = font.data[(glyph * font.height) + font_y];
+				for font_x in 0..8 {
+					let colour = if (font_line & 0x80) != 0 { fg } else { bg };
+					image.set(col * 8 + font_x, row * font.height + font_y, colour);
+					font_line <<= 1;
+				}
+			}
+		}
+	}
+}
+
+/// Draw the chunky framebuffer, with `BPP` bits per pixel.
+fn render_chunky<const BPP: usize>(image: &mut Image, mode: common::video::Mode) {
+	let colours = chunky_colours(1 << BPP);
+	for y in 0..usize::from(mode.vertical_lines()) {
+		for (x, value) in decode_line::<BPP>(mode, y).into_iter().enumerate() {
+			image.set(x, y, colours[usize::from(value)]);
+		}
+	}
+}
+
+/// The palette entry for each pixel on line `y` of the chunky framebuffer,
+/// with `BPP` bits per pixel, from left to right.
+pub fn decode_line<const BPP: usize>(mode: common::video::Mode, y: usize) -> Vec<u8> {
+	let pixels_per_byte = 8 / BPP;
+	let mask = ((1u16 << BPP) - 1) as u8;
+	let num_col_bytes = mode.line_size_bytes();
+	let mut line = Vec::with_capacity(num_col_bytes * pixels_per_byte);
+	for x_byte in 0..num_col_bytes {
+		let data = FRAMEBUFFER.get_at(y * num_col_bytes + x_byte);
+		// The left-most pixel is in the top bits
+		for x in 0..pixels_per_byte {
+			line.push((data >> (8 - BPP * (x + 1))) & mask);
+		}
+	}
+	line
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Image {
+	/// Make a black image.
+	pub fn new(width: usize, height: usize) -> Image {
+		let mut pixels = vec![0; width * height * 4];
+		for alpha in pixels.iter_mut().skip(3).step_by(4) {
+			*alpha = 0xFF;
+		}
+		Image {
+			width,
+			height,
+			pixels,
+		}
+	}
+
+	/// The red, green, blue and alpha bytes of the pixel at `x`, `y`.
+	pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+		let offset = (y * self.width + x) * 4;
+		self.pixels[offset..offset + 4].try_into().unwrap()
+	}
+
+	/// Set the pixel at `x`, `y` to `colour`, fully opaque.
+	pub fn set(&mut self, x: usize, y: usize, colour: RGBColour) {
+		let offset = (y * self.width + x) * 4;
+		self.pixels[offset..offset + 4].copy_from_slice(&[
+			colour.red(),
+			colour.green(),
+			colour.blue(),
+			0xFF,
+		]);
+	}
+
+	/// Save the image as a PNG file.
+	pub fn save_png(&self, path: &Path) -> std::io::Result<()> {
+		let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+		let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.set_compression(png::Compression::Best);
+		let mut writer = encoder.write_header()?;
+		writer.write_image_data(&self.pixels)?;
+		writer.finish()?;
+		Ok(())
+	}
+
+	/// Load an image from a PNG file, which must be 8-bit RGBA (like the ones
+	/// [`Image::save_png`] writes).
+	pub fn load_png(path: &Path) -> std::io::Result<Image> {
+		let decoder = png::Decoder::new(std::io::BufReader::new(std::fs::File::open(path)?));
+		let mut reader = decoder.read_info()?;
+		let mut pixels = vec![0; reader.output_buffer_size()];
+		let info = reader.next_frame(&mut pixels)?;
+		if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!(
+					"{} is {:?} {:?}, not 8-bit RGBA",
+					path.display(),
+					info.bit_depth,
+					info.color_type
+				),
+			));
+		}
+		pixels.truncate(info.buffer_size());
+		Ok(Image {
+			width: info.width as usize,
+			height: info.height as usize,
+			pixels,
+		})
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
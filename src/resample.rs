@@ -0,0 +1,126 @@
+//! # Sample rate conversion for the Neotron Desktop BIOS
+//!
+//! A linear-interpolating resampler, for when the host device doesn't run at
+//! the rate the OS asked for.
+//!
+//! We track where we are between two input frames as an exact fraction, so
+//! over a long time we produce exactly `to_hz / from_hz` output frames for
+//! every input frame, with no drift.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Converts a stream of sample frames from one rate to another.
+pub struct Resampler {
+	from_hz: u32,
+	to_hz: u32,
+	channels: usize,
+	/// How far we are from `prev` towards `next`, in units of `1 / to_hz` of
+	/// an input frame.
+	phase: u32,
+	/// The input frame before the current position
+	prev: [f32; MAX_CHANNELS],
+	/// The input frame after the current position
+	next: [f32; MAX_CHANNELS],
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The most channels we resample. The OS only does mono or stereo.
+pub const MAX_CHANNELS: usize = 2;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Resampler {
+	/// Make a resampler for frames of `channels` samples (at most
+	/// [`MAX_CHANNELS`]).
+	pub fn new(from_hz: u32, to_hz: u32, channels: usize) -> Resampler {
+		Resampler {
+			from_hz,
+			to_hz,
+			channels: channels.min(MAX_CHANNELS),
+			// Ask for an input frame straight away
+			phase: to_hz,
+			prev: [0.0; MAX_CHANNELS],
+			next: [0.0; MAX_CHANNELS],
+		}
+	}
+
+	/// Produce one output frame, calling `pull` whenever we need another input
+	/// frame.
+	///
+	/// If `pull` returns `false` (there's no input), we stay where we are and
+	/// return `false`, and `out` is left alone.
+	pub fn pull_frame<F>(&mut self, out: &mut [f32], mut pull: F) -> bool
+	where
+		F: FnMut(&mut [f32]) -> bool,
+	{
+		while self.phase >= self.to_hz {
+			let mut frame = [0.0; MAX_CHANNELS];
+			if !pull(&mut frame[0..self.channels]) {
+				return false;
+			}
+			self.prev = self.next;
+			self.next = frame;
+			self.phase -= self.to_hz;
+		}
+		self.interpolate(out);
+		self.phase += self.from_hz;
+		true
+	}
+
+	/// Take one input frame, calling `emit` for each output frame (possibly
+	/// none) that is now due.
+	pub fn push_frame<F>(&mut self, frame: &[f32], mut emit: F)
+	where
+		F: FnMut(&[f32]),
+	{
+		self.prev = self.next;
+		for (dest, src) in self.next.iter_mut().zip(frame) {
+			*dest = *src;
+		}
+		self.phase -= self.to_hz;
+		let mut out = [0.0; MAX_CHANNELS];
+		while self.phase < self.to_hz {
+			self.interpolate(&mut out[0..self.channels]);
+			emit(&out[0..self.channels]);
+			self.phase += self.from_hz;
+		}
+	}
+
+	/// Work out the frame at the current position.
+	fn interpolate(&self, out: &mut [f32]) {
+		let t = self.phase as f32 / self.to_hz as f32;
+		for ((dest, prev), next) in out.iter_mut().zip(self.prev).zip(self.next) {
+			*dest = prev + (next - prev) * t;
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,110 @@
+//! # BIOS identity ROM for the Neotron Desktop BIOS
+//!
+//! Hardware BIOSes often keep some information about themselves in flash,
+//! where the OS can read it directly. We do the same, with a small read-only
+//! memory region after the RAM regions. It contains:
+//!
+//! | Offset | Length | Contents                                          |
+//! | ------ | ------ | ------------------------------------------------- |
+//! | 0      | 4      | Magic number `NBID`                               |
+//! | 4      | 2      | Layout version (currently 1)                      |
+//! | 6      | 2      | Length of the whole structure, in bytes           |
+//! | 8      | 4      | Feature bits (see the `FEATURE_` constants)       |
+//! | 12     | 4      | Reserved (zero)                                   |
+//! | 16     | 8      | Build time, in seconds since 1970-01-01T00:00:00Z |
+//! | 24     | 32     | BIOS name, UTF-8, padded with zeroes              |
+//! | 56     | 32     | BIOS version, UTF-8, padded with zeroes           |
+//!
+//! All numbers are little-endian.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The start of the identity structure.
+const MAGIC: [u8; 4] = *b"NBID";
+
+/// Which version of the layout this is.
+const LAYOUT_VERSION: u16 = 1;
+
+/// How long the name and version fields are.
+const STRING_LEN: usize = 32;
+
+/// How long the whole structure is.
+pub const LENGTH: usize = 24 + 2 * STRING_LEN;
+
+/// The BIOS has audio output and input.
+pub const FEATURE_AUDIO: u32 = 1 << 0;
+
+/// The BIOS has NVRAM for the OS configuration.
+pub const FEATURE_NVRAM: u32 = 1 << 1;
+
+/// The BIOS has a disk image attached.
+pub const FEATURE_DISK: u32 = 1 << 2;
+
+/// The BIOS has a second RAM region.
+pub const FEATURE_RAM2: u32 = 1 << 3;
+
+/// The RAM regions have guard pages.
+pub const FEATURE_GUARD_PAGES: u32 = 1 << 4;
+
+/// What we call ourselves.
+const BIOS_NAME: &str = "Neotron Desktop BIOS";
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Build the identity structure, with the given feature bits set.
+pub fn identity(features: u32) -> Vec<u8> {
+	let mut data = Vec::with_capacity(LENGTH);
+	data.extend_from_slice(&MAGIC);
+	data.extend_from_slice(&LAYOUT_VERSION.to_le_bytes());
+	data.extend_from_slice(&(LENGTH as u16).to_le_bytes());
+	data.extend_from_slice(&features.to_le_bytes());
+	data.extend_from_slice(&[0; 4]);
+	data.extend_from_slice(&build_time().to_le_bytes());
+	push_string(&mut data, BIOS_NAME);
+	push_string(&mut data, env!("CARGO_PKG_VERSION"));
+	data
+}
+
+/// When this BIOS was built, in seconds since 1970.
+///
+/// The build script sets this, so it's zero if we were built some other way.
+fn build_time() -> u64 {
+	option_env!("NEOTRON_BUILD_TIME")
+		.and_then(|s| s.parse().ok())
+		.unwrap_or(0)
+}
+
+/// Add a string, cut short or padded with zeroes to fit the field.
+fn push_string(data: &mut Vec<u8>, s: &str) {
+	let mut field = [0u8; STRING_LEN];
+	let len = s.len().min(STRING_LEN);
+	field[..len].copy_from_slice(&s.as_bytes()[..len]);
+	data.extend_from_slice(&field);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,296 @@
+//! # Serial Ports
+//!
+//! The BIOS functions for serial ports, and the ports we emulate.
+//!
+//! There can be up to four ports, `UART0` to `UART3`, which you connect with
+//! `--serial<N>` or `--device serial:<N>=...`. Each one is a [`Port`], which
+//! takes the bytes the OS writes and has the bytes it reads. Any ports before
+//! the last one you connect have nothing plugged in. The bytes go through as
+//! fast as the OS sends them, whatever speed it configures.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Modules
+// -----------------------------------------------------------------------------
+
+mod loopback;
+mod null;
+mod tap;
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use neotron_common_bios as common;
+
+use crate::{heartbeat, lint, validate};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Something plugged into one of our serial ports.
+pub trait Port: Send {
+	/// Take bytes the OS wrote. Returns how many we took.
+	fn write(&mut self, data: &[u8]) -> usize;
+
+	/// Give the OS any bytes that have arrived, without waiting. Returns how
+	/// many there were.
+	fn read(&mut self, buffer: &mut [u8]) -> usize;
+
+	/// Describe what's plugged in, for `--list-devices`.
+	fn describe(&self) -> String;
+}
+
+/// What to plug into a serial port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSpec {
+	/// Nothing
+	Null,
+	/// A plug that sends back what it is sent
+	Loopback,
+	/// SLIP, to and from a host TUN device
+	SlipTap {
+		/// The TUN device's name
+		name: String,
+		/// The biggest packet we pass on, either way
+		mtu: usize,
+	},
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The names of our serial ports. We can't have more ports than this.
+const NAMES: [&str; 4] = ["UART0", "UART1", "UART2", "UART3"];
+
+/// How often we look for more bytes while the OS waits for them.
+const READ_POLL: Duration = Duration::from_millis(1);
+
+/// Our serial ports, by device ID.
+static PORTS: Mutex<Vec<Box<dyn Port>>> = Mutex::new(Vec::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// How many serial ports we can have.
+pub fn max_ports() -> usize {
+	NAMES.len()
+}
+
+/// Check the name of a TUN device, which the host keeps to 15 bytes.
+pub fn check_tap_name(name: &str) -> Result<(), String> {
+	if name.is_empty() || name.len() > 15 {
+		return Err(String::from("TUN device names are 1 to 15 bytes long"));
+	}
+	if name.contains(|c: char| c == '/' || c == ':' || c.is_whitespace()) {
+		return Err(String::from(
+			"TUN device names can't have a '/', ':' or spaces in them",
+		));
+	}
+	Ok(())
+}
+
+/// Plug something into serial port `port`. Any ports before it that don't
+/// exist yet are added with nothing plugged in.
+pub fn add_port(port: u8, spec: &DeviceSpec) -> Result<(), String> {
+	let port = usize::from(port);
+	if port >= NAMES.len() {
+		return Err(format!("there are only {} serial ports", NAMES.len()));
+	}
+	let device: Box<dyn Port> = match spec {
+		DeviceSpec::Null => Box::new(null::Null),
+		DeviceSpec::Loopback => Box::<loopback::Loopback>::default(),
+		DeviceSpec::SlipTap { name, mtu } => Box::new(tap::SlipTap::open(name, *mtu)?),
+	};
+	log::info!("Serial: {} is {}", NAMES[port], device.describe());
+	let mut ports = PORTS.lock().unwrap();
+	while ports.len() <= port {
+		ports.push(Box::new(null::Null));
+	}
+	ports[port] = device;
+	Ok(())
+}
+
+/// List our serial ports, for `--list-devices`.
+pub fn inventory() -> Vec<String> {
+	let ports = PORTS.lock().unwrap();
+	let mut lines = vec![String::from("Serial ports:")];
+	if ports.is_empty() {
+		lines.push(String::from("  (none)"));
+	}
+	for (name, port) in NAMES.iter().zip(ports.iter()) {
+		lines.push(format!("  {}: {}", name, port.describe()));
+	}
+	lines
+}
+
+/// Describe every serial port, for a snapshot.
+pub fn describe_devices() -> Vec<String> {
+	let ports = PORTS.lock().unwrap();
+	NAMES
+		.iter()
+		.zip(ports.iter())
+		.map(|(name, port)| format!("Serial {}: {}", name, port.describe()))
+		.collect()
+}
+
+/// Get information about the Serial ports in the system.
+///
+/// Serial ports are ordered octet-oriented pipes. You can push octets
+/// into them using a 'write' call, and pull bytes out of them using a
+/// 'read' call. They have options which allow them to be configured at
+/// different speeds, or with different transmission settings (parity
+/// bits, stop bits, etc) - you set these with a call to
+/// `SerialConfigure`. They may physically be a MIDI interface, an RS-232
+/// port or a USB-Serial port. There is no sense of 'open' or 'close' -
+/// that is an Operating System level design feature. These APIs just
+/// reflect the raw hardware, in a similar manner to the registers exposed
+/// by a memory-mapped UART peripheral.
+pub extern "C" fn serial_get_info(device: u8) -> common::FfiOption<common::serial::DeviceInfo> {
+	debug!("serial_get_info({})", device);
+	if usize::from(device) >= PORTS.lock().unwrap().len() {
+		return common::FfiOption::None;
+	}
+	common::FfiOption::Some(common::serial::DeviceInfo {
+		name: common::FfiString::new(NAMES[usize::from(device)]),
+		device_type: common::serial::DeviceType::TtlUart.into(),
+	})
+}
+
+/// Set the options for a given serial device. An error is returned if the
+/// options are invalid for that serial device.
+///
+/// Our ports take any speed and framing, as the bytes never go down a real
+/// wire.
+pub extern "C" fn serial_configure(
+	device: u8,
+	config: common::serial::Config,
+) -> common::ApiResult<()> {
+	debug!("serial_configure({}, {:?})", device, config);
+	if usize::from(device) >= PORTS.lock().unwrap().len() {
+		return common::ApiResult::Err(common::Error::InvalidDevice);
+	}
+	let valid = config.data_rate_bps > 0
+		&& config.data_bits.make_safe().is_ok()
+		&& config.stop_bits.make_safe().is_ok()
+		&& config.parity.make_safe().is_ok()
+		&& config.handshaking.make_safe().is_ok();
+	if !valid {
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	}
+	lint::serial_configured(device);
+	common::ApiResult::Ok(())
+}
+
+/// Write bytes to a serial port. There is no sense of 'opening' or
+/// 'closing' the device - serial devices are always open. If the return
+/// value is `Ok(n)`, the value `n` may be less than the size of the given
+/// buffer. If so, that means not all of the data could be transmitted -
+/// only the first `n` bytes were.
+pub extern "C" fn serial_write(
+	device: u8,
+	data: common::FfiByteSlice,
+	_timeout: common::FfiOption<common::Timeout>,
+) -> common::ApiResult<usize> {
+	let data = match validate::bytes("serial_write", "its data", &data) {
+		Ok(data) => data,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	debug!("serial_write({}, {} bytes)", device, data.len());
+	lint::check_serial_configured("serial_write", device);
+	let mut ports = PORTS.lock().unwrap();
+	let Some(port) = ports.get_mut(usize::from(device)) else {
+		return common::ApiResult::Err(common::Error::InvalidDevice);
+	};
+	let written = port.write(data);
+	heartbeat::serial_out(written);
+	common::ApiResult::Ok(written)
+}
+
+/// Read bytes from a serial port. There is no sense of 'opening' or
+/// 'closing' the device - serial devices are always open. If the return value
+///  is `Ok(n)`, the value `n` may be less than the size of the given buffer.
+///  If so, that means not all of the data could be received - only the
+///  first `n` bytes were filled in.
+///
+/// With a timeout, we wait that long (in emulated time) for the first byte
+/// to arrive. Without one, we only give back what has already arrived.
+pub extern "C" fn serial_read(
+	device: u8,
+	mut data: common::FfiBuffer,
+	timeout: common::FfiOption<common::Timeout>,
+) -> common::ApiResult<usize> {
+	let data = match validate::buffer("serial_read", "its buffer", &mut data) {
+		Ok(data) => data,
+		Err(e) => return common::ApiResult::Err(e),
+	};
+	debug!("serial_read({}, {} bytes)", device, data.len());
+	let timeout: Option<common::Timeout> = timeout.into();
+	let deadline = timeout.map(|timeout| {
+		let wait = Duration::from_millis(u64::from(timeout.get_ms()));
+		Instant::now() + crate::clock::host_duration(wait)
+	});
+	loop {
+		let read = match PORTS.lock().unwrap().get_mut(usize::from(device)) {
+			Some(port) => port.read(data),
+			None => return common::ApiResult::Err(common::Error::InvalidDevice),
+		};
+		if read > 0 || data.is_empty() || deadline.is_none_or(|d| Instant::now() >= d) {
+			heartbeat::serial_in(read);
+			return common::ApiResult::Ok(read);
+		}
+		lint::check_blocking("serial_read");
+		std::thread::sleep(READ_POLL);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl std::fmt::Display for DeviceSpec {
+	/// Show the spec as `--serial<N>` would take it.
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DeviceSpec::Null => write!(f, "null"),
+			DeviceSpec::Loopback => write!(f, "loopback"),
+			DeviceSpec::SlipTap { name, mtu } => {
+				write!(f, "slip-tap:{}", name)?;
+				if *mtu != crate::slip::DEFAULT_MTU {
+					write!(f, ",mtu={}", mtu)?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
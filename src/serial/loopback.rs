@@ -0,0 +1,77 @@
+//! # Serial loopback plug
+//!
+//! A plug with TX wired to RX, so the OS reads back what it wrote.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+
+use super::Port;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A loopback plug.
+#[derive(Default)]
+pub struct Loopback {
+	/// Bytes written but not yet read back
+	pending: VecDeque<u8>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many bytes we hold before we stop taking any more, like a UART's
+/// receive buffer filling up.
+const BUFFER_LEN: usize = 4096;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Port for Loopback {
+	fn write(&mut self, data: &[u8]) -> usize {
+		let taken = data.len().min(BUFFER_LEN - self.pending.len());
+		self.pending.extend(&data[..taken]);
+		taken
+	}
+
+	fn read(&mut self, buffer: &mut [u8]) -> usize {
+		let count = buffer.len().min(self.pending.len());
+		for (dest, src) in buffer.iter_mut().zip(self.pending.drain(..count)) {
+			*dest = src;
+		}
+		count
+	}
+
+	fn describe(&self) -> String {
+		"loopback (TX wired to RX)".to_string()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,221 @@
+//! # SLIP over a host TUN device
+//!
+//! The OS speaks SLIP on the serial port. Each packet it sends is unframed
+//! and written to a TUN device on the host, and each packet the host routes
+//! to the TUN device is framed and waits for the OS to read it. So with the
+//! host set up to route for it, the OS is on the network.
+//!
+//! Packets bigger than the MTU are dropped, either way. If the OS doesn't
+//! keep up with what arrives, packets are dropped rather than queued forever,
+//! as they would be by a UART's full receive buffer.
+//!
+//! TUN devices only exist on Linux.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use super::Port;
+use crate::slip;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A serial port carrying SLIP to and from a TUN device.
+pub struct SlipTap {
+	/// The TUN device's name
+	name: String,
+	/// The biggest packet we pass on
+	mtu: usize,
+	/// The TUN device, for packets from the OS
+	device: File,
+	/// Unframes what the OS writes
+	decoder: slip::Decoder,
+	/// Framed packets for the OS to read, which the thread reading the TUN
+	/// device fills
+	incoming: Arc<Mutex<VecDeque<u8>>>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many framed bytes can wait for the OS before we drop packets.
+const INCOMING_LIMIT: usize = 64 * 1024;
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl SlipTap {
+	/// Attach to the TUN device called `name`, or create it if we're allowed
+	/// to.
+	pub fn open(name: &str, mtu: usize) -> Result<SlipTap, String> {
+		let device = sys::open(name).map_err(|e| {
+			format!(
+				"can't open TUN device {}: {} (see the README for how to set one up)",
+				name, e
+			)
+		})?;
+		let reader = device
+			.try_clone()
+			.map_err(|e| format!("can't open TUN device {}: {}", name, e))?;
+		let incoming = Arc::new(Mutex::new(VecDeque::new()));
+		let queue = incoming.clone();
+		let thread_name = format!("tun-{}", name);
+		std::thread::Builder::new()
+			.name(thread_name)
+			.spawn(move || receive(reader, mtu, &queue))
+			.map_err(|e| format!("can't start reading TUN device {}: {}", name, e))?;
+		Ok(SlipTap {
+			name: name.to_string(),
+			mtu,
+			device,
+			decoder: slip::Decoder::new(mtu),
+			incoming,
+		})
+	}
+}
+
+impl Port for SlipTap {
+	fn write(&mut self, data: &[u8]) -> usize {
+		for &byte in data {
+			let Some(packet) = self.decoder.push(byte) else {
+				continue;
+			};
+			// The host turns down anything that isn't an IP packet, which is
+			// what would happen to it on a real link too
+			if let Err(e) = self.device.write(&packet) {
+				log::debug!(
+					"{}: host dropped a {} byte packet: {}",
+					self.name,
+					packet.len(),
+					e
+				);
+			}
+		}
+		data.len()
+	}
+
+	fn read(&mut self, buffer: &mut [u8]) -> usize {
+		let mut incoming = self.incoming.lock().unwrap();
+		let count = buffer.len().min(incoming.len());
+		for (dest, src) in buffer.iter_mut().zip(incoming.drain(..count)) {
+			*dest = src;
+		}
+		count
+	}
+
+	fn describe(&self) -> String {
+		format!(
+			"SLIP over TUN device {} (MTU {}, {} bad packets from the OS dropped)",
+			self.name,
+			self.mtu,
+			self.decoder.dropped()
+		)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Frame every packet that arrives on the TUN device, for the OS to read.
+fn receive(mut reader: File, mtu: usize, incoming: &Mutex<VecDeque<u8>>) {
+	let mut packet = vec![0; slip::MAX_MTU];
+	let mut framed = Vec::new();
+	loop {
+		let len = match reader.read(&mut packet) {
+			Ok(0) => return,
+			Ok(len) => len,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => {
+				log::warn!("Stopped reading from the TUN device: {}", e);
+				return;
+			}
+		};
+		if len > mtu {
+			log::debug!("Dropped a {} byte packet for the OS (MTU {})", len, mtu);
+			continue;
+		}
+		framed.clear();
+		slip::encode(&packet[..len], &mut framed);
+		let mut incoming = incoming.lock().unwrap();
+		if incoming.len() + framed.len() > INCOMING_LIMIT {
+			log::debug!("Dropped a {} byte packet the OS hasn't room for", len);
+			continue;
+		}
+		incoming.extend(&framed);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Host specific code
+// -----------------------------------------------------------------------------
+
+#[cfg(target_os = "linux")]
+mod sys {
+	use std::fs::File;
+	use std::os::fd::AsRawFd;
+
+	/// Attach to a TUN device, which the kernel creates if it doesn't exist
+	/// and we have `CAP_NET_ADMIN`.
+	pub fn open(name: &str) -> std::io::Result<File> {
+		let file = std::fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.open("/dev/net/tun")?;
+		// Safety: an all-zero `ifreq` is valid
+		let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+		for (dest, src) in request.ifr_name.iter_mut().zip(name.bytes()) {
+			*dest = src as libc::c_char;
+		}
+		request.ifr_ifru.ifru_flags = (libc::IFF_TUN | libc::IFF_NO_PI) as libc::c_short;
+		// Safety: TUNSETIFF takes an `ifreq`, which outlives the call
+		if unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF, &mut request) } < 0 {
+			return Err(std::io::Error::last_os_error());
+		}
+		Ok(file)
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+	use std::fs::File;
+
+	pub fn open(_name: &str) -> std::io::Result<File> {
+		Err(std::io::Error::new(
+			std::io::ErrorKind::Unsupported,
+			"TUN devices only work on Linux hosts",
+		))
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,180 @@
+//! # Shutting down cleanly
+//!
+//! We shut down either because the OS turned the power off (on the OS thread)
+//! or because the window was closed (on the GUI thread). Either way, we stop
+//! the OS at its next BIOS call, then write out everything the emulated
+//! hardware is holding on to (the disk image, the Neotron Bus peripherals and
+//! any trace), and only then exit.
+//!
+//! If the OS doesn't call into the BIOS within [`WATCHDOG`] (perhaps it's
+//! stuck in a loop), we give up waiting and shut down anyway.
+//!
+//! The exit code says why we shut down - see [`ExitCode`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Why we shut down, which decides our exit code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitCode {
+	/// The OS turned the power off (exit code 0)
+	PowerOff,
+	/// Something went wrong in the BIOS (exit code 1)
+	BiosError,
+	/// The OS panicked (exit code 2)
+	OsPanic,
+	/// The watchdog decided the OS had hung (exit code 3)
+	Watchdog,
+	/// The window was closed (exit code 0, or 4 with `--fail-on-close`)
+	WindowClosed,
+	/// We got to the `--exit-after` limit with the OS still healthy (exit
+	/// code 0)
+	TimeUp,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How long we wait for the OS to call into the BIOS so we can stop it.
+pub const WATCHDOG: Duration = Duration::from_secs(2);
+
+/// Set once we've started shutting down.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Set once the OS thread has stopped.
+static OS_STOPPED: Mutex<bool> = Mutex::new(false);
+
+/// Wakes up the GUI thread when the OS thread stops.
+static STOPPED: Condvar = Condvar::new();
+
+/// Whether closing the window counts as a failure.
+static FAIL_ON_CLOSE: AtomicBool = AtomicBool::new(false);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Decide whether closing the window counts as a failure.
+pub fn set_fail_on_close(fail: bool) {
+	FAIL_ON_CLOSE.store(fail, Ordering::Relaxed);
+}
+
+/// Have we started shutting down?
+pub fn is_stopping() -> bool {
+	STOPPING.load(Ordering::Relaxed)
+}
+
+/// If we're shutting down, stop the calling thread for good.
+///
+/// Call this from the OS thread only - never from the GUI thread.
+pub fn checkpoint() {
+	if !is_stopping() {
+		return;
+	}
+	*OS_STOPPED.lock().unwrap() = true;
+	STOPPED.notify_all();
+	loop {
+		std::thread::park();
+	}
+}
+
+/// Stop the OS, tidy up and exit. The window was closed, or something else
+/// outside the OS (like the watchdog) wants us to stop.
+///
+/// Never call this from the OS thread.
+pub fn shutdown(code: ExitCode) -> ! {
+	STOPPING.store(true, Ordering::Relaxed);
+	// Make sure the OS isn't waiting for something that will never happen
+	crate::pause::set_paused(false);
+	crate::idle::wake();
+	let stopped = OS_STOPPED.lock().unwrap();
+	let (stopped, _) = STOPPED
+		.wait_timeout_while(stopped, WATCHDOG, |stopped| !*stopped)
+		.unwrap();
+	if !*stopped {
+		log::warn!(
+			"OS didn't call the BIOS within {:?}, so shutting down anyway",
+			WATCHDOG
+		);
+	}
+	drop(stopped);
+	finish(code)
+}
+
+/// Tidy up and exit. The OS turned the power off (or panicked), so it is
+/// already stopped.
+///
+/// Call this from the OS thread only.
+pub fn power_off(code: ExitCode) -> ! {
+	STOPPING.store(true, Ordering::Relaxed);
+	finish(code)
+}
+
+/// Write everything out, then exit.
+fn finish(code: ExitCode) -> ! {
+	if let Ok(hw_guard) = crate::hardware::HARDWARE.try_lock() {
+		if let Some(file) = hw_guard.as_ref().and_then(|hw| hw.disk_file.as_ref()) {
+			if let Err(e) = file.sync_all() {
+				log::warn!("Failed to flush the disk image: {}", e);
+			}
+		}
+	} else {
+		log::warn!("OS is still using the disk image, so not flushing it");
+	}
+	crate::bus::flush();
+	crate::timeline::flush();
+	crate::lint::log_counts();
+	std::process::exit(code.code());
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl ExitCode {
+	/// The process exit code.
+	pub fn code(self) -> i32 {
+		match self {
+			ExitCode::PowerOff => 0,
+			ExitCode::BiosError => 1,
+			ExitCode::OsPanic => 2,
+			ExitCode::Watchdog => 3,
+			ExitCode::WindowClosed if FAIL_ON_CLOSE.load(Ordering::Relaxed) => 4,
+			ExitCode::WindowClosed => 0,
+			ExitCode::TimeUp => 0,
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
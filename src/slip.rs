@@ -0,0 +1,162 @@
+//! # SLIP framing for the Neotron Desktop BIOS
+//!
+//! [RFC 1055](https://www.rfc-editor.org/rfc/rfc1055) SLIP, which sends IP
+//! packets over a serial line. Each packet ends with an `END` byte. An `END`
+//! or `ESC` byte inside a packet is sent as `ESC` followed by `ESC_END` or
+//! `ESC_ESC`, so `END` only ever means the end of a packet.
+//!
+//! Like most SLIP drivers, we send an `END` before each packet too, which
+//! flushes out any line noise the other end has picked up. Empty packets are
+//! ignored, so this costs nothing.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Pulls packets out of a stream of SLIP bytes.
+pub struct Decoder {
+	/// The biggest packet we pass on
+	mtu: usize,
+	/// The packet so far
+	packet: Vec<u8>,
+	/// Was the last byte an `ESC`?
+	escaped: bool,
+	/// Is this packet too big or badly escaped? If so, we throw it away at
+	/// the next `END`.
+	bad: bool,
+	/// How many packets we have thrown away
+	dropped: u64,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The end of a packet.
+pub const END: u8 = 0xC0;
+
+/// The start of an escaped byte.
+pub const ESC: u8 = 0xDB;
+
+/// After an `ESC`, an `END` in the packet.
+pub const ESC_END: u8 = 0xDC;
+
+/// After an `ESC`, an `ESC` in the packet.
+pub const ESC_ESC: u8 = 0xDD;
+
+/// The MTU RFC 1055 suggests, and what we use unless we're told otherwise.
+pub const DEFAULT_MTU: usize = 1006;
+
+/// The smallest MTU an IPv4 link can have.
+pub const MIN_MTU: usize = 68;
+
+/// The biggest MTU we allow, which is the biggest IPv4 packet.
+pub const MAX_MTU: usize = 65535;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Frame a packet, adding it to the end of `out`.
+pub fn encode(packet: &[u8], out: &mut Vec<u8>) {
+	out.reserve(packet.len() + 2);
+	out.push(END);
+	for &byte in packet {
+		match byte {
+			END => out.extend([ESC, ESC_END]),
+			ESC => out.extend([ESC, ESC_ESC]),
+			_ => out.push(byte),
+		}
+	}
+	out.push(END);
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Decoder {
+	/// Make a decoder that throws away packets bigger than `mtu` bytes.
+	pub fn new(mtu: usize) -> Decoder {
+		Decoder {
+			mtu,
+			packet: Vec::new(),
+			escaped: false,
+			bad: false,
+			dropped: 0,
+		}
+	}
+
+	/// Take the next byte off the line. Returns a packet if this byte
+	/// finished one.
+	///
+	/// A packet that is too big, or has an `ESC` followed by anything but
+	/// `ESC_END` or `ESC_ESC`, is thrown away.
+	pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+		if byte == END {
+			let bad = std::mem::take(&mut self.bad) || self.escaped;
+			self.escaped = false;
+			if bad {
+				self.packet.clear();
+				self.dropped += 1;
+				return None;
+			}
+			if self.packet.is_empty() {
+				return None;
+			}
+			return Some(std::mem::take(&mut self.packet));
+		}
+		if self.bad {
+			return None;
+		}
+		let byte = if std::mem::take(&mut self.escaped) {
+			match byte {
+				ESC_END => END,
+				ESC_ESC => ESC,
+				_ => {
+					self.bad = true;
+					return None;
+				}
+			}
+		} else if byte == ESC {
+			self.escaped = true;
+			return None;
+		} else {
+			byte
+		};
+		if self.packet.len() == self.mtu {
+			self.bad = true;
+			return None;
+		}
+		self.packet.push(byte);
+		None
+	}
+
+	/// How many packets we have thrown away.
+	pub fn dropped(&self) -> u64 {
+		self.dropped
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
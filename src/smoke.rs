@@ -0,0 +1,144 @@
+//! # Smoke tests
+//!
+//! For CI, `--exit-after 5s` (or `--exit-after 300frames`) runs the OS for a
+//! while, then shuts down the same way as closing the window. The exit code
+//! says whether the OS was still healthy when time was up: 0 if it was, 2 if
+//! it had panicked, or 3 if the watchdog ever decided it had hung (give
+//! `--watchdog` too, or it never will).
+//!
+//! With `--headless` we draw into an invisible window with SDL's software
+//! renderer, so there's no need for a display server.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::shutdown::ExitCode;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// How long to run for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+	/// This much host time, from when the window opens
+	Time(Duration),
+	/// This many frames
+	Frames(u64),
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// When to stop, if we were asked to.
+static LIMIT: OnceLock<Limit> = OnceLock::new();
+
+/// Frames drawn so far.
+static FRAMES: AtomicU64 = AtomicU64::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse an `--exit-after` limit, like `5s`, `500ms`, `2m` or `300frames`.
+pub fn parse_limit(text: &str) -> Result<Limit, String> {
+	let text = text.trim();
+	let bad = || format!("{:?} is not a limit (try 5s or 300frames)", text);
+	if let Some(frames) = text.strip_suffix("frames") {
+		return match frames.trim().parse() {
+			Ok(0) | Err(_) => Err(bad()),
+			Ok(frames) => Ok(Limit::Frames(frames)),
+		};
+	}
+	crate::watchdog::parse_timeout(text)
+		.map(Limit::Time)
+		.map_err(|_| bad())
+}
+
+/// Stop once we reach `limit`.
+pub fn set_limit(limit: Limit) {
+	let _ = LIMIT.set(limit);
+}
+
+/// Draw into an invisible window, so we don't need a display server.
+///
+/// Call this before the window is made, and before starting any threads.
+pub fn go_headless() {
+	if std::env::var_os("SDL_VIDEODRIVER").is_none() {
+		std::env::set_var("SDL_VIDEODRIVER", "dummy");
+	}
+	// There's no GPU to accelerate anything, so ask for the software
+	// renderer by name
+	if std::env::var_os("SDL_RENDER_DRIVER").is_none() {
+		std::env::set_var("SDL_RENDER_DRIVER", "software");
+	}
+}
+
+/// The window is open, so start the clock on any time limit.
+pub fn start() {
+	if let Some(Limit::Time(limit)) = LIMIT.get().copied() {
+		std::thread::spawn(move || {
+			std::thread::sleep(limit);
+			time_up();
+		});
+	}
+}
+
+/// Count a frame, and stop if that's enough. Call this on the GUI thread.
+pub fn frame() {
+	let frames = FRAMES.fetch_add(1, Ordering::Relaxed) + 1;
+	if LIMIT.get() == Some(&Limit::Frames(frames)) {
+		time_up();
+	}
+}
+
+/// We've run for long enough. Say how the OS is, then shut down.
+fn time_up() -> ! {
+	let code = verdict();
+	match code {
+		ExitCode::TimeUp => log::info!("Time's up (--exit-after), and the OS is healthy"),
+		ExitCode::OsPanic => log::error!("Time's up (--exit-after), but the OS has panicked"),
+		_ => log::error!("Time's up (--exit-after), but the watchdog decided the OS had hung"),
+	}
+	crate::shutdown::shutdown(code)
+}
+
+/// Was the OS healthy?
+fn verdict() -> ExitCode {
+	if crate::crash::is_crashed() {
+		ExitCode::OsPanic
+	} else if crate::watchdog::has_fired() {
+		ExitCode::Watchdog
+	} else {
+		ExitCode::TimeUp
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,195 @@
+//! # Machine snapshots
+//!
+//! A snapshot holds the state of the emulated machine: the contents of every
+//! RAM region, the video RAM, palette and mode, where the clocks had got to,
+//! the NVRAM, and a description of the devices that were attached.
+//!
+//! We can't save the OS thread itself (its stack and registers belong to the
+//! host), so resuming a snapshot is a reset with the memory put back the way it
+//! was. An OS that keeps its state in RAM and checks for it at start-up can
+//! carry on from where it was; anything else just gets a head start.
+//!
+//! The file starts with a magic number and a format version, followed by
+//! tagged sections. We refuse to load a snapshot with a different version.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The state of the machine.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+	/// The contents of each RAM region, in region order
+	pub regions: Vec<Vec<u8>>,
+	/// The video RAM
+	pub vram: Vec<u8>,
+	/// The palette, as packed RGB values
+	pub palette: Vec<u32>,
+	/// The video mode
+	pub video_mode: u8,
+	/// How much emulated time had passed, in nanoseconds
+	pub elapsed_ns: u64,
+	/// How far the OS had moved its wall clock, in nanoseconds
+	pub clock_offset_ns: i128,
+	/// The NVRAM contents
+	pub nvram: Option<Vec<u8>>,
+	/// What devices were attached
+	pub devices: Vec<String>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What every snapshot file starts with.
+const MAGIC: &[u8; 8] = b"NEOSNAP\0";
+
+/// The version of the snapshot format. Change this whenever the format
+/// changes.
+const VERSION: u32 = 1;
+
+/// Section tags
+const TAG_REGION: &[u8; 4] = b"REGN";
+const TAG_VRAM: &[u8; 4] = b"VRAM";
+const TAG_PALETTE: &[u8; 4] = b"PALT";
+const TAG_VIDEO_MODE: &[u8; 4] = b"MODE";
+const TAG_TIME: &[u8; 4] = b"TIME";
+const TAG_NVRAM: &[u8; 4] = b"NVRM";
+const TAG_DEVICES: &[u8; 4] = b"DEVS";
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Write one section.
+fn write_section(out: &mut impl Write, tag: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+	out.write_all(tag)?;
+	out.write_all(&(data.len() as u64).to_le_bytes())?;
+	out.write_all(data)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Snapshot {
+	/// Save the snapshot to a file.
+	pub fn save(&self, path: &Path) -> std::io::Result<()> {
+		let mut out = Vec::new();
+		out.extend_from_slice(MAGIC);
+		out.extend_from_slice(&VERSION.to_le_bytes());
+		for region in &self.regions {
+			write_section(&mut out, TAG_REGION, region)?;
+		}
+		write_section(&mut out, TAG_VRAM, &self.vram)?;
+		let palette: Vec<u8> = self
+			.palette
+			.iter()
+			.flat_map(|rgb| rgb.to_le_bytes())
+			.collect();
+		write_section(&mut out, TAG_PALETTE, &palette)?;
+		write_section(&mut out, TAG_VIDEO_MODE, &[self.video_mode])?;
+		let mut time = self.elapsed_ns.to_le_bytes().to_vec();
+		time.extend_from_slice(&self.clock_offset_ns.to_le_bytes());
+		write_section(&mut out, TAG_TIME, &time)?;
+		if let Some(nvram) = &self.nvram {
+			write_section(&mut out, TAG_NVRAM, nvram)?;
+		}
+		write_section(&mut out, TAG_DEVICES, self.devices.join("\n").as_bytes())?;
+		crate::nvram::replace_file(path, &out)
+	}
+
+	/// Load a snapshot from a file.
+	pub fn load(path: &Path) -> Result<Snapshot, String> {
+		let data = std::fs::read(path).map_err(|e| e.to_string())?;
+		let rest = data
+			.strip_prefix(MAGIC)
+			.ok_or("not a Neotron Desktop BIOS snapshot")?;
+		let (version, mut rest) = rest
+			.split_first_chunk::<4>()
+			.ok_or("snapshot is truncated")?;
+		let version = u32::from_le_bytes(*version);
+		if version != VERSION {
+			return Err(format!(
+				"snapshot is format version {}, but we only understand version {}",
+				version, VERSION
+			));
+		}
+		let mut snapshot = Snapshot::default();
+		while !rest.is_empty() {
+			let (tag, after_tag) = rest
+				.split_first_chunk::<4>()
+				.ok_or("snapshot is truncated")?;
+			let (len, after_len) = after_tag
+				.split_first_chunk::<8>()
+				.ok_or("snapshot is truncated")?;
+			let len =
+				usize::try_from(u64::from_le_bytes(*len)).map_err(|_| "section is too big")?;
+			if after_len.len() < len {
+				return Err("snapshot is truncated".into());
+			}
+			let (section, after_section) = after_len.split_at(len);
+			rest = after_section;
+			match tag {
+				TAG_REGION => snapshot.regions.push(section.to_vec()),
+				TAG_VRAM => snapshot.vram = section.to_vec(),
+				TAG_PALETTE => {
+					snapshot.palette = section
+						.chunks_exact(4)
+						.map(|rgb| u32::from_le_bytes([rgb[0], rgb[1], rgb[2], rgb[3]]))
+						.collect()
+				}
+				TAG_VIDEO_MODE => snapshot.video_mode = *section.first().ok_or("bad video mode")?,
+				TAG_TIME => {
+					let (elapsed, offset) =
+						section.split_first_chunk::<8>().ok_or("bad time section")?;
+					let offset: [u8; 16] = offset.try_into().map_err(|_| "bad time section")?;
+					snapshot.elapsed_ns = u64::from_le_bytes(*elapsed);
+					snapshot.clock_offset_ns = i128::from_le_bytes(offset);
+				}
+				TAG_NVRAM => snapshot.nvram = Some(section.to_vec()),
+				TAG_DEVICES => {
+					snapshot.devices = String::from_utf8_lossy(section)
+						.lines()
+						.map(str::to_string)
+						.collect()
+				}
+				_ => log::warn!(
+					"Snapshot: skipping unknown section {:?}",
+					String::from_utf8_lossy(tag)
+				),
+			}
+		}
+		Ok(snapshot)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
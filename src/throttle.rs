@@ -0,0 +1,205 @@
+//! # CPU throttling for the OS
+//!
+//! The OS runs at the full speed of the host, which hides performance problems
+//! that would be obvious on a microcontroller. With `--cpu-throttle=5%`, the OS
+//! only gets that share of the time: every BIOS function calls [`pace`] on the
+//! way in, and if the OS has used more than its share since last time, we put
+//! it to sleep until it's back within budget.
+//!
+//! We measure the CPU time the OS thread has used, so time it spends waiting
+//! inside the BIOS (in `power_idle`, say) isn't charged to it. On hosts where
+//! we can't get that, we fall back to the time between BIOS calls.
+//!
+//! This isn't cycle-accurate. The BIOS itself runs at full speed, and device
+//! timing stays in real time. An OS that runs for a long time without calling
+//! the BIOS isn't slowed down until it does, at which point it pays for all of
+//! it at once. But compute-heavy work takes proportionally longer, which is
+//! enough to show up algorithmic problems.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// How much time the OS has used, and how much sleep it owes.
+struct Budget {
+	/// How much CPU time the OS thread had used at its last BIOS call
+	last_used: Option<Duration>,
+	/// How long the OS should be asleep for, to stay within its share
+	owed: Duration,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The share of the host's time the OS gets, if it is throttled.
+static SHARE: OnceLock<f64> = OnceLock::new();
+
+/// What the OS has used so far.
+static BUDGET: Mutex<Budget> = Mutex::new(Budget {
+	last_used: None,
+	owed: Duration::ZERO,
+});
+
+/// Set while we're holding the OS thread back.
+static SLEEPING: AtomicBool = AtomicBool::new(false);
+
+/// We don't bother sleeping for less than this, as the host's sleep isn't that
+/// accurate anyway. The debt carries over to the next call.
+const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Only give the OS this share of the host's time (between 0 and 1).
+pub fn set_share(share: f64) {
+	SHARE.set(share).expect("throttle to only be set once");
+	if sys::thread_cpu_time().is_none() {
+		log::warn!("Can't measure CPU time on this host - throttling on time between BIOS calls");
+	}
+}
+
+/// The OS has called the BIOS. Sleep if it has used more than its share.
+///
+/// Call this from the OS thread only - never from the GUI thread.
+pub fn pace() {
+	let Some(share) = SHARE.get() else {
+		return;
+	};
+	let mut budget = BUDGET.lock().unwrap();
+	if let Some(last_used) = budget.last_used {
+		let ran = used().saturating_sub(last_used);
+		budget.owed += ran.mul_f64((1.0 - share) / share);
+	}
+	if budget.owed >= MIN_SLEEP {
+		SLEEPING.store(true, Ordering::Relaxed);
+		std::thread::sleep(budget.owed);
+		SLEEPING.store(false, Ordering::Relaxed);
+		budget.owed = Duration::ZERO;
+	}
+	// Measure again, so our own sleeping isn't charged to the OS
+	budget.last_used = Some(used());
+}
+
+/// Are we holding the OS thread back right now?
+pub fn is_sleeping() -> bool {
+	SLEEPING.load(Ordering::Relaxed)
+}
+
+/// Parse a `--cpu-throttle` share, like `5%` or `0.05`.
+pub fn parse_share(text: &str) -> Result<f64, String> {
+	let text = text.trim();
+	let share = match text.strip_suffix('%') {
+		Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+		None => text.parse::<f64>(),
+	};
+	match share {
+		Ok(share) if share > 0.0 && share <= 1.0 => Ok(share),
+		_ => Err(format!(
+			"{:?} isn't a valid share of the CPU (try 5% or 0.05)",
+			text
+		)),
+	}
+}
+
+/// How much time the OS thread has used.
+///
+/// Ideally this is the CPU time the thread has used, but if we can't get that
+/// it's the time since we started.
+fn used() -> Duration {
+	static EPOCH: OnceLock<Instant> = OnceLock::new();
+	sys::thread_cpu_time().unwrap_or_else(|| EPOCH.get_or_init(Instant::now).elapsed())
+}
+
+// -----------------------------------------------------------------------------
+// Host specific code
+// -----------------------------------------------------------------------------
+
+#[cfg(unix)]
+mod sys {
+	use std::time::Duration;
+
+	/// How much CPU time the calling thread has used.
+	pub fn thread_cpu_time() -> Option<Duration> {
+		let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+		if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+			return None;
+		}
+		Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+	}
+}
+
+#[cfg(windows)]
+mod sys {
+	use std::ffi::c_void;
+	use std::time::Duration;
+
+	#[repr(C)]
+	#[derive(Default)]
+	struct FileTime {
+		low: u32,
+		high: u32,
+	}
+
+	extern "system" {
+		fn GetCurrentThread() -> *mut c_void;
+		fn GetThreadTimes(
+			thread: *mut c_void,
+			creation: *mut FileTime,
+			exit: *mut FileTime,
+			kernel: *mut FileTime,
+			user: *mut FileTime,
+		) -> i32;
+	}
+
+	/// How much CPU time the calling thread has used.
+	pub fn thread_cpu_time() -> Option<Duration> {
+		let mut times: [FileTime; 4] = Default::default();
+		let [creation, exit, kernel, user] = &mut times;
+		if unsafe { GetThreadTimes(GetCurrentThread(), creation, exit, kernel, user) } == 0 {
+			return None;
+		}
+		// These are in units of 100 ns
+		let ticks = |time: &FileTime| (u64::from(time.high) << 32) | u64::from(time.low);
+		Some(Duration::from_nanos((ticks(kernel) + ticks(user)) * 100))
+	}
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+	pub fn thread_cpu_time() -> Option<std::time::Duration> {
+		None
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,158 @@
+//! # Time
+//!
+//! The BIOS functions for the wall clock and the tick counter. Both run off
+//! the emulated clock (see [`crate::clock`]), so they speed up and slow down
+//! with `--time-scale`.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use log::{debug, warn};
+
+use neotron_common_bios as common;
+
+use crate::hardware::HARDWARE;
+use crate::{clock, profile};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Set once we've warned that the wall clock is out of range.
+static CLOCK_CLAMPED: AtomicBool = AtomicBool::new(false);
+
+/// How fast `time_ticks_get` counts.
+static TICKS_PER_SECOND: AtomicU64 = AtomicU64::new(1000);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Make `time_ticks_get` count in microseconds, rather than milliseconds.
+pub fn set_fine_ticks(fine: bool) {
+	let rate = if fine { 1_000_000 } else { 1000 };
+	TICKS_PER_SECOND.store(rate, Ordering::Relaxed);
+}
+
+/// How fast `time_ticks_get` counts.
+pub fn ticks_per_second() -> u64 {
+	TICKS_PER_SECOND.load(Ordering::Relaxed)
+}
+
+/// Get the current wall time.
+///
+/// The Neotron BIOS does not understand time zones, leap-seconds or the
+/// Gregorian calendar. It simply stores time as an incrementing number of
+/// seconds since some epoch, and the number of milliseconds since that second
+/// began. A day is assumed to be exactly 86,400 seconds long. This is a lot
+/// like POSIX time, except we have a different epoch - the Neotron epoch is
+/// 2000-01-01T00:00:00Z. It is highly recommend that you store UTC in the BIOS
+/// and use the OS to handle time-zones.
+///
+/// If the BIOS does not have a battery-backed clock, or if that battery has
+/// failed to keep time, the system starts up assuming it is the epoch.
+pub extern "C" fn time_clock_get() -> common::Time {
+	debug!("time_clock_get()");
+	let hw_guard = HARDWARE.lock().unwrap();
+	let hw = hw_guard.as_ref().unwrap();
+	nanos_to_time(hw.wall_nanos() + hw.clock_offset_ns)
+}
+
+/// Convert nanoseconds since the Neotron epoch into a `common::Time`.
+///
+/// A `u32` of seconds runs out in 2136. Times after that are clamped to the
+/// last representable moment, and times before 2000 (e.g. if the host clock
+/// is wrong) are clamped to the epoch. We warn the first time this happens.
+pub fn nanos_to_time(nanos: i128) -> common::Time {
+	const MAX_NANOS: i128 = (u32::MAX as i128 + 1) * 1_000_000_000 - 1;
+	let clamped = nanos.clamp(0, MAX_NANOS);
+	if clamped != nanos && !CLOCK_CLAMPED.swap(true, Ordering::Relaxed) {
+		warn!(
+			"Wall clock is {} the range the BIOS API can represent - clamping",
+			if nanos < 0 { "before" } else { "after" }
+		);
+	}
+	common::Time {
+		secs: (clamped / 1_000_000_000) as u32,
+		nsecs: (clamped % 1_000_000_000) as u32,
+	}
+}
+
+/// How many nanoseconds the host thinks it has been since the Neotron epoch.
+///
+/// Negative if the host clock is set to before 2000.
+pub fn host_nanos_since_neotron_epoch() -> i128 {
+	// 946684800 seconds between 2000-01-01 and 1970-01-01
+	let epoch = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(946684800);
+	match std::time::SystemTime::now().duration_since(epoch) {
+		Ok(difference) => difference.as_nanos() as i128,
+		Err(e) => -(e.duration().as_nanos() as i128),
+	}
+}
+
+/// Set the current wall time.
+///
+/// See `time_get` for a description of now the Neotron BIOS should handle
+/// time.
+///
+/// You only need to call this whenever you get a new sense of the current
+/// time (e.g. the user has updated the current time, or if you get a GPS
+/// fix). The BIOS should push the time out to the battery-backed Real
+/// Time Clock, if it has one.
+///
+/// We don't change the host's clock - we remember how far the OS's time is
+/// from our emulated time, and apply that in `time_clock_get`.
+pub extern "C" fn time_clock_set(time: common::Time) {
+	debug!("time_clock_set({:?})", time);
+	let requested = i128::from(time.secs) * 1_000_000_000 + i128::from(time.nsecs);
+	let offset_ns = {
+		let mut hw_guard = HARDWARE.lock().unwrap();
+		let hw = hw_guard.as_mut().unwrap();
+		hw.clock_offset_ns = requested - hw.wall_nanos();
+		hw.clock_offset_ns
+	};
+	profile::save_rtc(offset_ns);
+}
+
+pub extern "C" fn time_ticks_get() -> common::Ticks {
+	let difference = clock::elapsed();
+	let ticks = difference.as_nanos() * u128::from(TICKS_PER_SECOND.load(Ordering::Relaxed))
+		/ 1_000_000_000;
+	// A u64 of microseconds lasts half a million years, but let's not wrap
+	let ticks = u64::try_from(ticks).unwrap_or(u64::MAX);
+	debug!("time_ticks_get() -> {}", ticks);
+	common::Ticks(ticks)
+}
+
+/// We simulate a 1 kHz tick, or a 1 MHz tick with `--fine-ticks`
+pub extern "C" fn time_ticks_per_second() -> common::Ticks {
+	let ticks_per_second = TICKS_PER_SECOND.load(Ordering::Relaxed);
+	debug!("time_ticks_per_second() -> {}", ticks_per_second);
+	common::Ticks(ticks_per_second)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,299 @@
+//! # Event timeline
+//!
+//! With `--trace-timeline`, we note when interesting things start and how
+//! long they take - every BIOS call, every frame we draw, every disk
+//! operation and every audio callback - and write them out when we exit, in
+//! the Chrome tracing JSON format. Open the file in Perfetto
+//! (<https://ui.perfetto.dev>) or `chrome://tracing` to see the whole session
+//! on a timeline, one row per thread, and spot things like a long
+//! `block_write` holding up a frame.
+//!
+//! We keep the events in memory until we exit, so to stop a long session
+//! using all the RAM we only keep the latest [`MAX_EVENTS`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Something that's happening, which goes on the timeline when it's dropped.
+pub struct Span {
+	name: &'static str,
+	category: &'static str,
+	start: Instant,
+	args: Option<String>,
+}
+
+/// One thing that happened.
+struct Event {
+	name: &'static str,
+	category: &'static str,
+	/// Which thread it happened on (see [`thread_id`])
+	thread: u32,
+	/// When it started, in nanoseconds since we started recording
+	start_ns: u64,
+	/// How long it took, in nanoseconds, or `None` if it was a moment
+	duration_ns: Option<u64>,
+	/// Extra details, as the inside of a JSON object
+	args: Option<String>,
+}
+
+/// Everything we've recorded.
+struct Timeline {
+	/// Where to write it when we exit
+	path: PathBuf,
+	/// When we started recording
+	start: Instant,
+	/// The latest events, oldest first
+	events: VecDeque<Event>,
+	/// How many older events we've thrown away
+	dropped: u64,
+	/// The name of each thread we've seen, by its ID
+	threads: Vec<String>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The most events we keep. Each takes about 100 bytes, so this is around
+/// 50 MB.
+pub const MAX_EVENTS: usize = 500_000;
+
+/// Whether we're recording. This is checked before anything else, so leaving
+/// the timeline off costs next to nothing.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TIMELINE: Mutex<Option<Timeline>> = Mutex::new(None);
+
+thread_local! {
+	/// This thread's ID on the timeline, or 0 if it hasn't been given one.
+	static THREAD_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Start recording, to write out to `path` when we exit.
+pub fn start(path: PathBuf) {
+	log::info!("Recording a timeline, for {}", path.display());
+	*TIMELINE.lock().unwrap() = Some(Timeline {
+		path,
+		start: Instant::now(),
+		events: VecDeque::new(),
+		dropped: 0,
+		threads: Vec::new(),
+	});
+	ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Are we recording?
+pub fn is_enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Start something that takes a while, if we're recording. It goes on the
+/// timeline when the [`Span`] is dropped.
+pub fn span(name: &'static str, category: &'static str) -> Option<Span> {
+	is_enabled().then(|| Span {
+		name,
+		category,
+		start: Instant::now(),
+		args: None,
+	})
+}
+
+/// Note something that happened in a moment, like a call that never
+/// returns.
+pub fn instant(name: &'static str, category: &'static str) {
+	if is_enabled() {
+		record(name, category, Instant::now(), None, None);
+	}
+}
+
+/// Write out the timeline, if we're recording. Call this as we exit.
+pub fn flush() {
+	if !is_enabled() {
+		return;
+	}
+	ENABLED.store(false, Ordering::Relaxed);
+	let Ok(mut timeline) = TIMELINE.try_lock() else {
+		log::warn!("The timeline was busy, so not writing it out");
+		return;
+	};
+	let Some(timeline) = timeline.take() else {
+		return;
+	};
+	if timeline.dropped != 0 {
+		log::warn!(
+			"The timeline only has the latest {} events - {} earlier ones were dropped",
+			MAX_EVENTS,
+			timeline.dropped
+		);
+	}
+	match timeline.save() {
+		Ok(()) => log::info!("Timeline saved to {}", timeline.path.display()),
+		Err(e) => log::warn!(
+			"Failed to save the timeline to {}: {}",
+			timeline.path.display(),
+			e
+		),
+	}
+}
+
+/// Put an event on the timeline.
+fn record(
+	name: &'static str,
+	category: &'static str,
+	start: Instant,
+	end: Option<Instant>,
+	args: Option<String>,
+) {
+	let mut timeline = TIMELINE.lock().unwrap();
+	let Some(timeline) = timeline.as_mut() else {
+		return;
+	};
+	let thread = thread_id(timeline);
+	let start_ns = start.saturating_duration_since(timeline.start).as_nanos() as u64;
+	let duration_ns = end.map(|end| end.saturating_duration_since(start).as_nanos() as u64);
+	if timeline.events.len() == MAX_EVENTS {
+		timeline.events.pop_front();
+		timeline.dropped += 1;
+	}
+	timeline.events.push_back(Event {
+		name,
+		category,
+		thread,
+		start_ns,
+		duration_ns,
+		args,
+	});
+}
+
+/// This thread's ID on the timeline, giving it one (and noting its name) if
+/// it hasn't got one yet.
+fn thread_id(timeline: &mut Timeline) -> u32 {
+	THREAD_ID.with(|id| {
+		if id.get() == 0 {
+			let thread = std::thread::current();
+			timeline
+				.threads
+				.push(thread.name().unwrap_or("<unnamed>").to_string());
+			id.set(timeline.threads.len() as u32);
+		}
+		id.get()
+	})
+}
+
+/// Format nanoseconds as the microseconds the format wants.
+fn micros(ns: u64) -> String {
+	format!("{}.{:03}", ns / 1000, ns % 1000)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Span {
+	/// Add some details, as `"name": value` pairs (e.g. `"block": 3`), which
+	/// the viewer shows when you click on the span.
+	pub fn set_args(&mut self, args: String) {
+		self.args = Some(args);
+	}
+}
+
+impl Drop for Span {
+	fn drop(&mut self) {
+		if is_enabled() {
+			record(
+				self.name,
+				self.category,
+				self.start,
+				Some(Instant::now()),
+				self.args.take(),
+			);
+		}
+	}
+}
+
+impl Timeline {
+	/// Write the timeline out, in the Chrome tracing JSON format.
+	fn save(&self) -> std::io::Result<()> {
+		let file = std::fs::File::create(&self.path)?;
+		let mut out = std::io::BufWriter::new(file);
+		let pid = std::process::id();
+		writeln!(out, "{{\"displayTimeUnit\": \"ms\", \"traceEvents\": [")?;
+		writeln!(
+			out,
+			"{{\"name\": \"process_name\", \"ph\": \"M\", \"pid\": {}, \"args\": {{\"name\": \"Neotron Desktop BIOS\"}}}}",
+			pid
+		)?;
+		for (idx, name) in self.threads.iter().enumerate() {
+			writeln!(
+				out,
+				",{{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": {}, \"tid\": {}, \"args\": {{\"name\": {}}}}}",
+				pid,
+				idx + 1,
+				crate::json::quote(name)
+			)?;
+		}
+		for event in &self.events {
+			write!(
+				out,
+				",{{\"name\": \"{}\", \"cat\": \"{}\", \"pid\": {}, \"tid\": {}, \"ts\": {}",
+				event.name,
+				event.category,
+				pid,
+				event.thread,
+				micros(event.start_ns)
+			)?;
+			match event.duration_ns {
+				Some(duration_ns) => {
+					write!(out, ", \"ph\": \"X\", \"dur\": {}", micros(duration_ns))?
+				}
+				None => write!(out, ", \"ph\": \"i\", \"s\": \"t\"")?,
+			}
+			if let Some(args) = &event.args {
+				write!(out, ", \"args\": {{{}}}", args)?;
+			}
+			writeln!(out, "}}")?;
+		}
+		writeln!(out, "]}}")?;
+		out.flush()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
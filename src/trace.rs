@@ -0,0 +1,123 @@
+//! # Transaction tracing for the Neotron Desktop BIOS
+//!
+//! A trace is a log of what the OS did with some emulated hardware, one line
+//! per transaction, each stamped with the emulated time. It goes to standard
+//! error, or to a file of its own. We also keep the last few lines from every
+//! trace, for crash reports.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Somewhere to write trace lines.
+pub struct Trace {
+	/// Where the lines go
+	out: Box<dyn Write + Send>,
+	/// Show at most this many bytes of each payload
+	max_bytes: usize,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many of the latest trace lines we keep.
+const RECENT_LINES: usize = 100;
+
+/// The latest lines from every trace, oldest first.
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The latest lines from every trace, oldest first.
+///
+/// This doesn't wait if someone else is writing a line, as it's for crash
+/// reports, and they might have crashed while doing it.
+pub fn recent_lines() -> Option<Vec<String>> {
+	let recent = RECENT.try_lock().ok()?;
+	Some(recent.iter().cloned().collect())
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Trace {
+	/// Start a trace, writing to this file (or standard error, if there's no
+	/// file).
+	pub fn new(path: Option<&Path>, max_bytes: usize) -> Result<Trace, std::io::Error> {
+		let out: Box<dyn Write + Send> = match path {
+			Some(path) => Box::new(std::io::LineWriter::new(std::fs::File::create(path)?)),
+			None => Box::new(std::io::stderr()),
+		};
+		Ok(Trace { out, max_bytes })
+	}
+
+	/// Write a line, with the emulated time on the front.
+	pub fn line(&mut self, args: std::fmt::Arguments) {
+		let now = crate::clock::elapsed();
+		let line = format!("[{:6}.{:06}] {}", now.as_secs(), now.subsec_micros(), args);
+		// If we can't write the trace there's nobody to tell
+		let _ = writeln!(self.out, "{}", line);
+		let mut recent = RECENT.lock().unwrap();
+		if recent.len() == RECENT_LINES {
+			recent.pop_front();
+		}
+		recent.push_back(line);
+	}
+
+	/// Make sure everything we've written has gone out.
+	pub fn flush(&mut self) {
+		let _ = self.out.flush();
+	}
+
+	/// Format some bytes as hex, cut short if there are too many.
+	pub fn hex(&self, data: &[u8]) -> String {
+		let mut text = String::from("[");
+		for (index, byte) in data.iter().take(self.max_bytes).enumerate() {
+			if index != 0 {
+				text.push(' ');
+			}
+			text.push_str(&format!("{:02x}", byte));
+		}
+		if data.len() > self.max_bytes {
+			text.push_str(&format!(" ...+{}", data.len() - self.max_bytes));
+		}
+		text.push(']');
+		text
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,459 @@
+//! # Transfer device
+//!
+//! Block Device 1 can be a transfer device, attached with
+//! `--device disk:1=transfer:<dir>`. It lets a utility on the OS pull single
+//! files from a directory on the host, and push files back into it, without
+//! building a new disk image every time.
+//!
+//! Block 0 is the control block. The OS writes a request to it, then reads
+//! it back to get the status. The file itself goes through blocks 1 onwards.
+//! Both are little-endian:
+//!
+//! ```text
+//! Offset  Request                         Status
+//! 0       "XFER"                          "XFER"
+//! 4       command: 1 get, 2 put, 3 close  the last command (0 if none)
+//! 5       (zero)                          status: see [`Status`]
+//! 8       put: the file's length (u32)    the file's length (u32)
+//! 16      the path, ending in a zero byte a message, if it went wrong
+//! ```
+//!
+//! To get a file, write a get request and read the status: if it's OK, the
+//! file's bytes are in blocks 1 onwards (with zeros after the end). To put
+//! one, write a put request with the length, write the bytes to blocks 1
+//! onwards, then write a close request - the file appears on the host when
+//! the close succeeds.
+//!
+//! Paths are relative to the directory, with `/` between the parts. We turn
+//! down absolute paths, `..`, and anything that leads outside the directory
+//! through a symbolic link, and files bigger than the limit (16 MiB unless
+//! you give a `limit`). With `ro`, the OS can only get files.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use log::info;
+
+use neotron_common_bios as common;
+
+use crate::block::BLOCK_SIZE;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A transfer device, as `--device` gives it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+	/// The directory on the host the OS can get files from and put them in
+	pub dir: PathBuf,
+	/// Whether the OS can only get files
+	pub read_only: bool,
+	/// The biggest file, in bytes, either way
+	pub limit: usize,
+}
+
+/// How a request went, as it appears at offset 5 of the control block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+	Ok = 0,
+	/// There's no such file
+	NotFound = 1,
+	/// The path is absolute, has a `..` in it, or leads outside the
+	/// directory
+	BadPath = 2,
+	/// The file is bigger than the limit
+	TooBig = 3,
+	/// The device is read-only, so the OS can't put files
+	ReadOnly = 4,
+	/// Something went wrong on the host
+	IoError = 5,
+	/// The request didn't make sense
+	BadRequest = 6,
+	/// A close, with no get or put to finish
+	NotOpen = 7,
+}
+
+/// A transfer device that's attached.
+#[derive(Debug)]
+pub struct Transfer {
+	/// The directory, with any symbolic links followed
+	root: PathBuf,
+	read_only: bool,
+	limit: usize,
+	/// The file we're in the middle of getting or putting
+	state: State,
+	/// What the OS reads from the control block
+	status: [u8; BLOCK_SIZE],
+}
+
+/// What the transfer device is doing.
+#[derive(Debug)]
+enum State {
+	Idle,
+	/// The OS is reading a file we got for it
+	Getting(Vec<u8>),
+	/// The OS is writing a file, which goes here
+	Putting {
+		path: PathBuf,
+		data: Vec<u8>,
+	},
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Which block device the transfer device is.
+pub const DEV_ID: u8 = 1;
+
+/// The biggest file we'll move, unless the `--device` option says.
+pub const DEFAULT_LIMIT: usize = 16 * 1024 * 1024;
+
+/// What the control block starts with.
+const MAGIC: &[u8; 4] = b"XFER";
+
+/// Where the path or message starts in the control block.
+const TEXT_OFFSET: usize = 16;
+
+/// The commands.
+const GET: u8 = 1;
+const PUT: u8 = 2;
+const CLOSE: u8 = 3;
+
+/// The transfer device, if there is one.
+static TRANSFER: Mutex<Option<Transfer>> = Mutex::new(None);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Attach a transfer device as Block Device 1. The directory must exist.
+pub fn attach(spec: &Spec) -> Result<(), String> {
+	let transfer = Transfer::new(spec)?;
+	info!("Transfer device attached to {}", transfer.root.display());
+	TRANSFER.lock().unwrap().replace(transfer);
+	Ok(())
+}
+
+/// Whether there's a transfer device.
+pub fn is_attached() -> bool {
+	TRANSFER.lock().unwrap().is_some()
+}
+
+/// What the OS is told about the transfer device, if there is one.
+pub fn info() -> Option<common::block_dev::DeviceInfo> {
+	let guard = TRANSFER.lock().unwrap();
+	let transfer = guard.as_ref()?;
+	Some(common::block_dev::DeviceInfo {
+		name: common::FfiString::new("Transfer1"),
+		device_type: common::block_dev::DeviceType::HardDiskDrive.into(),
+		block_size: BLOCK_SIZE as u32,
+		num_blocks: transfer.num_blocks(),
+		ejectable: false,
+		removable: false,
+		media_present: true,
+		// The OS always has to write requests to the control block
+		read_only: false,
+	})
+}
+
+/// Read blocks from the transfer device, for `block_read`.
+pub fn read(block_idx: u64, buffer: &mut [u8]) -> Result<(), common::Error> {
+	match TRANSFER.lock().unwrap().as_mut() {
+		Some(transfer) => transfer.read(block_idx, buffer),
+		None => Err(common::Error::InvalidDevice),
+	}
+}
+
+/// Write blocks to the transfer device, for `block_write`.
+pub fn write(block_idx: u64, buffer: &[u8]) -> Result<(), common::Error> {
+	match TRANSFER.lock().unwrap().as_mut() {
+		Some(transfer) => transfer.write(block_idx, buffer),
+		None => Err(common::Error::InvalidDevice),
+	}
+}
+
+/// Find where `name`, a path the OS gave us, is under `root` (which must
+/// have had its symbolic links followed already).
+///
+/// The file doesn't have to exist, but the directory it goes in does.
+pub fn resolve(root: &Path, name: &str) -> Result<PathBuf, String> {
+	if name.is_empty() {
+		return Err("no path was given".into());
+	}
+	// Paths come from the OS with `/` between the parts, whatever the host
+	// uses
+	let mut path = root.to_path_buf();
+	for part in name.split('/') {
+		let mut components = Path::new(part).components();
+		match (components.next(), components.next()) {
+			(Some(Component::Normal(part)), None) => path.push(part),
+			_ => return Err(format!("{:?} isn't a path inside the directory", name)),
+		}
+	}
+	// Follow any symbolic links, and check we're still inside. A link to
+	// nowhere fails here, rather than letting us write through it.
+	let real = if path.symlink_metadata().is_ok() {
+		path.canonicalize()
+	} else {
+		let parent = path.parent().unwrap_or(root);
+		parent
+			.canonicalize()
+			.map(|parent| parent.join(path.file_name().unwrap()))
+	}
+	.map_err(|e| format!("{}: {}", name, e))?;
+	if real.starts_with(root) {
+		Ok(real)
+	} else {
+		Err(format!("{:?} leads outside the directory", name))
+	}
+}
+
+/// A status and message for a host error about `name`.
+fn io_error(name: &str, error: std::io::Error) -> (Status, String) {
+	let status = if error.kind() == std::io::ErrorKind::NotFound {
+		Status::NotFound
+	} else {
+		Status::IoError
+	};
+	(status, format!("{}: {}", name, error))
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Transfer {
+	/// Make a transfer device for the directory in `spec`.
+	pub fn new(spec: &Spec) -> Result<Transfer, String> {
+		let root = spec
+			.dir
+			.canonicalize()
+			.map_err(|e| format!("{}: {}", spec.dir.display(), e))?;
+		if !root.is_dir() {
+			return Err(format!("{} isn't a directory", spec.dir.display()));
+		}
+		if u32::try_from(spec.limit).is_err() {
+			return Err(String::from("the limit must be under 4 GiB"));
+		}
+		let mut transfer = Transfer {
+			root,
+			read_only: spec.read_only,
+			limit: spec.limit,
+			state: State::Idle,
+			status: [0; BLOCK_SIZE],
+		};
+		transfer.set_status(0, Status::Ok, 0, "");
+		Ok(transfer)
+	}
+
+	/// How many blocks the device has: the control block, then enough for
+	/// the biggest file.
+	pub fn num_blocks(&self) -> u64 {
+		1 + self.limit.div_ceil(BLOCK_SIZE) as u64
+	}
+
+	/// Read whole blocks, starting at `block_idx`.
+	pub fn read(&mut self, block_idx: u64, buffer: &mut [u8]) -> Result<(), common::Error> {
+		self.check_bounds(block_idx, buffer.len())?;
+		for (idx, block) in (block_idx..).zip(buffer.chunks_mut(BLOCK_SIZE)) {
+			if idx == 0 {
+				block.copy_from_slice(&self.status);
+				continue;
+			}
+			let State::Getting(data) = &self.state else {
+				log::warn!("The OS read the transfer device without getting a file");
+				return Err(common::Error::DeviceError);
+			};
+			let start = ((idx - 1) as usize * BLOCK_SIZE).min(data.len());
+			let end = (start + BLOCK_SIZE).min(data.len());
+			block.fill(0);
+			block[..end - start].copy_from_slice(&data[start..end]);
+		}
+		Ok(())
+	}
+
+	/// Write whole blocks, starting at `block_idx`.
+	pub fn write(&mut self, block_idx: u64, buffer: &[u8]) -> Result<(), common::Error> {
+		self.check_bounds(block_idx, buffer.len())?;
+		for (idx, block) in (block_idx..).zip(buffer.chunks(BLOCK_SIZE)) {
+			if idx == 0 {
+				self.request(block.try_into().unwrap());
+				continue;
+			}
+			let State::Putting { data, .. } = &mut self.state else {
+				log::warn!("The OS wrote to the transfer device without putting a file");
+				return Err(common::Error::DeviceError);
+			};
+			let start = (idx - 1) as usize * BLOCK_SIZE;
+			if start >= data.len() {
+				return Err(common::Error::BlockOutOfBounds);
+			}
+			let end = (start + BLOCK_SIZE).min(data.len());
+			data[start..end].copy_from_slice(&block[..end - start]);
+		}
+		Ok(())
+	}
+
+	/// Check that `len` bytes from `block_idx` are all on the device.
+	fn check_bounds(&self, block_idx: u64, len: usize) -> Result<(), common::Error> {
+		let in_bounds = block_idx
+			.checked_add((len / BLOCK_SIZE) as u64)
+			.is_some_and(|end| end <= self.num_blocks());
+		if in_bounds {
+			Ok(())
+		} else {
+			Err(common::Error::BlockOutOfBounds)
+		}
+	}
+
+	/// Carry out a request the OS wrote to the control block.
+	fn request(&mut self, block: &[u8; BLOCK_SIZE]) {
+		let command = block[4];
+		let length = u32::from_le_bytes(block[8..12].try_into().unwrap());
+		let name = block[TEXT_OFFSET..]
+			.split(|byte| *byte == 0)
+			.next()
+			.unwrap();
+		let result = if &block[0..4] != MAGIC {
+			Err((
+				Status::BadRequest,
+				String::from("the request doesn't start with XFER"),
+			))
+		} else {
+			match (command, std::str::from_utf8(name)) {
+				(GET, Ok(name)) => self.get(name),
+				(PUT, Ok(name)) => self.put(name, length as usize),
+				(CLOSE, _) => self.close(),
+				(GET | PUT, Err(_)) => Err((Status::BadPath, String::from("the path isn't UTF-8"))),
+				_ => Err((Status::BadRequest, format!("unknown command {}", command))),
+			}
+		};
+		match result {
+			Ok(length) => self.set_status(command, Status::Ok, length, ""),
+			Err((status, message)) => {
+				log::warn!("Transfer device: {}", message);
+				self.set_status(command, status, 0, &message);
+			}
+		}
+	}
+
+	/// Start getting a file, and give its length.
+	fn get(&mut self, name: &str) -> Result<u32, (Status, String)> {
+		self.state = State::Idle;
+		let path = resolve(&self.root, name).map_err(|e| (Status::BadPath, e))?;
+		let metadata = std::fs::metadata(&path).map_err(|e| io_error(name, e))?;
+		if !metadata.is_file() {
+			return Err((Status::NotFound, format!("{} isn't a file", name)));
+		}
+		if metadata.len() > self.limit as u64 {
+			return Err((
+				Status::TooBig,
+				format!("{} is bigger than the {} byte limit", name, self.limit),
+			));
+		}
+		let data = std::fs::read(&path).map_err(|e| io_error(name, e))?;
+		info!(
+			"Transfer device: the OS got {} ({} bytes)",
+			name,
+			data.len()
+		);
+		let length = data.len() as u32;
+		self.state = State::Getting(data);
+		Ok(length)
+	}
+
+	/// Start putting a file `length` bytes long.
+	fn put(&mut self, name: &str, length: usize) -> Result<u32, (Status, String)> {
+		self.state = State::Idle;
+		if self.read_only {
+			return Err((
+				Status::ReadOnly,
+				String::from("the transfer device is read-only"),
+			));
+		}
+		if length > self.limit {
+			return Err((
+				Status::TooBig,
+				format!(
+					"{} bytes is more than the {} byte limit",
+					length, self.limit
+				),
+			));
+		}
+		let path = resolve(&self.root, name).map_err(|e| (Status::BadPath, e))?;
+		if path.is_dir() {
+			return Err((Status::BadPath, format!("{} is a directory", name)));
+		}
+		self.state = State::Putting {
+			path,
+			data: vec![0; length],
+		};
+		Ok(length as u32)
+	}
+
+	/// Finish getting or putting a file.
+	fn close(&mut self) -> Result<u32, (Status, String)> {
+		match std::mem::replace(&mut self.state, State::Idle) {
+			State::Idle => Err((Status::NotOpen, String::from("there's no file to close"))),
+			State::Getting(data) => Ok(data.len() as u32),
+			State::Putting { path, data } => {
+				// Write it somewhere else first, so the file is never half
+				// there
+				let name = path
+					.strip_prefix(&self.root)
+					.unwrap_or(&path)
+					.display()
+					.to_string();
+				crate::nvram::replace_file(&path, &data).map_err(|e| io_error(&name, e))?;
+				info!(
+					"Transfer device: the OS put {} ({} bytes)",
+					name,
+					data.len()
+				);
+				Ok(data.len() as u32)
+			}
+		}
+	}
+
+	/// Fill in the control block for the OS to read.
+	fn set_status(&mut self, command: u8, status: Status, length: u32, message: &str) {
+		self.status = [0; BLOCK_SIZE];
+		self.status[0..4].copy_from_slice(MAGIC);
+		self.status[4] = command;
+		self.status[5] = status as u8;
+		self.status[8..12].copy_from_slice(&length.to_le_bytes());
+		// Leave room for a zero on the end
+		let message = &message.as_bytes()[..message.len().min(BLOCK_SIZE - TEXT_OFFSET - 1)];
+		self.status[TEXT_OFFSET..TEXT_OFFSET + message.len()].copy_from_slice(message);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,308 @@
+//! # Checking what the OS gives us
+//!
+//! The BIOS API passes buffers as a raw pointer and a length, and we have to
+//! take the OS's word for both. A buggy OS can hand us a null pointer, a
+//! length that runs off the end of the address space, or a buffer too small
+//! for what it asked us to do - and if we believed it, the BIOS would read or
+//! write wild memory and crash somewhere confusing.
+//!
+//! So every BIOS function checks its buffers with these functions before
+//! touching them. A bad buffer gets an error back to the OS, and a loud log
+//! message naming the function, so the bug is easy to find. We can't tell
+//! whether a pointer that looks reasonable really points at the OS's memory,
+//! so this catches the common mistakes, not a determined attacker.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::Mutex;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// What's wrong with a pointer and length the OS gave us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Problem {
+	/// A null pointer, with something in it
+	Null,
+	/// A pointer that isn't aligned for what it points at
+	Misaligned,
+	/// A length that runs off the end of memory
+	Impossible,
+	/// More palette entries than there are - we use the first 256
+	LongPalette,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The most palette entries there can be.
+const PALETTE_SIZE: usize = 256;
+
+/// Every problem, in the order of their codes.
+const PROBLEMS: [Problem; 4] = [
+	Problem::Null,
+	Problem::Misaligned,
+	Problem::Impossible,
+	Problem::LongPalette,
+];
+
+/// The functions we've already complained about, so a buggy OS calling one
+/// in a loop doesn't flood the log.
+static REPORTED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Check a byte slice the OS gave `call` (in its `what` argument), and turn
+/// it into a Rust slice.
+///
+/// An empty slice is fine whatever its pointer is.
+pub fn bytes<'a>(
+	call: &'static str,
+	what: &str,
+	slice: &'a common::FfiByteSlice,
+) -> Result<&'a [u8], common::Error> {
+	check_raw(call, what, slice.data, slice.data_len)?;
+	if slice.data_len == 0 {
+		return Ok(&[]);
+	}
+	// Safety: we checked the pointer isn't null and the length is possible,
+	// and the OS promises the rest
+	Ok(unsafe { std::slice::from_raw_parts(slice.data, slice.data_len) })
+}
+
+/// Check a buffer the OS gave `call` (in its `what` argument) to write into,
+/// and turn it into a Rust slice.
+///
+/// An empty buffer is fine whatever its pointer is.
+pub fn buffer<'a>(
+	call: &'static str,
+	what: &str,
+	buffer: &'a mut common::FfiBuffer,
+) -> Result<&'a mut [u8], common::Error> {
+	check_raw(call, what, buffer.data, buffer.data_len)?;
+	if buffer.data_len == 0 {
+		return Ok(&mut []);
+	}
+	// Safety: as for `bytes`
+	Ok(unsafe { std::slice::from_raw_parts_mut(buffer.data, buffer.data_len) })
+}
+
+/// The first `len` bytes of something the OS gave `call`, or an error if it
+/// isn't that long.
+pub fn prefix<'a>(
+	call: &'static str,
+	what: &str,
+	slice: &'a [u8],
+	len: usize,
+) -> Result<&'a [u8], common::Error> {
+	match slice.get(..len) {
+		Some(prefix) => Ok(prefix),
+		None => Err(too_short(call, what, slice.len(), len)),
+	}
+}
+
+/// The first `len` bytes of a buffer the OS gave `call`, or an error if it
+/// isn't that long.
+pub fn prefix_mut<'a>(
+	call: &'static str,
+	what: &str,
+	slice: &'a mut [u8],
+	len: usize,
+) -> Result<&'a mut [u8], common::Error> {
+	let actual = slice.len();
+	match slice.get_mut(..len) {
+		Some(prefix) => Ok(prefix),
+		None => Err(too_short(call, what, actual, len)),
+	}
+}
+
+/// Check a palette the OS gave `call`, and turn it into a Rust slice.
+///
+/// There are only 256 palette entries, so we ignore any more than that. A
+/// null or misaligned pointer gets us `None`.
+pub fn palette<'a>(
+	call: &'static str,
+	start: *const common::video::RGBColour,
+	length: usize,
+) -> Option<&'a [common::video::RGBColour]> {
+	match check_palette(start, length) {
+		Ok(colours) => {
+			if length > PALETTE_SIZE {
+				report_problem(
+					call,
+					"its palette",
+					start as usize,
+					length,
+					Problem::LongPalette,
+				);
+			}
+			Some(colours)
+		}
+		Err(problem) => {
+			report_problem(call, "its palette", start as usize, length, problem);
+			None
+		}
+	}
+}
+
+/// Check a palette, like [`palette`], but without telling anyone.
+///
+/// The `--isolate` child process can't take locks or log, so it uses this
+/// and passes any problem back to us to report.
+pub fn check_palette<'a>(
+	start: *const common::video::RGBColour,
+	length: usize,
+) -> Result<&'a [common::video::RGBColour], Problem> {
+	let length = length.min(PALETTE_SIZE);
+	check(start, length)?;
+	if length == 0 {
+		return Ok(&[]);
+	}
+	// Safety: we checked the pointer isn't null and is aligned, and there
+	// are at most 256 entries, and the OS promises the rest
+	Ok(unsafe { std::slice::from_raw_parts(start, length) })
+}
+
+/// Check a pointer and a length (in items) could be a real slice, without
+/// telling anyone.
+///
+/// An empty slice is fine whatever its pointer is.
+pub fn check<T>(data: *const T, len: usize) -> Result<(), Problem> {
+	if len == 0 {
+		return Ok(());
+	}
+	if data.is_null() {
+		return Err(Problem::Null);
+	}
+	if !data.is_aligned() {
+		return Err(Problem::Misaligned);
+	}
+	match len.checked_mul(std::mem::size_of::<T>()) {
+		Some(bytes)
+			if bytes <= isize::MAX as usize && (data as usize).checked_add(bytes).is_some() =>
+		{
+			Ok(())
+		}
+		_ => Err(Problem::Impossible),
+	}
+}
+
+/// Check a pointer and length could be a real slice, and report it if not.
+fn check_raw(
+	call: &'static str,
+	what: &str,
+	data: *const u8,
+	len: usize,
+) -> Result<(), common::Error> {
+	check(data, len).map_err(|problem| {
+		report_problem(call, what, data as usize, len, problem);
+		common::Error::DeviceError
+	})
+}
+
+/// Say what was wrong with the pointer and length the OS gave `call` for
+/// `what`.
+pub fn report_problem(
+	call: &'static str,
+	what: &str,
+	address: usize,
+	len: usize,
+	problem: Problem,
+) {
+	let problem = match problem {
+		Problem::Null => format!(
+			"a null pointer for {} of length {} - refusing it",
+			what, len
+		),
+		Problem::Misaligned => format!(
+			"a misaligned pointer for {} at {:#x} - refusing it",
+			what, address
+		),
+		Problem::Impossible => format!(
+			"{} at {:#x} of length {}, which can't exist - refusing it",
+			what, address, len
+		),
+		Problem::LongPalette => format!(
+			"{} palette entries, but there are only {} - using the first {}",
+			len, PALETTE_SIZE, PALETTE_SIZE
+		),
+	};
+	report(call, &problem);
+}
+
+/// Complain that something the OS gave us is too short.
+fn too_short(call: &'static str, what: &str, actual: usize, wanted: usize) -> common::Error {
+	report(
+		call,
+		&format!(
+			"{} of {} bytes, but it needs to be at least {} - refusing it",
+			what, actual, wanted
+		),
+	);
+	common::Error::DeviceError
+}
+
+/// Say loudly that the OS called `call` with a bad argument - the first time,
+/// anyway. After that it goes in the debug log.
+pub fn report(call: &'static str, problem: &str) {
+	let mut reported = REPORTED.lock().unwrap();
+	if reported.contains(&call) {
+		log::debug!("The OS called {} with {}", call, problem);
+	} else {
+		reported.push(call);
+		log::error!(
+			"The OS called {} with {}. This is a bug in the OS. Any more problems with {} are only logged at debug level.",
+			call,
+			problem,
+			call
+		);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Problem {
+	/// A number for this problem, to pass between processes.
+	pub fn code(self) -> u8 {
+		self as u8
+	}
+
+	/// The problem with this number, if there is one.
+	pub fn from_code(code: u8) -> Option<Problem> {
+		PROBLEMS.get(usize::from(code)).copied()
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,500 @@
+//! # Video
+//!
+//! The BIOS functions for the video mode, the framebuffer and the palette.
+//! The window (see [`crate::window`]) draws whatever is in [`FRAMEBUFFER`],
+//! in the mode in [`VIDEO_MODE`], using the colours in [`PALETTE`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+use log::{debug, info};
+
+use neotron_common_bios as common;
+
+use crate::{clock, font, lint, palette, validate};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// Our video RAM
+pub struct Framebuffer<const N: usize> {
+	contents: std::cell::UnsafeCell<[u8; N]>,
+	/// Where the contents really are, if we had to move them
+	moved_to: AtomicPtr<u8>,
+	/// The OS's own VRAM, if it gave us some with `video_set_mode`
+	alt_pointer: AtomicPtr<u32>,
+	/// How many bytes of the OS's VRAM we can use - one frame of the mode it
+	/// gave it to us for
+	alt_length: AtomicUsize,
+}
+
+/// What's on the screen in a text mode, read with [`read_text`].
+#[derive(Clone)]
+pub struct TextScreen {
+	/// One `String` per row, with one `char` per column: the Unicode
+	/// character that the glyph in that cell looks like. Nothing is trimmed,
+	/// so blank cells are spaces.
+	pub rows: Vec<String>,
+	/// The attribute of each cell, in the same layout as `rows`.
+	pub attrs: Vec<Vec<common::video::Attr>>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The default VRAM we share in a very hazardous way with the OS.
+///
+/// Big enough for 640x480 @ 256 colour.
+// static mut FRAMEBUFFER: [u8; 307200] = [0u8; 307200];
+pub static FRAMEBUFFER: Framebuffer<{ 640 * 480 }> = Framebuffer::new();
+
+/// Our standard 256 colour palette
+pub static PALETTE: [AtomicU32; 256] = palette::make_default_palette();
+
+/// Our current video mode.
+///
+/// Defaulting to Mode 0 - 640x480 timing, 80x30 text mode
+pub static VIDEO_MODE: AtomicU8 = AtomicU8::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Does this Neotron BIOS support this video mode?
+pub extern "C" fn video_is_valid_mode(mode: common::video::Mode) -> bool {
+	let result = is_supported(mode);
+	debug!("video_is_valid_mode({:?}) = {}", mode, result);
+	result
+}
+
+/// Is this one of the video modes we can draw?
+///
+/// This is the answer [`video_is_valid_mode`] gives, without the logging.
+pub fn is_supported(mode: common::video::Mode) -> bool {
+	use common::video::{Format, Timing};
+	if mode.is_horiz_2x() || mode.is_vert_2x() {
+		// We don't scale anything yet
+		return false;
+	}
+	match (mode.timing(), mode.format()) {
+		// 640x480, 80x30 and 80x60 text modes
+		(Timing::T640x480, Format::Text8x16 | Format::Text8x8) => true,
+		// 640x480, 8, 4, 2 and 1-bpp bitmap modes
+		(
+			Timing::T640x480,
+			Format::Chunky8 | Format::Chunky4 | Format::Chunky2 | Format::Chunky1,
+		) => true,
+		// 640x400, 4-bpp bitmap mode
+		(Timing::T640x400, Format::Chunky4) => true,
+		// 640x400 and 800x600, 2-bpp bitmap modes
+		(Timing::T640x400 | Timing::T800x600, Format::Chunky2) => true,
+		// 640x400, 1-bpp bitmap mode
+		(Timing::T640x400, Format::Chunky1) => true,
+		// 800x600, 8-bpp bitmap mode, which only works with VRAM from the OS
+		(Timing::T800x600, Format::Chunky8) => true,
+		// nothing else will work
+		_ => false,
+	}
+}
+
+/// Does the OS have to give us VRAM for this mode?
+///
+/// This is the answer [`video_mode_needs_vram`] gives, without the logging.
+/// It's only a question of whether a frame fits in [`FRAMEBUFFER`].
+pub fn needs_vram(mode: common::video::Mode) -> bool {
+	mode.frame_size_bytes() > FRAMEBUFFER.size()
+}
+
+/// Print every video mode the OS can pick, and what it looks like.
+pub fn list_modes() {
+	println!(
+		"{:>4}  {:<16}  {:<20}  {:>10}  {:>6}  {:>11}  {:>10}",
+		"Mode", "Timing", "Format", "Resolution", "Text", "Frame bytes", "Needs VRAM"
+	);
+	for mode in (0..=255).filter_map(common::video::Mode::try_from_u8) {
+		if !is_supported(mode) {
+			continue;
+		}
+		let timing = match mode.timing() {
+			common::video::Timing::T640x480 => "640x480 @ 60Hz",
+			common::video::Timing::T640x400 => "640x400 @ 70Hz",
+			common::video::Timing::T800x600 => "800x600 @ 60Hz",
+		};
+		let text = match (mode.text_width(), mode.text_height()) {
+			(Some(width), Some(height)) => format!("{}x{}", width, height),
+			_ => String::from("-"),
+		};
+		println!(
+			"{:>4}  {:<16}  {:<20}  {:>10}  {:>6}  {:>11}  {:>10}",
+			mode.as_u8(),
+			timing,
+			mode.format().to_string(),
+			format!("{}x{}", mode.horizontal_pixels(), mode.vertical_lines()),
+			text,
+			mode.frame_size_bytes(),
+			if needs_vram(mode) { "yes" } else { "no" }
+		);
+	}
+}
+
+/// Switch to a new video mode.
+///
+/// If `fb` isn't null, the OS is giving us its own VRAM, which must hold a
+/// frame of the new mode, and we draw from that until the next mode change.
+/// If it is null we go back to our own, and modes that don't fit in ours
+/// (see [`needs_vram`]) are refused.
+///
+/// The contents of the screen are undefined after a call to this function.
+pub extern "C" fn video_set_mode(mode: common::video::Mode, fb: *mut u32) -> common::ApiResult<()> {
+	info!("video_set_mode({:?}, {:p})", mode, fb);
+	if !video_is_valid_mode(mode) {
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	}
+	if fb.is_null() && needs_vram(mode) {
+		info!(
+			"Mode {} needs {} bytes of VRAM from the OS",
+			mode.as_u8(),
+			mode.frame_size_bytes()
+		);
+		return common::ApiResult::Err(common::Error::UnsupportedConfiguration);
+	}
+	// Switch memory first, so the GUI thread never draws the new mode from
+	// memory too small for it
+	FRAMEBUFFER.set_vram(fb, mode.frame_size_bytes());
+	let mode_value = mode.as_u8();
+	VIDEO_MODE.store(mode_value, Ordering::SeqCst);
+	crate::remote::notify(crate::remote::Event::Mode(mode_value));
+	common::ApiResult::Ok(())
+}
+
+/// Returns the video mode the BIOS is currently in.
+///
+/// The OS should call this function immediately after start-up and note
+/// the value - this is the `default` video mode which can always be
+/// serviced without supplying extra RAM.
+pub extern "C" fn video_get_mode() -> common::video::Mode {
+	debug!("video_get_mode()");
+	current_mode()
+}
+
+/// The video mode we're in.
+pub fn current_mode() -> common::video::Mode {
+	let mode_value = VIDEO_MODE.load(Ordering::Relaxed);
+	// We know this is a valid video mode because it was set with `video_set_mode`.
+	unsafe { common::video::Mode::from_u8(mode_value) }
+}
+
+/// Get the framebuffer address.
+///
+/// We can write through this address to the video framebuffer. The
+/// meaning of the data we write, and the size of the region we are
+/// allowed to write to, is a function of the current video mode (see
+/// `video_get_mode`).
+pub extern "C" fn video_get_framebuffer() -> *mut u32 {
+	lint::check_started("video_get_framebuffer");
+	let p = FRAMEBUFFER.get_pointer();
+	debug!("video_get_framebuffer() -> {:p}", p);
+	p
+}
+
+/// Find out whether the given video mode needs more VRAM than we currently have.
+///
+/// The answer is yes if a frame won't fit in our own framebuffer (see
+/// [`needs_vram`]).
+pub extern "C" fn video_mode_needs_vram(mode: common::video::Mode) -> bool {
+	debug!("video_mode_needs_vram()");
+	needs_vram(mode)
+}
+
+/// Wait for the next occurence of the specified video scan-line.
+///
+/// In general we must assume that the video memory is read top-to-bottom
+/// as the picture is being drawn on the monitor (e.g. via a VGA video
+/// signal). If you modify video memory during this *drawing period*
+/// there is a risk that the image on the monitor (however briefly) may
+/// contain some parts from before the modification and some parts from
+/// after. This can given rise to the *tearing effect* where it looks
+/// like the screen has been torn (or ripped) across because there is a
+/// discontinuity part-way through the image.
+///
+/// This function busy-waits until the video drawing has reached a
+/// specified scan-line on the video frame.
+///
+/// There is no error code here. If the line you ask for is beyond the
+/// number of visible scan-lines in the current video mode, it waits util
+/// the last visible scan-line is complete.
+///
+/// If you wait for the last visible line until drawing, you stand the
+/// best chance of your pixels operations on the video RAM being
+/// completed before scan-lines start being sent to the monitor for the
+/// next frame.
+///
+/// You can also use this for a crude `16.7 ms` delay but note that
+/// some video modes run at `70 Hz` and so this would then give you a
+/// `14.3ms` second delay.
+///
+/// We pretend the video is scanned out in emulated time, so this runs faster
+/// or slower with `--time-scale`.
+pub extern "C" fn video_wait_for_line(line: u16) {
+	debug!("video_wait_for_line({})", line);
+	lint::check_blocking("video_wait_for_line");
+	let mode = unsafe { common::video::Mode::from_u8(VIDEO_MODE.load(Ordering::Relaxed)) };
+	let frame_ns = 1_000_000_000 / u128::from(mode.frame_rate_hz().max(1));
+	let lines = u128::from(mode.vertical_lines().max(1));
+	let line = u128::from(line).min(lines - 1);
+	// When the line we want finishes, relative to the start of the frame
+	let target = frame_ns * (line + 1) / lines;
+	let now = clock::elapsed().as_nanos() % frame_ns;
+	let wait = if target > now {
+		target - now
+	} else {
+		frame_ns - now + target
+	};
+	std::thread::sleep(clock::host_duration(std::time::Duration::from_nanos(
+		wait as u64,
+	)));
+}
+
+pub extern "C" fn video_get_palette(index: u8) -> common::FfiOption<common::video::RGBColour> {
+	debug!("video_get_palette({})", index);
+	let entry = PALETTE.get(usize::from(index));
+	let entry_value =
+		entry.map(|raw| common::video::RGBColour::from_packed(raw.load(Ordering::Relaxed)));
+	match entry_value {
+		Some(rgb) => common::FfiOption::Some(rgb),
+		None => common::FfiOption::None,
+	}
+}
+
+pub extern "C" fn video_set_palette(index: u8, rgb: common::video::RGBColour) {
+	debug!("video_set_palette({}, #{:6x})", index, rgb.as_packed());
+	lint::check_palette_index("video_set_palette", index, current_mode());
+	if let Some(e) = PALETTE.get(usize::from(index)) {
+		e.store(rgb.as_packed(), Ordering::Relaxed);
+	}
+}
+
+/// Set all the palette entries at once, starting at entry 0.
+///
+/// # Safety
+///
+/// `palette` must point to `length` colours.
+pub unsafe extern "C" fn video_set_whole_palette(
+	palette: *const common::video::RGBColour,
+	length: usize,
+) {
+	debug!("video_set_whole_palette({:p}, {})", palette, length);
+	let Some(slice) = validate::palette("video_set_whole_palette", palette, length) else {
+		return;
+	};
+	if let Some(last) = slice.len().checked_sub(1) {
+		lint::check_palette_index("video_set_whole_palette", last as u8, current_mode());
+	}
+	for (entry, new_rgb) in PALETTE.iter().zip(slice) {
+		entry.store(new_rgb.as_packed(), Ordering::Relaxed);
+	}
+}
+
+/// Put the video back the way it is at power on: the default mode, using our
+/// own framebuffer, with a blank screen and the default palette.
+pub fn reset_video() {
+	VIDEO_MODE.store(0, Ordering::Relaxed);
+	FRAMEBUFFER.set_vram(std::ptr::null_mut(), 0);
+	clear_screen();
+	for (entry, default) in PALETTE.iter().zip(palette::make_default_palette().iter()) {
+		entry.store(default.load(Ordering::Relaxed), Ordering::Relaxed);
+	}
+}
+
+/// Fill the text framebuffer with white-on-black spaces.
+pub fn clear_screen() {
+	let white_on_black = common::video::Attr::new(
+		common::video::TextForegroundColour::White,
+		common::video::TextBackgroundColour::Black,
+		false,
+	);
+	for char_idx in 0..(80 * 60) {
+		// Blank
+		FRAMEBUFFER.write_at(char_idx * 2, b' ');
+		// White on Black
+		FRAMEBUFFER.write_at((char_idx * 2) + 1, white_on_black.as_u8());
+	}
+}
+
+/// Read the text on the screen, and the attribute of each cell.
+///
+/// Returns `None` if we're not in a text mode. The OS can be writing to the
+/// screen while we read it, so a row might be half old and half new.
+pub fn read_text() -> Option<TextScreen> {
+	let mode = current_mode();
+	let glyph_height = match mode.format() {
+		common::video::Format::Text8x16 => 16,
+		common::video::Format::Text8x8 => 8,
+		_ => return None,
+	};
+	let columns = usize::from(mode.horizontal_pixels()) / 8;
+	let rows = usize::from(mode.vertical_lines()) / glyph_height;
+	let mut screen = TextScreen {
+		rows: Vec::with_capacity(rows),
+		attrs: Vec::with_capacity(rows),
+	};
+	for row in 0..rows {
+		let cells = (row * columns..(row + 1) * columns).map(|cell| {
+			(
+				FRAMEBUFFER.get_at(cell * 2),
+				FRAMEBUFFER.get_at(cell * 2 + 1),
+			)
+		});
+		let (glyphs, attrs): (Vec<u8>, Vec<u8>) = cells.unzip();
+		screen
+			.rows
+			.push(glyphs.into_iter().map(font::to_char).collect());
+		screen
+			.attrs
+			.push(attrs.into_iter().map(common::video::Attr).collect());
+	}
+	Some(screen)
+}
+
+/// Describe the current video mode, like `Mode 0 (Text8x16), 640 x 480`.
+pub fn describe_mode() -> String {
+	let mode_value = VIDEO_MODE.load(Ordering::Relaxed);
+	// We know this is a valid video mode because it was set with `video_set_mode`.
+	let mode = unsafe { common::video::Mode::from_u8(mode_value) };
+	format!(
+		"Mode {} ({:?}), {} x {}",
+		mode_value,
+		mode.format(),
+		mode.horizontal_pixels(),
+		mode.vertical_lines()
+	)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl<const N: usize> Framebuffer<N> {
+	/// Create a new blank Framebuffer.
+	///
+	/// Everything is zero initialised.
+	const fn new() -> Framebuffer<N> {
+		Framebuffer {
+			contents: std::cell::UnsafeCell::new([0u8; N]),
+			moved_to: AtomicPtr::new(core::ptr::null_mut()),
+			alt_pointer: AtomicPtr::new(core::ptr::null_mut()),
+			alt_length: AtomicUsize::new(0),
+		}
+	}
+
+	/// How many bytes our own framebuffer holds.
+	pub const fn size(&self) -> usize {
+		N
+	}
+
+	/// Set a byte in the framebuffer.
+	///
+	/// Writes past the end are ignored.
+	///
+	/// Uses volatile writes.
+	pub fn write_at(&self, offset: usize, value: u8) {
+		let (array_ptr, length) = self.locate();
+		if offset < length {
+			unsafe { array_ptr.add(offset).write_volatile(value) }
+		}
+	}
+
+	/// Get a byte from the framebuffer.
+	///
+	/// Reads past the end give zero. That can happen if the OS changes mode
+	/// while we're drawing the old one.
+	///
+	/// Uses volatile reads.
+	pub fn get_at(&self, offset: usize) -> u8 {
+		let (array_ptr, length) = self.locate();
+		if offset < length {
+			unsafe { array_ptr.add(offset).read_volatile() }
+		} else {
+			0
+		}
+	}
+
+	/// Get a pointer to the framebuffer you can give to the OS.
+	pub fn get_pointer(&self) -> *mut u32 {
+		self.locate().0.cast()
+	}
+
+	/// Use the OS's VRAM at `vram`, which holds `length` bytes, instead of our
+	/// own - or go back to our own, if `vram` is null.
+	fn set_vram(&self, vram: *mut u32, length: usize) {
+		// The GUI thread could be reading while we do this, so it mustn't
+		// see the old length with the new pointer. See `locate`.
+		self.alt_length.store(0, Ordering::SeqCst);
+		self.alt_pointer.store(vram, Ordering::SeqCst);
+		self.alt_length.store(length, Ordering::SeqCst);
+	}
+
+	/// Where the contents are, and how many bytes of them we can touch.
+	fn locate(&self) -> (*mut u8, usize) {
+		let alt = self.alt_pointer.load(Ordering::SeqCst);
+		if alt.is_null() {
+			let moved = self.moved_to.load(Ordering::Relaxed);
+			let own = if moved.is_null() {
+				self.contents.get() as *mut u8
+			} else {
+				moved
+			};
+			return (own, N);
+		}
+		let length = self.alt_length.load(Ordering::SeqCst);
+		// If the pointer changed while we read the length, the length might
+		// not be for the VRAM we're pointing at, so don't touch any of it
+		if self.alt_pointer.load(Ordering::SeqCst) != alt {
+			return (alt.cast(), 0);
+		}
+		(alt.cast(), length)
+	}
+
+	/// Keep the contents at `new_home` (which must hold `N` bytes) from now
+	/// on, instead of in here. We use this to share the framebuffer with
+	/// another process.
+	///
+	/// # Safety
+	///
+	/// `new_home` must hold `N` bytes, and live as long as we do. Only call
+	/// this before the OS starts.
+	pub unsafe fn move_to(&self, new_home: *mut u8) {
+		std::ptr::copy_nonoverlapping(self.contents.get() as *const u8, new_home, N);
+		self.moved_to.store(new_home, Ordering::Relaxed);
+	}
+}
+
+unsafe impl<const N: usize> Sync for Framebuffer<N> {}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,256 @@
+//! # Rendering statistics
+//!
+//! Every frame, the GUI thread tells us how long it took to turn the
+//! framebuffer into pixels, how long the whole update took, how long it has
+//! been since the last frame, and how much it drew. We keep running totals,
+//! and the times of the last few hundred frames for a histogram. The debug
+//! console shows the lot with `stats video`, and `--video-stats` logs a
+//! summary once a minute.
+//!
+//! Recording a frame is one lock and a few additions, so this is always on.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// What happened in one frame.
+pub struct Frame {
+	/// How long since the previous frame started
+	pub interval: Duration,
+	/// How long the whole update took
+	pub update: Duration,
+	/// How long we spent turning the framebuffer into pixels
+	pub decode: Duration,
+	/// How many text cells we drew, each one a glyph texture copy
+	pub cells: usize,
+	/// How many pixels we drew one at a time, in the chunky modes
+	pub pixels: usize,
+}
+
+/// The running totals.
+struct Stats {
+	/// The name of the SDL renderer, once the window is open
+	renderer: Option<String>,
+	/// How many frames we've drawn
+	frames: u64,
+	/// Total time spent in updates
+	update: Duration,
+	/// Total time spent decoding the framebuffer
+	decode: Duration,
+	/// Total text cells drawn
+	cells: u64,
+	/// Total chunky pixels drawn
+	pixels: u64,
+	/// How many glyph textures we've rendered and uploaded
+	texture_uploads: u64,
+	/// The intervals between the last few frames, oldest first
+	recent: VecDeque<Duration>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+static STATS: Mutex<Stats> = Mutex::new(Stats::new());
+
+/// How many frame times go in the histogram - ten seconds' worth at 60 Hz.
+const HISTORY: usize = 600;
+
+/// The upper bound of each histogram bucket, in milliseconds. The last
+/// bucket has everything slower.
+const BUCKETS_MS: [u64; 5] = [8, 17, 33, 50, 100];
+
+/// How often `--video-stats` logs a summary.
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Log a summary once a minute.
+pub fn start_logging() {
+	std::thread::spawn(|| loop {
+		std::thread::sleep(LOG_INTERVAL);
+		log::info!("Video: {}", summary());
+	});
+}
+
+/// Note which SDL renderer we're drawing with.
+pub fn set_renderer(name: String) {
+	log::info!("Rendering with SDL's {} renderer", name);
+	STATS.lock().unwrap().renderer = Some(name);
+}
+
+/// Count some glyph textures we've rendered.
+pub fn record_uploads(count: usize) {
+	STATS.lock().unwrap().texture_uploads += count as u64;
+}
+
+/// Add a frame to the totals.
+pub fn record(frame: Frame) {
+	let mut stats = STATS.lock().unwrap();
+	stats.frames += 1;
+	stats.update += frame.update;
+	stats.decode += frame.decode;
+	stats.cells += frame.cells as u64;
+	stats.pixels += frame.pixels as u64;
+	if stats.recent.len() == HISTORY {
+		stats.recent.pop_front();
+	}
+	stats.recent.push_back(frame.interval);
+}
+
+/// Everything we know, with a histogram of the recent frame times.
+pub fn report() -> String {
+	let stats = STATS.lock().unwrap();
+	if stats.frames == 0 {
+		return "We haven't drawn a frame yet".to_string();
+	}
+	let mut output = format!(
+		"Renderer: {}\nFrames: {}\n",
+		stats.renderer.as_deref().unwrap_or("unknown"),
+		stats.frames
+	);
+	let frames = stats.frames as u32;
+	output.push_str(&format!(
+		"Average update: {:.2} ms, of which decoding the framebuffer: {:.2} ms\n",
+		millis(stats.update / frames),
+		millis(stats.decode / frames)
+	));
+	output.push_str(&format!(
+		"Average per frame: {} text cells, {} chunky pixels\n",
+		stats.cells / stats.frames,
+		stats.pixels / stats.frames
+	));
+	output.push_str(&format!(
+		"Glyph textures uploaded: {}\n",
+		stats.texture_uploads
+	));
+	output.push_str(&format!(
+		"Last {} frame times: {}",
+		stats.recent.len(),
+		stats.describe_recent()
+	));
+	let mut counts = [0usize; BUCKETS_MS.len() + 1];
+	for interval in &stats.recent {
+		let ms = interval.as_millis() as u64;
+		let bucket = BUCKETS_MS
+			.iter()
+			.position(|limit| ms < *limit)
+			.unwrap_or(BUCKETS_MS.len());
+		counts[bucket] += 1;
+	}
+	let most = counts.iter().copied().max().unwrap_or(0).max(1);
+	let mut lower = 0;
+	for (bucket, count) in counts.iter().enumerate() {
+		let label = match BUCKETS_MS.get(bucket) {
+			Some(upper) => format!("{:>3}-{:<3} ms", lower, upper),
+			None => format!("{:>3}+    ms", lower),
+		};
+		if let Some(upper) = BUCKETS_MS.get(bucket) {
+			lower = *upper;
+		}
+		output.push_str(&format!(
+			"\n  {} {:>5} {}",
+			label,
+			count,
+			"#".repeat(count * 40 / most)
+		));
+	}
+	output
+}
+
+/// The frame times in one line.
+pub fn summary() -> String {
+	let stats = STATS.lock().unwrap();
+	if stats.frames == 0 {
+		return "no frames yet".to_string();
+	}
+	format!(
+		"{} frames, average decode {:.2} ms, frame times {}",
+		stats.frames,
+		millis(stats.decode / stats.frames as u32),
+		stats.describe_recent()
+	)
+}
+
+/// Start counting again from zero.
+pub fn reset() {
+	let mut stats = STATS.lock().unwrap();
+	let renderer = stats.renderer.take();
+	*stats = Stats::new();
+	stats.renderer = renderer;
+}
+
+/// A duration in milliseconds, for printing.
+fn millis(duration: Duration) -> f64 {
+	duration.as_secs_f64() * 1000.0
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Stats {
+	const fn new() -> Stats {
+		Stats {
+			renderer: None,
+			frames: 0,
+			update: Duration::ZERO,
+			decode: Duration::ZERO,
+			cells: 0,
+			pixels: 0,
+			texture_uploads: 0,
+			recent: VecDeque::new(),
+		}
+	}
+
+	/// The shortest, average, 99th percentile and longest recent frame
+	/// times.
+	fn describe_recent(&self) -> String {
+		if self.recent.is_empty() {
+			return "none yet".to_string();
+		}
+		let mut sorted: Vec<Duration> = self.recent.iter().copied().collect();
+		sorted.sort();
+		let total: Duration = sorted.iter().sum();
+		format!(
+			"min {:.1} ms, average {:.1} ms, 99% {:.1} ms, max {:.1} ms",
+			millis(sorted[0]),
+			millis(total / sorted.len() as u32),
+			millis(sorted[(sorted.len() - 1) * 99 / 100]),
+			millis(sorted[sorted.len() - 1])
+		)
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,718 @@
+//! # VNC server
+//!
+//! With `--vnc`, we serve the screen over RFB (the VNC protocol), so you can
+//! see and type into the OS from any VNC viewer - handy when the emulator is
+//! running `--headless` on a build server.
+//!
+//! We draw the screen with [`crate::render`], the same as the golden-image
+//! tests do, and send it with the Raw encoding. For incremental updates we
+//! compare what we drew with what the viewer already has, a tile at a time,
+//! and only send the tiles that changed. Key presses go to the OS the same way
+//! `sendkey` does, so they skip the host hotkeys. The OS has no mouse, so
+//! pointer events are thrown away.
+//!
+//! There's no authentication and no encryption, and we talk to one viewer at
+//! a time.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use pix_engine::prelude::Key;
+
+use crate::render::{self, Image};
+use crate::window::{self, GuiRequest, WINDOW_TITLE};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// How the viewer wants its pixels. We only do true colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+	/// 8, 16 or 32
+	pub bits_per_pixel: u8,
+	/// Whether a pixel's bytes go most significant first
+	pub big_endian: bool,
+	pub red_max: u16,
+	pub green_max: u16,
+	pub blue_max: u16,
+	pub red_shift: u8,
+	pub green_shift: u8,
+	pub blue_shift: u8,
+}
+
+/// A part of the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+	pub x: usize,
+	pub y: usize,
+	pub width: usize,
+	pub height: usize,
+}
+
+/// Something the viewer sent us.
+#[derive(Debug)]
+enum Message {
+	/// Send pixels like this from now on
+	SetPixelFormat(PixelFormat),
+	/// These are the encodings the viewer understands
+	SetEncodings(Vec<i32>),
+	/// Send this part of the screen, or just what's changed in it
+	UpdateRequest { incremental: bool, rect: Rect },
+	/// A key with this X keysym went down or up
+	Key { down: bool, keysym: u32 },
+	/// Something we don't do anything with
+	Ignored,
+}
+
+/// One viewer, and what we know about it.
+struct Session {
+	stream: TcpStream,
+	format: PixelFormat,
+	/// Whether the viewer can cope with the screen changing size
+	desktop_size: bool,
+	/// What the viewer has, as far as we know
+	sent: Image,
+	/// What the viewer asked for, and hasn't had yet
+	pending: Option<(bool, Rect)>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The version of RFB we offer.
+const VERSION: &[u8; 12] = b"RFB 003.008\n";
+
+/// The Raw encoding.
+const ENCODING_RAW: i32 = 0;
+
+/// The pseudo-encoding for telling the viewer the screen has changed size.
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+
+/// How big a square we compare at a time. The text modes' cells are 8 pixels
+/// wide, so this is a couple of cells across.
+const TILE: usize = 16;
+
+/// How often we look for changes when the viewer is waiting for some.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--vnc` address, like `:5901` or `0.0.0.0:5901`. With no host we
+/// listen on `127.0.0.1`. As in a VNC viewer, a number below 100 is a display
+/// number, so `:1` is port 5901.
+pub fn parse_address(text: &str) -> Result<SocketAddr, String> {
+	let (host, port) = text
+		.rsplit_once(':')
+		.ok_or("expected [<host>]:<port>, like :5901")?;
+	let port: u16 = port
+		.parse()
+		.map_err(|_| format!("{:?} is not a port number", port))?;
+	let port = if port < 100 { 5900 + port } else { port };
+	let host = if host.is_empty() { "127.0.0.1" } else { host };
+	(host, port)
+		.to_socket_addrs()
+		.map_err(|e| format!("bad address {:?}: {}", text, e))?
+		.next()
+		.ok_or_else(|| format!("{:?} doesn't have an address", host))
+}
+
+/// Start listening for VNC viewers, on a new thread.
+///
+/// Fails if we can't listen on the address, like if something else already
+/// is.
+pub fn start(address: SocketAddr) -> Result<(), String> {
+	if !address.ip().is_loopback() {
+		log::warn!(
+			"The VNC server on {} can be used by other machines, with no password",
+			address
+		);
+	}
+	let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+	log::info!("VNC server listening on {}", address);
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			match stream {
+				Ok(stream) => serve(stream),
+				Err(e) => log::warn!("VNC server failed to accept a viewer: {}", e),
+			}
+		}
+	});
+	Ok(())
+}
+
+/// Talk to one viewer, until it hangs up.
+fn serve(stream: TcpStream) {
+	let peer = stream
+		.peer_addr()
+		.map_or_else(|_| String::from("?"), |addr| addr.to_string());
+	log::info!("VNC viewer connected from {}", peer);
+	if let Err(e) = talk(stream) {
+		log::warn!("VNC viewer {} went away: {}", peer, e);
+	}
+	log::info!("VNC viewer {} disconnected", peer);
+}
+
+/// Shake hands with a viewer, then send it the screen as it asks for it.
+fn talk(mut stream: TcpStream) -> std::io::Result<()> {
+	stream.set_nodelay(true)?;
+	handshake(&mut stream)?;
+	let screen = render::render_screen();
+	let format = PixelFormat::DEFAULT;
+	let mut init = Vec::new();
+	init.extend_from_slice(&(screen.width as u16).to_be_bytes());
+	init.extend_from_slice(&(screen.height as u16).to_be_bytes());
+	init.extend_from_slice(&format.to_bytes());
+	init.extend_from_slice(&(WINDOW_TITLE.len() as u32).to_be_bytes());
+	init.extend_from_slice(WINDOW_TITLE.as_bytes());
+	stream.write_all(&init)?;
+
+	// Messages come in on a thread of their own, so we can look for changes
+	// to the screen while we wait for them
+	let (sender, messages) = mpsc::channel();
+	let mut reader = std::io::BufReader::new(stream.try_clone()?);
+	std::thread::spawn(move || loop {
+		match read_message(&mut reader) {
+			Ok(message) => {
+				if sender.send(Ok(message)).is_err() {
+					break;
+				}
+			}
+			// The viewer hung up
+			Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+			Err(e) => {
+				let _ = sender.send(Err(e));
+				break;
+			}
+		}
+	});
+
+	let mut session = Session {
+		stream,
+		format,
+		desktop_size: false,
+		sent: unknown(screen.width, screen.height),
+		pending: None,
+	};
+	let result = session.run(&messages);
+	// Stop the reading thread too
+	let _ = session.stream.shutdown(Shutdown::Both);
+	result
+}
+
+/// Agree a protocol version with the viewer, and tell it there's no password.
+fn handshake(stream: &mut TcpStream) -> std::io::Result<()> {
+	stream.write_all(VERSION)?;
+	let mut version = [0u8; 12];
+	stream.read_exact(&mut version)?;
+	let minor = std::str::from_utf8(&version)
+		.ok()
+		.filter(|text| text.starts_with("RFB 003.") && text.ends_with('\n'))
+		.and_then(|text| text[8..11].parse::<u16>().ok())
+		.ok_or_else(|| bad_data(format!("{:?} is not an RFB version", version)))?;
+	if minor < 7 {
+		// 3.3 has no choosing: we say there's no security, and that's that
+		stream.write_all(&1u32.to_be_bytes())?;
+		return Ok(());
+	}
+	// One security type, which is None
+	stream.write_all(&[1, 1])?;
+	let mut choice = [0u8; 1];
+	stream.read_exact(&mut choice)?;
+	if choice[0] != 1 {
+		return Err(bad_data(format!("unknown security type {}", choice[0])));
+	}
+	if minor >= 8 {
+		stream.write_all(&0u32.to_be_bytes())?;
+	}
+	// ClientInit says whether other viewers can stay connected, and there
+	// aren't any
+	stream.read_exact(&mut choice)?;
+	Ok(())
+}
+
+/// Read one message from the viewer.
+fn read_message(reader: &mut impl Read) -> std::io::Result<Message> {
+	let mut kind = [0u8; 1];
+	reader.read_exact(&mut kind)?;
+	match kind[0] {
+		0 => {
+			let mut data = [0u8; 19];
+			reader.read_exact(&mut data)?;
+			let format =
+				PixelFormat::from_bytes(data[3..].try_into().unwrap()).map_err(bad_data)?;
+			Ok(Message::SetPixelFormat(format))
+		}
+		2 => {
+			let mut header = [0u8; 3];
+			reader.read_exact(&mut header)?;
+			let count = u16::from_be_bytes([header[1], header[2]]);
+			let mut encodings = Vec::with_capacity(usize::from(count));
+			for _ in 0..count {
+				let mut encoding = [0u8; 4];
+				reader.read_exact(&mut encoding)?;
+				encodings.push(i32::from_be_bytes(encoding));
+			}
+			Ok(Message::SetEncodings(encodings))
+		}
+		3 => {
+			let mut data = [0u8; 9];
+			reader.read_exact(&mut data)?;
+			let field = |idx: usize| usize::from(u16::from_be_bytes([data[idx], data[idx + 1]]));
+			Ok(Message::UpdateRequest {
+				incremental: data[0] != 0,
+				rect: Rect {
+					x: field(1),
+					y: field(3),
+					width: field(5),
+					height: field(7),
+				},
+			})
+		}
+		4 => {
+			let mut data = [0u8; 7];
+			reader.read_exact(&mut data)?;
+			Ok(Message::Key {
+				down: data[0] != 0,
+				keysym: u32::from_be_bytes(data[3..7].try_into().unwrap()),
+			})
+		}
+		5 => {
+			// The OS has no mouse to give this to
+			let mut data = [0u8; 5];
+			reader.read_exact(&mut data)?;
+			Ok(Message::Ignored)
+		}
+		6 => {
+			// Nor a clipboard
+			let mut header = [0u8; 7];
+			reader.read_exact(&mut header)?;
+			let length = u32::from_be_bytes(header[3..7].try_into().unwrap());
+			std::io::copy(&mut reader.take(u64::from(length)), &mut std::io::sink())?;
+			Ok(Message::Ignored)
+		}
+		kind => Err(bad_data(format!("unknown message type {}", kind))),
+	}
+}
+
+/// Turn an X keysym from a viewer into the key that makes it on a US
+/// keyboard.
+///
+/// Viewers send what a key typed, not which key it was, so with Shift held
+/// down `!` comes back as the `1` key.
+pub fn convert_keysym(keysym: u32) -> Option<Key> {
+	let key = match keysym {
+		0x20 => Key::Space,
+		0x21 => Key::Num1,
+		0x22 | 0x27 => Key::Quote,
+		0x23 => Key::Num3,
+		0x24 => Key::Num4,
+		0x25 => Key::Num5,
+		0x26 => Key::Num7,
+		0x28 => Key::Num9,
+		0x29 => Key::Num0,
+		0x2a => Key::Num8,
+		0x2b | 0x3d => Key::Equals,
+		0x2c | 0x3c => Key::Comma,
+		0x2d | 0x5f => Key::Minus,
+		0x2e | 0x3e => Key::Period,
+		0x2f | 0x3f => Key::Slash,
+		0x30 => Key::Num0,
+		0x31 => Key::Num1,
+		0x32 | 0x40 => Key::Num2,
+		0x33 => Key::Num3,
+		0x34 => Key::Num4,
+		0x35 => Key::Num5,
+		0x36 | 0x5e => Key::Num6,
+		0x37 => Key::Num7,
+		0x38 => Key::Num8,
+		0x39 => Key::Num9,
+		0x3a | 0x3b => Key::Semicolon,
+		0x41..=0x5a => return letter(keysym - 0x41),
+		0x5b | 0x7b => Key::LeftBracket,
+		0x5c | 0x7c => Key::Backslash,
+		0x5d | 0x7d => Key::RightBracket,
+		0x60 | 0x7e => Key::Backquote,
+		0x61..=0x7a => return letter(keysym - 0x61),
+		0xff08 => Key::Backspace,
+		0xff09 => Key::Tab,
+		0xff0d => Key::Return,
+		0xff13 => Key::Pause,
+		0xff14 => Key::ScrollLock,
+		0xff1b => Key::Escape,
+		0xff50 => Key::Home,
+		0xff51 => Key::Left,
+		0xff52 => Key::Up,
+		0xff53 => Key::Right,
+		0xff54 => Key::Down,
+		0xff55 => Key::PageUp,
+		0xff56 => Key::PageDown,
+		0xff57 => Key::End,
+		0xff61 => Key::PrintScreen,
+		0xff63 => Key::Insert,
+		0xff7f => Key::NumLock,
+		0xff8d => Key::KpEnter,
+		0xffaa => Key::KpMultiply,
+		0xffab => Key::KpPlus,
+		0xffad => Key::KpMinus,
+		0xffae => Key::KpPeriod,
+		0xffaf => Key::KpDivide,
+		0xffb0 => Key::Kp0,
+		0xffb1 => Key::Kp1,
+		0xffb2 => Key::Kp2,
+		0xffb3 => Key::Kp3,
+		0xffb4 => Key::Kp4,
+		0xffb5 => Key::Kp5,
+		0xffb6 => Key::Kp6,
+		0xffb7 => Key::Kp7,
+		0xffb8 => Key::Kp8,
+		0xffb9 => Key::Kp9,
+		0xffbe => Key::F1,
+		0xffbf => Key::F2,
+		0xffc0 => Key::F3,
+		0xffc1 => Key::F4,
+		0xffc2 => Key::F5,
+		0xffc3 => Key::F6,
+		0xffc4 => Key::F7,
+		0xffc5 => Key::F8,
+		0xffc6 => Key::F9,
+		0xffc7 => Key::F10,
+		0xffc8 => Key::F11,
+		0xffc9 => Key::F12,
+		0xffe1 => Key::LShift,
+		0xffe2 => Key::RShift,
+		0xffe3 => Key::LCtrl,
+		0xffe4 => Key::RCtrl,
+		0xffe5 => Key::CapsLock,
+		0xffe9 => Key::LAlt,
+		// Alt Gr comes as Alt_R or ISO_Level3_Shift, depending on the viewer
+		0xffea | 0xfe03 => Key::RAlt,
+		0xffeb => Key::LGui,
+		0xffec => Key::RGui,
+		0xffff => Key::Delete,
+		_ => return None,
+	};
+	Some(key)
+}
+
+/// The key for the letter `index` places after A.
+fn letter(index: u32) -> Option<Key> {
+	const LETTERS: [Key; 26] = [
+		Key::A,
+		Key::B,
+		Key::C,
+		Key::D,
+		Key::E,
+		Key::F,
+		Key::G,
+		Key::H,
+		Key::I,
+		Key::J,
+		Key::K,
+		Key::L,
+		Key::M,
+		Key::N,
+		Key::O,
+		Key::P,
+		Key::Q,
+		Key::R,
+		Key::S,
+		Key::T,
+		Key::U,
+		Key::V,
+		Key::W,
+		Key::X,
+		Key::Y,
+		Key::Z,
+	];
+	LETTERS.get(index as usize).copied()
+}
+
+/// The tiles inside `area` where `new` is different from `old`, which must
+/// be the same size. Each tile is clipped to `area`.
+pub fn changed_tiles(old: &Image, new: &Image, area: Rect) -> Vec<Rect> {
+	let mut result = Vec::new();
+	let right = (area.x + area.width).min(new.width);
+	let bottom = (area.y + area.height).min(new.height);
+	for tile_y in (area.y..bottom).step_by(TILE) {
+		for tile_x in (area.x..right).step_by(TILE) {
+			let tile = Rect {
+				x: tile_x,
+				y: tile_y,
+				width: TILE.min(right - tile_x),
+				height: TILE.min(bottom - tile_y),
+			};
+			let differs = (tile.y..tile.y + tile.height).any(|y| {
+				let start = (y * new.width + tile.x) * 4;
+				let end = start + tile.width * 4;
+				old.pixels[start..end] != new.pixels[start..end]
+			});
+			if differs {
+				result.push(tile);
+			}
+		}
+	}
+	result
+}
+
+/// Make `image` `width` by `height`, cutting off the right and bottom or
+/// filling them in with black.
+fn fit(image: &Image, width: usize, height: usize) -> Image {
+	if image.width == width && image.height == height {
+		return image.clone();
+	}
+	let mut result = Image::new(width, height);
+	let copy_width = image.width.min(width) * 4;
+	for y in 0..image.height.min(height) {
+		let from = y * image.width * 4;
+		let to = y * width * 4;
+		result.pixels[to..to + copy_width].copy_from_slice(&image.pixels[from..from + copy_width]);
+	}
+	result
+}
+
+/// An image that won't match anything we draw, for when we don't know what
+/// the viewer has.
+fn unknown(width: usize, height: usize) -> Image {
+	Image {
+		width,
+		height,
+		pixels: vec![0; width * height * 4],
+	}
+}
+
+/// An error for a viewer that sent us something we don't understand.
+fn bad_data(message: String) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl PixelFormat {
+	/// What we offer: 32-bit little-endian, with eight bits each of red,
+	/// green and blue.
+	pub const DEFAULT: PixelFormat = PixelFormat {
+		bits_per_pixel: 32,
+		big_endian: false,
+		red_max: 255,
+		green_max: 255,
+		blue_max: 255,
+		red_shift: 16,
+		green_shift: 8,
+		blue_shift: 0,
+	};
+
+	/// Read a pixel format as it comes in a SetPixelFormat message.
+	pub fn from_bytes(data: &[u8; 16]) -> Result<PixelFormat, String> {
+		let format = PixelFormat {
+			bits_per_pixel: data[0],
+			big_endian: data[2] != 0,
+			red_max: u16::from_be_bytes([data[4], data[5]]),
+			green_max: u16::from_be_bytes([data[6], data[7]]),
+			blue_max: u16::from_be_bytes([data[8], data[9]]),
+			red_shift: data[10],
+			green_shift: data[11],
+			blue_shift: data[12],
+		};
+		if data[3] == 0 {
+			return Err("the viewer wants a colour map, and we only do true colour".into());
+		}
+		if !matches!(format.bits_per_pixel, 8 | 16 | 32) {
+			return Err(format!("{} bits per pixel", format.bits_per_pixel));
+		}
+		Ok(format)
+	}
+
+	/// The pixel format as it goes in the ServerInit message.
+	pub fn to_bytes(&self) -> [u8; 16] {
+		let depth = if self.bits_per_pixel == 32 {
+			24
+		} else {
+			self.bits_per_pixel
+		};
+		let [red_hi, red_lo] = self.red_max.to_be_bytes();
+		let [green_hi, green_lo] = self.green_max.to_be_bytes();
+		let [blue_hi, blue_lo] = self.blue_max.to_be_bytes();
+		[
+			self.bits_per_pixel,
+			depth,
+			u8::from(self.big_endian),
+			1,
+			red_hi,
+			red_lo,
+			green_hi,
+			green_lo,
+			blue_hi,
+			blue_lo,
+			self.red_shift,
+			self.green_shift,
+			self.blue_shift,
+			0,
+			0,
+			0,
+		]
+	}
+
+	/// Add the pixels in `rect` of `image` to `out`, in this format.
+	pub fn encode(&self, image: &Image, rect: Rect, out: &mut Vec<u8>) {
+		let scale = |value: u8, max: u16| (u32::from(value) * u32::from(max) + 127) / 255;
+		let bytes = usize::from(self.bits_per_pixel / 8);
+		for y in rect.y..rect.y + rect.height {
+			for x in rect.x..rect.x + rect.width {
+				let [red, green, blue, _] = image.get(x, y);
+				let pixel = (scale(red, self.red_max) << self.red_shift)
+					| (scale(green, self.green_max) << self.green_shift)
+					| (scale(blue, self.blue_max) << self.blue_shift);
+				if self.big_endian {
+					out.extend_from_slice(&pixel.to_be_bytes()[4 - bytes..]);
+				} else {
+					out.extend_from_slice(&pixel.to_le_bytes()[..bytes]);
+				}
+			}
+		}
+	}
+}
+
+impl Session {
+	/// Handle messages and send updates until the viewer hangs up.
+	fn run(&mut self, messages: &mpsc::Receiver<std::io::Result<Message>>) -> std::io::Result<()> {
+		loop {
+			match messages.recv_timeout(POLL_INTERVAL) {
+				Ok(message) => self.handle(message?)?,
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+				Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+			}
+			self.update()?;
+		}
+	}
+
+	/// Deal with a message from the viewer.
+	fn handle(&mut self, message: Message) -> std::io::Result<()> {
+		match message {
+			Message::SetPixelFormat(format) => {
+				self.format = format;
+				// Anything we send now has to be in the new format
+				self.sent = unknown(self.sent.width, self.sent.height);
+			}
+			Message::SetEncodings(encodings) => {
+				self.desktop_size = encodings.contains(&ENCODING_DESKTOP_SIZE);
+			}
+			Message::UpdateRequest { incremental, rect } => {
+				// A full update beats an incremental one
+				let incremental = incremental && !matches!(self.pending, Some((false, _)));
+				self.pending = Some((incremental, rect));
+			}
+			Message::Key { down, keysym } => match convert_keysym(keysym) {
+				Some(key) => {
+					if let Err(e) = window::gui_request(GuiRequest::SetKey(key, down)) {
+						log::warn!("Can't send a key from the VNC viewer: {}", e);
+					}
+				}
+				None => log::debug!("VNC keysym {:#x} has no key", keysym),
+			},
+			Message::Ignored => {}
+		}
+		Ok(())
+	}
+
+	/// If the viewer is waiting for an update and we have one, send it.
+	fn update(&mut self) -> std::io::Result<()> {
+		let Some((incremental, mut area)) = self.pending else {
+			return Ok(());
+		};
+		let mut screen = render::render_screen();
+		let mut out = vec![0, 0, 0, 0];
+		let mut resized = false;
+		if (screen.width, screen.height) != (self.sent.width, self.sent.height) {
+			if self.desktop_size {
+				// Tell the viewer the new size, then send all of it
+				out.extend_from_slice(&[0, 0, 0, 0]);
+				out.extend_from_slice(&(screen.width as u16).to_be_bytes());
+				out.extend_from_slice(&(screen.height as u16).to_be_bytes());
+				out.extend_from_slice(&ENCODING_DESKTOP_SIZE.to_be_bytes());
+				resized = true;
+				self.sent = unknown(screen.width, screen.height);
+				area = Rect {
+					x: 0,
+					y: 0,
+					width: screen.width,
+					height: screen.height,
+				};
+			} else {
+				screen = fit(&screen, self.sent.width, self.sent.height);
+			}
+		}
+		let changed = if incremental {
+			changed_tiles(&self.sent, &screen, area)
+		} else {
+			let width = (area.x + area.width)
+				.min(screen.width)
+				.saturating_sub(area.x);
+			let height = (area.y + area.height)
+				.min(screen.height)
+				.saturating_sub(area.y);
+			vec![Rect {
+				x: area.x,
+				y: area.y,
+				width,
+				height,
+			}]
+		};
+		if incremental && changed.is_empty() && !resized {
+			// Nothing new yet, so keep it waiting
+			return Ok(());
+		}
+		for rect in &changed {
+			out.extend_from_slice(&(rect.x as u16).to_be_bytes());
+			out.extend_from_slice(&(rect.y as u16).to_be_bytes());
+			out.extend_from_slice(&(rect.width as u16).to_be_bytes());
+			out.extend_from_slice(&(rect.height as u16).to_be_bytes());
+			out.extend_from_slice(&ENCODING_RAW.to_be_bytes());
+			self.format.encode(&screen, *rect, &mut out);
+			for y in rect.y..rect.y + rect.height {
+				let start = (y * screen.width + rect.x) * 4;
+				let end = start + rect.width * 4;
+				self.sent.pixels[start..end].copy_from_slice(&screen.pixels[start..end]);
+			}
+		}
+		let count = (changed.len() + usize::from(resized)) as u16;
+		out[2..4].copy_from_slice(&count.to_be_bytes());
+		self.stream.write_all(&out)?;
+		self.pending = None;
+		Ok(())
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,203 @@
+//! # Watchdog for a hung OS
+//!
+//! Every BIOS function calls [`feed`] on the way in, which notes the time. With
+//! `--watchdog`, a thread keeps an eye on that time, and if the OS goes too
+//! long (in host time) without calling the BIOS, it decides the OS has hung.
+//! The window shows a banner while the OS is unresponsive, and then we either
+//! keep waiting, reset the OS, or exit, depending on `--watchdog-action`.
+//!
+//! We can't interrupt the OS thread, so a reset happens the next time the OS
+//! calls the BIOS. An OS stuck in a loop that never calls us will never reset,
+//! so use `exit` for unattended runs.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// What to do when the OS hangs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Action {
+	/// Keep waiting, in case it recovers
+	Wait,
+	/// Reset the OS the next time it calls the BIOS
+	Reset,
+	/// Shut down with a non-zero exit code
+	Exit,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// When we started, so we can keep times in an atomic.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// When the OS last called the BIOS, in nanoseconds since [`EPOCH`].
+static LAST_CALL_NS: AtomicU64 = AtomicU64::new(0);
+
+/// How long the OS can go without calling the BIOS, if we're watching.
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Set when the OS should be reset on its next BIOS call.
+static RESET_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Set once the watchdog has decided the OS had hung, even if it recovered.
+static FIRED: AtomicBool = AtomicBool::new(false);
+
+/// How many times the watchdog has decided the OS has hung.
+static TIMES_FIRED: AtomicU64 = AtomicU64::new(0);
+
+/// How many times the watchdog has reset the OS.
+static RESETS: AtomicU64 = AtomicU64::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The OS has called the BIOS.
+///
+/// Call this from the OS thread only - never from the GUI thread.
+pub fn feed() {
+	LAST_CALL_NS.store(now_ns(), Ordering::Relaxed);
+	if RESET_PENDING.swap(false, Ordering::Relaxed) {
+		log::warn!("Watchdog: resetting the OS");
+		crate::reset_os();
+	}
+}
+
+/// How many times the watchdog has decided the OS has hung, and how many
+/// times it has reset it.
+pub fn counts() -> (u64, u64) {
+	(
+		TIMES_FIRED.load(Ordering::Relaxed),
+		RESETS.load(Ordering::Relaxed),
+	)
+}
+
+/// Reset the OS the next time it calls the BIOS.
+pub fn request_reset() {
+	RESET_PENDING.store(true, Ordering::Relaxed);
+}
+
+/// Start watching the OS.
+pub fn start(timeout: Duration, action: Action) {
+	feed_from_host();
+	TIMEOUT.set(timeout).expect("watchdog to only start once");
+	std::thread::spawn(move || {
+		let mut fired = false;
+		loop {
+			std::thread::sleep((timeout / 4).max(Duration::from_millis(10)));
+			if crate::pause::is_paused()
+				|| crate::throttle::is_sleeping()
+				|| crate::crash::is_crashed()
+			{
+				// The OS can't call us while it's paused, throttled or
+				// waiting to be reset after a panic, which is fine
+				feed_from_host();
+			}
+			let hung = is_unresponsive();
+			if hung && !fired {
+				FIRED.store(true, Ordering::Relaxed);
+				TIMES_FIRED.fetch_add(1, Ordering::Relaxed);
+				log::warn!(
+					"Watchdog: the OS hasn't called the BIOS for {:?}",
+					since_last_call()
+				);
+				crate::remote::notify(crate::remote::Event::Watchdog(since_last_call()));
+				match action {
+					Action::Wait => {}
+					Action::Reset => {
+						RESETS.fetch_add(1, Ordering::Relaxed);
+						request_reset();
+					}
+					Action::Exit => crate::shutdown::shutdown(crate::shutdown::ExitCode::Watchdog),
+				}
+			} else if !hung && fired {
+				log::info!("Watchdog: the OS is responding again");
+			}
+			fired = hung;
+		}
+	});
+}
+
+/// Has the OS gone too long without calling the BIOS?
+pub fn is_unresponsive() -> bool {
+	TIMEOUT
+		.get()
+		.is_some_and(|timeout| since_last_call() > *timeout)
+}
+
+/// Has the watchdog ever decided the OS had hung?
+pub fn has_fired() -> bool {
+	FIRED.load(Ordering::Relaxed)
+}
+
+/// How long it is since the OS last called the BIOS.
+pub fn since_last_call() -> Duration {
+	let ns = now_ns().saturating_sub(LAST_CALL_NS.load(Ordering::Relaxed));
+	Duration::from_nanos(ns)
+}
+
+/// Parse a `--watchdog` timeout, like `10s`, `500ms` or `2m`. A plain number
+/// is in seconds.
+pub fn parse_timeout(text: &str) -> Result<Duration, String> {
+	let text = text.trim();
+	let split = text
+		.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+		.unwrap_or(text.len());
+	let (number, unit) = text.split_at(split);
+	let bad = || format!("{:?} is not a timeout (try 10s or 500ms)", text);
+	let number: f64 = number.parse().map_err(|_| bad())?;
+	let seconds = match unit {
+		"" | "s" => number,
+		"ms" => number / 1000.0,
+		"m" => number * 60.0,
+		_ => return Err(bad()),
+	};
+	match Duration::try_from_secs_f64(seconds) {
+		Ok(timeout) if !timeout.is_zero() => Ok(timeout),
+		_ => Err(bad()),
+	}
+}
+
+/// Pretend the OS just called us.
+fn feed_from_host() {
+	LAST_CALL_NS.store(now_ns(), Ordering::Relaxed);
+}
+
+/// The host's clock, in nanoseconds since [`EPOCH`].
+fn now_ns() -> u64 {
+	let elapsed = EPOCH.get_or_init(Instant::now).elapsed();
+	u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
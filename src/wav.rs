@@ -0,0 +1,151 @@
+//! # WAV file support for the Neotron Desktop BIOS
+//!
+//! Just enough of the RIFF/WAVE format to read uncompressed 8-bit and 16-bit
+//! PCM files.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A decoded WAV file.
+pub struct Wav {
+	/// Samples per second
+	pub sample_rate_hz: u32,
+	/// How many channels there are (1 or 2)
+	pub channels: usize,
+	/// The samples, interleaved if there is more than one channel
+	pub samples: Vec<f32>,
+}
+
+/// Things that can go wrong reading a WAV file.
+#[derive(Debug)]
+pub enum Error {
+	/// We couldn't read the file
+	Io(std::io::Error),
+	/// The file isn't a RIFF/WAVE file, or is truncated
+	NotWav,
+	/// The file is a WAV file, but not one we can play
+	Unsupported(String),
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Wav {
+	/// Load a WAV file from disk.
+	pub fn load(path: &Path) -> Result<Wav, Error> {
+		let data = std::fs::read(path).map_err(Error::Io)?;
+		Self::parse(&data)
+	}
+
+	/// Decode a WAV file held in memory.
+	pub fn parse(data: &[u8]) -> Result<Wav, Error> {
+		if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+			return Err(Error::NotWav);
+		}
+		let mut format: Option<(u16, usize, u32, u16)> = None;
+		let mut chunks = &data[12..];
+		while chunks.len() >= 8 {
+			let id = &chunks[0..4];
+			let len = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+			let body = chunks.get(8..8 + len).ok_or(Error::NotWav)?;
+			match id {
+				b"fmt " => {
+					if body.len() < 16 {
+						return Err(Error::NotWav);
+					}
+					let tag = u16::from_le_bytes([body[0], body[1]]);
+					let channels = usize::from(u16::from_le_bytes([body[2], body[3]]));
+					let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+					let bits = u16::from_le_bytes([body[14], body[15]]);
+					format = Some((tag, channels, rate, bits));
+				}
+				b"data" => {
+					let Some((tag, channels, sample_rate_hz, bits)) = format else {
+						return Err(Error::NotWav);
+					};
+					if tag != 1 {
+						return Err(Error::Unsupported(format!("compression type {}", tag)));
+					}
+					if !(1..=2).contains(&channels) {
+						return Err(Error::Unsupported(format!("{} channels", channels)));
+					}
+					let samples = match bits {
+						// 8-bit WAV samples are unsigned
+						8 => body
+							.iter()
+							.map(|b| (f32::from(*b) - 128.0) / 128.0)
+							.collect(),
+						16 => body
+							.chunks_exact(2)
+							.map(|pair| f32::from(i16::from_le_bytes([pair[0], pair[1]])) / 32768.0)
+							.collect(),
+						_ => return Err(Error::Unsupported(format!("{}-bit samples", bits))),
+					};
+					return Ok(Wav {
+						sample_rate_hz,
+						channels,
+						samples,
+					});
+				}
+				_ => {
+					// Skip anything we don't understand
+				}
+			}
+			// Chunks are padded to an even length
+			let skip = (8 + len + 1) & !1;
+			chunks = chunks.get(skip..).unwrap_or_default();
+		}
+		Err(Error::NotWav)
+	}
+
+	/// How many sample frames (one sample for every channel) there are.
+	pub fn num_frames(&self) -> usize {
+		self.samples.len() / self.channels
+	}
+
+	/// Get one sample frame.
+	pub fn frame(&self, idx: usize) -> &[f32] {
+		&self.samples[idx * self.channels..(idx + 1) * self.channels]
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "{}", e),
+			Error::NotWav => write!(f, "not a valid WAV file"),
+			Error::Unsupported(what) => write!(f, "unsupported WAV file ({})", what),
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
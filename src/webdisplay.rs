@@ -0,0 +1,641 @@
+//! # Browser display
+//!
+//! With `--web-display=127.0.0.1:8090`, we serve a web page that shows the
+//! screen and sends key presses back, so anyone with a browser can see (and
+//! type into) the OS without installing anything. The page talks to us over a
+//! WebSocket at `/ws`.
+//!
+//! We send the screen as binary messages, each holding the size of the screen
+//! and some RGBA tiles - the whole screen at first, then only the 16x16
+//! tiles that changed, as found by [`vnc::changed_tiles`]. The page sends
+//! `{"type":"ack"}` when it has drawn each one, and we don't send another
+//! until it has, so on a slow link we send fewer updates rather than a
+//! growing backlog of them. Keys come back as `{"type":"key","keysym":..,
+//! "down":..}`, using X keysyms so they go through the same mapping as VNC.
+//!
+//! One browser can watch at a time. The page tells any others to try later.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::json::{self, Value};
+use crate::render::{self, Image};
+use crate::vnc::{self, Rect};
+use crate::window::{self, GuiRequest};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A message from the browser, once we've put the pieces back together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+	/// A text message
+	Text(String),
+	/// A binary message
+	Binary(Vec<u8>),
+	/// Are you still there?
+	Ping(Vec<u8>),
+	/// Yes, I'm still here
+	Pong,
+	/// Goodbye
+	Close,
+}
+
+/// Reads WebSocket messages from the browser.
+pub struct FrameReader<R> {
+	reader: R,
+	/// The opcode and the pieces so far of a message sent in pieces
+	partial: Option<(u8, Vec<u8>)>,
+}
+
+/// One browser watching the screen.
+struct Session {
+	stream: TcpStream,
+	/// What the browser has on its canvas
+	sent: Option<Image>,
+	/// Whether the browser has drawn the last update we sent
+	ready: bool,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Set while a browser is connected to the WebSocket.
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Mixed into the browser's key to prove we speak WebSocket (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// How long we wait for a browser to send its HTTP request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The biggest HTTP request we'll read.
+const MAX_REQUEST: usize = 8192;
+
+/// The biggest message we'll take from the browser. Key presses are tiny.
+const MAX_MESSAGE: u64 = 64 * 1024;
+
+/// How often we look for changes to the screen.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// WebSocket opcodes
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// The page we serve at `/`.
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Neotron Desktop BIOS</title>
+<style>
+body { margin: 0; background: #222; color: #ccc; font-family: sans-serif; text-align: center; }
+canvas { margin-top: 1em; image-rendering: pixelated; outline: none; max-width: 100%; }
+</style>
+</head>
+<body>
+<canvas id="screen" width="640" height="480" tabindex="0"></canvas>
+<p id="status">Connecting...</p>
+<script>
+"use strict";
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const label = document.getElementById("status");
+const named = {
+	Backspace: 0xff08, Tab: 0xff09, Enter: 0xff0d, Pause: 0xff13, ScrollLock: 0xff14,
+	Escape: 0xff1b, Home: 0xff50, ArrowLeft: 0xff51, ArrowUp: 0xff52, ArrowRight: 0xff53,
+	ArrowDown: 0xff54, PageUp: 0xff55, PageDown: 0xff56, End: 0xff57, PrintScreen: 0xff61,
+	Insert: 0xff63, NumLock: 0xff7f, CapsLock: 0xffe5, Delete: 0xffff, AltGraph: 0xfe03,
+	ShiftLeft: 0xffe1, ShiftRight: 0xffe2, ControlLeft: 0xffe3, ControlRight: 0xffe4,
+	AltLeft: 0xffe9, AltRight: 0xffea, MetaLeft: 0xffeb, MetaRight: 0xffec,
+	NumpadEnter: 0xff8d, NumpadMultiply: 0xffaa, NumpadAdd: 0xffab, NumpadSubtract: 0xffad,
+	NumpadDecimal: 0xffae, NumpadDivide: 0xffaf,
+};
+function keysym(event) {
+	if (event.code in named) return named[event.code];
+	if (event.key in named) return named[event.key];
+	if (event.code.startsWith("Numpad") && event.code.length == 7) return 0xffb0 + Number(event.code[6]);
+	if (/^F([1-9]|1[0-2])$/.test(event.key)) return 0xffbd + Number(event.key.slice(1));
+	if (event.key.length == 1) return event.key.codePointAt(0);
+	return null;
+}
+const socket = new WebSocket((location.protocol == "https:" ? "wss://" : "ws://") + location.host + "/ws");
+socket.binaryType = "arraybuffer";
+socket.onopen = () => { label.textContent = "Connected - click the screen to type"; canvas.focus(); };
+socket.onclose = () => { if (label.textContent.startsWith("Connect")) label.textContent = "Disconnected"; };
+socket.onmessage = (message) => {
+	if (typeof message.data == "string") {
+		label.textContent = JSON.parse(message.data).message;
+		return;
+	}
+	const data = new DataView(message.data);
+	const width = data.getUint16(0), height = data.getUint16(2), count = data.getUint16(4);
+	if (canvas.width != width || canvas.height != height) {
+		canvas.width = width;
+		canvas.height = height;
+	}
+	let offset = 6;
+	for (let i = 0; i < count; i++) {
+		const x = data.getUint16(offset), y = data.getUint16(offset + 2);
+		const w = data.getUint16(offset + 4), h = data.getUint16(offset + 6);
+		offset += 8;
+		const pixels = new Uint8ClampedArray(message.data, offset, w * h * 4);
+		context.putImageData(new ImageData(pixels, w, h), x, y);
+		offset += w * h * 4;
+	}
+	socket.send('{"type":"ack"}');
+};
+function send(event, down) {
+	const code = keysym(event);
+	if (code === null || socket.readyState != WebSocket.OPEN) return;
+	event.preventDefault();
+	socket.send(JSON.stringify({ type: "key", keysym: code, down: down }));
+}
+canvas.addEventListener("keydown", (event) => send(event, true));
+canvas.addEventListener("keyup", (event) => send(event, false));
+</script>
+</body>
+</html>
+"#;
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Parse a `--web-display` address, like `127.0.0.1:8090` or just `8090`. A
+/// port with no host listens on `127.0.0.1`.
+pub fn parse_address(text: &str) -> Result<SocketAddr, String> {
+	let text = if text.parse::<u16>().is_ok() {
+		format!("127.0.0.1:{}", text)
+	} else {
+		text.to_string()
+	};
+	text.to_socket_addrs()
+		.map_err(|e| format!("bad address {:?}: {}", text, e))?
+		.next()
+		.ok_or_else(|| format!("{:?} doesn't have an address", text))
+}
+
+/// Start serving the page and the WebSocket, on a new thread.
+///
+/// Fails if we can't listen on the address, like if something else already
+/// is.
+pub fn start(address: SocketAddr) -> Result<(), String> {
+	if !address.ip().is_loopback() {
+		log::warn!(
+			"The web display on {} can be used by other machines, with no password",
+			address
+		);
+	}
+	let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+	log::info!("Web display on http://{}/", address);
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			match stream {
+				// Each on its own thread, so the page still loads while
+				// someone is watching
+				Ok(stream) => {
+					std::thread::spawn(move || {
+						if let Err(e) = serve(stream) {
+							log::debug!("Web display client went away: {}", e);
+						}
+					});
+				}
+				Err(e) => log::warn!("Web display failed to accept a client: {}", e),
+			}
+		}
+	});
+	Ok(())
+}
+
+/// The `Sec-WebSocket-Accept` we send back for a browser's
+/// `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+	let mut text = key.trim().as_bytes().to_vec();
+	text.extend_from_slice(WEBSOCKET_GUID.as_bytes());
+	base64(&sha1(&text))
+}
+
+/// Send one unfragmented WebSocket message to the browser.
+pub fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+	let mut header = vec![0x80 | opcode];
+	match payload.len() {
+		length @ 0..=125 => header.push(length as u8),
+		length @ 126..=0xFFFF => {
+			header.push(126);
+			header.extend_from_slice(&(length as u16).to_be_bytes());
+		}
+		length => {
+			header.push(127);
+			header.extend_from_slice(&(length as u64).to_be_bytes());
+		}
+	}
+	writer.write_all(&header)?;
+	writer.write_all(payload)?;
+	writer.flush()
+}
+
+/// Pack up some tiles of `screen` as one binary message: the screen's width,
+/// height and the number of tiles, then each tile's position and size
+/// followed by its RGBA pixels. Everything is big-endian.
+pub fn encode_update(screen: &Image, tiles: &[Rect]) -> Vec<u8> {
+	let mut out = Vec::new();
+	out.extend_from_slice(&(screen.width as u16).to_be_bytes());
+	out.extend_from_slice(&(screen.height as u16).to_be_bytes());
+	out.extend_from_slice(&(tiles.len() as u16).to_be_bytes());
+	for tile in tiles {
+		for value in [tile.x, tile.y, tile.width, tile.height] {
+			out.extend_from_slice(&(value as u16).to_be_bytes());
+		}
+		for y in tile.y..tile.y + tile.height {
+			let start = (y * screen.width + tile.x) * 4;
+			out.extend_from_slice(&screen.pixels[start..start + tile.width * 4]);
+		}
+	}
+	out
+}
+
+/// Answer one HTTP request, which might turn into a WebSocket.
+fn serve(mut stream: TcpStream) -> std::io::Result<()> {
+	stream.set_read_timeout(Some(READ_TIMEOUT))?;
+	let request = read_request(&mut stream)?;
+	let mut lines = request.lines();
+	let mut words = lines.next().unwrap_or("").split_whitespace();
+	let method = words.next().unwrap_or("");
+	let path = words.next().unwrap_or("").split('?').next().unwrap_or("");
+	let key = lines.find_map(|line| {
+		let (name, value) = line.split_once(':')?;
+		name.trim()
+			.eq_ignore_ascii_case("sec-websocket-key")
+			.then(|| value.trim().to_string())
+	});
+	match (method, path, key) {
+		("GET", "/ws", Some(key)) => {
+			if CONNECTED.swap(true, Ordering::SeqCst) {
+				// Say why in a way the page can show
+				stream.write_all(handshake(&key).as_bytes())?;
+				let busy = Value::object([(
+					"message",
+					Value::from("Someone else is watching - try again when they've gone"),
+				)]);
+				write_frame(&mut stream, OP_TEXT, busy.to_string().as_bytes())?;
+				return write_frame(&mut stream, OP_CLOSE, &[]);
+			}
+			stream.set_read_timeout(None)?;
+			stream.write_all(handshake(&key).as_bytes())?;
+			let result = watch(stream);
+			CONNECTED.store(false, Ordering::SeqCst);
+			result
+		}
+		("GET", "/", _) => respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE),
+		("GET", _, _) => respond(&mut stream, "404 Not Found", "text/plain", "Try /\n"),
+		_ => respond(
+			&mut stream,
+			"405 Method Not Allowed",
+			"text/plain",
+			"Only GET works here\n",
+		),
+	}
+}
+
+/// Read the request up to the blank line after the headers.
+fn read_request(stream: &mut TcpStream) -> std::io::Result<String> {
+	let mut request = Vec::new();
+	let mut buffer = [0u8; 1024];
+	while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+		let n = stream.read(&mut buffer)?;
+		if n == 0 {
+			break;
+		}
+		request.extend_from_slice(&buffer[..n]);
+		if request.len() > MAX_REQUEST {
+			return Err(bad_data("request too big".into()));
+		}
+	}
+	Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// Send a plain HTTP response.
+fn respond(stream: &mut TcpStream, status: &str, kind: &str, body: &str) -> std::io::Result<()> {
+	write!(
+		stream,
+		"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		kind,
+		body.len(),
+		body
+	)?;
+	stream.flush()
+}
+
+/// The response that turns an HTTP request into a WebSocket.
+fn handshake(key: &str) -> String {
+	format!(
+		"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+		accept_key(key)
+	)
+}
+
+/// Send the screen to a browser, and take keys from it, until it goes.
+fn watch(stream: TcpStream) -> std::io::Result<()> {
+	stream.set_nodelay(true)?;
+	let peer = stream
+		.peer_addr()
+		.map_or_else(|_| String::from("?"), |addr| addr.to_string());
+	log::info!("Web display client connected from {}", peer);
+
+	// Messages come in on a thread of their own, so we can look for changes
+	// to the screen while we wait for them
+	let (sender, frames) = mpsc::channel();
+	let mut reader = FrameReader::new(std::io::BufReader::new(stream.try_clone()?));
+	std::thread::spawn(move || loop {
+		let frame = reader.read_frame();
+		let stop = frame.is_err();
+		if sender.send(frame).is_err() || stop {
+			break;
+		}
+	});
+
+	let mut session = Session {
+		stream,
+		sent: None,
+		ready: true,
+	};
+	let result = session.run(&frames);
+	// Stop the reading thread too
+	let _ = session.stream.shutdown(Shutdown::Both);
+	log::info!("Web display client {} disconnected", peer);
+	match result {
+		Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(()),
+		result => result,
+	}
+}
+
+/// The SHA-1 hash of `data`. WebSocket needs it for the handshake; it isn't
+/// being used for anything secret.
+fn sha1(data: &[u8]) -> [u8; 20] {
+	let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+	let mut message = data.to_vec();
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0);
+	}
+	message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+	for block in message.chunks_exact(64) {
+		let mut w = [0u32; 80];
+		for (i, word) in block.chunks_exact(4).enumerate() {
+			w[i] = u32::from_be_bytes(word.try_into().unwrap());
+		}
+		for i in 16..80 {
+			w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+		}
+		let [mut a, mut b, mut c, mut d, mut e] = state;
+		for (i, word) in w.iter().enumerate() {
+			let (f, k) = match i {
+				0..=19 => ((b & c) | (!b & d), 0x5A827999),
+				20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+				40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+				_ => (b ^ c ^ d, 0xCA62C1D6),
+			};
+			let temp = a
+				.rotate_left(5)
+				.wrapping_add(f)
+				.wrapping_add(e)
+				.wrapping_add(k)
+				.wrapping_add(*word);
+			e = d;
+			d = c;
+			c = b.rotate_left(30);
+			b = a;
+			a = temp;
+		}
+		for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+			*value = value.wrapping_add(add);
+		}
+	}
+	let mut result = [0u8; 20];
+	for (bytes, value) in result.chunks_exact_mut(4).zip(state) {
+		bytes.copy_from_slice(&value.to_be_bytes());
+	}
+	result
+}
+
+/// `data` in base64, with padding.
+fn base64(data: &[u8]) -> String {
+	const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	let mut result = String::new();
+	for chunk in data.chunks(3) {
+		let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+			bits | u32::from(*byte) << (16 - 8 * i)
+		});
+		for i in 0..4 {
+			if i <= chunk.len() {
+				result.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+			} else {
+				result.push('=');
+			}
+		}
+	}
+	result
+}
+
+/// An error for a browser that sent us something we don't understand.
+fn bad_data(message: String) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl<R: Read> FrameReader<R> {
+	/// Read messages from `reader`.
+	pub fn new(reader: R) -> FrameReader<R> {
+		FrameReader {
+			reader,
+			partial: None,
+		}
+	}
+
+	/// Read the next message, which must be masked. Messages sent in pieces
+	/// are put back together, but we return any control messages that come
+	/// in between the pieces straight away.
+	pub fn read_frame(&mut self) -> std::io::Result<Frame> {
+		loop {
+			let mut header = [0u8; 2];
+			self.reader.read_exact(&mut header)?;
+			let fin = header[0] & 0x80 != 0;
+			let opcode = header[0] & 0x0F;
+			if header[1] & 0x80 == 0 {
+				return Err(bad_data("the browser must mask what it sends".into()));
+			}
+			let length = match header[1] & 0x7F {
+				126 => {
+					let mut length = [0u8; 2];
+					self.reader.read_exact(&mut length)?;
+					u64::from(u16::from_be_bytes(length))
+				}
+				127 => {
+					let mut length = [0u8; 8];
+					self.reader.read_exact(&mut length)?;
+					u64::from_be_bytes(length)
+				}
+				length => u64::from(length),
+			};
+			let so_far = self
+				.partial
+				.as_ref()
+				.map_or(0, |(_, data)| data.len() as u64);
+			if length.saturating_add(so_far) > MAX_MESSAGE {
+				return Err(bad_data(format!("a {} byte message is too big", length)));
+			}
+			let mut mask = [0u8; 4];
+			self.reader.read_exact(&mut mask)?;
+			let mut payload = vec![0u8; length as usize];
+			self.reader.read_exact(&mut payload)?;
+			for (i, byte) in payload.iter_mut().enumerate() {
+				*byte ^= mask[i % 4];
+			}
+			match (opcode, self.partial.as_mut()) {
+				(OP_CLOSE, _) => return Ok(Frame::Close),
+				(OP_PING, _) => return Ok(Frame::Ping(payload)),
+				(OP_PONG, _) => return Ok(Frame::Pong),
+				(OP_TEXT | OP_BINARY, None) => self.partial = Some((opcode, payload)),
+				(OP_CONTINUATION, Some((_, data))) => data.extend_from_slice(&payload),
+				_ => return Err(bad_data(format!("unexpected opcode {:#x}", opcode))),
+			}
+			if fin {
+				let (opcode, data) = self.partial.take().unwrap_or_default();
+				return if opcode == OP_TEXT {
+					String::from_utf8(data)
+						.map(Frame::Text)
+						.map_err(|_| bad_data("text that isn't UTF-8".into()))
+				} else {
+					Ok(Frame::Binary(data))
+				};
+			}
+		}
+	}
+}
+
+impl Session {
+	/// Handle messages and send updates until the browser goes.
+	fn run(&mut self, frames: &mpsc::Receiver<std::io::Result<Frame>>) -> std::io::Result<()> {
+		loop {
+			match frames.recv_timeout(POLL_INTERVAL) {
+				Ok(frame) => {
+					if !self.handle(frame?)? {
+						return Ok(());
+					}
+				}
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+				Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+			}
+			self.update()?;
+		}
+	}
+
+	/// Deal with a message from the browser. Returns `false` if it said
+	/// goodbye.
+	fn handle(&mut self, frame: Frame) -> std::io::Result<bool> {
+		match frame {
+			Frame::Text(text) => {
+				let message = match json::parse(&text) {
+					Ok(message) => message,
+					Err(e) => {
+						log::debug!("Web display sent bad JSON {:?}: {}", text, e);
+						return Ok(true);
+					}
+				};
+				match message.get("type").and_then(Value::as_str) {
+					Some("ack") => self.ready = true,
+					Some("key") => {
+						let keysym = message.get("keysym").and_then(Value::as_u64);
+						let down = message.get("down") == Some(&Value::Bool(true));
+						match keysym.and_then(|keysym| vnc::convert_keysym(keysym as u32)) {
+							Some(key) => {
+								if let Err(e) = window::gui_request(GuiRequest::SetKey(key, down)) {
+									log::warn!("Can't send a key from the web display: {}", e);
+								}
+							}
+							None => log::debug!("Web display keysym {:?} has no key", keysym),
+						}
+					}
+					_ => log::debug!("Web display sent {:?}, which we ignore", text),
+				}
+			}
+			Frame::Ping(payload) => write_frame(&mut self.stream, OP_PONG, &payload)?,
+			Frame::Close => {
+				write_frame(&mut self.stream, OP_CLOSE, &[])?;
+				return Ok(false);
+			}
+			Frame::Binary(_) | Frame::Pong => {}
+		}
+		Ok(true)
+	}
+
+	/// If the browser has drawn what we sent last and something has changed
+	/// since, send it.
+	fn update(&mut self) -> std::io::Result<()> {
+		if !self.ready {
+			return Ok(());
+		}
+		let screen = render::render_screen();
+		let whole = Rect {
+			x: 0,
+			y: 0,
+			width: screen.width,
+			height: screen.height,
+		};
+		let tiles = match &self.sent {
+			Some(sent) if (sent.width, sent.height) == (screen.width, screen.height) => {
+				vnc::changed_tiles(sent, &screen, whole)
+			}
+			// First time, or a new size, so send all of it
+			_ => vec![whole],
+		};
+		if tiles.is_empty() {
+			return Ok(());
+		}
+		write_frame(&mut self.stream, OP_BINARY, &encode_update(&screen, &tiles))?;
+		self.sent = Some(screen);
+		self.ready = false;
+		Ok(())
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
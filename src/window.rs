@@ -0,0 +1,700 @@
+//! # The Window
+//!
+//! We draw the framebuffer in a window, using PixEngine, and turn key presses
+//! in the window into HID events for the OS (unless they're host hotkeys).
+//!
+//! The window belongs to the GUI thread. Other threads (like the debug
+//! console) ask it to do things with a [`GuiRequest`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Mutex};
+
+use common::video::RGBColour;
+use log::{debug, info};
+use pix_engine::prelude::*;
+
+use neotron_common_bios as common;
+
+use crate::hid::AppEvent;
+use crate::video::{FRAMEBUFFER, PALETTE, VIDEO_MODE};
+use crate::{
+	apistats, attach, audio, bus, cellview, crash, font, heartbeat, hotkey, i2c, idle, loader,
+	panel, pause, profile, render, shutdown, smoke, timeline, videostats, watchdog,
+};
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+pub struct MyApp {
+	mode: common::video::Mode,
+	font8x16: Vec<TextureId>,
+	font8x8: Vec<TextureId>,
+	/// The hex digits for the cell view (see `cellview`)
+	digits: Vec<TextureId>,
+	sender: mpsc::Sender<AppEvent>,
+	reset: bool,
+	hotkeys: hotkey::Prefix,
+	audio: audio::Host,
+	/// The panel button held down with the mouse, if any
+	held_button: Option<u8>,
+	/// Whether we're showing that the OS has stopped responding
+	unresponsive: bool,
+	/// What the OS panicked with, if we're showing that it has
+	crashed: Option<String>,
+	/// The window title, before we add the emulator's status
+	title: String,
+	/// Whether we're showing that the OS is waiting for a debugger
+	waiting: bool,
+	/// Whether to show the BIOS call rates along the bottom
+	api_stats: bool,
+	/// Things other threads want the GUI thread to do
+	requests: mpsc::Receiver<GuiRequest>,
+	/// When the last frame started, so we can time the next one
+	last_frame: Option<std::time::Instant>,
+	/// The texture we draw bitmap modes with, and its width and height
+	bitmap: Option<(TextureId, u32, u32)>,
+}
+
+/// Something only the GUI thread can do, asked for by another thread (like the
+/// debug console).
+pub enum GuiRequest {
+	/// Pause or resume the emulation
+	SetPaused(bool),
+	/// Press these keys together, then let go of them
+	SendKeys(Vec<Key>),
+	/// Press (`true`) or let go of (`false`) one key, from a keyboard that
+	/// isn't the window's
+	SetKey(Key, bool),
+	/// Save what's in the window as a PNG, and say how it went
+	Screenshot(PathBuf, mpsc::Sender<Result<(), String>>),
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Scale the display to make it readable on a modern monitor
+pub const SCALE_FACTOR: f32 = 2.0;
+
+/// What we call our window.
+pub const WINDOW_TITLE: &str = "Neotron Desktop BIOS";
+
+/// Where to send requests for the GUI thread, once the window is open.
+static GUI_REQUESTS: Mutex<Option<mpsc::Sender<GuiRequest>>> = Mutex::new(None);
+
+/// How long we wait for the GUI thread to do something for us.
+const GUI_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Ask the GUI thread to do something. Fails if the window isn't open yet.
+pub fn gui_request(request: GuiRequest) -> Result<(), String> {
+	GUI_REQUESTS
+		.lock()
+		.unwrap()
+		.as_ref()
+		.ok_or("the window isn't open yet")?
+		.send(request)
+		.map_err(|_| "the window has closed".to_string())
+}
+
+/// Save what's in the window to a PNG file, waiting until it's done. Call
+/// this from any thread but the GUI thread.
+pub fn save_screenshot(path: &std::path::Path) -> Result<(), String> {
+	let (sender, receiver) = mpsc::channel();
+	gui_request(GuiRequest::Screenshot(path.to_path_buf(), sender))?;
+	receiver
+		.recv_timeout(GUI_TIMEOUT)
+		.map_err(|_| "the window didn't save it in time".to_string())?
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl MyApp {
+	const NUM_FG: usize = 16;
+
+	/// Get ready to show the OS in a window called `title`, sending its key
+	/// presses to `sender` (see [`crate::power_on`]).
+	///
+	/// Holding `hotkey_prefix` gives you the host hotkeys, and `api_stats`
+	/// shows the BIOS call rates along the bottom.
+	pub fn new(
+		title: String,
+		sender: mpsc::Sender<AppEvent>,
+		hotkey_prefix: Key,
+		api_stats: bool,
+		audio: audio::Host,
+	) -> MyApp {
+		let (request_sender, requests) = mpsc::channel();
+		GUI_REQUESTS.lock().unwrap().replace(request_sender);
+		MyApp {
+			// We know Mode 0 is a valid video mode
+			mode: unsafe { common::video::Mode::from_u8(0) },
+			font8x16: Vec::new(),
+			font8x8: Vec::new(),
+			digits: Vec::new(),
+			sender,
+			reset: true,
+			hotkeys: hotkey::Prefix::new(hotkey_prefix),
+			held_button: None,
+			unresponsive: false,
+			crashed: None,
+			title,
+			waiting: false,
+			api_stats,
+			requests,
+			last_frame: None,
+			bitmap: None,
+			audio,
+		}
+	}
+
+	/// Generate an RGBA texture for each glyph, in each foreground colour.
+	///
+	/// We have 256 glyphs, in each of 16 colours, so this is expensive and
+	/// slow. But it makes rendering text acceptably fast.
+	fn render_font(
+		font: &font::Font,
+		texture_buffer: &mut Vec<TextureId>,
+		s: &mut PixState,
+	) -> PixResult<()> {
+		let mut slot = 0;
+		for glyph in 0..=255 {
+			for palette_entry in PALETTE.iter().take(Self::NUM_FG) {
+				let fg = RGBColour::from_packed(palette_entry.load(Ordering::Relaxed));
+				debug!(
+					"Drawing glyph {} from font {} in colour {:06x}",
+					glyph,
+					font.name,
+					fg.as_packed()
+				);
+				let texture_id = if texture_buffer.len() > slot {
+					texture_buffer[slot]
+				} else {
+					let id = s.create_texture(8, font.height as u32, PixelFormat::Rgba)?;
+					texture_buffer.push(id);
+					id
+				};
+				slot += 1;
+				s.set_texture_target(texture_id)?;
+				s.background(Color::TRANSPARENT);
+				s.clear()?;
+				s.stroke(rgb!(fg.red(), fg.green(), fg.blue(), 255));
+				for font_y in 0..(font.height as i32) {
+					let mut font_line =
+						font.data[((glyph as usize) * font.height) + font_y as usize];
+					for font_x in 0..8i32 {
+						if (font_line & 0x80) != 0 {
+							s.point(Point::new([font_x, font_y]))?;
+						};
+						font_line <<= 1;
+					}
+				}
+				s.clear_texture_target();
+			}
+		}
+		videostats::record_uploads(slot);
+		Ok(())
+	}
+
+	/// Generate an RGBA texture for each glyph, in each foreground colour, in
+	/// each font.
+	fn render_glyphs(&mut self, s: &mut PixState) -> PixResult<()> {
+		Self::render_font(&font::font16::FONT, &mut self.font8x16, s)?;
+		Self::render_font(&font::font8::FONT, &mut self.font8x8, s)?;
+		Ok(())
+	}
+
+	/// Draw the text framebuffer, returning how many cells we drew.
+	fn render_text(
+		&self,
+		font: &[pix_engine::texture::TextureId],
+		font_height: u16,
+		s: &mut PixState,
+	) -> PixResult<usize> {
+		let num_cols = self.mode.text_width().unwrap();
+		let num_rows = self.mode.text_height().unwrap();
+		let mut bg_idx = 0;
+		let mut bg_rgb = {
+			let bg = RGBColour::from_packed(PALETTE[usize::from(bg_idx)].load(Ordering::Relaxed));
+			rgb!(bg.red(), bg.green(), bg.blue())
+		};
+		s.stroke(None);
+		// FRAMEBUFFER is an num_cols x num_rows size array of (u8_glyph, u8_attr).
+		for row in 0..num_rows {
+			let y = row * font_height;
+			for col in 0..num_cols {
+				let cell_no = (row * num_cols) + col;
+				let byte_offset = usize::from(cell_no) * 2;
+				let x = col * 8;
+				let glyph = FRAMEBUFFER.get_at(byte_offset);
+				let attr = common::video::Attr(FRAMEBUFFER.get_at(byte_offset + 1));
+				let fg_idx = attr.fg().make_ffi_safe().0;
+				let new_bg_idx = attr.bg().make_ffi_safe().0;
+				if new_bg_idx != bg_idx {
+					bg_idx = new_bg_idx;
+					let bg = RGBColour::from_packed(
+						PALETTE[usize::from(bg_idx)].load(Ordering::Relaxed),
+					);
+					bg_rgb = rgb!(bg.red(), bg.green(), bg.blue());
+				}
+				let glyph_box = rect!(i32::from(x), i32::from(y), 8i32, font_height as i32,);
+				s.fill(bg_rgb);
+				s.rect(glyph_box)?;
+				let slot = (usize::from(glyph) * Self::NUM_FG) + usize::from(fg_idx);
+				s.texture(font[slot], None, Some(glyph_box))?;
+			}
+		}
+		Ok(usize::from(num_rows) * usize::from(num_cols))
+	}
+
+	/// Draw a bitmap framebuffer, returning how many pixels we drew.
+	///
+	/// We decode it the same way as a screenshot, then hand the whole picture
+	/// to SDL as one streaming texture, rather than drawing it a point at a
+	/// time.
+	fn render_bitmap(&mut self, s: &mut PixState) -> PixResult<usize> {
+		let image = render::render_mode(self.mode);
+		let size = (image.width as u32, image.height as u32);
+		let texture = match self.bitmap {
+			Some((texture, width, height)) if (width, height) == size => texture,
+			old => {
+				// First time, or the mode changed size
+				if let Some((texture, _, _)) = old {
+					s.delete_texture(texture)?;
+				}
+				let texture = s.create_texture(size.0, size.1, PixelFormat::Rgba)?;
+				self.bitmap = Some((texture, size.0, size.1));
+				texture
+			}
+		};
+		s.update_texture(texture, None, &image.pixels, image.width * 4)?;
+		s.texture(texture, None, rect![0, 0, size.0 as i32, size.1 as i32])?;
+		Ok(image.width * image.height)
+	}
+
+	/// Either pass key events on to the OS, or perform a host action.
+	fn handle_key_outcome(&mut self, s: &mut PixState, outcome: hotkey::Outcome) -> PixResult<()> {
+		match outcome {
+			hotkey::Outcome::Forward(events) => {
+				for ev in events {
+					self.sender.send(ev).unwrap();
+				}
+				idle::wake();
+			}
+			hotkey::Outcome::Action(hotkey::Action::ToggleFullscreen) => {
+				info!("Toggling full-screen");
+				s.toggle_fullscreen()?;
+			}
+			hotkey::Outcome::Action(hotkey::Action::ToggleMute) => {
+				let muted = audio::toggle_mute();
+				info!("Audio {}", if muted { "muted" } else { "un-muted" });
+				self.update_title(s)?;
+			}
+			hotkey::Outcome::Action(hotkey::Action::Pause) => {
+				self.set_paused(s, !pause::is_paused())?;
+			}
+			hotkey::Outcome::Action(hotkey::Action::Reset) => crate::request_reset(),
+			hotkey::Outcome::Action(hotkey::Action::Snapshot) => {
+				if let Some(path) = crate::SNAPSHOT_PATH.get() {
+					crate::spawn_snapshot(path.clone());
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Pause or resume the emulation, including the audio.
+	fn set_paused(&mut self, s: &mut PixState, paused: bool) -> PixResult<()> {
+		info!("Emulation {}", if paused { "paused" } else { "resumed" });
+		pause::set_paused(paused);
+		self.audio.set_paused(paused);
+		self.update_title(s)
+	}
+
+	/// Do whatever other threads have asked us to.
+	fn handle_requests(&mut self, s: &mut PixState) -> PixResult<()> {
+		while let Ok(request) = self.requests.try_recv() {
+			match request {
+				GuiRequest::SetPaused(paused) => self.set_paused(s, paused)?,
+				GuiRequest::SendKeys(keys) => {
+					// These go straight to the OS, even if they're hotkeys
+					for key in &keys {
+						self.sender.send(AppEvent::KeyDown(*key)).unwrap();
+					}
+					for key in keys.iter().rev() {
+						self.sender.send(AppEvent::KeyUp(*key)).unwrap();
+					}
+					idle::wake();
+				}
+				GuiRequest::SetKey(key, down) => {
+					let event = if down {
+						AppEvent::KeyDown(key)
+					} else {
+						AppEvent::KeyUp(key)
+					};
+					self.sender.send(event).unwrap();
+					idle::wake();
+				}
+				GuiRequest::Screenshot(path, reply) => {
+					// Screenshots show what the OS drew, not the cell view
+					if cellview::is_enabled() {
+						match self.mode.format() {
+							common::video::Format::Text8x16 => {
+								self.render_text(&self.font8x16, 16, s)?;
+							}
+							common::video::Format::Text8x8 => {
+								self.render_text(&self.font8x8, 8, s)?;
+							}
+							_ => {}
+						}
+						panel::draw(s, i32::from(self.mode.horizontal_pixels()))?;
+					}
+					let result = s
+						.save_canvas(None, &path)
+						.map_err(|e| format!("{}: {}", path.display(), e));
+					if result.is_ok() {
+						info!("Saved screenshot to {}", path.display());
+					}
+					let _ = reply.send(result);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Put the emulator's status in the window title.
+	fn update_title(&self, s: &mut PixState) -> PixResult<()> {
+		let mut title = self.title.clone();
+		if pause::is_paused() {
+			title.push_str(" [Paused]");
+		}
+		if audio::is_muted() {
+			title.push_str(" [Muted]");
+		}
+		if self.unresponsive {
+			title.push_str(" [Not Responding]");
+		}
+		if self.crashed.is_some() {
+			title.push_str(" [Crashed]");
+		}
+		if self.waiting {
+			title.push_str(" [Waiting for Debugger]");
+		}
+		s.set_title(title)
+	}
+
+	/// Draw some lines of white text on a red background, across the top of
+	/// the screen. Lines that don't fit are cut short.
+	fn draw_banner(&self, s: &mut PixState, lines: &[String]) -> PixResult<()> {
+		let width = i32::from(self.mode.horizontal_pixels());
+		s.stroke(None);
+		s.fill(rgb!(160, 0, 0, 240));
+		s.rect(rect![0, 0, width, (lines.len() as i32 + 1) * 16])?;
+		for (row, line) in lines.iter().enumerate() {
+			self.draw_line(s, 8 + (row as i32 * 16), line)?;
+		}
+		Ok(())
+	}
+
+	/// Draw a line of white text on a dark background, along the bottom of
+	/// the screen.
+	fn draw_status_line(&self, s: &mut PixState, line: &str) -> PixResult<()> {
+		let width = i32::from(self.mode.horizontal_pixels());
+		let y = i32::from(self.mode.vertical_lines()) - 16;
+		s.stroke(None);
+		s.fill(rgb!(0, 0, 0, 192));
+		s.rect(rect![0, y, width, 16])?;
+		self.draw_line(s, y, line)
+	}
+
+	/// Draw a line of white text, indented by one character, cut short if it
+	/// doesn't fit.
+	fn draw_line(&self, s: &mut PixState, y: i32, line: &str) -> PixResult<()> {
+		const WHITE: usize = 15;
+		let width = i32::from(self.mode.horizontal_pixels());
+		let max_chars = (width / 8 - 2) as usize;
+		for (col, ch) in line.chars().take(max_chars).enumerate() {
+			let glyph = if ch.is_ascii() {
+				ch as usize
+			} else {
+				usize::from(b'?')
+			};
+			let glyph_box = rect!(8 + (col as i32 * 8), y, 8, 16);
+			s.texture(
+				self.font8x16[(glyph * Self::NUM_FG) + WHITE],
+				None,
+				Some(glyph_box),
+			)?;
+		}
+		Ok(())
+	}
+
+	/// The name of the SDL renderer drawing our window, like `opengl`.
+	///
+	/// We ask SDL directly because pix-engine doesn't expose this.
+	fn renderer_name(s: &PixState) -> Option<String> {
+		use sdl2::sys;
+		unsafe {
+			let window = sys::SDL_GetWindowFromID(*s.window_id());
+			if window.is_null() {
+				return None;
+			}
+			let renderer = sys::SDL_GetRenderer(window);
+			if renderer.is_null() {
+				return None;
+			}
+			let mut info = std::mem::zeroed::<sys::SDL_RendererInfo>();
+			if sys::SDL_GetRendererInfo(renderer, &mut info) != 0 || info.name.is_null() {
+				return None;
+			}
+			Some(
+				std::ffi::CStr::from_ptr(info.name)
+					.to_string_lossy()
+					.into_owned(),
+			)
+		}
+	}
+}
+
+impl PixEngine for MyApp {
+	/// Perform application initialisation.
+	fn on_start(&mut self, s: &mut PixState) -> PixResult<()> {
+		self.render_glyphs(s)?;
+		self.digits = cellview::render_digits(s)?;
+		if let Some(name) = Self::renderer_name(s) {
+			videostats::set_renderer(name);
+		}
+		self.audio.start(s);
+		smoke::start();
+		// Let the rest of the OS start now
+		self.sender.send(AppEvent::Started).unwrap();
+		Ok(())
+	}
+
+	/// Stop the OS, tidy up and exit.
+	fn on_stop(&mut self, _s: &mut PixState) -> PixResult<()> {
+		shutdown::shutdown(shutdown::ExitCode::WindowClosed)
+	}
+
+	/// Called when the window changes.
+	///
+	/// We remember where the window was moved to in the profile, if we have
+	/// one, so it opens there next time.
+	fn on_window_event(
+		&mut self,
+		_s: &mut PixState,
+		_window_id: WindowId,
+		event: WindowEvent,
+	) -> PixResult<()> {
+		if let WindowEvent::Moved(x, y) = event {
+			profile::save_window_position(x, y);
+		}
+		Ok(())
+	}
+
+	/// Called whenever the app has an event to process.
+	///
+	/// We send key up and key down events into a queue for the OS to process
+	/// later, unless they are host hotkeys.
+	fn on_event(&mut self, s: &mut PixState, event: &Event) -> PixResult<bool> {
+		match event {
+			Event::KeyUp {
+				key: Some(key),
+				keymod: _,
+				repeat: _,
+			} => {
+				let outcome = self.hotkeys.key_up(*key);
+				self.handle_key_outcome(s, outcome)?;
+				Ok(true)
+			}
+			Event::KeyDown {
+				key: Some(key),
+				keymod: _,
+				repeat: _,
+			} => {
+				let outcome = self.hotkeys.key_down(*key);
+				self.handle_key_outcome(s, outcome)?;
+				Ok(true)
+			}
+			Event::MouseDown {
+				button: Mouse::Left,
+				x,
+				y,
+			} => {
+				let width = i32::from(self.mode.horizontal_pixels());
+				match panel::control_at(*x, *y, width) {
+					Some(panel::Control::Button(button)) => {
+						let _ = i2c::set_button(button, true);
+						self.held_button = Some(button);
+					}
+					Some(panel::Control::Switch(line)) => {
+						let _ = bus::toggle_input(line);
+					}
+					None => return Ok(false),
+				}
+				Ok(true)
+			}
+			Event::MouseUp {
+				button: Mouse::Left,
+				..
+			} => {
+				let Some(button) = self.held_button.take() else {
+					return Ok(false);
+				};
+				let _ = i2c::set_button(button, false);
+				Ok(true)
+			}
+			Event::Window {
+				win_event: WindowEvent::Moved(_, _),
+				..
+			} => {
+				// need to reset the scale when the window is moved?
+				self.reset = true;
+				Ok(true)
+			}
+			_ => {
+				debug!("Didn't know about {:?}", event);
+				Ok(false)
+			}
+		}
+	}
+
+	/// Called in a tight-loop to update the application.
+	///
+	/// We convert the contents of `FRAMEBUFFER` into pixels on the canvas.
+	fn on_update(&mut self, s: &mut PixState) -> PixResult<()> {
+		let _span = timeline::span("frame", "video");
+		heartbeat::frame();
+		smoke::frame();
+		let frame_start = std::time::Instant::now();
+		let interval = self
+			.last_frame
+			.replace(frame_start)
+			.map_or(std::time::Duration::ZERO, |last| frame_start - last);
+		// Another frame, which the OS might be waiting for
+		idle::wake();
+		self.audio.service(s);
+
+		let mode_value = VIDEO_MODE.load(Ordering::Relaxed);
+		let new_mode = unsafe { common::video::Mode::from_u8(mode_value) };
+		if new_mode != self.mode || self.reset {
+			info!("New video mode detected, or needs reset");
+			self.reset = false;
+			self.mode = new_mode;
+			let width = (new_mode.horizontal_pixels() as f32) * SCALE_FACTOR;
+			let height = (new_mode.vertical_lines() as f32) * SCALE_FACTOR;
+			info!("Window set to {} x {}", width, height);
+			s.set_window_dimensions((width as u32, height as u32))?;
+			s.scale(SCALE_FACTOR, SCALE_FACTOR)?;
+			s.background(rgb!(0, 0, 0));
+			s.clear()?;
+		}
+
+		s.blend_mode(BlendMode::Blend);
+
+		let decode_span = timeline::span("decode", "video");
+		let decode_start = std::time::Instant::now();
+		let (mut cells, mut pixels) = (0, 0);
+		match self.mode.format() {
+			common::video::Format::Text8x16 if cellview::is_enabled() => {
+				cells = cellview::draw(s, &self.digits, self.mode, 16)?
+			}
+			common::video::Format::Text8x8 if cellview::is_enabled() => {
+				cells = cellview::draw(s, &self.digits, self.mode, 8)?
+			}
+			common::video::Format::Text8x16 => cells = self.render_text(&self.font8x16, 16, s)?,
+			common::video::Format::Text8x8 => cells = self.render_text(&self.font8x8, 8, s)?,
+			common::video::Format::Chunky1
+			| common::video::Format::Chunky2
+			| common::video::Format::Chunky4
+			| common::video::Format::Chunky8 => pixels = self.render_bitmap(s)?,
+			_ => {
+				// Unknown mode - do nothing
+			}
+		}
+		let decode = decode_start.elapsed();
+		drop(decode_span);
+
+		panel::draw(s, i32::from(self.mode.horizontal_pixels()))?;
+
+		self.handle_requests(s)?;
+
+		if self.api_stats {
+			self.draw_status_line(s, &apistats::summary())?;
+		}
+
+		if watchdog::is_unresponsive() != self.unresponsive {
+			self.unresponsive = !self.unresponsive;
+			self.update_title(s)?;
+		}
+		if self.unresponsive {
+			// A red bar across the top of the screen
+			s.stroke(None);
+			s.fill(rgb!(192, 0, 0, 224));
+			s.rect(rect![0, 0, i32::from(self.mode.horizontal_pixels()), 4])?;
+		}
+
+		if attach::is_waiting() != self.waiting {
+			self.waiting = !self.waiting;
+			self.update_title(s)?;
+		}
+
+		let crashed = crash::crash_message();
+		if crashed != self.crashed {
+			self.crashed = crashed;
+			self.update_title(s)?;
+		}
+		if let Some(message) = &self.crashed {
+			let mut lines = vec![message.clone()];
+			if let Some(version) = loader::os_version() {
+				lines.push(format!("OS: {}", version));
+			}
+			lines.push("Press the hotkey prefix and R to reset.".to_string());
+			self.draw_banner(s, &lines)?;
+		}
+
+		videostats::record(videostats::Frame {
+			interval,
+			update: frame_start.elapsed(),
+			decode,
+			cells,
+			pixels,
+		});
+
+		Ok(())
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
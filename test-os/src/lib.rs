@@ -0,0 +1,226 @@
+//! # Neotron Test OS
+//!
+//! A tiny stand-in for Neotron OS, for testing the Desktop BIOS.
+//!
+//! It makes a fixed sequence of BIOS calls - it writes to the screen, reads
+//! the clocks, reads, writes and verifies some disk blocks, and collects any
+//! key presses - and records what happened in a [`Results`]. Then it puts
+//! `DONE` on the screen and idles until the power goes off.
+//!
+//! Boot it like any other OS with `--os target/debug/libneotron_test_os.so`,
+//! or link it in and call [`os_main`] yourself, as the integration tests do.
+//! They then collect the results with [`wait_for_results`].
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use neotron_common_bios as common;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// What the test OS found out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Results {
+	/// What `api_version_get` said
+	pub api_version: u32,
+	/// What `bios_version_get` said
+	pub bios_version: String,
+	/// What `time_clock_get` said, in seconds since the Neotron epoch
+	pub clock_secs: u32,
+	/// Whether `time_ticks_get` didn't go backwards between two calls
+	pub ticks_monotonic: bool,
+	/// How many blocks Block Device 0 has, if there is one
+	pub disk_blocks: Option<u64>,
+	/// The start of block 0, if we could read it
+	pub block0: Option<[u8; 16]>,
+	/// Whether writing the [`pattern`] to [`PATTERN_BLOCK`] worked
+	pub write_ok: bool,
+	/// Whether `block_verify` agreed that it's there
+	pub verify_ok: bool,
+	/// The HID events we got
+	pub hid_events: Vec<common::hid::HidEvent>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// What we put on the top line of the screen.
+pub const BANNER: &str = "Neotron Test OS";
+
+/// What we put on the second line of the screen when we've finished.
+pub const DONE: &str = "DONE";
+
+/// The attribute we write our text with: yellow on blue.
+pub const ATTR: u8 = 0x1E;
+
+/// Which block we write the [`pattern`] to.
+pub const PATTERN_BLOCK: u64 = 1;
+
+/// How big a block is.
+const BLOCK_SIZE: usize = 512;
+
+/// How long we wait for a key to be pressed and let go, in ticks at most.
+const HID_WAIT_TICKS: u32 = 2000;
+
+/// Each column in a text mode takes this many bytes: the glyph, then the
+/// attribute.
+const BYTES_PER_CELL: usize = 2;
+
+/// Columns in Mode 0.
+const COLUMNS: usize = 80;
+
+/// What we found out last time, once we've finished.
+static RESULTS: Mutex<Option<Results>> = Mutex::new(None);
+
+/// Tells [`wait_for_results`] that there are some.
+static FINISHED: Condvar = Condvar::new();
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Which BIOS API we were built for.
+#[no_mangle]
+pub extern "C" fn os_api_version() -> common::Version {
+	common::API_VERSION
+}
+
+/// Our name and version.
+#[no_mangle]
+pub extern "C" fn os_version_get() -> common::FfiString<'static> {
+	common::FfiString::new("Neotron Test OS\0")
+}
+
+/// Run the tests against `api`, record what happened, and idle forever.
+#[no_mangle]
+pub extern "C" fn os_main(api: &'static common::Api) -> ! {
+	RESULTS.lock().unwrap().take();
+	let fb = (api.video_get_framebuffer)() as *mut u8;
+	write_line(fb, 0, BANNER);
+	let bios_version = (api.bios_version_get)();
+	let ticks_before = (api.time_ticks_get)();
+	let clock = (api.time_clock_get)();
+	let ticks_after = (api.time_ticks_get)();
+	let mut results = Results {
+		api_version: (api.api_version_get)().0,
+		bios_version: bios_version.as_str().trim_end_matches('\0').to_string(),
+		clock_secs: clock.secs,
+		ticks_monotonic: ticks_after.0 >= ticks_before.0,
+		..Default::default()
+	};
+	test_disk(api, &mut results);
+	test_hid(api, &mut results);
+	write_line(fb, 1, DONE);
+	*RESULTS.lock().unwrap() = Some(results);
+	FINISHED.notify_all();
+	loop {
+		(api.power_idle)();
+	}
+}
+
+/// Wait up to `timeout` for the test OS to finish, and take what it found.
+pub fn wait_for_results(timeout: Duration) -> Option<Results> {
+	let results = RESULTS.lock().unwrap();
+	let (mut results, _) = FINISHED
+		.wait_timeout_while(results, timeout, |results| results.is_none())
+		.unwrap();
+	results.take()
+}
+
+/// What we write to byte `index` of [`PATTERN_BLOCK`].
+pub fn pattern(index: usize) -> u8 {
+	(index % 251) as u8
+}
+
+/// Read block 0, then write the pattern to [`PATTERN_BLOCK`] and check it's
+/// there.
+fn test_disk(api: &common::Api, results: &mut Results) {
+	let common::FfiOption::Some(info) = (api.block_dev_get_info)(0) else {
+		return;
+	};
+	results.disk_blocks = Some(info.num_blocks);
+	let mut block = [0u8; BLOCK_SIZE];
+	let read = (api.block_read)(
+		0,
+		common::block_dev::BlockIdx(0),
+		1,
+		common::FfiBuffer::new(&mut block),
+	);
+	if let common::ApiResult::Ok(()) = read {
+		let mut start = [0u8; 16];
+		start.copy_from_slice(&block[..16]);
+		results.block0 = Some(start);
+	}
+	let pattern: Vec<u8> = (0..BLOCK_SIZE).map(pattern).collect();
+	let write = (api.block_write)(
+		0,
+		common::block_dev::BlockIdx(PATTERN_BLOCK),
+		1,
+		common::FfiByteSlice::new(&pattern),
+	);
+	results.write_ok = matches!(write, common::ApiResult::Ok(()));
+	let verify = (api.block_verify)(
+		0,
+		common::block_dev::BlockIdx(PATTERN_BLOCK),
+		1,
+		common::FfiByteSlice::new(&pattern),
+	);
+	results.verify_ok = matches!(verify, common::ApiResult::Ok(()));
+}
+
+/// Collect HID events until a key is let go, or we give up waiting.
+fn test_hid(api: &common::Api, results: &mut Results) {
+	for _ in 0..HID_WAIT_TICKS {
+		while let common::ApiResult::Ok(common::FfiOption::Some(event)) = (api.hid_get_event)() {
+			let released = matches!(event, common::hid::HidEvent::KeyRelease(_));
+			results.hid_events.push(event);
+			if released {
+				return;
+			}
+		}
+		(api.power_idle)();
+	}
+}
+
+/// Write `text` at the start of `row` of the screen, leaving the colours
+/// alone.
+fn write_line(fb: *mut u8, row: usize, text: &str) {
+	for (col, byte) in text.bytes().enumerate() {
+		let offset = (row * COLUMNS + col) * BYTES_PER_CELL;
+		// Safety: the BIOS gave us a framebuffer big enough for Mode 0
+		unsafe {
+			fb.add(offset).write_volatile(byte);
+			fb.add(offset + 1).write_volatile(ATTR);
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,172 @@
+//! # Audio sample format tests
+//!
+//! Turning each of the OS's sample formats into floating point samples and
+//! back again.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use neotron_common_bios as common;
+use neotron_desktop_bios::audio::Format;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Every format the OS can pick.
+const FORMATS: [Format; 4] = [
+	Format::EightBitMono,
+	Format::EightBitStereo,
+	Format::SixteenBitMono,
+	Format::SixteenBitStereo,
+];
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn sizes() {
+	let sizes: Vec<_> = FORMATS
+		.iter()
+		.map(|f| (f.channels(), f.sample_size(), f.frame_size()))
+		.collect();
+	assert_eq!(sizes, [(1, 1, 1), (2, 1, 2), (1, 2, 2), (2, 2, 4)]);
+}
+
+#[test]
+fn eight_bit_mono() {
+	let format = Format::EightBitMono;
+	assert_eq!(decode(format, &[0x00]), [0.0]);
+	assert_eq!(decode(format, &[0x40]), [0.5]);
+	assert_eq!(decode(format, &[0x80]), [-1.0]);
+	assert_eq!(decode(format, &[0xFF]), [-1.0 / 128.0]);
+	assert_eq!(encode(format, &[0.0]), [0x00]);
+	assert_eq!(encode(format, &[1.0]), [0x7F]);
+	assert_eq!(encode(format, &[-1.0]), [0x81]);
+	// Too loud is clipped, not wrapped
+	assert_eq!(encode(format, &[3.0]), [0x7F]);
+	assert_eq!(encode(format, &[-3.0]), [0x81]);
+}
+
+#[test]
+fn eight_bit_stereo() {
+	let format = Format::EightBitStereo;
+	// Left, then right
+	assert_eq!(decode(format, &[0x40, 0xC0]), [0.5, -0.5]);
+	assert_eq!(encode(format, &[1.0, -1.0]), [0x7F, 0x81]);
+}
+
+#[test]
+fn sixteen_bit_mono() {
+	let format = Format::SixteenBitMono;
+	// Little-endian
+	assert_eq!(decode(format, &[0x00, 0x40]), [0.5]);
+	assert_eq!(decode(format, &[0x00, 0x80]), [-1.0]);
+	assert_eq!(decode(format, &[0x01, 0x00]), [1.0 / 32768.0]);
+	assert_eq!(encode(format, &[0.0]), [0x00, 0x00]);
+	assert_eq!(encode(format, &[1.0]), [0xFF, 0x7F]);
+	assert_eq!(encode(format, &[-1.0]), [0x01, 0x80]);
+	assert_eq!(encode(format, &[f32::INFINITY]), [0xFF, 0x7F]);
+}
+
+#[test]
+fn sixteen_bit_stereo() {
+	let format = Format::SixteenBitStereo;
+	assert_eq!(decode(format, &[0x00, 0x40, 0x00, 0xC0]), [0.5, -0.5]);
+	assert_eq!(encode(format, &[1.0, -1.0]), [0xFF, 0x7F, 0x01, 0x80]);
+}
+
+#[test]
+fn round_trips() {
+	// Every 8-bit value, and a spread of 16-bit ones, come back as they went
+	// in (apart from the most negative, which we never make)
+	for format in FORMATS {
+		let values: Vec<i32> = match format.sample_size() {
+			1 => (-127..=127).collect(),
+			_ => (-32767..=32767).step_by(97).collect(),
+		};
+		for value in values {
+			let bytes: Vec<u8> = match format.sample_size() {
+				1 => vec![value as i8 as u8; format.channels()],
+				_ => (value as i16).to_le_bytes().repeat(format.channels()),
+			};
+			let back = encode(format, &decode(format, &bytes));
+			// Decoding divides by 128 (or 32768), encoding multiplies by 127
+			// (or 32767), so we can be out by one step
+			for (got, want) in back
+				.chunks_exact(format.sample_size())
+				.zip(bytes.chunks_exact(format.sample_size()))
+			{
+				let got = sample_value(got);
+				let want = sample_value(want);
+				assert!(
+					(got - want).abs() <= 1,
+					"{:?}: {} came back as {}",
+					format,
+					want,
+					got
+				);
+			}
+		}
+	}
+}
+
+#[test]
+fn ffi_formats() {
+	for format in FORMATS {
+		let ffi = common::audio::FfiSampleFormat::from(format);
+		assert_eq!(Format::try_from(ffi), Ok(format));
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Decode one frame.
+fn decode(format: Format, bytes: &[u8]) -> Vec<f32> {
+	let mut out = vec![0.0; format.channels()];
+	format.decode_frame(bytes, &mut out);
+	out
+}
+
+/// Encode one frame.
+fn encode(format: Format, samples: &[f32]) -> Vec<u8> {
+	let mut out = vec![0; format.frame_size()];
+	format.encode_frame(samples, &mut out);
+	out
+}
+
+/// The value of one raw sample.
+fn sample_value(bytes: &[u8]) -> i32 {
+	match bytes {
+		[byte] => i32::from(*byte as i8),
+		[low, high] => i32::from(i16::from_le_bytes([*low, *high])),
+		_ => unreachable!(),
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,229 @@
+//! # Bad pointers from the OS
+//!
+//! We call every BIOS function that takes a buffer or a slice from the OS
+//! with random broken ones - null pointers with something in them, and
+//! lengths that run off the end of memory - and check each call fails
+//! cleanly rather than crashing. A palette can also be misaligned, or claim
+//! more than 256 entries.
+//!
+//! A crash in a BIOS function aborts the whole test run, so we print the
+//! seed first: run again with `NEOTRON_FUZZ_SEED=<seed>` to repeat a failure.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::io::Write;
+
+use neotron_desktop_bios::{block, hardware, video};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use common::block_dev::BlockIdx;
+use common::video::RGBColour;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many times we call each function.
+const ITERATIONS: usize = 200;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn bad_pointers_are_refused() {
+	let seed = std::env::var("NEOTRON_FUZZ_SEED")
+		.ok()
+		.and_then(|seed| seed.parse().ok())
+		.unwrap_or_else(rand::random);
+	let _ = writeln!(
+		std::io::stderr(),
+		"Fuzzing with bad pointers with NEOTRON_FUZZ_SEED={}",
+		seed
+	);
+	let mut rng = StdRng::seed_from_u64(seed);
+	let _turn = common::take_turn();
+	let _keys = neotron_desktop_bios::power_on(None);
+	let mut good = [0u8; 16];
+
+	for iteration in 0..ITERATIONS {
+		let (data, len) = bad_raw(&mut rng, good.as_mut_ptr());
+		let context = format!(
+			"seed {} iteration {}: {:p} of {} bytes",
+			seed, iteration, data, len
+		);
+		let refused = |name: &str, result: Result<(), common::Error>| {
+			assert_eq!(
+				result,
+				Err(common::Error::DeviceError),
+				"{} {}",
+				name,
+				context
+			);
+		};
+
+		refused(
+			"configuration_get",
+			into(hardware::configuration_get(buffer(data, len))).map(drop),
+		);
+		refused(
+			"configuration_set",
+			into(hardware::configuration_set(slice(data, len))),
+		);
+		// Safety: these are the calls under test
+		refused(
+			"audio_output_data",
+			into(unsafe { hardware::audio_output_data(slice(data, len)) }).map(drop),
+		);
+		refused(
+			"audio_input_data",
+			into(unsafe { hardware::audio_input_data(buffer(data, len)) }).map(drop),
+		);
+		refused(
+			"bus_exchange",
+			into(hardware::bus_exchange(buffer(data, len))),
+		);
+		for block_call in [block::block_write, block::block_verify] {
+			refused(
+				"block_write/verify",
+				into(block_call(0, BlockIdx(0), 1, slice(data, len))),
+			);
+		}
+		refused(
+			"block_read",
+			into(block::block_read(0, BlockIdx(0), 1, buffer(data, len))),
+		);
+
+		// The bad one can be any of the three
+		let fine = good.as_mut_ptr();
+		for which in 0..3 {
+			let pick = |n: usize| if n == which { (data, len) } else { (fine, 4) };
+			let ((tx, tx_len), (tx2, tx2_len), (rx, rx_len)) = (pick(0), pick(1), pick(2));
+			refused(
+				"i2c_write_read",
+				into(hardware::i2c_write_read(
+					0,
+					0x50,
+					slice(tx, tx_len),
+					slice(tx2, tx2_len),
+					buffer(rx, rx_len),
+				)),
+			);
+			refused(
+				"bus_write_read",
+				into(hardware::bus_write_read(
+					slice(tx, tx_len),
+					slice(tx2, tx2_len),
+					buffer(rx, rx_len),
+				)),
+			);
+		}
+	}
+}
+
+#[test]
+fn bad_palettes_are_refused() {
+	let _turn = common::take_turn();
+	let _keys = neotron_desktop_bios::power_on(None);
+	let colours: Vec<RGBColour> = (0..=255u8).map(|i| RGBColour::from_rgb(i, 0, 0)).collect();
+	let before = palette();
+
+	// Safety: these are the calls under test
+	unsafe {
+		video::video_set_whole_palette(std::ptr::null(), 16);
+		video::video_set_whole_palette(colours.as_ptr().cast::<u8>().add(1).cast(), 16);
+		video::video_set_whole_palette(usize::MAX as *const RGBColour, 16);
+	}
+	let after = palette();
+	assert_eq!(before, after, "a bad palette changed the colours");
+
+	// Too many entries - we just use the first 256
+	// Safety: as above
+	unsafe { video::video_set_whole_palette(colours.as_ptr(), usize::MAX) };
+	let after = palette();
+	let expected: Vec<_> = colours.iter().map(|c| Some(c.as_packed())).collect();
+	assert_eq!(after, expected);
+	video::reset_video();
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// A pointer and length that can't be a real buffer.
+fn bad_raw(rng: &mut StdRng, good: *mut u8) -> (*mut u8, usize) {
+	match rng.gen_range(0..4) {
+		// Null, with something in it
+		0 => (std::ptr::null_mut(), rng.gen_range(1..=usize::MAX)),
+		// Bigger than memory can be
+		1 => (good, rng.gen_range(isize::MAX as usize + 1..=usize::MAX)),
+		// Off the end of memory
+		2 => {
+			let address = usize::MAX - rng.gen_range(0..4096);
+			(
+				address as *mut u8,
+				rng.gen_range(4097..=isize::MAX as usize),
+			)
+		}
+		// Just past the end of memory
+		_ => (usize::MAX as *mut u8, rng.gen_range(1..=16)),
+	}
+}
+
+/// An OS byte slice with any pointer and length.
+fn slice(data: *const u8, len: usize) -> common::FfiByteSlice<'static> {
+	let mut slice = common::FfiByteSlice::new(&[]);
+	slice.data = data;
+	slice.data_len = len;
+	slice
+}
+
+/// An OS buffer with any pointer and length.
+fn buffer(data: *mut u8, len: usize) -> common::FfiBuffer<'static> {
+	let mut buffer = common::FfiBuffer::new(&mut []);
+	buffer.data = data;
+	buffer.data_len = len;
+	buffer
+}
+
+/// The whole palette, packed so we can compare it.
+fn palette() -> Vec<Option<u32>> {
+	(0..=255u8)
+		.map(|index| {
+			let colour: Option<RGBColour> = video::video_get_palette(index).into();
+			colour.map(|colour| colour.as_packed())
+		})
+		.collect()
+}
+
+/// An API result, as a Rust one.
+fn into<T>(result: common::ApiResult<T>) -> Result<T, common::Error> {
+	result.into()
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,252 @@
+//! # Fuzzing the block device API
+//!
+//! We throw random calls at `block_read`, `block_write` and `block_verify`
+//! (random devices, block numbers, block counts and buffer lengths), against
+//! a small temporary disk image, and keep our own copy of what the image
+//! should hold. After every call we check that the BIOS gave the answer the
+//! API promises, that it didn't touch the image outside the blocks it was
+//! asked for, and that the image hasn't changed size.
+//!
+//! A panic in a BIOS function aborts the whole test run (they're all
+//! `extern "C"`), so we print the seed first: run again with
+//! `NEOTRON_FUZZ_SEED=<seed>` to repeat a failure. `NEOTRON_FUZZ_ITERATIONS`
+//! changes how many calls we make.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::io::Write;
+
+use neotron_desktop_bios::block::{self, BLOCK_SIZE};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use common::block_dev::BlockIdx;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// One call to the block API.
+#[derive(Debug)]
+enum Call {
+	Read,
+	Write,
+	Verify,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How many blocks our disk image has.
+const DISK_BLOCKS: u64 = 8;
+
+/// How many calls we make, unless `NEOTRON_FUZZ_ITERATIONS` says otherwise.
+const DEFAULT_ITERATIONS: u64 = 2000;
+
+/// What we fill the rest of a read buffer with, to check the BIOS leaves it
+/// alone.
+const FILL: u8 = 0xA5;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn block_api_keeps_its_contract() {
+	let seed = env_number("NEOTRON_FUZZ_SEED").unwrap_or_else(rand::random);
+	let iterations = env_number("NEOTRON_FUZZ_ITERATIONS").unwrap_or(DEFAULT_ITERATIONS);
+	// Straight to stderr, not through the test harness, so we see it even if
+	// a panic aborts the process
+	let _ = writeln!(
+		std::io::stderr(),
+		"Fuzzing the block API with NEOTRON_FUZZ_SEED={}",
+		seed
+	);
+	let mut rng = StdRng::seed_from_u64(seed);
+
+	// What the disk image should hold
+	let mut model: Vec<u8> = (0..DISK_BLOCKS as usize * BLOCK_SIZE)
+		.map(|_| rng.gen())
+		.collect();
+	let disk = common::TempFile::with_contents("fuzz", "img", &model);
+	let file = std::fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open(disk.path())
+		.unwrap();
+	let _keys = neotron_desktop_bios::power_on(Some(file));
+
+	for iteration in 0..iterations {
+		let call = match rng.gen_range(0..3) {
+			0 => Call::Read,
+			1 => Call::Write,
+			_ => Call::Verify,
+		};
+		let dev_id = if rng.gen_bool(0.8) { 0 } else { rng.gen() };
+		let block_idx = match rng.gen_range(0..4) {
+			0 => rng.gen(),
+			1 => u64::MAX - rng.gen_range(0..4),
+			_ => rng.gen_range(0..DISK_BLOCKS + 4),
+		};
+		let num_blocks = if rng.gen_bool(0.8) {
+			rng.gen_range(0..=4)
+		} else {
+			rng.gen()
+		};
+		let wanted = usize::from(num_blocks) * BLOCK_SIZE;
+		let buffer_len = match rng.gen_range(0..4) {
+			0 => rng.gen_range(0..wanted + BLOCK_SIZE),
+			1 => wanted + rng.gen_range(1..BLOCK_SIZE),
+			_ => wanted,
+		};
+		let context = format!(
+			"seed {} iteration {}: {:?} dev_id {} block_idx {} num_blocks {} buffer_len {}",
+			seed, iteration, call, dev_id, block_idx, num_blocks, buffer_len
+		);
+
+		let expected = expected_result(dev_id, block_idx, num_blocks, buffer_len);
+		let range = expected.is_ok().then(|| {
+			let start = block_idx as usize * BLOCK_SIZE;
+			start..start + wanted
+		});
+		match call {
+			Call::Read => {
+				let mut buffer = vec![FILL; buffer_len];
+				let result: Result<(), common::Error> = block::block_read(
+					dev_id,
+					BlockIdx(block_idx),
+					num_blocks,
+					common::FfiBuffer::new(&mut buffer),
+				)
+				.into();
+				assert_eq!(result, expected, "{}", context);
+				let filled = match &range {
+					Some(range) => {
+						assert_eq!(&buffer[..wanted], &model[range.clone()], "{}", context);
+						wanted
+					}
+					None => 0,
+				};
+				assert!(
+					buffer[filled..].iter().all(|b| *b == FILL),
+					"{}: the BIOS wrote past the blocks it read",
+					context
+				);
+			}
+			Call::Write => {
+				let buffer: Vec<u8> = (0..buffer_len).map(|_| rng.gen()).collect();
+				let result: Result<(), common::Error> = block::block_write(
+					dev_id,
+					BlockIdx(block_idx),
+					num_blocks,
+					common::FfiByteSlice::new(&buffer),
+				)
+				.into();
+				assert_eq!(result, expected, "{}", context);
+				if let Some(range) = range {
+					model[range].copy_from_slice(&buffer[..wanted]);
+				}
+			}
+			Call::Verify => {
+				// Mostly what's on the disk, so the verify can pass, but
+				// sometimes with a byte changed
+				let mut buffer = vec![FILL; buffer_len];
+				let mut expected = expected;
+				if let Some(range) = &range {
+					buffer[..wanted].copy_from_slice(&model[range.clone()]);
+					if wanted > 0 && rng.gen_bool(0.3) {
+						buffer[rng.gen_range(0..wanted)] ^= 0x01;
+						expected = Err(common::Error::DeviceError);
+					}
+				}
+				let result: Result<(), common::Error> = block::block_verify(
+					dev_id,
+					BlockIdx(block_idx),
+					num_blocks,
+					common::FfiByteSlice::new(&buffer),
+				)
+				.into();
+				assert_eq!(result, expected, "{}", context);
+			}
+		}
+
+		let on_disk = std::fs::read(disk.path()).unwrap();
+		assert_eq!(
+			on_disk.len(),
+			model.len(),
+			"{}: the disk image changed size",
+			context
+		);
+		assert!(
+			on_disk == model,
+			"{}: the disk image doesn't hold what we wrote",
+			context
+		);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// What the API says a call should return, ignoring whether a verify
+/// matches.
+///
+/// The buffer is checked first (a short one is a `DeviceError`), then the
+/// device, then whether all the blocks are on the disk.
+fn expected_result(
+	dev_id: u8,
+	block_idx: u64,
+	num_blocks: u8,
+	buffer_len: usize,
+) -> Result<(), common::Error> {
+	if buffer_len < usize::from(num_blocks) * BLOCK_SIZE {
+		Err(common::Error::DeviceError)
+	} else if dev_id != 0 {
+		Err(common::Error::InvalidDevice)
+	} else if !matches!(
+		block_idx.checked_add(u64::from(num_blocks)),
+		Some(end) if end <= DISK_BLOCKS
+	) {
+		Err(common::Error::BlockOutOfBounds)
+	} else {
+		Ok(())
+	}
+}
+
+/// Read a number from the environment variable `name`, if it's set.
+fn env_number(name: &str) -> Option<u64> {
+	let value = std::env::var(name).ok()?;
+	Some(
+		value
+			.parse()
+			.unwrap_or_else(|_| panic!("{}={:?} is not a number", name, value)),
+	)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
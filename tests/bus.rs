@@ -0,0 +1,138 @@
+//! # Neotron Bus dispatch tests
+//!
+//! Each transfer goes to whichever peripheral the OS selected, and nowhere
+//! if it selected one we don't have.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use neotron_desktop_bios::{bus, hardware};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Our peripherals' IDs.
+const SLOT: u8 = 0;
+const LOOPBACK: u8 = 1;
+const FLASH: u8 = 2;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn dispatch() {
+	let flash = common::TempFile::new("bus-test", "bin");
+	let _keys = neotron_desktop_bios::power_on(None);
+	for spec in [
+		"slot".to_string(),
+		"loopback".to_string(),
+		format!("flash:{}:64KiB", flash.path().display()),
+	] {
+		bus::add_device(&bus::parse_device(&spec).unwrap()).unwrap();
+	}
+	for id in [SLOT, LOOPBACK, FLASH] {
+		let info: Option<common::bus::PeripheralInfo> = hardware::bus_get_info(id).into();
+		assert!(info.is_some(), "peripheral {}", id);
+	}
+
+	// An empty slot only ever reads back idle bytes
+	select(Some(SLOT));
+	assert_eq!(write_read(&[1, 2, 3], 3), Ok(vec![0xFF; 3]));
+	assert_eq!(exchange(&[1, 2, 3]), Ok(vec![0xFF; 3]));
+
+	// The loopback sends back what it was sent
+	select(Some(LOOPBACK));
+	assert_eq!(write_read(&[4, 5, 6], 3), Ok(vec![4, 5, 6]));
+	assert_eq!(exchange(&[7, 8]), Ok(vec![7, 8]));
+
+	// The flash chip answers a JEDEC ID command: Winbond, then 64 KiB
+	select(Some(FLASH));
+	assert_eq!(write_read(&[0x9F], 3), Ok(vec![0xEF, 0x40, 0x10]));
+	select(None);
+	select(Some(FLASH));
+	let id = exchange(&[0x9F, 0, 0, 0]).unwrap();
+	assert_eq!(&id[1..], &[0xEF, 0x40, 0x10]);
+
+	// Nothing selected, or a chip-select with nothing on it, goes nowhere
+	for unknown in [None, Some(3), Some(7), Some(255)] {
+		select(unknown);
+		assert_eq!(
+			write_read(&[1], 1),
+			Err(common::Error::InvalidDevice),
+			"{:?}",
+			unknown
+		);
+		assert_eq!(
+			exchange(&[1]),
+			Err(common::Error::InvalidDevice),
+			"{:?}",
+			unknown
+		);
+	}
+	for unknown in [3, 7, 255] {
+		let info: Option<common::bus::PeripheralInfo> = hardware::bus_get_info(unknown).into();
+		assert!(info.is_none(), "peripheral {}", unknown);
+	}
+
+	// And the loopback didn't hear any of that
+	select(Some(LOOPBACK));
+	assert_eq!(write_read(&[], 5), Ok(vec![4, 5, 6, 7, 8]));
+	select(None);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Select a peripheral, or nothing.
+fn select(id: Option<u8>) {
+	hardware::bus_select(id.into());
+}
+
+/// Send `tx` to the selected peripheral, then read `len` bytes.
+fn write_read(tx: &[u8], len: usize) -> Result<Vec<u8>, common::Error> {
+	let mut rx = vec![0; len];
+	let result: Result<(), common::Error> = hardware::bus_write_read(
+		common::FfiByteSlice::new(tx),
+		common::FfiByteSlice::new(&[]),
+		common::FfiBuffer::new(&mut rx),
+	)
+	.into();
+	result.map(|()| rx)
+}
+
+/// Exchange bytes with the selected peripheral.
+fn exchange(tx: &[u8]) -> Result<Vec<u8>, common::Error> {
+	let mut buffer = tx.to_vec();
+	let result: Result<(), common::Error> =
+		hardware::bus_exchange(common::FfiBuffer::new(&mut buffer)).into();
+	result.map(|()| buffer)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
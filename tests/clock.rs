@@ -0,0 +1,66 @@
+//! # Emulated clock tests
+//!
+//! Parsing `--time-scale`, and using the fastest and slowest scales.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::time::Duration;
+
+use neotron_desktop_bios::clock;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn scales() {
+	assert_eq!(clock::parse_scale("1"), Ok(1.0));
+	assert_eq!(clock::parse_scale(" 0.5 "), Ok(0.5));
+	assert_eq!(clock::parse_scale("0.001"), Ok(clock::MIN_SCALE));
+	assert_eq!(clock::parse_scale("1000"), Ok(clock::MAX_SCALE));
+	for bad in [
+		"0", "-1", "0.0009", "1000.1", "1e300", "1e-300", "inf", "NaN", "fast", "",
+	] {
+		assert!(clock::parse_scale(bad).is_err(), "{:?}", bad);
+	}
+	let error = clock::parse_scale("1e300").unwrap_err();
+	assert!(error.contains("from 0.001 to 1000"), "{}", error);
+}
+
+#[test]
+fn extreme_scales() {
+	// A day of emulated time, at either end of the range
+	let day = Duration::from_secs(24 * 60 * 60);
+	clock::set_scale(clock::MIN_SCALE);
+	assert_eq!(clock::host_duration(day), day * 1000);
+	let _ = clock::elapsed();
+	clock::set_scale(clock::MAX_SCALE);
+	assert_eq!(clock::host_duration(day), day / 1000);
+	let _ = clock::elapsed();
+	clock::set_scale(1.0);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
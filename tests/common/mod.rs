@@ -0,0 +1,112 @@
+//! # Shared test helpers
+//!
+//! Temporary files that tidy themselves up, and a lock so that tests which
+//! use the BIOS's global state take turns. Each test binary gets its own
+//! copy of this module, so tests in different binaries still run at the same
+//! time - they're in different processes.
+//!
+//! This also brings in everything from `neotron_common_bios`, so a test can
+//! say `common::Error` just like the BIOS does.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// Each test binary only uses some of these
+#![allow(dead_code, unused_imports)]
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+pub use neotron_common_bios::*;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// A file in the host's temporary directory, deleted when we're done.
+pub struct TempFile(PathBuf);
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The BIOS is global, so one test at a time.
+static TURN: Mutex<()> = Mutex::new(());
+
+/// Numbers our temporary files, so each one has a name of its own.
+static NEXT_FILE: AtomicU32 = AtomicU32::new(0);
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Wait until no other test in this binary is using the BIOS, and stop any
+/// from starting until the guard is dropped.
+///
+/// A test that panicked while it had its turn doesn't stop the others.
+pub fn take_turn() -> MutexGuard<'static, ()> {
+	TURN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl TempFile {
+	/// Pick a name for a file that doesn't exist yet, like
+	/// `neotron-<what>-<pid>-<n>.<extension>`.
+	pub fn new(what: &str, extension: &str) -> TempFile {
+		let path = std::env::temp_dir().join(format!(
+			"neotron-{}-{}-{}.{}",
+			what,
+			std::process::id(),
+			NEXT_FILE.fetch_add(1, Ordering::Relaxed),
+			extension
+		));
+		let _ = std::fs::remove_file(&path);
+		TempFile(path)
+	}
+
+	/// Make a file holding `contents`.
+	pub fn with_contents(what: &str, extension: &str, contents: &[u8]) -> TempFile {
+		let file = TempFile::new(what, extension);
+		std::fs::write(&file.0, contents).unwrap();
+		file
+	}
+
+	/// Where the file is.
+	pub fn path(&self) -> &Path {
+		&self.0
+	}
+}
+
+impl Drop for TempFile {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.0);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,221 @@
+//! # Environment variable tests
+//!
+//! We build small command lines of our own, give them `NEOTRON_BIOS_*`
+//! variables with `config::with_env`, and check what each option ends up as
+//! and where `config::settings` says it came from.
+//!
+//! The environment belongs to the whole test process, and the tests run in
+//! parallel, so each test uses options with names of its own.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use clap::{Arg, ArgAction, Command};
+
+use neotron_desktop_bios::config::{self, Setting, Source};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn env_names() {
+	assert_eq!(config::env_name("disk"), "NEOTRON_BIOS_DISK");
+	assert_eq!(
+		config::env_name("audio-latency"),
+		"NEOTRON_BIOS_AUDIO_LATENCY"
+	);
+}
+
+#[test]
+fn command_line_beats_environment() {
+	std::env::set_var("NEOTRON_BIOS_PRECEDENCE_DISK", "env.img");
+	let command = config::with_env(
+		Command::new("test").arg(Arg::new("precedence_disk").long("precedence-disk")),
+		&[],
+	);
+	let matches = command
+		.clone()
+		.get_matches_from(["test", "--precedence-disk", "cli.img"]);
+	assert_eq!(
+		matches.get_one::<String>("precedence_disk").unwrap(),
+		"cli.img"
+	);
+	assert_eq!(
+		config::settings(&command, &matches, &[], None),
+		[setting(
+			"--precedence-disk",
+			Some("cli.img"),
+			Source::CommandLine
+		)]
+	);
+}
+
+#[test]
+fn environment_beats_default() {
+	std::env::set_var("NEOTRON_BIOS_DEFAULTED_LATENCY", "50ms");
+	let command = config::with_env(
+		Command::new("test")
+			.arg(
+				Arg::new("defaulted_latency")
+					.long("defaulted-latency")
+					.default_value("20ms"),
+			)
+			.arg(
+				Arg::new("defaulted_volume")
+					.long("defaulted-volume")
+					.default_value("100"),
+			)
+			.arg(Arg::new("defaulted_disk").long("defaulted-disk")),
+		&[],
+	);
+	let matches = command.clone().get_matches_from(["test"]);
+	assert_eq!(
+		config::settings(&command, &matches, &[], None),
+		[
+			setting(
+				"--defaulted-latency",
+				Some("50ms"),
+				Source::Environment(String::from("NEOTRON_BIOS_DEFAULTED_LATENCY"))
+			),
+			setting("--defaulted-volume", Some("100"), Source::Default),
+			setting("--defaulted-disk", None, Source::Unset),
+		]
+	);
+}
+
+#[test]
+fn flags_from_environment() {
+	std::env::set_var("NEOTRON_BIOS_FLAG_HEADLESS", "true");
+	std::env::set_var("NEOTRON_BIOS_FLAG_WAIT", "false");
+	let command = config::with_env(
+		Command::new("test")
+			.arg(
+				Arg::new("flag_headless")
+					.long("flag-headless")
+					.action(ArgAction::SetTrue),
+			)
+			.arg(
+				Arg::new("flag_wait")
+					.long("flag-wait")
+					.action(ArgAction::SetTrue),
+			),
+		&[],
+	);
+	let matches = command.get_matches_from(["test"]);
+	assert!(matches.get_flag("flag_headless"));
+	assert!(!matches.get_flag("flag_wait"));
+}
+
+#[test]
+fn skipped_options_ignore_environment() {
+	std::env::set_var("NEOTRON_BIOS_SKIPPED_LIST", "true");
+	let command = config::with_env(
+		Command::new("test").arg(
+			Arg::new("skipped_list")
+				.long("skipped-list")
+				.action(ArgAction::SetTrue),
+		),
+		&["skipped-list"],
+	);
+	let matches = command.clone().get_matches_from(["test"]);
+	assert!(!matches.get_flag("skipped_list"));
+	// It's a thing to do, not a setting, so it isn't listed either
+	assert_eq!(config::settings(&command, &matches, &[], None), []);
+}
+
+#[test]
+fn numbered_variables() {
+	std::env::set_var("NEOTRON_BIOS_NUMBERED_DEVICE_0", "1:0x48:lm75");
+	std::env::set_var("NEOTRON_BIOS_NUMBERED_DEVICE_1", "1:0x20:pcf8574");
+	// There's no _2, so this one is ignored
+	std::env::set_var("NEOTRON_BIOS_NUMBERED_DEVICE_3", "1:0x21:pcf8574");
+	assert_eq!(
+		config::indexed("numbered-device"),
+		[
+			(
+				String::from("NEOTRON_BIOS_NUMBERED_DEVICE_0"),
+				String::from("1:0x48:lm75")
+			),
+			(
+				String::from("NEOTRON_BIOS_NUMBERED_DEVICE_1"),
+				String::from("1:0x20:pcf8574")
+			),
+		]
+	);
+	assert_eq!(config::indexed("numbered-nothing"), []);
+
+	let command = config::with_env(
+		Command::new("test").arg(
+			Arg::new("numbered_device")
+				.long("numbered-device")
+				.action(ArgAction::Append),
+		),
+		&["numbered-device"],
+	);
+	let matches = command.clone().get_matches_from(["test"]);
+	assert_eq!(
+		config::settings(&command, &matches, &["numbered-device"], None),
+		[
+			setting(
+				"--numbered-device",
+				Some("1:0x48:lm75"),
+				Source::Environment(String::from("NEOTRON_BIOS_NUMBERED_DEVICE_0"))
+			),
+			setting(
+				"--numbered-device",
+				Some("1:0x20:pcf8574"),
+				Source::Environment(String::from("NEOTRON_BIOS_NUMBERED_DEVICE_1"))
+			),
+		]
+	);
+	// The command line replaces all of them
+	let matches = command
+		.clone()
+		.get_matches_from(["test", "--numbered-device", "0:0x50:lm75"]);
+	assert_eq!(
+		config::settings(&command, &matches, &["numbered-device"], None),
+		[setting(
+			"--numbered-device",
+			Some("0:0x50:lm75"),
+			Source::CommandLine
+		)]
+	);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// What we expect `config::settings` to say about one option.
+fn setting(option: &str, value: Option<&str>, source: Source) -> Setting {
+	Setting {
+		option: option.to_string(),
+		value: value.map(str::to_string),
+		source,
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
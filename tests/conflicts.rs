@@ -0,0 +1,213 @@
+//! # Conflicting option tests
+//!
+//! One test for each rule in `neotron_desktop_bios::conflicts`, checking both
+//! that it catches what it should, and that it leaves alone the combinations
+//! that are fine.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::PathBuf;
+
+use neotron_desktop_bios::conflicts::{self, Conflict, Options};
+use neotron_desktop_bios::{audio, bus, i2c};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn defaults_are_fine() {
+	assert_eq!(conflicts::check(&options()), Vec::new());
+}
+
+#[test]
+fn two_outputs_in_one_file() {
+	let mut options = options();
+	options.trace_api_file = Some(PathBuf::from("trace.txt"));
+	options.trace_bus_file = Some(PathBuf::from("./trace.txt"));
+	assert_eq!(
+		flags(&conflicts::check(&options)),
+		[("--trace-api-file", "--trace-bus-file")]
+	);
+}
+
+#[test]
+fn output_over_an_input() {
+	let mut options = options();
+	options.snapshot_file = Some(PathBuf::from("disk.img"));
+	options.trace_timeline = Some(PathBuf::from("libneotron_os.so"));
+	let found = conflicts::check(&options);
+	assert_eq!(
+		flags(&found),
+		[("--disk", "--snapshot-file"), ("--trace-timeline", "--os")]
+	);
+	assert!(found[1]
+		.reason
+		.starts_with("--trace-timeline would write to libneotron_os.so"));
+}
+
+#[test]
+fn disk_shared_with_a_bus_device() {
+	let mut options = options();
+	options.bus_devices = vec![bus::parse_device("sdcard:disk.img").unwrap()];
+	assert_eq!(
+		flags(&conflicts::check(&options)),
+		[("--disk", "--bus-device")]
+	);
+}
+
+#[test]
+fn eeprom_files_shared() {
+	let mut options = options();
+	options.i2c_eeprom = Some(i2c::DeviceSpec {
+		bus: 0,
+		address: 0x50,
+		kind: i2c::DeviceKind::Eeprom(i2c::parse_eeprom("eeprom.bin:8KiB").unwrap()),
+	});
+	options.i2c_devices = vec![i2c::parse_device("1:0x50:eeprom:eeprom.bin:8KiB").unwrap()];
+	assert_eq!(
+		flags(&conflicts::check(&options)),
+		[("--i2c-eeprom", "--i2c-device")]
+	);
+}
+
+#[test]
+fn reading_a_file_twice_is_fine() {
+	let mut options = options();
+	options.os = vec![PathBuf::from("os.so"), PathBuf::from("./os.so")];
+	options.resume = Some(PathBuf::from("snapshot.neo"));
+	assert_eq!(conflicts::check(&options), Vec::new());
+}
+
+#[test]
+fn null_audio_with_host_devices() {
+	let mut options = options();
+	options.audio_backend = audio::Backend::Null;
+	options.audio_device = Some(String::from("USB DAC"));
+	options.audio_input_device = Some(String::from("USB Mic"));
+	assert_eq!(
+		flags(&conflicts::check(&options)),
+		[
+			("--audio=null", "--audio-device"),
+			("--audio=null", "--audio-input-device")
+		]
+	);
+	options.audio_backend = audio::Backend::Sdl;
+	assert_eq!(conflicts::check(&options), Vec::new());
+}
+
+#[test]
+fn isolate_hides_devices() {
+	let mut options = options();
+	options.isolate = true;
+	assert_eq!(conflicts::check(&options), Vec::new());
+	options.i2c_devices = vec![i2c::parse_device("1:0x48:lm75").unwrap()];
+	options.bus_devices = vec![bus::parse_device("loopback").unwrap()];
+	options.audio_input = Some(PathBuf::from("input.wav"));
+	assert_eq!(
+		flags(&conflicts::check(&options)),
+		[
+			("--isolate", "--i2c-device"),
+			("--isolate", "--bus-device"),
+			("--isolate", "--audio-input")
+		]
+	);
+}
+
+#[test]
+fn two_i2c_devices_at_one_address() {
+	let mut options = options();
+	options.i2c_devices = vec![
+		i2c::parse_device("1:0x48:lm75").unwrap(),
+		i2c::parse_device("0:0x48:lm75").unwrap(),
+		i2c::parse_device("1:0x48:pcf8574").unwrap(),
+	];
+	let found = conflicts::check(&options);
+	assert_eq!(flags(&found), [("--i2c-device", "--i2c-device")]);
+	assert_eq!(
+		found[0].to_string(),
+		"'--i2c-device' can't be used like this: they both put a device at 0x48 on I2C Bus 1"
+	);
+}
+
+#[test]
+fn too_many_bus_devices() {
+	let mut options = options();
+	options.bus_devices = vec![bus::parse_device("slot").unwrap(); bus::max_peripherals()];
+	assert_eq!(conflicts::check(&options), Vec::new());
+	options.bus_devices.push(bus::parse_device("slot").unwrap());
+	assert_eq!(
+		flags(&conflicts::check(&options)),
+		[("--bus-device", "--bus-device")]
+	);
+}
+
+#[test]
+fn message_names_both_options() {
+	let mut options = options();
+	options.audio_backend = audio::Backend::Null;
+	options.audio_device = Some(String::from("USB DAC"));
+	assert_eq!(
+		conflicts::check(&options)[0].to_string(),
+		"'--audio=null' can't be used with '--audio-device': the null backend doesn't use a host audio device"
+	);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// A typical command line, with a disk, an OS and the default files.
+fn options() -> Options {
+	Options {
+		disk: Some(PathBuf::from("disk.img")),
+		nvram: Some(PathBuf::from("nvram.bin")),
+		os: vec![PathBuf::from("libneotron_os.so")],
+		resume: None,
+		snapshot_file: Some(PathBuf::from("snapshot.neo")),
+		trace_api_file: None,
+		trace_bus_file: None,
+		trace_timeline: None,
+		audio_backend: audio::Backend::Sdl,
+		audio_device: None,
+		audio_input: None,
+		audio_input_device: None,
+		isolate: false,
+		i2c_eeprom: None,
+		i2c_devices: Vec::new(),
+		bus_devices: Vec::new(),
+	}
+}
+
+/// Just the two options in each conflict.
+fn flags(found: &[Conflict]) -> Vec<(&str, &str)> {
+	found
+		.iter()
+		.map(|conflict| (conflict.first.as_str(), conflict.second.as_str()))
+		.collect()
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,243 @@
+//! # Determinism tests
+//!
+//! We run the same scenario twice, each in a new process: the test OS boots
+//! on a virtual clock, with a scripted key press and a fresh disk image, and
+//! with every BIOS call traced. Both runs must end with the same screen, the
+//! same disk image and the same trace. If they don't, both traces are left
+//! in `target/tmp/determinism`, and we show where they first differ.
+//!
+//! Pointers in the trace are left out of the comparison, as the OS's memory
+//! is somewhere different every run.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use neotron_desktop_bios::hardware::HARDWARE;
+use neotron_desktop_bios::hid::{self, AppEvent};
+use neotron_desktop_bios::video::FRAMEBUFFER;
+use neotron_desktop_bios::{apitrace, clock, trace};
+use pix_engine::prelude::Key;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// How one run of the scenario ended.
+struct Outcome {
+	/// Where the run left its files
+	dir: PathBuf,
+	/// The text framebuffer
+	screen: Vec<u8>,
+	/// A hash of the disk image
+	disk_hash: u64,
+	/// The BIOS calls the OS made
+	trace: Vec<String>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Tells the `scenario` test to run, and where to put what it finds.
+const SCENARIO_VAR: &str = "NEOTRON_SCENARIO_DIR";
+
+/// How far the virtual clock moves on each BIOS call.
+const STEP: Duration = Duration::from_millis(1);
+
+/// When the scripted key is pressed and let go, in emulated time.
+const KEY_AT: Duration = Duration::from_millis(50);
+
+/// How long the test OS gets to finish.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many blocks the disk image has.
+const DISK_BLOCKS: usize = 4;
+
+/// How many trace lines either side of a difference we show.
+const CONTEXT_LINES: usize = 3;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn runs_are_identical() {
+	let first = run_scenario("first");
+	let second = run_scenario("second");
+	assert!(
+		first.trace.iter().any(|line| line.contains("KeyRelease")),
+		"the scripted key never arrived (see {})",
+		first.dir.display()
+	);
+	if first.trace != second.trace {
+		panic!(
+			"The traces differ:\n{}",
+			describe_difference(&first, &second)
+		);
+	}
+	assert!(
+		first.screen == second.screen,
+		"the screens differ (see {} and {})",
+		first.dir.display(),
+		second.dir.display()
+	);
+	assert_eq!(
+		first.disk_hash,
+		second.disk_hash,
+		"the disk images differ (see {} and {})",
+		first.dir.display(),
+		second.dir.display()
+	);
+}
+
+/// One run of the scenario. This does nothing unless [`run_scenario`] asked
+/// for it, in a process of its own.
+#[test]
+fn scenario() {
+	let Some(dir) = std::env::var_os(SCENARIO_VAR).map(PathBuf::from) else {
+		return;
+	};
+	let disk_path = dir.join("disk.img");
+	let mut disk = vec![0u8; DISK_BLOCKS * 512];
+	disk[..16].copy_from_slice(b"NEOTRON TEST DSK");
+	std::fs::write(&disk_path, disk).unwrap();
+	let file = std::fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open(&disk_path)
+		.unwrap();
+
+	clock::set_virtual(Some(STEP));
+	let keys = neotron_desktop_bios::power_on(Some(file));
+	// The wall clock starts at the Neotron epoch, not the host's time
+	HARDWARE.lock().unwrap().as_mut().unwrap().boot_wall_ns = 0;
+	let trace = trace::Trace::new(Some(&dir.join("trace.txt")), 16).unwrap();
+	apitrace::start_trace(trace, Vec::new(), 0);
+	hid::play_script(vec![
+		(KEY_AT, AppEvent::KeyDown(Key::A)),
+		(KEY_AT, AppEvent::KeyUp(Key::A)),
+	]);
+	neotron_desktop_bios::boot(neotron_test_os::os_main, None).unwrap();
+	keys.send(AppEvent::Started).unwrap();
+	let results = neotron_test_os::wait_for_results(TIMEOUT).expect("the test OS to finish");
+	assert_eq!(results.hid_events.len(), 2);
+
+	let screen: Vec<u8> = (0..80 * 30 * 2).map(|i| FRAMEBUFFER.get_at(i)).collect();
+	std::fs::write(dir.join("screen.bin"), screen).unwrap();
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Run the scenario in a new process, leaving its files in a directory
+/// called `name`, and say how it ended.
+fn run_scenario(name: &str) -> Outcome {
+	let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+		.join("determinism")
+		.join(name);
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).unwrap();
+	let status = std::process::Command::new(std::env::current_exe().unwrap())
+		.args(["--exact", "scenario", "--nocapture"])
+		.env(SCENARIO_VAR, &dir)
+		.status()
+		.unwrap();
+	assert!(status.success(), "the {} run failed", name);
+	let disk = std::fs::read(dir.join("disk.img")).unwrap();
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	disk.hash(&mut hasher);
+	Outcome {
+		screen: std::fs::read(dir.join("screen.bin")).unwrap(),
+		disk_hash: hasher.finish(),
+		trace: read_trace(&dir.join("trace.txt")),
+		dir,
+	}
+}
+
+/// Read a trace, up to where the test OS finished.
+///
+/// Once it has finished, the test OS calls `power_idle` until the process
+/// ends, so we leave off the `power_idle` calls at the end (and any line
+/// still being written).
+fn read_trace(path: &Path) -> Vec<String> {
+	let text = std::fs::read_to_string(path).unwrap();
+	let complete = &text[..text.rfind('\n').map_or(0, |end| end + 1)];
+	let mut lines: Vec<String> = complete.lines().map(hide_pointers).collect();
+	while lines
+		.last()
+		.is_some_and(|line| line.contains("power_idle("))
+	{
+		lines.pop();
+	}
+	lines
+}
+
+/// Replace any `0x...` numbers in a trace line, which are pointers.
+fn hide_pointers(line: &str) -> String {
+	let mut result = String::new();
+	let mut rest = line;
+	while let Some(start) = rest.find("0x") {
+		result.push_str(&rest[..start + 2]);
+		rest = &rest[start + 2..];
+		let digits = rest
+			.find(|c: char| !c.is_ascii_hexdigit())
+			.unwrap_or(rest.len());
+		result.push_str("...");
+		rest = &rest[digits..];
+	}
+	result.push_str(rest);
+	result
+}
+
+/// Show where two traces first differ, with a few lines either side.
+fn describe_difference(first: &Outcome, second: &Outcome) -> String {
+	let index = first
+		.trace
+		.iter()
+		.zip(&second.trace)
+		.position(|(a, b)| a != b)
+		.unwrap_or(first.trace.len().min(second.trace.len()));
+	let mut text = format!("They first differ at line {}.\n", index + 1);
+	for outcome in [first, second] {
+		text.push_str(&format!("\n{}:\n", outcome.dir.join("trace.txt").display()));
+		let start = index.saturating_sub(CONTEXT_LINES);
+		let end = (index + CONTEXT_LINES + 1).min(outcome.trace.len());
+		for (number, line) in outcome.trace[start..end].iter().enumerate() {
+			let marker = if start + number == index { ">" } else { " " };
+			text.push_str(&format!("{} {}\n", marker, line));
+		}
+		if end == outcome.trace.len() {
+			text.push_str("  (end of trace)\n");
+		}
+	}
+	text
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,193 @@
+//! # `--device` tests
+//!
+//! Parsing `--device` options, printing them back out, pointing at the wrong
+//! part of a bad one, putting Neotron Bus peripherals in their slots, and
+//! `--serial<N>` options.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::Path;
+
+use neotron_desktop_bios::{bus, device, serial};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn round_trip() {
+	for (given, printed) in [
+		("disk:0=file:./boot.img,ro", "disk:0=file:./boot.img,ro"),
+		("disk=file:boot.img", "disk:0=file:boot.img"),
+		("disk=transfer:./outbox", "disk:1=transfer:./outbox"),
+		(
+			"disk:1=transfer:xfer,ro,limit=1MiB",
+			"disk:1=transfer:xfer,ro,limit=1MiB",
+		),
+		(
+			"i2c:0x50=eeprom:./ee.bin,size=8KiB",
+			"i2c:0x50=eeprom:./ee.bin,size=8KiB",
+		),
+		("i2c:0x48=lm75,bus=1", "i2c:0x48=lm75,celsius=25,bus=1"),
+		("i2c:32=pcf8574", "i2c:0x20=pcf8574"),
+		("bus=slot", "bus=slot"),
+		("bus:2=timer,rate=10Hz", "bus:2=timer,rate=10Hz"),
+		("bus=sdcard:C:\\sd.img", "bus=sdcard:C:\\sd.img"),
+		(
+			"bus:1=flash:flash.bin,size=16MiB",
+			"bus:1=flash:flash.bin,size=16MiB",
+		),
+		("serial:0=loopback", "serial:0=loopback"),
+		("serial:3=slip-tap:neotap0", "serial:3=slip-tap:neotap0"),
+		(
+			"serial:2=slip-tap:neotap0,mtu=1500",
+			"serial:2=slip-tap:neotap0,mtu=1500",
+		),
+		("serial:1=slip-tap:tun1,mtu=1006", "serial:1=slip-tap:tun1"),
+	] {
+		let device = device::parse(given).unwrap();
+		assert_eq!(device.to_string(), printed);
+		// What we print parses back to the same thing
+		assert_eq!(
+			device::parse(printed).unwrap().to_string(),
+			printed,
+			"{}",
+			given
+		);
+	}
+}
+
+#[test]
+fn error_spans() {
+	for (given, span, message) in [
+		("disk", 0..4, "expected <class>"),
+		("floppy:0=file:a.img", 0..6, "unknown device class"),
+		("serial=loopback", 0..6, "give the port number"),
+		("serial:4=loopback", 7..8, "not a port number"),
+		(
+			"serial:1=tcp:127.0.0.1:5555",
+			9..12,
+			"unknown serial device",
+		),
+		("serial:0=null:x", 14..15, "doesn't take"),
+		("serial:0=slip-tap", 9..17, "give a TUN device name"),
+		("serial:0=slip-tap:a/b", 18..21, "can't have a '/'"),
+		(
+			"serial:0=slip-tap:tunnel-to-the-lan",
+			18..35,
+			"1 to 15 bytes",
+		),
+		("serial:0=slip-tap:tun0,mtu=20", 27..29, "not an MTU"),
+		("disk:1=file:a.img", 5..6, "only block device 0"),
+		("disk=nbd:a.img", 5..8, "unknown disk backend"),
+		("disk:0=transfer:xfer", 5..6, "always block device 1"),
+		("disk=transfer:xfer,limit=8GiB", 25..29, "under 4GiB"),
+		("disk=file", 5..9, "give a path"),
+		("disk=file:a.img,rw", 16..18, "unknown option"),
+		("disk=file:a.img,ro=yes", 19..22, "doesn't take a value"),
+		("disk=file:a.img,ro,ro", 19..21, "already given"),
+		("i2c=lm75", 0..3, "give the device's address"),
+		("i2c:0x80=lm75", 4..8, "more than seven bits"),
+		("i2c:0x48=lm75,celsius=warm", 22..26, "not a temperature"),
+		("i2c:0x48=lm75,bus", 17..17, "not a bus number"),
+		("i2c:0x48=lm75:a.img", 14..19, "doesn't take a path"),
+		("i2c:0x50=eeprom:ee.bin", 9..15, "needs a size"),
+		("i2c:0x50=eeprom:ee.bin,size=8QiB", 28..32, "unknown unit"),
+		("bus:9=gpio", 4..5, "not a slot number"),
+		("bus=timer,rate=fast", 15..19, "not a rate"),
+		("bus=flash:f.bin,size=3KiB", 21..25, "not a flash size"),
+		("bus=", 4..4, "expected a backend"),
+	] {
+		let error = device::parse(given).unwrap_err();
+		assert_eq!((error.span.clone(), &error.text[..]), (span, given));
+		assert!(error.message.contains(message), "{}", error.message);
+	}
+}
+
+#[test]
+fn serial_options() {
+	assert_eq!(
+		device::parse_serial("slip-tap:neotap0,mtu=576").unwrap(),
+		serial::DeviceSpec::SlipTap {
+			name: String::from("neotap0"),
+			mtu: 576,
+		}
+	);
+	let error = device::parse_serial("slip-tap:neotap0,baud=9600").unwrap_err();
+	assert_eq!(error.span, 17..21);
+	assert!(
+		error.message.contains("unknown option"),
+		"{}",
+		error.message
+	);
+}
+
+#[test]
+fn error_display() {
+	let error = device::parse("disk:0=file:boot.img,rw").unwrap_err();
+	assert_eq!(
+		error.to_string(),
+		"unknown option\n\n    disk:0=file:boot.img,rw\n                         ^^"
+	);
+}
+
+#[test]
+fn slots() {
+	let mut peripherals = vec![bus::DeviceSpec::Gpio];
+	device::place(&mut peripherals, Some(3), bus::DeviceSpec::Loopback).unwrap();
+	device::place(&mut peripherals, Some(1), bus::DeviceSpec::Gpio).unwrap();
+	device::place(&mut peripherals, None, bus::DeviceSpec::Timer(10.0)).unwrap();
+	assert_eq!(
+		format!("{:?}", peripherals),
+		"[Gpio, Gpio, Slot, Loopback, Timer(10.0)]"
+	);
+	let error = device::place(&mut peripherals, Some(3), bus::DeviceSpec::Gpio).unwrap_err();
+	assert!(error.contains("bus:3=loopback and bus:3=gpio"), "{}", error);
+}
+
+#[test]
+fn relative_paths() {
+	let base = Path::new("/profiles/pico2");
+	for (given, printed) in [
+		(
+			"disk=file:disks/sd.img",
+			"disk:0=file:/profiles/pico2/disks/sd.img",
+		),
+		("disk=file:/srv/sd.img", "disk:0=file:/srv/sd.img"),
+		(
+			"i2c:0x50=eeprom:ee.bin,size=8KiB",
+			"i2c:0x50=eeprom:/profiles/pico2/ee.bin,size=8KiB",
+		),
+		("bus:0=sdcard:sd.img", "bus:0=sdcard:/profiles/pico2/sd.img"),
+		("bus=gpio", "bus=gpio"),
+	] {
+		let mut device = device::parse(given).unwrap();
+		device.relative_to(base);
+		assert_eq!(device.to_string(), printed);
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
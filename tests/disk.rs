@@ -0,0 +1,270 @@
+//! # Disk image tool tests
+//!
+//! We make disk images with `disk::create`, look inside them and the fixture
+//! images in `tests/disk` with `disk::info`, and convert between raw and
+//! compressed images with `disk::convert`. The fixture `mbr.img` has an MBR
+//! with a bootable FAT12 partition in slot 0 and an empty Linux partition in
+//! slot 2, and `mbr.img.gz` is the same image compressed.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use neotron_desktop_bios::disk::{self, Error, FileSystem, Format, Info, Layout, Partition};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+const MIB: u64 = 1024 * 1024;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn info_raw_fixture() {
+	let info = disk::info(&fixture("mbr.img")).unwrap();
+	assert_eq!(info, fixture_info(Format::Raw));
+}
+
+#[test]
+fn info_compressed_fixture() {
+	let info = disk::info(&fixture("mbr.img.gz")).unwrap();
+	assert_eq!(info, fixture_info(Format::Gzip));
+}
+
+#[test]
+fn info_describes_partitions() {
+	let text = disk::info(&fixture("mbr.img")).unwrap().to_string();
+	let expected = [
+		"raw, 64 KiB (128 blocks)",
+		"MBR partition table",
+		"  0: FAT12 (0x01), bootable, from block 1, 63 blocks (32256 bytes), FAT12 file system",
+		"  2: Linux (0x83), from block 64, 64 blocks (32 KiB)",
+	];
+	assert_eq!(text, expected.join("\n"));
+}
+
+#[test]
+fn info_on_truncated_image() {
+	let dir = scratch("info_on_truncated_image");
+	let compressed = std::fs::read(fixture("mbr.img.gz")).unwrap();
+	let path = dir.join("cut.img.gz");
+	std::fs::write(&path, &compressed[..compressed.len() / 2]).unwrap();
+	assert!(matches!(disk::info(&path), Err(Error::Io(..))));
+}
+
+#[test]
+fn info_on_missing_image() {
+	let path = scratch("info_on_missing_image").join("nothing.img");
+	assert!(matches!(disk::info(&path), Err(Error::Io(..))));
+}
+
+#[test]
+fn create_blank() {
+	let path = scratch("create_blank").join("blank.img");
+	disk::create(&path, MIB, false, false).unwrap();
+	let contents = std::fs::read(&path).unwrap();
+	assert_eq!(contents.len() as u64, MIB);
+	assert!(contents.iter().all(|b| *b == 0));
+	assert_eq!(disk::info(&path).unwrap().layout, Layout::Unknown);
+}
+
+#[test]
+fn create_mbr() {
+	let path = scratch("create_mbr").join("mbr.img");
+	disk::create(&path, 2 * MIB, true, false).unwrap();
+	assert_eq!(disk::info(&path).unwrap().layout, Layout::Mbr(Vec::new()));
+}
+
+#[test]
+fn create_mbr_fat32() {
+	let path = scratch("create_mbr_fat32").join("fat32.img");
+	disk::create(&path, 64 * MIB, true, true).unwrap();
+	let info = disk::info(&path).unwrap();
+	assert_eq!(info.size, 64 * MIB);
+	assert_eq!(
+		info.layout,
+		Layout::Mbr(vec![Partition {
+			slot: 0,
+			kind: 0x0C,
+			bootable: false,
+			start_block: 2048,
+			num_blocks: 129024,
+			file_system: Some(FileSystem::Fat32),
+		}])
+	);
+
+	let contents = std::fs::read(&path).unwrap();
+	let boot = &contents[2048 * 512..2049 * 512];
+	assert_eq!(&boot[82..90], b"FAT32   ");
+	// The backup boot block matches
+	assert_eq!(boot, &contents[2054 * 512..2055 * 512]);
+	// The FSInfo block has its signatures
+	let fs_info = &contents[2049 * 512..2050 * 512];
+	assert_eq!(&fs_info[0..4], b"RRaA");
+	assert_eq!(&fs_info[484..488], b"rrAa");
+	// The first FAT starts with the media byte, and the root directory's
+	// end-of-chain
+	let fat = &contents[(2048 + 32) * 512..];
+	assert_eq!(
+		&fat[..12],
+		&[0xF8, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F]
+	);
+}
+
+#[test]
+fn create_fat32_without_mbr() {
+	let path = scratch("create_fat32_without_mbr").join("fat32.img");
+	disk::create(&path, 40 * MIB, false, true).unwrap();
+	assert_eq!(
+		disk::info(&path).unwrap().layout,
+		Layout::Volume(FileSystem::Fat32)
+	);
+}
+
+#[test]
+fn create_refuses_bad_sizes() {
+	let dir = scratch("create_refuses_bad_sizes");
+	let path = dir.join("bad.img");
+	assert!(matches!(
+		disk::create(&path, 1000, false, false),
+		Err(Error::NotWholeBlocks(1000))
+	));
+	assert!(matches!(
+		disk::create(&path, MIB, true, false),
+		Err(Error::WrongSize { .. })
+	));
+	assert!(matches!(
+		disk::create(&path, 16 * MIB, true, true),
+		Err(Error::WrongSize { .. })
+	));
+	// Nothing is left behind
+	assert!(!path.exists());
+}
+
+#[test]
+fn create_refuses_to_overwrite() {
+	let path = scratch("create_refuses_to_overwrite").join("precious.img");
+	std::fs::write(&path, b"precious").unwrap();
+	assert!(matches!(
+		disk::create(&path, MIB, false, false),
+		Err(Error::Exists(_))
+	));
+	assert_eq!(std::fs::read(&path).unwrap(), b"precious");
+}
+
+#[test]
+fn convert_decompresses() {
+	let path = scratch("convert_decompresses").join("mbr.img");
+	let size = disk::convert(&fixture("mbr.img.gz"), &path).unwrap();
+	assert_eq!(size, 64 * 1024);
+	assert_eq!(
+		std::fs::read(&path).unwrap(),
+		std::fs::read(fixture("mbr.img")).unwrap()
+	);
+}
+
+#[test]
+fn convert_compresses() {
+	let path = scratch("convert_compresses").join("mbr.img.gz");
+	disk::convert(&fixture("mbr.img"), &path).unwrap();
+	assert_eq!(disk::info(&path).unwrap(), fixture_info(Format::Gzip));
+}
+
+#[test]
+fn convert_cleans_up_after_bad_input() {
+	let dir = scratch("convert_cleans_up_after_bad_input");
+	let compressed = std::fs::read(fixture("mbr.img.gz")).unwrap();
+	let input = dir.join("cut.img.gz");
+	std::fs::write(&input, &compressed[..compressed.len() / 2]).unwrap();
+	let output = dir.join("out.img");
+	assert!(matches!(
+		disk::convert(&input, &output),
+		Err(Error::Io(path, _)) if path == input
+	));
+	assert!(!output.exists());
+}
+
+#[test]
+fn block_device_refuses_compressed_images() {
+	assert!(matches!(
+		disk::open(&fixture("mbr.img.gz")),
+		Err(Error::Compressed(_))
+	));
+	assert!(disk::open(&fixture("mbr.img")).is_ok());
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The path to a fixture image.
+fn fixture(name: &str) -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR"))
+		.join("tests")
+		.join("disk")
+		.join(name)
+}
+
+/// What `disk::info` should say about `mbr.img`, stored in `format`.
+fn fixture_info(format: Format) -> Info {
+	Info {
+		format,
+		size: 64 * 1024,
+		layout: Layout::Mbr(vec![
+			Partition {
+				slot: 0,
+				kind: 0x01,
+				bootable: true,
+				start_block: 1,
+				num_blocks: 63,
+				file_system: Some(FileSystem::Fat12),
+			},
+			Partition {
+				slot: 2,
+				kind: 0x83,
+				bootable: false,
+				start_block: 64,
+				num_blocks: 64,
+				file_system: None,
+			},
+		]),
+	}
+}
+
+/// An empty directory for the test called `name` to put its images in.
+fn scratch(name: &str) -> PathBuf {
+	let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+		.join("disk")
+		.join(name);
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,333 @@
+//! # Golden-image tests
+//!
+//! We put known contents in the framebuffer, draw the screen in software
+//! (see `neotron_desktop_bios::render`), and compare the picture with a PNG
+//! in `tests/golden`. If they differ by more than a little, the test fails
+//! and leaves the picture it got, and a diff showing where it differs in
+//! red, in `target/tmp/golden`.
+//!
+//! If you meant to change how the screen looks, run the tests with
+//! `NEOTRON_BLESS=1` to write new golden images, and check them in.
+//!
+//! There's only one framebuffer per process, so the tests take turns.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::MutexGuard;
+
+use neotron_desktop_bios::render::{self, Image};
+use neotron_desktop_bios::video::{self, FRAMEBUFFER};
+
+use common::video::{Format, Mode, Timing};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Set this environment variable to write new golden images instead of
+/// checking against the old ones.
+const BLESS_VAR: &str = "NEOTRON_BLESS";
+
+/// How far apart a colour channel can be before we call it a different pixel.
+const TOLERANCE: u8 = 2;
+
+/// White on black, for showing glyphs.
+const WHITE_ON_BLACK: u8 = 0x07;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn glyphs_8x16() {
+	let _turn = set_mode(Format::Text8x16);
+	draw_glyph_grid();
+	check_golden("glyphs_8x16", &render::render_screen());
+}
+
+#[test]
+fn glyphs_8x8() {
+	let _turn = set_mode(Format::Text8x8);
+	draw_glyph_grid();
+	check_golden("glyphs_8x8", &render::render_screen());
+}
+
+#[test]
+fn text_colours() {
+	let _turn = set_mode(Format::Text8x16);
+	// One row per foreground colour, one column per background colour, with
+	// a letter and a shaded block in each
+	for fg in 0..16 {
+		for bg in 0..8 {
+			let attr = (bg << 4) | fg;
+			let cell = usize::from(1 + fg) * 80 + 2 + usize::from(bg) * 4;
+			for (offset, glyph) in [b'A', 0xB1].into_iter().enumerate() {
+				FRAMEBUFFER.write_at((cell + offset) * 2, glyph);
+				FRAMEBUFFER.write_at((cell + offset) * 2 + 1, attr);
+			}
+		}
+	}
+	check_golden("text_colours", &render::render_screen());
+}
+
+#[test]
+fn chunky8() {
+	let _turn = set_mode(Format::Chunky8);
+	// Every palette entry, in a 16 x 16 grid of 40 x 30 blocks
+	for y in 0..480 {
+		for x in 0..640 {
+			FRAMEBUFFER.write_at(y * 640 + x, ((y / 30) * 16 + (x / 40)) as u8);
+		}
+	}
+	check_golden("chunky8", &render::render_screen());
+}
+
+#[test]
+fn chunky4_640x400() {
+	let _turn = set_timing_and_mode(Timing::T640x400, Format::Chunky4);
+	// The left pixel of each pair picks a colour by column and the right one
+	// by row, so we check the nibble order
+	for y in 0..400 {
+		for x_byte in 0..320 {
+			let left = (x_byte / 20) as u8;
+			let right = (y / 25) as u8;
+			FRAMEBUFFER.write_at(y * 320 + x_byte, left << 4 | right);
+		}
+	}
+	let screen = render::render_screen();
+	assert_eq!((screen.width, screen.height), (640, 400));
+	check_golden("chunky4_640x400", &screen);
+}
+
+#[test]
+fn chunky2_decode() {
+	let _turn = set_timing_and_mode(Timing::T800x600, Format::Chunky2);
+	for offset in 0..200 {
+		FRAMEBUFFER.write_at(offset, 0);
+	}
+	// 0b00_01_10_11, then the other way round, then all colour 2
+	for (offset, byte) in [0x1B, 0xE4, 0xAA].into_iter().enumerate() {
+		FRAMEBUFFER.write_at(offset, byte);
+	}
+	// The same on the last line, to check where each line starts
+	FRAMEBUFFER.write_at(599 * 200, 0x1B);
+	let mode = Mode::new(Timing::T800x600, Format::Chunky2);
+	let first = render::decode_line::<2>(mode, 0);
+	assert_eq!(first.len(), 800);
+	assert_eq!(&first[0..12], &[0, 1, 2, 3, 3, 2, 1, 0, 2, 2, 2, 2]);
+	assert!(first[12..].iter().all(|value| *value == 0));
+	let last = render::decode_line::<2>(mode, 599);
+	assert_eq!(&last[0..4], &[0, 1, 2, 3]);
+	// A frame fits in our own framebuffer
+	assert!(!video::video_mode_needs_vram(mode));
+	assert!(video::video_mode_needs_vram(Mode::new(
+		Timing::T800x600,
+		Format::Chunky8
+	)));
+}
+
+#[test]
+fn os_vram() {
+	let _turn = set_mode(Format::Text8x16);
+	let mode = Mode::new(Timing::T800x600, Format::Chunky8);
+	assert!(video::video_mode_needs_vram(mode));
+	// Too big for our own framebuffer
+	let result = video::video_set_mode(mode, std::ptr::null_mut());
+	assert!(matches!(result, common::ApiResult::Err(_)));
+
+	// Palette entry 9 down the right-hand column, which is past the end of
+	// our own framebuffer
+	let mut vram = vec![0u32; mode.frame_size_bytes() / 4];
+	for y in 0..600 {
+		vram[(y * 800 + 796) / 4] = 0x0000_0009u32.to_be();
+	}
+	let result = video::video_set_mode(mode, vram.as_mut_ptr());
+	assert!(matches!(result, common::ApiResult::Ok(())));
+	assert_eq!(video::video_get_framebuffer(), vram.as_mut_ptr());
+	assert_eq!(FRAMEBUFFER.get_at(599 * 800 + 799), 9);
+	// Nothing past the end of the OS's VRAM
+	assert_eq!(FRAMEBUFFER.get_at(vram.len() * 4), 0);
+	let screen = render::render_screen();
+	assert_eq!(
+		packed(screen.get(799, 599)),
+		video::PALETTE[9].load(Ordering::Relaxed)
+	);
+	assert_eq!(
+		packed(screen.get(798, 599)),
+		video::PALETTE[0].load(Ordering::Relaxed)
+	);
+
+	// Changing mode without VRAM goes back to ours
+	let result = video::video_set_mode(
+		Mode::new(Timing::T640x480, Format::Text8x16),
+		std::ptr::null_mut(),
+	);
+	assert!(matches!(result, common::ApiResult::Ok(())));
+	assert_ne!(video::video_get_framebuffer(), vram.as_mut_ptr());
+	assert_eq!(FRAMEBUFFER.get_at(599 * 800 + 799), 0);
+}
+
+#[test]
+fn chunky1() {
+	let _turn = set_mode(Format::Chunky1);
+	// Stripes that shift along a pixel every line, so we check the bit order
+	for y in 0..480 {
+		for x_byte in 0..80 {
+			FRAMEBUFFER.write_at(y * 80 + x_byte, 0xF0u8.rotate_right((y % 8) as u32));
+		}
+	}
+	check_golden("chunky1", &render::render_screen());
+}
+
+#[test]
+fn chunky1_640x400() {
+	let _turn = set_timing_and_mode(Timing::T640x400, Format::Chunky1);
+	for offset in 0..80 * 400 {
+		FRAMEBUFFER.write_at(offset, 0x80);
+	}
+	let mode = Mode::new(Timing::T640x400, Format::Chunky1);
+	// The top bit is the left-most pixel
+	let last = render::decode_line::<1>(mode, 399);
+	assert_eq!(&last[0..9], &[1, 0, 0, 0, 0, 0, 0, 0, 1]);
+	// Set bits are palette entry 1, and clear bits are entry 0
+	let screen = render::render_screen();
+	assert_eq!((screen.width, screen.height), (640, 400));
+	let foreground = video::PALETTE[1].load(Ordering::Relaxed);
+	let background = video::PALETTE[0].load(Ordering::Relaxed);
+	assert_eq!(packed(screen.get(0, 399)), foreground);
+	assert_eq!(packed(screen.get(1, 399)), background);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Wait for our turn, clear the screen, and switch to a 640 x 480 mode in
+/// `format`.
+fn set_mode(format: Format) -> MutexGuard<'static, ()> {
+	set_timing_and_mode(Timing::T640x480, format)
+}
+
+/// Wait for our turn, clear the screen, and switch to a mode with `timing`
+/// and `format`.
+fn set_timing_and_mode(timing: Timing, format: Format) -> MutexGuard<'static, ()> {
+	let turn = common::take_turn();
+	video::reset_video();
+	let result = video::video_set_mode(Mode::new(timing, format), std::ptr::null_mut());
+	assert!(matches!(result, common::ApiResult::Ok(())));
+	turn
+}
+
+/// A pixel as `0x00RRGGBB`, like a palette entry.
+fn packed(pixel: [u8; 4]) -> u32 {
+	u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]])
+}
+
+/// Put all 256 glyphs on the screen in a 16 x 16 grid, with a space between
+/// each.
+fn draw_glyph_grid() {
+	for glyph in 0..=255u8 {
+		let row = 1 + usize::from(glyph / 16);
+		let col = 2 + usize::from(glyph % 16) * 2;
+		FRAMEBUFFER.write_at((row * 80 + col) * 2, glyph);
+		FRAMEBUFFER.write_at((row * 80 + col) * 2 + 1, WHITE_ON_BLACK);
+	}
+}
+
+/// Check `actual` against the golden image called `name`, or replace the
+/// golden image if we've been asked to.
+fn check_golden(name: &str, actual: &Image) {
+	let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+		.join("tests")
+		.join("golden")
+		.join(format!("{}.png", name));
+	if std::env::var_os(BLESS_VAR).is_some() {
+		std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+		actual.save_png(&golden).unwrap();
+		return;
+	}
+	let expected = Image::load_png(&golden).unwrap_or_else(|e| {
+		panic!(
+			"Can't load {}: {} (run with {}=1 to make it)",
+			golden.display(),
+			e,
+			BLESS_VAR
+		)
+	});
+	let Some((diff, count)) = compare(&expected, actual) else {
+		return;
+	};
+	let dir = artifact_dir();
+	std::fs::create_dir_all(&dir).unwrap();
+	let actual_path = dir.join(format!("{}.actual.png", name));
+	let diff_path = dir.join(format!("{}.diff.png", name));
+	actual.save_png(&actual_path).unwrap();
+	diff.save_png(&diff_path).unwrap();
+	panic!(
+		"{} pixels differ from {}. We drew {}, and the differences are in red in {}",
+		count,
+		golden.display(),
+		actual_path.display(),
+		diff_path.display()
+	);
+}
+
+/// Where we leave the pictures from failed tests.
+fn artifact_dir() -> PathBuf {
+	Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+/// Compare two images. If they differ, returns a diff image (the expected
+/// image, dimmed, with the different pixels in red) and how many pixels
+/// differ.
+fn compare(expected: &Image, actual: &Image) -> Option<(Image, usize)> {
+	if (expected.width, expected.height) != (actual.width, actual.height) {
+		// Everything is different
+		return Some((actual.clone(), actual.width * actual.height));
+	}
+	let mut diff = Image::new(expected.width, expected.height);
+	let mut count = 0;
+	for y in 0..expected.height {
+		for x in 0..expected.width {
+			let want = expected.get(x, y);
+			let got = actual.get(x, y);
+			let colour = if want.iter().zip(got).any(|(a, b)| a.abs_diff(b) > TOLERANCE) {
+				count += 1;
+				common::video::RGBColour::from_rgb(0xFF, 0, 0)
+			} else {
+				common::video::RGBColour::from_rgb(want[0] / 4, want[1] / 4, want[2] / 4)
+			};
+			diff.set(x, y, colour);
+		}
+	}
+	(count != 0).then_some((diff, count))
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,84 @@
+//! # Guard page tests
+//!
+//! Memory regions with guard pages either side, which aren't a whole number
+//! of pages (or even of words) long. They have their own test binary, as
+//! `memory::init` adds to the global list of regions.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use neotron_common_bios as common;
+use neotron_desktop_bios::{hardware, memory};
+
+use common::MemoryKind;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn odd_sized_regions() {
+	// `--ram2-size` takes a plain byte count
+	let ram2_size = 1001;
+	let rom = b"An odd-sized ROM image".to_vec();
+	memory::init(memory::Options {
+		ram2_size: Some(ram2_size),
+		fill: 0xA5,
+		guard_pages: true,
+		shared: false,
+		rom: rom.clone(),
+	});
+
+	let expected = [
+		(memory::REGION0_SIZE, MemoryKind::Ram),
+		(ram2_size, MemoryKind::Ram),
+		(rom.len(), MemoryKind::Rom),
+	];
+	for (region, (length, kind)) in (0..).zip(expected) {
+		let found: Option<common::MemoryRegion> = hardware::memory_get_region(region).into();
+		let found = found.unwrap_or_else(|| panic!("Region {} is missing", region));
+		assert_eq!(
+			found.start as usize % 16,
+			0,
+			"Region {} is misaligned",
+			region
+		);
+		assert_eq!(found.length, length, "Region {}", region);
+		assert_eq!(found.kind.make_safe().ok(), Some(kind), "Region {}", region);
+		// Safety: the BIOS gave us this memory to use
+		let contents = unsafe { std::slice::from_raw_parts(found.start, found.length) };
+		if kind == MemoryKind::Rom {
+			assert_eq!(contents, &rom[..]);
+		} else {
+			assert!(
+				contents.iter().all(|byte| *byte == 0xA5),
+				"Region {}",
+				region
+			);
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,150 @@
+//! # Emulated I²C device tests
+//!
+//! Writing to and reading from the emulated EEPROM, the way the real chip
+//! behaves at page boundaries and at the end of the array.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use neotron_desktop_bios::i2c;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// An EEPROM on I2C Bus 0, kept in a temporary file.
+struct Eeprom {
+	address: u8,
+	file: common::TempFile,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How big our EEPROMs are - eight pages.
+const SIZE: usize = 256;
+
+/// How big an EEPROM page is.
+const PAGE_SIZE: usize = 32;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn eeprom_page_writes_wrap() {
+	let eeprom = Eeprom::new(0x50);
+	let mut model = vec![0xFF; SIZE];
+
+	// Two bytes from the end of page 0: the last two go back to its start
+	eeprom.write(30, &[1, 2, 3, 4]);
+	model[30..32].copy_from_slice(&[1, 2]);
+	model[0..2].copy_from_slice(&[3, 4]);
+	assert_eq!(eeprom.read(0, SIZE), model);
+	assert_eq!(model[32], 0xFF, "page 1 was touched");
+
+	// More than a page, starting at page 1: the extra overwrites its start
+	let long: Vec<u8> = (100..100 + PAGE_SIZE as u8 + 2).collect();
+	eeprom.write(PAGE_SIZE as u16, &long);
+	model[PAGE_SIZE..PAGE_SIZE * 2].copy_from_slice(&long[..PAGE_SIZE]);
+	model[PAGE_SIZE..PAGE_SIZE + 2].copy_from_slice(&long[PAGE_SIZE..]);
+	assert_eq!(eeprom.read(0, SIZE), model);
+
+	// The last page wraps within itself, not to the start of the array
+	eeprom.write(SIZE as u16 - 1, &[7, 8]);
+	model[SIZE - 1] = 7;
+	model[SIZE - PAGE_SIZE] = 8;
+	assert_eq!(eeprom.read(0, SIZE), model);
+
+	// And it was all saved
+	assert_eq!(std::fs::read(eeprom.file.path()).unwrap(), model);
+}
+
+#[test]
+fn eeprom_sequential_reads() {
+	let eeprom = Eeprom::new(0x51);
+	let contents: Vec<u8> = (0..SIZE).map(|i| i as u8).collect();
+	for (page, data) in contents.chunks(PAGE_SIZE).enumerate() {
+		eeprom.write((page * PAGE_SIZE) as u16, data);
+	}
+
+	// A read carries on across page boundaries
+	assert_eq!(eeprom.read(28, 8), [28, 29, 30, 31, 32, 33, 34, 35]);
+	// A read with no address carries on from where the last one stopped
+	assert_eq!(eeprom.read_on(3), [36, 37, 38]);
+	// At the end of the array, a read wraps to the start
+	assert_eq!(eeprom.read(SIZE as u16 - 2, 4), [254, 255, 0, 1]);
+	assert_eq!(eeprom.read_on(2), [2, 3]);
+	// The whole array, and then some
+	let mut expected = contents.clone();
+	expected.extend_from_slice(&contents[..10]);
+	assert_eq!(eeprom.read(0, SIZE + 10), expected);
+	// An address past the end wraps around too
+	assert_eq!(eeprom.read(SIZE as u16 + 5, 2), [5, 6]);
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Eeprom {
+	/// Attach a blank EEPROM at `address`.
+	fn new(address: u8) -> Eeprom {
+		let file = common::TempFile::new("eeprom-test", "bin");
+		let spec = i2c::parse_device(&format!(
+			"0:0x{:02x}:eeprom:{}:{}",
+			address,
+			file.path().display(),
+			SIZE
+		))
+		.unwrap();
+		i2c::add_device(&spec).unwrap();
+		Eeprom { address, file }
+	}
+
+	/// Write `data` starting at `offset`.
+	fn write(&self, offset: u16, data: &[u8]) {
+		i2c::write_read(0, self.address, &offset.to_be_bytes(), data, &mut []).unwrap();
+	}
+
+	/// Read `len` bytes starting at `offset`.
+	fn read(&self, offset: u16, len: usize) -> Vec<u8> {
+		let mut buffer = vec![0; len];
+		i2c::write_read(0, self.address, &offset.to_be_bytes(), &[], &mut buffer).unwrap();
+		buffer
+	}
+
+	/// Read `len` bytes from wherever the EEPROM's address counter is.
+	fn read_on(&self, len: usize) -> Vec<u8> {
+		let mut buffer = vec![0; len];
+		i2c::write_read(0, self.address, &[], &[], &mut buffer).unwrap();
+		buffer
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,139 @@
+//! # OS isolation tests
+//!
+//! With `--isolate`, the OS can scribble over the mailbox it shares with the
+//! BIOS as easily as over anything else. Whatever it leaves there, the BIOS
+//! should stop the OS process and say why, and carry on running itself.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use neotron_desktop_bios::crash;
+use neotron_desktop_bios::hid::AppEvent;
+use neotron_desktop_bios::isolate::{self, Opcode, CALL_WORDS};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How long the BIOS gets to stop each OS process.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many bytes the mailbox's buffer holds.
+const MAILBOX_BYTES: u64 = 256 * 1024;
+
+/// What each OS process writes in the mailbox, in turn.
+const SCRIBBLES: [[u64; CALL_WORDS]; 14] = [
+	// Opcodes we don't have
+	[u64::MAX; CALL_WORDS],
+	[200, 0, 0, 0, 0],
+	// Buffers bigger than the mailbox
+	[Opcode::ConfigurationSet as u64, MAILBOX_BYTES + 1, 0, 0, 0],
+	[Opcode::ConfigurationGet as u64, u64::MAX, 0, 0, 0],
+	[Opcode::BlockRead as u64, 0, 0, 1, u64::MAX],
+	[
+		Opcode::VideoSetWholePalette as u64,
+		MAILBOX_BYTES / 4 + 1,
+		0,
+		0,
+		0,
+	],
+	// A palette length that overflows when we count its bytes
+	[
+		Opcode::VideoSetWholePalette as u64,
+		u64::MAX / 4 + 1,
+		0,
+		0,
+		0,
+	],
+	// Arguments too big for their types
+	[Opcode::BlockWrite as u64, 256, 0, 1, 512],
+	[Opcode::VideoSetMode as u64, 0x100, 0, 0, 0],
+	[Opcode::HidSetLeds as u64, 8, 0, 0, 0],
+	// Arguments a call doesn't have
+	[Opcode::TimeTicksGet as u64, 1, 0, 0, 0],
+	[Opcode::PowerIdle as u64, 0, 0, 0, u64::MAX],
+	// Bad argument reports about functions and problems we don't have
+	[Opcode::BadArgument as u64, 99, 0, 0, 0],
+	[Opcode::BadArgument as u64, 0, 0, 0, 99],
+];
+
+/// Which scribble the next OS process writes. We set it before we reset the
+/// OS, and the new process is forked from us, so it sees the new value.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn scribbled_mailbox() {
+	let _turn = common::take_turn();
+	isolate::init().unwrap();
+	let keys = neotron_desktop_bios::power_on(None);
+	neotron_desktop_bios::boot(scribbler, None).unwrap();
+	keys.send(AppEvent::Started).unwrap();
+	for (idx, words) in SCRIBBLES.iter().enumerate() {
+		let message = wait_for_crash();
+		let expected = format!("we don't understand (opcode {:#x})", words[0]);
+		assert!(message.contains(&expected), "{}: {}", idx, message);
+		if idx + 1 < SCRIBBLES.len() {
+			NEXT.store(idx + 1, Ordering::Relaxed);
+			crash::reset();
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// An OS that writes the next scribble in the mailbox and passes it to the
+/// BIOS.
+unsafe extern "C" fn scribbler(api: &'static common::Api) -> ! {
+	isolate::scribble(SCRIBBLES[NEXT.load(Ordering::Relaxed)], 0xFF);
+	// The BIOS ran it, so the test fails when it gets fed up waiting
+	loop {
+		(api.power_idle)();
+	}
+}
+
+/// Wait for the OS to be stopped, and say why it was.
+fn wait_for_crash() -> String {
+	let deadline = Instant::now() + TIMEOUT;
+	loop {
+		if let Some(message) = crash::crash_message() {
+			return message;
+		}
+		assert!(Instant::now() < deadline, "the OS process wasn't stopped");
+		std::thread::sleep(Duration::from_millis(10));
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
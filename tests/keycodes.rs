@@ -0,0 +1,224 @@
+//! # Tests for converting host keys to Neotron keycodes
+//!
+//! [`EXPECTED`] says what every key pix-engine knows about should turn into,
+//! so any change to the mapping has to change this table too. We also check
+//! that no two host keys turn into the same Neotron keycode, because the OS
+//! couldn't tell them apart.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::collections::BTreeMap;
+
+use neotron_common_bios::hid::KeyCode;
+use neotron_desktop_bios::hid::convert_keycode;
+use pix_engine::prelude::Key;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// Every pix-engine key, in the order pix-engine lists them, and the Neotron
+/// keycode it should turn into (or `None` if it shouldn't reach the OS).
+const EXPECTED: &[(Key, Option<KeyCode>)] = &[
+	(Key::Backspace, Some(KeyCode::Backspace)),
+	(Key::Tab, Some(KeyCode::Tab)),
+	(Key::Return, Some(KeyCode::Return)),
+	(Key::Escape, Some(KeyCode::Escape)),
+	(Key::Space, Some(KeyCode::Spacebar)),
+	(Key::Exclaim, None),
+	(Key::Quotedbl, None),
+	(Key::Hash, Some(KeyCode::Oem7)),
+	(Key::Dollar, None),
+	(Key::Percent, None),
+	(Key::Ampersand, None),
+	(Key::Quote, Some(KeyCode::Oem3)),
+	(Key::LeftParen, None),
+	(Key::RightParen, None),
+	(Key::Asterisk, None),
+	(Key::Plus, None),
+	(Key::Comma, Some(KeyCode::OemComma)),
+	(Key::Minus, Some(KeyCode::OemMinus)),
+	(Key::Period, Some(KeyCode::OemPeriod)),
+	(Key::Slash, Some(KeyCode::Oem2)),
+	(Key::Num0, Some(KeyCode::Key0)),
+	(Key::Num1, Some(KeyCode::Key1)),
+	(Key::Num2, Some(KeyCode::Key2)),
+	(Key::Num3, Some(KeyCode::Key3)),
+	(Key::Num4, Some(KeyCode::Key4)),
+	(Key::Num5, Some(KeyCode::Key5)),
+	(Key::Num6, Some(KeyCode::Key6)),
+	(Key::Num7, Some(KeyCode::Key7)),
+	(Key::Num8, Some(KeyCode::Key8)),
+	(Key::Num9, Some(KeyCode::Key9)),
+	(Key::Colon, None),
+	(Key::Semicolon, Some(KeyCode::Oem1)),
+	(Key::Less, None),
+	(Key::Equals, Some(KeyCode::OemPlus)),
+	(Key::Greater, None),
+	(Key::Question, None),
+	(Key::At, None),
+	(Key::LeftBracket, Some(KeyCode::Oem4)),
+	(Key::Backslash, Some(KeyCode::Oem5)),
+	(Key::RightBracket, Some(KeyCode::Oem6)),
+	(Key::Caret, None),
+	(Key::Underscore, None),
+	(Key::Backquote, Some(KeyCode::Oem8)),
+	(Key::A, Some(KeyCode::A)),
+	(Key::B, Some(KeyCode::B)),
+	(Key::C, Some(KeyCode::C)),
+	(Key::D, Some(KeyCode::D)),
+	(Key::E, Some(KeyCode::E)),
+	(Key::F, Some(KeyCode::F)),
+	(Key::G, Some(KeyCode::G)),
+	(Key::H, Some(KeyCode::H)),
+	(Key::I, Some(KeyCode::I)),
+	(Key::J, Some(KeyCode::J)),
+	(Key::K, Some(KeyCode::K)),
+	(Key::L, Some(KeyCode::L)),
+	(Key::M, Some(KeyCode::M)),
+	(Key::N, Some(KeyCode::N)),
+	(Key::O, Some(KeyCode::O)),
+	(Key::P, Some(KeyCode::P)),
+	(Key::Q, Some(KeyCode::Q)),
+	(Key::R, Some(KeyCode::R)),
+	(Key::S, Some(KeyCode::S)),
+	(Key::T, Some(KeyCode::T)),
+	(Key::U, Some(KeyCode::U)),
+	(Key::V, Some(KeyCode::V)),
+	(Key::W, Some(KeyCode::W)),
+	(Key::X, Some(KeyCode::X)),
+	(Key::Y, Some(KeyCode::Y)),
+	(Key::Z, Some(KeyCode::Z)),
+	(Key::Delete, Some(KeyCode::Delete)),
+	(Key::CapsLock, Some(KeyCode::CapsLock)),
+	(Key::F1, Some(KeyCode::F1)),
+	(Key::F2, Some(KeyCode::F2)),
+	(Key::F3, Some(KeyCode::F3)),
+	(Key::F4, Some(KeyCode::F4)),
+	(Key::F5, Some(KeyCode::F5)),
+	(Key::F6, Some(KeyCode::F6)),
+	(Key::F7, Some(KeyCode::F7)),
+	(Key::F8, Some(KeyCode::F8)),
+	(Key::F9, Some(KeyCode::F9)),
+	(Key::F10, Some(KeyCode::F10)),
+	(Key::F11, Some(KeyCode::F11)),
+	(Key::F12, Some(KeyCode::F12)),
+	(Key::PrintScreen, Some(KeyCode::PrintScreen)),
+	(Key::ScrollLock, Some(KeyCode::ScrollLock)),
+	(Key::Pause, Some(KeyCode::PauseBreak)),
+	(Key::Insert, Some(KeyCode::Insert)),
+	(Key::Home, Some(KeyCode::Home)),
+	(Key::PageUp, Some(KeyCode::PageUp)),
+	(Key::End, Some(KeyCode::End)),
+	(Key::PageDown, Some(KeyCode::PageDown)),
+	(Key::Right, Some(KeyCode::ArrowRight)),
+	(Key::Left, Some(KeyCode::ArrowLeft)),
+	(Key::Down, Some(KeyCode::ArrowDown)),
+	(Key::Up, Some(KeyCode::ArrowUp)),
+	(Key::NumLock, Some(KeyCode::NumpadLock)),
+	(Key::KpDivide, Some(KeyCode::NumpadDivide)),
+	(Key::KpMultiply, Some(KeyCode::NumpadMultiply)),
+	(Key::KpMinus, Some(KeyCode::NumpadSubtract)),
+	(Key::KpPlus, Some(KeyCode::NumpadAdd)),
+	(Key::KpEnter, Some(KeyCode::NumpadEnter)),
+	(Key::Kp1, Some(KeyCode::Numpad1)),
+	(Key::Kp2, Some(KeyCode::Numpad2)),
+	(Key::Kp3, Some(KeyCode::Numpad3)),
+	(Key::Kp4, Some(KeyCode::Numpad4)),
+	(Key::Kp5, Some(KeyCode::Numpad5)),
+	(Key::Kp6, Some(KeyCode::Numpad6)),
+	(Key::Kp7, Some(KeyCode::Numpad7)),
+	(Key::Kp8, Some(KeyCode::Numpad8)),
+	(Key::Kp9, Some(KeyCode::Numpad9)),
+	(Key::Kp0, Some(KeyCode::Numpad0)),
+	(Key::KpPeriod, Some(KeyCode::NumpadPeriod)),
+	(Key::KpEquals, None),
+	(Key::KpComma, None),
+	(Key::LCtrl, Some(KeyCode::LControl)),
+	(Key::LShift, Some(KeyCode::LShift)),
+	(Key::LAlt, Some(KeyCode::LAlt)),
+	(Key::LGui, Some(KeyCode::LWin)),
+	(Key::RCtrl, Some(KeyCode::RControl)),
+	(Key::RShift, Some(KeyCode::RShift)),
+	(Key::RAlt, Some(KeyCode::RAltGr)),
+	(Key::RGui, Some(KeyCode::RWin)),
+	(Key::Unhandled, None),
+];
+
+/// Groups of host keys that are allowed to turn into the same keycode.
+const SHARED_KEYCODES: &[&[Key]] = &[];
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn every_key_converts_as_expected() {
+	for (key, keycode) in EXPECTED {
+		assert_eq!(convert_keycode(*key), *keycode, "converting {:?}", key);
+	}
+}
+
+#[test]
+fn every_key_is_listed_once() {
+	for (index, (key, _)) in EXPECTED.iter().enumerate() {
+		assert!(
+			EXPECTED[index + 1..].iter().all(|(other, _)| other != key),
+			"{:?} is listed twice",
+			key
+		);
+	}
+}
+
+#[test]
+fn keycodes_are_not_shared() {
+	let mut users: BTreeMap<KeyCode, Vec<Key>> = BTreeMap::new();
+	for (key, _) in EXPECTED {
+		if let Some(keycode) = convert_keycode(*key) {
+			users.entry(keycode).or_default().push(*key);
+		}
+	}
+	for (keycode, keys) in users {
+		if keys.len() > 1 {
+			assert!(
+				SHARED_KEYCODES
+					.iter()
+					.any(|allowed| keys.iter().all(|key| allowed.contains(key))),
+				"{:?} all turn into {:?}",
+				keys,
+				keycode
+			);
+		}
+	}
+}
+
+#[test]
+fn unhandled_keys_are_dropped() {
+	assert_eq!(convert_keycode(Key::Unhandled), None);
+	assert_eq!(convert_keycode(Key::default()), None);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
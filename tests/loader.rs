@@ -0,0 +1,109 @@
+//! # OS loader tests
+//!
+//! Loading stub OS libraries built for other versions of the BIOS API. A
+//! different major version is turned down, naming both versions, and a
+//! different minor version is only warned about.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use neotron_common_bios as common;
+use neotron_desktop_bios::loader;
+
+use common::Version;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn api_versions() {
+	let ours = common::API_VERSION;
+	let (major, minor, patch) = (ours.major(), ours.minor(), ours.patch());
+
+	// Another major version, newer or older, is turned down
+	for theirs in [
+		Version::new(major + 1, minor, patch),
+		Version::new(major.wrapping_sub(1), minor, patch),
+	] {
+		let error = match loader::load(&build_stub(theirs)) {
+			Ok(_) => panic!("an OS for {} was accepted", loader::describe(theirs)),
+			Err(error) => error,
+		};
+		assert!(
+			error.contains(&loader::describe(theirs)) && error.contains(&loader::describe(ours)),
+			"{}",
+			error
+		);
+	}
+
+	// Another minor or patch version is fine, as is our own
+	for theirs in [
+		Version::new(major, minor + 1, patch),
+		Version::new(major, minor, patch + 1),
+		ours,
+	] {
+		if let Err(error) = loader::load(&build_stub(theirs)) {
+			panic!(
+				"an OS for {} was turned down: {}",
+				loader::describe(theirs),
+				error
+			);
+		}
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Build the stub OS for BIOS API `version`, and say where it is.
+fn build_stub(version: Version) -> PathBuf {
+	let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/stub-os/stub_os.rs");
+	let name = format!("stub_os_{:08x}", version.0);
+	let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("stub-os");
+	std::fs::create_dir_all(&out_dir).unwrap();
+	let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+	let status = std::process::Command::new(rustc)
+		.args(["--crate-type=cdylib", "--edition=2021", "--crate-name"])
+		.arg(&name)
+		.arg("--out-dir")
+		.arg(&out_dir)
+		.arg(&source)
+		.env("STUB_API_VERSION", version.0.to_string())
+		.status()
+		.expect("rustc to run");
+	assert!(status.success(), "couldn't build {}", source.display());
+	out_dir.join(format!(
+		"{}{}{}",
+		std::env::consts::DLL_PREFIX,
+		name,
+		std::env::consts::DLL_SUFFIX
+	))
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,94 @@
+//! # Memory region tests
+//!
+//! Walking the memory regions the OS is given, with a second RAM region and a
+//! ROM.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use neotron_common_bios as common;
+use neotron_desktop_bios::{hardware, memory};
+
+use common::MemoryKind;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn regions() {
+	let ram2_size = 8 * 1024 * 1024;
+	let rom = b"A ROM image".to_vec();
+	memory::init(memory::Options {
+		ram2_size: Some(ram2_size),
+		fill: 0x5A,
+		guard_pages: false,
+		shared: false,
+		rom: rom.clone(),
+	});
+
+	// Walk the regions, like an OS does, until one isn't there
+	let mut found = Vec::new();
+	for region in 0..=3u8 {
+		let found_region: Option<common::MemoryRegion> = hardware::memory_get_region(region).into();
+		match found_region {
+			Some(found_region) => {
+				assert!(!found_region.start.is_null(), "Region {}", region);
+				found.push(found_region);
+			}
+			None => break,
+		}
+	}
+	let shapes: Vec<_> = found
+		.iter()
+		.map(|region| (region.length, region.kind.make_safe().unwrap()))
+		.collect();
+	assert_eq!(
+		shapes,
+		[
+			(memory::REGION0_SIZE, MemoryKind::Ram),
+			(ram2_size, MemoryKind::Ram),
+			(rom.len(), MemoryKind::Rom),
+		]
+	);
+
+	// There's nothing after the end of the list, however far we look
+	for region in [3, 4, 255] {
+		let missing: Option<common::MemoryRegion> = hardware::memory_get_region(region).into();
+		assert!(missing.is_none(), "Region {}", region);
+	}
+
+	// The RAM starts out filled, and the ROM holds its image
+	for region in &found[0..2] {
+		// Safety: the BIOS gave us this memory to use
+		let ram = unsafe { std::slice::from_raw_parts(region.start, region.length) };
+		assert!(ram.iter().all(|byte| *byte == 0x5A));
+	}
+	// Safety: as above
+	let image = unsafe { std::slice::from_raw_parts(found[2].start, found[2].length) };
+	assert_eq!(image, &rom[..]);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
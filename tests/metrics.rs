@@ -0,0 +1,114 @@
+//! # `--metrics` tests
+//!
+//! Parsing the address, and fetching the metrics over HTTP.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpStream};
+
+use neotron_desktop_bios::metrics;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn addresses() {
+	assert_eq!(
+		metrics::parse_address("9600").unwrap(),
+		"127.0.0.1:9600".parse::<SocketAddr>().unwrap()
+	);
+	assert_eq!(
+		metrics::parse_address("0.0.0.0:9600").unwrap(),
+		"0.0.0.0:9600".parse::<SocketAddr>().unwrap()
+	);
+	assert!(metrics::parse_address("localhost").is_err());
+	assert!(metrics::parse_address("127.0.0.1:http-ish").is_err());
+}
+
+#[test]
+fn render() {
+	let text = metrics::render();
+	for name in [
+		"neotron_uptime_seconds",
+		"neotron_frames_total",
+		"neotron_disk_read_bytes_total",
+		"neotron_serial_sent_bytes_total",
+		"neotron_audio_output_underruns_total",
+		"neotron_hid_events_dropped_total",
+		"neotron_watchdog_resets_total",
+	] {
+		assert!(
+			text.contains(&format!("# TYPE {} ", name)),
+			"{} missing from:\n{}",
+			name,
+			text
+		);
+	}
+	assert!(text.contains("neotron_bios_calls_total{function=\"video_set_mode\"} "));
+	// Every sample line is a name, maybe some labels, then a number
+	for line in text.lines().filter(|line| !line.starts_with('#')) {
+		let (_, value) = line.rsplit_once(' ').unwrap();
+		assert!(value.parse::<f64>().is_ok(), "{:?}", line);
+	}
+}
+
+#[test]
+fn http() {
+	// Find a port nobody is using
+	let address = std::net::TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap();
+	metrics::start(address).unwrap();
+
+	let response = get(address, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+	assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+	assert!(response.contains("\r\n\r\n# HELP neotron_uptime_seconds "));
+
+	let response = get(address, "GET / HTTP/1.1\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+
+	let response = get(address, "POST /metrics HTTP/1.1\r\n\r\n");
+	assert!(response.starts_with("HTTP/1.1 405 "), "{}", response);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Send a request and read the whole response.
+fn get(address: SocketAddr, request: &str) -> String {
+	let mut stream = TcpStream::connect(address).unwrap();
+	stream.write_all(request.as_bytes()).unwrap();
+	let mut response = String::new();
+	stream.read_to_string(&mut response).unwrap();
+	response
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,154 @@
+//! # NVRAM tests
+//!
+//! Getting and setting the OS's configuration through the BIOS API, with a
+//! fixed size NVRAM, and making sure `nvram write` on the command line can't
+//! write past the end of it.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use neotron_desktop_bios::{hardware, nvram};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How big our NVRAM is.
+const SIZE: usize = 16;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn configuration() {
+	let _turn = common::take_turn();
+	let file = common::TempFile::new("nvram-test", "bin");
+	nvram::set_path(file.path().to_path_buf());
+	nvram::set_size(SIZE);
+
+	// Empty: a blank NVRAM is all erased bytes, and an empty buffer tells
+	// the OS how big a buffer it needs
+	assert_eq!(get(0), (Ok(SIZE), vec![]));
+	assert_eq!(get(SIZE), (Ok(SIZE), vec![0xFF; SIZE]));
+
+	// Exactly the size of the NVRAM
+	let config: Vec<u8> = (1..=SIZE as u8).collect();
+	assert_eq!(set(&config), Ok(()));
+	assert!(file.path().exists());
+	assert_eq!(get(SIZE), (Ok(SIZE), config.clone()));
+
+	// A buffer that's too small gets as much as fits, and the real length
+	assert_eq!(get(4), (Ok(SIZE), config[..4].to_vec()));
+	// One that's too big is left alone past the end
+	let (result, got) = get(SIZE + 4);
+	assert_eq!(result, Ok(SIZE));
+	assert_eq!(&got[..SIZE], &config);
+	assert_eq!(&got[SIZE..], &[0xAA; 4]);
+	// Too much to store is turned down, and the old contents kept
+	assert_eq!(
+		set(&[0; SIZE + 1]),
+		Err(common::Error::UnsupportedConfiguration)
+	);
+	assert_eq!(get(SIZE), (Ok(SIZE), config));
+
+	// Setting nothing erases it
+	assert_eq!(set(&[]), Ok(()));
+	assert!(!file.path().exists());
+	assert_eq!(get(SIZE), (Ok(SIZE), vec![0xFF; SIZE]));
+}
+
+#[test]
+fn command_line_writes() {
+	let _turn = common::take_turn();
+	let file = common::TempFile::new("nvram-test", "bin");
+	nvram::set_path(file.path().to_path_buf());
+	nvram::set_size(SIZE);
+
+	// Right up to the end
+	assert!(write(SIZE - 4, "01 02 03 04").is_ok());
+	let mut expected = vec![0xFF; SIZE];
+	expected[SIZE - 4..].copy_from_slice(&[1, 2, 3, 4]);
+	assert_eq!(get(SIZE), (Ok(SIZE), expected.clone()));
+
+	// Past the end, or so far that the end can't be counted, doesn't grow
+	// the NVRAM, and changes nothing
+	for offset in [SIZE - 3, SIZE, usize::MAX - 1, usize::MAX] {
+		match write(offset, "01 02 03 04") {
+			Err(nvram::Error::TooBig { size, .. }) => assert_eq!(size, SIZE),
+			other => panic!("offset {}: {:?}", offset, other),
+		}
+		assert_eq!(get(SIZE), (Ok(SIZE), expected.clone()));
+	}
+}
+
+#[cfg(unix)]
+#[test]
+fn planted_links() {
+	let _turn = common::take_turn();
+	let file = common::TempFile::new("nvram-test", "bin");
+	nvram::set_path(file.path().to_path_buf());
+	nvram::set_size(SIZE);
+
+	// A link where the temporary file used to go isn't written through
+	let mut planted = file.path().to_path_buf().into_os_string();
+	planted.push(".tmp");
+	let victim = file.path().with_extension("victim");
+	std::fs::write(&victim, "victim").unwrap();
+	std::os::unix::fs::symlink(&victim, &planted).unwrap();
+	assert_eq!(set(&[1, 2, 3]), Ok(()));
+	assert_eq!(std::fs::read(&victim).unwrap(), b"victim");
+	assert_eq!(get(3), (Ok(3), vec![1, 2, 3]));
+	let _ = std::fs::remove_file(&planted);
+	let _ = std::fs::remove_file(&victim);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Get the configuration into a buffer of `len` bytes, filled with `0xAA`.
+fn get(len: usize) -> (Result<usize, common::Error>, Vec<u8>) {
+	let mut buffer = vec![0xAA; len];
+	let result = hardware::configuration_get(common::FfiBuffer::new(&mut buffer)).into();
+	(result, buffer)
+}
+
+/// Write some bytes, as `nvram write` does.
+fn write(offset: usize, hex: &str) -> Result<(), nvram::Error> {
+	nvram::run(&nvram::Action::Write {
+		offset,
+		hex: hex.to_string(),
+	})
+}
+
+/// Set the configuration.
+fn set(data: &[u8]) -> Result<(), common::Error> {
+	hardware::configuration_set(common::FfiByteSlice::new(data)).into()
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
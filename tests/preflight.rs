@@ -0,0 +1,119 @@
+//! # `--check` tests
+//!
+//! The checks `--check` makes that don't need the host's audio or a real OS:
+//! opening disk images, binding the monitor socket, reading files, and how
+//! the report comes out.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use neotron_desktop_bios::{monitor, preflight};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn disk_image() {
+	let path = fixture("mbr.img");
+	assert_eq!(
+		preflight::disk(&path),
+		Ok(format!("{}, 128 blocks (64 KiB)", path.display()))
+	);
+	// The block device can't seek around a compressed image
+	assert!(preflight::disk(&fixture("mbr.img.gz")).is_err());
+	assert!(preflight::disk(&fixture("nothing.img")).is_err());
+}
+
+#[test]
+fn monitor_port_in_use() {
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let busy = listener.local_addr().unwrap();
+	let address = monitor::parse_address(&format!("tcp:{}", busy)).unwrap();
+	assert!(monitor::probe(&address).is_err());
+	drop(listener);
+	// It's free now, and probing doesn't keep hold of it
+	assert_eq!(monitor::probe(&address), Ok(()));
+	assert_eq!(monitor::probe(&address), Ok(()));
+}
+
+#[cfg(unix)]
+#[test]
+fn monitor_socket_tidied_up() {
+	let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("preflight.sock");
+	let _ = std::fs::remove_file(&path);
+	let address = monitor::parse_address(&format!("unix:{}", path.display())).unwrap();
+	assert_eq!(monitor::probe(&address), Ok(()));
+	assert!(!path.exists());
+}
+
+#[test]
+fn missing_files() {
+	let nothing = fixture("nothing.wav");
+	assert!(preflight::wav(&nothing)
+		.unwrap_err()
+		.starts_with(&nothing.display().to_string()));
+	assert!(preflight::snapshot(&fixture("nothing.neo")).is_err());
+	assert!(preflight::os(&[fixture("libnothing.so")]).is_err());
+}
+
+#[test]
+fn report() {
+	let mut report = preflight::Report::new();
+	report.record("Disk", Ok(String::from("disk.img")));
+	report.record("Monitor", Err(String::from("tcp:4444: in use")));
+	report.record("OS", Ok(String::from("libneotron_os.so")));
+	assert!(!report.passed());
+	assert_eq!(
+		report.lines(),
+		[
+			"PASS  Disk     disk.img",
+			"FAIL  Monitor  tcp:4444: in use",
+			"PASS  OS       libneotron_os.so",
+			"1 of 3 checks failed",
+		]
+	);
+
+	let mut report = preflight::Report::new();
+	report.record("NVRAM", Ok(String::from("none")));
+	assert!(report.passed());
+	assert_eq!(report.lines().last().unwrap(), "All checks passed");
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The path to a file in the disk image fixtures.
+fn fixture(name: &str) -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR"))
+		.join("tests")
+		.join("disk")
+		.join(name)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
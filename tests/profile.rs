@@ -0,0 +1,230 @@
+//! # Profile tests
+//!
+//! Making and listing profiles in a directory of our own, and reading a
+//! profile's `config.toml` into a small command line with
+//! `config::with_file`.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, Command, ValueHint};
+
+use neotron_desktop_bios::config::{self, Setting, Source};
+use neotron_desktop_bios::profile::{self, Profile};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn first_use_scaffolds() {
+	let root = scratch("scaffold");
+	let profile = Profile::open(&root, "pico2").unwrap();
+	assert_eq!(profile.name(), "pico2");
+	assert_eq!(profile.dir(), root.join("pico2"));
+	assert!(profile.disks_dir().is_dir());
+	let text = std::fs::read_to_string(profile.config_path()).unwrap();
+	assert!(text.contains("'pico2'"));
+	// The template is all comments, so it sets nothing
+	let file = config::File::parse(&profile.config_path(), &text).unwrap();
+	assert!(file.entries.is_empty());
+
+	// Opening it again leaves our changes alone
+	std::fs::write(profile.config_path(), "headless = true\n").unwrap();
+	let profile = Profile::open(&root, "pico2").unwrap();
+	assert_eq!(
+		std::fs::read_to_string(profile.config_path()).unwrap(),
+		"headless = true\n"
+	);
+}
+
+#[test]
+fn bad_names() {
+	let root = scratch("bad_names");
+	for name in ["", "..", "a/b", "my pico"] {
+		assert!(
+			matches!(Profile::open(&root, name), Err(profile::Error::BadName(_))),
+			"{:?}",
+			name
+		);
+	}
+	assert!(!root.exists());
+}
+
+#[test]
+fn listing() {
+	let root = scratch("listing");
+	assert_eq!(profile::list(&root).unwrap(), Vec::<String>::new());
+	Profile::open(&root, "zx-board").unwrap();
+	Profile::open(&root, "pico2").unwrap();
+	Profile::open(&root, "a_test").unwrap();
+	// Stray files aren't profiles
+	std::fs::write(root.join("notes.txt"), "hello").unwrap();
+	assert_eq!(
+		profile::list(&root).unwrap(),
+		["a_test", "pico2", "zx-board"]
+	);
+}
+
+#[test]
+fn config_file_values() {
+	let path = Path::new("/profiles/pico2/config.toml");
+	let file = config::File::parse(
+		path,
+		"disk = \"disks/sd.img\"\nheadless = true\naudio-volume = 80\ni2c-device = [\"1:0x48:lm75\", \"1:0x20:pcf8574\"]\n",
+	)
+	.unwrap();
+	let mut entries = file.entries.clone();
+	entries.sort();
+	assert_eq!(
+		entries,
+		[
+			(String::from("audio-volume"), vec![String::from("80")]),
+			(String::from("disk"), vec![String::from("disks/sd.img")]),
+			(String::from("headless"), vec![String::from("true")]),
+			(
+				String::from("i2c-device"),
+				vec![String::from("1:0x48:lm75"), String::from("1:0x20:pcf8574")]
+			),
+		]
+	);
+	assert!(config::File::parse(path, "[audio]\nvolume = 80\n").is_err());
+	assert!(config::File::parse(path, "disk = [[\"a\"]]\n").is_err());
+	assert!(config::File::parse(path, "disk = \n").is_err());
+	// A file that isn't there sets nothing
+	let file = config::File::load(&scratch("missing").join("config.toml")).unwrap();
+	assert!(file.entries.is_empty());
+}
+
+#[test]
+fn file_beats_default() {
+	std::env::set_var("NEOTRON_BIOS_FILED_LATENCY", "50ms");
+	let path = Path::new("/profiles/pico2/config.toml");
+	let file = config::File::parse(
+		path,
+		"filed-disk = \"disks/sd.img\"\nfiled-os = \"/opt/libneotron_os.so\"\nfiled-latency = \"30ms\"\nfiled-headless = true\n",
+	)
+	.unwrap();
+	let command = config::with_env(
+		Command::new("test")
+			.arg(
+				Arg::new("filed_disk")
+					.long("filed-disk")
+					.value_hint(ValueHint::FilePath),
+			)
+			.arg(
+				Arg::new("filed_os")
+					.long("filed-os")
+					.value_hint(ValueHint::FilePath),
+			)
+			.arg(
+				Arg::new("filed_latency")
+					.long("filed-latency")
+					.default_value("20ms"),
+			)
+			.arg(
+				Arg::new("filed_volume")
+					.long("filed-volume")
+					.default_value("100"),
+			)
+			.arg(
+				Arg::new("filed_headless")
+					.long("filed-headless")
+					.action(ArgAction::SetTrue),
+			),
+		&[],
+	);
+	let command = config::with_file(command, &file, &[]).unwrap();
+	let matches = command
+		.clone()
+		.get_matches_from(["test", "--filed-os", "cli.so"]);
+	assert!(matches.get_flag("filed_headless"));
+	let from_file = Source::ConfigFile(path.to_owned());
+	assert_eq!(
+		config::settings(&command, &matches, &[], Some(&file)),
+		[
+			// Relative to the file, not to wherever we are
+			setting(
+				"--filed-disk",
+				Some("/profiles/pico2/disks/sd.img"),
+				from_file.clone()
+			),
+			setting("--filed-os", Some("cli.so"), Source::CommandLine),
+			setting(
+				"--filed-latency",
+				Some("50ms"),
+				Source::Environment(String::from("NEOTRON_BIOS_FILED_LATENCY"))
+			),
+			setting("--filed-volume", Some("100"), Source::Default),
+			setting("--filed-headless", Some("true"), from_file),
+		]
+	);
+}
+
+#[test]
+fn bad_keys() {
+	let path = Path::new("config.toml");
+	let command = Command::new("test")
+		.arg(Arg::new("keyed_disk").long("keyed-disk"))
+		.arg(
+			Arg::new("keyed_list")
+				.long("keyed-list")
+				.action(ArgAction::SetTrue),
+		);
+	let unknown = config::File::parse(path, "keyed-dsik = \"sd.img\"\n").unwrap();
+	assert!(config::with_file(command.clone(), &unknown, &[])
+		.unwrap_err()
+		.contains("--keyed-dsik"));
+	let skipped = config::File::parse(path, "keyed-list = true\n").unwrap();
+	assert!(config::with_file(command, &skipped, &["keyed-list"])
+		.unwrap_err()
+		.contains("can't go in a config file"));
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// An empty directory of our own to keep profiles in.
+fn scratch(name: &str) -> PathBuf {
+	let root = Path::new(env!("CARGO_TARGET_TMPDIR"))
+		.join("profiles")
+		.join(name);
+	let _ = std::fs::remove_dir_all(&root);
+	root
+}
+
+/// What we expect `config::settings` to say about one option.
+fn setting(option: &str, value: Option<&str>, source: Source) -> Setting {
+	Setting {
+		option: option.to_string(),
+		value: value.map(str::to_string),
+		source,
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,147 @@
+//! # `--remote` tests
+//!
+//! Reading and writing the JSON the remote-control socket speaks, and a
+//! client's session over a Unix socket: replies to commands, errors for bad
+//! requests, and only hearing about the events it subscribed to.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use neotron_desktop_bios::json::{self, Value};
+use neotron_desktop_bios::{monitor, remote};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn json_round_trip() {
+	for text in [
+		"null",
+		"[true,false,0,-1.5,1000000]",
+		r#"{"id":"a\"b\\c\n","cmd":"keys","keys":["ctrl-alt-del","enter"]}"#,
+		r#"{"nested":{"empty":[],"also":{}}}"#,
+		"\"caf\u{e9} \u{1f600}\"",
+	] {
+		assert_eq!(json::parse(text).unwrap().to_string(), text);
+	}
+	assert_eq!(
+		json::parse(" { \"a\" : [ 1 , 2 ] } ").unwrap().to_string(),
+		r#"{"a":[1,2]}"#
+	);
+	assert_eq!(
+		json::parse(r#""é😀\u0001""#).unwrap(),
+		Value::from("\u{e9}\u{1f600}\u{1}")
+	);
+	assert_eq!(Value::from("\u{1}").to_string(), r#""\u0001""#);
+	for bad in ["", "{", "[1,]", "{\"a\"}", "tru", "\"abc", "1 2", "{a:1}"] {
+		assert!(json::parse(bad).is_err(), "{:?}", bad);
+	}
+}
+
+#[test]
+fn local_only() {
+	assert!(remote::parse_address("tcp:5555").is_ok());
+	assert!(remote::parse_address("tcp:127.0.0.1:5555").is_ok());
+	assert!(remote::parse_address("unix:/tmp/neotron-rc.sock").is_ok());
+	let error = remote::parse_address("tcp:0.0.0.0:5555").unwrap_err();
+	assert!(error.contains("no authentication"), "{}", error);
+}
+
+#[cfg(unix)]
+#[test]
+fn session() {
+	use std::io::{BufRead, BufReader, Write};
+	use std::os::unix::net::UnixStream;
+
+	let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("remote.sock");
+	remote::start(&monitor::Address::Unix(path.clone())).unwrap();
+	let stream = UnixStream::connect(&path).unwrap();
+	let mut writer = stream.try_clone().unwrap();
+	let mut reader = BufReader::new(stream);
+	let mut next = move || {
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		json::parse(&line).unwrap()
+	};
+	assert_eq!(next().get("event"), Some(&Value::from("hello")));
+
+	// Each reply comes back with the request's id
+	let mut send = move |line: &str| writeln!(writer, "{}", line).unwrap();
+	send(r#"{"id":7,"cmd":"console","line":"help"}"#);
+	let reply = next();
+	assert_eq!(reply.get("id"), Some(&Value::from(7)));
+	assert_eq!(reply.get("ok"), Some(&Value::Bool(true)));
+	assert!(reply
+		.get("output")
+		.and_then(Value::as_str)
+		.unwrap()
+		.starts_with("Commands:"));
+
+	send("{not json");
+	let reply = next();
+	assert_eq!(reply.get("ok"), Some(&Value::Bool(false)));
+	assert!(reply.get("id").is_none());
+	assert!(error(&reply).starts_with("bad JSON"));
+
+	send(r#"{"id":"x","cmd":"dance"}"#);
+	assert!(error(&next()).contains("unknown command"));
+	send(r#"{"cmd":"keys","keys":["ctrl-nope"]}"#);
+	assert_eq!(next().get("ok"), Some(&Value::Bool(false)));
+	send(r#"{"cmd":"eject","drive":-1}"#);
+	assert!(error(&next()).contains("not a drive number"));
+	send(r#"{"cmd":"subscribe","events":["reboot"]}"#);
+	assert!(error(&next()).contains("unknown event \"reboot\""));
+
+	// We only hear about the events we asked for
+	send(r#"{"cmd":"subscribe","events":["panic"]}"#);
+	assert_eq!(
+		next().get("events"),
+		Some(&Value::Array(vec![Value::from("panic")]))
+	);
+	remote::notify(remote::Event::Mode(0));
+	remote::notify(remote::Event::Panic(String::from("oops")));
+	let event = next();
+	assert_eq!(event.get("event"), Some(&Value::from("panic")));
+	assert_eq!(event.get("message"), Some(&Value::from("oops")));
+
+	send(r#"{"id":1,"cmd":"unsubscribe"}"#);
+	assert_eq!(next().get("id"), Some(&Value::from(1)));
+	remote::notify(remote::Event::Panic(String::from("again")));
+	send(r#"{"id":2,"cmd":"unsubscribe"}"#);
+	assert_eq!(next().get("id"), Some(&Value::from(2)));
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// The error in a reply that should have failed.
+fn error(reply: &Value) -> &str {
+	assert_eq!(reply.get("ok"), Some(&Value::Bool(false)));
+	reply.get("error").and_then(Value::as_str).unwrap()
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,136 @@
+//! # Sample rate conversion tests
+//!
+//! We resample a 1 kHz sine wave, both ways the audio code uses the
+//! resampler, and check it's still a 1 kHz sine wave of the same size.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use neotron_desktop_bios::resample::Resampler;
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The tone we resample.
+const TONE_HZ: f32 = 1000.0;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn pull_upsamples_a_sine() {
+	// Like playback: the host device asks for frames at its own rate
+	for (from_hz, to_hz) in [(8000, 48000), (44100, 48000), (48000, 44100)] {
+		let input = sine(from_hz, from_hz as usize);
+		let mut input = input.iter();
+		let mut resampler = Resampler::new(from_hz, to_hz, 1);
+		let mut output = Vec::new();
+		let mut frame = [0.0];
+		while resampler.pull_frame(&mut frame, |out| match input.next() {
+			Some(sample) => {
+				out[0] = *sample;
+				true
+			}
+			None => false,
+		}) {
+			output.push(frame[0]);
+		}
+		check_tone(&output, to_hz, &format!("pull {} -> {}", from_hz, to_hz));
+	}
+}
+
+#[test]
+fn push_downsamples_a_sine() {
+	// Like recording: the host device hands us frames at its own rate
+	for (from_hz, to_hz) in [(48000, 8000), (48000, 44100), (22050, 48000)] {
+		let mut resampler = Resampler::new(from_hz, to_hz, 2);
+		let mut output = Vec::new();
+		for sample in sine(from_hz, from_hz as usize) {
+			resampler.push_frame(&[sample, -sample], |frame| {
+				assert_eq!(frame[1], -frame[0]);
+				output.push(frame[0]);
+			});
+		}
+		check_tone(&output, to_hz, &format!("push {} -> {}", from_hz, to_hz));
+	}
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// `count` samples of a full-scale 1 kHz sine, at `rate_hz`.
+fn sine(rate_hz: u32, count: usize) -> Vec<f32> {
+	(0..count)
+		.map(|i| (std::f32::consts::TAU * TONE_HZ * i as f32 / rate_hz as f32).sin())
+		.collect()
+}
+
+/// Check `samples` is about a second of 1 kHz at full scale, at `rate_hz`.
+fn check_tone(samples: &[f32], rate_hz: u32, context: &str) {
+	// A second in should be a second out
+	let expected = rate_hz as usize;
+	assert!(
+		samples.len().abs_diff(expected) <= 2,
+		"{}: {} samples, not {}",
+		context,
+		samples.len(),
+		expected
+	);
+
+	// Two zero crossings a cycle, going by rising ones
+	let rising = samples
+		.windows(2)
+		.filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+		.count();
+	let seconds = samples.len() as f32 / rate_hz as f32;
+	let frequency = rising as f32 / seconds;
+	assert!(
+		(frequency - TONE_HZ).abs() <= 2.0,
+		"{}: came out at {} Hz",
+		context,
+		frequency
+	);
+
+	// Linear interpolation loses a little off the peaks, but not much
+	let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+	assert!(
+		(0.9..=1.0001).contains(&peak),
+		"{}: the peak is {}",
+		context,
+		peak
+	);
+	let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+	assert!(
+		(rms - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.05,
+		"{}: the RMS level is {}",
+		context,
+		rms
+	);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
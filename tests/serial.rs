@@ -0,0 +1,174 @@
+//! # Serial port tests
+//!
+//! SLIP framing, and the BIOS's serial ports with things plugged into them.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use neotron_desktop_bios::serial;
+use neotron_desktop_bios::slip::{self, END, ESC, ESC_END, ESC_ESC};
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// The port we plug the loopback into. The ones below it are empty.
+const LOOPBACK: u8 = 2;
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn slip_escaping() {
+	let mut line = Vec::new();
+	slip::encode(&[1, END, 2, ESC, 3], &mut line);
+	assert_eq!(line, [END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, 3, END]);
+
+	// Back-to-back packets come out one at a time, and the empty frame
+	// between them is ignored
+	slip::encode(&[END, ESC, ESC_END], &mut line);
+	assert_eq!(
+		decode(&mut slip::Decoder::new(16), &line),
+		[vec![1, END, 2, ESC, 3], vec![END, ESC, ESC_END]]
+	);
+}
+
+#[test]
+fn slip_bad_packets() {
+	let mut decoder = slip::Decoder::new(4);
+	// An ESC followed by something else, a packet one byte too big, and an
+	// ESC right before the END are all thrown away
+	let line = [
+		1, ESC, 7, 2, END, 1, 2, 3, 4, 5, END, 1, ESC, END, 9, 8, 7, 6, END,
+	];
+	assert_eq!(decode(&mut decoder, &line), [vec![9, 8, 7, 6]]);
+	assert_eq!(decoder.dropped(), 3);
+
+	// An escaped byte counts once towards the MTU
+	let mut line = Vec::new();
+	slip::encode(&[END; 4], &mut line);
+	assert_eq!(decode(&mut decoder, &line), [vec![END; 4]]);
+	assert_eq!(decoder.dropped(), 3);
+}
+
+#[test]
+fn ports() {
+	let _turn = common::take_turn();
+	serial::add_port(LOOPBACK, &serial::DeviceSpec::Loopback).unwrap();
+	assert!(serial::add_port(4, &serial::DeviceSpec::Null).is_err());
+
+	// The ports below the loopback are there, with nothing plugged in
+	for port in 0..=LOOPBACK {
+		let info: Option<common::serial::DeviceInfo> = serial::serial_get_info(port).into();
+		assert_eq!(info.unwrap().name.as_str(), format!("UART{}", port));
+	}
+	let info: Option<common::serial::DeviceInfo> = serial::serial_get_info(LOOPBACK + 1).into();
+	assert!(info.is_none());
+	assert_eq!(
+		serial::describe_devices(),
+		[
+			"Serial UART0: nothing plugged in",
+			"Serial UART1: nothing plugged in",
+			"Serial UART2: loopback (TX wired to RX)",
+		]
+	);
+
+	// Any speed and framing will do, but not nonsense
+	let mut config = common::serial::Config {
+		data_rate_bps: 115_200,
+		data_bits: common::serial::DataBits::Eight.into(),
+		stop_bits: common::serial::StopBits::One.into(),
+		parity: common::serial::Parity::None.into(),
+		handshaking: common::serial::Handshaking::None.into(),
+	};
+	assert_eq!(configure(LOOPBACK, config.clone()), Ok(()));
+	assert_eq!(
+		configure(LOOPBACK + 1, config.clone()),
+		Err(common::Error::InvalidDevice)
+	);
+	config.parity = common::serial::FfiParity(9);
+	assert_eq!(
+		configure(LOOPBACK, config),
+		Err(common::Error::UnsupportedConfiguration)
+	);
+
+	// The loopback sends back what it was sent, and an empty port doesn't
+	assert_eq!(write(LOOPBACK, b"hello"), Ok(5));
+	assert_eq!(read(LOOPBACK, 3, None), Ok(b"hel".to_vec()));
+	assert_eq!(read(LOOPBACK, 8, None), Ok(b"lo".to_vec()));
+	assert_eq!(write(0, b"hello"), Ok(5));
+	assert_eq!(read(0, 8, None), Ok(Vec::new()));
+	assert_eq!(write(LOOPBACK + 1, b"x"), Err(common::Error::InvalidDevice));
+	assert_eq!(
+		read(LOOPBACK + 1, 1, None),
+		Err(common::Error::InvalidDevice)
+	);
+
+	// With a timeout, we wait for something to arrive
+	let start = Instant::now();
+	assert_eq!(read(LOOPBACK, 1, Some(50)), Ok(Vec::new()));
+	assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Feed a line of SLIP bytes to a decoder, and collect the packets.
+fn decode(decoder: &mut slip::Decoder, line: &[u8]) -> Vec<Vec<u8>> {
+	line.iter().filter_map(|&byte| decoder.push(byte)).collect()
+}
+
+/// Configure a serial port.
+fn configure(port: u8, config: common::serial::Config) -> Result<(), common::Error> {
+	serial::serial_configure(port, config).into()
+}
+
+/// Write to a serial port.
+fn write(port: u8, data: &[u8]) -> Result<usize, common::Error> {
+	serial::serial_write(
+		port,
+		common::FfiByteSlice::new(data),
+		common::FfiOption::None,
+	)
+	.into()
+}
+
+/// Read up to `len` bytes from a serial port, waiting up to `timeout`
+/// milliseconds for the first one.
+fn read(port: u8, len: usize, timeout: Option<u32>) -> Result<Vec<u8>, common::Error> {
+	let mut buffer = vec![0; len];
+	let timeout = timeout.map(common::Timeout::new_ms);
+	let result: Result<usize, common::Error> =
+		serial::serial_read(port, common::FfiBuffer::new(&mut buffer), timeout.into()).into();
+	result.map(|read| buffer[..read].to_vec())
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,48 @@
+//! # A stub OS
+//!
+//! It does nothing but say which version of the BIOS API it was built for,
+//! which is the `STUB_API_VERSION` environment variable when it is compiled
+//! (the `u32` form of a `neotron_common_bios::Version`). `tests/loader.rs`
+//! builds it with `rustc`, for each version it wants to try.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Which BIOS API we were built for, as a `Version` (which is a `u32`).
+#[no_mangle]
+pub extern "C" fn os_api_version() -> u32 {
+	env!("STUB_API_VERSION").parse().unwrap_or(0)
+}
+
+/// We never get this far - the BIOS should turn us down first, and the test
+/// doesn't start an OS it accepts.
+#[no_mangle]
+pub extern "C" fn os_main(_api: *const u8) -> ! {
+	loop {
+		std::thread::park();
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,184 @@
+//! # Integration tests with the test OS
+//!
+//! We boot the stub OS in `test-os` on the BIOS, without opening a window,
+//! then check what it saw, what it put on the screen and what it left on the
+//! disk.
+//!
+//! There's only one emulated machine per process, so the tests take turns
+//! (see [`Machine`]).
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::sync::{mpsc, MutexGuard};
+use std::time::Duration;
+
+use neotron_desktop_bios::hid::AppEvent;
+use neotron_desktop_bios::video;
+use neotron_test_os::Results;
+use pix_engine::prelude::Key;
+
+// -----------------------------------------------------------------------------
+// Types
+// -----------------------------------------------------------------------------
+
+/// The emulated machine, running the test OS.
+struct Machine {
+	/// Where key presses for the OS go
+	keys: mpsc::Sender<AppEvent>,
+	/// The disk image, if there is one
+	disk: Option<common::TempFile>,
+	/// Stops any other test using the machine until we're done
+	_turn: MutexGuard<'static, ()>,
+}
+
+// -----------------------------------------------------------------------------
+// Static and Const Data
+// -----------------------------------------------------------------------------
+
+/// How long the test OS gets to finish.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many blocks our disk images have.
+const DISK_BLOCKS: usize = 4;
+
+/// What block 0 of our disk images starts with.
+const DISK_MAGIC: &[u8; 16] = b"NEOTRON TEST DSK";
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn boots_and_writes_to_screen() {
+	let machine = Machine::boot(false);
+	let results = machine.results();
+	assert_eq!(results.api_version, common::API_VERSION.0);
+	assert_eq!(results.bios_version, "Neotron Desktop BIOS");
+	assert!(results.ticks_monotonic);
+	// The wall clock starts at the host's time, which is after 2023
+	assert!(results.clock_secs > 23 * 365 * 86400);
+	let screen = video::read_text().expect("the test OS stays in a text mode");
+	assert_eq!(screen.rows.len(), 30);
+	assert!(screen.rows.iter().all(|row| row.chars().count() == 80));
+	assert_eq!(screen.rows[0].trim_end(), neotron_test_os::BANNER);
+	assert_eq!(screen.rows[1].trim_end(), neotron_test_os::DONE);
+	assert!(screen.rows[2..].iter().all(|row| row.trim().is_empty()));
+	let banner_attrs = &screen.attrs[0][..neotron_test_os::BANNER.len()];
+	assert!(banner_attrs
+		.iter()
+		.all(|attr| attr.0 == neotron_test_os::ATTR));
+}
+
+#[test]
+fn reads_and_writes_disk() {
+	let machine = Machine::boot(true);
+	let results = machine.results();
+	assert_eq!(results.disk_blocks, Some(DISK_BLOCKS as u64));
+	assert_eq!(results.block0.as_ref(), Some(DISK_MAGIC));
+	assert!(results.write_ok);
+	assert!(results.verify_ok);
+	let disk = std::fs::read(machine.disk.as_ref().unwrap().path()).unwrap();
+	let start = neotron_test_os::PATTERN_BLOCK as usize * 512;
+	for (index, byte) in disk[start..start + 512].iter().enumerate() {
+		assert_eq!(*byte, neotron_test_os::pattern(index), "byte {}", index);
+	}
+	// The blocks either side are untouched
+	assert_eq!(&disk[..16], DISK_MAGIC);
+	assert!(disk[start + 512..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn no_disk() {
+	let machine = Machine::boot(false);
+	let results = machine.results();
+	assert_eq!(results.disk_blocks, None);
+	assert!(!results.write_ok);
+}
+
+#[test]
+fn delivers_key_presses() {
+	let machine = Machine::boot(false);
+	machine.keys.send(AppEvent::KeyDown(Key::A)).unwrap();
+	machine.keys.send(AppEvent::KeyUp(Key::A)).unwrap();
+	let results = machine.results();
+	assert_eq!(
+		results.hid_events,
+		[
+			common::hid::HidEvent::KeyPress(common::hid::KeyCode::A),
+			common::hid::HidEvent::KeyRelease(common::hid::KeyCode::A),
+		]
+	);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Make a disk image in a new temporary file, with [`DISK_MAGIC`] at the
+/// start and zeros everywhere else.
+fn make_disk() -> common::TempFile {
+	let mut contents = vec![0u8; DISK_BLOCKS * 512];
+	contents[..DISK_MAGIC.len()].copy_from_slice(DISK_MAGIC);
+	common::TempFile::with_contents("test", "img", &contents)
+}
+
+// -----------------------------------------------------------------------------
+// Impl Blocks
+// -----------------------------------------------------------------------------
+
+impl Machine {
+	/// Wait for our turn, switch the machine on (with a new disk image, if
+	/// `with_disk`), and start the test OS.
+	fn boot(with_disk: bool) -> Machine {
+		let turn = common::take_turn();
+		let disk = with_disk.then(make_disk);
+		let file = disk.as_ref().map(|disk| {
+			std::fs::OpenOptions::new()
+				.read(true)
+				.write(true)
+				.open(disk.path())
+				.unwrap()
+		});
+		let keys = neotron_desktop_bios::power_on(file);
+		neotron_desktop_bios::boot(neotron_test_os::os_main, None).unwrap();
+		// There's no window, so we say it's ready
+		keys.send(AppEvent::Started).unwrap();
+		Machine {
+			keys,
+			disk,
+			_turn: turn,
+		}
+	}
+
+	/// Wait for the test OS to finish, and say what it found.
+	fn results(&self) -> Results {
+		neotron_test_os::wait_for_results(TIMEOUT).expect("the test OS to finish")
+	}
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
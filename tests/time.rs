@@ -0,0 +1,116 @@
+//! # Time API tests
+//!
+//! Setting the wall clock and reading it back, and clamping it to what the
+//! API can hold. Ticks stop while the emulator is paused.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+mod common;
+
+use std::time::Duration;
+
+use neotron_desktop_bios::{clock, time};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn clock_set() {
+	let _turn = common::take_turn();
+	let _keys = neotron_desktop_bios::power_on(None);
+	let host = time::time_clock_get();
+	// The host's clock is well after the epoch
+	assert!(host.secs > 700_000_000, "{:?}", host);
+
+	// Back to the epoch itself (2000-01-01), which is before the host's
+	// time, then a year after it, then well into the future
+	for secs in [0, 365 * 24 * 60 * 60, host.secs + 1_000_000_000] {
+		time::time_clock_set(common::Time {
+			secs,
+			nsecs: 500_000_000,
+		});
+		let got = time::time_clock_get();
+		let elapsed_ns = (i64::from(got.secs) - i64::from(secs)) * 1_000_000_000
+			+ i64::from(got.nsecs)
+			- 500_000_000;
+		assert!(
+			(0..1_000_000_000).contains(&elapsed_ns),
+			"set {} and read back {:?}",
+			secs,
+			got
+		);
+	}
+}
+
+#[test]
+fn clamping() {
+	let max = common::Time {
+		secs: u32::MAX,
+		nsecs: 999_999_999,
+	};
+	let max_nanos = (i128::from(u32::MAX) + 1) * 1_000_000_000 - 1;
+	let cases = [
+		(0, (0, 0)),
+		(1, (0, 1)),
+		(1_000_000_000, (1, 0)),
+		(max_nanos, (max.secs, max.nsecs)),
+		// Just past either end sticks at that end
+		(max_nanos + 1, (max.secs, max.nsecs)),
+		(i128::MAX, (max.secs, max.nsecs)),
+		(-1, (0, 0)),
+		(i128::MIN, (0, 0)),
+	];
+	for (nanos, (secs, nsecs)) in cases {
+		let time = time::nanos_to_time(nanos);
+		assert_eq!((time.secs, time.nsecs), (secs, nsecs), "{}", nanos);
+	}
+}
+
+#[test]
+fn ticks_stop_while_paused() {
+	let _turn = common::take_turn();
+	let _keys = neotron_desktop_bios::power_on(None);
+	assert_eq!(time::time_ticks_per_second().0, 1000);
+	std::thread::sleep(Duration::from_millis(20));
+	let before = time::time_ticks_get().0;
+	assert!(before >= 20, "{}", before);
+
+	clock::set_paused(true);
+	let paused = time::time_ticks_get().0;
+	for _ in 0..5 {
+		std::thread::sleep(Duration::from_millis(20));
+		assert_eq!(time::time_ticks_get().0, paused);
+	}
+	clock::set_paused(false);
+
+	// The 100 ms we were paused for never happened
+	let after = time::time_ticks_get().0;
+	assert!(after >= paused);
+	assert!(after - before < 50, "{} ticks went by", after - before);
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
@@ -0,0 +1,263 @@
+//! # Transfer device tests
+//!
+//! Getting and putting files through the transfer device's control block
+//! and data blocks, and turning down paths that lead outside its directory,
+//! files that are too big, and puts to a read-only one.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::path::{Path, PathBuf};
+
+use neotron_common_bios as common;
+use neotron_desktop_bios::block::BLOCK_SIZE;
+use neotron_desktop_bios::transfer::{self, Spec, Status, Transfer};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn get() {
+	let dir = scratch("get");
+	let contents: Vec<u8> = (0..700u32).map(|n| n as u8).collect();
+	std::fs::create_dir(dir.join("docs")).unwrap();
+	std::fs::write(dir.join("docs/readme.txt"), &contents).unwrap();
+	let mut device = Transfer::new(&spec(&dir)).unwrap();
+	assert_eq!(device.num_blocks(), 1 + 32768);
+
+	assert_eq!(
+		request(&mut device, 1, 0, "docs/readme.txt"),
+		(Status::Ok, 700)
+	);
+	let mut data = vec![0xAA; BLOCK_SIZE * 2];
+	device.read(1, &mut data).unwrap();
+	assert_eq!(&data[..700], &contents[..]);
+	// Zeros after the end
+	assert!(data[700..].iter().all(|byte| *byte == 0));
+
+	assert_eq!(request(&mut device, 3, 0, ""), (Status::Ok, 700));
+	assert_eq!(request(&mut device, 3, 0, "").0, Status::NotOpen);
+	assert_eq!(device.read(1, &mut data), Err(common::Error::DeviceError));
+	assert_eq!(
+		request(&mut device, 1, 0, "missing.txt").0,
+		Status::NotFound
+	);
+	assert_eq!(request(&mut device, 1, 0, "docs").0, Status::NotFound);
+}
+
+#[test]
+fn put() {
+	let dir = scratch("put");
+	let mut device = Transfer::new(&spec(&dir)).unwrap();
+	assert_eq!(
+		request(&mut device, 2, 600, "result.bin"),
+		(Status::Ok, 600)
+	);
+	let mut data = vec![0x55; BLOCK_SIZE * 2];
+	data[0] = 1;
+	data[599] = 2;
+	device.write(1, &data).unwrap();
+	// Nothing there until it's closed
+	assert!(!dir.join("result.bin").exists());
+	assert_eq!(request(&mut device, 3, 0, ""), (Status::Ok, 600));
+	assert_eq!(std::fs::read(dir.join("result.bin")).unwrap(), &data[..600]);
+
+	// Writing past the end of the file is out of bounds
+	request(&mut device, 2, 10, "small.bin");
+	assert_eq!(
+		device.write(2, &data[..BLOCK_SIZE]),
+		Err(common::Error::BlockOutOfBounds)
+	);
+	// And so is writing past the end of the device
+	assert_eq!(
+		device.write(device.num_blocks(), &data[..BLOCK_SIZE]),
+		Err(common::Error::BlockOutOfBounds)
+	);
+}
+
+#[test]
+fn sandbox() {
+	let dir = scratch("sandbox");
+	std::fs::write(dir.with_extension("secret"), "secret").unwrap();
+	let mut device = Transfer::new(&spec(&dir)).unwrap();
+	for name in [
+		"",
+		"../sandbox.secret",
+		"a/../../sandbox.secret",
+		"/etc/passwd",
+		"./x",
+		"a//b",
+	] {
+		assert_eq!(
+			request(&mut device, 1, 0, name).0,
+			Status::BadPath,
+			"{:?}",
+			name
+		);
+		assert_eq!(
+			request(&mut device, 2, 1, name).0,
+			Status::BadPath,
+			"{:?}",
+			name
+		);
+	}
+	#[cfg(unix)]
+	{
+		std::os::unix::fs::symlink(dir.with_extension("secret"), dir.join("link")).unwrap();
+		std::os::unix::fs::symlink(dir.with_extension("nothing"), dir.join("dangling")).unwrap();
+		assert_eq!(request(&mut device, 1, 0, "link").0, Status::BadPath);
+		assert_eq!(request(&mut device, 2, 1, "link").0, Status::BadPath);
+		assert_eq!(request(&mut device, 2, 1, "dangling").0, Status::BadPath);
+		// A link where a temporary file might go isn't written through
+		for planted in ["planted.bin.partial", ".planted.bin.partial"] {
+			std::os::unix::fs::symlink(dir.with_extension("secret"), dir.join(planted)).unwrap();
+		}
+		assert_eq!(request(&mut device, 2, 3, "planted.bin"), (Status::Ok, 3));
+		let mut data = vec![0x55; BLOCK_SIZE];
+		data[..3].copy_from_slice(b"new");
+		device.write(1, &data).unwrap();
+		assert_eq!(request(&mut device, 3, 0, ""), (Status::Ok, 3));
+		assert_eq!(std::fs::read(dir.join("planted.bin")).unwrap(), b"new");
+		assert_eq!(
+			std::fs::read(dir.with_extension("secret")).unwrap(),
+			b"secret"
+		);
+		// And the temporary file is gone
+		let mut names: Vec<_> = std::fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect();
+		names.sort();
+		assert_eq!(
+			names,
+			[
+				".planted.bin.partial",
+				"dangling",
+				"link",
+				"planted.bin",
+				"planted.bin.partial"
+			]
+		);
+	}
+	let root = dir.canonicalize().unwrap();
+	assert_eq!(
+		transfer::resolve(&root, "new/file.txt").unwrap_err(),
+		"new/file.txt: No such file or directory (os error 2)"
+	);
+}
+
+#[test]
+fn limits() {
+	let dir = scratch("limits");
+	std::fs::write(dir.join("big.bin"), vec![0; 2000]).unwrap();
+	let mut device = Transfer::new(&Spec {
+		dir: dir.clone(),
+		read_only: true,
+		limit: 1024,
+	})
+	.unwrap();
+	assert_eq!(device.num_blocks(), 3);
+	assert_eq!(request(&mut device, 1, 0, "big.bin").0, Status::TooBig);
+	assert_eq!(request(&mut device, 2, 10, "out.bin").0, Status::ReadOnly);
+	let mut device = Transfer::new(&spec(&dir)).unwrap();
+	assert_eq!(
+		request(&mut device, 2, 17 * 1024 * 1024, "out.bin").0,
+		Status::TooBig
+	);
+
+	// Requests have to say what they are
+	let mut block = [0u8; BLOCK_SIZE];
+	block[4] = 1;
+	device.write(0, &block).unwrap();
+	assert_eq!(status(&mut device).0, Status::BadRequest);
+	assert_eq!(request(&mut device, 9, 0, "").0, Status::BadRequest);
+	assert!(Transfer::new(&spec(&dir.join("nowhere"))).is_err());
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// An empty directory of our own to transfer files in.
+fn scratch(name: &str) -> PathBuf {
+	let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+		.join("transfer")
+		.join(name);
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+/// A transfer device in `dir`, with the default limit.
+fn spec(dir: &Path) -> Spec {
+	Spec {
+		dir: dir.to_path_buf(),
+		read_only: false,
+		limit: transfer::DEFAULT_LIMIT,
+	}
+}
+
+/// Write a request to the control block, and read back the status and
+/// length.
+fn request(device: &mut Transfer, command: u8, length: u32, path: &str) -> (Status, u32) {
+	let mut block = [0u8; BLOCK_SIZE];
+	block[0..4].copy_from_slice(b"XFER");
+	block[4] = command;
+	block[8..12].copy_from_slice(&length.to_le_bytes());
+	block[16..16 + path.len()].copy_from_slice(path.as_bytes());
+	device.write(0, &block).unwrap();
+	let (status, echoed, length) = status(device);
+	assert_eq!(echoed, command);
+	(status, length)
+}
+
+/// Read the control block.
+fn status(device: &mut Transfer) -> (Status, u8, u32) {
+	let mut block = [0u8; BLOCK_SIZE];
+	device.read(0, &mut block).unwrap();
+	assert_eq!(&block[0..4], b"XFER");
+	let status = [
+		Status::Ok,
+		Status::NotFound,
+		Status::BadPath,
+		Status::TooBig,
+		Status::ReadOnly,
+		Status::IoError,
+		Status::BadRequest,
+		Status::NotOpen,
+	][usize::from(block[5])];
+	if status != Status::Ok {
+		// There's always a reason
+		assert_ne!(block[16], 0);
+	}
+	(
+		status,
+		block[4],
+		u32::from_le_bytes(block[8..12].try_into().unwrap()),
+	)
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
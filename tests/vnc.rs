@@ -0,0 +1,231 @@
+//! # `--vnc` tests
+//!
+//! Turning keysyms into keys, finding the parts of the screen that changed,
+//! packing pixels the way a viewer asks, and a viewer's session over TCP.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::net::TcpStream;
+
+use pix_engine::prelude::Key;
+
+use neotron_common_bios as common;
+use neotron_desktop_bios::render::Image;
+use neotron_desktop_bios::video::FRAMEBUFFER;
+use neotron_desktop_bios::vnc::{self, PixelFormat, Rect};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn keysyms() {
+	for (keysym, key) in [
+		(0x61, Some(Key::A)),
+		(0x5a, Some(Key::Z)),
+		// Shifted symbols are the key that makes them on a US keyboard
+		(0x21, Some(Key::Num1)),
+		(0x7c, Some(Key::Backslash)),
+		(0x3f, Some(Key::Slash)),
+		(0xff0d, Some(Key::Return)),
+		(0xffc9, Some(Key::F12)),
+		(0xfe03, Some(Key::RAlt)),
+		(0xe9, None),
+	] {
+		assert_eq!(vnc::convert_keysym(keysym), key, "{:#x}", keysym);
+	}
+}
+
+#[test]
+fn addresses() {
+	for (given, expected) in [
+		(":5901", "127.0.0.1:5901"),
+		(":1", "127.0.0.1:5901"),
+		("0.0.0.0:6000", "0.0.0.0:6000"),
+	] {
+		assert_eq!(vnc::parse_address(given).unwrap().to_string(), expected);
+	}
+	assert!(vnc::parse_address("5901").is_err());
+	assert!(vnc::parse_address(":vnc").is_err());
+}
+
+#[test]
+fn tiles() {
+	let old = Image::new(40, 20);
+	let mut new = old.clone();
+	let whole = Rect {
+		x: 0,
+		y: 0,
+		width: 40,
+		height: 20,
+	};
+	assert_eq!(vnc::changed_tiles(&old, &new, whole), []);
+	new.set(20, 5, common::video::RGBColour::from_rgb(0xFF, 0, 0));
+	new.set(39, 19, common::video::RGBColour::from_rgb(0, 0xFF, 0));
+	// Tiles at the edges are cut down to fit
+	assert_eq!(
+		vnc::changed_tiles(&old, &new, whole),
+		[
+			Rect {
+				x: 16,
+				y: 0,
+				width: 16,
+				height: 16
+			},
+			Rect {
+				x: 32,
+				y: 16,
+				width: 8,
+				height: 4
+			},
+		]
+	);
+	// Only inside the area asked for
+	let top_left = Rect {
+		x: 0,
+		y: 0,
+		width: 24,
+		height: 10,
+	};
+	assert_eq!(
+		vnc::changed_tiles(&old, &new, top_left),
+		[Rect {
+			x: 16,
+			y: 0,
+			width: 8,
+			height: 10
+		}]
+	);
+}
+
+#[test]
+fn pixel_formats() {
+	let mut image = Image::new(2, 1);
+	image.set(0, 0, common::video::RGBColour::from_rgb(0xFF, 0x80, 0x00));
+	let both = Rect {
+		x: 0,
+		y: 0,
+		width: 2,
+		height: 1,
+	};
+	let mut out = Vec::new();
+	PixelFormat::DEFAULT.encode(&image, both, &mut out);
+	assert_eq!(out, [0x00, 0x80, 0xFF, 0x00, 0, 0, 0, 0]);
+
+	// RGB565, most significant byte first
+	let rgb565 = PixelFormat {
+		bits_per_pixel: 16,
+		big_endian: true,
+		red_max: 31,
+		green_max: 63,
+		blue_max: 31,
+		red_shift: 11,
+		green_shift: 5,
+		blue_shift: 0,
+	};
+	assert_eq!(PixelFormat::from_bytes(&rgb565.to_bytes()), Ok(rgb565));
+	let mut out = Vec::new();
+	rgb565.encode(&image, both, &mut out);
+	assert_eq!(out, [0xFC, 0x00, 0x00, 0x00]);
+
+	let mut colour_map = PixelFormat::DEFAULT.to_bytes();
+	colour_map[3] = 0;
+	assert!(PixelFormat::from_bytes(&colour_map).is_err());
+}
+
+#[test]
+fn session() {
+	// Find a port nobody is using
+	let address = std::net::TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap();
+	vnc::start(address).unwrap();
+	let mut viewer = TcpStream::connect(address).unwrap();
+	assert_eq!(&read::<12>(&mut viewer), b"RFB 003.008\n");
+	viewer.write_all(b"RFB 003.008\n").unwrap();
+	// Just the None security type, which always works
+	assert_eq!(read::<2>(&mut viewer), [1, 1]);
+	viewer.write_all(&[1]).unwrap();
+	assert_eq!(read::<4>(&mut viewer), [0, 0, 0, 0]);
+	viewer.write_all(&[1]).unwrap();
+
+	// ServerInit: the screen size, the pixel format and our name
+	let init = read::<24>(&mut viewer);
+	assert_eq!(&init[0..4], &[0x02, 0x80, 0x01, 0xE0]);
+	assert_eq!(&init[4..20], &PixelFormat::DEFAULT.to_bytes());
+	let name_length = u32::from_be_bytes(init[20..24].try_into().unwrap()) as usize;
+	let mut name = vec![0; name_length];
+	viewer.read_exact(&mut name).unwrap();
+	assert_eq!(name, b"Neotron Desktop BIOS");
+
+	// Raw encoding only, then the whole screen
+	viewer.write_all(&[2, 0, 0, 1, 0, 0, 0, 0]).unwrap();
+	viewer
+		.write_all(&[3, 0, 0, 0, 0, 0, 0x02, 0x80, 0x01, 0xE0])
+		.unwrap();
+	assert_eq!(read::<4>(&mut viewer), [0, 0, 0, 1]);
+	assert_eq!(
+		read::<12>(&mut viewer),
+		[0, 0, 0, 0, 0x02, 0x80, 0x01, 0xE0, 0, 0, 0, 0]
+	);
+	let mut pixels = vec![0; 640 * 480 * 4];
+	viewer.read_exact(&mut pixels).unwrap();
+
+	// Pointer events and keys go nowhere without a window, but don't upset
+	// anything
+	viewer.write_all(&[5, 1, 0, 10, 0, 10]).unwrap();
+	viewer.write_all(&[4, 1, 0, 0, 0, 0, 0, 0x61]).unwrap();
+
+	// Put an 'A' in the top-left cell, and only that tile comes back
+	FRAMEBUFFER.write_at(0, b'A');
+	FRAMEBUFFER.write_at(1, 0x0F);
+	viewer
+		.write_all(&[3, 1, 0, 0, 0, 0, 0x02, 0x80, 0x01, 0xE0])
+		.unwrap();
+	assert_eq!(read::<4>(&mut viewer), [0, 0, 0, 1]);
+	assert_eq!(
+		read::<12>(&mut viewer),
+		[0, 0, 0, 0, 0, 16, 0, 16, 0, 0, 0, 0]
+	);
+	let mut tile = vec![0; 16 * 16 * 4];
+	viewer.read_exact(&mut tile).unwrap();
+	assert!(tile.chunks(4).any(|pixel| pixel != [0, 0, 0, 0]));
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// Read exactly `N` bytes from the server.
+fn read<const N: usize>(viewer: &mut TcpStream) -> [u8; N] {
+	let mut data = [0; N];
+	viewer.read_exact(&mut data).unwrap();
+	data
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------
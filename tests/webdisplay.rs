@@ -0,0 +1,213 @@
+//! # `--web-display` tests
+//!
+//! The WebSocket handshake and framing, packing up tiles, and a browser's
+//! session over TCP.
+
+// -----------------------------------------------------------------------------
+// Licence Statement
+// -----------------------------------------------------------------------------
+// Copyright (c) Jonathan 'theJPster' Pallant and the Neotron Developers, 2023
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program.  If not, see <https://www.gnu.org/licenses/>.
+// -----------------------------------------------------------------------------
+
+// -----------------------------------------------------------------------------
+// Imports
+// -----------------------------------------------------------------------------
+
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpStream};
+
+use neotron_desktop_bios::render::Image;
+use neotron_desktop_bios::vnc::Rect;
+use neotron_desktop_bios::webdisplay::{self, Frame, FrameReader};
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+#[test]
+fn accept_key() {
+	// The example from RFC 6455
+	assert_eq!(
+		webdisplay::accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+		"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+	);
+}
+
+#[test]
+fn frames() {
+	// "Hello", masked, in one piece and then in two
+	let hello = masked(0x81, b"Hello");
+	assert_eq!(
+		FrameReader::new(&hello[..]).read_frame().unwrap(),
+		Frame::Text("Hello".into())
+	);
+	let mut split = masked(0x01, b"Hel");
+	split.extend(masked(0x89, b"?"));
+	split.extend(masked(0x80, b"lo"));
+	let mut reader = FrameReader::new(&split[..]);
+	assert_eq!(reader.read_frame().unwrap(), Frame::Ping(b"?".to_vec()));
+	assert_eq!(reader.read_frame().unwrap(), Frame::Text("Hello".into()));
+	// Browsers must mask what they send
+	assert!(FrameReader::new(&[0x81, 0x01, b'x'][..])
+		.read_frame()
+		.is_err());
+
+	// A huge length, even part way through a message, is turned down
+	// rather than overflowing
+	let mut huge = masked(0x01, b"Hel");
+	huge.extend([0x80, 0x80 | 127]);
+	huge.extend(u64::MAX.to_be_bytes());
+	let mut reader = FrameReader::new(&huge[..]);
+	assert!(reader.read_frame().is_err());
+
+	// Each length takes the smallest header that fits it
+	for (length, header) in [(5, 2), (126, 4), (70000, 10)] {
+		let mut out = Vec::new();
+		webdisplay::write_frame(&mut out, 0x2, &vec![0; length]).unwrap();
+		assert_eq!(out.len(), header + length, "{}", length);
+		assert_eq!(out[0], 0x82);
+	}
+}
+
+#[test]
+fn updates() {
+	let mut screen = Image::new(32, 16);
+	for (i, pixel) in screen.pixels.chunks_exact_mut(4).enumerate() {
+		pixel.copy_from_slice(&[i as u8, 0, 0, 255]);
+	}
+	let tile = Rect {
+		x: 16,
+		y: 1,
+		width: 2,
+		height: 2,
+	};
+	let out = webdisplay::encode_update(&screen, &[tile]);
+	assert_eq!(
+		out,
+		[
+			0, 32, 0, 16, 0, 1, // Screen size and one tile
+			0, 16, 0, 1, 0, 2, 0, 2, // Where the tile is
+			48, 0, 0, 255, 49, 0, 0, 255, // Its first row
+			80, 0, 0, 255, 81, 0, 0, 255, // Its second row
+		]
+	);
+}
+
+#[test]
+fn session() {
+	// Find a port nobody is using
+	let address = std::net::TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap();
+	webdisplay::start(address).unwrap();
+
+	let page = request(address, "GET / HTTP/1.1\r\n\r\n");
+	assert!(page.starts_with("HTTP/1.1 200 OK\r\n"), "{}", page);
+	assert!(page.contains("new WebSocket("));
+	let missing = request(address, "GET /nothing HTTP/1.1\r\n\r\n");
+	assert!(missing.starts_with("HTTP/1.1 404 "), "{}", missing);
+
+	// The whole screen comes first
+	let mut browser = upgrade(address);
+	let update = read_frame(&mut browser);
+	assert_eq!(update[0], 0x82);
+	assert_eq!(&update[1..4], &[127, 0, 0]);
+	let payload = &update[10..];
+	assert_eq!(&payload[0..6], &[0x02, 0x80, 0x01, 0xE0, 0, 1]);
+	assert_eq!(&payload[6..14], &[0, 0, 0, 0, 0x02, 0x80, 0x01, 0xE0]);
+	assert_eq!(payload.len(), 14 + 640 * 480 * 4);
+
+	// Anyone else is told to wait their turn
+	let mut other = upgrade(address);
+	let busy = read_frame(&mut other);
+	assert_eq!(busy[0], 0x81);
+	assert!(String::from_utf8_lossy(&busy).contains("Someone else is watching"));
+
+	// A ping gets a pong, and a close gets a close
+	browser.write_all(&masked(0x89, b"hi")).unwrap();
+	assert_eq!(read_frame(&mut browser), [0x8A, 2, b'h', b'i']);
+	browser.write_all(&masked(0x88, b"")).unwrap();
+	assert_eq!(read_frame(&mut browser), [0x88, 0]);
+}
+
+// -----------------------------------------------------------------------------
+// Functions
+// -----------------------------------------------------------------------------
+
+/// A frame as a browser would send it.
+fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+	let mask = [0x37, 0xfa, 0x21, 0x3d];
+	let mut frame = vec![first, 0x80 | payload.len() as u8];
+	frame.extend_from_slice(&mask);
+	frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+	frame
+}
+
+/// Send a plain HTTP request and read the whole response.
+fn request(address: SocketAddr, request: &str) -> String {
+	let mut stream = TcpStream::connect(address).unwrap();
+	stream.write_all(request.as_bytes()).unwrap();
+	let mut response = String::new();
+	stream.read_to_string(&mut response).unwrap();
+	response
+}
+
+/// Open a WebSocket, checking the handshake.
+fn upgrade(address: SocketAddr) -> TcpStream {
+	let mut stream = TcpStream::connect(address).unwrap();
+	stream
+		.write_all(b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+		.unwrap();
+	let mut response = Vec::new();
+	while !response.ends_with(b"\r\n\r\n") {
+		let mut byte = [0];
+		stream.read_exact(&mut byte).unwrap();
+		response.push(byte[0]);
+	}
+	let response = String::from_utf8(response).unwrap();
+	assert!(response.starts_with("HTTP/1.1 101 "), "{}", response);
+	assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+	stream
+}
+
+/// Read one unmasked frame from the server, header and all.
+fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+	let mut frame = vec![0; 2];
+	stream.read_exact(&mut frame).unwrap();
+	let extra = match frame[1] {
+		126 => 2,
+		127 => 8,
+		_ => 0,
+	};
+	let mut length_bytes = vec![0; extra];
+	stream.read_exact(&mut length_bytes).unwrap();
+	let length = match extra {
+		0 => frame[1] as usize,
+		_ => length_bytes
+			.iter()
+			.fold(0usize, |length, byte| length << 8 | *byte as usize),
+	};
+	frame.extend(length_bytes);
+	let start = frame.len();
+	frame.resize(start + length, 0);
+	stream.read_exact(&mut frame[start..]).unwrap();
+	frame
+}
+
+// -----------------------------------------------------------------------------
+// End of file
+// -----------------------------------------------------------------------------